@@ -20,13 +20,26 @@ import shutil
 import binpacking
 from deepdiff import DeepDiff
 
-from ispConfig import sqm, upstreamBandwidthCapacityDownloadMbps, upstreamBandwidthCapacityUploadMbps, \
+from ispSettings import sqm, upstreamBandwidthCapacityDownloadMbps, upstreamBandwidthCapacityUploadMbps, \
 	interfaceA, interfaceB, enableActualShellCommands, useBinPackingToBalanceCPU, monitorOnlyMode, \
 	runShellCommandsAsSudo, generatedPNDownloadMbps, generatedPNUploadMbps, queuesAvailableOverride, \
 	OnAStick
 
 from liblqos_python import is_lqosd_alive, clear_ip_mappings, delete_ip_mapping, validate_shaped_devices, \
-	is_libre_already_running, create_lock_file, free_lock_file, add_ip_mapping, BatchedCommands
+	is_libre_already_running, create_lock_file, free_lock_file, add_ip_mapping, BatchedCommands, interface_pairs, \
+	sqm_profiles
+
+# /etc/lqos.conf may declare several shaping bridge pairs. The first takes the place of interfaceA/interfaceB,
+# and the others receive a copy of its queues (all pairs share the same IP mappings).
+try:
+	shapingPairs = interface_pairs()
+	interfaceA = shapingPairs[0].isp_interface
+	interfaceB = shapingPairs[0].internet_interface
+	OnAStick = shapingPairs[0].on_a_stick
+	extraShapingPairs = shapingPairs[1:]
+except OSError as e:
+	warnings.warn("Unable to read interface pairs, using ispConfig.py interfaces only: " + str(e), stacklevel=2)
+	extraShapingPairs = []
 
 # Automatically account for TCP overhead of plans. For example a 100Mbps plan needs to be set to 109Mbps for the user to ever see that result on a speed test
 # Does not apply to nodes of any sort, just endpoint devices
@@ -89,6 +102,17 @@ def tearDown(interfaceA, interfaceB):
 		#result = os.system('./bin/xdp_iphash_to_cpu_cmdline clear')
 		clear_ip_mappings() # Use the bus
 		clearPriorSettings(interfaceA, interfaceB)
+		for pair in extraShapingPairs:
+			clearPriorSettings(pair.isp_interface, pair.internet_interface)
+
+def copyCommandsToPair(commands, pair):
+	# Rewrite interfaceA/interfaceB tc commands for an additional shaping bridge pair
+	copied = []
+	for command in commands:
+		command = command.replace(' dev ' + interfaceA + ' ', ' dev ' + pair.isp_interface + ' ', 1)
+		command = command.replace(' dev ' + interfaceB + ' ', ' dev ' + pair.internet_interface + ' ', 1)
+		copied.append(command)
+	return copied
 
 def findQueuesAvailable(interfaceName):
 	# Find queues and CPU cores available. Use min between those two as queuesAvailable
@@ -171,7 +195,7 @@ def validateNetworkAndDevices():
 		commentsRemoved.pop(0) 
 		seenTheseIPsAlready = []
 		for row in commentsRemoved:
-			circuitID, circuitName, deviceID, deviceName, ParentNode, mac, ipv4_input, ipv6_input, downloadMin, uploadMin, downloadMax, uploadMax, comment = row
+			circuitID, circuitName, deviceID, deviceName, ParentNode, mac, ipv4_input, ipv6_input, downloadMin, uploadMin, downloadMax, uploadMax, comment = row[:13]
 			# Must have circuitID, it's a unique identifier required for stateful changes to queue structure
 			if circuitID == '':
 				warnings.warn("No Circuit ID provided in ShapedDevices.csv at row " + str(rowNum), stacklevel=2)
@@ -296,7 +320,7 @@ def loadSubscriberCircuits(shapedDevicesFile):
 		# Remove header
 		commentsRemoved.pop(0)
 		for row in commentsRemoved:
-			circuitID, circuitName, deviceID, deviceName, ParentNode, mac, ipv4_input, ipv6_input, downloadMin, uploadMin, downloadMax, uploadMax, comment = row
+			circuitID, circuitName, deviceID, deviceName, ParentNode, mac, ipv4_input, ipv6_input, downloadMin, uploadMin, downloadMax, uploadMax, comment = row[:13]
 			# If in monitorOnlyMode, override bandwidth rates to where no shaping will actually occur
 			if monitorOnlyMode == True:
 				downloadMin = 10000
@@ -417,6 +441,15 @@ def loadSubscriberCircuits(shapedDevicesFile):
 					dictForCircuitsWithoutParentNodes[counterForCircuitsWithoutParentNodes] = ((round(int(downloadMax)*tcpOverheadFactor))+(round(int(uploadMax)*tcpOverheadFactor)))
 					counterForCircuitsWithoutParentNodes += 1
 				subscriberCircuits.append(thisCircuit)
+	# Circuits listed in SqmProfiles.toml use their profile's sqm in place of the global one
+	try:
+		circuitProfiles = sqm_profiles()
+	except OSError as e:
+		warnings.warn("Unable to load SqmProfiles.toml, so every circuit will use the global sqm: " + str(e), stacklevel=2)
+		circuitProfiles = {}
+	for circuit in subscriberCircuits:
+		if circuit['circuitID'] in circuitProfiles:
+			circuit['sqmProfile'], circuit['sqm'] = circuitProfiles[circuit['circuitID']]
 	return (subscriberCircuits,	dictForCircuitsWithoutParentNodes)
 
 def refreshShapers():
@@ -477,6 +510,9 @@ def refreshShapers():
 		InterfaceAQueuesAvailable = findQueuesAvailable(interfaceA)
 		InterfaceBQueuesAvailable = findQueuesAvailable(interfaceB)
 		queuesAvailable = min(InterfaceAQueuesAvailable, InterfaceBQueuesAvailable)
+		# Additional bridge pairs share the queue structure, so it must fit all of them
+		for pair in extraShapingPairs:
+			queuesAvailable = min(queuesAvailable, findQueuesAvailable(pair.isp_interface), findQueuesAvailable(pair.internet_interface))
 		stickOffset = 0
 		if OnAStick:
 			print("On-a-stick override dividing queues")
@@ -691,6 +727,9 @@ def refreshShapers():
 							"classMinor": hex(minorByCPU[queue]),
 							"comment": circuit['comment']
 						}
+						if 'sqm' in circuit:
+							thisNewCircuitItemForNetwork['sqmProfile'] = circuit['sqmProfile']
+							thisNewCircuitItemForNetwork['sqm'] = circuit['sqm']
 						# Generate TC commands to be executed later
 						thisNewCircuitItemForNetwork['devices'] = circuit['devices']
 						circuitsForThisNetworkNode.append(thisNewCircuitItemForNetwork)
@@ -801,18 +840,20 @@ def refreshShapers():
 						tcComment = tcComment.replace("\n", "")
 						command = 'class add dev ' + interfaceA + ' parent ' + data[node]['classid'] + ' classid ' + circuit['classMinor'] + ' htb rate '+ str(circuit['minDownload']) + 'mbit ceil '+ str(circuit['maxDownload']) + 'mbit prio 3' + tcComment
 						linuxTCcommands.append(command)
+						# The circuit's SQM profile, if it has one, replaces the global sqm. "none" leaves plain HTB.
+						circuitSqm = circuit.get('sqm', sqm)
 						# Only add CAKE / fq_codel qdisc if monitorOnlyMode is Off
-						if monitorOnlyMode == False:
+						if monitorOnlyMode == False and circuitSqm != 'none':
 							# SQM Fixup for lower rates
-							useSqm = sqmFixupRate(circuit['maxDownload'], sqm)
+							useSqm = sqmFixupRate(circuit['maxDownload'], circuitSqm)
 							command = 'qdisc add dev ' + interfaceA + ' parent ' + circuit['classMajor'] + ':' + circuit['classMinor'] + ' ' + useSqm
 							linuxTCcommands.append(command)
 						command = 'class add dev ' + interfaceB + ' parent ' + data[node]['up_classid'] + ' classid ' + circuit['classMinor'] + ' htb rate '+ str(circuit['minUpload']) + 'mbit ceil '+ str(circuit['maxUpload']) + 'mbit prio 3'
 						linuxTCcommands.append(command)
 						# Only add CAKE / fq_codel qdisc if monitorOnlyMode is Off
-						if monitorOnlyMode == False:
+						if monitorOnlyMode == False and circuitSqm != 'none':
 							# SQM Fixup for lower rates
-							useSqm = sqmFixupRate(circuit['maxUpload'], sqm)
+							useSqm = sqmFixupRate(circuit['maxUpload'], circuitSqm)
 							command = 'qdisc add dev ' + interfaceB + ' parent ' + circuit['up_classMajor'] + ':' + circuit['classMinor'] + ' ' + useSqm
 							linuxTCcommands.append(command)
 							pass
@@ -839,6 +880,12 @@ def refreshShapers():
 		# Here is the actual call to the recursive traverseNetwork() function.
 		traverseNetwork(network)
 		
+		# Additional shaping bridge pairs get the same queues, since they share the IP mappings
+		firstPairCommands = list(linuxTCcommands)
+		for pair in extraShapingPairs:
+			logging.info("# Copying queues to interface pair " + pair.name)
+			linuxTCcommands.extend(copyCommandsToPair(firstPairCommands, pair))
+		
 		# Save queuingStructure
 		queuingStructure = {}
 		queuingStructure['Network'] = network
@@ -854,6 +901,8 @@ def refreshShapers():
 		
 		# Clear Prior Settings
 		clearPriorSettings(interfaceA, interfaceB)
+		for pair in extraShapingPairs:
+			clearPriorSettings(pair.isp_interface, pair.internet_interface)
 
 		
 		# Setup XDP and disable XPS regardless of whether it is first run or not (necessary to handle cases where systemctl stop was used)
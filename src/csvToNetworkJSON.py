@@ -3,8 +3,8 @@ checkPythonVersion()
 import os
 import csv
 import json
-from ispConfig import uispSite, uispStrategy, overwriteNetworkJSONalways
-from ispConfig import generatedPNUploadMbps, generatedPNDownloadMbps, upstreamBandwidthCapacityDownloadMbps, upstreamBandwidthCapacityUploadMbps
+from ispSettings import uispSite, uispStrategy, overwriteNetworkJSONalways
+from ispSettings import generatedPNUploadMbps, generatedPNDownloadMbps, upstreamBandwidthCapacityDownloadMbps, upstreamBandwidthCapacityUploadMbps
 from integrationCommon import NetworkGraph, NetworkNode, NodeType
 
 def csvToNetworkJSONfile():
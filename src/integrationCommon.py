@@ -2,7 +2,7 @@
 # integrations.
 
 from typing import List, Any
-from ispConfig import allowedSubnets, ignoreSubnets, generatedPNUploadMbps, generatedPNDownloadMbps, circuitNameUseAddress, upstreamBandwidthCapacityDownloadMbps, upstreamBandwidthCapacityUploadMbps
+from ispSettings import allowedSubnets, ignoreSubnets, generatedPNUploadMbps, generatedPNDownloadMbps, circuitNameUseAddress, upstreamBandwidthCapacityDownloadMbps, upstreamBandwidthCapacityUploadMbps
 import ipaddress
 import enum
 import os
@@ -129,7 +129,7 @@ class NetworkGraph:
 	exceptionCPEs: Any
 
 	def __init__(self) -> None:
-		from ispConfig import findIPv6usingMikrotik, excludeSites, exceptionCPEs
+		from ispSettings import findIPv6usingMikrotik, excludeSites, exceptionCPEs
 		self.nodes = [
 			NetworkNode("FakeRoot", type=NodeType.root,
 						parentId="", displayName="Shaper Root")
@@ -355,9 +355,9 @@ class NetworkGraph:
 
 	def createShapedDevices(self):
 			import csv
-			from ispConfig import bandwidthOverheadFactor
+			from ispSettings import bandwidthOverheadFactor
 			try:
-				from ispConfig import committedBandwidthMultiplier
+				from ispSettings import committedBandwidthMultiplier
 			except:
 				committedBandwidthMultiplier = 0.98
 		# Builds ShapedDevices.csv from the network tree.
@@ -2,7 +2,7 @@ from pythonCheck import checkPythonVersion
 checkPythonVersion()
 import requests
 import warnings
-from ispConfig import excludeSites, findIPv6usingMikrotik, bandwidthOverheadFactor, exceptionCPEs, powercode_api_key, powercode_api_url
+from ispSettings import excludeSites, findIPv6usingMikrotik, bandwidthOverheadFactor, exceptionCPEs, powercode_api_key, powercode_api_url
 from integrationCommon import isIpv4Permitted
 import base64
 from requests.auth import HTTPBasicAuth
@@ -5,7 +5,7 @@ from datetime import datetime
 
 from requests import get
 
-from ispConfig import automaticImportRestHttp as restconf
+from ispSettings import automaticImportRestHttp as restconf
 from pydash import objects
 
 requestsBaseConfig = {
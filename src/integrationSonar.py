@@ -2,7 +2,7 @@ from pythonCheck import checkPythonVersion
 checkPythonVersion()
 import requests
 import subprocess
-from ispConfig import sonar_api_url,sonar_api_key,sonar_airmax_ap_model_ids,sonar_active_status_ids,sonar_ltu_ap_model_ids,snmp_community
+from ispSettings import sonar_api_url,sonar_api_key,sonar_airmax_ap_model_ids,sonar_active_status_ids,sonar_ltu_ap_model_ids,snmp_community
 all_models = sonar_airmax_ap_model_ids + sonar_ltu_ap_model_ids
 from integrationCommon import NetworkGraph, NetworkNode, NodeType
 from multiprocessing.pool import ThreadPool
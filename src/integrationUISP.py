@@ -6,24 +6,24 @@ import csv
 from datetime import datetime, timedelta
 from integrationCommon import isIpv4Permitted, fixSubnet
 try:
-	from ispConfig import uispSite, uispStrategy, overwriteNetworkJSONalways
+	from ispSettings import uispSite, uispStrategy, overwriteNetworkJSONalways
 except:
-	from ispConfig import uispSite, uispStrategy
+	from ispSettings import uispSite, uispStrategy
 	overwriteNetworkJSONalways = False
 try:
-	from ispConfig import uispSuspendedStrategy
+	from ispSettings import uispSuspendedStrategy
 except:
 	uispSuspendedStrategy = "none"
 try:
-	from ispConfig import airMax_capacity
+	from ispSettings import airMax_capacity
 except:
 	airMax_capacity = 0.65
 try:
-	from ispConfig import ltu_capacity
+	from ispSettings import ltu_capacity
 except:
 	ltu_capacity = 0.90
 try:
-	from ispConfig import usePtMPasParent
+	from ispSettings import usePtMPasParent
 except:
 	usePtMPasParent = False
 
@@ -31,7 +31,7 @@ def uispRequest(target):
 	# Sends an HTTP request to UISP and returns the
 	# result in JSON. You only need to specify the
 	# tail end of the URL, e.g. "sites"
-	from ispConfig import UISPbaseURL, uispAuthToken
+	from ispSettings import UISPbaseURL, uispAuthToken
 	url = UISPbaseURL + "/nms/api/v2.1/" + target
 	headers = {'accept': 'application/json', 'x-auth-token': uispAuthToken}
 	r = requests.get(url, headers=headers, timeout=60)
@@ -41,7 +41,7 @@ def buildFlatGraph():
 	# Builds a high-performance (but lacking in site or AP bandwidth control)
 	# network.
 	from integrationCommon import NetworkGraph, NetworkNode, NodeType
-	from ispConfig import generatedPNUploadMbps, generatedPNDownloadMbps
+	from ispSettings import generatedPNUploadMbps, generatedPNDownloadMbps
 
 	# Load network sites
 	print("Loading Data from UISP")
@@ -396,7 +396,7 @@ def buildFullGraph():
 	# Attempts to build a full network graph, incorporating as much of the UISP
 	# hierarchy as possible.
 	from integrationCommon import NetworkGraph, NetworkNode, NodeType
-	from ispConfig import uispSite, generatedPNUploadMbps, generatedPNDownloadMbps
+	from ispSettings import uispSite, generatedPNUploadMbps, generatedPNDownloadMbps
 
 	# Load network sites
 	print("Loading Data from UISP")
@@ -37,6 +37,10 @@ OnAStick = False
 StickVlanA = 0
 # VLAN facing the edge router
 StickVlanB = 0
+# QinQ (802.1ad) only: inner C-VLAN tags that accompany StickVlanA and
+# StickVlanB as the outer S-VLAN tags. Leave at 0 for single tags.
+StickVlanAInner = 0
+StickVlanBInner = 0
 
 # Allow shell commands. False causes commands print to console only without being executed.
 # MUST BE ENABLED FOR PROGRAM TO FUNCTION
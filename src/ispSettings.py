@@ -0,0 +1,91 @@
+# Provides the settings that used to be read directly from ispConfig.py, under their original names.
+# Once ispConfig.py has been imported into /etc/lqos.conf (with "lqconfig migrate"), the values come
+# from there, via liblqos_python; until then, ispConfig.py is read as before.
+from liblqos_python import isp_settings
+
+_settings = isp_settings()
+
+if _settings is None:
+	from ispConfig import *
+else:
+	_interfaces = _settings['interfaces']
+	interfaceA = _interfaces['isp_interface']
+	interfaceB = _interfaces['internet_interface']
+	OnAStick = _interfaces['on_a_stick']
+	StickVlanA = _interfaces['isp_vlan']
+	StickVlanB = _interfaces['internet_vlan']
+	StickVlanAInner = _interfaces['isp_inner_vlan']
+	StickVlanBInner = _interfaces['internet_inner_vlan']
+
+	_queues = _settings['queues']
+	sqm = _queues['sqm']
+	monitorOnlyMode = _queues['monitor_only']
+	upstreamBandwidthCapacityDownloadMbps = _queues['uplink_download_mbps']
+	upstreamBandwidthCapacityUploadMbps = _queues['uplink_upload_mbps']
+	generatedPNDownloadMbps = _queues['generated_pn_download_mbps']
+	generatedPNUploadMbps = _queues['generated_pn_upload_mbps']
+	useBinPackingToBalanceCPU = _queues['use_binpacking']
+	queuesAvailableOverride = _queues['override_available_queues']
+	enableActualShellCommands = _queues['enable_shell_commands']
+	runShellCommandsAsSudo = _queues['run_as_sudo']
+	queueRefreshIntervalMins = _queues['queue_refresh_interval_mins']
+
+	allowedSubnets = _settings['subnets']['allowed']
+	ignoreSubnets = _settings['subnets']['ignored']
+
+	_integrations = _settings['integrations']
+	circuitNameUseAddress = _integrations['circuit_name_use_address']
+	overwriteNetworkJSONalways = _integrations['overwrite_network_json_always']
+	excludeSites = _integrations['exclude_sites']
+	findIPv6usingMikrotik = _integrations['find_ipv6_using_mikrotik']
+	bandwidthOverheadFactor = _integrations['bandwidth_overhead_factor']
+	committedBandwidthMultiplier = _integrations['committed_bandwidth_multiplier']
+	exceptionCPEs = _integrations['exception_cpes']
+
+	_uisp = _integrations['uisp']
+	automaticImportUISP = _uisp['enabled']
+	uispAuthToken = _uisp['token']
+	UISPbaseURL = _uisp['url']
+	uispSite = _uisp['site']
+	uispStrategy = _uisp['strategy']
+	uispSuspendedStrategy = _uisp['suspended_strategy']
+	airMax_capacity = _uisp['airmax_capacity']
+	ltu_capacity = _uisp['ltu_capacity']
+	usePtMPasParent = _uisp['use_ptmp_as_parent']
+
+	_splynx = _integrations['splynx']
+	automaticImportSplynx = _splynx['enabled']
+	splynx_api_key = _splynx['api_key']
+	splynx_api_secret = _splynx['api_secret']
+	splynx_api_url = _splynx['url']
+
+	_powercode = _integrations['powercode']
+	automaticImportPowercode = _powercode['enabled']
+	powercode_api_key = _powercode['api_key']
+	powercode_api_url = _powercode['api_url']
+
+	_sonar = _integrations['sonar']
+	automaticImportSonar = _sonar['enabled']
+	sonar_api_key = _sonar['api_key']
+	sonar_api_url = _sonar['api_url']
+	snmp_community = _sonar['snmp_community']
+	sonar_airmax_ap_model_ids = _sonar['airmax_ap_model_ids']
+	sonar_ltu_ap_model_ids = _sonar['ltu_ap_model_ids']
+	sonar_active_status_ids = _sonar['active_status_ids']
+
+	_rest = _integrations['rest_http']
+	automaticImportRestHttp = {
+		'enabled': _rest['enabled'],
+		'baseURL': _rest['base_url'],
+		'devicesURI': _rest['devices_uri'],
+		'networkURI': _rest['network_uri'],
+		'requestsConfig': _rest['requests_config'],
+		'logChanges': _rest['log_changes'],
+	}
+
+	_influxdb = _integrations['influxdb']
+	influxDBEnabled = _influxdb['enabled']
+	influxDBurl = _influxdb['url']
+	influxDBBucket = _influxdb['bucket']
+	influxDBOrg = _influxdb['org']
+	influxDBtoken = _influxdb['token']
@@ -0,0 +1,86 @@
+use anyhow::{Error, Result};
+use clap::{Parser, Subcommand};
+use lqos_config::{
+  migrate_isp_config, record_config_revision, EtcLqos, IspSettings,
+};
+use std::{path::PathBuf, process::exit};
+
+#[derive(Parser)]
+#[command()]
+struct Args {
+  #[command(subcommand)]
+  command: Option<Commands>,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+  /// Import ispConfig.py into /etc/lqos.conf. Afterwards, ispConfig.py
+  /// is no longer read.
+  Migrate {
+    /// The ispConfig.py to import. Defaults to the one in the LibreQoS
+    /// directory.
+    #[arg(long)]
+    isp_config: Option<PathBuf>,
+
+    /// Replace settings already in /etc/lqos.conf
+    #[arg(long)]
+    force: bool,
+
+    /// Show what would be written, without changing anything
+    #[arg(long)]
+    dry_run: bool,
+  },
+  /// Show the settings in /etc/lqos.conf that replace ispConfig.py
+  Show,
+}
+
+fn print_settings(settings: &IspSettings) -> Result<()> {
+  let document = toml_edit::ser::to_string_pretty(settings)?;
+  println!("{document}");
+  Ok(())
+}
+
+fn main() -> Result<()> {
+  let cli = Args::parse();
+  match cli.command {
+    Some(Commands::Migrate { isp_config, force, dry_run }) => {
+      let isp_config = match isp_config {
+        Some(path) => path,
+        None => {
+          let cfg = EtcLqos::load()
+            .map_err(|_| Error::msg("Unable to load /etc/lqos.conf"))?;
+          PathBuf::from(cfg.lqos_directory).join("ispConfig.py")
+        }
+      };
+      if dry_run {
+        print_settings(&IspSettings::import_isp_config(&isp_config)?)?;
+      } else {
+        // Keep the pre-migration configuration in the revision history
+        record_config_revision("lqconfig", "Before migrating ispConfig.py")?;
+        migrate_isp_config(&isp_config, force)?;
+        record_config_revision(
+          "lqconfig",
+          "Moved ispConfig.py settings into /etc/lqos.conf",
+        )?;
+        println!(
+          "Imported {} into /etc/lqos.conf (previous version saved as /etc/lqos.conf.backup).",
+          isp_config.display()
+        );
+        println!("Restart lqosd and lqos_scheduler to use the new settings.");
+      }
+    }
+    Some(Commands::Show) => match IspSettings::load() {
+      Some(settings) => print_settings(&settings)?,
+      None => {
+        println!("/etc/lqos.conf has no [queues] section; ispConfig.py is still in use.");
+        println!("Run `lqconfig migrate` to import it.");
+      }
+    },
+    None => {
+      println!("Run with --help to see instructions");
+      exit(0);
+    }
+  }
+
+  Ok(())
+}
@@ -0,0 +1,18 @@
+use serde::{Deserialize, Serialize};
+
+/// A circuit's burst allowance: how much of it is left, and whether
+/// the circuit is using it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BurstStatus {
+  /// The circuit's ID from `ShapedDevices.csv`
+  pub circuit_id: String,
+
+  /// The burst rates (download, upload), in Mbps
+  pub burst_mbps: (u32, u32),
+
+  /// How full each burst bucket is (download, upload), from 0 to 100
+  pub bucket_percent: (f32, f32),
+
+  /// Is each direction currently bursting (download, upload)?
+  pub bursting: (bool, bool),
+}
@@ -12,6 +12,10 @@ pub struct QueueStoreTransit {
   //pub prev_upload: Option<CakeTransit>,
   pub current_download: CakeTransit,
   pub current_upload: CakeTransit,
+  /// The queue discipline in use (download, upload), e.g. `cake`
+  pub kind: (String, String),
+  /// The circuit's SQM profile, if it doesn't use the global `sqm`
+  pub sqm_profile: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
@@ -1,5 +1,5 @@
-use crate::TcHandle;
-use lqos_config::Tunables;
+use crate::{TcHandle, TraceFrame};
+use lqos_config::{HistoryRange, ShapedDevice, Tunables};
 use serde::{Deserialize, Serialize};
 
 /// One or more `BusRequest` objects must be included in a `BusSession`
@@ -20,6 +20,8 @@ pub enum BusRequest {
     start: u32,
     /// Last row to retrieve (10 for top-10 starting at 0)
     end: u32,
+    /// Only include hosts whose circuit has this tag (name, value)
+    tag: Option<(String, String)>,
   },
 
   /// Retrieves the TopN hosts with the worst RTT, sorted by RTT descending.
@@ -28,6 +30,8 @@ pub enum BusRequest {
     start: u32,
     /// Last row to retrieve (10 for top-10 starting at 0)
     end: u32,
+    /// Only include hosts whose circuit has this tag (name, value)
+    tag: Option<(String, String)>,
   },
 
   /// Retrieves the TopN hosts with the best RTT, sorted by RTT descending.
@@ -36,6 +40,8 @@ pub enum BusRequest {
     start: u32,
     /// Last row to retrieve (10 for top-10 starting at 0)
     end: u32,
+    /// Only include hosts whose circuit has this tag (name, value)
+    tag: Option<(String, String)>,
   },
 
   /// Retrieves current byte counters for all hosts.
@@ -117,6 +123,13 @@ pub enum BusRequest {
     parent: usize,
   },
 
+  /// Request details of part of the network tree, using the parent's
+  /// stable node id (which doesn't change when `network.json` is edited)
+  GetNetworkMapById {
+    /// The `id` of the parent of the map to retrieve
+    id: String,
+  },
+
   /// Retrieves the top N queues from the root level, and summarizes
   /// the others as "other"
   TopMapQueues(usize),
@@ -148,6 +161,135 @@ pub enum BusRequest {
   /// Request data from the long-term stats system
   GetLongTermStats(StatsRequest),
 
+  /// Run a frame through the loaded XDP and TC programs (without
+  /// sending it anywhere), and explain how it was classified.
+  TracePacket {
+    /// The interface whose programs should be used
+    interface: String,
+    /// The frame to trace
+    frame: TraceFrame,
+  },
+
+  /// Explain everything known about an IP address: which circuit it
+  /// belongs to, how it is mapped in XDP, where its queue is, and
+  /// its current counters.
+  ExplainIp(String),
+
+  /// Retrieve the results of the startup self-test of the packet
+  /// dissector and classifier.
+  GetSelfTestResults,
+
+  /// Request the current throughput of each shaping bridge pair.
+  /// Returns a `BusResponse::PairThroughput` value.
+  GetPairThroughput,
+
+  /// Validate `ShapedDevices.csv` and `network.json` together, and
+  /// report everything found. Returns a
+  /// `BusResponse::ValidationFindings` value.
+  ValidateConfiguration,
+
+  /// List the saved revisions of the configuration files, newest
+  /// first. Returns a `BusResponse::ConfigRevisions` value.
+  ListConfigRevisions,
+
+  /// Save the current configuration files as a new revision, after
+  /// changing them. Returns a `BusResponse::ConfigRevision` value.
+  RecordConfigRevision {
+    /// Who made the change
+    author: String,
+    /// Why the change was made
+    comment: String,
+  },
+
+  /// Compare two configuration revisions. Returns a
+  /// `BusResponse::ConfigRevisionDiff` value.
+  DiffConfigRevisions {
+    /// The older revision
+    from: String,
+    /// The newer revision, or `None` for the current files
+    to: Option<String>,
+  },
+
+  /// Put the files from a configuration revision back in place, and
+  /// reload LibreQoS. Returns a `BusResponse::ConfigRevision` value
+  /// describing the new revision made by the rollback.
+  RollbackConfigRevision {
+    /// The revision to roll back to
+    id: String,
+    /// Who requested the rollback
+    author: String,
+  },
+
+  /// Report the rates circuits are currently shaped at, after their
+  /// rate plans are applied. Returns a `BusResponse::CircuitRates`
+  /// value.
+  GetEffectiveCircuitRates {
+    /// Only report this circuit, rather than every circuit
+    circuit_id: Option<String>,
+  },
+
+  /// Report circuits' data usage against their quotas. Returns a
+  /// `BusResponse::QuotaUsage` value.
+  GetQuotaUsage {
+    /// Only report this circuit, rather than every circuit
+    circuit_id: Option<String>,
+  },
+
+  /// Grant a circuit extra data for its current quota period. Returns
+  /// a `BusResponse::QuotaUsage` value for the circuit.
+  GrantQuotaTopUp {
+    /// The circuit to top up
+    circuit_id: String,
+    /// How much to add, in gigabytes
+    gigabytes: u64,
+  },
+
+  /// Forget a circuit's usage and top-ups, restoring its normal rates
+  /// if it was throttled. Returns a `BusResponse::QuotaUsage` value for
+  /// the circuit.
+  ResetQuotaUsage {
+    /// The circuit to reset
+    circuit_id: String,
+  },
+
+  /// Report circuits' burst allowances. Returns a
+  /// `BusResponse::BurstStatus` value.
+  GetBurstStatus {
+    /// Only report this circuit, rather than every circuit
+    circuit_id: Option<String>,
+  },
+
+  /// Add a circuit, or replace an existing circuit's devices, and
+  /// shape it straight away rather than waiting for a reload. Every
+  /// device must have the same circuit ID. The change is saved to
+  /// `ShapedDevices.csv` and recorded as a configuration revision.
+  /// Returns an `Ack`.
+  UpsertCircuit {
+    /// The circuit's devices
+    devices: Vec<ShapedDevice>,
+    /// Who made the change, for the revision history
+    author: String,
+  },
+
+  /// Stop shaping a circuit and remove its devices from
+  /// `ShapedDevices.csv`, recording a configuration revision. Returns
+  /// an `Ack`.
+  RemoveCircuit {
+    /// The circuit to remove
+    circuit_id: String,
+    /// Who made the change, for the revision history
+    author: String,
+  },
+
+  /// Retrieve a circuit's recorded history over a time range. Returns
+  /// a `BusResponse::CircuitHistory` value.
+  GetCircuitHistory {
+    /// The circuit to report
+    circuit_id: String,
+    /// How far back to go
+    range: HistoryRange,
+  },
+
   /// If running on Equinix (the `equinix_test` feature is enabled),
   /// display a "run bandwidht test" link.
   #[cfg(feature = "equinix_tests")]
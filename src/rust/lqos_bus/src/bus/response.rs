@@ -1,6 +1,8 @@
 use super::QueueStoreTransit;
 use crate::{
-  ip_stats::PacketHeader, FlowTransport, IpMapping, IpStats, XdpPpingResult,
+  ip_stats::PacketHeader, BurstStatus, CircuitRateStatus, FlowTransport,
+  IpExplanation, IpMapping, IpStats, PacketTrace, PairThroughput,
+  QuotaStatus, SelfTestResult, XdpPpingResult,
 };
 use lts_client::transport_data::{StatsTotals, StatsHost, StatsTreeNode};
 use serde::{Deserialize, Serialize};
@@ -116,4 +118,42 @@ pub enum BusResponse {
 
   /// Long-term stats tree
   LongTermTree(Vec<StatsTreeNode>),
+
+  /// Explanation of how a traced packet was classified
+  PacketTrace(PacketTrace),
+
+  /// Everything known about a single IP address
+  IpExplanation(Box<IpExplanation>),
+
+  /// Results of the startup self-test. `None` if the self-test is
+  /// disabled, or hasn't finished.
+  SelfTestResults(Option<Vec<SelfTestResult>>),
+
+  /// Current throughput of each shaping bridge pair
+  PairThroughput(Vec<PairThroughput>),
+
+  /// Everything found while validating `ShapedDevices.csv` and
+  /// `network.json`. Empty if nothing was wrong.
+  ValidationFindings(Vec<lqos_config::ValidationFinding>),
+
+  /// Saved configuration revisions, newest first
+  ConfigRevisions(Vec<lqos_config::RevisionInfo>),
+
+  /// A single configuration revision
+  ConfigRevision(lqos_config::RevisionInfo),
+
+  /// Differences between two configuration revisions
+  ConfigRevisionDiff(lqos_config::RevisionDiff),
+
+  /// Circuits' current effective rates
+  CircuitRates(Vec<CircuitRateStatus>),
+
+  /// Circuits' data usage against their quotas
+  QuotaUsage(Vec<QuotaStatus>),
+
+  /// Circuits' burst allowances
+  BurstStatus(Vec<BurstStatus>),
+
+  /// A circuit's recorded history, oldest first
+  CircuitHistory(Vec<lqos_config::CircuitSample>),
 }
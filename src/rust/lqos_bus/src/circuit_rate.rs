@@ -0,0 +1,30 @@
+use lqos_config::CircuitRates;
+use serde::{Deserialize, Serialize};
+
+/// The rates a circuit is being shaped at right now, after applying
+/// its rate plan (if it has one).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CircuitRateStatus {
+  /// The circuit's ID from `ShapedDevices.csv`
+  pub circuit_id: String,
+
+  /// The rate plan assigned to the circuit, if any
+  pub plan: Option<String>,
+
+  /// The plan window currently in effect, if any. Unnamed windows are
+  /// described by their times.
+  pub window: Option<String>,
+
+  /// Why the circuit is limited below its plan, if it is (for example,
+  /// a used-up data quota)
+  pub override_reason: Option<String>,
+
+  /// Is the circuit's ceiling raised while it bursts?
+  pub bursting: bool,
+
+  /// The circuit's rates from `ShapedDevices.csv`
+  pub base: CircuitRates,
+
+  /// The rates currently applied to the circuit's HTB classes
+  pub effective: CircuitRates,
+}
@@ -0,0 +1,111 @@
+use crate::{IpMapping, TcHandle};
+use serde::{Deserialize, Serialize};
+
+/// Everything `lqosd` knows about a single IP address, gathered from
+/// `ShapedDevices.csv`, the XDP mapping table, `network.json`,
+/// `queuingStructure.json` and the throughput tracker.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct IpExplanation {
+  /// The IP address being explained
+  pub ip: String,
+
+  /// The `ShapedDevices.csv` row that matched, if any
+  pub shaped_device: Option<ExplainedDevice>,
+
+  /// The most specific entry in the XDP IP mapping table that covers
+  /// this address, if any
+  pub xdp_mapping: Option<IpMapping>,
+
+  /// The most specific entry in the upload mapping table, which is
+  /// only filled on a stick, if any
+  pub xdp_upload_mapping: Option<IpMapping>,
+
+  /// The queue assigned to the matched circuit in
+  /// `queuingStructure.json`, if any
+  pub queue: Option<CircuitQueue>,
+
+  /// Names of the `network.json` nodes above the circuit, starting
+  /// at the top of the tree.
+  pub network_parents: Vec<String>,
+
+  /// Traffic counters from the throughput tracker, if the IP has
+  /// been seen.
+  pub counters: Option<ExplainedCounters>,
+
+  /// Is the address inside `allowedSubnets`?
+  pub in_allowed_subnets: bool,
+
+  /// Is the address inside `ignoredSubnets`?
+  pub in_ignored_subnets: bool,
+
+  /// Would the address be listed as an unknown IP? (Seen recently,
+  /// but not shaped)
+  pub unknown: bool,
+
+  /// Human readable observations, including any disagreement between
+  /// the sources.
+  pub notes: Vec<String>,
+}
+
+/// The `ShapedDevices.csv` entry that matched an IP address.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ExplainedDevice {
+  /// Circuit ID
+  pub circuit_id: String,
+  /// Circuit name
+  pub circuit_name: String,
+  /// Device ID
+  pub device_id: String,
+  /// Device name
+  pub device_name: String,
+  /// Parent node in `network.json`
+  pub parent_node: String,
+  /// The prefix from the CSV that matched, e.g. `100.64.1.0/24`
+  pub matched_prefix: String,
+  /// Maximum download, in Mbps
+  pub download_max_mbps: u32,
+  /// Maximum upload, in Mbps
+  pub upload_max_mbps: u32,
+}
+
+/// A circuit's queue, as listed in `queuingStructure.json`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CircuitQueue {
+  /// Download class
+  pub class_id: TcHandle,
+  /// Upload class
+  pub up_class_id: TcHandle,
+  /// Parent of the download class
+  pub parent_class_id: TcHandle,
+  /// CPU handling download
+  pub cpu: u32,
+  /// CPU handling upload
+  pub up_cpu: u32,
+  /// Maximum download, in Mbps
+  pub download_bandwidth_mbps: u64,
+  /// Maximum upload, in Mbps
+  pub upload_bandwidth_mbps: u64,
+  /// The circuit's SQM profile, if it doesn't use the global `sqm`
+  pub sqm_profile: Option<String>,
+  /// The profile's queue discipline and options, e.g. `fq_codel`
+  pub sqm: Option<String>,
+}
+
+/// Throughput tracker counters for a single IP address.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ExplainedCounters {
+  /// Total bytes (down, up)
+  pub bytes: (u64, u64),
+  /// Total packets (down, up)
+  pub packets: (u64, u64),
+  /// Current bits per second (down, up)
+  pub bits_per_second: (u64, u64),
+  /// Current packets per second (down, up)
+  pub packets_per_second: (u64, u64),
+  /// Median TCP round-trip time in ms, if enough samples exist
+  pub median_tcp_rtt: Option<f32>,
+  /// The TC handle the kernel reported for this IP's traffic
+  pub tc_handle: TcHandle,
+  /// The circuit ID the tracker associated with this IP
+  pub circuit_id: Option<String>,
+}
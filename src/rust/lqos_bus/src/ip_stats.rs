@@ -1,5 +1,6 @@
 use crate::TcHandle;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 /// Transmission representation of IP statistics associated
 /// with a host.
@@ -24,6 +25,10 @@ pub struct IpStats {
 
   /// Associated TC traffic control handle.
   pub tc_handle: TcHandle,
+
+  /// Tags from the host's circuit in `ShapedDevices.csv`, such as plan
+  /// or tower.
+  pub tags: BTreeMap<String, String>,
 }
 
 /// Represents an IP Mapping in the XDP IP to TC/CPU mapping system.
@@ -17,6 +17,22 @@ pub use ip_stats::{
   XdpPpingResult,
 };
 mod tc_handle;
+mod packet_trace;
+pub use packet_trace::{PacketTrace, TraceDirection, TraceFrame};
+mod ip_explanation;
+mod self_test;
+pub use self_test::SelfTestResult;
+mod pair_throughput;
+pub use pair_throughput::PairThroughput;
+mod circuit_rate;
+pub use circuit_rate::CircuitRateStatus;
+mod quota_status;
+pub use quota_status::QuotaStatus;
+mod burst_status;
+pub use burst_status::BurstStatus;
+pub use ip_explanation::{
+  CircuitQueue, ExplainedCounters, ExplainedDevice, IpExplanation,
+};
 pub use bus::{
   bus_request, decode_request, decode_response, encode_request,
   encode_response, BusClient, BusReply, BusRequest, BusResponse, BusSession,
@@ -0,0 +1,89 @@
+use crate::TcHandle;
+use serde::{Deserialize, Serialize};
+
+/// The frame to run through the XDP/TC programs when tracing
+/// packet classification.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum TraceFrame {
+  /// A raw Ethernet frame, starting at the destination MAC address.
+  Raw(Vec<u8>),
+
+  /// Ask `lqosd` to build a frame from a description.
+  Synthetic {
+    /// Source IP address (IPv4 or IPv6)
+    src: String,
+    /// Destination IP address (same family as `src`)
+    dst: String,
+    /// 802.1Q VLAN tag, if any
+    vlan: Option<u16>,
+    /// IP protocol number (e.g. 6 for TCP, 17 for UDP)
+    protocol: u8,
+  },
+}
+
+/// The direction in which the eBPF programs decided a packet was
+/// travelling.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceDirection {
+  /// Classification didn't get far enough to decide.
+  Unknown,
+  /// Arriving from the Internet, heading to a customer.
+  Download,
+  /// Arriving from a customer, heading to the Internet.
+  Upload,
+}
+
+impl From<u32> for TraceDirection {
+  fn from(direction: u32) -> Self {
+    match direction {
+      1 => Self::Download,
+      2 => Self::Upload,
+      _ => Self::Unknown,
+    }
+  }
+}
+
+/// Explains how the loaded XDP and TC programs handled a single
+/// frame, obtained with `BPF_PROG_TEST_RUN`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PacketTrace {
+  /// The interface whose programs were used
+  pub interface: String,
+
+  /// The XDP verdict, e.g. `XDP_REDIRECT`
+  pub xdp_verdict: String,
+
+  /// Did the XDP dissector find an IP header?
+  pub dissected: bool,
+
+  /// Did the LPM lookup find a matching entry?
+  pub matched: bool,
+
+  /// The direction chosen by the XDP program
+  pub effective_direction: TraceDirection,
+
+  /// The CPU chosen by the LPM entry, if there was a match.
+  pub cpu: Option<u32>,
+
+  /// The TC handle chosen by the LPM entry (XDP side)
+  pub tc_handle: TcHandle,
+
+  /// VLAN tags (outermost first) before XDP processing
+  pub vlans_in: Vec<u16>,
+
+  /// VLAN tags (outermost first) after XDP processing. If these
+  /// differ from `vlans_in`, Bifrost rewrote the tag.
+  pub vlans_out: Vec<u16>,
+
+  /// The TC egress verdict, e.g. `TC_ACT_OK`
+  pub tc_verdict: String,
+
+  /// The TC handle the egress program assigned (`skb->priority`)
+  pub tc_egress_handle: TcHandle,
+
+  /// The direction chosen by the TC egress program
+  pub tc_direction: TraceDirection,
+
+  /// Human readable observations about the classification
+  pub notes: Vec<String>,
+}
@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+
+/// Throughput through one shaping bridge pair, as declared in
+/// `/etc/lqos.conf` (or the single pair from `ispConfig.py`).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PairThroughput {
+  /// The pair's name
+  pub name: String,
+
+  /// The interfaces the pair is attached to
+  pub interfaces: Vec<String>,
+
+  /// Current bits per second (down, up)
+  pub bits_per_second: (u64, u64),
+
+  /// Current packets per second (down, up)
+  pub packets_per_second: (u64, u64),
+
+  /// Total bytes since the daemon started (down, up)
+  pub bytes: (u64, u64),
+
+  /// Total packets since the daemon started (down, up)
+  pub packets: (u64, u64),
+}
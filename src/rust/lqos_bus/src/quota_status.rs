@@ -0,0 +1,32 @@
+use serde::{Deserialize, Serialize};
+
+/// A circuit's data usage against its quota.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct QuotaStatus {
+  /// The circuit's ID from `ShapedDevices.csv`
+  pub circuit_id: String,
+
+  /// The name of the quota assigned to the circuit
+  pub quota: String,
+
+  /// The first day of the current period (YYYY-MM-DD)
+  pub period_start: String,
+
+  /// When usage next resets (YYYY-MM-DD). `None` for rolling quotas.
+  pub next_reset: Option<String>,
+
+  /// Bytes used this period (download, upload)
+  pub used_bytes: (u64, u64),
+
+  /// The quota, in bytes
+  pub limit_bytes: u64,
+
+  /// Extra bytes granted by top-ups this period
+  pub top_up_bytes: u64,
+
+  /// Has the circuit been slowed to its fair-use rates?
+  pub throttled: bool,
+
+  /// The fair-use rates (download, upload), in Mbps
+  pub fair_use_mbps: (u64, u64),
+}
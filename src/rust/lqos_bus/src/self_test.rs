@@ -0,0 +1,19 @@
+use crate::PacketTrace;
+use serde::{Deserialize, Serialize};
+
+/// The outcome of one case in `lqosd`'s startup self-test, in which
+/// a synthetic frame is run through the loaded XDP/TC programs.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SelfTestResult {
+  /// Description of the case, e.g. "IPv4 QinQ download"
+  pub name: String,
+
+  /// Did the programs classify the frame as expected?
+  pub passed: bool,
+
+  /// The differences from the expected classification
+  pub problems: Vec<String>,
+
+  /// The full trace, if the frame could be run at all
+  pub trace: Option<PacketTrace>,
+}
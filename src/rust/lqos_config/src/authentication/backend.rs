@@ -0,0 +1,209 @@
+//! Password backends: where a web user's password is checked. Local
+//! accounts live in `lqusers.toml`; the others ask a central server,
+//! so that staff don't need local passwords.
+
+use super::UserRole;
+use serde::{Deserialize, Serialize};
+
+/// Which backend checks web users' passwords, as set in the `[web_ui]`
+/// section of `/etc/lqos.conf`
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthBackendKind {
+  /// Passwords in `lqusers.toml`
+  #[default]
+  Local,
+  /// A bind to an LDAP directory, with the role taken from the user's
+  /// groups
+  Ldap,
+  /// A RADIUS PAP request, with the role taken from an attribute of
+  /// the reply
+  Radius,
+}
+
+/// When a remote backend falls back to local accounts
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LocalFallback {
+  /// Never; only the server's users may sign in
+  Never,
+  /// When the server can't be reached, so that local accounts work
+  /// during an outage
+  #[default]
+  Unreachable,
+  /// When the server can't be reached or rejects the user, so that
+  /// local accounts work alongside the server's
+  Always,
+}
+
+/// A backend's answer to a username and password
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BackendResult {
+  /// The password is right, and the user has this role
+  Accepted(UserRole),
+  /// The password is wrong, or the user has no role here
+  Rejected,
+  /// The server couldn't be asked, for this reason
+  Unreachable(String),
+}
+
+/// Something that can check a username and password
+pub trait PasswordBackend {
+  /// The backend's name, such as "ldap"
+  fn name(&self) -> &'static str;
+
+  /// Checks a username and password. Blocks until the server answers
+  /// or times out.
+  fn authenticate(&self, username: &str, password: &str) -> BackendResult;
+
+  /// When to try local accounts instead
+  fn local_fallback(&self) -> LocalFallback;
+}
+
+/// The configured backend's verdict on a sign-in, from
+/// `WebUsers::check_backend`, for `WebUsers::login_checked`
+#[derive(Clone, Debug)]
+pub struct BackendCheck {
+  pub(super) backend: &'static str,
+  /// `None` for local accounts
+  pub(super) result: Option<BackendResult>,
+  pub(super) fallback: LocalFallback,
+}
+
+impl BackendCheck {
+  /// Local accounts only, whatever backend is configured
+  pub fn local() -> Self {
+    Self {
+      backend: "local",
+      result: None,
+      fallback: LocalFallback::Never,
+    }
+  }
+
+  /// The name of the backend asked, such as "ldap"
+  pub fn backend(&self) -> &'static str {
+    self.backend
+  }
+
+  /// The backend's answer, or `None` for local accounts
+  pub fn result(&self) -> Option<&BackendResult> {
+    self.result.as_ref()
+  }
+
+  /// Asks a backend
+  pub(super) fn ask(
+    backend: &dyn PasswordBackend,
+    username: &str,
+    password: &str,
+  ) -> Self {
+    // An empty password is an anonymous bind to many servers
+    let result = if password.is_empty() {
+      BackendResult::Rejected
+    } else {
+      backend.authenticate(username, password)
+    };
+    Self {
+      backend: backend.name(),
+      result: Some(result),
+      fallback: backend.local_fallback(),
+    }
+  }
+
+  /// Asks the backend configured in `/etc/lqos.conf`. A backend
+  /// without its settings section can't be reached.
+  pub(super) fn configured(username: &str, password: &str) -> Self {
+    let web_ui = crate::EtcLqos::load().ok().and_then(|cfg| cfg.web_ui);
+    let Some(web_ui) = web_ui else {
+      return Self::local();
+    };
+    let missing = |backend, section: &str| Self {
+      backend,
+      result: Some(BackendResult::Unreachable(format!(
+        "/etc/lqos.conf has no [web_ui.{section}] section"
+      ))),
+      fallback: LocalFallback::Unreachable,
+    };
+    match web_ui.backend {
+      AuthBackendKind::Local => Self::local(),
+      AuthBackendKind::Ldap => match &web_ui.ldap {
+        Some(ldap) => Self::ask(ldap, username, password),
+        None => missing("ldap", "ldap"),
+      },
+      AuthBackendKind::Radius => match &web_ui.radius {
+        Some(radius) => Self::ask(radius, username, password),
+        None => missing("radius", "radius"),
+      },
+    }
+  }
+}
+
+/// Picks a role from a user's groups or attribute values: `Admin` if
+/// any is an admin value, otherwise `ReadOnly` if any is a read-only
+/// value, otherwise `default`. Values are compared ignoring case.
+pub(super) fn map_role<'a>(
+  values: impl IntoIterator<Item = &'a str> + Clone,
+  admin: &[String],
+  read_only: &[String],
+  default: Option<UserRole>,
+) -> Option<UserRole> {
+  let any_of = |wanted: &[String]| {
+    values
+      .clone()
+      .into_iter()
+      .any(|v| wanted.iter().any(|w| w.eq_ignore_ascii_case(v)))
+  };
+  if any_of(admin) {
+    Some(UserRole::Admin)
+  } else if any_of(read_only) {
+    Some(UserRole::ReadOnly)
+  } else {
+    default
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn roles_are_mapped() {
+    let admin = vec!["cn=NetOps,ou=groups".to_string()];
+    let read_only = vec!["cn=support,ou=groups".to_string()];
+    let role = |groups: &[&str], default| {
+      map_role(groups.iter().copied(), &admin, &read_only, default)
+    };
+    assert_eq!(
+      role(&["cn=support,ou=groups", "cn=netops,ou=groups"], None),
+      Some(UserRole::Admin)
+    );
+    assert_eq!(role(&["cn=support,ou=groups"], None), Some(UserRole::ReadOnly));
+    assert_eq!(role(&["cn=sales,ou=groups"], None), None);
+    assert_eq!(
+      role(&[], Some(UserRole::ReadOnly)),
+      Some(UserRole::ReadOnly)
+    );
+  }
+
+  struct Fixed(BackendResult);
+
+  impl PasswordBackend for Fixed {
+    fn name(&self) -> &'static str {
+      "fixed"
+    }
+    fn authenticate(&self, _: &str, _: &str) -> BackendResult {
+      self.0.clone()
+    }
+    fn local_fallback(&self) -> LocalFallback {
+      LocalFallback::Never
+    }
+  }
+
+  #[test]
+  fn empty_passwords_are_never_sent() {
+    let backend = Fixed(BackendResult::Accepted(UserRole::Admin));
+    let check = BackendCheck::ask(&backend, "admin", "");
+    assert_eq!(check.result, Some(BackendResult::Rejected));
+    let check = BackendCheck::ask(&backend, "admin", "pw");
+    assert_eq!(check.result, Some(BackendResult::Accepted(UserRole::Admin)));
+  }
+}
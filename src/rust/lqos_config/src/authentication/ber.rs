@@ -0,0 +1,168 @@
+//! Just enough ASN.1 BER to speak LDAP: definite lengths and
+//! single-byte tags.
+
+use std::io::{self, Read};
+
+/// The largest message we'll read, to bound memory if a server (or
+/// something that isn't one) sends nonsense
+const MAX_MESSAGE: usize = 1024 * 1024;
+
+pub(super) const INTEGER: u8 = 0x02;
+pub(super) const OCTET_STRING: u8 = 0x04;
+pub(super) const ENUMERATED: u8 = 0x0a;
+pub(super) const BOOLEAN: u8 = 0x01;
+pub(super) const SEQUENCE: u8 = 0x30;
+pub(super) const SET: u8 = 0x31;
+
+/// Encodes a tag, length and content
+pub(super) fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+  let mut out = vec![tag];
+  let len = content.len();
+  if len < 0x80 {
+    out.push(len as u8);
+  } else {
+    let bytes: Vec<u8> = len
+      .to_be_bytes()
+      .into_iter()
+      .skip_while(|b| *b == 0)
+      .collect();
+    out.push(0x80 | bytes.len() as u8);
+    out.extend(bytes);
+  }
+  out.extend_from_slice(content);
+  out
+}
+
+/// Encodes a constructed value from its already-encoded parts
+pub(super) fn constructed(tag: u8, parts: &[Vec<u8>]) -> Vec<u8> {
+  tlv(tag, &parts.concat())
+}
+
+/// Encodes a non-negative integer (or enumeration)
+pub(super) fn integer(tag: u8, value: u32) -> Vec<u8> {
+  let mut bytes: Vec<u8> = value
+    .to_be_bytes()
+    .into_iter()
+    .skip_while(|b| *b == 0)
+    .collect();
+  // Zero needs a byte, and a leading 1 bit would read as negative
+  if bytes.is_empty() || bytes[0] & 0x80 != 0 {
+    bytes.insert(0, 0);
+  }
+  tlv(tag, &bytes)
+}
+
+/// A decoded tag and its content
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) struct Element<'a> {
+  pub(super) tag: u8,
+  pub(super) content: &'a [u8],
+}
+
+impl<'a> Element<'a> {
+  /// Decodes the first element of `data`, returning it and the rest
+  pub(super) fn parse(data: &'a [u8]) -> Option<(Self, &'a [u8])> {
+    let (&tag, data) = data.split_first()?;
+    let (&first, data) = data.split_first()?;
+    let (len, data) = if first < 0x80 {
+      (first as usize, data)
+    } else {
+      let count = (first & 0x7f) as usize;
+      if count == 0 || count > 4 || data.len() < count {
+        return None;
+      }
+      let len = data[..count].iter().fold(0, |n, b| n << 8 | *b as usize);
+      (len, &data[count..])
+    };
+    if data.len() < len {
+      return None;
+    }
+    Some((Self { tag, content: &data[..len] }, &data[len..]))
+  }
+
+  /// The elements inside a constructed element
+  pub(super) fn children(&self) -> Option<Vec<Element<'a>>> {
+    let mut rest = self.content;
+    let mut children = Vec::new();
+    while !rest.is_empty() {
+      let (child, after) = Element::parse(rest)?;
+      children.push(child);
+      rest = after;
+    }
+    Some(children)
+  }
+
+  /// The value of an integer or enumeration
+  pub(super) fn integer(&self) -> Option<u32> {
+    if self.content.is_empty() || self.content.len() > 5 {
+      return None;
+    }
+    let value = self.content.iter().fold(0u64, |n, b| n << 8 | *b as u64);
+    u32::try_from(value).ok()
+  }
+
+  /// The content as text
+  pub(super) fn text(&self) -> String {
+    String::from_utf8_lossy(self.content).to_string()
+  }
+}
+
+/// Reads one whole element from a stream
+pub(super) fn read_element(stream: &mut impl Read) -> io::Result<Vec<u8>> {
+  let mut header = [0u8; 2];
+  stream.read_exact(&mut header)?;
+  let mut message = header.to_vec();
+  let len = if header[1] < 0x80 {
+    header[1] as usize
+  } else {
+    let count = (header[1] & 0x7f) as usize;
+    if count == 0 || count > 4 {
+      return Err(io::Error::new(io::ErrorKind::InvalidData, "bad length"));
+    }
+    let mut bytes = vec![0u8; count];
+    stream.read_exact(&mut bytes)?;
+    message.extend_from_slice(&bytes);
+    bytes.iter().fold(0, |n, b| n << 8 | *b as usize)
+  };
+  if len > MAX_MESSAGE {
+    return Err(io::Error::new(io::ErrorKind::InvalidData, "too long"));
+  }
+  let start = message.len();
+  message.resize(start + len, 0);
+  stream.read_exact(&mut message[start..])?;
+  Ok(message)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn lengths_round_trip() {
+    for len in [0, 5, 127, 128, 300, 70_000] {
+      let content = vec![7u8; len];
+      let encoded = tlv(OCTET_STRING, &content);
+      let (element, rest) = Element::parse(&encoded).unwrap();
+      assert_eq!(element.content.len(), len);
+      assert!(rest.is_empty());
+      let read = read_element(&mut encoded.as_slice()).unwrap();
+      assert_eq!(read, encoded);
+    }
+  }
+
+  #[test]
+  fn integers_round_trip() {
+    for value in [0, 3, 127, 128, 65_535, u32::MAX] {
+      let encoded = integer(INTEGER, value);
+      let (element, _) = Element::parse(&encoded).unwrap();
+      assert_eq!(element.integer(), Some(value));
+    }
+    assert_eq!(integer(INTEGER, 128), vec![INTEGER, 2, 0, 128]);
+  }
+
+  #[test]
+  fn truncated_data_is_rejected() {
+    assert_eq!(Element::parse(&[OCTET_STRING, 4, 1, 2]), None);
+    assert_eq!(Element::parse(&[OCTET_STRING, 0x82, 1]), None);
+  }
+}
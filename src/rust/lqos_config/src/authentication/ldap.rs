@@ -0,0 +1,736 @@
+//! The LDAP password backend. It binds to the directory as the user to
+//! check their password, and picks their role from their groups.
+
+use super::{
+  backend::{map_role, BackendResult, LocalFallback, PasswordBackend},
+  ber::{self, Element},
+  UserRole,
+};
+use log::{info, warn};
+use native_tls::TlsConnector;
+use serde::{Deserialize, Serialize};
+use std::{
+  fmt::Display,
+  io::{self, Read, Write},
+  net::{TcpStream, ToSocketAddrs},
+  time::Duration,
+};
+
+const BIND_REQUEST: u8 = 0x60;
+const BIND_RESPONSE: u8 = 0x61;
+const UNBIND_REQUEST: u8 = 0x42;
+const SEARCH_REQUEST: u8 = 0x63;
+const SEARCH_ENTRY: u8 = 0x64;
+const SEARCH_DONE: u8 = 0x65;
+const SIMPLE_AUTH: u8 = 0x80;
+
+const SCOPE_BASE: u32 = 0;
+const SCOPE_SUBTREE: u32 = 2;
+
+const SUCCESS: u32 = 0;
+const SIZE_LIMIT_EXCEEDED: u32 = 4;
+const INVALID_CREDENTIALS: u32 = 49;
+
+/// Settings for the LDAP backend: the `[web_ui.ldap]` section of
+/// `/etc/lqos.conf`
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct LdapSettings {
+  /// The server, as `ldaps://host[:port]`, or `ldap://host[:port]` on
+  /// a trusted network (passwords are sent in the clear)
+  pub url: String,
+
+  /// The DN to bind as, with `{username}` in place of the username,
+  /// such as `uid={username},ou=people,dc=example,dc=com`. If unset,
+  /// the user is found by searching under `base_dn`.
+  pub user_dn: Option<String>,
+
+  /// Where to search for users, such as `ou=people,dc=example,dc=com`
+  pub base_dn: Option<String>,
+
+  /// How to find a user, with `{username}` in place of the username.
+  /// Defaults to `(uid={username})`.
+  #[serde(default = "default_user_filter")]
+  pub user_filter: String,
+
+  /// The DN to bind as to search for users, if the directory doesn't
+  /// allow anonymous searches
+  pub search_dn: Option<String>,
+
+  /// The password for `search_dn`
+  pub search_password: Option<String>,
+
+  /// The attribute of a user's entry that lists their groups.
+  /// Defaults to `memberOf`.
+  #[serde(default = "default_group_attribute")]
+  pub group_attribute: String,
+
+  /// Members of these groups are admins
+  #[serde(default)]
+  pub admin_groups: Vec<String>,
+
+  /// Members of these groups have read-only access
+  #[serde(default)]
+  pub read_only_groups: Vec<String>,
+
+  /// The role of users in none of the groups above. If unset, they
+  /// can't sign in.
+  pub default_role: Option<UserRole>,
+
+  /// How long to wait for the server. Defaults to 5 seconds.
+  #[serde(default = "default_timeout")]
+  pub timeout_seconds: u64,
+
+  /// When to try local accounts instead
+  #[serde(default)]
+  pub local_fallback: LocalFallback,
+}
+
+fn default_user_filter() -> String {
+  "(uid={username})".to_string()
+}
+
+fn default_group_attribute() -> String {
+  "memberOf".to_string()
+}
+
+fn default_timeout() -> u64 {
+  5
+}
+
+impl PasswordBackend for LdapSettings {
+  fn name(&self) -> &'static str {
+    "ldap"
+  }
+
+  fn authenticate(&self, username: &str, password: &str) -> BackendResult {
+    match self.user_groups(username, password) {
+      Ok(Some(groups)) => {
+        let role = map_role(
+          groups.iter().map(|g| g.as_str()),
+          &self.admin_groups,
+          &self.read_only_groups,
+          self.default_role,
+        );
+        match role {
+          Some(role) => BackendResult::Accepted(role),
+          None => {
+            info!("LDAP user {username} isn't in any LibreQoS group");
+            BackendResult::Rejected
+          }
+        }
+      }
+      Ok(None) => BackendResult::Rejected,
+      Err(e) => BackendResult::Unreachable(format!("LDAP: {e}")),
+    }
+  }
+
+  fn local_fallback(&self) -> LocalFallback {
+    self.local_fallback
+  }
+}
+
+impl LdapSettings {
+  /// Binds as the user and reads their groups. `None` if the user
+  /// doesn't exist or the password is wrong.
+  fn user_groups(
+    &self,
+    username: &str,
+    password: &str,
+  ) -> Result<Option<Vec<String>>, LdapError> {
+    let timeout = Duration::from_secs(self.timeout_seconds.max(1));
+    let mut connection = Connection::open(&self.url, timeout)?;
+    let attributes = [self.group_attribute.as_str()];
+
+    if let Some(template) = &self.user_dn {
+      let dn = template.replace("{username}", &escape_dn(username));
+      if !connection.bind(&dn, password)? {
+        return Ok(None);
+      }
+      let filter = parse_filter("(objectClass=*)")?;
+      let entries = connection.search(&dn, SCOPE_BASE, filter, &attributes)?;
+      let entry = entries.into_iter().next();
+      return Ok(Some(entry.map(|e| e.values).unwrap_or_default()));
+    }
+
+    let Some(base_dn) = &self.base_dn else {
+      return Err(LdapError::Config("set either user_dn or base_dn"));
+    };
+    if let Some(search_dn) = &self.search_dn {
+      let search_password = self.search_password.as_deref().unwrap_or("");
+      if !connection.bind(search_dn, search_password)? {
+        return Err(LdapError::Config("search_dn's password was refused"));
+      }
+    }
+    let filter = self
+      .user_filter
+      .replace("{username}", &escape_filter_value(username));
+    let filter = parse_filter(&filter)?;
+    let mut entries =
+      connection.search(base_dn, SCOPE_SUBTREE, filter, &attributes)?;
+    if entries.len() > 1 {
+      warn!("More than one LDAP entry matches {username}; refusing");
+      return Ok(None);
+    }
+    let Some(entry) = entries.pop() else {
+      return Ok(None);
+    };
+    if !connection.bind(&entry.dn, password)? {
+      return Ok(None);
+    }
+    Ok(Some(entry.values))
+  }
+}
+
+#[derive(Debug)]
+enum LdapError {
+  Io(io::Error),
+  Tls(String),
+  Config(&'static str),
+  Protocol(&'static str),
+  Result(u32, String),
+}
+
+impl Display for LdapError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Self::Io(e) => write!(f, "{e}"),
+      Self::Tls(e) => write!(f, "TLS: {e}"),
+      Self::Config(e) => write!(f, "{e}"),
+      Self::Protocol(e) => write!(f, "unexpected reply: {e}"),
+      Self::Result(code, message) => write!(f, "error {code}: {message}"),
+    }
+  }
+}
+
+impl From<io::Error> for LdapError {
+  fn from(e: io::Error) -> Self {
+    Self::Io(e)
+  }
+}
+
+trait Stream: Read + Write {}
+impl<T: Read + Write> Stream for T {}
+
+/// A search result: the entry's DN, and the values of the attribute
+/// asked for
+struct Entry {
+  dn: String,
+  values: Vec<String>,
+}
+
+struct Connection {
+  stream: Box<dyn Stream>,
+  last_id: u32,
+}
+
+impl Connection {
+  fn open(url: &str, timeout: Duration) -> Result<Self, LdapError> {
+    let (tls, address, default_port) =
+      if let Some(address) = url.strip_prefix("ldaps://") {
+        (true, address, 636)
+      } else if let Some(address) = url.strip_prefix("ldap://") {
+        (false, address, 389)
+      } else {
+        return Err(LdapError::Config("url must be ldap:// or ldaps://"));
+      };
+    let address = address.trim_end_matches('/');
+    let (host, port) = match address.rsplit_once(':') {
+      Some((host, port)) if port.parse::<u16>().is_ok() => {
+        (host, port.parse().unwrap_or(default_port))
+      }
+      _ => (address, default_port),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    let mut last_error = None;
+    let mut tcp = None;
+    for address in (host, port).to_socket_addrs()? {
+      match TcpStream::connect_timeout(&address, timeout) {
+        Ok(stream) => {
+          tcp = Some(stream);
+          break;
+        }
+        Err(e) => last_error = Some(e),
+      }
+    }
+    let Some(tcp) = tcp else {
+      return Err(LdapError::Io(last_error.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "no address for the host")
+      })));
+    };
+    tcp.set_read_timeout(Some(timeout))?;
+    tcp.set_write_timeout(Some(timeout))?;
+    let stream: Box<dyn Stream> = if tls {
+      let connector =
+        TlsConnector::new().map_err(|e| LdapError::Tls(e.to_string()))?;
+      let stream = connector
+        .connect(host, tcp)
+        .map_err(|e| LdapError::Tls(e.to_string()))?;
+      Box::new(stream)
+    } else {
+      Box::new(tcp)
+    };
+    Ok(Self { stream, last_id: 0 })
+  }
+
+  fn send(&mut self, operation: Vec<u8>) -> Result<u32, LdapError> {
+    self.last_id += 1;
+    let message = ber::constructed(
+      ber::SEQUENCE,
+      &[ber::integer(ber::INTEGER, self.last_id), operation],
+    );
+    self.stream.write_all(&message)?;
+    Ok(self.last_id)
+  }
+
+  /// Reads the next reply to message `id`, returning the operation's
+  /// tag and content
+  fn receive(&mut self, id: u32) -> Result<(u8, Vec<u8>), LdapError> {
+    loop {
+      let message = ber::read_element(&mut self.stream)?;
+      let (message, _) =
+        Element::parse(&message).ok_or(LdapError::Protocol("bad message"))?;
+      let parts = message.children().unwrap_or_default();
+      let (Some(reply_id), Some(operation)) = (parts.first(), parts.get(1))
+      else {
+        return Err(LdapError::Protocol("bad message"));
+      };
+      match reply_id.integer() {
+        Some(reply_id) if reply_id == id => {
+          return Ok((operation.tag, operation.content.to_vec()));
+        }
+        // Unsolicited, such as a notice that the server is closing
+        Some(0) => return Err(LdapError::Protocol("disconnected")),
+        _ => continue,
+      }
+    }
+  }
+
+  /// Binds with a DN and password. Returns false if the server says
+  /// they're wrong.
+  fn bind(&mut self, dn: &str, password: &str) -> Result<bool, LdapError> {
+    let id = self.send(ber::constructed(
+      BIND_REQUEST,
+      &[
+        ber::integer(ber::INTEGER, 3),
+        ber::tlv(ber::OCTET_STRING, dn.as_bytes()),
+        ber::tlv(SIMPLE_AUTH, password.as_bytes()),
+      ],
+    ))?;
+    let (tag, content) = self.receive(id)?;
+    if tag != BIND_RESPONSE {
+      return Err(LdapError::Protocol("expected a bind response"));
+    }
+    match ldap_result(&content)? {
+      (SUCCESS, _) => Ok(true),
+      (INVALID_CREDENTIALS, _) => Ok(false),
+      (code, message) => Err(LdapError::Result(code, message)),
+    }
+  }
+
+  fn search(
+    &mut self,
+    base: &str,
+    scope: u32,
+    filter: Vec<u8>,
+    attributes: &[&str],
+  ) -> Result<Vec<Entry>, LdapError> {
+    let attributes: Vec<Vec<u8>> = attributes
+      .iter()
+      .map(|a| ber::tlv(ber::OCTET_STRING, a.as_bytes()))
+      .collect();
+    let id = self.send(ber::constructed(
+      SEARCH_REQUEST,
+      &[
+        ber::tlv(ber::OCTET_STRING, base.as_bytes()),
+        ber::integer(ber::ENUMERATED, scope),
+        ber::integer(ber::ENUMERATED, 0),
+        // Two is enough to notice an ambiguous username
+        ber::integer(ber::INTEGER, 2),
+        ber::integer(ber::INTEGER, 0),
+        ber::tlv(ber::BOOLEAN, &[0]),
+        filter,
+        ber::constructed(ber::SEQUENCE, &attributes),
+      ],
+    ))?;
+    let mut entries = Vec::new();
+    loop {
+      let (tag, content) = self.receive(id)?;
+      match tag {
+        SEARCH_ENTRY => entries.push(parse_entry(&content)?),
+        SEARCH_DONE => {
+          return match ldap_result(&content)? {
+            (SUCCESS | SIZE_LIMIT_EXCEEDED, _) => Ok(entries),
+            (code, message) => Err(LdapError::Result(code, message)),
+          };
+        }
+        // Search references point to other servers; ignore them
+        _ => {}
+      }
+    }
+  }
+}
+
+impl Drop for Connection {
+  fn drop(&mut self) {
+    let _ = self.send(ber::tlv(UNBIND_REQUEST, &[]));
+  }
+}
+
+/// Decodes an LDAPResult's code and diagnostic message
+fn ldap_result(content: &[u8]) -> Result<(u32, String), LdapError> {
+  let result = Element { tag: ber::SEQUENCE, content };
+  let parts = result.children().unwrap_or_default();
+  let code = parts.first().and_then(|c| c.integer());
+  let Some(code) = code else {
+    return Err(LdapError::Protocol("bad result"));
+  };
+  let message = parts.get(2).map(|m| m.text()).unwrap_or_default();
+  Ok((code, message))
+}
+
+fn parse_entry(content: &[u8]) -> Result<Entry, LdapError> {
+  let bad = LdapError::Protocol("bad search entry");
+  let entry = Element { tag: SEARCH_ENTRY, content };
+  let parts = entry.children().unwrap_or_default();
+  let (Some(dn), Some(attributes)) = (parts.first(), parts.get(1)) else {
+    return Err(bad);
+  };
+  let mut values = Vec::new();
+  for attribute in attributes.children().ok_or(bad)? {
+    let parts = attribute.children().unwrap_or_default();
+    if let Some(set) = parts.get(1).filter(|set| set.tag == ber::SET) {
+      let set = set.children().unwrap_or_default();
+      values.extend(set.iter().map(Element::text));
+    }
+  }
+  Ok(Entry { dn: dn.text(), values })
+}
+
+/// Escapes a value for a DN (RFC 4514)
+fn escape_dn(value: &str) -> String {
+  let last = value.chars().count().saturating_sub(1);
+  value
+    .chars()
+    .enumerate()
+    .map(|(i, c)| match c {
+      ',' | '+' | '"' | '\\' | '<' | '>' | ';' | '=' => format!("\\{c}"),
+      '#' if i == 0 => format!("\\{c}"),
+      ' ' if i == 0 || i == last => format!("\\{c}"),
+      '\0' => "\\00".to_string(),
+      c => c.to_string(),
+    })
+    .collect()
+}
+
+/// Escapes a value for a search filter (RFC 4515)
+fn escape_filter_value(value: &str) -> String {
+  value
+    .chars()
+    .map(|c| match c {
+      '*' | '(' | ')' | '\\' | '\0' => format!("\\{:02x}", c as u32),
+      c => c.to_string(),
+    })
+    .collect()
+}
+
+/// Encodes a search filter, such as `(&(objectClass=person)(uid=x))`.
+/// Supports `&`, `|`, `!`, equality and presence, which is all that's
+/// needed to find a user.
+fn parse_filter(filter: &str) -> Result<Vec<u8>, LdapError> {
+  let (encoded, rest) = parse_filter_at(filter.trim())?;
+  if !rest.trim().is_empty() {
+    return Err(LdapError::Config("user_filter has trailing text"));
+  }
+  Ok(encoded)
+}
+
+fn parse_filter_at(filter: &str) -> Result<(Vec<u8>, &str), LdapError> {
+  let bad = LdapError::Config("user_filter isn't a valid filter");
+  let Some(inner) = filter.strip_prefix('(') else {
+    return Err(bad);
+  };
+  let (tag, mut rest) = match inner.chars().next() {
+    Some('&') => (0xa0, &inner[1..]),
+    Some('|') => (0xa1, &inner[1..]),
+    Some('!') => (0xa2, &inner[1..]),
+    _ => {
+      let end = inner.find(')').ok_or(bad)?;
+      let (attribute, value) = inner[..end]
+        .split_once('=')
+        .ok_or(LdapError::Config("user_filter isn't a valid filter"))?;
+      let encoded = if value == "*" {
+        ber::tlv(0x87, attribute.as_bytes())
+      } else if value.contains('*') {
+        return Err(LdapError::Config("user_filter can't use wildcards"));
+      } else {
+        ber::constructed(
+          0xa3,
+          &[
+            ber::tlv(ber::OCTET_STRING, attribute.as_bytes()),
+            ber::tlv(ber::OCTET_STRING, &unescape_filter_value(value)?),
+          ],
+        )
+      };
+      return Ok((encoded, &inner[end + 1..]));
+    }
+  };
+  let mut parts = Vec::new();
+  while rest.starts_with('(') {
+    let (part, after) = parse_filter_at(rest)?;
+    parts.push(part);
+    rest = after;
+  }
+  let rest = rest.strip_prefix(')').ok_or(bad)?;
+  if parts.is_empty() || (tag == 0xa2 && parts.len() != 1) {
+    return Err(LdapError::Config("user_filter isn't a valid filter"));
+  }
+  Ok((ber::constructed(tag, &parts), rest))
+}
+
+fn unescape_filter_value(value: &str) -> Result<Vec<u8>, LdapError> {
+  let bytes = value.as_bytes();
+  let mut out = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+  while i < bytes.len() {
+    if bytes[i] == b'\\' {
+      let hex = value.get(i + 1..i + 3);
+      let byte = hex.and_then(|h| u8::from_str_radix(h, 16).ok());
+      out.push(byte.ok_or(LdapError::Config("bad escape in user_filter"))?);
+      i += 3;
+    } else {
+      out.push(bytes[i]);
+      i += 1;
+    }
+  }
+  Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::net::TcpListener;
+
+  struct Person {
+    dn: &'static str,
+    uid: &'static str,
+    password: &'static str,
+    groups: &'static [&'static str],
+  }
+
+  const SERVICE_DN: &str = "cn=lqos,dc=example";
+  const DIRECTORY: &[Person] = &[
+    Person {
+      dn: "uid=alice,ou=people,dc=example",
+      uid: "alice",
+      password: "wonderland",
+      groups: &["cn=NetOps,ou=groups,dc=example"],
+    },
+    Person {
+      dn: "uid=bob,ou=people,dc=example",
+      uid: "bob",
+      password: "builder",
+      groups: &["cn=support,ou=groups,dc=example"],
+    },
+    Person {
+      dn: "uid=carol,ou=people,dc=example",
+      uid: "carol",
+      password: "singer",
+      groups: &[],
+    },
+  ];
+
+  fn reply(id: u32, tag: u8, parts: &[Vec<u8>]) -> Vec<u8> {
+    ber::constructed(
+      ber::SEQUENCE,
+      &[ber::integer(ber::INTEGER, id), ber::constructed(tag, parts)],
+    )
+  }
+
+  fn result(code: u32) -> Vec<Vec<u8>> {
+    vec![
+      ber::integer(ber::ENUMERATED, code),
+      ber::tlv(ber::OCTET_STRING, b""),
+      ber::tlv(ber::OCTET_STRING, b""),
+    ]
+  }
+
+  /// Does an entry match a filter? Understands `&` and equality, which
+  /// is what the tests use.
+  fn matches(person: &Person, filter: &Element) -> bool {
+    let parts = filter.children().unwrap_or_default();
+    match filter.tag {
+      0xa0 => parts.iter().all(|p| matches(person, p)),
+      0xa3 => {
+        let attribute = parts[0].text().to_lowercase();
+        let value = parts[1].content;
+        match attribute.as_str() {
+          "uid" => person.uid.as_bytes() == value,
+          "objectclass" => value == b"person",
+          _ => false,
+        }
+      }
+      0x87 => true,
+      _ => false,
+    }
+  }
+
+  /// Serves `DIRECTORY` until the test ends. Searches need a bind.
+  fn stand_in_server() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("ldap://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+      for stream in listener.incoming() {
+        let mut stream = stream.unwrap();
+        let mut bound = None;
+        while let Ok(message) = ber::read_element(&mut stream) {
+          let (message, _) = Element::parse(&message).unwrap();
+          let parts = message.children().unwrap();
+          let id = parts[0].integer().unwrap();
+          let request = parts[1].children().unwrap_or_default();
+          let response = match parts[1].tag {
+            BIND_REQUEST => {
+              let (dn, password) = (request[1].text(), request[2].text());
+              let ok = (dn == SERVICE_DN && password == "secret")
+                || DIRECTORY
+                  .iter()
+                  .any(|p| p.dn == dn && p.password == password);
+              bound = ok.then_some(dn);
+              let code = if ok { SUCCESS } else { INVALID_CREDENTIALS };
+              reply(id, BIND_RESPONSE, &result(code))
+            }
+            SEARCH_REQUEST if bound.is_none() => {
+              reply(id, SEARCH_DONE, &result(50))
+            }
+            SEARCH_REQUEST => {
+              let base = request[0].text();
+              let mut out = Vec::new();
+              for person in DIRECTORY {
+                let in_scope = if request[1].integer() == Some(SCOPE_BASE) {
+                  person.dn == base
+                } else {
+                  person.dn.ends_with(&base)
+                };
+                if in_scope && matches(person, &request[6]) {
+                  let groups: Vec<Vec<u8>> = person
+                    .groups
+                    .iter()
+                    .map(|g| ber::tlv(ber::OCTET_STRING, g.as_bytes()))
+                    .collect();
+                  let attribute = ber::constructed(
+                    ber::SEQUENCE,
+                    &[
+                      ber::tlv(ber::OCTET_STRING, b"memberOf"),
+                      ber::constructed(ber::SET, &groups),
+                    ],
+                  );
+                  out.extend(reply(
+                    id,
+                    SEARCH_ENTRY,
+                    &[
+                      ber::tlv(ber::OCTET_STRING, person.dn.as_bytes()),
+                      ber::constructed(ber::SEQUENCE, &[attribute]),
+                    ],
+                  ));
+                }
+              }
+              out.extend(reply(id, SEARCH_DONE, &result(SUCCESS)));
+              out
+            }
+            _ => break,
+          };
+          stream.write_all(&response).unwrap();
+        }
+      }
+    });
+    url
+  }
+
+  fn settings(url: String) -> LdapSettings {
+    LdapSettings {
+      url,
+      user_dn: Some("uid={username},ou=people,dc=example".to_string()),
+      base_dn: None,
+      user_filter: default_user_filter(),
+      search_dn: None,
+      search_password: None,
+      group_attribute: default_group_attribute(),
+      admin_groups: vec!["cn=netops,ou=groups,dc=example".to_string()],
+      read_only_groups: vec!["cn=support,ou=groups,dc=example".to_string()],
+      default_role: None,
+      timeout_seconds: 2,
+      local_fallback: LocalFallback::Never,
+    }
+  }
+
+  #[test]
+  fn binds_as_the_user() {
+    let mut ldap = settings(stand_in_server());
+    let accepted = BackendResult::Accepted;
+    assert_eq!(
+      ldap.authenticate("alice", "wonderland"),
+      accepted(UserRole::Admin)
+    );
+    assert_eq!(
+      ldap.authenticate("bob", "builder"),
+      accepted(UserRole::ReadOnly)
+    );
+    assert_eq!(ldap.authenticate("alice", "builder"), BackendResult::Rejected);
+    assert_eq!(ldap.authenticate("dave", "x"), BackendResult::Rejected);
+
+    // Carol is in no group
+    assert_eq!(ldap.authenticate("carol", "singer"), BackendResult::Rejected);
+    ldap.default_role = Some(UserRole::ReadOnly);
+    assert_eq!(
+      ldap.authenticate("carol", "singer"),
+      accepted(UserRole::ReadOnly)
+    );
+  }
+
+  #[test]
+  fn searches_for_the_user() {
+    let mut ldap = settings(stand_in_server());
+    ldap.user_dn = None;
+    ldap.base_dn = Some("ou=people,dc=example".to_string());
+    ldap.user_filter = "(&(objectClass=person)(uid={username}))".to_string();
+
+    // The stand-in doesn't allow anonymous searches
+    assert!(matches!(
+      ldap.authenticate("alice", "wonderland"),
+      BackendResult::Unreachable(_)
+    ));
+
+    ldap.search_dn = Some(SERVICE_DN.to_string());
+    ldap.search_password = Some("secret".to_string());
+    assert_eq!(
+      ldap.authenticate("alice", "wonderland"),
+      BackendResult::Accepted(UserRole::Admin)
+    );
+    assert_eq!(ldap.authenticate("alice", "nope"), BackendResult::Rejected);
+    assert_eq!(ldap.authenticate("*", "wonderland"), BackendResult::Rejected);
+  }
+
+  #[test]
+  fn unreachable_servers_are_reported() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("ldap://{}", listener.local_addr().unwrap());
+    drop(listener);
+    let ldap = settings(url);
+    assert!(matches!(
+      ldap.authenticate("alice", "wonderland"),
+      BackendResult::Unreachable(_)
+    ));
+  }
+
+  #[test]
+  fn values_are_escaped() {
+    assert_eq!(escape_dn("a,b=c"), "a\\,b\\=c");
+    assert_eq!(escape_dn(" #x "), "\\ #x\\ ");
+    assert_eq!(escape_filter_value("a*(b)\\"), "a\\2a\\28b\\29\\5c");
+    let filter = parse_filter("(uid=a\\2a)").unwrap();
+    let (element, _) = Element::parse(&filter).unwrap();
+    assert_eq!(element.children().unwrap()[1].content, b"a*");
+    assert!(parse_filter("(uid=a*)").is_err());
+    assert!(parse_filter("(&(uid=a)").is_err());
+  }
+}
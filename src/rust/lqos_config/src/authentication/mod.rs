@@ -0,0 +1,1552 @@
+//! The `authentication` module provides authorization for use of the
+//! local web UI on LibreQoS boxes. It maps to `/<install dir>/lqusers.toml`
+
+use argon2::{
+  password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+  Argon2,
+};
+use log::{error, info, warn};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+  collections::HashMap,
+  fmt::Display,
+  fs::{metadata, read_to_string, remove_file, OpenOptions},
+  io::Write,
+  path::{Path, PathBuf},
+  str::FromStr,
+  sync::OnceLock,
+  time::{SystemTime, UNIX_EPOCH},
+};
+use thiserror::Error;
+use totp::TotpEnrolment;
+use uuid::Uuid;
+
+mod backend;
+mod ber;
+mod ldap;
+mod radius;
+mod scope;
+mod totp;
+
+pub use backend::{
+  AuthBackendKind, BackendCheck, BackendResult, LocalFallback,
+  PasswordBackend,
+};
+pub use ldap::LdapSettings;
+pub use radius::RadiusSettings;
+pub use scope::{ScopedView, UserScope};
+
+/// How long a session lasts without being used, unless `lqusers.toml`
+/// says otherwise
+const DEFAULT_SESSION_IDLE_MINUTES: u64 = 4 * 60;
+
+/// The longest a session lasts, however often it is used, unless
+/// `lqusers.toml` says otherwise
+const DEFAULT_SESSION_MAX_HOURS: u64 = 7 * 24;
+
+/// A session's last use is only saved when it is older than this (in
+/// seconds), so that every request doesn't rewrite `lqusers.toml`
+const SESSION_TOUCH_SECONDS: u64 = 5 * 60;
+
+/// Failed logins allowed for a username, or from an address, within
+/// `LOGIN_FAILURE_WINDOW` seconds before it is locked out
+const MAX_LOGIN_FAILURES: u32 = 5;
+const LOGIN_FAILURE_WINDOW: u64 = 15 * 60;
+
+/// How long (in seconds) a lockout lasts
+const LOGIN_LOCKOUT_SECONDS: u64 = 15 * 60;
+
+fn now() -> u64 {
+  SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Access rights of a user
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum UserRole {
+  /// The user may view data but not change it.
+  ReadOnly,
+  /// The user may make any changes they request.
+  Admin,
+}
+
+impl From<&str> for UserRole {
+  fn from(s: &str) -> Self {
+    let s = s.to_lowercase();
+    if s == "admin" {
+      UserRole::Admin
+    } else {
+      UserRole::ReadOnly
+    }
+  }
+}
+
+impl Display for UserRole {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      UserRole::Admin => write!(f, "admin"),
+      UserRole::ReadOnly => write!(f, "read-only"),
+    }
+  }
+}
+
+/// What an API key may be used for
+#[derive(
+  Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum ApiScope {
+  /// Read any data
+  Read,
+  /// Add, change and remove circuits, and reload the shaper
+  ShapingWrite,
+  /// Change the configuration
+  ConfigWrite,
+}
+
+impl ApiScope {
+  /// Every scope, which is what an admin signed in to the web UI has
+  pub const ALL: [ApiScope; 3] =
+    [ApiScope::Read, ApiScope::ShapingWrite, ApiScope::ConfigWrite];
+
+  /// The scopes a user with this role may use
+  pub fn allowed_for(role: UserRole) -> &'static [ApiScope] {
+    match role {
+      UserRole::Admin => &Self::ALL,
+      UserRole::ReadOnly => &[ApiScope::Read],
+    }
+  }
+}
+
+impl FromStr for ApiScope {
+  type Err = AuthenticationError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_lowercase().as_str() {
+      "read" => Ok(ApiScope::Read),
+      "shaping-write" => Ok(ApiScope::ShapingWrite),
+      "config-write" => Ok(ApiScope::ConfigWrite),
+      _ => Err(AuthenticationError::UnknownScope(s.to_string())),
+    }
+  }
+}
+
+impl Display for ApiScope {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      ApiScope::Read => write!(f, "read"),
+      ApiScope::ShapingWrite => write!(f, "shaping-write"),
+      ApiScope::ConfigWrite => write!(f, "config-write"),
+    }
+  }
+}
+
+/// An API key's details. The key itself is only available when it is
+/// created.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ApiKeyInfo {
+  /// Identifies the key, for revoking it
+  pub id: String,
+  /// What the key is for, such as "billing"
+  pub name: String,
+  /// The user the key acts as
+  pub username: String,
+  /// What the key may be used for. A key never has more rights than
+  /// its user.
+  pub scopes: Vec<ApiScope>,
+  /// When the key was created, in seconds since the Unix epoch
+  pub created: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct ApiKey {
+  id: String,
+  name: String,
+  key_hash: String,
+  scopes: Vec<ApiScope>,
+  created: u64,
+}
+
+/// A signed-in session's details. The token itself is only available
+/// when the session starts.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct SessionInfo {
+  /// Identifies the session, for revoking it
+  pub id: String,
+  /// The user who signed in
+  pub username: String,
+  /// When the user signed in, in seconds since the Unix epoch
+  pub created: u64,
+  /// When the session was last used, to within a few minutes
+  pub last_seen: u64,
+  /// When the session will end if it isn't used again
+  pub expires: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Session {
+  id: String,
+  token_hash: String,
+  created: u64,
+  last_seen: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct WebUser {
+  username: String,
+  password_hash: String,
+  role: UserRole,
+  #[serde(default)]
+  api_keys: Vec<ApiKey>,
+  #[serde(default)]
+  sessions: Vec<Session>,
+  /// Present once the user has enrolled an authenticator app
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  totp: Option<TotpEnrolment>,
+  /// The backend that signed the user in, such as "ldap", for users
+  /// without a local password
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  source: Option<String>,
+  /// Present for tenants, who only see part of the network
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  scope: Option<UserScope>,
+}
+
+impl WebUser {
+  fn api_key_info(&self, key: &ApiKey) -> ApiKeyInfo {
+    let allowed = ApiScope::allowed_for(self.role);
+    ApiKeyInfo {
+      id: key.id.clone(),
+      name: key.name.clone(),
+      username: self.username.clone(),
+      scopes: key
+        .scopes
+        .iter()
+        .filter(|s| allowed.contains(s))
+        .copied()
+        .collect(),
+      created: key.created,
+    }
+  }
+}
+
+/// Container holding the authorized web users.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct WebUsers {
+  allow_unauthenticated_to_view: bool,
+  /// Sessions end after this many minutes without being used
+  #[serde(default = "default_session_idle_minutes")]
+  session_idle_minutes: u64,
+  /// Sessions end this many hours after signing in
+  #[serde(default = "default_session_max_hours")]
+  session_max_hours: u64,
+  users: Vec<WebUser>,
+  /// Where `lqusers.toml` was loaded from, and when it was last
+  /// loaded or saved, to notice changes made by `lqusers`
+  #[serde(skip)]
+  loaded_from: Option<(PathBuf, Option<SystemTime>)>,
+}
+
+fn default_session_idle_minutes() -> u64 {
+  DEFAULT_SESSION_IDLE_MINUTES
+}
+
+fn default_session_max_hours() -> u64 {
+  DEFAULT_SESSION_MAX_HOURS
+}
+
+impl Default for WebUsers {
+  fn default() -> Self {
+    Self {
+      allow_unauthenticated_to_view: false,
+      session_idle_minutes: DEFAULT_SESSION_IDLE_MINUTES,
+      session_max_hours: DEFAULT_SESSION_MAX_HOURS,
+      users: Vec::new(),
+      loaded_from: None,
+    }
+  }
+}
+
+impl WebUsers {
+  fn path() -> Result<PathBuf, AuthenticationError> {
+    let base_path = crate::EtcLqos::load()
+      .map_err(|_| AuthenticationError::UnableToLoadEtcLqos)?
+      .lqos_directory;
+    let filename = Path::new(&base_path).join("lqusers.toml");
+    Ok(filename)
+  }
+
+  fn save_to_disk(&mut self) -> Result<(), AuthenticationError> {
+    let path = Self::path()?;
+    let new_contents = toml_edit::ser::to_string(&self);
+    if let Err(e) = new_contents {
+      return Err(AuthenticationError::SerializationError(e));
+    }
+    let new_contents = new_contents.unwrap();
+    if path.exists() && remove_file(&path).is_err() {
+      error!("Unable to delete web users file");
+      return Err(AuthenticationError::UnableToDelete);
+    }
+    if let Ok(mut file) =
+      OpenOptions::new().write(true).create_new(true).open(&path)
+    {
+      if file.write_all(new_contents.as_bytes()).is_err() {
+        error!("Unable to write web users file to disk.");
+        return Err(AuthenticationError::UnableToWrite);
+      }
+    } else {
+      error!("Unable to open web users file for writing.");
+      return Err(AuthenticationError::UnableToWrite);
+    }
+    let modified = metadata(&path).and_then(|m| m.modified()).ok();
+    self.loaded_from = Some((path, modified));
+    Ok(())
+  }
+
+  /// Does the user's file exist? True if it does, false otherwise.
+  pub fn does_users_file_exist() -> Result<bool, AuthenticationError> {
+    Ok(Self::path()?.exists())
+  }
+
+  /// Try to load `lqusers.toml`. If it is unavailable, create a new--empty--
+  /// file.
+  pub fn load_or_create() -> Result<Self, AuthenticationError> {
+    let path = Self::path()?;
+    if !path.exists() {
+      // Create a new users file, save it and return the
+      // empty file
+      let mut new_users = Self::default();
+      new_users.save_to_disk()?;
+      Ok(new_users)
+    } else {
+      // Load from disk
+      let modified = metadata(&path).and_then(|m| m.modified()).ok();
+      if let Ok(raw) = read_to_string(&path) {
+        let parse_result = toml_edit::de::from_str::<Self>(&raw);
+        if let Ok(mut users) = parse_result {
+          users.loaded_from = Some((path, modified));
+          Ok(users)
+        } else {
+          error!("Unable to deserialize lqusers.toml. Error in next message.");
+          error!("{:?}", parse_result);
+          Err(AuthenticationError::UnableToParse)
+        }
+      } else {
+        error!("Unable to read lqusers.toml");
+        Err(AuthenticationError::UnableToRead)
+      }
+    }
+  }
+
+  /// Reloads `lqusers.toml` if it has changed since it was loaded or
+  /// saved, such as by `lqusers` revoking a session.
+  pub fn reload_if_changed(&mut self) -> Result<(), AuthenticationError> {
+    let Some((path, loaded)) = &self.loaded_from else {
+      return Ok(());
+    };
+    let modified = metadata(path).and_then(|m| m.modified()).ok();
+    if modified.is_some() && modified != *loaded {
+      *self = Self::load_or_create()?;
+    }
+    Ok(())
+  }
+
+  /// Hashes a password with Argon2id and a random salt
+  fn hash_password(password: &str) -> Result<String, AuthenticationError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+      .hash_password(password.as_bytes(), &salt)
+      .map(|hash| hash.to_string())
+      .map_err(|_| AuthenticationError::PasswordHash)
+  }
+
+  /// Earlier versions stored an unsalted SHA-256 hash, which is
+  /// replaced with an Argon2id hash the next time the user logs in.
+  fn hash_legacy_password(password: &str) -> String {
+    let salted = format!("!x{password}_LibreQosLikesPasswordsForDinner");
+    let mut sha256 = Sha256::new();
+    sha256.update(salted);
+    format!("{:X}", sha256.finalize())
+  }
+
+  fn verify_password(password: &str, stored_hash: &str) -> bool {
+    match PasswordHash::new(stored_hash) {
+      Ok(hash) => {
+        Argon2::default().verify_password(password.as_bytes(), &hash).is_ok()
+      }
+      Err(_) => Self::hash_legacy_password(password) == stored_hash,
+    }
+  }
+
+  /// If a user exists with this username, update their details to the
+  /// provided values. If the user does not exist, create them with the
+  /// provided values. Changing a user's password or role ends their
+  /// sessions.
+  pub fn add_or_update_user(
+    &mut self,
+    username: &str,
+    password: &str,
+    role: UserRole,
+  ) -> Result<(), AuthenticationError> {
+    let password_hash = Self::hash_password(password)?;
+    if let Some(user) =
+      self.users.iter_mut().find(|u| u.username == username)
+    {
+      user.password_hash = password_hash;
+      user.role = role;
+      user.sessions.clear();
+      user.source = None;
+    } else {
+      let new_user = WebUser {
+        username: username.to_string(),
+        password_hash,
+        role,
+        api_keys: Vec::new(),
+        sessions: Vec::new(),
+        totp: None,
+        source: None,
+        scope: None,
+      };
+      self.users.push(new_user);
+    }
+
+    self.save_to_disk()
+  }
+
+  /// Delete a user from `lqusers.toml`
+  pub fn remove_user(
+    &mut self,
+    username: &str,
+  ) -> Result<(), AuthenticationError> {
+    let old_len = self.users.len();
+    self.users.retain(|u| u.username != username);
+    if old_len == self.users.len() {
+      error!("User {username} not found, hence not deleted.");
+      return Err(AuthenticationError::UserNotFound);
+    }
+    self.save_to_disk()?;
+    Ok(())
+  }
+
+  /// Attempt a login with the specified username, password and (for
+  /// users who have enrolled an authenticator app) TOTP or recovery
+  /// code. If the login succeeds, starts a new session and returns its
+  /// token, to be sent back as a cookie. If it fails, returns an `Err`;
+  /// `SecondFactorRequired` means the password was right, but a code
+  /// is needed too.
+  pub fn login(
+    &mut self,
+    username: &str,
+    password: &str,
+    code: Option<&str>,
+  ) -> Result<String, AuthenticationError> {
+    let check = Self::check_backend(username, password);
+    let local = self.local_password(username).verify(password);
+    self.login_checked(username, &local, code, check)
+  }
+
+  /// A copy of a user's password hash, to check with
+  /// `LocalPassword::verify` without holding the users, for
+  /// `login_checked`.
+  pub fn local_password(&self, username: &str) -> LocalPassword {
+    LocalPassword {
+      username: username.to_string(),
+      hash: self
+        .users
+        .iter()
+        .find(|u| u.username == username)
+        .map(|u| u.password_hash.clone()),
+      valid: false,
+      upgraded: None,
+    }
+  }
+
+  /// Asks the password backend set in `/etc/lqos.conf` about a
+  /// username and password, for `login_checked`. This may wait for a
+  /// server, so doesn't need the users.
+  pub fn check_backend(username: &str, password: &str) -> BackendCheck {
+    BackendCheck::configured(username, password)
+  }
+
+  /// Like `login`, with the password backend already asked and the
+  /// local password already checked
+  pub fn login_checked(
+    &mut self,
+    username: &str,
+    password: &LocalPassword,
+    code: Option<&str>,
+    check: BackendCheck,
+  ) -> Result<String, AuthenticationError> {
+    let require_totp = crate::EtcLqos::load()
+      .ok()
+      .and_then(|cfg| cfg.web_ui)
+      .map(|web_ui| web_ui.require_totp)
+      .unwrap_or_default();
+    let now = now();
+    // A failed login is an error even if anonymous users may view:
+    // they don't need to sign in, and the caller has to see the
+    // failure to slow guessing.
+    let index =
+      self.check_login(username, password, code, &check, &require_totp, now)?;
+
+    let token = self.users[index].start_session(now);
+    self.remove_expired_sessions(now);
+    self.save_to_disk()?;
+    Ok(token)
+  }
+
+  /// Checks a user's password and, if they have enrolled an
+  /// authenticator app, their code. A TOTP code can't be used twice,
+  /// and nor can a recovery code. Users whose role is in
+  /// `require_totp` can't sign in until they enroll. Returns the
+  /// user's index.
+  fn check_login(
+    &mut self,
+    username: &str,
+    password: &LocalPassword,
+    code: Option<&str>,
+    check: &BackendCheck,
+    require_totp: &[UserRole],
+    now: u64,
+  ) -> Result<usize, AuthenticationError> {
+    let index = self.check_password(username, password, check)?;
+    self.check_second_factor(index, code, require_totp, now)
+  }
+
+  /// Checks a password, with the backend's verdict and then (if the
+  /// backend's settings allow) local accounts. Returns the user's
+  /// index.
+  fn check_password(
+    &mut self,
+    username: &str,
+    password: &LocalPassword,
+    check: &BackendCheck,
+  ) -> Result<usize, AuthenticationError> {
+    let try_local = match &check.result {
+      None => true,
+      Some(BackendResult::Accepted(role)) => {
+        return self.backend_user(username, *role, check.backend);
+      }
+      Some(BackendResult::Rejected) => {
+        check.fallback == LocalFallback::Always
+      }
+      Some(BackendResult::Unreachable(reason)) => {
+        if check.fallback == LocalFallback::Never {
+          error!("Unable to check {username}'s password: {reason}");
+          return Err(AuthenticationError::BackendUnavailable(
+            reason.clone(),
+          ));
+        }
+        warn!("Checking {username} against local accounts: {reason}");
+        true
+      }
+    };
+    if !try_local {
+      return Err(AuthenticationError::InvalidLogin);
+    }
+    self.check_local_password(username, password)
+  }
+
+  /// Checks a password against the users' own. Users added by a
+  /// backend have no password here, so can't sign in this way. The
+  /// password must have been checked against the user's current hash:
+  /// if it has changed since it was copied, the login fails.
+  fn check_local_password(
+    &mut self,
+    username: &str,
+    password: &LocalPassword,
+  ) -> Result<usize, AuthenticationError> {
+    let index = self.users.iter().position(|u| {
+      u.username == username
+        && password.username == username
+        && password.hash.as_ref() == Some(&u.password_hash)
+    });
+    let Some(index) = index.filter(|_| password.valid) else {
+      return Err(AuthenticationError::InvalidLogin);
+    };
+
+    if let Some(upgraded) = &password.upgraded {
+      info!("Upgrading {username}'s password hash to Argon2id");
+      self.users[index].password_hash.clone_from(upgraded);
+    }
+    Ok(index)
+  }
+
+  /// A user signed in by a backend, added or updated with the role it
+  /// gave them. Directories don't care about case, so nor does the
+  /// match: "ALICE" is the existing "alice", with her scope and second
+  /// factor, and new users are added in lower case. A backend can't
+  /// sign in as a local account, or one added by another backend.
+  fn backend_user(
+    &mut self,
+    username: &str,
+    role: UserRole,
+    backend: &str,
+  ) -> Result<usize, AuthenticationError> {
+    let username = username.to_lowercase();
+    let existing =
+      self.users.iter().position(|u| u.username.to_lowercase() == username);
+    if let Some(index) = existing {
+      let user = &mut self.users[index];
+      if user.source.as_deref() != Some(backend) {
+        let source = user.source.as_deref().unwrap_or("local");
+        error!(
+          "{backend} signed in {username}, but {} is a {source} account; \
+           refusing the sign-in",
+          user.username
+        );
+        return Err(AuthenticationError::InvalidLogin);
+      }
+      if user.role != role {
+        info!("{backend} changed {username}'s role to {role}");
+        user.role = role;
+      }
+      return Ok(index);
+    }
+    info!("Adding {username} as {role}, signed in by {backend}");
+    self.users.push(WebUser {
+      username,
+      password_hash: String::new(),
+      role,
+      api_keys: Vec::new(),
+      sessions: Vec::new(),
+      totp: None,
+      source: Some(backend.to_string()),
+      scope: None,
+    });
+    Ok(self.users.len() - 1)
+  }
+
+  /// Checks a user's second factor, if they have one
+  fn check_second_factor(
+    &mut self,
+    index: usize,
+    code: Option<&str>,
+    require_totp: &[UserRole],
+    now: u64,
+  ) -> Result<usize, AuthenticationError> {
+    let user = &mut self.users[index];
+    let username = user.username.clone();
+    let Some(enrolment) = user.totp.as_mut() else {
+      return if require_totp.contains(&user.role) {
+        Err(AuthenticationError::SecondFactorNotEnrolled)
+      } else {
+        Ok(index)
+      };
+    };
+    let Some(code) = code.filter(|c| !c.trim().is_empty()) else {
+      return Err(AuthenticationError::SecondFactorRequired);
+    };
+    if let Some(step) =
+      totp::verify(&enrolment.secret, code, now, enrolment.last_step)
+    {
+      enrolment.last_step = step;
+      return Ok(index);
+    }
+    let hash = Self::hash_recovery_code(code);
+    let codes = &mut enrolment.recovery_codes;
+    if let Some(used) = codes.iter().position(|c| *c == hash) {
+      codes.remove(used);
+      warn!("{username} signed in with a recovery code; {} left", codes.len());
+      return Ok(index);
+    }
+    Err(AuthenticationError::InvalidSecondFactor)
+  }
+
+  /// Starts enrolling a user's authenticator app. Returns a new secret
+  /// and its `otpauth://` URI, to show as a QR code. Nothing changes
+  /// until `confirm_totp` is given a code from the app.
+  pub fn begin_totp(
+    &self,
+    username: &str,
+  ) -> Result<(String, String), AuthenticationError> {
+    if !self.users.iter().any(|u| u.username == username) {
+      return Err(AuthenticationError::UserNotFound);
+    }
+    let secret = totp::new_secret();
+    let uri = totp::uri(username, &secret);
+    Ok((secret, uri))
+  }
+
+  /// Finishes enrolling a user's authenticator app, given a code it
+  /// shows for `secret`. Replaces any earlier enrolment, and returns
+  /// new recovery codes, which aren't stored and can't be retrieved
+  /// later.
+  pub fn confirm_totp(
+    &mut self,
+    username: &str,
+    secret: &str,
+    code: &str,
+  ) -> Result<Vec<String>, AuthenticationError> {
+    let codes = self.enroll_totp(username, secret, code)?;
+    self.save_to_disk()?;
+    Ok(codes)
+  }
+
+  fn enroll_totp(
+    &mut self,
+    username: &str,
+    secret: &str,
+    code: &str,
+  ) -> Result<Vec<String>, AuthenticationError> {
+    let Some(user) = self.users.iter_mut().find(|u| u.username == username)
+    else {
+      return Err(AuthenticationError::UserNotFound);
+    };
+    let Some(step) = totp::verify(secret, code, now(), 0) else {
+      return Err(AuthenticationError::InvalidSecondFactor);
+    };
+    let codes = totp::new_recovery_codes();
+    let recovery_codes =
+      codes.iter().map(|c| Self::hash_recovery_code(c)).collect();
+    user.totp = Some(TotpEnrolment {
+      secret: secret.to_string(),
+      last_step: step,
+      recovery_codes,
+    });
+    Ok(codes)
+  }
+
+  /// Removes a user's authenticator app and recovery codes, such as
+  /// when they have lost both.
+  pub fn reset_totp(
+    &mut self,
+    username: &str,
+  ) -> Result<(), AuthenticationError> {
+    let Some(user) = self.users.iter_mut().find(|u| u.username == username)
+    else {
+      return Err(AuthenticationError::UserNotFound);
+    };
+    if user.totp.take().is_none() {
+      return Err(AuthenticationError::SecondFactorNotEnrolled);
+    }
+    self.save_to_disk()
+  }
+
+  /// Replaces an enrolled user's recovery codes, returning the new ones.
+  pub fn new_recovery_codes(
+    &mut self,
+    username: &str,
+  ) -> Result<Vec<String>, AuthenticationError> {
+    let Some(user) = self.users.iter_mut().find(|u| u.username == username)
+    else {
+      return Err(AuthenticationError::UserNotFound);
+    };
+    let Some(enrolment) = user.totp.as_mut() else {
+      return Err(AuthenticationError::SecondFactorNotEnrolled);
+    };
+    let codes = totp::new_recovery_codes();
+    enrolment.recovery_codes =
+      codes.iter().map(|c| Self::hash_recovery_code(c)).collect();
+    self.save_to_disk()?;
+    Ok(codes)
+  }
+
+  /// Given a session token, returns the user's name and role, and
+  /// notes that the session is still in use. Anyone gets read-only
+  /// access as "Anonymous" if anonymous access is allowed.
+  pub fn session_user(
+    &mut self,
+    token: &str,
+  ) -> Result<(String, UserRole), AuthenticationError> {
+    let now = now();
+    match self.find_session(token, now) {
+      Some((user, session)) => {
+        let user = &mut self.users[user];
+        let result = (user.username.clone(), user.role);
+        let session = &mut user.sessions[session];
+        if now.saturating_sub(session.last_seen) >= SESSION_TOUCH_SECONDS {
+          session.last_seen = now;
+          if let Err(e) = self.save_to_disk() {
+            warn!("Unable to record session use: {e}");
+          }
+        }
+        Ok(result)
+      }
+      None if self.allow_unauthenticated_to_view => {
+        Ok(("Anonymous".to_string(), UserRole::ReadOnly))
+      }
+      None => Err(AuthenticationError::InvalidToken),
+    }
+  }
+
+  /// Is there an unexpired session with this token? Unlike
+  /// `session_user`, this doesn't count as using the session, so
+  /// checking on an open page doesn't keep it from going idle.
+  pub fn has_session(&self, token: &str) -> bool {
+    self.find_session(token, now()).is_some()
+  }
+
+  /// Ends the session with this token, if there is one.
+  pub fn logout(&mut self, token: &str) -> Result<(), AuthenticationError> {
+    match self.find_session(token, now()) {
+      Some((user, session)) => {
+        self.users[user].sessions.remove(session);
+        self.save_to_disk()
+      }
+      None => Ok(()),
+    }
+  }
+
+  /// Finds an unexpired session by token, as (user, session) indexes
+  fn find_session(&self, token: &str, now: u64) -> Option<(usize, usize)> {
+    let (id, _) = token.split_once('_')?;
+    let hash = Self::hash_secret(token);
+    self.users.iter().enumerate().find_map(|(u, user)| {
+      user
+        .sessions
+        .iter()
+        .position(|s| s.id == id && s.token_hash == hash)
+        .filter(|s| !self.session_expired(&user.sessions[*s], now))
+        .map(|s| (u, s))
+    })
+  }
+
+  /// When a session will end, unless it is used before then
+  fn session_expiry(&self, session: &Session) -> u64 {
+    let idle = session.last_seen + self.session_idle_minutes * 60;
+    let max = session.created + self.session_max_hours * 60 * 60;
+    idle.min(max)
+  }
+
+  fn session_expired(&self, session: &Session, now: u64) -> bool {
+    now >= self.session_expiry(session)
+  }
+
+  fn remove_expired_sessions(&mut self, now: u64) {
+    let mut users = std::mem::take(&mut self.users);
+    for user in users.iter_mut() {
+      user.sessions.retain(|s| !self.session_expired(s, now));
+    }
+    self.users = users;
+  }
+
+  /// Lists the current sessions of a user, or of every user.
+  pub fn list_sessions(&self, username: Option<&str>) -> Vec<SessionInfo> {
+    let now = now();
+    self
+      .users
+      .iter()
+      .filter(|u| username.is_none() || username == Some(u.username.as_str()))
+      .flat_map(|u| {
+        u.sessions
+          .iter()
+          .filter(move |s| !self.session_expired(s, now))
+          .map(|s| SessionInfo {
+            id: s.id.clone(),
+            username: u.username.clone(),
+            created: s.created,
+            last_seen: s.last_seen,
+            expires: self.session_expiry(s),
+          })
+      })
+      .collect()
+  }
+
+  /// Ends a session, by ID.
+  pub fn revoke_session(
+    &mut self,
+    id: &str,
+  ) -> Result<(), AuthenticationError> {
+    let user = self
+      .users
+      .iter_mut()
+      .find(|u| u.sessions.iter().any(|s| s.id == id))
+      .ok_or(AuthenticationError::SessionNotFound)?;
+    user.sessions.retain(|s| s.id != id);
+    self.save_to_disk()
+  }
+
+  /// Ends all of a user's sessions. Returns how many there were.
+  pub fn revoke_user_sessions(
+    &mut self,
+    username: &str,
+  ) -> Result<usize, AuthenticationError> {
+    let user = self
+      .users
+      .iter_mut()
+      .find(|u| u.username == username)
+      .ok_or(AuthenticationError::UserNotFound)?;
+    let count = user.sessions.len();
+    user.sessions.clear();
+    self.save_to_disk()?;
+    Ok(count)
+  }
+
+  /// Dump all users to the console.
+  pub fn print_users(&self) -> Result<(), AuthenticationError> {
+    self.users.iter().for_each(|u| {
+      let totp = if u.totp.is_some() { "TOTP" } else { "" };
+      let source = u.source.as_deref().unwrap_or("local");
+      let scope =
+        u.scope.as_ref().map_or(String::new(), |s| format!("scope: {s}"));
+      println!(
+        "{:<40} {:<10} {source:<8} {totp:<4} {scope}",
+        u.username,
+        u.role.to_string()
+      );
+    });
+    Ok(())
+  }
+
+  /// The part of the network a user may see, or `None` if they may
+  /// see all of it. Anonymous users see all of it.
+  pub fn user_scope(&self, username: &str) -> Option<UserScope> {
+    self
+      .users
+      .iter()
+      .find(|u| u.username == username)
+      .and_then(|u| u.scope.clone())
+  }
+
+  /// Restricts a user to part of the network, or with `None`, lets
+  /// them see all of it. Takes effect on their next request.
+  pub fn set_scope(
+    &mut self,
+    username: &str,
+    scope: Option<UserScope>,
+  ) -> Result<(), AuthenticationError> {
+    let user = self
+      .users
+      .iter_mut()
+      .find(|u| u.username == username)
+      .ok_or(AuthenticationError::UserNotFound)?;
+    user.scope = scope;
+    self.save_to_disk()
+  }
+
+  /// Sets the "allow unauthenticated users" field. If true,
+  /// unauthenticated users gain read-only access. This is useful
+  /// for demonstration purposes.
+  pub fn allow_anonymous(
+    &mut self,
+    allow: bool,
+  ) -> Result<(), AuthenticationError> {
+    self.allow_unauthenticated_to_view = allow;
+    self.save_to_disk()?;
+    Ok(())
+  }
+
+  /// Do we allow unauthenticated users to read site data?
+  pub fn do_we_allow_anonymous(&self) -> bool {
+    self.allow_unauthenticated_to_view
+  }
+
+  /// Creates a long-lived API key for a user, for automation. Returns
+  /// the key's details and the key itself, which isn't stored and
+  /// can't be retrieved later.
+  pub fn create_api_key(
+    &mut self,
+    username: &str,
+    name: &str,
+    scopes: &[ApiScope],
+  ) -> Result<(ApiKeyInfo, String), AuthenticationError> {
+    let created = self.add_api_key(username, name, scopes)?;
+    self.save_to_disk()?;
+    Ok(created)
+  }
+
+  fn add_api_key(
+    &mut self,
+    username: &str,
+    name: &str,
+    scopes: &[ApiScope],
+  ) -> Result<(ApiKeyInfo, String), AuthenticationError> {
+    let user = self
+      .users
+      .iter_mut()
+      .find(|u| u.username == username)
+      .ok_or(AuthenticationError::UserNotFound)?;
+    let allowed = ApiScope::allowed_for(user.role);
+    if let Some(scope) = scopes.iter().find(|s| !allowed.contains(s)) {
+      return Err(AuthenticationError::ScopeNotAllowed(*scope));
+    }
+    let mut scopes = scopes.to_vec();
+    scopes.sort();
+    scopes.dedup();
+
+    let id = Uuid::new_v4().simple().to_string()[..12].to_string();
+    let key = format!("lqos_{id}_{}", Uuid::new_v4().simple());
+    let created = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map_or(0, |d| d.as_secs());
+    let api_key = ApiKey {
+      id,
+      name: name.to_string(),
+      key_hash: Self::hash_secret(&key),
+      scopes,
+      created,
+    };
+    let info = user.api_key_info(&api_key);
+    user.api_keys.push(api_key);
+    Ok((info, key))
+  }
+
+  /// Revokes one of a user's API keys, by ID.
+  pub fn revoke_api_key(
+    &mut self,
+    username: &str,
+    id: &str,
+  ) -> Result<(), AuthenticationError> {
+    let user = self
+      .users
+      .iter_mut()
+      .find(|u| u.username == username)
+      .ok_or(AuthenticationError::UserNotFound)?;
+    let old_len = user.api_keys.len();
+    user.api_keys.retain(|k| k.id != id);
+    if old_len == user.api_keys.len() {
+      return Err(AuthenticationError::ApiKeyNotFound);
+    }
+    self.save_to_disk()
+  }
+
+  /// Lists a user's API keys, or every user's keys.
+  pub fn list_api_keys(&self, username: Option<&str>) -> Vec<ApiKeyInfo> {
+    self
+      .users
+      .iter()
+      .filter(|u| username.is_none() || username == Some(u.username.as_str()))
+      .flat_map(|u| u.api_keys.iter().map(|k| u.api_key_info(k)))
+      .collect()
+  }
+
+  /// Looks up an API key, as sent by a client. The scopes returned are
+  /// limited to those the key's user currently has.
+  pub fn authenticate_api_key(
+    &self,
+    key: &str,
+  ) -> Result<ApiKeyInfo, AuthenticationError> {
+    let id = key
+      .strip_prefix("lqos_")
+      .and_then(|rest| rest.split_once('_'))
+      .map(|(id, _)| id)
+      .ok_or(AuthenticationError::InvalidApiKey)?;
+    let hash = Self::hash_secret(key);
+    self
+      .users
+      .iter()
+      .find_map(|u| {
+        u.api_keys
+          .iter()
+          .find(|k| k.id == id && k.key_hash == hash)
+          .map(|k| u.api_key_info(k))
+      })
+      .ok_or(AuthenticationError::InvalidApiKey)
+  }
+
+  /// API keys and session tokens are random, so don't need salting
+  fn hash_secret(secret: &str) -> String {
+    let mut sha256 = Sha256::new();
+    sha256.update(secret);
+    format!("{:X}", sha256.finalize())
+  }
+
+  fn hash_recovery_code(code: &str) -> String {
+    Self::hash_secret(&totp::normalize_recovery_code(code))
+  }
+}
+
+impl WebUser {
+  /// Starts a session, returning its token. Tokens are the session's
+  /// ID followed by 256 random bits.
+  fn start_session(&mut self, now: u64) -> String {
+    let id = Uuid::new_v4().simple().to_string()[..12].to_string();
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut secret);
+    let secret = secret
+      .iter()
+      .fold(String::new(), |hex, b| hex + &format!("{b:02x}"));
+    let token = format!("{id}_{secret}");
+    self.sessions.push(Session {
+      id,
+      token_hash: WebUsers::hash_secret(&token),
+      created: now,
+      last_seen: now,
+    });
+    token
+  }
+}
+
+/// A password checked against a copy of a user's hash, from
+/// `WebUsers::local_password`. Argon2 is slow on purpose, so the web
+/// server checks the password without holding the users.
+#[derive(Clone, Debug)]
+pub struct LocalPassword {
+  username: String,
+  /// `None` if there is no such user
+  hash: Option<String>,
+  valid: bool,
+  /// An Argon2id hash to replace a valid legacy one
+  upgraded: Option<String>,
+}
+
+impl LocalPassword {
+  /// Checks a password against the copied hash.
+  pub fn verify(mut self, password: &str) -> Self {
+    // Take as long as checking a real password, so that response times
+    // don't reveal which usernames exist.
+    let hash = self.hash.as_deref().unwrap_or(unknown_user_hash());
+    let valid = WebUsers::verify_password(password, hash);
+    let legacy = PasswordHash::new(hash).is_err();
+    self.valid = valid && self.hash.is_some();
+    if self.valid && legacy {
+      match WebUsers::hash_password(password) {
+        Ok(upgraded) => self.upgraded = Some(upgraded),
+        Err(e) => warn!("Unable to upgrade {}'s password: {e}", self.username),
+      }
+    }
+    self
+  }
+}
+
+/// A hash to check passwords against when the username doesn't exist
+fn unknown_user_hash() -> &'static str {
+  static HASH: OnceLock<String> = OnceLock::new();
+  HASH.get_or_init(|| {
+    WebUsers::hash_password("unknown user").unwrap_or_default()
+  })
+}
+
+/// Counts failed logins by username and by client address, locking
+/// either out for a while after too many. Kept in memory by the web
+/// server.
+#[derive(Default)]
+pub struct LoginThrottle {
+  failures: HashMap<String, LoginFailures>,
+}
+
+struct LoginFailures {
+  count: u32,
+  first: u64,
+  locked_until: u64,
+}
+
+impl LoginThrottle {
+  /// If any of `keys` (such as the username and the client's address)
+  /// is locked out, returns how many seconds remain.
+  pub fn locked_for(&mut self, keys: &[&str]) -> Option<u64> {
+    let now = now();
+    self.forget_old(now);
+    keys
+      .iter()
+      .filter_map(|key| self.failures.get(*key))
+      .map(|f| f.locked_until.saturating_sub(now))
+      .filter(|remaining| *remaining > 0)
+      .max()
+  }
+
+  /// Counts a failed login against each of `keys`. Returns true if
+  /// that locks any of them out.
+  pub fn failed(&mut self, keys: &[&str]) -> bool {
+    let now = now();
+    self.forget_old(now);
+    let mut locked = false;
+    for key in keys {
+      let failures =
+        self.failures.entry(key.to_string()).or_insert(LoginFailures {
+          count: 0,
+          first: now,
+          locked_until: 0,
+        });
+      failures.count += 1;
+      if failures.count >= MAX_LOGIN_FAILURES {
+        failures.locked_until = now + LOGIN_LOCKOUT_SECONDS;
+        locked = true;
+      }
+    }
+    locked
+  }
+
+  /// Forgets a key's failures after a successful login.
+  pub fn succeeded(&mut self, key: &str) {
+    self.failures.remove(key);
+  }
+
+  fn forget_old(&mut self, now: u64) {
+    self.failures.retain(|_, f| {
+      f.locked_until > now || f.first + LOGIN_FAILURE_WINDOW > now
+    });
+  }
+}
+
+/// Why a user or session operation failed
+#[derive(Error, Debug)]
+pub enum AuthenticationError {
+  /// `/etc/lqos.conf` couldn't be loaded
+  #[error("Unable to load /etc/lqos.conf")]
+  UnableToLoadEtcLqos,
+  /// The users couldn't be written as TOML
+  #[error("Unable to serialize to TOML")]
+  SerializationError(toml_edit::ser::Error),
+  /// The old `lqusers.toml` couldn't be removed
+  #[error("Unable to remove existing web users file")]
+  UnableToDelete,
+  /// `lqusers.toml` couldn't be written
+  #[error("Unable to open lqusers.toml for writing. Check permissions?")]
+  UnableToWrite,
+  /// `lqusers.toml` couldn't be read
+  #[error("Unable to read lqusers.toml")]
+  UnableToRead,
+  /// `lqusers.toml` isn't valid
+  #[error("Unable to parse lqusers.toml")]
+  UnableToParse,
+  /// There's no user with that name
+  #[error("User not found")]
+  UserNotFound,
+  /// The username or password is wrong
+  #[error("Invalid Login")]
+  InvalidLogin,
+  /// The session token is unknown or expired
+  #[error("Invalid User Token")]
+  InvalidToken,
+  /// There's no session with that ID
+  #[error("Session not found")]
+  SessionNotFound,
+  /// The password couldn't be hashed
+  #[error("Unable to hash the password")]
+  PasswordHash,
+  /// The password is right, but the user must also give a code
+  #[error("A code from the user's authenticator app is required")]
+  SecondFactorRequired,
+  /// The authenticator or recovery code is wrong
+  #[error("Invalid authenticator or recovery code")]
+  InvalidSecondFactor,
+  /// The user hasn't enrolled an authenticator app, or must before
+  /// signing in
+  #[error("The user hasn't enrolled an authenticator app")]
+  SecondFactorNotEnrolled,
+  /// The password backend's server couldn't be reached, and local
+  /// accounts aren't a fallback
+  #[error("Unable to reach the sign-in server: {0}")]
+  BackendUnavailable(String),
+  /// The API key is unknown
+  #[error("Invalid API key")]
+  InvalidApiKey,
+  /// There's no API key with that ID
+  #[error("API key not found")]
+  ApiKeyNotFound,
+  /// The API scope isn't one of those known
+  #[error("Unknown API scope {0}; use read, shaping-write or config-write")]
+  UnknownScope(String),
+  /// The user's role doesn't allow an API scope
+  #[error("The user's role doesn't allow the {0} scope")]
+  ScopeNotAllowed(ApiScope),
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn users() -> WebUsers {
+    let user = |username: &str, role| WebUser {
+      username: username.to_string(),
+      password_hash: WebUsers::hash_legacy_password("pw"),
+      role,
+      api_keys: Vec::new(),
+      sessions: Vec::new(),
+      totp: None,
+      source: None,
+      scope: None,
+    };
+    WebUsers {
+      users: vec![
+        user("admin", UserRole::Admin),
+        user("viewer", UserRole::ReadOnly),
+      ],
+      ..Default::default()
+    }
+  }
+
+  /// Checks a password as the web server does, before taking the users
+  fn checked(users: &WebUsers, username: &str, pw: &str) -> LocalPassword {
+    users.local_password(username).verify(pw)
+  }
+
+  #[test]
+  fn passwords_are_salted_argon2() {
+    let first = WebUsers::hash_password("hunter2").unwrap();
+    let second = WebUsers::hash_password("hunter2").unwrap();
+    assert!(first.starts_with("$argon2id$"));
+    assert_ne!(first, second);
+    assert!(WebUsers::verify_password("hunter2", &first));
+    assert!(!WebUsers::verify_password("hunter3", &first));
+  }
+
+  #[test]
+  fn legacy_password_hashes_still_verify() {
+    let legacy = WebUsers::hash_legacy_password("hunter2");
+    assert!(WebUsers::verify_password("hunter2", &legacy));
+    assert!(!WebUsers::verify_password("hunter3", &legacy));
+  }
+
+  #[test]
+  fn passwords_are_checked_against_a_copy() {
+    let mut users = users();
+    let local = BackendCheck::local();
+    // A legacy hash is upgraded when the login goes through
+    let pw = checked(&users, "viewer", "pw");
+    assert_eq!(users.check_password("viewer", &pw, &local).unwrap(), 1);
+    assert!(users.users[1].password_hash.starts_with("$argon2id$"));
+
+    // Checks are for one user, and stale once the password changes
+    let pw = checked(&users, "admin", "pw");
+    assert!(users.check_password("viewer", &pw, &local).is_err());
+    users.users[0].password_hash = WebUsers::hash_password("new").unwrap();
+    assert!(users.check_password("admin", &pw, &local).is_err());
+  }
+
+  #[test]
+  fn sessions_expire() {
+    let mut users = users();
+    let now = now();
+    let token = users.users[1].start_session(now);
+    assert_eq!(users.session_user(&token).unwrap().0, "viewer");
+    assert!(users.has_session(&token));
+    assert!(users.session_user(&format!("{token}0")).is_err());
+    assert!(users.session_user("default").is_err());
+
+    // Idle for too long
+    users.users[1].sessions[0].last_seen = now - 5 * 60 * 60;
+    assert!(!users.has_session(&token));
+    assert!(users.session_user(&token).is_err());
+
+    // Signed in too long ago, however recently used
+    users.users[1].sessions[0].last_seen = now;
+    users.users[1].sessions[0].created = now - 8 * 24 * 60 * 60;
+    assert!(users.session_user(&token).is_err());
+    assert!(users.list_sessions(None).is_empty());
+  }
+
+  #[test]
+  fn sessions_are_separate() {
+    let mut users = users();
+    let now = now();
+    let first = users.users[0].start_session(now);
+    let second = users.users[0].start_session(now);
+    assert_ne!(first, second);
+    let sessions = users.list_sessions(Some("admin"));
+    assert_eq!(sessions.len(), 2);
+    let toml = toml_edit::ser::to_string(&users).unwrap();
+    assert!(!toml.contains(&first));
+
+    // Ending one session leaves the other
+    users.users[0].sessions.retain(|s| s.id != sessions[0].id);
+    assert!(users.session_user(&first).is_err());
+    assert_eq!(users.session_user(&second).unwrap().1, UserRole::Admin);
+  }
+
+  #[test]
+  fn anonymous_sessions() {
+    let mut users = users();
+    users.allow_unauthenticated_to_view = true;
+    let (name, role) = users.session_user("default").unwrap();
+    assert_eq!(name, "Anonymous");
+    assert_eq!(role, UserRole::ReadOnly);
+
+    // Anonymous viewing doesn't turn a bad login into a session
+    let wrong = checked(&users, "admin", "wrong");
+    let bad = users.login_checked("admin", &wrong, None, BackendCheck::local());
+    assert!(matches!(bad, Err(AuthenticationError::InvalidLogin)));
+  }
+
+  #[test]
+  fn enrolled_users_need_a_code() {
+    let mut users = users();
+    let secret = totp::new_secret();
+    let now = now();
+    let code = |step| {
+      let key = data_encoding::BASE32_NOPAD.decode(secret.as_bytes());
+      totp::code_at(&key.unwrap(), step).unwrap()
+    };
+    let first = code(now / 30);
+    let codes = users.enroll_totp("admin", &secret, &first).unwrap();
+    let mut login = |password, code: Option<String>| {
+      let local = BackendCheck::local();
+      let password = checked(&users, "admin", password);
+      users.check_login("admin", &password, code.as_deref(), &local, &[], now)
+    };
+
+    assert!(matches!(
+      login("pw", None),
+      Err(AuthenticationError::SecondFactorRequired)
+    ));
+    assert!(matches!(
+      login("wrong", Some(code(now / 30 + 1))),
+      Err(AuthenticationError::InvalidLogin)
+    ));
+    // The code used to enroll, or any other, can't be used again
+    assert!(login("pw", Some(first)).is_err());
+    assert_eq!(login("pw", Some(code(now / 30 + 1))).unwrap(), 0);
+    assert!(login("pw", Some(code(now / 30 + 1))).is_err());
+
+    // Recovery codes work once each, however they're typed
+    let recovery = codes[0].to_uppercase().replace('-', " ");
+    assert_eq!(login("pw", Some(recovery.clone())).unwrap(), 0);
+    assert!(matches!(
+      login("pw", Some(recovery.clone())),
+      Err(AuthenticationError::InvalidSecondFactor)
+    ));
+  }
+
+  #[test]
+  fn roles_can_require_a_second_factor() {
+    let mut users = users();
+    let admin = [UserRole::Admin];
+    let local = BackendCheck::local();
+    let now = now();
+    let mut login = |username, require_totp: &[UserRole]| {
+      let pw = checked(&users, username, "pw");
+      users.check_login(username, &pw, None, &local, require_totp, now)
+    };
+    assert!(matches!(
+      login("admin", &admin),
+      Err(AuthenticationError::SecondFactorNotEnrolled)
+    ));
+    assert_eq!(login("viewer", &admin).unwrap(), 1);
+    assert_eq!(login("admin", &[]).unwrap(), 0);
+  }
+
+  fn remote(result: BackendResult, fallback: LocalFallback) -> BackendCheck {
+    BackendCheck { backend: "ldap", result: Some(result), fallback }
+  }
+
+  #[test]
+  fn backends_add_their_users() {
+    let mut users = users();
+    let accepted = BackendResult::Accepted(UserRole::ReadOnly);
+    let check = remote(accepted, LocalFallback::Never);
+    let pw = checked(&users, "erin", "ldap pw");
+    assert_eq!(users.check_password("erin", &pw, &check).unwrap(), 2);
+    assert_eq!(users.users[2].source.as_deref(), Some("ldap"));
+    assert_eq!(users.users[2].role, UserRole::ReadOnly);
+
+    // The backend's role wins, and the user has no local password
+    let admin = BackendResult::Accepted(UserRole::Admin);
+    let check = remote(admin, LocalFallback::Never);
+    assert_eq!(users.check_password("erin", &pw, &check).unwrap(), 2);
+    assert_eq!(users.users[2].role, UserRole::Admin);
+    let local = BackendCheck::local();
+    let pw = checked(&users, "erin", "ldap pw");
+    assert!(users.check_password("erin", &pw, &local).is_err());
+    let pw = checked(&users, "erin", "");
+    assert!(users.check_password("erin", &pw, &local).is_err());
+  }
+
+  #[test]
+  fn backend_usernames_ignore_case() {
+    let mut users = users();
+    let accepted = BackendResult::Accepted(UserRole::ReadOnly);
+    let check = remote(accepted, LocalFallback::Never);
+    // The backend's verdict is all that counts
+    let pw = users.local_password("");
+    assert_eq!(users.check_password("Erin", &pw, &check).unwrap(), 2);
+    assert_eq!(users.users[2].username, "erin");
+    users.users[2].scope = Some(UserScope {
+      nodes: vec!["Tower A".to_string()],
+      tags: Vec::new(),
+    });
+    assert_eq!(users.check_password("ERIN", &pw, &check).unwrap(), 2);
+    assert!(users.users[2].scope.is_some());
+    assert_eq!(users.users.len(), 3);
+  }
+
+  #[test]
+  fn backends_cant_sign_in_as_other_accounts() {
+    let mut users = users();
+    let accepted = BackendResult::Accepted(UserRole::Admin);
+    let pw = users.local_password("");
+    let ldap = remote(accepted, LocalFallback::Never);
+    assert!(matches!(
+      users.check_password("VIEWER", &pw, &ldap),
+      Err(AuthenticationError::InvalidLogin)
+    ));
+    assert_eq!(users.users[1].role, UserRole::ReadOnly);
+
+    // Nor as a user another backend added
+    users.check_password("erin", &pw, &ldap).unwrap();
+    let radius = BackendCheck { backend: "radius", ..ldap };
+    assert!(users.check_password("Erin", &pw, &radius).is_err());
+    assert_eq!(users.users.len(), 3);
+  }
+
+  #[test]
+  fn backends_fall_back_to_local_accounts() {
+    let mut users = users();
+    let unreachable = || BackendResult::Unreachable("down".to_string());
+    let mut login = |result, fallback| {
+      let pw = checked(&users, "admin", "pw");
+      users.check_password("admin", &pw, &remote(result, fallback))
+    };
+    assert!(matches!(
+      login(unreachable(), LocalFallback::Never),
+      Err(AuthenticationError::BackendUnavailable(_))
+    ));
+    assert_eq!(login(unreachable(), LocalFallback::Unreachable).unwrap(), 0);
+    assert!(matches!(
+      login(BackendResult::Rejected, LocalFallback::Unreachable),
+      Err(AuthenticationError::InvalidLogin)
+    ));
+    let rejected = login(BackendResult::Rejected, LocalFallback::Always);
+    assert_eq!(rejected.unwrap(), 0);
+  }
+
+  #[test]
+  fn repeated_login_failures_lock_out() {
+    let mut throttle = LoginThrottle::default();
+    let keys = ["user:admin", "ip:192.0.2.1"];
+    for _ in 1..MAX_LOGIN_FAILURES {
+      assert!(!throttle.failed(&keys));
+    }
+    assert_eq!(throttle.locked_for(&["user:admin"]), None);
+    assert!(throttle.failed(&keys));
+    assert!(throttle.locked_for(&["user:admin"]).is_some());
+    assert!(throttle.locked_for(&["ip:192.0.2.1"]).is_some());
+    assert_eq!(throttle.locked_for(&["user:viewer"]), None);
+
+    // Success only clears the username
+    throttle.succeeded("user:admin");
+    assert_eq!(throttle.locked_for(&["user:admin"]), None);
+    assert!(throttle.locked_for(&keys).is_some());
+  }
+
+  #[test]
+  fn api_keys_authenticate() {
+    let mut users = users();
+    let scopes = [ApiScope::ShapingWrite, ApiScope::Read];
+    let (info, key) = users.add_api_key("admin", "billing", &scopes).unwrap();
+    assert_eq!(info.scopes, vec![ApiScope::Read, ApiScope::ShapingWrite]);
+    assert_eq!(users.authenticate_api_key(&key).unwrap(), info);
+    assert!(users.authenticate_api_key(&format!("{key}x")).is_err());
+    assert!(users.authenticate_api_key("default").is_err());
+  }
+
+  #[test]
+  fn api_keys_are_limited_by_role() {
+    let mut users = users();
+    let result = users.add_api_key("viewer", "ci", &[ApiScope::ConfigWrite]);
+    assert!(matches!(
+      result,
+      Err(AuthenticationError::ScopeNotAllowed(ApiScope::ConfigWrite))
+    ));
+
+    // A key keeps only the rights its user still has
+    let (_, key) = users.add_api_key("admin", "ci", &ApiScope::ALL).unwrap();
+    users.users[0].role = UserRole::ReadOnly;
+    let info = users.authenticate_api_key(&key).unwrap();
+    assert_eq!(info.scopes, vec![ApiScope::Read]);
+  }
+
+  #[test]
+  fn api_keys_are_saved() {
+    let mut users = users();
+    let (info, key) =
+      users.add_api_key("admin", "billing", &[ApiScope::Read]).unwrap();
+    let toml = toml_edit::ser::to_string(&users).unwrap();
+    assert!(!toml.contains(&key));
+    let loaded: WebUsers = toml_edit::de::from_str(&toml).unwrap();
+    assert_eq!(loaded.authenticate_api_key(&key).unwrap(), info);
+    assert_eq!(loaded.list_api_keys(Some("viewer")), Vec::new());
+  }
+}
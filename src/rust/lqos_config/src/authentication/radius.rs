@@ -0,0 +1,462 @@
+//! The RADIUS password backend. It sends a PAP Access-Request (RFC
+//! 2865), and picks the user's role from an attribute of the reply.
+
+use super::{
+  backend::{map_role, BackendResult, LocalFallback, PasswordBackend},
+  UserRole,
+};
+use hmac::{Hmac, Mac};
+use log::info;
+use md5::{Digest, Md5};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::{
+  io,
+  net::{ToSocketAddrs, UdpSocket},
+  time::Duration,
+};
+
+const ACCESS_REQUEST: u8 = 1;
+const ACCESS_ACCEPT: u8 = 2;
+const ACCESS_REJECT: u8 = 3;
+const ACCESS_CHALLENGE: u8 = 11;
+
+const USER_NAME: u8 = 1;
+const USER_PASSWORD: u8 = 2;
+const NAS_IDENTIFIER: u8 = 32;
+const MESSAGE_AUTHENTICATOR: u8 = 80;
+
+/// The longest password PAP can carry
+const MAX_PASSWORD: usize = 128;
+
+/// How many times to send a request before giving up
+const ATTEMPTS: usize = 3;
+
+/// Settings for the RADIUS backend: the `[web_ui.radius]` section of
+/// `/etc/lqos.conf`
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct RadiusSettings {
+  /// The server, as `host:port`. The port is usually 1812.
+  pub server: String,
+
+  /// The secret shared with the server
+  pub secret: String,
+
+  /// Sent as the NAS-Identifier, for the server's policies. Defaults to
+  /// `libreqos`.
+  #[serde(default = "default_nas_identifier")]
+  pub nas_identifier: String,
+
+  /// The reply attribute (by number) holding the user's role. Defaults
+  /// to 11, Filter-Id; 25 (Class) is also common.
+  #[serde(default = "default_role_attribute")]
+  pub role_attribute: u8,
+
+  /// Values of `role_attribute` that make the user an admin
+  #[serde(default)]
+  pub admin_values: Vec<String>,
+
+  /// Values of `role_attribute` that give the user read-only access
+  #[serde(default)]
+  pub read_only_values: Vec<String>,
+
+  /// The role of users whose reply has none of the values above. If
+  /// unset, they can't sign in.
+  pub default_role: Option<UserRole>,
+
+  /// How long to wait for each reply. Requests are sent up to 3 times.
+  /// Defaults to 3 seconds.
+  #[serde(default = "default_timeout")]
+  pub timeout_seconds: u64,
+
+  /// When to try local accounts instead
+  #[serde(default)]
+  pub local_fallback: LocalFallback,
+}
+
+fn default_nas_identifier() -> String {
+  "libreqos".to_string()
+}
+
+fn default_role_attribute() -> u8 {
+  11
+}
+
+fn default_timeout() -> u64 {
+  3
+}
+
+impl PasswordBackend for RadiusSettings {
+  fn name(&self) -> &'static str {
+    "radius"
+  }
+
+  fn authenticate(&self, username: &str, password: &str) -> BackendResult {
+    if password.len() > MAX_PASSWORD {
+      return BackendResult::Rejected;
+    }
+    let reply = match self.exchange(username, password) {
+      Ok(reply) => reply,
+      Err(e) => return BackendResult::Unreachable(format!("RADIUS: {e}")),
+    };
+    if reply.code != ACCESS_ACCEPT {
+      return BackendResult::Rejected;
+    }
+    let values: Vec<String> = reply
+      .attributes
+      .iter()
+      .filter(|(kind, _)| *kind == self.role_attribute)
+      .map(|(_, value)| String::from_utf8_lossy(value).to_string())
+      .collect();
+    let role = map_role(
+      values.iter().map(|v| v.as_str()),
+      &self.admin_values,
+      &self.read_only_values,
+      self.default_role,
+    );
+    match role {
+      Some(role) => BackendResult::Accepted(role),
+      None => {
+        info!("RADIUS accepted {username}, but gave no LibreQoS role");
+        BackendResult::Rejected
+      }
+    }
+  }
+
+  fn local_fallback(&self) -> LocalFallback {
+    self.local_fallback
+  }
+}
+
+/// A verified reply from the server
+struct Reply {
+  code: u8,
+  attributes: Vec<(u8, Vec<u8>)>,
+}
+
+impl RadiusSettings {
+  /// Sends an Access-Request, and waits for a reply that's really from
+  /// the server
+  fn exchange(&self, username: &str, password: &str) -> io::Result<Reply> {
+    let server = self
+      .server
+      .to_socket_addrs()?
+      .next()
+      .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address"))?;
+    let local = if server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+    let socket = UdpSocket::bind(local)?;
+    socket.connect(server)?;
+    socket.set_read_timeout(Some(Duration::from_secs(
+      self.timeout_seconds.max(1),
+    )))?;
+
+    let mut authenticator = [0u8; 16];
+    OsRng.fill_bytes(&mut authenticator);
+    let id = (OsRng.next_u32() & 0xff) as u8;
+    let request = self.access_request(id, authenticator, username, password);
+
+    let mut buffer = [0u8; 4096];
+    for _ in 0..ATTEMPTS {
+      socket.send(&request)?;
+      loop {
+        let len = match socket.recv(&mut buffer) {
+          Ok(len) => len,
+          Err(e)
+            if matches!(
+              e.kind(),
+              io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+          {
+            break
+          }
+          Err(e) => return Err(e),
+        };
+        // Ignore anything that isn't the answer to this request
+        let reply = self.verify_reply(&buffer[..len], id, authenticator);
+        if let Some(reply) = reply {
+          return Ok(reply);
+        }
+      }
+    }
+    Err(io::Error::new(io::ErrorKind::TimedOut, "no reply from the server"))
+  }
+
+  fn access_request(
+    &self,
+    id: u8,
+    authenticator: [u8; 16],
+    username: &str,
+    password: &str,
+  ) -> Vec<u8> {
+    let mut attributes = Vec::new();
+    push_attribute(&mut attributes, USER_NAME, username.as_bytes());
+    push_attribute(
+      &mut attributes,
+      USER_PASSWORD,
+      &hide_password(password, self.secret.as_bytes(), &authenticator),
+    );
+    push_attribute(
+      &mut attributes,
+      NAS_IDENTIFIER,
+      self.nas_identifier.as_bytes(),
+    );
+    // Signed (RFC 3579), which servers increasingly insist on
+    push_attribute(&mut attributes, MESSAGE_AUTHENTICATOR, &[0u8; 16]);
+
+    let mut packet = vec![ACCESS_REQUEST, id, 0, 0];
+    packet.extend_from_slice(&authenticator);
+    packet.extend_from_slice(&attributes);
+    let len = packet.len() as u16;
+    packet[2..4].copy_from_slice(&len.to_be_bytes());
+    let signature = hmac_md5(self.secret.as_bytes(), &packet);
+    let at = packet.len() - 16;
+    packet[at..].copy_from_slice(&signature);
+    packet
+  }
+
+  /// Checks that a packet is the reply to our request and is signed
+  /// with the shared secret. Returns `None` if it isn't.
+  fn verify_reply(
+    &self,
+    packet: &[u8],
+    id: u8,
+    request_authenticator: [u8; 16],
+  ) -> Option<Reply> {
+    if packet.len() < 20 || packet[1] != id {
+      return None;
+    }
+    let len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+    if len < 20 || len > packet.len() {
+      return None;
+    }
+    let packet = &packet[..len];
+    let code = packet[0];
+    if ![ACCESS_ACCEPT, ACCESS_REJECT, ACCESS_CHALLENGE].contains(&code) {
+      return None;
+    }
+
+    let mut hash = Md5::new();
+    hash.update(&packet[..4]);
+    hash.update(request_authenticator);
+    hash.update(&packet[20..]);
+    hash.update(self.secret.as_bytes());
+    if hash.finalize().as_slice() != &packet[4..20] {
+      return None;
+    }
+
+    let attributes = parse_attributes(&packet[20..])?;
+    if let Some(at) = attribute_offset(&packet[20..], MESSAGE_AUTHENTICATOR) {
+      let mut unsigned = packet.to_vec();
+      unsigned[4..20].copy_from_slice(&request_authenticator);
+      let signature = 20 + at + 2;
+      let given = packet.get(signature..signature + 16)?.to_vec();
+      unsigned[signature..signature + 16].fill(0);
+      if hmac_md5(self.secret.as_bytes(), &unsigned) != given {
+        return None;
+      }
+    }
+    Some(Reply { code, attributes })
+  }
+}
+
+fn push_attribute(packet: &mut Vec<u8>, kind: u8, value: &[u8]) {
+  // Attribute values are at most 253 bytes
+  let value = &value[..value.len().min(253)];
+  packet.push(kind);
+  packet.push(value.len() as u8 + 2);
+  packet.extend_from_slice(value);
+}
+
+fn parse_attributes(mut data: &[u8]) -> Option<Vec<(u8, Vec<u8>)>> {
+  let mut attributes = Vec::new();
+  while !data.is_empty() {
+    let (kind, len) = (*data.first()?, *data.get(1)? as usize);
+    if len < 2 || len > data.len() {
+      return None;
+    }
+    attributes.push((kind, data[2..len].to_vec()));
+    data = &data[len..];
+  }
+  Some(attributes)
+}
+
+/// Where the first attribute of a kind starts in the attributes
+fn attribute_offset(mut data: &[u8], kind: u8) -> Option<usize> {
+  let mut offset = 0;
+  while data.len() >= 2 {
+    let len = data[1] as usize;
+    if data[0] == kind {
+      return Some(offset);
+    }
+    if len < 2 || len > data.len() {
+      return None;
+    }
+    offset += len;
+    data = &data[len..];
+  }
+  None
+}
+
+/// Hides a password as RFC 2865 section 5.2 describes
+fn hide_password(
+  password: &str,
+  secret: &[u8],
+  authenticator: &[u8],
+) -> Vec<u8> {
+  let mut padded = password.as_bytes().to_vec();
+  let blocks = padded.len().div_ceil(16).max(1);
+  padded.resize(blocks * 16, 0);
+  let mut previous = authenticator.to_vec();
+  let mut hidden = Vec::with_capacity(padded.len());
+  for block in padded.chunks(16) {
+    let mut hash = Md5::new();
+    hash.update(secret);
+    hash.update(&previous);
+    let key = hash.finalize();
+    previous = block.iter().zip(key.iter()).map(|(p, k)| p ^ k).collect();
+    hidden.extend_from_slice(&previous);
+  }
+  hidden
+}
+
+fn hmac_md5(secret: &[u8], data: &[u8]) -> Vec<u8> {
+  let Ok(mut mac) = Hmac::<Md5>::new_from_slice(secret) else {
+    return Vec::new();
+  };
+  mac.update(data);
+  mac.finalize().into_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  const SECRET: &str = "testing123";
+
+  /// Reverses `hide_password`
+  fn reveal_password(hidden: &[u8], authenticator: &[u8]) -> String {
+    let mut previous = authenticator.to_vec();
+    let mut password = Vec::new();
+    for block in hidden.chunks(16) {
+      let mut hash = Md5::new();
+      hash.update(SECRET.as_bytes());
+      hash.update(&previous);
+      let key = hash.finalize();
+      password.extend(block.iter().zip(key.iter()).map(|(c, k)| c ^ k));
+      previous = block.to_vec();
+    }
+    String::from_utf8_lossy(&password).trim_end_matches('\0').to_string()
+  }
+
+  /// Answers Access-Requests as a RADIUS server would, for a user
+  /// "alice" with the password "wonderland". Replies are signed with
+  /// `secret`, so a wrong secret shows up as no reply.
+  fn stand_in_server(secret: &'static str, drop_first: bool) -> String {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let address = socket.local_addr().unwrap().to_string();
+    std::thread::spawn(move || {
+      let mut buffer = [0u8; 4096];
+      let mut dropped = !drop_first;
+      while let Ok((len, from)) = socket.recv_from(&mut buffer) {
+        if !dropped {
+          dropped = true;
+          continue;
+        }
+        let request = &buffer[..len];
+        let authenticator = &request[4..20];
+        let attributes = parse_attributes(&request[20..]).unwrap();
+        let value = |kind| {
+          attributes.iter().find(|(k, _)| *k == kind).unwrap().1.clone()
+        };
+        let username = String::from_utf8(value(USER_NAME)).unwrap();
+        let password = reveal_password(&value(USER_PASSWORD), authenticator);
+        assert_eq!(value(NAS_IDENTIFIER), b"libreqos");
+
+        let mut reply_attributes = Vec::new();
+        let code = if password == "wonderland" && username == "alice" {
+          push_attribute(&mut reply_attributes, 11, b"lqos-admin");
+          ACCESS_ACCEPT
+        } else if password == "pw" && username == "bob" {
+          ACCESS_ACCEPT
+        } else {
+          ACCESS_REJECT
+        };
+        push_attribute(&mut reply_attributes, MESSAGE_AUTHENTICATOR, &[0; 16]);
+        let mut reply = vec![code, request[1], 0, 0];
+        reply.extend_from_slice(authenticator);
+        reply.extend_from_slice(&reply_attributes);
+        let len = reply.len() as u16;
+        reply[2..4].copy_from_slice(&len.to_be_bytes());
+        let signature = hmac_md5(secret.as_bytes(), &reply);
+        let at = reply.len() - 16;
+        reply[at..].copy_from_slice(&signature);
+        let mut hash = Md5::new();
+        hash.update(&reply);
+        hash.update(secret.as_bytes());
+        reply[4..20].copy_from_slice(&hash.finalize());
+        socket.send_to(&reply, from).unwrap();
+      }
+    });
+    address
+  }
+
+  fn settings(server: String) -> RadiusSettings {
+    RadiusSettings {
+      server,
+      secret: SECRET.to_string(),
+      nas_identifier: default_nas_identifier(),
+      role_attribute: default_role_attribute(),
+      admin_values: vec!["lqos-admin".to_string()],
+      read_only_values: vec!["lqos-read".to_string()],
+      default_role: None,
+      timeout_seconds: 1,
+      local_fallback: LocalFallback::Never,
+    }
+  }
+
+  #[test]
+  fn passwords_are_hidden_reversibly() {
+    let authenticator = [7u8; 16];
+    for password in ["", "pw", "exactly16chars!!", "a much longer password"] {
+      let hidden = hide_password(password, SECRET.as_bytes(), &authenticator);
+      assert_eq!(hidden.len() % 16, 0);
+      assert_eq!(reveal_password(&hidden, &authenticator), password);
+    }
+  }
+
+  #[test]
+  fn replies_map_to_roles() {
+    let mut radius = settings(stand_in_server(SECRET, false));
+    assert_eq!(
+      radius.authenticate("alice", "wonderland"),
+      BackendResult::Accepted(UserRole::Admin)
+    );
+    assert_eq!(radius.authenticate("alice", "x"), BackendResult::Rejected);
+
+    // Bob is accepted without a role
+    assert_eq!(radius.authenticate("bob", "pw"), BackendResult::Rejected);
+    radius.default_role = Some(UserRole::ReadOnly);
+    assert_eq!(
+      radius.authenticate("bob", "pw"),
+      BackendResult::Accepted(UserRole::ReadOnly)
+    );
+  }
+
+  #[test]
+  fn lost_requests_are_resent() {
+    let radius = settings(stand_in_server(SECRET, true));
+    assert_eq!(
+      radius.authenticate("alice", "wonderland"),
+      BackendResult::Accepted(UserRole::Admin)
+    );
+  }
+
+  #[test]
+  fn replies_with_the_wrong_secret_are_ignored() {
+    let radius = settings(stand_in_server("not the secret", false));
+    assert!(matches!(
+      radius.authenticate("alice", "wonderland"),
+      BackendResult::Unreachable(_)
+    ));
+  }
+}
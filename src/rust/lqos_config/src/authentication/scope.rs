@@ -0,0 +1,229 @@
+//! Tenant scopes: users who may only see part of the network, such as
+//! a smaller WISP reselling capacity on our shapers.
+
+use crate::{NetworkJson, ShapedDevice};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, fmt::Display};
+
+/// The part of the network a user may see and act on. A circuit is in
+/// scope if its parent node is one of `nodes` or below one, or if one
+/// of its devices has one of `tags`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct UserScope {
+  /// `network.json` nodes, by name or `id`
+  #[serde(default)]
+  pub nodes: Vec<String>,
+  /// Circuit tags, as `name:value`
+  #[serde(default)]
+  pub tags: Vec<String>,
+}
+
+impl UserScope {
+  /// Does the scope name nothing, so that nothing is in it?
+  pub fn is_empty(&self) -> bool {
+    self.nodes.is_empty() && self.tags.is_empty()
+  }
+}
+
+impl Display for UserScope {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let mut parts = Vec::new();
+    if !self.nodes.is_empty() {
+      parts.push(format!("nodes {}", self.nodes.join(", ")));
+    }
+    if !self.tags.is_empty() {
+      parts.push(format!("tags {}", self.tags.join(", ")));
+    }
+    if parts.is_empty() {
+      write!(f, "nothing")
+    } else {
+      write!(f, "{}", parts.join("; "))
+    }
+  }
+}
+
+/// A scope resolved against the current `network.json` and
+/// `ShapedDevices.csv`, to check nodes and circuits quickly
+#[derive(Clone, Debug, Default)]
+pub struct ScopedView {
+  /// Indexes of the nodes in scope
+  nodes: HashSet<usize>,
+  node_names: HashSet<String>,
+  /// The in-scope nodes whose parents aren't, in tree order
+  top_nodes: Vec<usize>,
+  tags: Vec<(String, String)>,
+  circuits: HashSet<String>,
+}
+
+impl ScopedView {
+  /// Finds the nodes and circuits in a scope. Nodes the scope names
+  /// that aren't in `network.json` match nothing.
+  pub fn resolve(
+    scope: &UserScope,
+    network: &NetworkJson,
+    devices: &[ShapedDevice],
+  ) -> Self {
+    let roots: HashSet<usize> = network
+      .nodes
+      .iter()
+      .enumerate()
+      .filter(|(_, n)| scope.nodes.iter().any(|s| *s == n.name || *s == n.id))
+      .map(|(i, _)| i)
+      .collect();
+    let mut view = Self::default();
+    for (index, node) in network.nodes.iter().enumerate() {
+      if node.parents.iter().any(|p| roots.contains(p)) || roots.contains(&index)
+      {
+        view.nodes.insert(index);
+        view.node_names.insert(node.name.clone());
+      }
+    }
+    view.top_nodes = (0..network.nodes.len())
+      .filter(|i| view.nodes.contains(i))
+      .filter(|i| {
+        !network.nodes[*i]
+          .immediate_parent
+          .is_some_and(|p| view.nodes.contains(&p))
+      })
+      .collect();
+    view.tags = scope
+      .tags
+      .iter()
+      .filter_map(|tag| tag.split_once(':'))
+      .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+      .collect();
+    view.circuits = devices
+      .iter()
+      .filter(|d| view.admits(d))
+      .map(|d| d.circuit_id.clone())
+      .collect();
+    view
+  }
+
+  /// Is the node at this index in `network.json` in scope?
+  pub fn allows_node(&self, index: usize) -> bool {
+    self.nodes.contains(&index)
+  }
+
+  /// Is the node with this name in scope?
+  pub fn allows_node_name(&self, name: &str) -> bool {
+    self.node_names.contains(name)
+  }
+
+  /// Is the circuit in scope?
+  pub fn allows_circuit(&self, circuit_id: &str) -> bool {
+    self.circuits.contains(circuit_id)
+  }
+
+  /// Would the device be in scope? Unlike `allows_circuit`, this
+  /// checks the device as given, such as a new or changed one.
+  pub fn admits(&self, device: &ShapedDevice) -> bool {
+    self.node_names.contains(&device.parent_node)
+      || self.tags.iter().any(|(name, value)| {
+        device.tag(name).is_some_and(|v| v.eq_ignore_ascii_case(value))
+      })
+  }
+
+  /// The tops of the scope's part of the tree: nodes in scope whose
+  /// parents aren't
+  pub fn top_nodes(&self) -> &[usize] {
+    &self.top_nodes
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use serde_json::json;
+
+  fn network() -> NetworkJson {
+    NetworkJson::from_json(&json!({
+      "North": {
+        "downloadBandwidthMbps": 1000,
+        "uploadBandwidthMbps": 1000,
+        "children": {
+          "Tower A": {
+            "id": "tower-a",
+            "downloadBandwidthMbps": 500,
+            "uploadBandwidthMbps": 500,
+            "children": {
+              "AP 1": {
+                "downloadBandwidthMbps": 100,
+                "uploadBandwidthMbps": 100
+              }
+            }
+          },
+          "Tower B": {
+            "downloadBandwidthMbps": 500,
+            "uploadBandwidthMbps": 500
+          }
+        }
+      }
+    }))
+  }
+
+  fn device(circuit_id: &str, parent_node: &str, tag: &str) -> ShapedDevice {
+    let mut device = ShapedDevice {
+      circuit_id: circuit_id.to_string(),
+      parent_node: parent_node.to_string(),
+      ..Default::default()
+    };
+    if let Some((name, value)) = tag.split_once(':') {
+      device.tags.insert(name.to_string(), value.to_string());
+    }
+    device
+  }
+
+  #[test]
+  fn nodes_include_everything_below_them() {
+    let network = network();
+    let devices = [
+      device("1", "AP 1", ""),
+      device("2", "Tower B", ""),
+      device("3", "North", ""),
+    ];
+    let scope =
+      UserScope { nodes: vec!["tower-a".to_string()], tags: Vec::new() };
+    let view = ScopedView::resolve(&scope, &network, &devices);
+    let index = |name| network.get_index_for_name(name).unwrap();
+    assert!(view.allows_node(index("Tower A")));
+    assert!(view.allows_node(index("AP 1")));
+    assert!(!view.allows_node(index("Tower B")));
+    assert!(!view.allows_node(index("North")));
+    assert!(!view.allows_node(0));
+    assert_eq!(view.top_nodes(), &[index("Tower A")]);
+    assert!(view.allows_circuit("1"));
+    assert!(!view.allows_circuit("2"));
+    assert!(!view.allows_circuit("3"));
+  }
+
+  #[test]
+  fn tags_add_circuits_anywhere() {
+    let network = network();
+    let devices = [
+      device("1", "Tower B", "Reseller:Acme"),
+      device("2", "Tower B", "reseller:Other"),
+      device("3", "AP 1", ""),
+    ];
+    let scope = UserScope {
+      nodes: Vec::new(),
+      tags: vec!["reseller:acme".to_string()],
+    };
+    let view = ScopedView::resolve(&scope, &network, &devices);
+    assert!(view.allows_circuit("1"));
+    assert!(!view.allows_circuit("2"));
+    assert!(!view.allows_circuit("3"));
+    assert!(view.top_nodes().is_empty());
+    assert!(view.admits(&device("4", "North", "RESELLER:ACME")));
+  }
+
+  #[test]
+  fn an_empty_scope_allows_nothing() {
+    let network = network();
+    let devices = [device("1", "AP 1", "")];
+    let view = ScopedView::resolve(&UserScope::default(), &network, &devices);
+    assert!(!view.allows_circuit("1"));
+    assert!(!view.allows_node(0));
+    assert!(!view.admits(&devices[0]));
+  }
+}
@@ -0,0 +1,147 @@
+//! Time-based one-time passwords (RFC 6238), as shown by authenticator
+//! apps, and the recovery codes that stand in for them.
+
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+
+/// Seconds each code is valid for
+const STEP_SECONDS: u64 = 30;
+
+/// Digits in each code
+const DIGITS: usize = 6;
+
+/// Codes from this many steps either side of now are accepted, to
+/// allow for clock drift
+const WINDOW_STEPS: u64 = 1;
+
+/// How many recovery codes a user is given
+const RECOVERY_CODES: usize = 10;
+
+/// A user's authenticator enrolment, as stored in `lqusers.toml`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(super) struct TotpEnrolment {
+  /// The shared secret, in base32
+  pub(super) secret: String,
+  /// The step of the last code used, so that it can't be used again
+  #[serde(default)]
+  pub(super) last_step: u64,
+  /// Hashes of the recovery codes not yet used
+  #[serde(default)]
+  pub(super) recovery_codes: Vec<String>,
+}
+
+/// A new random 160-bit secret, in base32
+pub(super) fn new_secret() -> String {
+  let mut secret = [0u8; 20];
+  OsRng.fill_bytes(&mut secret);
+  BASE32_NOPAD.encode(&secret)
+}
+
+/// An `otpauth://` URI for authenticator apps, usually shown as a QR
+/// code
+pub(super) fn uri(username: &str, secret: &str) -> String {
+  let username: String = username
+    .bytes()
+    .map(|b| match b {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' => {
+        (b as char).to_string()
+      }
+      _ => format!("%{b:02X}"),
+    })
+    .collect();
+  format!(
+    "otpauth://totp/LibreQoS:{username}?secret={secret}&issuer=LibreQoS\
+     &algorithm=SHA1&digits={DIGITS}&period={STEP_SECONDS}"
+  )
+}
+
+/// The code for a time step
+pub(super) fn code_at(secret: &[u8], step: u64) -> Option<String> {
+  let mut mac = Hmac::<Sha1>::new_from_slice(secret).ok()?;
+  mac.update(&step.to_be_bytes());
+  let hash = mac.finalize().into_bytes();
+  let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+  let value = u32::from_be_bytes(hash[offset..offset + 4].try_into().ok()?)
+    & 0x7fff_ffff;
+  Some(format!("{:0DIGITS$}", value % 10u32.pow(DIGITS as u32)))
+}
+
+/// Checks a code at `now` (seconds since the Unix epoch). Returns the
+/// code's time step if it's valid and later than `after`.
+pub(super) fn verify(
+  secret: &str,
+  code: &str,
+  now: u64,
+  after: u64,
+) -> Option<u64> {
+  let secret = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+  let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+  if code.len() != DIGITS {
+    return None;
+  }
+  let current = now / STEP_SECONDS;
+  (current.saturating_sub(WINDOW_STEPS)..=current + WINDOW_STEPS)
+    .filter(|step| *step > after)
+    .find(|step| code_at(&secret, *step).as_deref() == Some(code.as_str()))
+}
+
+/// New recovery codes, such as `k7qp-m2xw-d4`. They are random, so
+/// are stored with the same hash as API keys.
+pub(super) fn new_recovery_codes() -> Vec<String> {
+  (0..RECOVERY_CODES)
+    .map(|_| {
+      let mut bytes = [0u8; 6];
+      OsRng.fill_bytes(&mut bytes);
+      let code = BASE32_NOPAD.encode(&bytes).to_lowercase();
+      format!("{}-{}-{}", &code[0..4], &code[4..8], &code[8..10])
+    })
+    .collect()
+}
+
+/// Recovery codes are compared without dashes, spaces or case
+pub(super) fn normalize_recovery_code(code: &str) -> String {
+  code
+    .chars()
+    .filter(|c| c.is_ascii_alphanumeric())
+    .map(|c| c.to_ascii_lowercase())
+    .collect()
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn rfc_6238_test_vectors() {
+    let secret = b"12345678901234567890";
+    // The RFC's eight-digit codes, less their first two digits
+    for (time, code) in
+      [(59, "287082"), (1111111109, "081804"), (1234567890, "005924")]
+    {
+      assert_eq!(code_at(secret, time / STEP_SECONDS).unwrap(), code);
+    }
+  }
+
+  #[test]
+  fn codes_are_accepted_once_within_the_window() {
+    let secret = BASE32_NOPAD.encode(b"12345678901234567890");
+    let now = 1111111109;
+    assert_eq!(verify(&secret, "081804", now, 0), Some(now / 30));
+    assert_eq!(verify(&secret, "081 804", now + 30, 0), Some(now / 30));
+    assert_eq!(verify(&secret, "081804", now + 90, 0), None);
+    assert_eq!(verify(&secret, "081804", now, now / 30), None);
+    assert_eq!(verify(&secret, "081805", now, 0), None);
+  }
+
+  #[test]
+  fn recovery_codes_are_distinct() {
+    let codes = new_recovery_codes();
+    assert_eq!(codes.len(), RECOVERY_CODES);
+    assert_eq!(codes[0].len(), 12);
+    assert_ne!(codes[0], codes[1]);
+    assert_eq!(normalize_recovery_code("K7QP-m2xw d4"), "k7qpm2xwd4");
+  }
+}
@@ -0,0 +1,469 @@
+//! Per-circuit statistics over time, so that a circuit's history (for
+//! example, last night's congestion) can be graphed after the fact.
+//!
+//! `lqosd` records a [`CircuitSample`] for each circuit once a minute.
+//! Minutes are kept for a day, and rolled up into hours that are kept
+//! for 31 days. Each circuit has a file in
+//! `<lqos_directory>/circuit_history/` with a fixed number of
+//! fixed-size slots for each resolution, overwritten in turn, so a
+//! circuit's history never takes more than about 190 KB of disk.
+use crate::etc;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+  fs::{self, File, OpenOptions},
+  os::unix::fs::FileExt,
+  path::PathBuf,
+  str::FromStr,
+  time::{Duration, SystemTime},
+};
+use thiserror::Error;
+
+/// The directory (inside `lqos_directory`) holding circuit history
+const HISTORY_DIR: &str = "circuit_history";
+
+/// Longer escaped circuit IDs are cut short and given a hash, to stay
+/// within the 255 byte file name limit
+const MAX_NAME: usize = 160;
+
+const MINUTE: u64 = 60;
+const HOUR: u64 = 60 * MINUTE;
+
+/// Minutes are kept for a day
+const MINUTE_SLOTS: u64 = 24 * 60;
+
+/// Hours are kept for 31 days
+const HOUR_SLOTS: u64 = 31 * 24;
+
+/// The size of an encoded sample
+const SAMPLE_BYTES: usize = 88;
+
+/// How far back to show a circuit's history.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HistoryRange {
+  /// The last hour, by minute
+  #[serde(rename = "1h")]
+  Hour,
+  /// The last day, by minute
+  #[serde(rename = "24h")]
+  Day,
+  /// The last week, by hour
+  #[serde(rename = "7d")]
+  Week,
+  /// The last 30 days, by hour
+  #[serde(rename = "30d")]
+  Month,
+}
+
+impl HistoryRange {
+  fn seconds(self) -> u64 {
+    match self {
+      Self::Hour => HOUR,
+      Self::Day => 24 * HOUR,
+      Self::Week => 7 * 24 * HOUR,
+      Self::Month => 30 * 24 * HOUR,
+    }
+  }
+
+  fn by_hour(self) -> bool {
+    matches!(self, Self::Week | Self::Month)
+  }
+}
+
+impl FromStr for HistoryRange {
+  type Err = CircuitHistoryError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "1h" => Ok(Self::Hour),
+      "24h" => Ok(Self::Day),
+      "7d" => Ok(Self::Week),
+      "30d" => Ok(Self::Month),
+      _ => Err(CircuitHistoryError::Range(s.to_string())),
+    }
+  }
+}
+
+/// A circuit's statistics for one minute or hour. Pairs are
+/// (download, upload).
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub struct CircuitSample {
+  /// When the minute or hour started (UNIX time)
+  pub timestamp: u64,
+
+  /// The ceiling the circuit was shaped to, in Mbps
+  pub plan_mbps: (u64, u64),
+
+  /// Average throughput, in bits per second
+  pub bits_avg: (u64, u64),
+
+  /// The busiest second's throughput, in bits per second
+  pub bits_max: (u64, u64),
+
+  /// Median round-trip time in milliseconds, if any was measured
+  pub rtt_p50: Option<f32>,
+
+  /// 95th percentile round-trip time in milliseconds, if any was
+  /// measured
+  pub rtt_p95: Option<f32>,
+
+  /// Packets dropped by CAKE
+  pub drops: (u32, u32),
+
+  /// Packets ECN-marked by CAKE
+  pub marks: (u32, u32),
+
+  /// The largest queue backlog seen, in bytes
+  pub backlog: (u32, u32),
+}
+
+impl CircuitSample {
+  fn encode(&self) -> [u8; SAMPLE_BYTES] {
+    let rtt = |rtt: Option<f32>| rtt.unwrap_or(f32::NAN).to_le_bytes();
+    let mut bytes = [0; SAMPLE_BYTES];
+    let fields: [&[u8]; 15] = [
+      &self.timestamp.to_le_bytes(),
+      &self.plan_mbps.0.to_le_bytes(),
+      &self.plan_mbps.1.to_le_bytes(),
+      &self.bits_avg.0.to_le_bytes(),
+      &self.bits_avg.1.to_le_bytes(),
+      &self.bits_max.0.to_le_bytes(),
+      &self.bits_max.1.to_le_bytes(),
+      &rtt(self.rtt_p50),
+      &rtt(self.rtt_p95),
+      &self.drops.0.to_le_bytes(),
+      &self.drops.1.to_le_bytes(),
+      &self.marks.0.to_le_bytes(),
+      &self.marks.1.to_le_bytes(),
+      &self.backlog.0.to_le_bytes(),
+      &self.backlog.1.to_le_bytes(),
+    ];
+    let mut offset = 0;
+    for field in fields {
+      bytes[offset..offset + field.len()].copy_from_slice(field);
+      offset += field.len();
+    }
+    bytes
+  }
+
+  /// Decodes a slot, returning `None` if it has never been written.
+  fn decode(bytes: &[u8]) -> Option<Self> {
+    let (wide, narrow) = bytes.get(..SAMPLE_BYTES)?.split_at(56);
+    let mut wide = wide
+      .chunks_exact(8)
+      .map(|b| u64::from_le_bytes(b.try_into().unwrap()));
+    let mut narrow = narrow
+      .chunks_exact(4)
+      .map(|b| u32::from_le_bytes(b.try_into().unwrap()));
+    let mut next_u64 = || wide.next();
+    let mut next_u32 = || narrow.next();
+    let rtt = |bits: u32| Some(f32::from_bits(bits)).filter(|r| !r.is_nan());
+    let timestamp = next_u64()?;
+    if timestamp == 0 {
+      return None;
+    }
+    Some(Self {
+      timestamp,
+      plan_mbps: (next_u64()?, next_u64()?),
+      bits_avg: (next_u64()?, next_u64()?),
+      bits_max: (next_u64()?, next_u64()?),
+      rtt_p50: rtt(next_u32()?),
+      rtt_p95: rtt(next_u32()?),
+      drops: (next_u32()?, next_u32()?),
+      marks: (next_u32()?, next_u32()?),
+      backlog: (next_u32()?, next_u32()?),
+    })
+  }
+
+  /// Combines the minutes of an hour. Throughput and round-trip times
+  /// are averaged, peaks keep the highest, drops and marks are added
+  /// up, and the plan is the latest.
+  fn roll_up(timestamp: u64, minutes: &[CircuitSample]) -> Self {
+    let mut hour = Self { timestamp, ..Default::default() };
+    let Some(latest) = minutes.iter().max_by_key(|m| m.timestamp) else {
+      return hour;
+    };
+    hour.plan_mbps = latest.plan_mbps;
+    let count = minutes.len() as u64;
+    let avg_bits = |f: fn(&CircuitSample) -> u64| {
+      minutes.iter().map(f).sum::<u64>() / count
+    };
+    hour.bits_avg = (avg_bits(|m| m.bits_avg.0), avg_bits(|m| m.bits_avg.1));
+    let avg_rtt = |f: fn(&CircuitSample) -> Option<f32>| {
+      let rtts: Vec<f32> = minutes.iter().filter_map(f).collect();
+      Some(rtts.iter().sum::<f32>() / rtts.len() as f32)
+        .filter(|_| !rtts.is_empty())
+    };
+    hour.rtt_p50 = avg_rtt(|m| m.rtt_p50);
+    hour.rtt_p95 = avg_rtt(|m| m.rtt_p95);
+    for m in minutes {
+      hour.bits_max.0 = hour.bits_max.0.max(m.bits_max.0);
+      hour.bits_max.1 = hour.bits_max.1.max(m.bits_max.1);
+      hour.drops.0 = hour.drops.0.saturating_add(m.drops.0);
+      hour.drops.1 = hour.drops.1.saturating_add(m.drops.1);
+      hour.marks.0 = hour.marks.0.saturating_add(m.marks.0);
+      hour.marks.1 = hour.marks.1.saturating_add(m.marks.1);
+      hour.backlog.0 = hour.backlog.0.max(m.backlog.0);
+      hour.backlog.1 = hour.backlog.1.max(m.backlog.1);
+    }
+    hour
+  }
+}
+
+/// Provides access to the circuit history kept in a directory.
+pub struct CircuitHistory {
+  directory: PathBuf,
+}
+
+impl CircuitHistory {
+  /// Opens the circuit history for the LibreQoS directory named in
+  /// `/etc/lqos.conf`.
+  pub fn open() -> Result<Self, CircuitHistoryError> {
+    let cfg =
+      etc::EtcLqos::load().map_err(|_| CircuitHistoryError::ConfigLoad)?;
+    Ok(Self::for_directory(&cfg.lqos_directory))
+  }
+
+  /// Opens the circuit history for a specific LibreQoS directory.
+  pub fn for_directory(lqos_directory: &str) -> Self {
+    Self { directory: PathBuf::from(lqos_directory).join(HISTORY_DIR) }
+  }
+
+  /// The file for a circuit. Circuit IDs can contain anything, so
+  /// characters other than letters, digits, `-` and `_` are escaped,
+  /// and long IDs are shortened to a prefix and a hash of the whole ID.
+  fn path(&self, circuit_id: &str) -> PathBuf {
+    let mut name = String::with_capacity(circuit_id.len());
+    for b in circuit_id.bytes() {
+      if b.is_ascii_alphanumeric() || b == b'-' || b == b'_' {
+        name.push(b as char);
+      } else {
+        name.push_str(&format!("%{b:02X}"));
+      }
+    }
+    if name.len() > MAX_NAME {
+      let hash = Sha256::digest(circuit_id.as_bytes());
+      name.truncate(MAX_NAME - 65);
+      name.push('~');
+      for b in hash {
+        name.push_str(&format!("{b:02x}"));
+      }
+    }
+    self.directory.join(format!("{name}.history"))
+  }
+
+  /// Stores a circuit's sample for a minute, and updates the hour it
+  /// falls in.
+  pub fn record(
+    &self,
+    circuit_id: &str,
+    sample: &CircuitSample,
+  ) -> Result<(), CircuitHistoryError> {
+    let minute = CircuitSample {
+      timestamp: sample.timestamp - sample.timestamp % MINUTE,
+      ..*sample
+    };
+    fs::create_dir_all(&self.directory)?;
+    let file = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .create(true)
+      .truncate(false)
+      .open(self.path(circuit_id))?;
+    let slot = minute.timestamp / MINUTE % MINUTE_SLOTS;
+    file.write_all_at(&minute.encode(), slot * SAMPLE_BYTES as u64)?;
+
+    // An hour's minutes are next to each other, because there are a
+    // whole number of hours of minute slots.
+    let hour_start = minute.timestamp - minute.timestamp % HOUR;
+    let first = hour_start / MINUTE % MINUTE_SLOTS;
+    let minutes: Vec<CircuitSample> =
+      read_slots(&file, first, HOUR / MINUTE)?
+        .into_iter()
+        .filter(|m| m.timestamp / HOUR * HOUR == hour_start)
+        .collect();
+    let hour = CircuitSample::roll_up(hour_start, &minutes);
+    let slot = MINUTE_SLOTS + hour_start / HOUR % HOUR_SLOTS;
+    file.write_all_at(&hour.encode(), slot * SAMPLE_BYTES as u64)?;
+    Ok(())
+  }
+
+  /// A circuit's samples for `range` before `now` (UNIX time), oldest
+  /// first: by minute for the last hour or day, and by hour for longer.
+  pub fn query(
+    &self,
+    circuit_id: &str,
+    range: HistoryRange,
+    now: u64,
+  ) -> Result<Vec<CircuitSample>, CircuitHistoryError> {
+    let file = match File::open(self.path(circuit_id)) {
+      Ok(file) => file,
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+        return Ok(Vec::new())
+      }
+      Err(e) => return Err(e.into()),
+    };
+    let mut samples = if range.by_hour() {
+      read_slots(&file, MINUTE_SLOTS, HOUR_SLOTS)?
+    } else {
+      read_slots(&file, 0, MINUTE_SLOTS)?
+    };
+    let since = now.saturating_sub(range.seconds());
+    samples.retain(|s| s.timestamp > since && s.timestamp <= now);
+    samples.sort_by_key(|s| s.timestamp);
+    Ok(samples)
+  }
+
+  /// Deletes the history of circuits that haven't been recorded for
+  /// longer than hours are kept, such as circuits that were removed.
+  /// Returns how many were deleted.
+  pub fn remove_expired(&self) -> Result<usize, CircuitHistoryError> {
+    let expiry = Duration::from_secs(HOUR_SLOTS * HOUR);
+    let mut removed = 0;
+    let entries = match fs::read_dir(&self.directory) {
+      Ok(entries) => entries,
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+      Err(e) => return Err(e.into()),
+    };
+    for entry in entries {
+      let entry = entry?;
+      let modified = entry.metadata()?.modified()?;
+      let age = SystemTime::now().duration_since(modified).unwrap_or_default();
+      if age > expiry {
+        fs::remove_file(entry.path())?;
+        removed += 1;
+      }
+    }
+    Ok(removed)
+  }
+}
+
+/// Reads `count` slots from `first`, skipping those never written.
+fn read_slots(
+  file: &File,
+  first: u64,
+  count: u64,
+) -> Result<Vec<CircuitSample>, CircuitHistoryError> {
+  let mut bytes = vec![0; count as usize * SAMPLE_BYTES];
+  let offset = first * SAMPLE_BYTES as u64;
+  let mut read = 0;
+  while read < bytes.len() {
+    match file.read_at(&mut bytes[read..], offset + read as u64)? {
+      0 => break, // The rest of the file hasn't been written
+      n => read += n,
+    }
+  }
+  Ok(
+    bytes
+      .chunks_exact(SAMPLE_BYTES)
+      .filter_map(CircuitSample::decode)
+      .collect(),
+  )
+}
+
+/// Errors reading or writing circuit history
+#[derive(Error, Debug)]
+pub enum CircuitHistoryError {
+  /// `/etc/lqos.conf` could not be loaded
+  #[error("Unable to load /etc/lqos.conf")]
+  ConfigLoad,
+  /// Reading or writing a history file failed
+  #[error("Unable to read or write circuit history: {0}")]
+  Io(String),
+  /// The range isn't one of `1h`, `24h`, `7d` or `30d`
+  #[error("Unknown history range: {0}")]
+  Range(String),
+}
+
+impl From<std::io::Error> for CircuitHistoryError {
+  fn from(e: std::io::Error) -> Self {
+    Self::Io(e.to_string())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir()
+      .join(format!("lqos_history_{name}_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  fn sample(timestamp: u64, bits: u64, rtt: Option<f32>) -> CircuitSample {
+    CircuitSample {
+      timestamp,
+      plan_mbps: (100, 20),
+      bits_avg: (bits, bits / 10),
+      bits_max: (bits * 2, bits / 5),
+      rtt_p50: rtt,
+      rtt_p95: rtt.map(|r| r * 2.0),
+      drops: (3, 1),
+      marks: (5, 0),
+      backlog: (bits as u32, 0),
+    }
+  }
+
+  #[test]
+  fn encoding_round_trips() {
+    let s = sample(1_700_000_040, 1_000, Some(12.5));
+    assert_eq!(CircuitSample::decode(&s.encode()), Some(s));
+    let s = sample(1_700_000_040, 1_000, None);
+    assert_eq!(CircuitSample::decode(&s.encode()), Some(s));
+    assert_eq!(CircuitSample::decode(&[0; SAMPLE_BYTES]), None);
+  }
+
+  #[test]
+  fn records_minutes_and_rolls_up_hours() {
+    let dir = scratch_dir("roll_up");
+    let history = CircuitHistory::for_directory(dir.to_str().unwrap());
+    let hour = 1_700_000_000 / HOUR * HOUR;
+    history.record("c/1", &sample(hour + 5, 1_000, Some(10.0))).unwrap();
+    history.record("c/1", &sample(hour + 65, 3_000, None)).unwrap();
+    history.record("c/1", &sample(hour + 125, 2_000, Some(20.0))).unwrap();
+    history.record("c/1", &sample(hour + HOUR, 500, Some(5.0))).unwrap();
+    assert!(dir.join(HISTORY_DIR).join("c%2F1.history").exists());
+
+    // IDs too long for a file name are shortened
+    let long_id = "/".repeat(300);
+    history.record(&long_id, &sample(hour + 5, 1_000, None)).unwrap();
+    let file = history.path(&long_id);
+    assert_eq!(file.file_name().unwrap().len(), MAX_NAME + ".history".len());
+    assert_ne!(file, history.path(&"/".repeat(301)));
+    let minutes = history.query(&long_id, HistoryRange::Hour, hour + 30);
+    assert_eq!(minutes.unwrap().len(), 1);
+
+    let now = hour + HOUR + 30;
+    let minutes = history.query("c/1", HistoryRange::Hour, now).unwrap();
+    let times: Vec<u64> = minutes.iter().map(|m| m.timestamp).collect();
+    assert_eq!(times, vec![hour + 60, hour + 120, hour + HOUR]);
+    assert_eq!(history.query("c/1", HistoryRange::Day, now).unwrap().len(), 4);
+
+    let hours = history.query("c/1", HistoryRange::Week, now).unwrap();
+    assert_eq!(hours.len(), 2);
+    let first = hours[0];
+    assert_eq!(first.timestamp, hour);
+    assert_eq!(first.bits_avg, (2_000, 200));
+    assert_eq!(first.bits_max, (6_000, 600));
+    assert_eq!(first.rtt_p50, Some(15.0));
+    assert_eq!(first.rtt_p95, Some(30.0));
+    assert_eq!(first.drops, (9, 3));
+    assert_eq!(first.backlog, (3_000, 0));
+    assert_eq!(hours[1].bits_avg, (500, 50));
+
+    // A day later, the minute slots are reused
+    let later = hour + 24 * HOUR;
+    history.record("c/1", &sample(later, 7_000, None)).unwrap();
+    let minutes = history.query("c/1", HistoryRange::Day, later).unwrap();
+    let times: Vec<u64> = minutes.iter().map(|m| m.timestamp).collect();
+    assert_eq!(times, vec![hour + 60, hour + 120, hour + HOUR, later]);
+    assert_eq!(minutes[3].bits_avg.0, 7_000);
+    let hours = history.query("c/1", HistoryRange::Month, later).unwrap();
+    assert_eq!(hours.len(), 3);
+    assert!(history.query("c2", HistoryRange::Day, later).unwrap().is_empty());
+    assert_eq!(history.remove_expired().unwrap(), 0);
+  }
+}
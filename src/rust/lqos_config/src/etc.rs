@@ -4,6 +4,11 @@ use serde::{Deserialize, Serialize};
 use toml_edit::{Document, value};
 use std::{fs, path::Path};
 use thiserror::Error;
+use crate::{AuthBackendKind, LdapSettings, RadiusSettings, UserRole};
+use crate::isp_settings::{
+  IntegrationSettings, InterfaceSettings, IspSettings, QueueSettings,
+  SubnetSettings,
+};
 
 /// Represents the top-level of the `/etc/lqos.conf` file. Serialization
 /// structure.
@@ -41,6 +46,153 @@ pub struct EtcLqos {
 
   /// Long-term statistics retention settings.
   pub long_term_stats: Option<LongTermStats>,
+
+  /// If present, controls the startup self-test of the packet
+  /// dissector and classifier.
+  pub self_test: Option<SelfTest>,
+
+  /// If present, controls whether `lqosd` builds the queues itself
+  /// when asked to reload, in place of running `LibreQoS.py`.
+  pub queue_planner: Option<QueuePlanner>,
+
+  /// If present, controls whether `lqosd` keeps a history of each
+  /// circuit's statistics. It does unless disabled here.
+  pub circuit_history: Option<CircuitHistorySettings>,
+
+  /// If present, sign-in requirements for the local web UI.
+  pub web_ui: Option<WebUiSettings>,
+
+  /// If present, the shaping bridge pairs served by this `lqosd`.
+  /// Replaces `interfaceA`/`interfaceB` (and the stick settings) from
+  /// `ispConfig.py`, which otherwise describe a single pair.
+  pub interface_pairs: Option<Vec<InterfacePair>>,
+
+  /// If present, the shaping interfaces. Replaces `interfaceA`,
+  /// `interfaceB` and the stick settings in `ispConfig.py`.
+  pub interfaces: Option<InterfaceSettings>,
+
+  /// If present, the queue settings. Its presence means that
+  /// `ispConfig.py` has been migrated into this file.
+  pub queues: Option<QueueSettings>,
+
+  /// If present, the allowed and ignored customer subnets.
+  pub subnets: Option<SubnetSettings>,
+
+  /// If present, the CRM/NMS integration and graphing settings.
+  pub integrations: Option<IntegrationSettings>,
+}
+
+/// One shaping bridge: an Internet-facing interface and an ISP-facing
+/// interface, or a single interface "on a stick" with VLANs marking
+/// each direction. Every pair shares the IP mappings and queues.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct InterfacePair {
+  /// A name for the pair, used when reporting per-pair throughput.
+  pub name: String,
+
+  /// The interface facing the Internet. On a stick, the only
+  /// interface.
+  pub internet_interface: String,
+
+  /// The interface facing the ISP core router. Unused on a stick.
+  #[serde(default)]
+  pub isp_interface: String,
+
+  /// Is this pair a single interface, with VLANs per direction?
+  #[serde(default)]
+  pub on_a_stick: bool,
+
+  /// On a stick, the VLAN facing the Internet.
+  #[serde(default)]
+  pub internet_vlan: u16,
+
+  /// On a stick, the VLAN facing the ISP core router.
+  #[serde(default)]
+  pub isp_vlan: u16,
+
+  /// On a stick with QinQ, the inner VLAN accompanying
+  /// `internet_vlan`. 0 if single-tagged.
+  #[serde(default)]
+  pub internet_inner_vlan: u16,
+
+  /// On a stick with QinQ, the inner VLAN accompanying `isp_vlan`.
+  /// 0 if single-tagged.
+  #[serde(default)]
+  pub isp_inner_vlan: u16,
+}
+
+impl InterfacePair {
+  /// The interfaces the pair attaches to: one on a stick, otherwise
+  /// two.
+  pub fn interfaces(&self) -> Vec<&str> {
+    if self.on_a_stick {
+      vec![self.internet_interface.as_str()]
+    } else {
+      vec![self.internet_interface.as_str(), self.isp_interface.as_str()]
+    }
+  }
+
+  /// The interface traffic towards customers (download) leaves by.
+  /// On a stick, this is the stick itself.
+  pub fn isp_facing_interface(&self) -> &str {
+    if self.on_a_stick {
+      &self.internet_interface
+    } else {
+      &self.isp_interface
+    }
+  }
+}
+
+/// Controls the self-test `lqosd` runs after loading the XDP/TC
+/// programs, which feeds synthetic frames through them and checks
+/// that they are classified as expected.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SelfTest {
+  /// Should the self-test run at startup?
+  pub enabled: bool,
+
+  /// If true, `lqosd` refuses to start when any self-test case
+  /// fails.
+  pub strict: bool,
+}
+
+/// Controls `lqosd`'s built-in queue planner. When enabled, reloads
+/// build the queues from `network.json` and `ShapedDevices.csv`
+/// without `LibreQoS.py`, and apply only what changed since the last
+/// reload.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct QueuePlanner {
+  /// Should reloads use the built-in planner?
+  pub enabled: bool,
+}
+
+/// Controls the per-circuit history `lqosd` records once a minute, for
+/// the web UI's history graphs.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CircuitHistorySettings {
+  /// Should circuit history be recorded?
+  pub enabled: bool,
+}
+
+/// Sign-in requirements for the local web UI
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Default)]
+pub struct WebUiSettings {
+  /// Roles whose users must also enter a code from an authenticator
+  /// app (TOTP) to sign in, such as `[ "Admin" ]`. Users with these
+  /// roles can't sign in until they have enrolled with `lqusers`.
+  #[serde(default)]
+  pub require_totp: Vec<UserRole>,
+
+  /// Where passwords are checked: `local` (the default) for
+  /// `lqusers.toml`, `ldap` or `radius`
+  #[serde(default)]
+  pub backend: AuthBackendKind,
+
+  /// Settings for the `ldap` backend
+  pub ldap: Option<LdapSettings>,
+
+  /// Settings for the `radius` backend
+  pub radius: Option<RadiusSettings>,
 }
 
 /// Represents a set of `sysctl` and `ethtool` tweaks that may be
@@ -117,11 +269,24 @@ pub struct BridgeVlan {
   /// The parent interface name on which the VLAN occurs.
   pub parent: String,
 
-  /// The VLAN tag number to redirect if matched.
+  /// The VLAN tag number to redirect if matched. With QinQ, this is
+  /// the outer (S-VLAN) tag.
   pub tag: u32,
 
-  /// The destination VLAN tag number if matched.
+  /// The destination VLAN tag number if matched. With QinQ, this is
+  /// the new outer tag; set it equal to `tag` to only rewrite the
+  /// inner tag.
   pub redirect_to: u32,
+
+  /// Only match QinQ frames whose inner (C-VLAN) tag is this number.
+  /// If unset, the entry matches on `tag` alone.
+  #[serde(default)]
+  pub inner_tag: Option<u32>,
+
+  /// Rewrite the inner (C-VLAN) tag of a QinQ frame to this number.
+  /// If unset, the inner tag is left alone.
+  #[serde(default)]
+  pub redirect_inner_to: Option<u32>,
 }
 
 /// Definitions for anonymous usage submission
@@ -156,6 +321,17 @@ pub struct LongTermStats {
 }
 
 impl EtcLqos {
+  /// The settings migrated from `ispConfig.py`, or `None` if there is
+  /// no `[queues]` section (and `ispConfig.py` is still in use).
+  pub fn isp_settings(&self) -> Option<IspSettings> {
+    self.queues.as_ref().map(|queues| IspSettings {
+      interfaces: self.interfaces.clone().unwrap_or_default(),
+      queues: queues.clone(),
+      subnets: self.subnets.clone().unwrap_or_default(),
+      integrations: self.integrations.clone().unwrap_or_default(),
+    })
+  }
+
   /// Loads `/etc/lqos.conf`.
   pub fn load() -> Result<Self, EtcLqosError> {
     if !Path::new("/etc/lqos.conf").exists() {
@@ -211,6 +387,19 @@ impl EtcLqos {
   }
 }
 
+/// Reads `/etc/lqos.conf` as an editable document, preserving its
+/// comments and layout.
+pub(crate) fn load_document() -> Result<Document, EtcLqosError> {
+  let raw = std::fs::read_to_string("/etc/lqos.conf").map_err(|e| {
+    error!("Unable to read contents of /etc/lqos.conf: {e:?}");
+    EtcLqosError::CannotReadFile
+  })?;
+  raw.parse::<Document>().map_err(|e| {
+    error!("Unable to parse TOML from /etc/lqos.conf: {e:?}");
+    EtcLqosError::CannotParseToml
+  })
+}
+
 /// Run this if you've received the OK from the licensing server, and been
 /// sent a license key. This appends a [long_term_stats] section to your 
 /// config file - ONLY if one doesn't already exist.
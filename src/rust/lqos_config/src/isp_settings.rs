@@ -0,0 +1,714 @@
+//! Typed `/etc/lqos.conf` sections replacing `ispConfig.py`. When a
+//! `[queues]` section is present, these are the single source of
+//! truth: `LibreQoSConfig` is built from them, and Python reads them
+//! through `liblqos_python`. An existing `ispConfig.py` can be imported
+//! with `migrate_isp_config`.
+
+use crate::{etc, libre_qos_config::LibreQoSConfigError};
+use log::error;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{collections::BTreeMap, path::Path, process::Command};
+use toml_edit::{Document, Item};
+
+/// The shaping interfaces (`[interfaces]`). Superseded by
+/// `interface_pairs`, if present.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct InterfaceSettings {
+  /// Interface facing the ISP core router (`interfaceA`)
+  pub isp_interface: String,
+
+  /// Interface facing the Internet (`interfaceB`)
+  pub internet_interface: String,
+
+  /// Single interface mode, with VLANs marking each direction
+  pub on_a_stick: bool,
+
+  /// On a stick, the VLAN facing the core router (`StickVlanA`)
+  pub isp_vlan: u16,
+
+  /// On a stick, the VLAN facing the Internet (`StickVlanB`)
+  pub internet_vlan: u16,
+
+  /// On a stick with QinQ, the inner VLAN accompanying `isp_vlan`
+  pub isp_inner_vlan: u16,
+
+  /// On a stick with QinQ, the inner VLAN accompanying
+  /// `internet_vlan`
+  pub internet_inner_vlan: u16,
+}
+
+/// How queues are built (`[queues]`).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct QueueSettings {
+  /// The queue discipline, e.g. `cake diffserv4` or `fq_codel`
+  pub sqm: String,
+
+  /// Monitor the network without shaping it
+  pub monitor_only: bool,
+
+  /// Total download available at the edge of the network (Mbps)
+  pub uplink_download_mbps: u32,
+
+  /// Total upload available at the edge of the network (Mbps)
+  pub uplink_upload_mbps: u32,
+
+  /// Download capacity of each generated parent node (Mbps)
+  pub generated_pn_download_mbps: u32,
+
+  /// Upload capacity of each generated parent node (Mbps)
+  pub generated_pn_upload_mbps: u32,
+
+  /// Balance circuits without a parent across CPUs by plan size
+  pub use_binpacking: bool,
+
+  /// Override the number of queues/CPUs used. 0 uses them all.
+  pub override_available_queues: u32,
+
+  /// Execute the generated shell commands, rather than printing them
+  pub enable_shell_commands: bool,
+
+  /// Prefix shell commands with `sudo`
+  pub run_as_sudo: bool,
+
+  /// Minutes between scheduled queue refreshes
+  pub queue_refresh_interval_mins: u32,
+}
+
+impl Default for QueueSettings {
+  fn default() -> Self {
+    Self {
+      sqm: "cake diffserv4".to_string(),
+      monitor_only: false,
+      uplink_download_mbps: 1000,
+      uplink_upload_mbps: 1000,
+      generated_pn_download_mbps: 1000,
+      generated_pn_upload_mbps: 1000,
+      use_binpacking: false,
+      override_available_queues: 0,
+      enable_shell_commands: true,
+      run_as_sudo: false,
+      queue_refresh_interval_mins: 30,
+    }
+  }
+}
+
+/// Which customer subnets may be shaped (`[subnets]`).
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct SubnetSettings {
+  /// Only addresses inside these subnets are imported
+  pub allowed: Vec<String>,
+
+  /// Addresses inside these subnets are assumed to be behind NAT,
+  /// and are ignored
+  pub ignored: Vec<String>,
+}
+
+/// CRM/NMS integrations and graphing (`[integrations]`).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct IntegrationSettings {
+  /// Name circuits after the customer's address rather than name
+  pub circuit_name_use_address: bool,
+
+  /// Should integrations overwrite `network.json` on each run?
+  pub overwrite_network_json_always: bool,
+
+  /// Sites to leave out of the imported network
+  pub exclude_sites: Vec<String>,
+
+  /// Look up IPv6 prefixes for IPv4 addresses on Mikrotik routers
+  pub find_ipv6_using_mikrotik: bool,
+
+  /// Multiplier applied to plan rates (e.g. 1.15 for 15% headroom)
+  pub bandwidth_overhead_factor: f64,
+
+  /// Multiplier of the maximum rate used as the minimum rate
+  pub committed_bandwidth_multiplier: f64,
+
+  /// Parent node overrides for specific CPEs, by CPE name
+  pub exception_cpes: BTreeMap<String, String>,
+
+  /// UISP integration
+  pub uisp: UispIntegration,
+
+  /// Splynx integration
+  pub splynx: SplynxIntegration,
+
+  /// Powercode integration
+  pub powercode: PowercodeIntegration,
+
+  /// Sonar integration
+  pub sonar: SonarIntegration,
+
+  /// Generic REST/HTTP integration
+  pub rest_http: RestHttpIntegration,
+
+  /// InfluxDB graphing
+  pub influxdb: InfluxDbIntegration,
+}
+
+impl Default for IntegrationSettings {
+  fn default() -> Self {
+    Self {
+      circuit_name_use_address: true,
+      overwrite_network_json_always: false,
+      exclude_sites: Vec::new(),
+      find_ipv6_using_mikrotik: false,
+      bandwidth_overhead_factor: 1.0,
+      committed_bandwidth_multiplier: 0.98,
+      exception_cpes: BTreeMap::new(),
+      uisp: UispIntegration::default(),
+      splynx: SplynxIntegration::default(),
+      powercode: PowercodeIntegration::default(),
+      sonar: SonarIntegration::default(),
+      rest_http: RestHttpIntegration::default(),
+      influxdb: InfluxDbIntegration::default(),
+    }
+  }
+}
+
+/// `[integrations.uisp]`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct UispIntegration {
+  /// Import from UISP on a schedule
+  pub enabled: bool,
+
+  /// UISP API token
+  pub token: String,
+
+  /// Everything before `/nms/` in the UISP URL
+  pub url: String,
+
+  /// The root site of the network tree
+  pub site: String,
+
+  /// `full` or `flat`
+  pub strategy: String,
+
+  /// `none`, `ignore` or `slow`
+  pub suspended_strategy: String,
+
+  /// Assumed capacity of AirMax radios, as a fraction of reported
+  pub airmax_capacity: f64,
+
+  /// Assumed capacity of LTU radios, as a fraction of reported
+  pub ltu_capacity: f64,
+
+  /// Use the PtMP AP as the parent of its clients
+  pub use_ptmp_as_parent: bool,
+}
+
+impl Default for UispIntegration {
+  fn default() -> Self {
+    Self {
+      enabled: false,
+      token: String::new(),
+      url: String::new(),
+      site: String::new(),
+      strategy: "full".to_string(),
+      suspended_strategy: "none".to_string(),
+      airmax_capacity: 0.65,
+      ltu_capacity: 0.9,
+      use_ptmp_as_parent: false,
+    }
+  }
+}
+
+/// `[integrations.splynx]`
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct SplynxIntegration {
+  /// Import from Splynx on a schedule
+  pub enabled: bool,
+
+  /// Splynx API key
+  pub api_key: String,
+
+  /// Splynx API secret
+  pub api_secret: String,
+
+  /// Everything before `/api/2.0/` in the Splynx URL
+  pub url: String,
+}
+
+/// `[integrations.powercode]`
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct PowercodeIntegration {
+  /// Import from Powercode on a schedule
+  pub enabled: bool,
+
+  /// Powercode API key
+  pub api_key: String,
+
+  /// Everything before `:444/api/` in the Powercode URL
+  pub api_url: String,
+}
+
+/// `[integrations.sonar]`
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct SonarIntegration {
+  /// Import from Sonar on a schedule
+  pub enabled: bool,
+
+  /// Sonar API key
+  pub api_key: String,
+
+  /// Sonar GraphQL URL
+  pub api_url: String,
+
+  /// SNMP community used to query AP clients
+  pub snmp_community: String,
+
+  /// AirMax AP model IDs to query over SNMP
+  pub airmax_ap_model_ids: Vec<String>,
+
+  /// LTU AP model IDs to query over SNMP
+  pub ltu_ap_model_ids: Vec<String>,
+
+  /// Account statuses to shape. Empty uses every activating status.
+  pub active_status_ids: Vec<String>,
+}
+
+/// `[integrations.rest_http]`
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct RestHttpIntegration {
+  /// Import over HTTP on a schedule
+  pub enabled: bool,
+
+  /// Base URL of the service
+  pub base_url: String,
+
+  /// Path of the `ShapedDevices.csv` data
+  pub devices_uri: String,
+
+  /// Path of the `network.json` data
+  pub network_uri: String,
+
+  /// Extra options passed to `requests` (`params`, `headers`,
+  /// `verify`)
+  pub requests_config: Map<String, Value>,
+
+  /// If set, keep timestamped copies of each import here
+  pub log_changes: String,
+}
+
+/// `[integrations.influxdb]`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct InfluxDbIntegration {
+  /// Send bandwidth and latency graphs to InfluxDB
+  pub enabled: bool,
+
+  /// InfluxDB URL
+  pub url: String,
+
+  /// InfluxDB bucket
+  pub bucket: String,
+
+  /// InfluxDB organization
+  pub org: String,
+
+  /// InfluxDB token
+  pub token: String,
+}
+
+impl Default for InfluxDbIntegration {
+  fn default() -> Self {
+    Self {
+      enabled: false,
+      url: "http://localhost:8086".to_string(),
+      bucket: "libreqos".to_string(),
+      org: String::new(),
+      token: String::new(),
+    }
+  }
+}
+
+/// Every setting that used to live in `ispConfig.py`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct IspSettings {
+  /// `[interfaces]`
+  pub interfaces: InterfaceSettings,
+
+  /// `[queues]`
+  pub queues: QueueSettings,
+
+  /// `[subnets]`
+  pub subnets: SubnetSettings,
+
+  /// `[integrations]`
+  pub integrations: IntegrationSettings,
+}
+
+// Evaluates ispConfig.py with Python itself, so that multi-line
+// values, comments and computed values all come out right.
+const DUMP_ISP_CONFIG: &str = r#"
+import json, runpy, sys
+values = runpy.run_path(sys.argv[1])
+print(json.dumps({k: v for k, v in values.items() if not k.startswith('_') and isinstance(v, (bool, int, float, str, list, dict))}, default=str))
+"#;
+
+impl IspSettings {
+  /// Reads the settings from `/etc/lqos.conf`. Returns `None` if it
+  /// has no `[queues]` section, i.e. `ispConfig.py` is still in use.
+  pub fn load() -> Option<Self> {
+    etc::EtcLqos::load().ok().and_then(|cfg| cfg.isp_settings())
+  }
+
+  /// Evaluates an `ispConfig.py` file (with `python3`) and maps its
+  /// values onto typed settings.
+  pub fn import_isp_config(path: &Path) -> Result<Self, LibreQoSConfigError> {
+    let output = Command::new("python3")
+      .args(["-c", DUMP_ISP_CONFIG])
+      .arg(path)
+      .output()
+      .map_err(|e| LibreQoSConfigError::MigrationFailed(e.to_string()))?;
+    if !output.status.success() {
+      let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+      error!("Unable to evaluate {}: {stderr}", path.display());
+      return Err(LibreQoSConfigError::MigrationFailed(stderr));
+    }
+    let values: Map<String, Value> = serde_json::from_slice(&output.stdout)
+      .map_err(|e| LibreQoSConfigError::MigrationFailed(e.to_string()))?;
+    Ok(Self::from_isp_config(&values))
+  }
+
+  /// Maps the variables of an evaluated `ispConfig.py` onto typed
+  /// settings. Anything missing takes its default.
+  pub fn from_isp_config(values: &Map<String, Value>) -> Self {
+    let py = PyValues(values);
+    let queues = QueueSettings::default();
+    let integrations = IntegrationSettings::default();
+    let uisp = UispIntegration::default();
+    let influxdb = InfluxDbIntegration::default();
+    let rest = values
+      .get("automaticImportRestHttp")
+      .or(values.get("httpRestIntegrationConfig"))
+      .and_then(Value::as_object)
+      .map(PyValues);
+    Self {
+      interfaces: InterfaceSettings {
+        isp_interface: py.string("interfaceA", ""),
+        internet_interface: py.string("interfaceB", ""),
+        on_a_stick: py.bool("OnAStick", false),
+        isp_vlan: py.number("StickVlanA", 0.0) as u16,
+        internet_vlan: py.number("StickVlanB", 0.0) as u16,
+        isp_inner_vlan: py.number("StickVlanAInner", 0.0) as u16,
+        internet_inner_vlan: py.number("StickVlanBInner", 0.0) as u16,
+      },
+      queues: QueueSettings {
+        sqm: py.string("sqm", &queues.sqm),
+        monitor_only: py.bool("monitorOnlyMode", queues.monitor_only),
+        uplink_download_mbps: py.number(
+          "upstreamBandwidthCapacityDownloadMbps",
+          queues.uplink_download_mbps.into(),
+        ) as u32,
+        uplink_upload_mbps: py.number(
+          "upstreamBandwidthCapacityUploadMbps",
+          queues.uplink_upload_mbps.into(),
+        ) as u32,
+        generated_pn_download_mbps: py.number(
+          "generatedPNDownloadMbps",
+          queues.generated_pn_download_mbps.into(),
+        ) as u32,
+        generated_pn_upload_mbps: py.number(
+          "generatedPNUploadMbps",
+          queues.generated_pn_upload_mbps.into(),
+        ) as u32,
+        use_binpacking: py
+          .bool("useBinPackingToBalanceCPU", queues.use_binpacking),
+        override_available_queues: py.number(
+          "queuesAvailableOverride",
+          queues.override_available_queues.into(),
+        ) as u32,
+        enable_shell_commands: py
+          .bool("enableActualShellCommands", queues.enable_shell_commands),
+        run_as_sudo: py.bool("runShellCommandsAsSudo", queues.run_as_sudo),
+        queue_refresh_interval_mins: py.number(
+          "queueRefreshIntervalMins",
+          queues.queue_refresh_interval_mins.into(),
+        ) as u32,
+      },
+      subnets: SubnetSettings {
+        allowed: py.list("allowedSubnets"),
+        ignored: py.list("ignoreSubnets"),
+      },
+      integrations: IntegrationSettings {
+        circuit_name_use_address: py.bool(
+          "circuitNameUseAddress",
+          integrations.circuit_name_use_address,
+        ),
+        overwrite_network_json_always: py.bool(
+          "overwriteNetworkJSONalways",
+          integrations.overwrite_network_json_always,
+        ),
+        exclude_sites: py.list("excludeSites"),
+        find_ipv6_using_mikrotik: py.bool("findIPv6usingMikrotik", false),
+        bandwidth_overhead_factor: py.number(
+          "bandwidthOverheadFactor",
+          integrations.bandwidth_overhead_factor,
+        ),
+        committed_bandwidth_multiplier: py.number(
+          "committedBandwidthMultiplier",
+          integrations.committed_bandwidth_multiplier,
+        ),
+        exception_cpes: py.map("exceptionCPEs"),
+        uisp: UispIntegration {
+          enabled: py.bool("automaticImportUISP", false),
+          token: py.string("uispAuthToken", ""),
+          url: py.string("UISPbaseURL", ""),
+          site: py.string("uispSite", ""),
+          strategy: py.string("uispStrategy", &uisp.strategy),
+          suspended_strategy: py
+            .string("uispSuspendedStrategy", &uisp.suspended_strategy),
+          airmax_capacity: py.number("airMax_capacity", uisp.airmax_capacity),
+          ltu_capacity: py.number("ltu_capacity", uisp.ltu_capacity),
+          use_ptmp_as_parent: py.bool("usePtMPasParent", false),
+        },
+        splynx: SplynxIntegration {
+          enabled: py.bool("automaticImportSplynx", false),
+          api_key: py.string("splynx_api_key", ""),
+          api_secret: py.string("splynx_api_secret", ""),
+          url: py.string("splynx_api_url", ""),
+        },
+        powercode: PowercodeIntegration {
+          enabled: py.bool("automaticImportPowercode", false),
+          api_key: py.string("powercode_api_key", ""),
+          api_url: py.string("powercode_api_url", ""),
+        },
+        sonar: SonarIntegration {
+          enabled: py.bool("automaticImportSonar", false),
+          api_key: py.string("sonar_api_key", ""),
+          api_url: py.string("sonar_api_url", ""),
+          snmp_community: py.string("snmp_community", ""),
+          airmax_ap_model_ids: py.list("sonar_airmax_ap_model_ids"),
+          ltu_ap_model_ids: py.list("sonar_ltu_ap_model_ids"),
+          active_status_ids: py.list("sonar_active_status_ids"),
+        },
+        rest_http: rest
+          .map(|rest| RestHttpIntegration {
+            enabled: rest.bool("enabled", false),
+            base_url: rest.string("baseURL", ""),
+            devices_uri: rest
+              .string("devicesURI", &rest.string("shaperURI", "")),
+            network_uri: rest.string("networkURI", ""),
+            requests_config: rest
+              .0
+              .get("requestsConfig")
+              .and_then(Value::as_object)
+              .map(without_nulls)
+              .unwrap_or_default(),
+            log_changes: rest.string("logChanges", ""),
+          })
+          .unwrap_or_default(),
+        influxdb: InfluxDbIntegration {
+          enabled: py.bool("influxDBEnabled", false),
+          url: py.string("influxDBurl", &influxdb.url),
+          bucket: py.string("influxDBBucket", &influxdb.bucket),
+          org: py.string("influxDBOrg", ""),
+          token: py.string("influxDBtoken", ""),
+        },
+      },
+    }
+  }
+
+  /// Writes the settings into the `[interfaces]`, `[queues]`,
+  /// `[subnets]` and `[integrations]` sections of an `/etc/lqos.conf`
+  /// document, replacing any already there. Other sections (and
+  /// their comments) are left alone.
+  pub fn write_to_document(
+    &self,
+    document: &mut Document,
+  ) -> Result<(), LibreQoSConfigError> {
+    let serialized = toml_edit::ser::to_document(self)
+      .map_err(|e| LibreQoSConfigError::SerializeFail(e.to_string()))?;
+    for (key, item) in serialized.iter() {
+      let mut item = item.clone();
+      promote_tables(&mut item);
+      document[key] = item;
+    }
+    Ok(())
+  }
+}
+
+// toml_edit serializes nested structs as inline tables; write them as
+// regular `[section]` tables instead.
+fn promote_tables(item: &mut Item) {
+  if let Some(inline) = item.as_inline_table() {
+    *item = Item::Table(inline.clone().into_table());
+  }
+  if let Some(table) = item.as_table_mut() {
+    for (_, child) in table.iter_mut() {
+      promote_tables(child);
+    }
+  }
+}
+
+/// Imports `ispConfig.py` into `/etc/lqos.conf` (backing it up to
+/// `/etc/lqos.conf.backup` first). Fails if `/etc/lqos.conf` already
+/// has the settings, unless `force` is set.
+pub fn migrate_isp_config(
+  isp_config: &Path,
+  force: bool,
+) -> Result<IspSettings, LibreQoSConfigError> {
+  let cfg =
+    etc::EtcLqos::load().map_err(|_| LibreQoSConfigError::CannotOpenEtcLqos)?;
+  if cfg.isp_settings().is_some() && !force {
+    return Err(LibreQoSConfigError::MigrationFailed(
+      "/etc/lqos.conf already contains a [queues] section".to_string(),
+    ));
+  }
+  let settings = IspSettings::import_isp_config(isp_config)?;
+  let mut document = etc::load_document()
+    .map_err(|_| LibreQoSConfigError::CannotOpenEtcLqos)?;
+  settings.write_to_document(&mut document)?;
+  cfg
+    .save(&mut document)
+    .map_err(|_| LibreQoSConfigError::CannotWrite)?;
+  Ok(settings)
+}
+
+// TOML has no null, so drop Python's `None`s.
+fn without_nulls(map: &Map<String, Value>) -> Map<String, Value> {
+  map
+    .iter()
+    .filter(|(_, v)| !v.is_null())
+    .map(|(k, v)| match v {
+      Value::Object(child) => (k.clone(), Value::Object(without_nulls(child))),
+      _ => (k.clone(), v.clone()),
+    })
+    .collect()
+}
+
+/// Reads Python values tolerantly: numbers may be strings, lists may
+/// be numbers or strings.
+struct PyValues<'a>(&'a Map<String, Value>);
+
+impl PyValues<'_> {
+  fn string(&self, key: &str, default: &str) -> String {
+    match self.0.get(key) {
+      Some(Value::String(s)) => s.clone(),
+      Some(Value::Number(n)) => n.to_string(),
+      _ => default.to_string(),
+    }
+  }
+
+  fn bool(&self, key: &str, default: bool) -> bool {
+    match self.0.get(key) {
+      Some(Value::Bool(b)) => *b,
+      Some(Value::String(s)) => s == "True" || s == "true",
+      _ => default,
+    }
+  }
+
+  fn number(&self, key: &str, default: f64) -> f64 {
+    match self.0.get(key) {
+      Some(Value::Number(n)) => n.as_f64().unwrap_or(default),
+      Some(Value::String(s)) => s.trim().parse().unwrap_or(default),
+      _ => default,
+    }
+  }
+
+  fn list(&self, key: &str) -> Vec<String> {
+    match self.0.get(key) {
+      Some(Value::Array(items)) => items
+        .iter()
+        .filter_map(|item| match item {
+          Value::String(s) => Some(s.clone()),
+          Value::Number(n) => Some(n.to_string()),
+          _ => None,
+        })
+        .collect(),
+      _ => Vec::new(),
+    }
+  }
+
+  fn map(&self, key: &str) -> BTreeMap<String, String> {
+    match self.0.get(key) {
+      Some(Value::Object(map)) => map
+        .iter()
+        .filter_map(|(k, v)| v.as_str().map(|v| (k.clone(), v.to_string())))
+        .collect(),
+      _ => BTreeMap::new(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn isp_config() -> Map<String, Value> {
+    serde_json::from_str(
+      r#"{
+        "sqm": "fq_codel",
+        "upstreamBandwidthCapacityDownloadMbps": 2000,
+        "upstreamBandwidthCapacityUploadMbps": 500,
+        "interfaceA": "eth1",
+        "interfaceB": "eth2",
+        "OnAStick": false,
+        "StickVlanA": 3,
+        "ignoreSubnets": ["192.168.0.0/16"],
+        "allowedSubnets": ["100.64.0.0/10", "10.0.0.0/8"],
+        "sonar_airmax_ap_model_ids": ["29", 43],
+        "exceptionCPEs": { "CPE-1": "AP-1" },
+        "automaticImportUISP": true,
+        "uispSite": "Root",
+        "airMax_capacity": 0.5,
+        "automaticImportRestHttp": {
+          "enabled": true,
+          "baseURL": "https://example.com",
+          "requestsConfig": { "verify": false, "auth": null }
+        }
+      }"#,
+    )
+    .unwrap()
+  }
+
+  #[test]
+  fn maps_isp_config_values() {
+    let settings = IspSettings::from_isp_config(&isp_config());
+    assert_eq!(settings.interfaces.isp_interface, "eth1");
+    assert_eq!(settings.interfaces.internet_interface, "eth2");
+    assert_eq!(settings.interfaces.isp_vlan, 3);
+    assert_eq!(settings.queues.sqm, "fq_codel");
+    assert_eq!(settings.queues.uplink_download_mbps, 2000);
+    assert_eq!(settings.queues.queue_refresh_interval_mins, 30);
+    assert!(settings.queues.enable_shell_commands);
+    assert_eq!(settings.subnets.allowed.len(), 2);
+    assert_eq!(settings.integrations.sonar.airmax_ap_model_ids, ["29", "43"]);
+    assert_eq!(settings.integrations.exception_cpes["CPE-1"], "AP-1");
+    assert!(settings.integrations.uisp.enabled);
+    assert_eq!(settings.integrations.uisp.site, "Root");
+    assert_eq!(settings.integrations.uisp.airmax_capacity, 0.5);
+    assert_eq!(settings.integrations.uisp.strategy, "full");
+    assert!(settings.integrations.rest_http.enabled);
+    assert_eq!(
+      settings.integrations.rest_http.requests_config["verify"],
+      Value::Bool(false)
+    );
+  }
+
+  #[test]
+  fn settings_round_trip_through_lqos_conf() {
+    let settings = IspSettings::from_isp_config(&isp_config());
+    let mut document = include_str!("../../../lqos.example")
+      .parse::<Document>()
+      .unwrap();
+    settings.write_to_document(&mut document).unwrap();
+    let text = document.to_string();
+    assert!(text.contains("[queues]"));
+    assert!(text.contains("[integrations.uisp]"));
+    assert!(text.contains("[bridge]"));
+    let cfg: etc::EtcLqos = toml_edit::de::from_str(&text).unwrap();
+    assert_eq!(cfg.isp_settings().unwrap(), settings);
+  }
+}
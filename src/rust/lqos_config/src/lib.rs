@@ -1,24 +1,67 @@
 //! The `lqos_config` crate stores and handles LibreQoS configuration.
 //! Configuration is drawn from:
-//! * The `ispConfig.py` file.
+//! * The `ispConfig.py` file (or, once migrated, `/etc/lqos.conf`).
 //! * The `/etc/lqos.conf` file.
 //! * `ShapedDevices.csv` files.
 //! * `network.json` files.
+//! * `RatePlans.toml`, `Quotas.toml` and `SqmProfiles.toml` files.
 
 #![warn(missing_docs)]
 mod authentication;
+mod circuit_history;
 mod etc;
+mod isp_settings;
 mod libre_qos_config;
 mod network_json;
 mod program_control;
+mod quotas;
+mod rate_plans;
+mod revisions;
 mod shaped_devices;
+mod sqm_profiles;
+mod validation;
 
-pub use authentication::{UserRole, WebUsers};
-pub use etc::{BridgeConfig, BridgeInterface, BridgeVlan, EtcLqos, Tunables, enable_long_term_stats};
+pub use authentication::{
+  ApiKeyInfo, ApiScope, AuthBackendKind, AuthenticationError, BackendCheck,
+  BackendResult, LdapSettings, LocalFallback, LocalPassword, LoginThrottle,
+  PasswordBackend, RadiusSettings, ScopedView, SessionInfo, UserRole,
+  UserScope, WebUsers,
+};
+pub use circuit_history::{
+  CircuitHistory, CircuitHistoryError, CircuitSample, HistoryRange,
+};
+pub use etc::{BridgeConfig, BridgeInterface, BridgeVlan, CircuitHistorySettings, EtcLqos, InterfacePair, QueuePlanner, SelfTest, Tunables, WebUiSettings, enable_long_term_stats};
+pub use isp_settings::{
+  migrate_isp_config, InfluxDbIntegration, IntegrationSettings,
+  InterfaceSettings, IspSettings, PowercodeIntegration, QueueSettings,
+  RestHttpIntegration, SonarIntegration, SplynxIntegration, SubnetSettings,
+  UispIntegration,
+};
 pub use libre_qos_config::LibreQoSConfig;
-pub use network_json::{NetworkJson, NetworkJsonNode, NetworkJsonTransport};
+pub use network_json::{
+  NetworkJson, NetworkJsonNode, NetworkJsonTransport, NetworkTree,
+  NetworkTreeError, NetworkTreeNode, NodeSettings, ROOT_ID as NETWORK_ROOT_ID,
+};
 pub use program_control::load_libreqos;
-pub use shaped_devices::{ConfigShapedDevices, ShapedDevice};
+pub use quotas::{
+  quota_today, CircuitUsage, QuotaError, QuotaPeriod, QuotaPlan, QuotaPlans,
+  QuotaTopUp, QuotaUsage,
+};
+pub use rate_plans::{
+  CircuitRates, PlanTime, RatePlan, RatePlanError, RatePlans, RateWindow,
+};
+pub use revisions::{
+  diff_shaped_devices, record_config_revision, CircuitChange, CircuitSummary,
+  ConfigFile, ConfigRevisions, FieldChange, NodeChange, NodeMove,
+  NodeSummary, RevisionDiff, RevisionError, RevisionFiles, RevisionInfo,
+};
+pub use shaped_devices::{BurstSettings, ConfigShapedDevices, ShapedDevice};
+pub use sqm_profiles::{SqmProfile, SqmProfileError, SqmProfiles};
+pub use validation::{
+  validate, validate_configuration, validate_network_edit,
+  validate_shaped_devices, ValidationFinding, ValidationInput,
+  ValidationSeverity, ValidationSource,
+};
 
 /// Used as a constant in determining buffer preallocation
 pub const SUPPORTED_CUSTOMERS: usize = 16_000_000;
@@ -1,11 +1,13 @@
 //! `ispConfig.py` is part of the Python side of LibreQoS. This module
-//! reads, writes and maps values from the Python file.
+//! reads, writes and maps values from the Python file - or, once it
+//! has been migrated, from the typed sections of `/etc/lqos.conf`.
 
-use crate::etc;
+use crate::{etc, isp_settings::IspSettings, InterfacePair};
 use ip_network::IpNetwork;
 use log::error;
 use serde::{Deserialize, Serialize};
 use std::{
+  collections::HashSet,
   fs::{self, read_to_string, remove_file, OpenOptions},
   io::Write,
   net::IpAddr,
@@ -29,6 +31,11 @@ pub struct LibreQoSConfig {
   /// In (internet, ISP) order.
   pub stick_vlans: (u16, u16),
 
+  /// With QinQ on a stick, the inner (C-VLAN) tags that accompany
+  /// `stick_vlans`, in the same order. 0 if single-tagged.
+  #[serde(default)]
+  pub stick_inner_vlans: (u16, u16),
+
   /// The value of the SQM field from `ispConfig.py`
   pub sqm: String,
 
@@ -96,9 +103,13 @@ pub struct LibreQoSConfig {
 }
 
 impl LibreQoSConfig {
-  /// Does the ispConfig.py file exist?
+  /// Does the ispConfig.py file exist? (Or have its settings been
+  /// migrated into `/etc/lqos.conf`?)
   pub fn config_exists() -> bool {
     if let Ok(cfg) = etc::EtcLqos::load() {
+      if cfg.isp_settings().is_some() {
+        return true;
+      }
       let base_path = Path::new(&cfg.lqos_directory);
       let final_path = base_path.join("ispConfig.py");
       final_path.exists()
@@ -107,9 +118,43 @@ impl LibreQoSConfig {
     }
   }
 
-  /// Loads `ispConfig.py` into a management object.
+  /// The shaping bridge pairs `lqosd` should attach to. These come
+  /// from `interface_pairs` in `/etc/lqos.conf` if it is present,
+  /// otherwise a single pair named `default` is built from the
+  /// interface and stick settings in `ispConfig.py`.
+  pub fn interface_pairs(
+    &self,
+  ) -> Result<Vec<InterfacePair>, LibreQoSConfigError> {
+    let configured =
+      etc::EtcLqos::load().ok().and_then(|cfg| cfg.interface_pairs);
+    let pairs = match configured {
+      Some(pairs) if !pairs.is_empty() => pairs,
+      _ => vec![self.default_interface_pair()],
+    };
+    validate_interface_pairs(&pairs)?;
+    Ok(pairs)
+  }
+
+  fn default_interface_pair(&self) -> InterfacePair {
+    InterfacePair {
+      name: "default".to_string(),
+      internet_interface: self.internet_interface.clone(),
+      isp_interface: self.isp_interface.clone(),
+      on_a_stick: self.on_a_stick_mode,
+      internet_vlan: self.stick_vlans.1,
+      isp_vlan: self.stick_vlans.0,
+      internet_inner_vlan: self.stick_inner_vlans.1,
+      isp_inner_vlan: self.stick_inner_vlans.0,
+    }
+  }
+
+  /// Loads the settings from `/etc/lqos.conf` if they have been
+  /// migrated there, otherwise from `ispConfig.py`.
   pub fn load() -> Result<Self, LibreQoSConfigError> {
     if let Ok(cfg) = etc::EtcLqos::load() {
+      if let Some(settings) = cfg.isp_settings() {
+        return Ok(Self::from_settings(&settings));
+      }
       let base_path = Path::new(&cfg.lqos_directory);
       let final_path = base_path.join("ispConfig.py");
       Ok(Self::load_from_path(&final_path)?)
@@ -119,6 +164,68 @@ impl LibreQoSConfig {
     }
   }
 
+  fn from_settings(settings: &IspSettings) -> Self {
+    let interfaces = &settings.interfaces;
+    let queues = &settings.queues;
+    let integrations = &settings.integrations;
+    Self {
+      internet_interface: interfaces.internet_interface.clone(),
+      isp_interface: interfaces.isp_interface.clone(),
+      on_a_stick_mode: interfaces.on_a_stick,
+      stick_vlans: (interfaces.isp_vlan, interfaces.internet_vlan),
+      stick_inner_vlans: (
+        interfaces.isp_inner_vlan,
+        interfaces.internet_inner_vlan,
+      ),
+      sqm: queues.sqm.clone(),
+      monitor_mode: queues.monitor_only,
+      total_download_mbps: queues.uplink_download_mbps,
+      total_upload_mbps: queues.uplink_upload_mbps,
+      generated_download_mbps: queues.generated_pn_download_mbps,
+      generated_upload_mbps: queues.generated_pn_upload_mbps,
+      use_binpacking: queues.use_binpacking,
+      enable_shell_commands: queues.enable_shell_commands,
+      run_as_sudo: queues.run_as_sudo,
+      override_queue_count: queues.override_available_queues,
+      automatic_import_uisp: integrations.uisp.enabled,
+      uisp_auth_token: integrations.uisp.token.clone(),
+      uisp_base_url: integrations.uisp.url.clone(),
+      uisp_root_site: integrations.uisp.site.clone(),
+      circuit_name_use_address: integrations.circuit_name_use_address,
+      uisp_strategy: integrations.uisp.strategy.clone(),
+      uisp_suspended_strategy: integrations.uisp.suspended_strategy.clone(),
+      bandwidth_overhead_factor: integrations.bandwidth_overhead_factor
+        as f32,
+      allowed_subnets: format!("[{}]", settings.subnets.allowed.join(",")),
+      ignored_subnets: format!("[{}]", settings.subnets.ignored.join(",")),
+      overwrite_network_json_always: integrations
+        .overwrite_network_json_always,
+    }
+  }
+
+  /// Copies the values `save` is able to change back into typed
+  /// settings.
+  fn apply_to_settings(&self, settings: &mut IspSettings) {
+    let interfaces = &mut settings.interfaces;
+    interfaces.isp_interface.clone_from(&self.isp_interface);
+    interfaces.internet_interface.clone_from(&self.internet_interface);
+    interfaces.on_a_stick = self.on_a_stick_mode;
+    (interfaces.isp_vlan, interfaces.internet_vlan) = self.stick_vlans;
+    (interfaces.isp_inner_vlan, interfaces.internet_inner_vlan) =
+      self.stick_inner_vlans;
+    let queues = &mut settings.queues;
+    queues.sqm.clone_from(&self.sqm);
+    queues.monitor_only = self.monitor_mode;
+    queues.uplink_download_mbps = self.total_download_mbps;
+    queues.uplink_upload_mbps = self.total_upload_mbps;
+    queues.generated_pn_download_mbps = self.generated_download_mbps;
+    queues.generated_pn_upload_mbps = self.generated_upload_mbps;
+    queues.use_binpacking = self.use_binpacking;
+    queues.enable_shell_commands = self.enable_shell_commands;
+    queues.run_as_sudo = self.run_as_sudo;
+    queues.override_available_queues = self.override_queue_count;
+  }
+
   fn load_from_path(path: &PathBuf) -> Result<Self, LibreQoSConfigError> {
     let path = Path::new(path);
     if !path.exists() {
@@ -132,6 +239,7 @@ impl LibreQoSConfig {
       isp_interface: String::new(),
       on_a_stick_mode: false,
       stick_vlans: (0, 0),
+      stick_inner_vlans: (0, 0),
       sqm: String::new(),
       monitor_mode: false,
       total_download_mbps: 0,
@@ -183,29 +291,17 @@ impl LibreQoSConfig {
               self.on_a_stick_mode = true;
             }
           }
-          if line.starts_with("StickVlanA") {
-            let vlan_string = split_at_equals(line);
-            if let Ok(vlan) = vlan_string.parse() {
-              self.stick_vlans.0 = vlan;
-            } else {
-              error!(
-                "Unable to parse contents of StickVlanA from ispConfig.py"
-              );
-              error!("{line}");
-              return Err(LibreQoSConfigError::ParseError(line.to_string()));
-            }
+          if is_key(line, "StickVlanA") {
+            self.stick_vlans.0 = parse_vlan(line, "StickVlanA")?;
           }
-          if line.starts_with("StickVlanB") {
-            let vlan_string = split_at_equals(line);
-            if let Ok(vlan) = vlan_string.parse() {
-              self.stick_vlans.1 = vlan;
-            } else {
-              error!(
-                "Unable to parse contents of StickVlanB from ispConfig.py"
-              );
-              error!("{line}");
-              return Err(LibreQoSConfigError::ParseError(line.to_string()));
-            }
+          if is_key(line, "StickVlanB") {
+            self.stick_vlans.1 = parse_vlan(line, "StickVlanB")?;
+          }
+          if is_key(line, "StickVlanAInner") {
+            self.stick_inner_vlans.0 = parse_vlan(line, "StickVlanAInner")?;
+          }
+          if is_key(line, "StickVlanBInner") {
+            self.stick_inner_vlans.1 = parse_vlan(line, "StickVlanBInner")?;
           }
           if line.starts_with("sqm") {
             self.sqm = split_at_equals(line);
@@ -324,13 +420,24 @@ impl LibreQoSConfig {
   }
 
   /// Saves the current values to `ispConfig.py` and store the
-  /// previous settings in `ispConfig.py.backup`.
+  /// previous settings in `ispConfig.py.backup`. If the settings have
+  /// been migrated, they are saved to `/etc/lqos.conf` instead (with
+  /// a backup in `/etc/lqos.conf.backup`).
   ///
   pub fn save(&self) -> Result<(), LibreQoSConfigError> {
     // Find the config
     let cfg = etc::EtcLqos::load().map_err(|_| {
       crate::libre_qos_config::LibreQoSConfigError::CannotOpenEtcLqos
     })?;
+    if let Some(mut settings) = cfg.isp_settings() {
+      self.apply_to_settings(&mut settings);
+      let mut document = etc::load_document()
+        .map_err(|_| LibreQoSConfigError::CannotOpenEtcLqos)?;
+      settings.write_to_document(&mut document)?;
+      return cfg
+        .save(&mut document)
+        .map_err(|_| LibreQoSConfigError::CannotWrite);
+    }
     let base_path = Path::new(&cfg.lqos_directory);
     let final_path = base_path.join("ispConfig.py");
     let backup_path = base_path.join("ispConfig.py.backup");
@@ -370,12 +477,18 @@ impl LibreQoSConfig {
           if self.on_a_stick_mode { "True" } else { "False" }
         );
       }
-      if line.starts_with("StickVlanA") {
+      if is_key(&line, "StickVlanA") {
         line = format!("StickVlanA = {}", self.stick_vlans.0);
       }
-      if line.starts_with("StickVlanB") {
+      if is_key(&line, "StickVlanB") {
         line = format!("StickVlanB = {}", self.stick_vlans.1);
       }
+      if is_key(&line, "StickVlanAInner") {
+        line = format!("StickVlanAInner = {}", self.stick_inner_vlans.0);
+      }
+      if is_key(&line, "StickVlanBInner") {
+        line = format!("StickVlanBInner = {}", self.stick_inner_vlans.1);
+      }
       if line.starts_with("sqm") {
         line = format!("sqm = '{}'", self.sqm);
       }
@@ -470,6 +583,61 @@ fn split_at_equals(line: &str) -> String {
   line.split('=').nth(1).unwrap_or("").trim().replace(['\"', '\''], "")
 }
 
+/// Every pair shares one queue layout, so they must agree on stick
+/// mode; and an interface can only carry one XDP program.
+fn validate_interface_pairs(
+  pairs: &[InterfacePair],
+) -> Result<(), LibreQoSConfigError> {
+  let fail = |msg: String| {
+    error!("{msg}");
+    Err(LibreQoSConfigError::InvalidInterfacePairs(msg))
+  };
+  let mut names = HashSet::new();
+  let mut interfaces = HashSet::new();
+  for pair in pairs.iter() {
+    if pair.name.is_empty() || !names.insert(pair.name.as_str()) {
+      return fail(format!(
+        "Interface pair names must be unique and not empty ({:?})",
+        pair.name
+      ));
+    }
+    if pair.on_a_stick != pairs[0].on_a_stick {
+      return fail(
+        "Interface pairs cannot mix on-a-stick and two-interface modes"
+          .to_string(),
+      );
+    }
+    for interface in pair.interfaces() {
+      if interface.is_empty() {
+        return fail(format!(
+          "Interface pair {} is missing an interface",
+          pair.name
+        ));
+      }
+      if !interfaces.insert(interface) {
+        return fail(format!("Interface {interface} is used more than once"));
+      }
+    }
+  }
+  Ok(())
+}
+
+/// Does `line` assign to exactly `key`? (`StickVlanA` must not match
+/// `StickVlanAInner`)
+fn is_key(line: &str, key: &str) -> bool {
+  line.split_once('=').map(|(k, _)| k.trim() == key).unwrap_or(false)
+}
+
+fn parse_vlan(line: &str, key: &str) -> Result<u16, LibreQoSConfigError> {
+  if let Ok(vlan) = split_at_equals(line).parse() {
+    Ok(vlan)
+  } else {
+    error!("Unable to parse contents of {key} from ispConfig.py");
+    error!("{line}");
+    Err(LibreQoSConfigError::ParseError(line.to_string()))
+  }
+}
+
 #[derive(Debug, Error)]
 pub enum LibreQoSConfigError {
   #[error("Unable to read /etc/lqos.conf. See other errors for details.")]
@@ -492,6 +660,12 @@ pub enum LibreQoSConfigError {
   CannotWrite,
   #[error("Unable to read IP")]
   CannotReadIP,
+  #[error("Invalid interface pairs in /etc/lqos.conf")]
+  InvalidInterfacePairs(String),
+  #[error("Unable to migrate ispConfig.py: {0}")]
+  MigrationFailed(String),
+  #[error("Unable to serialize settings: {0}")]
+  SerializeFail(String),
 }
 
 fn ip_list_to_ips(
@@ -504,6 +678,7 @@ fn ip_list_to_ips(
   Ok(
     source
       .split(',')
+      .filter(|raw| !raw.is_empty())
       .map(|raw| {
         let split: Vec<&str> = raw.split('/').collect();
         let cidr = split[1].parse::<u8>().unwrap();
@@ -539,3 +714,65 @@ fn ip_list_to_trie(
     });
   table
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn stick_keys_match_exactly() {
+    assert!(is_key("StickVlanA = 3", "StickVlanA"));
+    assert!(is_key("StickVlanA=3", "StickVlanA"));
+    assert!(!is_key("StickVlanAInner = 3", "StickVlanA"));
+    assert!(is_key("StickVlanAInner = 3", "StickVlanAInner"));
+    assert!(!is_key("# StickVlanA = 3", "StickVlanA"));
+  }
+
+  fn pair(name: &str, internet: &str, isp: &str) -> InterfacePair {
+    InterfacePair {
+      name: name.to_string(),
+      internet_interface: internet.to_string(),
+      isp_interface: isp.to_string(),
+      on_a_stick: false,
+      internet_vlan: 0,
+      isp_vlan: 0,
+      internet_inner_vlan: 0,
+      isp_inner_vlan: 0,
+    }
+  }
+
+  #[test]
+  fn valid_interface_pairs() {
+    let pairs = [pair("pop1", "eth0", "eth1"), pair("pop2", "eth2", "eth3")];
+    assert!(validate_interface_pairs(&pairs).is_ok());
+  }
+
+  #[test]
+  fn interface_pairs_cannot_share_interfaces() {
+    let pairs = [pair("pop1", "eth0", "eth1"), pair("pop2", "eth1", "eth3")];
+    assert!(validate_interface_pairs(&pairs).is_err());
+    let pairs = [pair("pop1", "eth0", "eth0")];
+    assert!(validate_interface_pairs(&pairs).is_err());
+  }
+
+  #[test]
+  fn interface_pairs_need_unique_names() {
+    let pairs = [pair("pop", "eth0", "eth1"), pair("pop", "eth2", "eth3")];
+    assert!(validate_interface_pairs(&pairs).is_err());
+  }
+
+  #[test]
+  fn interface_pairs_cannot_mix_modes() {
+    let mut stick = pair("pop2", "eth2", "");
+    stick.on_a_stick = true;
+    let pairs = [pair("pop1", "eth0", "eth1"), stick];
+    assert!(validate_interface_pairs(&pairs).is_err());
+  }
+
+  #[test]
+  fn parse_stick_vlan() {
+    let vlan = parse_vlan("StickVlanBInner = 42", "StickVlanBInner");
+    assert_eq!(vlan.unwrap(), 42);
+    assert!(parse_vlan("StickVlanB = banana", "StickVlanB").is_err());
+  }
+}
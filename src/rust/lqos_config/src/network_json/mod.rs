@@ -1,13 +1,19 @@
+mod tree;
 use crate::etc;
 use dashmap::DashSet;
 use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
 use std::{
+  collections::HashSet,
   fs,
   path::{Path, PathBuf}, sync::atomic::AtomicU64,
 };
 use thiserror::Error;
+pub use tree::{
+  NetworkTree, NetworkTreeError, NetworkTreeNode, NodeSettings,
+};
 
 /// Describes a node in the network map tree.
 #[derive(Debug)]
@@ -15,6 +21,11 @@ pub struct NetworkJsonNode {
   /// The node name, as it appears in `network.json`
   pub name: String,
 
+  /// Stable identifier for the node. Taken from the node's `id` in
+  /// `network.json` if it has one, otherwise generated from the names
+  /// on the path to the node, so it survives re-ordering the file.
+  pub id: String,
+
   /// The maximum throughput allowed per `network.json` for this node
   pub max_throughput: (u32, u32), // In mbps
 
@@ -35,6 +46,9 @@ pub struct NetworkJsonNode {
 
   /// The node type
   pub node_type: Option<String>,
+
+  /// Stable identifiers of the nodes in `parents`, in the same order
+  pub parent_ids: Vec<String>,
 }
 
 impl NetworkJsonNode {
@@ -52,6 +66,8 @@ impl NetworkJsonNode {
       parents: self.parents.clone(),
       immediate_parent: self.immediate_parent,
       node_type: self.node_type.clone(),
+      id: self.id.clone(),
+      parent_ids: self.parent_ids.clone(),
     }
   }
 }
@@ -76,6 +92,12 @@ pub struct NetworkJsonTransport {
   /// The type of node (site, ap, etc.)
   #[serde(rename = "type")]
   pub node_type: Option<String>,
+  /// Stable node identifier
+  #[serde(default)]
+  pub id: String,
+  /// Stable identifiers of parents, matching `parents`
+  #[serde(default)]
+  pub parent_ids: Vec<String>,
 }
 
 /// Holder for the network.json representation.
@@ -84,7 +106,8 @@ pub struct NetworkJsonTransport {
 #[derive(Debug)]
 pub struct NetworkJson {
   /// Nodes that make up the tree, flattened and referenced by index number.
-  /// TODO: We should add a primary key to nodes in network.json.
+  /// Indices change when `network.json` is edited; use `id` to refer to a
+  /// node across reloads.
   pub nodes: Vec<NetworkJsonNode>,
 }
 
@@ -121,35 +144,43 @@ impl NetworkJson {
 
   /// Attempt to load network.json from disk
   pub fn load() -> Result<Self, NetworkJsonError> {
+    if !Self::exists() {
+      return Err(NetworkJsonError::FileNotFound);
+    }
+    let path = Self::path()?;
+    let raw = fs::read_to_string(path)
+      .map_err(|_| NetworkJsonError::ConfigLoadError)?;
+    let json: Value = serde_json::from_str(&raw)
+      .map_err(|_| NetworkJsonError::ConfigLoadError)?;
+    Ok(Self::from_json(&json))
+  }
+
+  /// Builds the flattened tree from parsed `network.json` contents.
+  pub fn from_json(json: &Value) -> Self {
     let mut nodes = vec![NetworkJsonNode {
       name: "Root".to_string(),
+      id: ROOT_ID.to_string(),
       max_throughput: (0, 0),
       current_throughput: (AtomicU64::new(0), AtomicU64::new(0)),
       parents: Vec::new(),
       immediate_parent: None,
       rtts: DashSet::new(),
       node_type: None,
+      parent_ids: Vec::new(),
     }];
-    if !Self::exists() {
-      return Err(NetworkJsonError::FileNotFound);
-    }
-    let path = Self::path()?;
-    let raw = fs::read_to_string(path)
-      .map_err(|_| NetworkJsonError::ConfigLoadError)?;
-    let json: Value = serde_json::from_str(&raw)
-      .map_err(|_| NetworkJsonError::ConfigLoadError)?;
+    let mut ids = HashSet::from([ROOT_ID.to_string()]);
 
     // Start reading from the top. We are at the root node.
     let parents = vec![0];
-    if let Value::Object(map) = &json {
+    if let Value::Object(map) = json {
       for (key, value) in map.iter() {
         if let Value::Object(inner_map) = value {
-          recurse_node(&mut nodes, key, inner_map, &parents, 0);
+          recurse_node(&mut nodes, &mut ids, key, inner_map, &parents, 0);
         }
       }
     }
 
-    Ok(Self { nodes })
+    Self { nodes }
   }
 
   /// Find the index of a circuit_id
@@ -157,6 +188,11 @@ impl NetworkJson {
     self.nodes.iter().position(|n| n.name == name)
   }
 
+  /// Find the index of a node from its stable `id`
+  pub fn get_index_for_id(&self, id: &str) -> Option<usize> {
+    self.nodes.iter().position(|n| n.id == id)
+  }
+
   /// Retrieve a cloned copy of a NetworkJsonNode entry, or None if there isn't
   /// an entry at that index.
   pub fn get_cloned_entry_by_index(
@@ -251,44 +287,92 @@ fn json_to_u32(val: Option<&Value>) -> u32 {
   }
 }
 
+/// The stable identifier of the root node
+pub const ROOT_ID: &str = "root";
+
+// Node IDs generated from the path of names (e.g. "Site1", "AP1") down
+// to the node. Separated with a character that can't appear in JSON
+// keys without escaping, so "A/B" + "C" and "A" + "B/C" differ.
+fn generated_id(nodes: &[NetworkJsonNode], parents: &[usize], name: &str) -> String {
+  let mut hasher = Sha256::new();
+  for idx in parents.iter().skip(1) {
+    hasher.update(nodes[*idx].name.as_bytes());
+    hasher.update([0]);
+  }
+  hasher.update(name.as_bytes());
+  let hash = hasher.finalize();
+  let mut prefix = [0u8; 8];
+  prefix.copy_from_slice(&hash[..8]);
+  format!("{:016x}", u64::from_be_bytes(prefix))
+}
+
+fn explicit_id(json: &Map<String, Value>) -> Option<String> {
+  match json.get("id") {
+    Some(Value::String(id)) if !id.is_empty() => Some(id.clone()),
+    Some(Value::Number(id)) => Some(id.to_string()),
+    _ => None,
+  }
+}
+
 fn recurse_node(
   nodes: &mut Vec<NetworkJsonNode>,
+  ids: &mut HashSet<String>,
   name: &str,
   json: &Map<String, Value>,
   parents: &[usize],
   immediate_parent: usize,
 ) {
-  info!("Mapping {name} from network.json");
   let mut parents = parents.to_vec();
   let my_id = if name != "children" {
+    info!("Mapping {name} from network.json");
+    // Generated IDs can still collide with an explicit one, or with
+    // each other for duplicate names, so add a suffix until unique
+    let mut generated = generated_id(nodes, &parents, name);
+    let base = generated.clone();
+    let mut suffix = 1;
+    while ids.contains(&generated) {
+      suffix += 1;
+      generated = format!("{base}-{suffix}");
+    }
+    let id = match explicit_id(json) {
+      Some(id) if ids.contains(&id) => {
+        warn!("network.json node {name} has a duplicate id ({id}). Using {generated} instead.");
+        generated
+      }
+      Some(id) => id,
+      None => generated,
+    };
+    ids.insert(id.clone());
+    let mut parent_ids: Vec<String> =
+      parents.iter().map(|p| nodes[*p].id.clone()).collect();
+    parent_ids.push(id.clone());
     parents.push(nodes.len());
-    nodes.len()
+    let node = NetworkJsonNode {
+      parents: parents.to_vec(),
+      parent_ids,
+      max_throughput: (
+        json_to_u32(json.get("downloadBandwidthMbps")),
+        json_to_u32(json.get("uploadBandwidthMbps")),
+      ),
+      current_throughput: (AtomicU64::new(0), AtomicU64::new(0)),
+      name: name.to_string(),
+      id,
+      immediate_parent: Some(immediate_parent),
+      rtts: DashSet::new(),
+      node_type: json.get("type").and_then(Value::as_str).map(str::to_string),
+    };
+    nodes.push(node);
+    nodes.len() - 1
   } else {
     nodes.len() - 1
   };
-  let node = NetworkJsonNode {
-    parents: parents.to_vec(),
-    max_throughput: (
-      json_to_u32(json.get("downloadBandwidthMbps")),
-      json_to_u32(json.get("uploadBandwidthMbps")),
-    ),
-    current_throughput: (AtomicU64::new(0), AtomicU64::new(0)),
-    name: name.to_string(),
-    immediate_parent: Some(immediate_parent),
-    rtts: DashSet::new(),
-    node_type: json.get("type").map(|v| v.as_str().unwrap().to_string()),
-  };
-
-  if node.name != "children" {
-    nodes.push(node);
-  }
 
   // Recurse children
   for (key, value) in json.iter() {
     let key_str = key.as_str();
     if key_str != "uploadBandwidthMbps" && key_str != "downloadBandwidthMbps" {
       if let Value::Object(value) = value {
-        recurse_node(nodes, key, value, &parents, my_id);
+        recurse_node(nodes, ids, key, value, &parents, my_id);
       }
     }
   }
@@ -301,3 +385,55 @@ pub enum NetworkJsonError {
   #[error("network.json not found or does not exist")]
   FileNotFound,
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn tree(raw: &str) -> NetworkJson {
+    NetworkJson::from_json(&serde_json::from_str(raw).unwrap())
+  }
+
+  #[test]
+  fn ids_are_stable_and_explicit_ids_win() {
+    let a = tree(
+      r#"{
+        "Site1": { "children": { "AP1": {}, "AP2": { "id": "ap-two" } } },
+        "Site2": { "id": 42, "children": { "AP1": { "id": "ap-two" } } }
+      }"#,
+    );
+    let b = tree(
+      r#"{
+        "Site0": {},
+        "Site1": { "children": { "AP0": {}, "AP1": {} } }
+      }"#,
+    );
+    let name_of = |id| a.get_index_for_id(id).map(|i| a.nodes[i].name.as_str());
+    let site1_ap1 = a.nodes.iter().position(|n| n.name == "AP1").unwrap();
+    assert_eq!(a.nodes[0].id, ROOT_ID);
+    assert_eq!(name_of("ap-two"), Some("AP2"));
+    assert_eq!(name_of("42"), Some("Site2"));
+
+    // The second "ap-two" is a duplicate, so gets a generated id
+    let site2_ap1 = &a.nodes[a.nodes.iter().rposition(|n| n.name == "AP1").unwrap()];
+    assert_ne!(site2_ap1.id, "ap-two");
+    assert_ne!(site2_ap1.id, a.nodes[site1_ap1].id);
+    assert_eq!(site2_ap1.parent_ids, vec![ROOT_ID, "42", &site2_ap1.id]);
+
+    // Adding nodes moves AP1 to a new index, but keeps its generated id
+    let moved = b.nodes.iter().position(|n| n.name == "AP1").unwrap();
+    assert_ne!(moved, site1_ap1);
+    assert_eq!(b.nodes[moved].id, a.nodes[site1_ap1].id);
+  }
+
+  #[test]
+  fn generated_ids_are_unique_and_types_are_optional() {
+    let generated = tree(r#"{ "Site1": {} }"#).nodes[1].id.clone();
+    let taken = tree(&format!(
+      r#"{{ "Site0": {{ "id": "{generated}", "type": 5 }}, "Site1": {{}} }}"#
+    ));
+    assert_eq!(taken.nodes[1].id, generated);
+    assert_eq!(taken.nodes[1].node_type, None);
+    assert_eq!(taken.nodes[2].id, format!("{generated}-2"));
+  }
+}
@@ -0,0 +1,511 @@
+use super::NetworkJson;
+use crate::ShapedDevice;
+use serde::{
+  de::{self, MapAccess, Visitor},
+  ser::SerializeMap,
+  Deserialize, Deserializer, Serialize, Serializer,
+};
+use serde_json::Value;
+use std::{collections::HashSet, fmt, fs, path::Path};
+use thiserror::Error;
+
+/// `network.json` as an editable tree. Nodes are kept in file order,
+/// because the order decides which CPU each top-level node lands on,
+/// and fields the editor doesn't know about are written back as they
+/// were.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct NetworkTree {
+  /// The top-level nodes
+  pub nodes: Vec<NetworkTreeNode>,
+}
+
+/// A node in an editable `network.json` tree
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct NetworkTreeNode {
+  /// The node's name, which circuits use as their parent node
+  pub name: String,
+
+  /// Download capacity, in Mbps
+  pub download_mbps: u32,
+
+  /// Upload capacity, in Mbps
+  pub upload_mbps: u32,
+
+  /// The node type, such as `site` or `ap`
+  pub node_type: Option<String>,
+
+  /// Other fields, such as `id`, in file order
+  #[serde(skip)]
+  pub extra: Vec<(String, Value)>,
+
+  /// Nodes below this one
+  pub children: Vec<NetworkTreeNode>,
+}
+
+/// The editable settings of a node
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct NodeSettings {
+  /// The node's name
+  pub name: String,
+
+  /// Download capacity, in Mbps
+  pub download_mbps: u32,
+
+  /// Upload capacity, in Mbps
+  pub upload_mbps: u32,
+
+  /// The node type, such as `site` or `ap`
+  pub node_type: Option<String>,
+}
+
+impl NetworkTree {
+  /// Loads `network.json` from the LibreQoS directory. A missing file
+  /// is an empty tree, for a flat network.
+  pub fn load() -> Result<Self, NetworkTreeError> {
+    let path =
+      NetworkJson::path().map_err(|_| NetworkTreeError::ConfigLoad)?;
+    if !path.exists() {
+      return Ok(Self::default());
+    }
+    let raw = fs::read_to_string(&path)
+      .map_err(|e| NetworkTreeError::Io(e.to_string()))?;
+    Self::from_json(&raw)
+  }
+
+  /// Parses the contents of `network.json`.
+  pub fn from_json(raw: &str) -> Result<Self, NetworkTreeError> {
+    serde_json::from_str::<Level>(raw)
+      .map(|level| Self { nodes: level.0 })
+      .map_err(|e| NetworkTreeError::Parse(e.to_string()))
+  }
+
+  /// The tree as `network.json` contents
+  pub fn to_json(&self) -> Result<String, NetworkTreeError> {
+    serde_json::to_string_pretty(&FileLevel(&self.nodes))
+      .map_err(|e| NetworkTreeError::Parse(e.to_string()))
+  }
+
+  /// Writes the tree to `network.json`, first copying the current file
+  /// to `network.json.backup`. The new file is written alongside and
+  /// then renamed into place, so `network.json` is never half-written.
+  pub fn save(&self) -> Result<(), NetworkTreeError> {
+    let path =
+      NetworkJson::path().map_err(|_| NetworkTreeError::ConfigLoad)?;
+    self.save_to(&path)
+  }
+
+  fn save_to(&self, path: &Path) -> Result<(), NetworkTreeError> {
+    let io = |e: std::io::Error| NetworkTreeError::Io(e.to_string());
+    let json = self.to_json()?;
+    if path.exists() {
+      fs::copy(path, path.with_extension("json.backup")).map_err(io)?;
+    }
+    let temporary = path.with_extension("json.new");
+    fs::write(&temporary, json + "\n").map_err(io)?;
+    fs::rename(&temporary, path).map_err(io)
+  }
+
+  /// Finds a node by name. Circuits look nodes up by name, so the
+  /// first match (depth first) is the one they attach to.
+  pub fn find(&self, name: &str) -> Option<&NetworkTreeNode> {
+    fn search<'a>(
+      nodes: &'a [NetworkTreeNode],
+      name: &str,
+    ) -> Option<&'a NetworkTreeNode> {
+      nodes.iter().find_map(|n| {
+        if n.name == name {
+          Some(n)
+        } else {
+          search(&n.children, name)
+        }
+      })
+    }
+    search(&self.nodes, name)
+  }
+
+  fn find_mut(&mut self, name: &str) -> Option<&mut NetworkTreeNode> {
+    fn search<'a>(
+      nodes: &'a mut [NetworkTreeNode],
+      name: &str,
+    ) -> Option<&'a mut NetworkTreeNode> {
+      nodes.iter_mut().find_map(|n| {
+        if n.name == name {
+          Some(n)
+        } else {
+          search(&mut n.children, name)
+        }
+      })
+    }
+    search(&mut self.nodes, name)
+  }
+
+  /// The children of `parent`, or the top level for `None`
+  fn level_mut(
+    &mut self,
+    parent: Option<&str>,
+  ) -> Result<&mut Vec<NetworkTreeNode>, NetworkTreeError> {
+    match parent {
+      None => Ok(&mut self.nodes),
+      Some(parent) => self
+        .find_mut(parent)
+        .map(|n| &mut n.children)
+        .ok_or_else(|| NetworkTreeError::NotFound(parent.to_string())),
+    }
+  }
+
+  fn check_name(&self, name: &str) -> Result<(), NetworkTreeError> {
+    if name.trim().is_empty() {
+      return Err(NetworkTreeError::EmptyName);
+    }
+    if self.find(name).is_some() {
+      return Err(NetworkTreeError::NameInUse(name.to_string()));
+    }
+    Ok(())
+  }
+
+  /// Adds a node below `parent`, or at the top level for `None`.
+  pub fn add_node(
+    &mut self,
+    parent: Option<&str>,
+    settings: NodeSettings,
+  ) -> Result<(), NetworkTreeError> {
+    self.check_name(&settings.name)?;
+    let mut node = NetworkTreeNode::default();
+    node.apply(settings);
+    self.level_mut(parent)?.push(node);
+    Ok(())
+  }
+
+  /// Changes a node's name, capacity or type.
+  pub fn update_node(
+    &mut self,
+    name: &str,
+    settings: NodeSettings,
+  ) -> Result<(), NetworkTreeError> {
+    if settings.name != name {
+      self.check_name(&settings.name)?;
+    }
+    let node = self
+      .find_mut(name)
+      .ok_or_else(|| NetworkTreeError::NotFound(name.to_string()))?;
+    node.apply(settings);
+    Ok(())
+  }
+
+  /// Moves a node, with everything below it, to the end of another
+  /// node's children (or the top level, for `None`).
+  pub fn move_node(
+    &mut self,
+    name: &str,
+    parent: Option<&str>,
+  ) -> Result<(), NetworkTreeError> {
+    let node = self
+      .find(name)
+      .ok_or_else(|| NetworkTreeError::NotFound(name.to_string()))?;
+    if let Some(parent) = parent {
+      if parent == name || node.descendant_names().contains(parent) {
+        return Err(NetworkTreeError::InvalidMove(name.to_string()));
+      }
+    }
+    self.level_mut(parent)?;
+    let node = self.remove_node(name)?;
+    self.level_mut(parent)?.push(node);
+    Ok(())
+  }
+
+  /// Removes a node, with everything below it.
+  pub fn remove_node(
+    &mut self,
+    name: &str,
+  ) -> Result<NetworkTreeNode, NetworkTreeError> {
+    fn remove(
+      nodes: &mut Vec<NetworkTreeNode>,
+      name: &str,
+    ) -> Option<NetworkTreeNode> {
+      match nodes.iter().position(|n| n.name == name) {
+        Some(idx) => Some(nodes.remove(idx)),
+        None => nodes.iter_mut().find_map(|n| remove(&mut n.children, name)),
+      }
+    }
+    remove(&mut self.nodes, name)
+      .ok_or_else(|| NetworkTreeError::NotFound(name.to_string()))
+  }
+
+  /// The circuits whose parent node isn't in the tree, in the order
+  /// they first appear.
+  pub fn orphaned_circuits(&self, devices: &[ShapedDevice]) -> Vec<String> {
+    let mut names = HashSet::new();
+    for node in self.nodes.iter() {
+      names.insert(node.name.as_str());
+      names.extend(node.descendant_names());
+    }
+    let mut orphans: Vec<String> = Vec::new();
+    for device in devices.iter() {
+      if !device.parent_node.is_empty()
+        && !names.contains(device.parent_node.as_str())
+        && !orphans.contains(&device.circuit_id)
+      {
+        orphans.push(device.circuit_id.clone());
+      }
+    }
+    orphans
+  }
+}
+
+impl NetworkTreeNode {
+  fn apply(&mut self, settings: NodeSettings) {
+    self.name = settings.name;
+    self.download_mbps = settings.download_mbps;
+    self.upload_mbps = settings.upload_mbps;
+    self.node_type = settings.node_type.filter(|t| !t.is_empty());
+  }
+
+  fn descendant_names(&self) -> HashSet<&str> {
+    let mut names = HashSet::new();
+    for child in self.children.iter() {
+      names.insert(child.name.as_str());
+      names.extend(child.descendant_names());
+    }
+    names
+  }
+}
+
+/// One level of the tree: a JSON object of nodes, by name
+struct Level(Vec<NetworkTreeNode>);
+
+impl<'de> Deserialize<'de> for Level {
+  fn deserialize<D: Deserializer<'de>>(
+    deserializer: D,
+  ) -> Result<Self, D::Error> {
+    struct LevelVisitor;
+
+    impl<'de> Visitor<'de> for LevelVisitor {
+      type Value = Level;
+
+      fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an object of network nodes")
+      }
+
+      fn visit_map<A: MapAccess<'de>>(
+        self,
+        mut map: A,
+      ) -> Result<Level, A::Error> {
+        let mut nodes = Vec::new();
+        while let Some((name, node)) = map.next_entry::<String, RawNode>()? {
+          nodes.push(NetworkTreeNode { name, ..node.0 });
+        }
+        Ok(Level(nodes))
+      }
+    }
+
+    deserializer.deserialize_map(LevelVisitor)
+  }
+}
+
+/// A node's fields, without its name
+struct RawNode(NetworkTreeNode);
+
+impl<'de> Deserialize<'de> for RawNode {
+  fn deserialize<D: Deserializer<'de>>(
+    deserializer: D,
+  ) -> Result<Self, D::Error> {
+    struct NodeVisitor;
+
+    impl<'de> Visitor<'de> for NodeVisitor {
+      type Value = RawNode;
+
+      fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a network node")
+      }
+
+      fn visit_map<A: MapAccess<'de>>(
+        self,
+        mut map: A,
+      ) -> Result<RawNode, A::Error> {
+        let mut node = NetworkTreeNode::default();
+        while let Some(key) = map.next_key::<String>()? {
+          match key.as_str() {
+            "downloadBandwidthMbps" => {
+              node.download_mbps = mbps(map.next_value()?)?;
+            }
+            "uploadBandwidthMbps" => {
+              node.upload_mbps = mbps(map.next_value()?)?;
+            }
+            "children" => node.children = map.next_value::<Level>()?.0,
+            _ => match map.next_value::<Value>()? {
+              Value::String(t) if key == "type" => node.node_type = Some(t),
+              value => node.extra.push((key, value)),
+            },
+          }
+        }
+        Ok(RawNode(node))
+      }
+    }
+
+    deserializer.deserialize_map(NodeVisitor)
+  }
+}
+
+/// Reads a rate, which integrations sometimes write as a string or a
+/// float.
+fn mbps<E: de::Error>(value: Value) -> Result<u32, E> {
+  let mbps = match &value {
+    Value::Number(n) => n
+      .as_u64()
+      .map(|n| n.min(u32::MAX as u64) as u32)
+      .or_else(|| n.as_f64().filter(|n| *n >= 0.0).map(|n| n as u32)),
+    Value::String(s) => s.trim().parse().ok(),
+    _ => None,
+  };
+  mbps.ok_or_else(|| E::custom(format!("invalid bandwidth {value}")))
+}
+
+/// Writes one level of the tree in order
+struct FileLevel<'a>(&'a [NetworkTreeNode]);
+
+impl Serialize for FileLevel<'_> {
+  fn serialize<S: Serializer>(
+    &self,
+    serializer: S,
+  ) -> Result<S::Ok, S::Error> {
+    let mut map = serializer.serialize_map(Some(self.0.len()))?;
+    for node in self.0.iter() {
+      map.serialize_entry(&node.name, &FileNode(node))?;
+    }
+    map.end()
+  }
+}
+
+struct FileNode<'a>(&'a NetworkTreeNode);
+
+impl Serialize for FileNode<'_> {
+  fn serialize<S: Serializer>(
+    &self,
+    serializer: S,
+  ) -> Result<S::Ok, S::Error> {
+    let node = self.0;
+    let mut map = serializer.serialize_map(None)?;
+    map.serialize_entry("downloadBandwidthMbps", &node.download_mbps)?;
+    map.serialize_entry("uploadBandwidthMbps", &node.upload_mbps)?;
+    if let Some(node_type) = &node.node_type {
+      map.serialize_entry("type", node_type)?;
+    }
+    for (key, value) in node.extra.iter() {
+      map.serialize_entry(key, value)?;
+    }
+    if !node.children.is_empty() {
+      map.serialize_entry("children", &FileLevel(&node.children))?;
+    }
+    map.end()
+  }
+}
+
+/// Errors editing `network.json`
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum NetworkTreeError {
+  /// `/etc/lqos.conf` couldn't be loaded
+  #[error("Unable to load /etc/lqos.conf")]
+  ConfigLoad,
+  /// `network.json` couldn't be read or written
+  #[error("Unable to read or write network.json: {0}")]
+  Io(String),
+  /// `network.json` isn't a valid tree
+  #[error("Unable to parse network.json: {0}")]
+  Parse(String),
+  /// There is no node with the name
+  #[error("There is no node named {0}")]
+  NotFound(String),
+  /// Another node already has the name
+  #[error("There is already a node named {0}")]
+  NameInUse(String),
+  /// A node needs a name
+  #[error("A node needs a name")]
+  EmptyName,
+  /// A node can't be moved below itself
+  #[error("{0} can't be moved below itself")]
+  InvalidMove(String),
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  const NETWORK: &str = r#"{
+    "Zulu": {
+      "downloadBandwidthMbps": 1000, "uploadBandwidthMbps": "500",
+      "type": "site", "id": "z", "children": {
+        "Bravo": { "downloadBandwidthMbps": 100.0, "uploadBandwidthMbps": 50 },
+        "Alpha": { "downloadBandwidthMbps": 100, "uploadBandwidthMbps": 50 }
+      }
+    },
+    "Alpha Site": { "downloadBandwidthMbps": 200, "uploadBandwidthMbps": 200 }
+  }"#;
+
+  fn settings(name: &str, mbps: u32) -> NodeSettings {
+    NodeSettings {
+      name: name.to_string(),
+      download_mbps: mbps,
+      upload_mbps: mbps,
+      node_type: Some("ap".to_string()),
+    }
+  }
+
+  #[test]
+  fn round_trip_keeps_order_and_other_fields() {
+    let tree = NetworkTree::from_json(NETWORK).unwrap();
+    let names: Vec<&str> =
+      tree.nodes.iter().map(|n| n.name.as_str()).collect();
+    assert_eq!(names, vec!["Zulu", "Alpha Site"]);
+    assert_eq!(tree.nodes[0].upload_mbps, 500);
+    assert_eq!(tree.nodes[0].children[0].name, "Bravo");
+    assert_eq!(tree.nodes[0].extra, vec![("id".to_string(), "z".into())]);
+
+    let written = tree.to_json().unwrap();
+    let at = |text: &str| written.find(text).unwrap();
+    assert!(at("Zulu") < at("Alpha Site"));
+    assert!(at("Bravo") < at("\"Alpha\""));
+    assert_eq!(NetworkTree::from_json(&written).unwrap(), tree);
+
+    let dir = std::env::temp_dir()
+      .join(format!("lqos-network-tree-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("network.json");
+    fs::write(&path, NETWORK).unwrap();
+    tree.save_to(&path).unwrap();
+    let backup = fs::read_to_string(path.with_extension("json.backup"));
+    assert_eq!(backup.unwrap(), NETWORK);
+    let saved = fs::read_to_string(&path).unwrap();
+    assert_eq!(NetworkTree::from_json(&saved).unwrap(), tree);
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn edits() {
+    let mut tree = NetworkTree::from_json(NETWORK).unwrap();
+    tree.add_node(Some("Alpha Site"), settings("AP1", 100)).unwrap();
+    assert_eq!(
+      tree.add_node(None, settings("AP1", 100)),
+      Err(NetworkTreeError::NameInUse("AP1".to_string()))
+    );
+    assert_eq!(
+      tree.move_node("Zulu", Some("Bravo")),
+      Err(NetworkTreeError::InvalidMove("Zulu".to_string()))
+    );
+    tree.move_node("Bravo", Some("AP1")).unwrap();
+    assert_eq!(tree.find("AP1").unwrap().children[0].name, "Bravo");
+    tree.update_node("Alpha", settings("Charlie", 10)).unwrap();
+    let charlie = tree.find("Charlie").unwrap();
+    assert_eq!(charlie.node_type.as_deref(), Some("ap"));
+
+    let device = |circuit: &str, parent: &str| ShapedDevice {
+      circuit_id: circuit.to_string(),
+      parent_node: parent.to_string(),
+      ..Default::default()
+    };
+    let devices =
+      [device("1", "Bravo"), device("2", "Alpha"), device("3", "")];
+    tree.remove_node("AP1").unwrap();
+    assert!(tree.find("Bravo").is_none());
+    assert_eq!(tree.orphaned_circuits(&devices), vec!["1", "2"]);
+  }
+}
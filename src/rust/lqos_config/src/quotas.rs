@@ -0,0 +1,421 @@
+//! Per-circuit data quotas.
+//!
+//! `Quotas.toml` (in `lqos_directory`) defines named quotas and assigns
+//! circuits to them:
+//!
+//! ```toml
+//! [[quota]]
+//! name = "capped_100gb"
+//! limit_gb = 100
+//! period = "monthly"
+//! reset_day = 1
+//! fair_use_download_mbps = 2
+//! fair_use_upload_mbps = 1
+//!
+//! [circuits]
+//! "12345" = "capped_100gb"
+//! ```
+//!
+//! A quota counts download and upload together, over either a calendar
+//! month (starting on `reset_day`) or the last `rolling_days` days.
+//! Usage is kept per local day in `quota_usage.json`, so that it
+//! survives restarts and rolling periods can drop old days.
+use crate::etc;
+use chrono::{Datelike, Days, Local, Months, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::PathBuf};
+use thiserror::Error;
+
+/// The file quotas are read from, inside `lqos_directory`
+const QUOTAS_FILE: &str = "Quotas.toml";
+
+/// The file usage is stored in, inside `lqos_directory`
+const USAGE_FILE: &str = "quota_usage.json";
+
+/// Daily usage older than this is never needed
+const MAX_HISTORY_DAYS: u64 = 366;
+
+const BYTES_PER_GB: u64 = 1_000_000_000;
+
+/// How a quota's period is measured.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum QuotaPeriod {
+  /// A calendar month, starting on `reset_day`
+  #[default]
+  Monthly,
+  /// The last `rolling_days` days, including today
+  Rolling,
+}
+
+fn default_reset_day() -> u32 {
+  1
+}
+
+fn default_rolling_days() -> u32 {
+  30
+}
+
+/// A named quota.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct QuotaPlan {
+  /// The quota's name, referenced from `[circuits]`
+  pub name: String,
+
+  /// How much data (download and upload together) a circuit may use
+  /// each period, in gigabytes
+  pub limit_gb: u64,
+
+  /// How the period is measured
+  #[serde(default)]
+  pub period: QuotaPeriod,
+
+  /// For monthly quotas, the day of the month (1-28) usage resets on
+  #[serde(default = "default_reset_day")]
+  pub reset_day: u32,
+
+  /// For rolling quotas, how many days usage is counted over
+  #[serde(default = "default_rolling_days")]
+  pub rolling_days: u32,
+
+  /// The download ceiling applied once the quota is used up
+  pub fair_use_download_mbps: u64,
+
+  /// The upload ceiling applied once the quota is used up
+  pub fair_use_upload_mbps: u64,
+}
+
+impl QuotaPlan {
+  /// The quota, in bytes
+  pub fn limit_bytes(&self) -> u64 {
+    self.limit_gb.saturating_mul(BYTES_PER_GB)
+  }
+
+  /// The first day of the period containing `today`.
+  pub fn period_start(&self, today: NaiveDate) -> NaiveDate {
+    match self.period {
+      QuotaPeriod::Monthly => {
+        let this_month = today.with_day(self.reset_day).unwrap_or(today);
+        if this_month <= today {
+          this_month
+        } else {
+          this_month - Months::new(1)
+        }
+      }
+      QuotaPeriod::Rolling => {
+        today - Days::new(u64::from(self.rolling_days.max(1)) - 1)
+      }
+    }
+  }
+
+  /// The day usage next resets. Rolling quotas never reset; old days
+  /// drop out one at a time instead.
+  pub fn next_reset(&self, today: NaiveDate) -> Option<NaiveDate> {
+    match self.period {
+      QuotaPeriod::Monthly => {
+        Some(self.period_start(today) + Months::new(1))
+      }
+      QuotaPeriod::Rolling => None,
+    }
+  }
+}
+
+/// The contents of `Quotas.toml`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Default)]
+pub struct QuotaPlans {
+  /// The defined quotas
+  #[serde(default, rename = "quota")]
+  pub plans: Vec<QuotaPlan>,
+
+  /// Circuit ID to quota name
+  #[serde(default)]
+  pub circuits: BTreeMap<String, String>,
+}
+
+impl QuotaPlans {
+  /// The path to `Quotas.toml`
+  pub fn path() -> Result<PathBuf, QuotaError> {
+    let cfg = etc::EtcLqos::load().map_err(|_| QuotaError::ConfigLoad)?;
+    Ok(PathBuf::from(&cfg.lqos_directory).join(QUOTAS_FILE))
+  }
+
+  /// Loads and checks `Quotas.toml`. A missing file is the same as an
+  /// empty one: no circuit has a quota.
+  pub fn load() -> Result<Self, QuotaError> {
+    let path = Self::path()?;
+    if !path.exists() {
+      return Ok(Self::default());
+    }
+    let raw = std::fs::read_to_string(&path)
+      .map_err(|e| QuotaError::Io(e.to_string()))?;
+    Self::parse(&raw)
+  }
+
+  /// Parses and checks the contents of a `Quotas.toml` file.
+  pub fn parse(raw: &str) -> Result<Self, QuotaError> {
+    let plans: Self = toml_edit::de::from_str(raw)
+      .map_err(|e| QuotaError::Parse(e.to_string()))?;
+    plans.check()?;
+    Ok(plans)
+  }
+
+  fn check(&self) -> Result<(), QuotaError> {
+    for (i, plan) in self.plans.iter().enumerate() {
+      let invalid = |message: &str| {
+        QuotaError::Invalid(format!("{}: {message}", plan.name))
+      };
+      if plan.name.is_empty() {
+        return Err(QuotaError::Invalid("A quota has no name".to_string()));
+      }
+      if self.plans[..i].iter().any(|p| p.name == plan.name) {
+        return Err(invalid("defined more than once"));
+      }
+      if plan.limit_gb == 0 {
+        return Err(invalid("limit_gb must be above zero"));
+      }
+      if !(1..=28).contains(&plan.reset_day) {
+        return Err(invalid("reset_day must be between 1 and 28"));
+      }
+      if plan.rolling_days == 0
+        || u64::from(plan.rolling_days) > MAX_HISTORY_DAYS
+      {
+        return Err(invalid("rolling_days must be between 1 and 366"));
+      }
+      if plan.fair_use_download_mbps == 0 || plan.fair_use_upload_mbps == 0 {
+        return Err(invalid("fair use rates must be above zero"));
+      }
+    }
+    for (circuit, plan) in self.circuits.iter() {
+      if !self.plans.iter().any(|p| &p.name == plan) {
+        return Err(QuotaError::Invalid(format!(
+          "Circuit {circuit} uses unknown quota {plan}"
+        )));
+      }
+    }
+    Ok(())
+  }
+
+  /// The quota assigned to a circuit, if any.
+  pub fn plan_for_circuit(&self, circuit_id: &str) -> Option<&QuotaPlan> {
+    let name = self.circuits.get(circuit_id)?;
+    self.plans.iter().find(|p| &p.name == name)
+  }
+}
+
+/// Extra data granted to a circuit for one period.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct QuotaTopUp {
+  /// The day the top-up was granted
+  pub date: NaiveDate,
+  /// How much was granted, in bytes
+  pub bytes: u64,
+}
+
+/// One circuit's recorded usage.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Default)]
+pub struct CircuitUsage {
+  /// Bytes used each day (download, upload)
+  #[serde(default)]
+  pub days: BTreeMap<NaiveDate, (u64, u64)>,
+
+  /// Top-ups granted
+  #[serde(default)]
+  pub top_ups: Vec<QuotaTopUp>,
+}
+
+impl CircuitUsage {
+  /// Bytes used (download, upload) since `start`.
+  pub fn used_since(&self, start: NaiveDate) -> (u64, u64) {
+    self.days.range(start..).fold((0, 0), |acc, (_, bytes)| {
+      (acc.0 + bytes.0, acc.1 + bytes.1)
+    })
+  }
+
+  /// Bytes granted by top-ups since `start`.
+  pub fn top_ups_since(&self, start: NaiveDate) -> u64 {
+    self.top_ups.iter().filter(|t| t.date >= start).map(|t| t.bytes).sum()
+  }
+}
+
+/// Usage of every circuit with a quota, stored in `quota_usage.json`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Default)]
+pub struct QuotaUsage {
+  /// Usage by circuit ID
+  pub circuits: BTreeMap<String, CircuitUsage>,
+}
+
+impl QuotaUsage {
+  fn path() -> Result<PathBuf, QuotaError> {
+    let cfg = etc::EtcLqos::load().map_err(|_| QuotaError::ConfigLoad)?;
+    Ok(PathBuf::from(&cfg.lqos_directory).join(USAGE_FILE))
+  }
+
+  /// Loads the saved usage, or starts afresh if there is none.
+  pub fn load() -> Result<Self, QuotaError> {
+    let path = Self::path()?;
+    if !path.exists() {
+      return Ok(Self::default());
+    }
+    let raw = std::fs::read_to_string(&path)
+      .map_err(|e| QuotaError::Io(e.to_string()))?;
+    serde_json::from_str(&raw).map_err(|e| QuotaError::Parse(e.to_string()))
+  }
+
+  /// Saves the usage, replacing the file atomically.
+  pub fn save(&self) -> Result<(), QuotaError> {
+    let path = Self::path()?;
+    let json = serde_json::to_string(self)
+      .map_err(|e| QuotaError::Parse(e.to_string()))?;
+    let temp = path.with_extension("json.new");
+    std::fs::write(&temp, json)
+      .and_then(|_| std::fs::rename(&temp, &path))
+      .map_err(|e| QuotaError::Io(e.to_string()))
+  }
+
+  /// Adds bytes (download, upload) used by a circuit on `date`.
+  pub fn add(
+    &mut self,
+    circuit_id: &str,
+    date: NaiveDate,
+    bytes: (u64, u64),
+  ) {
+    if bytes == (0, 0) {
+      return;
+    }
+    if !self.circuits.contains_key(circuit_id) {
+      self.circuits.insert(circuit_id.to_string(), CircuitUsage::default());
+    }
+    let usage = self.circuits.get_mut(circuit_id).unwrap();
+    let day = usage.days.entry(date).or_default();
+    day.0 += bytes.0;
+    day.1 += bytes.1;
+  }
+
+  /// A circuit's usage, if it has used anything.
+  pub fn circuit(&self, circuit_id: &str) -> Option<&CircuitUsage> {
+    self.circuits.get(circuit_id)
+  }
+
+  /// Grants a circuit extra data for its current period.
+  pub fn grant_top_up(
+    &mut self,
+    circuit_id: &str,
+    date: NaiveDate,
+    bytes: u64,
+  ) {
+    self
+      .circuits
+      .entry(circuit_id.to_string())
+      .or_default()
+      .top_ups
+      .push(QuotaTopUp { date, bytes });
+  }
+
+  /// Forgets a circuit's usage and top-ups.
+  pub fn reset(&mut self, circuit_id: &str) {
+    self.circuits.remove(circuit_id);
+  }
+
+  /// Drops circuits that no longer have a quota, and days too old to
+  /// count towards any period.
+  pub fn prune(&mut self, plans: &QuotaPlans, today: NaiveDate) {
+    self.circuits.retain(|id, _| plans.plan_for_circuit(id).is_some());
+    for (id, usage) in self.circuits.iter_mut() {
+      let Some(plan) = plans.plan_for_circuit(id) else { continue };
+      let start = plan.period_start(today);
+      usage.days.retain(|day, _| *day >= start);
+      usage.top_ups.retain(|t| t.date >= start);
+    }
+  }
+}
+
+/// Today's date, in local time.
+pub fn quota_today() -> NaiveDate {
+  Local::now().date_naive()
+}
+
+/// Errors reading quotas or their usage
+#[derive(Error, Debug)]
+pub enum QuotaError {
+  /// Unable to load `/etc/lqos.conf`
+  #[error("Unable to load /etc/lqos.conf")]
+  ConfigLoad,
+  /// Unable to read or write a file
+  #[error("Unable to access quota file: {0}")]
+  Io(String),
+  /// A file didn't parse
+  #[error("Unable to parse quota file: {0}")]
+  Parse(String),
+  /// `Quotas.toml` parsed, but doesn't make sense
+  #[error("Invalid quota: {0}")]
+  Invalid(String),
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  const EXAMPLE: &str = r#"
+[[quota]]
+name = "monthly"
+limit_gb = 100
+reset_day = 15
+fair_use_download_mbps = 2
+fair_use_upload_mbps = 1
+
+[[quota]]
+name = "weekly"
+limit_gb = 10
+period = "rolling"
+rolling_days = 7
+fair_use_download_mbps = 2
+fair_use_upload_mbps = 1
+
+[circuits]
+"c1" = "monthly"
+"c2" = "weekly"
+"#;
+
+  fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(y, m, d).unwrap()
+  }
+
+  #[test]
+  fn periods() {
+    let plans = QuotaPlans::parse(EXAMPLE).unwrap();
+    let monthly = plans.plan_for_circuit("c1").unwrap();
+    assert_eq!(monthly.period_start(date(2024, 3, 20)), date(2024, 3, 15));
+    assert_eq!(monthly.period_start(date(2024, 3, 14)), date(2024, 2, 15));
+    assert_eq!(monthly.period_start(date(2024, 1, 1)), date(2023, 12, 15));
+    let next = monthly.next_reset(date(2024, 3, 15));
+    assert_eq!(next, Some(date(2024, 4, 15)));
+    let weekly = plans.plan_for_circuit("c2").unwrap();
+    assert_eq!(weekly.period_start(date(2024, 3, 7)), date(2024, 3, 1));
+    assert_eq!(weekly.next_reset(date(2024, 3, 7)), None);
+    assert!(QuotaPlans::parse(&EXAMPLE.replace("= 15", "= 31")).is_err());
+  }
+
+  #[test]
+  fn usage_and_top_ups() {
+    let plans = QuotaPlans::parse(EXAMPLE).unwrap();
+    let weekly = plans.plan_for_circuit("c2").unwrap();
+    let mut usage = QuotaUsage::default();
+    usage.add("c2", date(2024, 3, 1), (5, 1));
+    usage.add("c2", date(2024, 3, 7), (2, 2));
+    usage.add("c2", date(2024, 3, 7), (1, 0));
+    usage.add("c3", date(2024, 3, 7), (1, 0));
+    usage.grant_top_up("c2", date(2024, 3, 2), 50);
+    let c2 = usage.circuit("c2").unwrap();
+    assert_eq!(c2.used_since(weekly.period_start(date(2024, 3, 7))), (8, 3));
+    assert_eq!(c2.used_since(weekly.period_start(date(2024, 3, 8))), (3, 2));
+    assert_eq!(c2.top_ups_since(date(2024, 3, 2)), 50);
+
+    usage.prune(&plans, date(2024, 3, 8));
+    assert!(usage.circuit("c3").is_none());
+    assert_eq!(usage.circuit("c2").unwrap().days.len(), 1);
+    let json = serde_json::to_string(&usage).unwrap();
+    assert_eq!(serde_json::from_str::<QuotaUsage>(&json).unwrap(), usage);
+    usage.reset("c2");
+    assert!(usage.circuit("c2").is_none());
+  }
+}
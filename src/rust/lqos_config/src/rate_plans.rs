@@ -0,0 +1,379 @@
+//! Time-of-day and day-of-week rate plans.
+//!
+//! `RatePlans.toml` (in `lqos_directory`) defines named plans, each a
+//! list of time windows that either scale a circuit's
+//! `ShapedDevices.csv` rates or replace them, and a table assigning
+//! circuits to plans. For example:
+//!
+//! ```toml
+//! [[plan]]
+//! name = "night_boost"
+//!
+//! [[plan.window]]
+//! name = "overnight"
+//! start = "23:00"
+//! end = "07:00"
+//! download_multiplier = 2.0
+//! upload_multiplier = 2.0
+//!
+//! [[plan.window]]
+//! days = [ "sat", "sun" ]
+//! start = "00:00"
+//! end = "00:00"
+//! download_mbps = 500
+//!
+//! [circuits]
+//! "12345" = "night_boost"
+//! ```
+//!
+//! Windows are evaluated in local time, and the first matching window
+//! wins. A window whose end is before its start runs past midnight,
+//! and one whose start and end are equal lasts all day. Outside every
+//! window, a circuit gets its normal rates.
+use crate::etc;
+use chrono::{Datelike, Local, Timelike, Weekday};
+use serde::{Deserialize, Serialize};
+use std::{cmp::Ordering, collections::BTreeMap, path::PathBuf};
+use thiserror::Error;
+
+/// The file rate plans are read from, inside `lqos_directory`
+const RATE_PLANS_FILE: &str = "RatePlans.toml";
+
+/// A circuit's minimum and maximum rates, in Mbps.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct CircuitRates {
+  /// Guaranteed download rate
+  pub download_min_mbps: u64,
+  /// Guaranteed upload rate
+  pub upload_min_mbps: u64,
+  /// Download ceiling
+  pub download_max_mbps: u64,
+  /// Upload ceiling
+  pub upload_max_mbps: u64,
+}
+
+/// A point in the week, in local time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PlanTime {
+  /// The day of the week
+  pub weekday: Weekday,
+  /// Minutes since midnight
+  pub minute: u32,
+}
+
+impl PlanTime {
+  /// The current local time.
+  pub fn now() -> Self {
+    let now = Local::now();
+    Self { weekday: now.weekday(), minute: now.hour() * 60 + now.minute() }
+  }
+}
+
+/// One window of a rate plan. Absolute rates take precedence over
+/// multipliers; a direction with neither keeps its normal rate.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct RateWindow {
+  /// An optional label, reported with a circuit's effective rate
+  #[serde(default)]
+  pub name: String,
+
+  /// The days the window starts on ("mon" ... "sun"). Empty means
+  /// every day.
+  #[serde(default)]
+  pub days: Vec<String>,
+
+  /// Start time, "HH:MM"
+  pub start: String,
+
+  /// End time, "HH:MM"
+  pub end: String,
+
+  /// Multiplies the download rates
+  #[serde(default)]
+  pub download_multiplier: Option<f64>,
+
+  /// Multiplies the upload rates
+  #[serde(default)]
+  pub upload_multiplier: Option<f64>,
+
+  /// Replaces the maximum download rate
+  #[serde(default)]
+  pub download_mbps: Option<u64>,
+
+  /// Replaces the maximum upload rate
+  #[serde(default)]
+  pub upload_mbps: Option<u64>,
+}
+
+impl RateWindow {
+  /// Is the window in effect at `time`?
+  pub fn is_active(&self, time: PlanTime) -> bool {
+    let (Ok(start), Ok(end)) = (parse_time(&self.start), parse_time(&self.end))
+    else {
+      return false;
+    };
+    let starts_on = |day: Weekday| {
+      self.days.is_empty()
+        || self.days.iter().any(|d| parse_day(d) == Ok(day))
+    };
+    match start.cmp(&end) {
+      Ordering::Equal => starts_on(time.weekday),
+      Ordering::Less => {
+        starts_on(time.weekday) && time.minute >= start && time.minute < end
+      }
+      Ordering::Greater => {
+        (starts_on(time.weekday) && time.minute >= start)
+          || (starts_on(time.weekday.pred()) && time.minute < end)
+      }
+    }
+  }
+
+  /// The rates a circuit normally shaped at `base` gets during the
+  /// window. The minimums are scaled too, but never exceed the new
+  /// maximums.
+  pub fn apply(&self, base: CircuitRates) -> CircuitRates {
+    let scale = |rate: u64, multiplier: Option<f64>| match multiplier {
+      Some(m) => (rate as f64 * m).round() as u64,
+      None => rate,
+    };
+    let download_max = self.download_mbps.unwrap_or_else(|| {
+      scale(base.download_max_mbps, self.download_multiplier)
+    });
+    let upload_max = self
+      .upload_mbps
+      .unwrap_or_else(|| scale(base.upload_max_mbps, self.upload_multiplier));
+    let download_max = download_max.max(1);
+    let upload_max = upload_max.max(1);
+    CircuitRates {
+      download_min_mbps: scale(
+        base.download_min_mbps,
+        self.download_multiplier,
+      )
+      .clamp(1, download_max),
+      upload_min_mbps: scale(base.upload_min_mbps, self.upload_multiplier)
+        .clamp(1, upload_max),
+      download_max_mbps: download_max,
+      upload_max_mbps: upload_max,
+    }
+  }
+}
+
+/// A named rate plan.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct RatePlan {
+  /// The plan's name, referenced from `[circuits]`
+  pub name: String,
+
+  /// The plan's windows, in priority order
+  #[serde(default, rename = "window")]
+  pub windows: Vec<RateWindow>,
+}
+
+impl RatePlan {
+  /// The first window in effect at `time`, if any.
+  pub fn active_window(&self, time: PlanTime) -> Option<&RateWindow> {
+    self.windows.iter().find(|w| w.is_active(time))
+  }
+}
+
+/// The contents of `RatePlans.toml`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct RatePlans {
+  /// The defined plans
+  #[serde(default, rename = "plan")]
+  pub plans: Vec<RatePlan>,
+
+  /// Circuit ID to plan name
+  #[serde(default)]
+  pub circuits: BTreeMap<String, String>,
+}
+
+impl RatePlans {
+  /// The path to `RatePlans.toml`
+  pub fn path() -> Result<PathBuf, RatePlanError> {
+    let cfg = etc::EtcLqos::load().map_err(|_| RatePlanError::ConfigLoad)?;
+    Ok(PathBuf::from(&cfg.lqos_directory).join(RATE_PLANS_FILE))
+  }
+
+  /// Loads and checks `RatePlans.toml`. A missing file is the same as
+  /// an empty one: no circuit has a plan.
+  pub fn load() -> Result<Self, RatePlanError> {
+    let path = Self::path()?;
+    if !path.exists() {
+      return Ok(Self::default());
+    }
+    let raw = std::fs::read_to_string(&path)
+      .map_err(|e| RatePlanError::Io(e.to_string()))?;
+    Self::parse(&raw)
+  }
+
+  /// Parses and checks the contents of a `RatePlans.toml` file.
+  pub fn parse(raw: &str) -> Result<Self, RatePlanError> {
+    let plans: Self = toml_edit::de::from_str(raw)
+      .map_err(|e| RatePlanError::Parse(e.to_string()))?;
+    plans.check()?;
+    Ok(plans)
+  }
+
+  fn check(&self) -> Result<(), RatePlanError> {
+    for (i, plan) in self.plans.iter().enumerate() {
+      if plan.name.is_empty() {
+        return Err(RatePlanError::Invalid("A plan has no name".to_string()));
+      }
+      if self.plans[..i].iter().any(|p| p.name == plan.name) {
+        return Err(RatePlanError::Invalid(format!(
+          "Plan {} is defined more than once",
+          plan.name
+        )));
+      }
+      for window in plan.windows.iter() {
+        let invalid = |message: String| {
+          RatePlanError::Invalid(format!("{}: {message}", plan.name))
+        };
+        parse_time(&window.start).map_err(invalid)?;
+        parse_time(&window.end).map_err(invalid)?;
+        for day in window.days.iter() {
+          parse_day(day).map_err(invalid)?;
+        }
+        for multiplier in
+          [window.download_multiplier, window.upload_multiplier]
+            .into_iter()
+            .flatten()
+        {
+          if !(multiplier > 0.0 && multiplier.is_finite()) {
+            return Err(invalid(format!(
+              "multiplier {multiplier} must be above zero"
+            )));
+          }
+        }
+        if window.download_mbps == Some(0) || window.upload_mbps == Some(0) {
+          return Err(invalid("rates must be above zero".to_string()));
+        }
+      }
+    }
+    for (circuit, plan) in self.circuits.iter() {
+      if !self.plans.iter().any(|p| &p.name == plan) {
+        return Err(RatePlanError::Invalid(format!(
+          "Circuit {circuit} uses unknown plan {plan}"
+        )));
+      }
+    }
+    Ok(())
+  }
+
+  /// The plan assigned to a circuit, if any.
+  pub fn plan_for_circuit(&self, circuit_id: &str) -> Option<&RatePlan> {
+    let name = self.circuits.get(circuit_id)?;
+    self.plans.iter().find(|p| &p.name == name)
+  }
+}
+
+fn parse_time(text: &str) -> Result<u32, String> {
+  let invalid = || format!("{text:?} is not a time (HH:MM)");
+  let (hours, minutes) = text.trim().split_once(':').ok_or_else(invalid)?;
+  let hours: u32 = hours.parse().map_err(|_| invalid())?;
+  let minutes: u32 = minutes.parse().map_err(|_| invalid())?;
+  if hours > 23 || minutes > 59 {
+    return Err(invalid());
+  }
+  Ok(hours * 60 + minutes)
+}
+
+fn parse_day(text: &str) -> Result<Weekday, String> {
+  text
+    .trim()
+    .parse::<Weekday>()
+    .map_err(|_| format!("{text:?} is not a day of the week"))
+}
+
+/// Errors reading `RatePlans.toml`
+#[derive(Error, Debug)]
+pub enum RatePlanError {
+  /// Unable to load `/etc/lqos.conf`
+  #[error("Unable to load /etc/lqos.conf")]
+  ConfigLoad,
+  /// Unable to read the file
+  #[error("Unable to read RatePlans.toml: {0}")]
+  Io(String),
+  /// The file isn't valid TOML, or doesn't match the expected layout
+  #[error("Unable to parse RatePlans.toml: {0}")]
+  Parse(String),
+  /// The file parsed, but doesn't make sense
+  #[error("Invalid rate plan: {0}")]
+  Invalid(String),
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  const EXAMPLE: &str = r#"
+[[plan]]
+name = "night_boost"
+
+[[plan.window]]
+name = "overnight"
+start = "23:00"
+end = "07:00"
+download_multiplier = 2.0
+
+[[plan.window]]
+days = [ "sat", "sun" ]
+start = "00:00"
+end = "00:00"
+download_mbps = 500
+upload_mbps = 50
+
+[circuits]
+"c1" = "night_boost"
+"#;
+
+  fn at(weekday: Weekday, hour: u32, minute: u32) -> PlanTime {
+    PlanTime { weekday, minute: hour * 60 + minute }
+  }
+
+  #[test]
+  fn windows_match_and_apply() {
+    let plans = RatePlans::parse(EXAMPLE).unwrap();
+    let plan = plans.plan_for_circuit("c1").unwrap();
+    assert!(plans.plan_for_circuit("c2").is_none());
+    let base = CircuitRates {
+      download_min_mbps: 20,
+      upload_min_mbps: 5,
+      download_max_mbps: 100,
+      upload_max_mbps: 20,
+    };
+
+    // Tuesday 23:30, and the small hours of Wednesday, are overnight
+    for time in [at(Weekday::Tue, 23, 30), at(Weekday::Wed, 6, 59)] {
+      let window = plan.active_window(time).unwrap();
+      assert_eq!(window.name, "overnight");
+      let rates = window.apply(base);
+      assert_eq!(rates.download_max_mbps, 200);
+      assert_eq!(rates.download_min_mbps, 40);
+      assert_eq!(rates.upload_max_mbps, 20);
+    }
+    assert!(plan.active_window(at(Weekday::Wed, 7, 0)).is_none());
+
+    // Weekends are absolute, and the minimums are clamped
+    let window = plan.active_window(at(Weekday::Sat, 12, 0)).unwrap();
+    let rates = window.apply(base);
+    assert_eq!(rates.download_max_mbps, 500);
+    assert_eq!(rates.upload_max_mbps, 50);
+    assert_eq!(rates.download_min_mbps, 20);
+    let lower = RateWindow { upload_mbps: Some(2), ..window.clone() };
+    assert_eq!(lower.apply(base).upload_min_mbps, 2);
+  }
+
+  #[test]
+  fn bad_plans_are_rejected() {
+    let unknown = EXAMPLE.replace("\"c1\" = \"night_boost\"", "\"c1\" = \"x\"");
+    assert!(RatePlans::parse(&unknown).is_err());
+    let bad_time = EXAMPLE.replace("23:00", "24:00");
+    assert!(RatePlans::parse(&bad_time).is_err());
+    let bad_day = EXAMPLE.replace("\"sat\"", "\"someday\"");
+    assert!(RatePlans::parse(&bad_day).is_err());
+    let bad_multiplier = EXAMPLE.replace("= 2.0", "= -1.0");
+    assert!(RatePlans::parse(&bad_multiplier).is_err());
+  }
+}
@@ -0,0 +1,632 @@
+use super::{ConfigFile, RevisionFiles};
+use crate::{
+  shaped_devices::{CsvLayout, ShapedDevicesError, STANDARD_COLUMNS},
+  BurstSettings, ConfigShapedDevices, NetworkJson,
+};
+use csv::{ReaderBuilder, StringRecord};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+
+/// A single value that differs between two revisions.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FieldChange {
+  /// What changed, e.g. `Download Max Mbps` or `queues.sqm`
+  pub field: String,
+  /// The old value (empty if it didn't exist)
+  pub before: String,
+  /// The new value (empty if it was removed)
+  pub after: String,
+}
+
+/// A circuit that was added or removed.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CircuitSummary {
+  /// Circuit ID
+  pub circuit_id: String,
+  /// Circuit name
+  pub circuit_name: String,
+}
+
+/// A circuit that exists in both revisions, with what changed.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CircuitChange {
+  /// Circuit ID
+  pub circuit_id: String,
+  /// Circuit name (as of the newer revision)
+  pub circuit_name: String,
+  /// Changed fields. Device fields are named `Device <id>: <field>`.
+  pub changes: Vec<FieldChange>,
+}
+
+/// A `network.json` node that was added or removed.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct NodeSummary {
+  /// Stable node id
+  pub id: String,
+  /// Path from the top of the tree, e.g. `Site1/AP2`
+  pub path: String,
+}
+
+/// A `network.json` node that now has a different parent.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct NodeMove {
+  /// Stable node id (as of the newer revision)
+  pub id: String,
+  /// Node name
+  pub name: String,
+  /// Path before the move
+  pub from_path: String,
+  /// Path after the move
+  pub to_path: String,
+}
+
+/// A `network.json` node whose settings changed.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct NodeChange {
+  /// Stable node id
+  pub id: String,
+  /// Path from the top of the tree (as of the newer revision)
+  pub path: String,
+  /// Changed fields
+  pub changes: Vec<FieldChange>,
+}
+
+/// A structured comparison of two configuration revisions.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RevisionDiff {
+  /// The older revision id
+  pub from: String,
+  /// The newer revision id, or `current` for the live files
+  pub to: String,
+  /// Files whose contents differ at all
+  pub files_changed: Vec<ConfigFile>,
+  /// Changed `/etc/lqos.conf` settings, named `section.key`. Values
+  /// that look like secrets are not shown.
+  pub settings_changed: Vec<FieldChange>,
+  /// Circuits only in the newer revision
+  pub circuits_added: Vec<CircuitSummary>,
+  /// Circuits only in the older revision
+  pub circuits_removed: Vec<CircuitSummary>,
+  /// Circuits in both, with changes
+  pub circuits_changed: Vec<CircuitChange>,
+  /// Nodes only in the newer revision
+  pub nodes_added: Vec<NodeSummary>,
+  /// Nodes only in the older revision
+  pub nodes_removed: Vec<NodeSummary>,
+  /// Nodes that moved to a different parent
+  pub nodes_moved: Vec<NodeMove>,
+  /// Nodes with changed settings
+  pub nodes_changed: Vec<NodeChange>,
+}
+
+/// Compares two sets of configuration files.
+pub fn diff_files(
+  before: &RevisionFiles,
+  after: &RevisionFiles,
+) -> RevisionDiff {
+  let mut diff = RevisionDiff {
+    files_changed: ConfigFile::ALL
+      .iter()
+      .filter(|f| before.get(**f) != after.get(**f))
+      .copied()
+      .collect(),
+    ..Default::default()
+  };
+  if diff.files_changed.contains(&ConfigFile::LqosConf) {
+    diff.settings_changed =
+      diff_settings(before.lqos_conf.as_deref(), after.lqos_conf.as_deref());
+  }
+  if diff.files_changed.contains(&ConfigFile::ShapedDevices) {
+    diff_circuits(
+      before.shaped_devices.as_deref(),
+      after.shaped_devices.as_deref(),
+      &mut diff,
+    );
+  }
+  if diff.files_changed.contains(&ConfigFile::NetworkJson) {
+    diff_nodes(
+      before.network_json.as_deref(),
+      after.network_json.as_deref(),
+      &mut diff,
+    );
+  }
+  diff
+}
+
+/// Compares two lists of shaped devices, such as the loaded
+/// `ShapedDevices.csv` and an edited copy. Only the circuit fields of
+/// the result are filled in.
+pub fn diff_shaped_devices(
+  before: &ConfigShapedDevices,
+  after: &ConfigShapedDevices,
+) -> Result<RevisionDiff, ShapedDevicesError> {
+  let files = |devices: &ConfigShapedDevices| {
+    devices.to_csv_string().map(|csv| RevisionFiles {
+      shaped_devices: Some(csv),
+      ..Default::default()
+    })
+  };
+  Ok(diff_files(&files(before)?, &files(after)?))
+}
+
+fn change(field: String, before: &str, after: &str) -> FieldChange {
+  FieldChange { field, before: before.to_string(), after: after.to_string() }
+}
+
+// lqos.conf
+
+fn flatten(prefix: &str, value: &Value, out: &mut BTreeMap<String, String>) {
+  match value {
+    Value::Object(map) => {
+      for (key, value) in map.iter() {
+        let name = if prefix.is_empty() {
+          key.clone()
+        } else {
+          format!("{prefix}.{key}")
+        };
+        flatten(&name, value, out);
+      }
+    }
+    Value::String(s) => {
+      out.insert(prefix.to_string(), s.clone());
+    }
+    _ => {
+      out.insert(prefix.to_string(), value.to_string());
+    }
+  }
+}
+
+fn settings(raw: Option<&str>) -> BTreeMap<String, String> {
+  let mut result = BTreeMap::new();
+  if let Some(Ok(value)) = raw.map(toml_edit::de::from_str::<Value>) {
+    flatten("", &value, &mut result);
+  }
+  result
+}
+
+fn is_secret(field: &str) -> bool {
+  let last = field.rsplit('.').next().unwrap_or(field).to_lowercase();
+  ["token", "password", "secret", "key"].iter().any(|s| last.contains(s))
+}
+
+fn diff_settings(
+  before: Option<&str>,
+  after: Option<&str>,
+) -> Vec<FieldChange> {
+  let before = settings(before);
+  let after = settings(after);
+  let mut changes = Vec::new();
+  let fields =
+    before.keys().chain(after.keys().filter(|k| !before.contains_key(*k)));
+  for field in fields {
+    let old = before.get(field).map(String::as_str).unwrap_or("");
+    let new = after.get(field).map(String::as_str).unwrap_or("");
+    if old != new {
+      if is_secret(field) {
+        changes.push(change(field.clone(), "(hidden)", "(hidden)"));
+      } else {
+        changes.push(change(field.clone(), old, new));
+      }
+    }
+  }
+  changes
+}
+
+// ShapedDevices.csv
+
+/// Standard columns that describe each device. Tag columns do too;
+/// the other columns describe the whole circuit, and are taken from
+/// its first row.
+const DEVICE_COLUMNS: [&str; 5] =
+  ["Device Name", "MAC", "IPv4", "IPv6", "Comment"];
+
+/// A `ShapedDevices.csv` row, named by the file's headings
+struct Row(Vec<Field>);
+
+struct Field {
+  heading: String,
+  value: String,
+  per_device: bool,
+}
+
+impl Row {
+  /// The burst and tag columns are optional, so the heading row says
+  /// which columns are present
+  fn new(layout: &CsvLayout, record: &StringRecord) -> Self {
+    let mut headings = STANDARD_COLUMNS.to_vec();
+    if layout.has_burst(record.len()) {
+      headings.extend(BurstSettings::COLUMNS);
+    }
+    let first_tag = headings.len();
+    headings.extend(layout.tags.iter().map(String::as_str));
+    Self(
+      headings
+        .iter()
+        .enumerate()
+        .map(|(i, heading)| Field {
+          heading: heading.to_string(),
+          value: record.get(i).unwrap_or("").to_string(),
+          per_device: i >= first_tag || DEVICE_COLUMNS.contains(heading),
+        })
+        .collect(),
+    )
+  }
+
+  fn get(&self, heading: &str) -> &str {
+    self
+      .0
+      .iter()
+      .find(|f| f.heading == heading)
+      .map_or("", |f| f.value.as_str())
+  }
+
+  /// The circuit or device fields that differ, including columns only
+  /// one of the rows has
+  fn changes<'a>(
+    &'a self,
+    new: &'a Self,
+    per_device: bool,
+  ) -> Vec<(&'a str, &'a str, &'a str)> {
+    let headings = self.0.iter().chain(
+      new.0.iter().filter(|f| !self.0.iter().any(|o| o.heading == f.heading)),
+    );
+    headings
+      .filter(|f| f.per_device == per_device)
+      .map(|f| (f.heading.as_str(), self.get(&f.heading), new.get(&f.heading)))
+      .filter(|(_, old, new)| old != new)
+      .collect()
+  }
+}
+
+struct Circuit {
+  first_row: Row,
+  devices: BTreeMap<String, Row>,
+}
+
+fn circuits(raw: Option<&str>) -> BTreeMap<String, Circuit> {
+  let mut result: BTreeMap<String, Circuit> = BTreeMap::new();
+  let Some(raw) = raw else { return result };
+  let mut reader = ReaderBuilder::new()
+    .comment(Some(b'#'))
+    .trim(csv::Trim::All)
+    .flexible(true)
+    .from_reader(raw.as_bytes());
+  let layout = reader
+    .headers()
+    .ok()
+    .and_then(|headers| CsvLayout::from_headers(headers).ok())
+    .unwrap_or_default();
+  for record in reader.records().flatten() {
+    let circuit_id = record.get(0).unwrap_or("").to_string();
+    let device_id = record.get(2).unwrap_or("").to_string();
+    let circuit = result.entry(circuit_id).or_insert_with(|| Circuit {
+      first_row: Row::new(&layout, &record),
+      devices: BTreeMap::new(),
+    });
+    circuit.devices.insert(device_id, Row::new(&layout, &record));
+  }
+  result
+}
+
+fn diff_circuits(
+  before: Option<&str>,
+  after: Option<&str>,
+  diff: &mut RevisionDiff,
+) {
+  let before = circuits(before);
+  let after = circuits(after);
+  let summary = |id: &String, c: &Circuit| CircuitSummary {
+    circuit_id: id.clone(),
+    circuit_name: c.first_row.get("Circuit Name").to_string(),
+  };
+  for (id, old) in before.iter() {
+    let Some(new) = after.get(id) else {
+      diff.circuits_removed.push(summary(id, old));
+      continue;
+    };
+    let mut changes = Vec::new();
+    for (heading, before, after) in old.first_row.changes(&new.first_row, false)
+    {
+      if heading != "Circuit ID" && heading != "Device ID" {
+        changes.push(change(heading.to_string(), before, after));
+      }
+    }
+    for (device_id, old_device) in old.devices.iter() {
+      match new.devices.get(device_id) {
+        None => changes.push(change(
+          format!("Device {device_id}"),
+          old_device.get("Device Name"),
+          "",
+        )),
+        Some(new_device) => {
+          for (heading, before, after) in old_device.changes(new_device, true) {
+            changes.push(change(
+              format!("Device {device_id}: {heading}"),
+              before,
+              after,
+            ));
+          }
+        }
+      }
+    }
+    for (device_id, new_device) in new.devices.iter() {
+      if !old.devices.contains_key(device_id) {
+        changes.push(change(
+          format!("Device {device_id}"),
+          "",
+          new_device.get("Device Name"),
+        ));
+      }
+    }
+    if !changes.is_empty() {
+      diff.circuits_changed.push(CircuitChange {
+        circuit_id: id.clone(),
+        circuit_name: new.first_row.get("Circuit Name").to_string(),
+        changes,
+      });
+    }
+  }
+  for (id, new) in after.iter() {
+    if !before.contains_key(id) {
+      diff.circuits_added.push(summary(id, new));
+    }
+  }
+}
+
+// network.json
+
+struct Node {
+  id: String,
+  name: String,
+  path: String,
+  parent_id: String,
+  fields: [(&'static str, String); 3],
+}
+
+fn nodes(raw: Option<&str>) -> Vec<Node> {
+  let Some(Ok(json)) = raw.map(serde_json::from_str::<Value>) else {
+    return Vec::new();
+  };
+  let tree = NetworkJson::from_json(&json);
+  tree
+    .nodes
+    .iter()
+    .skip(1) // The generated root
+    .map(|n| Node {
+      id: n.id.clone(),
+      name: n.name.clone(),
+      path: n
+        .parents
+        .iter()
+        .skip(1)
+        .map(|p| tree.nodes[*p].name.as_str())
+        .collect::<Vec<_>>()
+        .join("/"),
+      parent_id: n
+        .immediate_parent
+        .map(|p| tree.nodes[p].id.clone())
+        .unwrap_or_default(),
+      fields: [
+        ("Download Mbps", n.max_throughput.0.to_string()),
+        ("Upload Mbps", n.max_throughput.1.to_string()),
+        ("Type", n.node_type.clone().unwrap_or_default()),
+      ],
+    })
+    .collect()
+}
+
+fn unique_by_name<'a>(nodes: &[&'a Node], name: &str) -> Option<&'a Node> {
+  let mut found = nodes.iter().filter(|n| n.name == name);
+  match (found.next(), found.next()) {
+    (Some(n), None) => Some(*n),
+    _ => None,
+  }
+}
+
+fn diff_nodes(
+  before: Option<&str>,
+  after: Option<&str>,
+  diff: &mut RevisionDiff,
+) {
+  let before = nodes(before);
+  let after = nodes(after);
+  let after_by_id: HashMap<&str, &Node> =
+    after.iter().map(|n| (n.id.as_str(), n)).collect();
+  let before_ids: HashMap<&str, &Node> =
+    before.iter().map(|n| (n.id.as_str(), n)).collect();
+
+  // Nodes without an explicit id get one generated from their path,
+  // so moving them changes the id. Match those up by name instead,
+  // where the name is unique.
+  let unmatched_before: Vec<&Node> = before
+    .iter()
+    .filter(|n| !after_by_id.contains_key(n.id.as_str()))
+    .collect();
+  let unmatched_after: Vec<&Node> = after
+    .iter()
+    .filter(|n| !before_ids.contains_key(n.id.as_str()))
+    .collect();
+  let mut pairs: Vec<(&Node, &Node)> = Vec::new();
+  for old in before.iter() {
+    if let Some(new) = after_by_id.get(old.id.as_str()) {
+      pairs.push((old, new));
+    } else if let Some(new) = unique_by_name(&unmatched_after, &old.name)
+      .filter(|_| unique_by_name(&unmatched_before, &old.name).is_some())
+    {
+      pairs.push((old, new));
+    } else {
+      diff.nodes_removed.push(NodeSummary {
+        id: old.id.clone(),
+        path: old.path.clone(),
+      });
+    }
+  }
+  for new in after.iter() {
+    if !pairs.iter().any(|(_, n)| n.id == new.id) {
+      diff.nodes_added.push(NodeSummary {
+        id: new.id.clone(),
+        path: new.path.clone(),
+      });
+    }
+  }
+
+  for (old, new) in pairs {
+    let moved = old.id != new.id || old.parent_id != new.parent_id;
+    if moved && old.path != new.path {
+      diff.nodes_moved.push(NodeMove {
+        id: new.id.clone(),
+        name: new.name.clone(),
+        from_path: old.path.clone(),
+        to_path: new.path.clone(),
+      });
+    }
+    let mut changes = Vec::new();
+    if old.name != new.name {
+      changes.push(change("Name".to_string(), &old.name, &new.name));
+    }
+    for ((field, old_value), (_, new_value)) in
+      old.fields.iter().zip(new.fields.iter())
+    {
+      if old_value != new_value {
+        changes.push(change(field.to_string(), old_value, new_value));
+      }
+    }
+    if !changes.is_empty() {
+      diff.nodes_changed.push(NodeChange {
+        id: new.id.clone(),
+        path: new.path.clone(),
+        changes,
+      });
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  const HEADER: &str = "Circuit ID,Circuit Name,Device ID,Device Name,Parent Node,MAC,IPv4,IPv6,Download Min Mbps,Upload Min Mbps,Download Max Mbps,Upload Max Mbps,Comment\n";
+
+  fn files(csv: &str, network: &str, conf: &str) -> RevisionFiles {
+    RevisionFiles {
+      lqos_conf: Some(conf.to_string()),
+      shaped_devices: Some(format!("{HEADER}{csv}")),
+      network_json: Some(network.to_string()),
+      isp_config: None,
+    }
+  }
+
+  #[test]
+  fn structured_diff() {
+    let before = files(
+      "1,One,1,D1,AP1,,100.64.0.1,,5,5,50,50,\n\
+       2,Two,2,D2,AP1,,100.64.0.2,,5,5,50,50,\n",
+      r#"{ "Site1": { "children": { "AP1": { "downloadBandwidthMbps": 100 } } },
+           "Site2": {} }"#,
+      "[queues]\nsqm = \"cake\"\n[integrations.uisp]\ntoken = \"a\"\n",
+    );
+    let after = files(
+      "1,One,1,D1,AP1,,100.64.0.1,,5,5,100,50,\n\
+       1,One,3,D3,AP1,,100.64.0.3,,5,5,100,50,\n\
+       4,Four,4,D4,AP1,,100.64.0.4,,5,5,50,50,\n",
+      r#"{ "Site1": {},
+           "Site2": { "children": { "AP1": { "downloadBandwidthMbps": 200 } } } }"#,
+      "[queues]\nsqm = \"fq_codel\"\n[integrations.uisp]\ntoken = \"b\"\n",
+    );
+    let diff = diff_files(&before, &after);
+    assert_eq!(
+      diff.files_changed,
+      vec![
+        ConfigFile::LqosConf,
+        ConfigFile::ShapedDevices,
+        ConfigFile::NetworkJson
+      ]
+    );
+    assert_eq!(diff.settings_changed.len(), 2);
+    assert_eq!(diff.settings_changed[0].after, "(hidden)");
+    assert_eq!(
+      diff.settings_changed[1],
+      change("queues.sqm".into(), "cake", "fq_codel")
+    );
+
+    assert_eq!(diff.circuits_removed[0].circuit_id, "2");
+    assert_eq!(diff.circuits_added[0].circuit_id, "4");
+    let fields: Vec<_> = diff.circuits_changed[0]
+      .changes
+      .iter()
+      .map(|c| c.field.as_str())
+      .collect();
+    assert_eq!(fields, vec!["Download Max Mbps", "Device 3"]);
+
+    assert!(diff.nodes_added.is_empty() && diff.nodes_removed.is_empty());
+    assert_eq!(diff.nodes_moved.len(), 1);
+    assert_eq!(diff.nodes_moved[0].from_path, "Site1/AP1");
+    assert_eq!(diff.nodes_moved[0].to_path, "Site2/AP1");
+    assert_eq!(diff.nodes_changed[0].changes[0].after, "200");
+  }
+
+  #[test]
+  fn burst_and_tag_columns_diff() {
+    let header = HEADER.trim_end();
+    let before = RevisionFiles {
+      shaped_devices: Some(format!(
+        "{header}\n1,One,1,D1,AP1,,100.64.0.1,,5,5,50,50,\n"
+      )),
+      ..Default::default()
+    };
+    let after = RevisionFiles {
+      shaped_devices: Some(format!(
+        "{header},Burst Download Mbps,Burst Upload Mbps,Burst Seconds,\
+         Burst Refill Seconds,Plan\n\
+         1,One,1,D1,AP1,,100.64.0.1,,5,5,50,50,,100,100,5,30,Gold\n"
+      )),
+      ..Default::default()
+    };
+    let diff = diff_files(&before, &after);
+    let fields: Vec<_> = diff.circuits_changed[0]
+      .changes
+      .iter()
+      .map(|c| (c.field.as_str(), c.after.as_str()))
+      .collect();
+    assert_eq!(
+      fields,
+      vec![
+        ("Burst Download Mbps", "100"),
+        ("Burst Upload Mbps", "100"),
+        ("Burst Seconds", "5"),
+        ("Burst Refill Seconds", "30"),
+        ("Device 1: Plan", "Gold"),
+      ]
+    );
+  }
+
+  #[test]
+  fn edited_devices_diff() {
+    let device = |circuit: &str, id: &str, max: u32| crate::ShapedDevice {
+      circuit_id: circuit.to_string(),
+      circuit_name: format!("Circuit {circuit}"),
+      device_id: id.to_string(),
+      device_name: format!("Device {id}"),
+      ipv4: vec![("100.64.0.1".parse().unwrap(), 32)],
+      download_max_mbps: max,
+      upload_max_mbps: max,
+      ..Default::default()
+    };
+    let before = ConfigShapedDevices::from_devices(vec![
+      device("1", "1", 50),
+      device("2", "2", 50),
+    ]);
+    let after = ConfigShapedDevices::from_devices(vec![
+      device("1", "1", 100),
+      device("3", "3", 50),
+    ]);
+    let diff = diff_shaped_devices(&before, &after).unwrap();
+    assert_eq!(diff.files_changed, vec![ConfigFile::ShapedDevices]);
+    assert_eq!(diff.circuits_removed[0].circuit_id, "2");
+    assert_eq!(diff.circuits_added[0].circuit_id, "3");
+    assert_eq!(diff.circuits_changed[0].changes.len(), 2);
+    let unchanged = diff_shaped_devices(&after, &after).unwrap();
+    assert!(unchanged.files_changed.is_empty());
+  }
+}
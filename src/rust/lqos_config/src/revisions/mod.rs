@@ -0,0 +1,406 @@
+//! Version history for the LibreQoS configuration files.
+//!
+//! Each revision is a complete snapshot of `/etc/lqos.conf`,
+//! `ShapedDevices.csv`, `network.json` and `ispConfig.py`, stored under
+//! `<lqos_directory>/config_revisions/<id>/` along with a
+//! `revision.json` describing who made the change and why. The files
+//! are small, so whole copies keep diffs and rollbacks simple.
+mod diff;
+use crate::etc;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::{
+  fs,
+  path::PathBuf,
+  time::{SystemTime, UNIX_EPOCH},
+};
+use thiserror::Error;
+pub use diff::{
+  diff_files, diff_shaped_devices, CircuitChange, CircuitSummary,
+  FieldChange, NodeChange, NodeMove, NodeSummary, RevisionDiff,
+};
+
+/// How many revisions to keep before the oldest are removed
+const MAX_REVISIONS: usize = 200;
+
+/// The name of the directory (inside `lqos_directory`) holding revisions
+const REVISIONS_DIR: &str = "config_revisions";
+
+/// The name of the metadata file inside each revision
+const REVISION_INFO: &str = "revision.json";
+
+/// A configuration file tracked by the revision history.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigFile {
+  /// `/etc/lqos.conf`
+  LqosConf,
+  /// `ShapedDevices.csv`
+  ShapedDevices,
+  /// `network.json`
+  NetworkJson,
+  /// `ispConfig.py`
+  IspConfig,
+}
+
+impl ConfigFile {
+  /// Every tracked file
+  pub const ALL: [ConfigFile; 4] = [
+    ConfigFile::LqosConf,
+    ConfigFile::ShapedDevices,
+    ConfigFile::NetworkJson,
+    ConfigFile::IspConfig,
+  ];
+
+  /// The name the file is stored under in a revision
+  pub fn file_name(&self) -> &'static str {
+    match self {
+      ConfigFile::LqosConf => "lqos.conf",
+      ConfigFile::ShapedDevices => "ShapedDevices.csv",
+      ConfigFile::NetworkJson => "network.json",
+      ConfigFile::IspConfig => "ispConfig.py",
+    }
+  }
+}
+
+/// Describes one saved revision of the configuration.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RevisionInfo {
+  /// Revision identifier. Revisions sort by `timestamp`, then `id`.
+  pub id: String,
+
+  /// When the revision was recorded (UNIX time, seconds)
+  pub timestamp: u64,
+
+  /// Who made the change (a web user, or the tool that made it)
+  pub author: String,
+
+  /// Why the change was made
+  pub comment: String,
+
+  /// The files that existed, and were saved, in this revision
+  pub files: Vec<ConfigFile>,
+}
+
+/// The contents of a revision's files, as read from disk.
+#[derive(Default, Clone, Debug)]
+pub struct RevisionFiles {
+  /// `/etc/lqos.conf`
+  pub lqos_conf: Option<String>,
+  /// `ShapedDevices.csv`
+  pub shaped_devices: Option<String>,
+  /// `network.json`
+  pub network_json: Option<String>,
+  /// `ispConfig.py`
+  pub isp_config: Option<String>,
+}
+
+impl RevisionFiles {
+  fn get(&self, file: ConfigFile) -> Option<&String> {
+    match file {
+      ConfigFile::LqosConf => self.lqos_conf.as_ref(),
+      ConfigFile::ShapedDevices => self.shaped_devices.as_ref(),
+      ConfigFile::NetworkJson => self.network_json.as_ref(),
+      ConfigFile::IspConfig => self.isp_config.as_ref(),
+    }
+  }
+
+  fn get_mut(&mut self, file: ConfigFile) -> &mut Option<String> {
+    match file {
+      ConfigFile::LqosConf => &mut self.lqos_conf,
+      ConfigFile::ShapedDevices => &mut self.shaped_devices,
+      ConfigFile::NetworkJson => &mut self.network_json,
+      ConfigFile::IspConfig => &mut self.isp_config,
+    }
+  }
+
+  fn read(path_for: impl Fn(ConfigFile) -> PathBuf) -> Self {
+    let mut files = Self::default();
+    for file in ConfigFile::ALL {
+      *files.get_mut(file) = fs::read_to_string(path_for(file)).ok();
+    }
+    files
+  }
+}
+
+/// Provides access to the revision history kept in a directory.
+pub struct ConfigRevisions {
+  lqos_directory: PathBuf,
+  lqos_conf: PathBuf,
+}
+
+impl ConfigRevisions {
+  /// Opens the revision history for the LibreQoS directory named in
+  /// `/etc/lqos.conf`.
+  pub fn open() -> Result<Self, RevisionError> {
+    let cfg = etc::EtcLqos::load().map_err(|_| RevisionError::ConfigLoad)?;
+    Ok(Self::for_directory(&cfg.lqos_directory))
+  }
+
+  /// Opens the revision history for a specific LibreQoS directory.
+  pub fn for_directory(lqos_directory: &str) -> Self {
+    Self {
+      lqos_directory: PathBuf::from(lqos_directory),
+      lqos_conf: PathBuf::from("/etc/lqos.conf"),
+    }
+  }
+
+  fn live_path(&self, file: ConfigFile) -> PathBuf {
+    match file {
+      ConfigFile::LqosConf => self.lqos_conf.clone(),
+      _ => self.lqos_directory.join(file.file_name()),
+    }
+  }
+
+  fn revisions_dir(&self) -> PathBuf {
+    self.lqos_directory.join(REVISIONS_DIR)
+  }
+
+  fn revision_dir(&self, id: &str) -> Result<PathBuf, RevisionError> {
+    // Ids come from the web UI and the bus, so don't let them escape
+    // the revisions directory.
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '-';
+    if id.is_empty() || !id.chars().all(valid) {
+      return Err(RevisionError::NotFound(id.to_string()));
+    }
+    Ok(self.revisions_dir().join(id))
+  }
+
+  /// The configuration files as they are now.
+  pub fn current_files(&self) -> RevisionFiles {
+    RevisionFiles::read(|f| self.live_path(f))
+  }
+
+  /// Lists all revisions, newest first.
+  pub fn list(&self) -> Result<Vec<RevisionInfo>, RevisionError> {
+    let dir = self.revisions_dir();
+    if !dir.exists() {
+      return Ok(Vec::new());
+    }
+    let mut revisions = Vec::new();
+    for entry in fs::read_dir(&dir).map_err(RevisionError::io)? {
+      let entry = entry.map_err(RevisionError::io)?;
+      let info_path = entry.path().join(REVISION_INFO);
+      let Ok(raw) = fs::read_to_string(&info_path) else { continue };
+      match serde_json::from_str::<RevisionInfo>(&raw) {
+        Ok(info) => revisions.push(info),
+        Err(e) => error!("Unable to read {}: {e:?}", info_path.display()),
+      }
+    }
+    revisions.sort_by(|a, b| {
+      b.timestamp
+        .cmp(&a.timestamp)
+        .then_with(|| natural_id(&b.id).cmp(&natural_id(&a.id)))
+    });
+    Ok(revisions)
+  }
+
+  /// Retrieves the description of a single revision.
+  pub fn info(&self, id: &str) -> Result<RevisionInfo, RevisionError> {
+    let raw = fs::read_to_string(self.revision_dir(id)?.join(REVISION_INFO))
+      .map_err(|_| RevisionError::NotFound(id.to_string()))?;
+    serde_json::from_str(&raw)
+      .map_err(|e| RevisionError::Corrupt(e.to_string()))
+  }
+
+  /// Reads the files saved in a revision.
+  pub fn files(&self, id: &str) -> Result<RevisionFiles, RevisionError> {
+    let info = self.info(id)?;
+    let dir = self.revision_dir(id)?;
+    let mut files = RevisionFiles::read(|f| dir.join(f.file_name()));
+    // Only trust files the revision says it saved
+    for file in ConfigFile::ALL {
+      if !info.files.contains(&file) {
+        *files.get_mut(file) = None;
+      }
+    }
+    Ok(files)
+  }
+
+  /// Saves the current configuration files as a new revision. If
+  /// nothing has changed since the latest revision, no revision is
+  /// added and the latest one is returned instead.
+  pub fn record(
+    &self,
+    author: &str,
+    comment: &str,
+  ) -> Result<RevisionInfo, RevisionError> {
+    let current = self.current_files();
+    if let Some(latest) = self.list()?.into_iter().next() {
+      let saved = self.files(&latest.id)?;
+      if ConfigFile::ALL.iter().all(|f| saved.get(*f) == current.get(*f)) {
+        return Ok(latest);
+      }
+    }
+
+    let timestamp = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map(|d| d.as_secs())
+      .unwrap_or(0);
+    let mut id = timestamp.to_string();
+    let mut suffix = 1;
+    while self.revision_dir(&id)?.exists() {
+      suffix += 1;
+      id = format!("{timestamp}-{suffix}");
+    }
+    let dir = self.revision_dir(&id)?;
+    fs::create_dir_all(&dir).map_err(RevisionError::io)?;
+
+    let mut files = Vec::new();
+    for file in ConfigFile::ALL {
+      if let Some(contents) = current.get(file) {
+        fs::write(dir.join(file.file_name()), contents)
+          .map_err(RevisionError::io)?;
+        files.push(file);
+      }
+    }
+    let info = RevisionInfo {
+      id,
+      timestamp,
+      author: author.to_string(),
+      comment: comment.to_string(),
+      files,
+    };
+    let json = serde_json::to_string_pretty(&info)
+      .map_err(|e| RevisionError::Corrupt(e.to_string()))?;
+    fs::write(dir.join(REVISION_INFO), json).map_err(RevisionError::io)?;
+    info!("Recorded configuration revision {} ({comment})", info.id);
+
+    self.prune()?;
+    Ok(info)
+  }
+
+  fn prune(&self) -> Result<(), RevisionError> {
+    for old in self.list()?.iter().skip(MAX_REVISIONS) {
+      if let Err(e) = fs::remove_dir_all(self.revision_dir(&old.id)?) {
+        error!("Unable to remove old revision {}: {e:?}", old.id);
+      }
+    }
+    Ok(())
+  }
+
+  /// Compares two revisions. `to` may be `None` to compare against
+  /// the files as they are now.
+  pub fn diff(
+    &self,
+    from: &str,
+    to: Option<&str>,
+  ) -> Result<RevisionDiff, RevisionError> {
+    let before = self.files(from)?;
+    let after = match to {
+      Some(to) => self.files(to)?,
+      None => self.current_files(),
+    };
+    let mut diff = diff_files(&before, &after);
+    diff.from = from.to_string();
+    diff.to = to.unwrap_or(CURRENT).to_string();
+    Ok(diff)
+  }
+
+  /// Puts the files from a revision back in place, and records the
+  /// result as a new revision. The current state is recorded first
+  /// (if it isn't already), so a rollback can itself be undone. Files
+  /// that didn't exist in the revision are left alone.
+  ///
+  /// This does not reload LibreQoS; `lqosd` does that after a rollback
+  /// requested over the bus.
+  pub fn rollback(
+    &self,
+    id: &str,
+    author: &str,
+  ) -> Result<RevisionInfo, RevisionError> {
+    let target = self.files(id)?;
+    self.record(author, &format!("Before rolling back to revision {id}"))?;
+    for file in ConfigFile::ALL {
+      if let Some(contents) = target.get(file) {
+        let path = self.live_path(file);
+        fs::write(&path, contents).map_err(|e| {
+          error!("Unable to restore {}: {e:?}", path.display());
+          RevisionError::io(e)
+        })?;
+      }
+    }
+    self.record(author, &format!("Rolled back to revision {id}"))
+  }
+}
+
+/// The name used for the live configuration files in a diff
+pub const CURRENT: &str = "current";
+
+// "1700000000-10" sorts after "1700000000-9"
+fn natural_id(id: &str) -> (u64, u64) {
+  let mut parts = id.splitn(2, '-');
+  let time = parts.next().and_then(|p| p.parse().ok()).unwrap_or(0);
+  let suffix = parts.next().and_then(|p| p.parse().ok()).unwrap_or(1);
+  (time, suffix)
+}
+
+/// Records the current configuration as a new revision, in the
+/// LibreQoS directory named by `/etc/lqos.conf`.
+pub fn record_config_revision(
+  author: &str,
+  comment: &str,
+) -> Result<RevisionInfo, RevisionError> {
+  ConfigRevisions::open()?.record(author, comment)
+}
+
+/// Errors from the configuration revision history
+#[derive(Error, Debug)]
+pub enum RevisionError {
+  /// `/etc/lqos.conf` could not be loaded
+  #[error("Unable to load /etc/lqos.conf")]
+  ConfigLoad,
+  /// Reading or writing a file failed
+  #[error("Unable to read or write a revision: {0}")]
+  Io(String),
+  /// There is no revision with that id
+  #[error("No such revision: {0}")]
+  NotFound(String),
+  /// A revision's description could not be read or written
+  #[error("Revision metadata is invalid: {0}")]
+  Corrupt(String),
+}
+
+impl RevisionError {
+  fn io(e: std::io::Error) -> Self {
+    Self::Io(e.to_string())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir()
+      .join(format!("lqos_revisions_{name}_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  #[test]
+  fn record_skips_unchanged_and_rolls_back() {
+    let dir = scratch_dir("rollback");
+    let mut revisions = ConfigRevisions::for_directory(dir.to_str().unwrap());
+    revisions.lqos_conf = dir.join("lqos.conf");
+    let csv = dir.join("ShapedDevices.csv");
+    fs::write(&csv, "one").unwrap();
+    let first = revisions.record("alice", "First").unwrap();
+    assert!(first.files.contains(&ConfigFile::ShapedDevices));
+    assert!(!first.files.contains(&ConfigFile::NetworkJson));
+    assert_eq!(revisions.record("alice", "Again").unwrap(), first);
+
+    fs::write(&csv, "two").unwrap();
+    let second = revisions.record("bob", "Second").unwrap();
+    assert_ne!(second.id, first.id);
+    assert_eq!(revisions.list().unwrap()[0], second);
+
+    revisions.rollback(&first.id, "carol").unwrap();
+    assert_eq!(fs::read_to_string(&csv).unwrap(), "one");
+    let list = revisions.list().unwrap();
+    assert_eq!(list.len(), 3);
+    assert_eq!(list[0].author, "carol");
+    assert!(revisions.info("../etc").is_err());
+    let _ = fs::remove_dir_all(&dir);
+  }
+}
@@ -0,0 +1,152 @@
+use crate::BurstSettings;
+use csv::StringRecord;
+
+/// The headings of the columns every `ShapedDevices.csv` row has
+pub(crate) const STANDARD_COLUMNS: [&str; 13] = [
+  "Circuit ID",
+  "Circuit Name",
+  "Device ID",
+  "Device Name",
+  "Parent Node",
+  "MAC",
+  "IPv4",
+  "IPv6",
+  "Download Min Mbps",
+  "Upload Min Mbps",
+  "Download Max Mbps",
+  "Upload Max Mbps",
+  "Comment",
+];
+
+/// The columns of a `ShapedDevices.csv` file, worked out from its
+/// heading row: the standard columns, then optionally the four burst
+/// columns, then any number of tag columns named by their headings.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct CsvLayout {
+  /// Are the burst columns present?
+  pub(crate) burst: bool,
+
+  /// Tag names, in column order
+  pub(crate) tags: Vec<String>,
+}
+
+impl CsvLayout {
+  pub(crate) fn from_headers(headers: &StringRecord) -> Result<Self, String> {
+    if headers.is_empty() {
+      return Ok(Self::default());
+    }
+    let standard = STANDARD_COLUMNS.len();
+    if headers.len() < standard {
+      return Err(format!(
+        "Expected at least {standard} columns, found {}",
+        headers.len()
+      ));
+    }
+    // Files written by older versions use snake_case headings
+    let normalize =
+      |heading: &str| heading.trim().replace('_', " ").to_lowercase();
+    let burst = headers.len() >= standard + BurstSettings::COLUMNS.len()
+      && headers
+        .iter()
+        .skip(standard)
+        .zip(BurstSettings::COLUMNS)
+        .all(|(heading, column)| normalize(heading) == normalize(column));
+
+    let mut tags: Vec<String> = Vec::new();
+    let first_tag =
+      standard + if burst { BurstSettings::COLUMNS.len() } else { 0 };
+    for heading in headers.iter().skip(first_tag) {
+      let heading = heading.trim();
+      if heading.is_empty() {
+        return Err("A tag column has no heading".to_string());
+      }
+      if tags.iter().any(|tag| tag.eq_ignore_ascii_case(heading)) {
+        return Err(format!("Tag column {heading:?} appears more than once"));
+      }
+      tags.push(heading.to_string());
+    }
+    Ok(Self { burst, tags })
+  }
+
+  /// The number of fields each row should have
+  pub(crate) fn len(&self) -> usize {
+    STANDARD_COLUMNS.len()
+      + if self.burst { BurstSettings::COLUMNS.len() } else { 0 }
+      + self.tags.len()
+  }
+
+  /// Does a row have the right number of fields? Files with only the
+  /// standard headings may still carry burst columns without headings,
+  /// as they could before tags were added.
+  pub(crate) fn fits(&self, fields: usize) -> bool {
+    fields == self.len() || self.headingless_burst(fields)
+  }
+
+  /// Does a row with this many fields carry the burst columns?
+  pub(crate) fn has_burst(&self, fields: usize) -> bool {
+    self.burst || self.headingless_burst(fields)
+  }
+
+  fn headingless_burst(&self, fields: usize) -> bool {
+    !self.burst
+      && self.tags.is_empty()
+      && fields == STANDARD_COLUMNS.len() + BurstSettings::COLUMNS.len()
+  }
+
+  /// The index of the first tag column
+  pub(crate) fn first_tag(&self) -> usize {
+    self.len() - self.tags.len()
+  }
+
+  /// The heading row to write
+  pub(crate) fn headings(&self) -> Vec<&str> {
+    let mut headings = STANDARD_COLUMNS.to_vec();
+    if self.burst {
+      headings.extend(BurstSettings::COLUMNS);
+    }
+    headings.extend(self.tags.iter().map(String::as_str));
+    headings
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn layout(headings: &str) -> Result<CsvLayout, String> {
+    CsvLayout::from_headers(&StringRecord::from(
+      headings.split(',').collect::<Vec<_>>(),
+    ))
+  }
+
+  #[test]
+  fn tags_follow_the_burst_columns() {
+    let standard = STANDARD_COLUMNS.join(",");
+    let plain = layout(&standard).unwrap();
+    assert_eq!(plain, CsvLayout::default());
+    assert!(plain.fits(13) && plain.fits(17) && !plain.fits(14));
+    assert!(plain.has_burst(17));
+
+    let tagged = layout(&format!(
+      "{standard},burst_download_mbps,burst_upload_mbps,burst_seconds,\
+       burst_refill_seconds,Plan,Tower"
+    ))
+    .unwrap();
+    assert!(tagged.burst);
+    assert_eq!(tagged.tags, vec!["Plan", "Tower"]);
+    assert_eq!(tagged.first_tag(), 17);
+    assert!(tagged.fits(19) && !tagged.fits(17));
+
+    let tags_only = layout(&format!("{standard},Plan")).unwrap();
+    assert!(!tags_only.burst && !tags_only.has_burst(14));
+    assert_eq!(tags_only.headings().last(), Some(&"Plan"));
+  }
+
+  #[test]
+  fn bad_headings_are_rejected() {
+    let standard = STANDARD_COLUMNS.join(",");
+    assert!(layout("Circuit ID,Circuit Name").is_err());
+    assert!(layout(&format!("{standard},Plan,")).is_err());
+    assert!(layout(&format!("{standard},Plan,plan")).is_err());
+  }
+}
@@ -1,11 +1,16 @@
+mod csv_layout;
 mod serializable;
 mod shaped_device;
 use crate::{etc, SUPPORTED_CUSTOMERS};
+pub(crate) use csv_layout::{CsvLayout, STANDARD_COLUMNS};
 use csv::{QuoteStyle, ReaderBuilder, WriterBuilder};
 use log::error;
 use serializable::SerializableShapedDevice;
-pub use shaped_device::ShapedDevice;
-use std::path::{Path, PathBuf};
+pub use shaped_device::{BurstSettings, ShapedDevice};
+use std::{
+  collections::BTreeSet,
+  path::{Path, PathBuf},
+};
 use thiserror::Error;
 
 /// Provides handling of the `ShapedDevices.csv` file that maps
@@ -55,6 +60,7 @@ impl ConfigShapedDevices {
     let reader = ReaderBuilder::new()
       .comment(Some(b'#'))
       .trim(csv::Trim::All)
+      .flexible(true)
       .from_path(final_path);
     if reader.is_err() {
       error!("Unable to read ShapedDevices.csv");
@@ -64,10 +70,37 @@ impl ConfigShapedDevices {
 
     // Example: StringRecord(["1", "968 Circle St., Gurnee, IL 60031", "1", "Device 1", "", "", "192.168.101.2", "", "25", "5", "10000", "10000", ""])
 
+    // The burst and tag columns are optional, so the heading row says
+    // which columns are present
+    let layout = reader
+      .headers()
+      .map_err(|e| ShapedDevicesError::GenericCsvError(e.to_string()))
+      .and_then(|headers| {
+        CsvLayout::from_headers(headers)
+          .map_err(ShapedDevicesError::BadHeadings)
+      });
+    let layout = match layout {
+      Ok(layout) => layout,
+      Err(e) => {
+        error!("Unable to read ShapedDevices.csv headings: {e:?}");
+        return Err(e);
+      }
+    };
+
     let mut devices = Vec::with_capacity(SUPPORTED_CUSTOMERS);
     for result in reader.records() {
       if let Ok(result) = result {
-        let device = ShapedDevice::from_csv(&result);
+        if !layout.fits(result.len()) {
+          let line = result.position().map_or(0, |p| p.line());
+          let msg = format!(
+            "At line {line}. Expected {} fields, found {}",
+            layout.len(),
+            result.len()
+          );
+          error!("CSV decode error: {msg}");
+          return Err(ShapedDevicesError::UnequalLengths(msg));
+        }
+        let device = ShapedDevice::from_csv(&result, &layout);
         if let Ok(device) = device {
           devices.push(device);
         } else {
@@ -108,6 +141,13 @@ impl ConfigShapedDevices {
     Ok(Self { devices, trie })
   }
 
+  /// Builds a `ConfigShapedDevices` from a list of devices, such as an
+  /// edited copy of the loaded list.
+  pub fn from_devices(devices: Vec<ShapedDevice>) -> Self {
+    let trie = ConfigShapedDevices::make_trie(&devices);
+    Self { devices, trie }
+  }
+
   fn make_trie(
     devices: &[ShapedDevice],
   ) -> ip_network_table::IpNetworkTable<usize> {
@@ -125,12 +165,32 @@ impl ConfigShapedDevices {
     table
   }
 
-  fn to_csv_string(&self) -> Result<String, ShapedDevicesError> {
+  /// The columns needed to write every device: the burst columns if
+  /// any device bursts, and a column for every tag in use.
+  fn csv_layout(&self) -> CsvLayout {
+    let mut tags: Vec<String> = Vec::new();
+    let in_use: BTreeSet<&String> =
+      self.devices.iter().flat_map(|d| d.tags.keys()).collect();
+    for tag in in_use {
+      if !tags.iter().any(|t| t.eq_ignore_ascii_case(tag)) {
+        tags.push(tag.clone());
+      }
+    }
+    CsvLayout { burst: self.devices.iter().any(|d| d.burst.is_some()), tags }
+  }
+
+  pub(crate) fn to_csv_string(&self) -> Result<String, ShapedDevicesError> {
+    let layout = self.csv_layout();
     let mut writer = WriterBuilder::new()
       .quote_style(QuoteStyle::NonNumeric)
       .from_writer(vec![]);
-    for d in self.devices.iter().map(SerializableShapedDevice::from) {
-      if writer.serialize(&d).is_err() {
+    if writer.write_record(layout.headings()).is_err() {
+      error!("Unable to write ShapedDevices.csv headings");
+      return Err(ShapedDevicesError::SerializeFail);
+    }
+    for d in self.devices.iter() {
+      let d = SerializableShapedDevice::new(d, &layout);
+      if writer.write_record(&d.0).is_err() {
         error!("Unable to serialize record, {:?}", d);
         return Err(ShapedDevicesError::SerializeFail);
       }
@@ -178,6 +238,8 @@ pub enum ShapedDevicesError {
   Utf8Error,
   #[error("Unable to decode device entry in ShapedDevices.csv")]
   DeviceDecode(String),
+  #[error("ShapedDevices.csv has unusable column headings")]
+  BadHeadings(String),
   #[error("CSV line contains an unepected number of entries")]
   UnequalLengths(String),
   #[error("Unexpected CSV file error")]
@@ -306,4 +368,45 @@ mod test {
     let v6 = addr.to_ipv6_mapped();
     assert!(trie.longest_match(v6).is_some());
   }
+
+  #[test]
+  fn tags_are_written_as_columns() {
+    let devices = ConfigShapedDevices {
+      devices: vec![
+        ShapedDevice {
+          circuit_id: "One".to_string(),
+          tags: [("Plan".to_string(), "Gold".to_string())].into(),
+          ..Default::default()
+        },
+        ShapedDevice {
+          circuit_id: "Two".to_string(),
+          tags: [("plan".to_string(), "Silver".to_string())].into(),
+          ..Default::default()
+        },
+      ],
+      ..Default::default()
+    };
+    let csv = devices.to_csv_string().unwrap();
+    let mut lines = csv.lines();
+    assert!(lines.next().unwrap().ends_with(r#""Comment","Plan""#));
+    assert!(lines.next().unwrap().ends_with(r#""","Gold""#));
+    assert!(lines.next().unwrap().ends_with(r#""","Silver""#));
+  }
+
+  #[test]
+  fn every_address_is_written() {
+    let devices = ConfigShapedDevices {
+      devices: vec![ShapedDevice {
+        circuit_id: "One".to_string(),
+        ipv4: ShapedDevice::parse_ipv4("100.64.0.1, 100.64.1.0/24"),
+        ipv6: ShapedDevice::parse_ipv6("2001:db8::1, 2001:db8:1::/48"),
+        ..Default::default()
+      }],
+      ..Default::default()
+    };
+    let csv = devices.to_csv_string().unwrap();
+    let row = csv.lines().nth(1).unwrap();
+    assert!(row.contains(r#""100.64.0.1, 100.64.1.0/24""#));
+    assert!(row.contains(r#""2001:db8::1, 2001:db8:1::/48""#));
+  }
 }
@@ -1,45 +1,48 @@
-use crate::ShapedDevice;
-use serde::Serialize;
+use super::csv_layout::CsvLayout;
+use crate::{BurstSettings, ShapedDevice};
 use std::net::{Ipv4Addr, Ipv6Addr};
 
-// Example: StringRecord(["1", "968 Circle St., Gurnee, IL 60031", "1", "Device 1", "", "", "192.168.101.2", "", "25", "5", "10000", "10000", ""])
-#[derive(Serialize, Debug)]
-pub(crate) struct SerializableShapedDevice {
-  pub circuit_id: String,
-  pub circuit_name: String,
-  pub device_id: String,
-  pub device_name: String,
-  pub parent_node: String,
-  pub mac: String,
-  pub ipv4: String,
-  pub ipv6: String,
-  pub download_min_mbps: u32,
-  pub upload_min_mbps: u32,
-  pub download_max_mbps: u32,
-  pub upload_max_mbps: u32,
-  pub comment: String,
-}
+/// A `ShapedDevices.csv` row, ready to write.
+// Example: ["1", "968 Circle St., Gurnee, IL 60031", "1", "Device 1", "", "", "192.168.101.2", "", "25", "5", "10000", "10000", ""]
+#[derive(Debug)]
+pub(crate) struct SerializableShapedDevice(pub Vec<String>);
 
-impl From<&ShapedDevice> for SerializableShapedDevice {
-  fn from(d: &ShapedDevice) -> Self {
-    Self {
-      circuit_id: d.circuit_id.clone(),
-      circuit_name: d.circuit_name.clone(),
-      device_id: d.device_id.clone(),
-      device_name: d.device_name.clone(),
-      parent_node: d.parent_node.clone(),
-      mac: d.mac.clone(),
-      ipv4: ipv4_list_to_string(&d.ipv4),
-      ipv6: ipv6_list_to_string(&d.ipv6),
-      download_min_mbps: d.download_min_mbps,
-      upload_min_mbps: d.upload_min_mbps,
-      download_max_mbps: d.download_max_mbps,
-      upload_max_mbps: d.upload_max_mbps,
-      comment: d.comment.clone(),
+impl SerializableShapedDevice {
+  pub(crate) fn new(d: &ShapedDevice, layout: &CsvLayout) -> Self {
+    let mut fields = vec![
+      d.circuit_id.clone(),
+      d.circuit_name.clone(),
+      d.device_id.clone(),
+      d.device_name.clone(),
+      d.parent_node.clone(),
+      d.mac.clone(),
+      ipv4_list_to_string(&d.ipv4),
+      ipv6_list_to_string(&d.ipv6),
+      d.download_min_mbps.to_string(),
+      d.upload_min_mbps.to_string(),
+      d.download_max_mbps.to_string(),
+      d.upload_max_mbps.to_string(),
+      d.comment.clone(),
+    ];
+    if layout.burst {
+      fields.extend([
+        burst_column(d, |b| b.download_mbps),
+        burst_column(d, |b| b.upload_mbps),
+        burst_column(d, |b| b.duration_seconds),
+        burst_column(d, |b| b.refill_seconds),
+      ]);
+    }
+    for tag in layout.tags.iter() {
+      fields.push(d.tag(tag).unwrap_or_default().to_string());
     }
+    Self(fields)
   }
 }
 
+fn burst_column(d: &ShapedDevice, field: fn(&BurstSettings) -> u32) -> String {
+  d.burst.as_ref().map_or(String::new(), |b| field(b).to_string())
+}
+
 fn ipv4_to_string(ip: &(Ipv4Addr, u32)) -> String {
   if ip.1 == 32 {
     format!("{}", ip.0)
@@ -60,11 +63,11 @@ fn ipv4_list_to_string(ips: &[(Ipv4Addr, u32)]) -> String {
     buffer += &format!("{}, ", ipv4_to_string(i));
   }
   buffer += &ipv4_to_string(&ips[ips.len() - 1]);
-  String::new()
+  buffer
 }
 
 fn ipv6_to_string(ip: &(Ipv6Addr, u32)) -> String {
-  if ip.1 == 32 {
+  if ip.1 == 128 {
     format!("{}", ip.0)
   } else {
     format! {"{}/{}", ip.0, ip.1}
@@ -83,5 +86,5 @@ fn ipv6_list_to_string(ips: &[(Ipv6Addr, u32)]) -> String {
     buffer += &format!("{}, ", ipv6_to_string(i));
   }
   buffer += &ipv6_to_string(&ips[ips.len() - 1]);
-  String::new()
+  buffer
 }
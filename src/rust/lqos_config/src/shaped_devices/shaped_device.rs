@@ -1,12 +1,15 @@
 use csv::StringRecord;
 use log::error;
 use serde::{Deserialize, Serialize};
-use std::net::{Ipv4Addr, Ipv6Addr};
+use std::{
+  collections::BTreeMap,
+  net::{Ipv4Addr, Ipv6Addr},
+};
 
-use super::ShapedDevicesError;
+use super::{csv_layout::CsvLayout, ShapedDevicesError};
 
 /// Represents a row in the `ShapedDevices.csv` file.
-#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+#[derive(Clone, Debug, Serialize, Deserialize, Default, PartialEq, Eq)]
 pub struct ShapedDevice {
   // Circuit ID,Circuit Name,Device ID,Device Name,Parent Node,MAC,IPv4,IPv6,Download Min Mbps,Upload Min Mbps,Download Max Mbps,Upload Max Mbps,Comment
   /// The ID of the circuit to which the device belongs. Circuits are 1:many,
@@ -55,11 +58,87 @@ pub struct ShapedDevice {
 
   /// Generic comments field, does nothing.
   pub comment: String,
+
+  /// Optional burst allowance, from the four optional columns after
+  /// the comment.
+  #[serde(default)]
+  pub burst: Option<BurstSettings>,
+
+  /// Optional tags, such as plan, tower or reseller, from any columns
+  /// after the standard (and burst) columns. Keyed by the column
+  /// heading; empty cells are left out.
+  #[serde(default)]
+  pub tags: BTreeMap<String, String>,
+}
+
+/// A circuit's burst allowance: it may run faster than its maximum
+/// rate for a short time, such as for page loads and small downloads.
+/// The allowance is used up by traffic above the maximum rate, and
+/// refills while the circuit stays below it.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BurstSettings {
+  /// Download rate while bursting, in Mbps
+  pub download_mbps: u32,
+
+  /// Upload rate while bursting, in Mbps
+  pub upload_mbps: u32,
+
+  /// How long a full allowance lasts at the burst rate, in seconds
+  pub duration_seconds: u32,
+
+  /// How long an empty allowance takes to refill, in seconds
+  pub refill_seconds: u32,
+}
+
+impl BurstSettings {
+  /// The column headings of the optional burst columns
+  pub const COLUMNS: [&'static str; 4] = [
+    "Burst Download Mbps",
+    "Burst Upload Mbps",
+    "Burst Seconds",
+    "Burst Refill Seconds",
+  ];
+
+  /// Reads the burst columns: either all empty (no burst), or all
+  /// whole numbers above zero.
+  pub fn from_columns(columns: &[&str]) -> Result<Option<Self>, String> {
+    if columns.iter().all(|c| c.is_empty()) {
+      return Ok(None);
+    }
+    let mut values = [0u32; 4];
+    for (i, (column, name)) in columns.iter().zip(Self::COLUMNS).enumerate()
+    {
+      values[i] = match column.parse::<u32>() {
+        Ok(value) if value > 0 => value,
+        _ => {
+          return Err(format!(
+            "{name} must be a whole number above zero ({column:?})"
+          ))
+        }
+      };
+    }
+    Ok(Some(Self {
+      download_mbps: values[0],
+      upload_mbps: values[1],
+      duration_seconds: values[2],
+      refill_seconds: values[3],
+    }))
+  }
 }
 
 impl ShapedDevice {
+  /// The value of a tag, matching its name without regard to case
+  pub fn tag(&self, name: &str) -> Option<&str> {
+    self
+      .tags
+      .iter()
+      .find(|(tag, _)| tag.eq_ignore_ascii_case(name))
+      .map(|(_, value)| value.as_str())
+  }
+
   pub(crate) fn from_csv(
     record: &StringRecord,
+    layout: &CsvLayout,
   ) -> Result<Self, ShapedDevicesError> {
     Ok(Self {
       circuit_id: record[0].to_string(),
@@ -83,10 +162,26 @@ impl ShapedDevice {
         ShapedDevicesError::CsvEntryParseError(record[11].to_string())
       })?,
       comment: record[12].to_string(),
+      burst: if layout.has_burst(record.len()) {
+        let columns: Vec<&str> = record.iter().skip(13).take(4).collect();
+        BurstSettings::from_columns(&columns)
+          .map_err(ShapedDevicesError::CsvEntryParseError)?
+      } else {
+        None
+      },
+      tags: layout
+        .tags
+        .iter()
+        .zip(record.iter().skip(layout.first_tag()))
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(tag, value)| (tag.clone(), value.to_string()))
+        .collect(),
     })
   }
 
-  pub(crate) fn parse_cidr_v4(
+  /// Parses an IPv4 address or CIDR subnet, such as `100.64.0.0/24`.
+  /// A bare address is a /32.
+  pub fn parse_cidr_v4(
     address: &str,
   ) -> Result<(Ipv4Addr, u32), ShapedDevicesError> {
     if address.contains('/') {
@@ -132,7 +227,8 @@ impl ShapedDevice {
     result
   }
 
-  pub(crate) fn parse_cidr_v6(
+  /// Parses an IPv6 address or CIDR subnet. A bare address is a /128.
+  pub fn parse_cidr_v6(
     address: &str,
   ) -> Result<(Ipv6Addr, u32), ShapedDevicesError> {
     if address.contains('/') {
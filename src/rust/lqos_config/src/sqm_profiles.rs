@@ -0,0 +1,206 @@
+//! Per-circuit SQM profiles.
+//!
+//! By default every circuit's queues use the global `sqm` setting.
+//! `SqmProfiles.toml` (in `lqos_directory`) defines named profiles,
+//! each with its own queue discipline and options, and a table
+//! assigning circuits to them. For example:
+//!
+//! ```toml
+//! [[profile]]
+//! name = "business"
+//! sqm = "fq_codel target 3ms"
+//!
+//! [[profile]]
+//! name = "voip"
+//! sqm = "cake diffserv4"
+//!
+//! [[profile]]
+//! name = "transit"
+//! sqm = "none"
+//!
+//! [circuits]
+//! "12345" = "voip"
+//! ```
+//!
+//! A profile of `none` leaves the circuit's HTB classes without a
+//! queue discipline of their own. Profiles take effect the next time
+//! `LibreQoS.py` builds the queues.
+use crate::etc;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::PathBuf};
+use thiserror::Error;
+
+/// The file SQM profiles are read from, inside `lqos_directory`
+const SQM_PROFILES_FILE: &str = "SqmProfiles.toml";
+
+/// The queue disciplines a profile may use
+const SQM_KINDS: [&str; 3] = ["cake", "fq_codel", "none"];
+
+/// A named queue discipline, with its options.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SqmProfile {
+  /// The profile's name, used to assign circuits to it
+  pub name: String,
+
+  /// The queue discipline and its options, as passed to `tc qdisc add`
+  /// (e.g. `cake diffserv4`), or `none` for plain HTB
+  pub sqm: String,
+}
+
+impl SqmProfile {
+  /// The queue discipline, e.g. `cake`
+  pub fn kind(&self) -> &str {
+    self.sqm.split_whitespace().next().unwrap_or_default()
+  }
+
+  /// Does the profile leave circuits with plain HTB classes?
+  pub fn is_plain_htb(&self) -> bool {
+    self.kind() == "none"
+  }
+}
+
+/// Every SQM profile, and the circuits that use them.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct SqmProfiles {
+  /// The profiles
+  #[serde(default, rename = "profile")]
+  pub profiles: Vec<SqmProfile>,
+
+  /// Profile names, by circuit ID
+  #[serde(default)]
+  pub circuits: BTreeMap<String, String>,
+}
+
+impl SqmProfiles {
+  /// The path to `SqmProfiles.toml`
+  pub fn path() -> Result<PathBuf, SqmProfileError> {
+    let cfg = etc::EtcLqos::load().map_err(|_| SqmProfileError::ConfigLoad)?;
+    Ok(PathBuf::from(&cfg.lqos_directory).join(SQM_PROFILES_FILE))
+  }
+
+  /// Loads and checks `SqmProfiles.toml`. A missing file is the same
+  /// as an empty one: every circuit uses the global `sqm`.
+  pub fn load() -> Result<Self, SqmProfileError> {
+    let path = Self::path()?;
+    if !path.exists() {
+      return Ok(Self::default());
+    }
+    let raw = std::fs::read_to_string(&path)
+      .map_err(|e| SqmProfileError::Io(e.to_string()))?;
+    Self::parse(&raw)
+  }
+
+  /// Parses and checks the contents of a `SqmProfiles.toml` file.
+  pub fn parse(raw: &str) -> Result<Self, SqmProfileError> {
+    let profiles: Self = toml_edit::de::from_str(raw)
+      .map_err(|e| SqmProfileError::Parse(e.to_string()))?;
+    profiles.check()?;
+    Ok(profiles)
+  }
+
+  fn check(&self) -> Result<(), SqmProfileError> {
+    for (i, profile) in self.profiles.iter().enumerate() {
+      if profile.name.is_empty() {
+        return Err(SqmProfileError::Invalid(
+          "A profile has no name".to_string(),
+        ));
+      }
+      if self.profiles[..i].iter().any(|p| p.name == profile.name) {
+        return Err(SqmProfileError::Invalid(format!(
+          "Profile {} is defined more than once",
+          profile.name
+        )));
+      }
+      if !SQM_KINDS.contains(&profile.kind()) {
+        return Err(SqmProfileError::Invalid(format!(
+          "{}: {:?} should start with one of {}",
+          profile.name,
+          profile.sqm,
+          SQM_KINDS.join(", ")
+        )));
+      }
+      if profile.is_plain_htb() && profile.sqm.trim() != "none" {
+        return Err(SqmProfileError::Invalid(format!(
+          "{}: none takes no options",
+          profile.name
+        )));
+      }
+    }
+    for (circuit_id, name) in self.circuits.iter() {
+      if !self.profiles.iter().any(|p| &p.name == name) {
+        return Err(SqmProfileError::Invalid(format!(
+          "Circuit {circuit_id} uses profile {name}, which isn't defined"
+        )));
+      }
+    }
+    Ok(())
+  }
+
+  /// The profile assigned to a circuit, if any
+  pub fn profile_for_circuit(&self, circuit_id: &str) -> Option<&SqmProfile> {
+    let name = self.circuits.get(circuit_id)?;
+    self.profiles.iter().find(|p| &p.name == name)
+  }
+}
+
+/// Errors reading `SqmProfiles.toml`
+#[derive(Debug, Error)]
+pub enum SqmProfileError {
+  /// Unable to load `/etc/lqos.conf`
+  #[error("Unable to load /etc/lqos.conf")]
+  ConfigLoad,
+  /// Unable to read the file
+  #[error("Unable to read SqmProfiles.toml: {0}")]
+  Io(String),
+  /// The file isn't valid TOML, or doesn't match the expected layout
+  #[error("Unable to parse SqmProfiles.toml: {0}")]
+  Parse(String),
+  /// The file parsed, but doesn't make sense
+  #[error("Invalid SQM profile: {0}")]
+  Invalid(String),
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  const EXAMPLE: &str = r#"
+[[profile]]
+name = "business"
+sqm = "fq_codel target 3ms"
+
+[[profile]]
+name = "transit"
+sqm = "none"
+
+[circuits]
+"c1" = "business"
+"c2" = "transit"
+"#;
+
+  #[test]
+  fn profiles_are_assigned() {
+    let profiles = SqmProfiles::parse(EXAMPLE).unwrap();
+    let business = profiles.profile_for_circuit("c1").unwrap();
+    assert_eq!(business.kind(), "fq_codel");
+    assert!(!business.is_plain_htb());
+    assert!(profiles.profile_for_circuit("c2").unwrap().is_plain_htb());
+    assert!(profiles.profile_for_circuit("c3").is_none());
+  }
+
+  #[test]
+  fn bad_profiles_are_rejected() {
+    for raw in [
+      "[[profile]]\nname = \"a\"\nsqm = \"pfifo\"\n",
+      "[[profile]]\nname = \"a\"\nsqm = \"none limit 5\"\n",
+      "[[profile]]\nname = \"a\"\nsqm = \"cake\"\n\
+       [[profile]]\nname = \"a\"\nsqm = \"fq_codel\"\n",
+      "[circuits]\n\"c1\" = \"missing\"\n",
+    ] {
+      assert!(
+        matches!(SqmProfiles::parse(raw), Err(SqmProfileError::Invalid(_))),
+        "{raw}"
+      );
+    }
+  }
+}
@@ -0,0 +1,438 @@
+use super::{
+  network_checks::NetworkSummary, ValidationFinding, ValidationSeverity,
+  ValidationSeverity::*, ValidationSource,
+};
+use crate::{shaped_devices::CsvLayout, BurstSettings, ShapedDevice};
+use csv::ReaderBuilder;
+use std::collections::HashMap;
+
+/// An address range, with IPv4 stored as IPv4-mapped IPv6 so that both
+/// families can be compared together.
+type Range = (u128, u128);
+
+fn to_range(address: u128, prefix: u32) -> Range {
+  let mask = if prefix == 0 { 0 } else { u128::MAX << (128 - prefix) };
+  (address & mask, (address & mask) | !mask)
+}
+
+fn contains(outer: &Range, inner: &Range) -> bool {
+  outer.0 <= inner.0 && outer.1 >= inner.1
+}
+
+/// Parses one address or CIDR subnet, returning its range and the
+/// prefix length it was written with.
+fn parse_address(text: &str) -> Result<(u128, u32, bool), String> {
+  if text.contains(':') {
+    let (address, prefix) = ShapedDevice::parse_cidr_v6(text)
+      .map_err(|_| format!("Unable to parse IPv6 address {text}"))?;
+    if prefix > 128 {
+      return Err(format!("{text} has a prefix longer than 128 bits"));
+    }
+    Ok((u128::from(address), prefix, false))
+  } else {
+    let (address, prefix) = ShapedDevice::parse_cidr_v4(text)
+      .map_err(|_| format!("Unable to parse IPv4 address {text}"))?;
+    if prefix > 32 {
+      return Err(format!("{text} has a prefix longer than 32 bits"));
+    }
+    Ok((u128::from(address.to_ipv6_mapped()), prefix + 96, true))
+  }
+}
+
+/// The `allowedSubnets` and `ignoredSubnets` lists from `ispConfig.py`.
+pub(crate) struct SubnetFilter {
+  allowed: Vec<Range>,
+  ignored: Vec<Range>,
+}
+
+impl SubnetFilter {
+  pub(crate) fn new(
+    allowed: Option<&str>,
+    ignored: Option<&str>,
+    findings: &mut Vec<ValidationFinding>,
+  ) -> Self {
+    Self {
+      allowed: Self::parse_list("allowedSubnets", allowed, findings),
+      ignored: Self::parse_list("ignoredSubnets", ignored, findings),
+    }
+  }
+
+  // Tolerates the Python list syntax, with or without quotes.
+  fn parse_list(
+    name: &str,
+    list: Option<&str>,
+    findings: &mut Vec<ValidationFinding>,
+  ) -> Vec<Range> {
+    let Some(list) = list else { return Vec::new() };
+    list
+      .replace(['[', ']', '"', '\'', ' '], "")
+      .split(',')
+      .filter(|entry| !entry.is_empty())
+      .filter_map(|entry| match parse_address(entry) {
+        Ok((address, prefix, _)) => Some(to_range(address, prefix)),
+        Err(e) => {
+          findings.push(ValidationFinding::new(
+            Error,
+            ValidationSource::IspConfig,
+            format!("{name}: {e}"),
+          ));
+          None
+        }
+      })
+      .collect()
+  }
+}
+
+// The csv crate doesn't count comment lines, and a record's position
+// is where it started reading (before any comments), so work the line
+// number out from the byte offset instead.
+fn line_at(csv: &str, position: &csv::Position) -> u64 {
+  let offset = (position.byte() as usize).min(csv.len());
+  let line = csv.as_bytes()[..offset].iter().filter(|b| **b == b'\n').count();
+  let comments = csv[offset..]
+    .lines()
+    .take_while(|l| l.starts_with('#') || l.is_empty())
+    .count();
+  (line + comments) as u64 + 1
+}
+
+struct CircuitFirstRow {
+  row: usize,
+  name: String,
+  parent_node: String,
+  rates: Option<[u32; 4]>,
+}
+
+struct Prefix {
+  range: Range,
+  text: String,
+  line: u64,
+  row: usize,
+  circuit_id: String,
+}
+
+fn finding(severity: ValidationSeverity, message: String) -> ValidationFinding {
+  ValidationFinding::new(severity, ValidationSource::ShapedDevices, message)
+}
+
+const RATE_NAMES: [&str; 4] = [
+  "Download Min Mbps",
+  "Upload Min Mbps",
+  "Download Max Mbps",
+  "Upload Max Mbps",
+];
+
+pub(crate) fn check_shaped_devices(
+  csv: &str,
+  network: Option<&NetworkSummary>,
+  subnets: &SubnetFilter,
+  findings: &mut Vec<ValidationFinding>,
+) {
+  let mut reader = ReaderBuilder::new()
+    .comment(Some(b'#'))
+    .trim(csv::Trim::All)
+    .flexible(true)
+    .from_reader(csv.as_bytes());
+  // The heading row says which optional columns are present
+  let layout = match reader.headers() {
+    Ok(headers) => CsvLayout::from_headers(headers),
+    Err(e) => Err(e.to_string()),
+  };
+  let layout = match layout {
+    Ok(layout) => layout,
+    Err(e) => {
+      let mut f = finding(Error, format!("Column headings: {e}"));
+      f.line = Some(line_at(csv, &csv::Position::new()));
+      findings.push(f);
+      return;
+    }
+  };
+  let network = network.filter(|n| !n.is_empty());
+  let mut circuits: HashMap<String, CircuitFirstRow> = HashMap::new();
+  let mut prefixes = Vec::new();
+
+  for (idx, record) in reader.records().enumerate() {
+    let row = idx + 1;
+    let record = match record {
+      Ok(record) => record,
+      Err(e) => {
+        let mut f = finding(Error, format!("Unable to read row: {e}"));
+        f.line = e.position().map(|p| line_at(csv, p));
+        f.row = Some(row);
+        findings.push(f);
+        continue;
+      }
+    };
+    let line = record.position().map(|p| line_at(csv, p)).unwrap_or(0);
+    let mut report = |severity, circuit_id: &str, message: String| {
+      findings.push(
+        finding(severity, message).at_row(line, row).for_circuit(circuit_id),
+      );
+    };
+    if !layout.fits(record.len()) {
+      report(
+        Error,
+        "",
+        format!("Expected {} fields, found {}", layout.len(), record.len()),
+      );
+      continue;
+    }
+    let circuit_id = &record[0];
+    if circuit_id.is_empty() {
+      report(Error, "", "Circuit ID is empty".to_string());
+    }
+
+    // Rates
+    let mut rates = Some([0u32; 4]);
+    for (i, name) in RATE_NAMES.iter().enumerate() {
+      match record[8 + i].parse::<u32>() {
+        Ok(rate) => {
+          if let Some(rates) = rates.as_mut() {
+            rates[i] = rate;
+          }
+        }
+        Err(_) => {
+          report(
+            Error,
+            circuit_id,
+            format!("{name} is not a whole number ({:?})", &record[8 + i]),
+          );
+          rates = None;
+        }
+      }
+    }
+    if let Some([down_min, up_min, down_max, up_max]) = rates {
+      for (direction, min, max) in
+        [("Download", down_min, down_max), ("Upload", up_min, up_max)]
+      {
+        if min > max {
+          report(
+            Error,
+            circuit_id,
+            format!(
+              "{direction} minimum ({min} Mbps) is above the maximum ({max} Mbps)"
+            ),
+          );
+        }
+      }
+    }
+
+    // Optional burst allowance
+    if layout.has_burst(record.len()) {
+      let columns: Vec<&str> = record.iter().skip(13).take(4).collect();
+      match BurstSettings::from_columns(&columns) {
+        Err(e) => report(Error, circuit_id, e),
+        Ok(Some(burst)) => {
+          if let Some([_, _, down_max, up_max]) = rates {
+            if burst.download_mbps <= down_max || burst.upload_mbps <= up_max {
+              report(
+                Warning,
+                circuit_id,
+                "Burst rates should be above the maximum rates".to_string(),
+              );
+            }
+          }
+        }
+        Ok(None) => {}
+      }
+    }
+
+    // Circuit consistency, and checks that only need doing once per
+    // circuit
+    let circuit_name = &record[1];
+    let parent_node = &record[4];
+    let mut check_parent = true;
+    if let Some(first) = circuits.get(circuit_id) {
+      if first.name != circuit_name {
+        report(
+          Warning,
+          circuit_id,
+          format!(
+            "Circuit name {circuit_name:?} differs from row {} ({:?})",
+            first.row, first.name
+          ),
+        );
+      }
+      if first.parent_node != parent_node {
+        report(
+          Warning,
+          circuit_id,
+          format!(
+            "Parent node {parent_node:?} differs from row {} ({:?})",
+            first.row, first.parent_node
+          ),
+        );
+      } else {
+        check_parent = false;
+      }
+      if let (Some(first_rates), Some(rates)) = (first.rates, rates) {
+        if first_rates != rates {
+          report(
+            Warning,
+            circuit_id,
+            format!(
+              "Circuit rates differ from row {}; only one set of rates is used",
+              first.row
+            ),
+          );
+        }
+      }
+    } else if !circuit_id.is_empty() {
+      circuits.insert(
+        circuit_id.to_string(),
+        CircuitFirstRow {
+          row,
+          name: circuit_name.to_string(),
+          parent_node: parent_node.to_string(),
+          rates,
+        },
+      );
+    }
+    let network = network.filter(|_| check_parent && !parent_node.is_empty());
+    if let Some(network) = network {
+      match network.find(parent_node) {
+        None => report(
+          Error,
+          circuit_id,
+          format!("Parent node {parent_node:?} is not in network.json"),
+        ),
+        Some(node) => {
+          if let Some([_, _, down_max, up_max]) = rates {
+            for (direction, max, capacity) in [
+              ("download", down_max, node.download_mbps),
+              ("upload", up_max, node.upload_mbps),
+            ] {
+              if u64::from(max) > capacity {
+                report(
+                  Warning,
+                  circuit_id,
+                  format!(
+                    "Maximum {direction} of {max} Mbps exceeds parent node {}'s {capacity} Mbps",
+                    node.path
+                  ),
+                );
+              }
+            }
+          }
+        }
+      }
+    }
+
+    // Addresses
+    for text in record[6].split(',').chain(record[7].split(',')) {
+      let text = text.trim();
+      if text.is_empty() {
+        continue;
+      }
+      let (address, prefix, is_v4) = match parse_address(text) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+          report(Error, circuit_id, e);
+          continue;
+        }
+      };
+      let range = to_range(address, prefix);
+      if range.0 != address {
+        let written_prefix = if is_v4 { prefix - 96 } else { prefix };
+        let network_address = if is_v4 {
+          std::net::Ipv6Addr::from(range.0).to_ipv4_mapped().unwrap().to_string()
+        } else {
+          std::net::Ipv6Addr::from(range.0).to_string()
+        };
+        report(
+          Error,
+          circuit_id,
+          format!(
+            "{text} has host bits set and will be ignored; did you mean {network_address}/{written_prefix}?"
+          ),
+        );
+        continue;
+      }
+      if !subnets.allowed.is_empty()
+        && !subnets.allowed.iter().any(|allowed| contains(allowed, &range))
+      {
+        report(
+          Warning,
+          circuit_id,
+          format!("{text} is outside allowedSubnets and will not be shaped"),
+        );
+      }
+      if subnets.ignored.iter().any(|ignored| contains(ignored, &range)) {
+        report(
+          Warning,
+          circuit_id,
+          format!("{text} is inside ignoredSubnets and will not be shaped"),
+        );
+      }
+      prefixes.push(Prefix {
+        range,
+        text: text.to_string(),
+        line,
+        row,
+        circuit_id: circuit_id.to_string(),
+      });
+    }
+  }
+
+  check_overlaps(prefixes, findings);
+}
+
+/// Sorting by start (and widest first) means that every prefix is
+/// preceded by the prefixes containing it, so a stack of the enclosing
+/// prefixes finds each overlap in one pass.
+fn check_overlaps(
+  mut prefixes: Vec<Prefix>,
+  findings: &mut Vec<ValidationFinding>,
+) {
+  prefixes.sort_by(|a, b| {
+    a.range.0.cmp(&b.range.0).then(b.range.1.cmp(&a.range.1))
+  });
+  let mut stack: Vec<&Prefix> = Vec::new();
+  for prefix in prefixes.iter() {
+    while let Some(top) = stack.last() {
+      if top.range.1 >= prefix.range.0 {
+        break;
+      }
+      stack.pop();
+    }
+    if let Some(outer) = stack.last() {
+      let same_circuit = outer.circuit_id == prefix.circuit_id;
+      let (severity, message) = match (outer.range == prefix.range, same_circuit) {
+        (true, true) => (
+          Warning,
+          format!(
+            "{} is listed more than once in this circuit (row {})",
+            prefix.text, outer.row
+          ),
+        ),
+        (true, false) => (
+          Error,
+          format!(
+            "{} is also assigned to circuit {} (row {})",
+            prefix.text, outer.circuit_id, outer.row
+          ),
+        ),
+        (false, true) => (
+          Warning,
+          format!(
+            "{} is already covered by {} (row {})",
+            prefix.text, outer.text, outer.row
+          ),
+        ),
+        (false, false) => (
+          Warning,
+          format!(
+            "{} overlaps {} in circuit {} (row {}); the most specific match wins",
+            prefix.text, outer.text, outer.circuit_id, outer.row
+          ),
+        ),
+      };
+      findings.push(
+        finding(severity, message)
+          .at_row(prefix.line, prefix.row)
+          .for_circuit(&prefix.circuit_id),
+      );
+    }
+    stack.push(prefix);
+  }
+}
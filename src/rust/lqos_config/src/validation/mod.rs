@@ -0,0 +1,378 @@
+//! Semantic validation of `ShapedDevices.csv` and `network.json`,
+//! checked together. Unlike `ConfigShapedDevices::load`, which stops
+//! at the first decode error (and quietly drops addresses it can't
+//! parse), validation keeps going and reports everything it finds.
+mod csv_checks;
+mod network_checks;
+use crate::{etc, ConfigShapedDevices, LibreQoSConfig};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// How serious a validation finding is.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValidationSeverity {
+  /// LibreQoS will not shape this as intended.
+  Error,
+  /// Probably a mistake, but LibreQoS can work around it.
+  Warning,
+}
+
+/// The file a validation finding refers to.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValidationSource {
+  /// `ShapedDevices.csv`
+  ShapedDevices,
+  /// `network.json`
+  NetworkJson,
+  /// `ispConfig.py`
+  IspConfig,
+}
+
+/// A single problem found while validating the configuration.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ValidationFinding {
+  /// How serious the problem is
+  pub severity: ValidationSeverity,
+
+  /// Which file the problem is in
+  pub source: ValidationSource,
+
+  /// Line number in the file, if known (1-based, including any
+  /// header)
+  pub line: Option<u64>,
+
+  /// `ShapedDevices.csv` data row number (1-based, excluding the
+  /// header and comments)
+  pub row: Option<usize>,
+
+  /// The circuit involved, if any
+  pub circuit_id: Option<String>,
+
+  /// The `network.json` node involved, if any, as a path from the top
+  /// of the tree (e.g. `Site1/AP2`)
+  pub node: Option<String>,
+
+  /// Human readable description of the problem
+  pub message: String,
+}
+
+impl ValidationFinding {
+  pub(crate) fn new(
+    severity: ValidationSeverity,
+    source: ValidationSource,
+    message: String,
+  ) -> Self {
+    Self {
+      severity,
+      source,
+      line: None,
+      row: None,
+      circuit_id: None,
+      node: None,
+      message,
+    }
+  }
+
+  pub(crate) fn at_row(mut self, line: u64, row: usize) -> Self {
+    self.line = Some(line);
+    self.row = Some(row);
+    self
+  }
+
+  pub(crate) fn for_circuit(mut self, circuit_id: &str) -> Self {
+    if !circuit_id.is_empty() {
+      self.circuit_id = Some(circuit_id.to_string());
+    }
+    self
+  }
+
+  pub(crate) fn for_node(mut self, node: &str) -> Self {
+    self.node = Some(node.to_string());
+    self
+  }
+}
+
+/// The raw contents to validate.
+#[derive(Default)]
+pub struct ValidationInput<'a> {
+  /// Contents of `ShapedDevices.csv`
+  pub shaped_devices_csv: &'a str,
+
+  /// Contents of `network.json`, if there is one
+  pub network_json: Option<&'a str>,
+
+  /// The `allowedSubnets` list from `ispConfig.py`, if known
+  pub allowed_subnets: Option<&'a str>,
+
+  /// The `ignoredSubnets` list from `ispConfig.py`, if known
+  pub ignored_subnets: Option<&'a str>,
+}
+
+/// Validates `ShapedDevices.csv` and `network.json` together, returning
+/// every finding. An empty list means nothing was wrong.
+pub fn validate(input: &ValidationInput) -> Vec<ValidationFinding> {
+  let mut findings = Vec::new();
+  let network = input
+    .network_json
+    .map(|raw| network_checks::check_network(raw, &mut findings));
+  let subnets = csv_checks::SubnetFilter::new(
+    input.allowed_subnets,
+    input.ignored_subnets,
+    &mut findings,
+  );
+  csv_checks::check_shaped_devices(
+    input.shaped_devices_csv,
+    network.as_ref(),
+    &subnets,
+    &mut findings,
+  );
+  findings
+}
+
+/// Loads `ShapedDevices.csv`, `network.json` and `ispConfig.py` from
+/// the LibreQoS directory and validates them.
+pub fn validate_configuration() -> Vec<ValidationFinding> {
+  use ValidationSeverity::*;
+  let cfg = match etc::EtcLqos::load() {
+    Ok(cfg) => cfg,
+    Err(_) => return config_load_failed(),
+  };
+  let base_path = Path::new(&cfg.lqos_directory);
+
+  let csv = match std::fs::read_to_string(base_path.join("ShapedDevices.csv")) {
+    Ok(csv) => csv,
+    Err(e) => {
+      return vec![ValidationFinding::new(
+        Error,
+        ValidationSource::ShapedDevices,
+        format!("Unable to read ShapedDevices.csv: {e}"),
+      )]
+    }
+  };
+  validate_in_directory(base_path, &csv)
+}
+
+/// Validates shaped devices that haven't been saved yet, against the
+/// `network.json` and `ispConfig.py` in the LibreQoS directory.
+pub fn validate_shaped_devices(
+  devices: &ConfigShapedDevices,
+) -> Vec<ValidationFinding> {
+  let cfg = match etc::EtcLqos::load() {
+    Ok(cfg) => cfg,
+    Err(_) => return config_load_failed(),
+  };
+  match devices.to_csv_string() {
+    Ok(csv) => validate_in_directory(Path::new(&cfg.lqos_directory), &csv),
+    Err(e) => vec![ValidationFinding::new(
+      ValidationSeverity::Error,
+      ValidationSource::ShapedDevices,
+      format!("Unable to write the shaped devices: {e}"),
+    )],
+  }
+}
+
+/// Validates edited shaped devices against an edited `network.json`,
+/// and the `ispConfig.py` in the LibreQoS directory.
+pub fn validate_network_edit(
+  devices: &ConfigShapedDevices,
+  network_json: &str,
+) -> Vec<ValidationFinding> {
+  match devices.to_csv_string() {
+    Ok(csv) => validate_with_network(&csv, Some(network_json.to_string())),
+    Err(e) => vec![ValidationFinding::new(
+      ValidationSeverity::Error,
+      ValidationSource::ShapedDevices,
+      format!("Unable to write the shaped devices: {e}"),
+    )],
+  }
+}
+
+fn config_load_failed() -> Vec<ValidationFinding> {
+  vec![ValidationFinding::new(
+    ValidationSeverity::Error,
+    ValidationSource::IspConfig,
+    "Unable to load /etc/lqos.conf".to_string(),
+  )]
+}
+
+fn validate_in_directory(
+  base_path: &Path,
+  csv: &str,
+) -> Vec<ValidationFinding> {
+  let network_json = std::fs::read_to_string(base_path.join("network.json")).ok();
+  validate_with_network(csv, network_json)
+}
+
+fn validate_with_network(
+  csv: &str,
+  network_json: Option<String>,
+) -> Vec<ValidationFinding> {
+  use ValidationSeverity::*;
+  let config = LibreQoSConfig::load().ok();
+
+  let mut findings = validate(&ValidationInput {
+    shaped_devices_csv: csv,
+    network_json: network_json.as_deref(),
+    allowed_subnets: config.as_ref().map(|c| c.allowed_subnets.as_str()),
+    ignored_subnets: config.as_ref().map(|c| c.ignored_subnets.as_str()),
+  });
+  if network_json.is_none() {
+    findings.push(ValidationFinding::new(
+      Warning,
+      ValidationSource::NetworkJson,
+      "Unable to read network.json; parent nodes were not checked".to_string(),
+    ));
+  }
+  if config.is_none() {
+    findings.push(ValidationFinding::new(
+      Warning,
+      ValidationSource::IspConfig,
+      "Unable to load ispConfig.py; allowed and ignored subnets were not checked".to_string(),
+    ));
+  }
+  findings
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use ValidationSeverity::*;
+
+  const HEADER: &str = "Circuit ID,Circuit Name,Device ID,Device Name,Parent Node,MAC,IPv4,IPv6,Download Min Mbps,Upload Min Mbps,Download Max Mbps,Upload Max Mbps,Comment\n";
+
+  const NETWORK: &str = r#"{
+    "Site1": {
+      "downloadBandwidthMbps": 100, "uploadBandwidthMbps": 100,
+      "children": {}
+    }
+  }"#;
+
+  fn run(rows: &str) -> Vec<ValidationFinding> {
+    let csv = format!("{HEADER}{rows}");
+    validate(&ValidationInput {
+      shaped_devices_csv: &csv,
+      network_json: Some(NETWORK),
+      allowed_subnets: Some("['100.64.0.0/10', '2001:db8::/32']"),
+      ignored_subnets: Some("['100.64.99.0/24']"),
+    })
+  }
+
+  #[test]
+  fn clean_file_has_no_findings() {
+    let findings = run(
+      "1,One,1,D1,Site1,,100.64.0.1,2001:db8::/64,5,5,50,50,\n\
+       1,One,2,D2,Site1,,100.64.0.2,,5,5,50,50,\n",
+    );
+    assert!(findings.is_empty(), "{findings:?}");
+  }
+
+  #[test]
+  fn row_level_errors() {
+    let findings = run(
+      "# comment\n\
+       1,One,1,D1,Nowhere,,100.64.0.300,,60,5,50,50,\n\
+       2,Two,2,D2,Site1,,100.64.1.1/24,,5,5,500,50,\n\
+       3,Three\n",
+    );
+    let found: Vec<_> = findings
+      .iter()
+      .map(|f| (f.severity, f.line, f.row, f.message.as_str()))
+      .collect();
+    assert_eq!(findings.len(), 6, "{found:#?}");
+    assert!(findings.iter().all(|f| f.source == ValidationSource::ShapedDevices));
+    assert_eq!(found[0].0, Error);
+    assert_eq!(found[0].1, Some(3));
+    assert_eq!(found[0].2, Some(1));
+    assert!(found[0].3.contains("Download minimum"));
+    assert!(found[1].3.contains("not in network.json"));
+    assert!(found[2].3.contains("Unable to parse IPv4"));
+    assert!(found[3].3.contains("exceeds parent node Site1"));
+    assert_eq!(found[3].0, Warning);
+    assert!(found[4].3.contains("did you mean 100.64.1.0/24"));
+    assert_eq!(found[5].2, Some(3));
+    assert!(found[5].3.contains("Expected 13 fields"));
+  }
+
+  #[test]
+  fn burst_columns() {
+    // Rows may carry the burst columns without headings for them
+    let findings = run(
+      "1,One,1,D1,Site1,,100.64.0.1,,5,5,50,50,,100,100,10,60\n\
+       2,Two,2,D2,Site1,,100.64.0.2,,5,5,50,50,,,,,\n\
+       3,Three,3,D3,Site1,,100.64.0.3,,5,5,50,50,,100,,10,60\n\
+       4,Four,4,D4,Site1,,100.64.0.4,,5,5,50,50,,40,100,10,60\n",
+    );
+    let messages: Vec<_> = findings.iter().map(|f| f.message.as_str()).collect();
+    assert_eq!(findings.len(), 2, "{messages:#?}");
+    assert_eq!(findings[0].severity, Error);
+    assert!(messages[0].contains("Burst Upload Mbps"));
+    assert_eq!(findings[1].severity, Warning);
+    assert_eq!(findings[1].circuit_id.as_deref(), Some("4"));
+  }
+
+  #[test]
+  fn tag_columns() {
+    let header = HEADER.trim_end();
+    let validate_csv = |csv: String| {
+      validate(&ValidationInput {
+        shaped_devices_csv: &csv,
+        network_json: Some(NETWORK),
+        allowed_subnets: None,
+        ignored_subnets: None,
+      })
+    };
+    let findings = validate_csv(format!(
+      "{header},Burst Download Mbps,Burst Upload Mbps,Burst Seconds,\
+       Burst Refill Seconds,Plan,Tower\n\
+       1,One,1,D1,Site1,,100.64.0.1,,5,5,50,50,,100,100,10,60,Gold,\n\
+       2,Two,2,D2,Site1,,100.64.0.2,,5,5,50,50,,,,,,Silver,North\n\
+       3,Three,3,D3,Site1,,100.64.0.3,,5,5,50,50,,,,,\n"
+    ));
+    assert_eq!(findings.len(), 1, "{findings:#?}");
+    assert_eq!(findings[0].row, Some(3));
+    assert!(findings[0].message.contains("Expected 19 fields"));
+
+    let findings = validate_csv(format!("{header},Plan,PLAN\n"));
+    assert_eq!(findings.len(), 1, "{findings:#?}");
+    assert!(findings[0].message.contains("more than once"));
+  }
+
+  #[test]
+  fn circuits_must_be_consistent() {
+    let findings = run(
+      "1,One,1,D1,Site1,,100.64.0.1,,5,5,50,50,\n\
+       1,Uno,2,D2,Site1,,100.64.0.2,,5,5,40,50,\n",
+    );
+    assert_eq!(findings.len(), 2);
+    assert!(findings.iter().all(|f| f.severity == Warning && f.row == Some(2)));
+    assert_eq!(findings[0].circuit_id.as_deref(), Some("1"));
+  }
+
+  #[test]
+  fn overlapping_prefixes() {
+    let findings = run(
+      "1,One,1,D1,Site1,,100.64.0.0/30,,5,5,50,50,\n\
+       2,Two,2,D2,Site1,,100.64.0.1,,5,5,50,50,\n\
+       3,Three,3,D3,Site1,,100.64.0.0/30,,5,5,50,50,\n\
+       4,Four,4,D4,Site1,,\"100.64.5.5, 100.64.5.5\",,5,5,50,50,\n",
+    );
+    let by_row = |row| findings.iter().find(|f| f.row == Some(row)).unwrap();
+    assert_eq!(findings.len(), 3);
+    assert_eq!(by_row(2).severity, Warning);
+    assert!(by_row(2).message.contains("overlaps 100.64.0.0/30"));
+    assert_eq!(by_row(3).severity, Error);
+    assert!(by_row(3).message.contains("circuit 1 (row 1)"));
+    assert_eq!(by_row(4).severity, Warning);
+  }
+
+  #[test]
+  fn allowed_and_ignored_subnets() {
+    let findings = run(
+      "1,One,1,D1,Site1,,\"192.168.1.1, 100.64.99.4\",2001:db9::1,5,5,50,50,\n",
+    );
+    assert_eq!(findings.len(), 3);
+    assert!(findings[0].message.contains("outside allowedSubnets"));
+    assert!(findings[1].message.contains("inside ignoredSubnets"));
+    assert!(findings[2].message.contains("2001:db9::1 is outside"));
+  }
+}
@@ -0,0 +1,154 @@
+use super::{ValidationFinding, ValidationSeverity::*, ValidationSource};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+pub(crate) struct NetworkNode {
+  pub(crate) path: String,
+  pub(crate) download_mbps: u64,
+  pub(crate) upload_mbps: u64,
+}
+
+/// The parts of `network.json` that `ShapedDevices.csv` is checked
+/// against. Nodes are looked up by name, as `lqosd` does.
+pub(crate) struct NetworkSummary {
+  nodes: Vec<NetworkNode>,
+  by_name: HashMap<String, usize>,
+}
+
+impl NetworkSummary {
+  pub(crate) fn is_empty(&self) -> bool {
+    self.nodes.is_empty()
+  }
+
+  pub(crate) fn find(&self, name: &str) -> Option<&NetworkNode> {
+    self.by_name.get(name).map(|idx| &self.nodes[*idx])
+  }
+}
+
+fn finding(message: String) -> ValidationFinding {
+  ValidationFinding::new(Error, ValidationSource::NetworkJson, message)
+}
+
+pub(crate) fn check_network(
+  raw: &str,
+  findings: &mut Vec<ValidationFinding>,
+) -> NetworkSummary {
+  let mut summary = NetworkSummary { nodes: Vec::new(), by_name: HashMap::new() };
+  let json: Value = match serde_json::from_str(raw) {
+    Ok(json) => json,
+    Err(e) => {
+      let mut f = finding(format!("network.json is not valid JSON: {e}"));
+      f.line = Some(e.line() as u64);
+      findings.push(f);
+      return summary;
+    }
+  };
+  if let Value::Object(map) = &json {
+    walk(map, None, &mut summary, findings);
+  } else {
+    findings.push(finding("network.json must be a JSON object".to_string()));
+  }
+  summary
+}
+
+// Mirrors the way `NetworkJson::load` reads the tree: every object
+// is a node, except "children" which holds the children of the node
+// above it.
+fn walk(
+  map: &Map<String, Value>,
+  parent: Option<usize>,
+  summary: &mut NetworkSummary,
+  findings: &mut Vec<ValidationFinding>,
+) {
+  for (name, value) in map.iter() {
+    let Value::Object(node) = value else { continue };
+    if name == "children" {
+      walk(node, parent, summary, findings);
+      continue;
+    }
+    let path = match parent {
+      Some(idx) => format!("{}/{name}", summary.nodes[idx].path),
+      None => name.clone(),
+    };
+    let download_mbps = bandwidth(node, "downloadBandwidthMbps");
+    let upload_mbps = bandwidth(node, "uploadBandwidthMbps");
+
+    if let Some(existing) = summary.by_name.get(name) {
+      findings.push(
+        finding(format!(
+          "Node name {name} is used more than once (also at {}). Nodes are looked up by name, so circuits may attach to the wrong one.",
+          summary.nodes[*existing].path
+        ))
+        .for_node(&path),
+      );
+    }
+    if let Some(parent) = parent.map(|idx| &summary.nodes[idx]) {
+      for (direction, child, limit) in [
+        ("download", download_mbps, parent.download_mbps),
+        ("upload", upload_mbps, parent.upload_mbps),
+      ] {
+        if child > limit {
+          findings.push(
+            ValidationFinding::new(
+              Warning,
+              ValidationSource::NetworkJson,
+              format!(
+                "{direction} capacity of {child} Mbps exceeds its parent {}'s {limit} Mbps. It will be limited to the parent's capacity.",
+                parent.path
+              ),
+            )
+            .for_node(&path),
+          );
+        }
+      }
+    }
+
+    let idx = summary.nodes.len();
+    summary.nodes.push(NetworkNode { path, download_mbps, upload_mbps });
+    summary.by_name.entry(name.clone()).or_insert(idx);
+    walk(node, Some(idx), summary, findings);
+  }
+}
+
+fn bandwidth(node: &Map<String, Value>, key: &str) -> u64 {
+  node.get(key).and_then(Value::as_u64).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn finds_duplicate_names_and_oversized_children() {
+    let raw = r#"{
+      "Site1": {
+        "downloadBandwidthMbps": 100, "uploadBandwidthMbps": 100,
+        "children": {
+          "AP1": { "downloadBandwidthMbps": 200, "uploadBandwidthMbps": 50 }
+        }
+      },
+      "Site2": {
+        "downloadBandwidthMbps": 100, "uploadBandwidthMbps": 100,
+        "children": {
+          "AP1": { "downloadBandwidthMbps": 50, "uploadBandwidthMbps": 50 }
+        }
+      }
+    }"#;
+    let mut findings = Vec::new();
+    let summary = check_network(raw, &mut findings);
+    assert_eq!(findings.len(), 2);
+    assert_eq!(findings[0].severity, Warning);
+    assert_eq!(findings[0].node.as_deref(), Some("Site1/AP1"));
+    assert_eq!(findings[1].severity, Error);
+    assert_eq!(findings[1].node.as_deref(), Some("Site2/AP1"));
+    assert_eq!(summary.find("AP1").unwrap().path, "Site1/AP1");
+  }
+
+  #[test]
+  fn reports_json_line() {
+    let mut findings = Vec::new();
+    check_network("{\n\"Site1\": {,\n}", &mut findings);
+    assert_eq!(findings.len(), 1);
+    assert_eq!(findings[0].line, Some(2));
+  }
+}
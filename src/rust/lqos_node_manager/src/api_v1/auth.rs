@@ -0,0 +1,124 @@
+use super::ApiError;
+use crate::{auth_guard::with_web_users, scope::Scope};
+use lqos_config::{ApiScope, UserRole};
+use rocket::{
+  http::Status,
+  request::{FromRequest, Outcome},
+  Request,
+};
+
+/// Who is calling the API, and what they may do. Automation sends an
+/// API key as `Authorization: Bearer <key>`; the web UI's sign-in
+/// cookie is also accepted, with the rights of the user's role.
+#[derive(Debug, Clone)]
+pub struct ApiAuth {
+  pub username: String,
+  pub scopes: Vec<ApiScope>,
+  /// Did the caller use an API key, rather than signing in?
+  pub api_key: bool,
+  /// The part of the network the user (and so their keys) may see
+  pub scope: Scope,
+}
+
+impl ApiAuth {
+  /// Fails unless the caller has the scope
+  pub fn require(&self, scope: ApiScope) -> Result<(), ApiError> {
+    if self.scopes.contains(&scope) {
+      Ok(())
+    } else {
+      Err(ApiError::forbidden(format!("This needs the {scope} scope")))
+    }
+  }
+
+  /// Fails unless the circuit is in the caller's scope. Circuits that
+  /// don't exist are outside every scope, so the answer doesn't show
+  /// whether another tenant's circuit does.
+  pub fn require_circuit(&self, circuit_id: &str) -> Result<(), ApiError> {
+    if self.scope.allows_circuit(circuit_id) {
+      Ok(())
+    } else {
+      Err(ApiError::forbidden(format!(
+        "Circuit {circuit_id} isn't in your scope"
+      )))
+    }
+  }
+
+  /// Fails if the caller is limited to part of the network, for
+  /// requests that see or change all of it
+  pub fn require_unscoped(&self) -> Result<(), ApiError> {
+    if self.scope.is_restricted() {
+      Err(ApiError::forbidden("This isn't available to scoped users"))
+    } else {
+      Ok(())
+    }
+  }
+
+  /// Fails unless the caller signed in, as needed to manage API keys
+  pub fn require_session(&self) -> Result<(), ApiError> {
+    if self.api_key {
+      Err(ApiError::forbidden("API keys can't be managed with an API key"))
+    } else {
+      Ok(())
+    }
+  }
+
+  fn from_api_key(key: &str) -> Result<Self, ApiError> {
+    let info =
+      with_web_users(|users| users.authenticate_api_key(key).ok())
+        .flatten()
+        .ok_or_else(|| ApiError::unauthorized("Invalid API key"))?;
+    Ok(Self {
+      scope: Scope::for_user(&info.username),
+      username: info.username,
+      scopes: info.scopes,
+      api_key: true,
+    })
+  }
+
+  fn from_session(token: Option<&str>) -> Result<Self, ApiError> {
+    let session = with_web_users(|users| match token {
+      Some(token) => users.session_user(token).ok(),
+      None if users.do_we_allow_anonymous() => {
+        Some(("Anonymous".to_string(), UserRole::ReadOnly))
+      }
+      None => None,
+    })
+    .flatten();
+    match session {
+      Some((username, role)) => Ok(Self {
+        scope: Scope::for_user(&username),
+        username,
+        scopes: ApiScope::allowed_for(role).to_vec(),
+        api_key: false,
+      }),
+      None => Err(ApiError::unauthorized(
+        "Send an API key as Authorization: Bearer <key>, or sign in",
+      )),
+    }
+  }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ApiAuth {
+  type Error = ApiError;
+
+  async fn from_request(
+    request: &'r Request<'_>,
+  ) -> Outcome<Self, Self::Error> {
+    let result = match request.headers().get_one("Authorization") {
+      Some(header) => match header.strip_prefix("Bearer ") {
+        Some(key) => Self::from_api_key(key.trim()),
+        None => Err(ApiError::unauthorized(
+          "The Authorization header must be Bearer <key>",
+        )),
+      },
+      None => Self::from_session(
+        request.cookies().get("User-Token").map(|c| c.value()),
+      ),
+    };
+    match result {
+      Ok(auth) => Outcome::Success(auth),
+      Err(e) => Outcome::Error((Status::Unauthorized, e)),
+    }
+  }
+}
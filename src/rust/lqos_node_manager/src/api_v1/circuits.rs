@@ -0,0 +1,159 @@
+use super::{lqosd, ok, ApiAuth, ApiError, ApiResult, Message};
+use crate::tracker::SHAPED_DEVICES;
+use lqos_bus::{BusRequest, BusResponse};
+use lqos_config::{ApiScope, ShapedDevice};
+use rocket::{
+  http::Status,
+  serde::{json::Json, Serialize},
+};
+use std::collections::HashMap;
+
+/// A circuit and its devices, from `ShapedDevices.csv`
+#[derive(Serialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct Circuit {
+  pub circuit_id: String,
+  pub circuit_name: String,
+  pub parent_node: String,
+  pub devices: Vec<ShapedDevice>,
+}
+
+/// Groups devices into circuits, in the order they first appear
+fn circuits_from(devices: &[ShapedDevice]) -> Vec<Circuit> {
+  let mut circuits: Vec<Circuit> = Vec::new();
+  let mut index: HashMap<&str, usize> = HashMap::new();
+  for device in devices.iter() {
+    match index.get(device.circuit_id.as_str()) {
+      Some(&i) => circuits[i].devices.push(device.clone()),
+      None => {
+        index.insert(&device.circuit_id, circuits.len());
+        circuits.push(Circuit {
+          circuit_id: device.circuit_id.clone(),
+          circuit_name: device.circuit_name.clone(),
+          parent_node: device.parent_node.clone(),
+          devices: vec![device.clone()],
+        });
+      }
+    }
+  }
+  circuits
+}
+
+fn find_circuit(circuit_id: &str) -> Result<Option<Circuit>, ApiError> {
+  let devices: Vec<ShapedDevice> = SHAPED_DEVICES
+    .read()?
+    .devices
+    .iter()
+    .filter(|d| d.circuit_id == circuit_id)
+    .cloned()
+    .collect();
+  Ok(circuits_from(&devices).pop())
+}
+
+#[get("/api/v1/circuits")]
+pub fn list_circuits(
+  auth: Result<ApiAuth, ApiError>,
+) -> ApiResult<Vec<Circuit>> {
+  let auth = auth?;
+  auth.require(ApiScope::Read)?;
+  let devices: Vec<ShapedDevice> = SHAPED_DEVICES
+    .read()?
+    .devices
+    .iter()
+    .filter(|d| auth.scope.allows_circuit(&d.circuit_id))
+    .cloned()
+    .collect();
+  ok(circuits_from(&devices))
+}
+
+#[get("/api/v1/circuits/<circuit_id>")]
+pub fn get_circuit(
+  auth: Result<ApiAuth, ApiError>,
+  circuit_id: String,
+) -> ApiResult<Circuit> {
+  let auth = auth?;
+  auth.require(ApiScope::Read)?;
+  auth.require_circuit(&circuit_id)?;
+  match find_circuit(&circuit_id)? {
+    Some(circuit) => ok(circuit),
+    None => Err(ApiError::not_found(format!("No circuit {circuit_id}"))),
+  }
+}
+
+/// Adds or replaces a circuit, shaping it straight away. Devices may
+/// leave out the circuit ID. Scoped users may only replace circuits in
+/// their scope, and only with devices that would be in it.
+#[put("/api/v1/circuits/<circuit_id>", data = "<devices>")]
+pub async fn put_circuit(
+  auth: Result<ApiAuth, ApiError>,
+  circuit_id: String,
+  devices: Json<Vec<ShapedDevice>>,
+) -> ApiResult<Circuit> {
+  let auth = auth?;
+  auth.require(ApiScope::ShapingWrite)?;
+  if find_circuit(&circuit_id)?.is_some() {
+    auth.require_circuit(&circuit_id)?;
+  }
+  let mut devices = devices.into_inner();
+  if devices.is_empty() {
+    return Err(ApiError::bad_request("A circuit needs at least one device"));
+  }
+  for device in devices.iter_mut() {
+    if device.circuit_id.is_empty() {
+      device.circuit_id.clone_from(&circuit_id);
+    } else if device.circuit_id != circuit_id {
+      return Err(ApiError::bad_request(format!(
+        "Device {} is in circuit {}, not {circuit_id}",
+        device.device_id, device.circuit_id
+      )));
+    }
+    if !auth.scope.allows_device(device) {
+      return Err(ApiError::forbidden(format!(
+        "Device {} would be outside your scope",
+        device.device_id
+      )));
+    }
+  }
+  let request = BusRequest::UpsertCircuit {
+    devices: devices.clone(),
+    author: auth.username.clone(),
+  };
+  match lqosd(request).await? {
+    BusResponse::Ack => ok(circuits_from(&devices).remove(0)),
+    BusResponse::Fail(e) => Err(ApiError::bad_request(e)),
+    _ => Err(ApiError::unexpected_response()),
+  }
+}
+
+#[delete("/api/v1/circuits/<circuit_id>")]
+pub async fn delete_circuit(
+  auth: Result<ApiAuth, ApiError>,
+  circuit_id: String,
+) -> Result<Status, ApiError> {
+  let auth = auth?;
+  auth.require(ApiScope::ShapingWrite)?;
+  auth.require_circuit(&circuit_id)?;
+  if find_circuit(&circuit_id)?.is_none() {
+    return Err(ApiError::not_found(format!("No circuit {circuit_id}")));
+  }
+  let request =
+    BusRequest::RemoveCircuit { circuit_id, author: auth.username.clone() };
+  match lqosd(request).await? {
+    BusResponse::Ack => Ok(Status::NoContent),
+    BusResponse::Fail(e) => Err(ApiError::bad_request(e)),
+    _ => Err(ApiError::unexpected_response()),
+  }
+}
+
+/// Rebuilds the queues from `ShapedDevices.csv` and `network.json`
+#[post("/api/v1/reload")]
+pub async fn reload(auth: Result<ApiAuth, ApiError>) -> ApiResult<Message> {
+  let auth = auth?;
+  auth.require(ApiScope::ShapingWrite)?;
+  auth.require_unscoped()?;
+  match lqosd(BusRequest::ReloadLibreQoS).await? {
+    BusResponse::ReloadLibreQoS(message) => ok(Message { message }),
+    BusResponse::Fail(e) => Err(ApiError::internal(e)),
+    _ => Err(ApiError::unexpected_response()),
+  }
+}
@@ -0,0 +1,77 @@
+use super::{lqosd, ok, ApiAuth, ApiError, ApiResult, Message};
+use lqos_bus::{BusRequest, BusResponse};
+use lqos_config::{ApiScope, EtcLqos, LibreQoSConfig, RevisionInfo};
+use rocket::serde::json::Json;
+
+#[get("/api/v1/config/lqosd")]
+pub fn lqosd_config(auth: Result<ApiAuth, ApiError>) -> ApiResult<EtcLqos> {
+  let auth = auth?;
+  auth.require(ApiScope::Read)?;
+  auth.require_unscoped()?;
+  EtcLqos::load()
+    .map_err(|e| ApiError::internal(e.to_string()))
+    .and_then(ok)
+}
+
+#[get("/api/v1/config/python")]
+pub fn python_config(
+  auth: Result<ApiAuth, ApiError>,
+) -> ApiResult<LibreQoSConfig> {
+  let auth = auth?;
+  auth.require(ApiScope::Read)?;
+  auth.require_unscoped()?;
+  LibreQoSConfig::load()
+    .map_err(|e| ApiError::internal(e.to_string()))
+    .and_then(ok)
+}
+
+/// Saves the settings, recording a configuration revision
+#[put("/api/v1/config/python", data = "<config>")]
+pub fn put_python_config(
+  auth: Result<ApiAuth, ApiError>,
+  config: Json<LibreQoSConfig>,
+) -> ApiResult<Message> {
+  let auth = auth?;
+  auth.require(ApiScope::ConfigWrite)?;
+  auth.require_unscoped()?;
+  config.save().map_err(|e| ApiError::internal(e.to_string()))?;
+  if let Err(e) = lqos_config::record_config_revision(
+    &auth.username,
+    "Updated settings through the API",
+  ) {
+    warn!("Unable to record configuration revision: {e}");
+  }
+  ok(Message { message: "Saved".to_string() })
+}
+
+#[get("/api/v1/config/revisions")]
+pub async fn revisions(
+  auth: Result<ApiAuth, ApiError>,
+) -> ApiResult<Vec<RevisionInfo>> {
+  let auth = auth?;
+  auth.require(ApiScope::Read)?;
+  auth.require_unscoped()?;
+  match lqosd(BusRequest::ListConfigRevisions).await? {
+    BusResponse::ConfigRevisions(revisions) => ok(revisions),
+    BusResponse::Fail(e) => Err(ApiError::internal(e)),
+    _ => Err(ApiError::unexpected_response()),
+  }
+}
+
+/// Puts a revision's files back in place and reloads LibreQoS
+#[post("/api/v1/config/revisions/<id>/rollback")]
+pub async fn rollback(
+  auth: Result<ApiAuth, ApiError>,
+  id: String,
+) -> ApiResult<RevisionInfo> {
+  let auth = auth?;
+  auth.require(ApiScope::ConfigWrite)?;
+  auth.require_unscoped()?;
+  let request =
+    BusRequest::RollbackConfigRevision { id, author: auth.username };
+  match lqosd(request).await? {
+    BusResponse::ConfigRevision(revision) => ok(revision),
+    BusResponse::Fail(e) => Err(ApiError::bad_request(e)),
+    _ => Err(ApiError::unexpected_response()),
+  }
+}
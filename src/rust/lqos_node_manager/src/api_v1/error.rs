@@ -0,0 +1,90 @@
+use rocket::{
+  http::Status,
+  response::{self, Responder},
+  serde::{json::Json, Serialize},
+  Request,
+};
+use std::sync::PoisonError;
+
+/// An API error. Every error is returned as the same JSON document,
+/// e.g. `{"error": {"status": 404, "message": "No such circuit"}}`.
+#[derive(Debug)]
+pub struct ApiError {
+  status: Status,
+  message: String,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct ErrorBody<'a> {
+  error: ErrorDetail<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct ErrorDetail<'a> {
+  status: u16,
+  message: &'a str,
+}
+
+impl ApiError {
+  pub fn new(status: Status, message: impl Into<String>) -> Self {
+    Self { status, message: message.into() }
+  }
+
+  pub fn unauthorized(message: impl Into<String>) -> Self {
+    Self::new(Status::Unauthorized, message)
+  }
+
+  pub fn forbidden(message: impl Into<String>) -> Self {
+    Self::new(Status::Forbidden, message)
+  }
+
+  pub fn not_found(message: impl Into<String>) -> Self {
+    Self::new(Status::NotFound, message)
+  }
+
+  pub fn bad_request(message: impl Into<String>) -> Self {
+    Self::new(Status::BadRequest, message)
+  }
+
+  pub fn internal(message: impl Into<String>) -> Self {
+    Self::new(Status::InternalServerError, message)
+  }
+
+  /// `lqosd` couldn't be reached
+  pub fn bus(e: impl std::fmt::Display) -> Self {
+    Self::new(Status::ServiceUnavailable, format!("lqosd: {e}"))
+  }
+
+  /// `lqosd` replied with something other than what was asked for
+  pub fn unexpected_response() -> Self {
+    Self::internal("Unexpected response from lqosd")
+  }
+}
+
+/// A thread panicked while holding a lock the request needs
+impl<T> From<PoisonError<T>> for ApiError {
+  fn from(_: PoisonError<T>) -> Self {
+    Self::internal("Shared state is unavailable after an earlier failure")
+  }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+  fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+    let body = ErrorBody {
+      error: ErrorDetail { status: self.status.code, message: &self.message },
+    };
+    let mut response = Json(body).respond_to(request)?;
+    response.set_status(self.status);
+    Ok(response)
+  }
+}
+
+/// Errors Rocket raises itself under `/api/v1`, such as unknown routes
+/// and malformed bodies, in the same format.
+#[catch(default)]
+pub fn default_catcher(status: Status, _request: &Request) -> ApiError {
+  let message = status.reason().unwrap_or("Unknown error");
+  ApiError::new(status, message)
+}
@@ -0,0 +1,83 @@
+use super::{ok, ApiAuth, ApiError, ApiResult};
+use crate::auth_guard::with_web_users;
+use lqos_config::{ApiKeyInfo, ApiScope};
+use rocket::{
+  http::Status,
+  serde::{json::Json, Deserialize, Serialize},
+};
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct NewApiKey {
+  pub name: String,
+  pub scopes: Vec<ApiScope>,
+}
+
+/// A new key. This is the only time the key itself is available.
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CreatedApiKey {
+  #[serde(flatten)]
+  pub info: ApiKeyInfo,
+  pub key: String,
+}
+
+fn no_users() -> ApiError {
+  ApiError::internal("Unable to load lqusers.toml")
+}
+
+/// The signed-in user's API keys
+#[get("/api/v1/keys")]
+pub fn list_keys(
+  auth: Result<ApiAuth, ApiError>,
+) -> ApiResult<Vec<ApiKeyInfo>> {
+  let auth = auth?;
+  auth.require_session()?;
+  with_web_users(|users| users.list_api_keys(Some(&auth.username)))
+    .ok_or_else(no_users)
+    .and_then(ok)
+}
+
+/// Creates an API key for the signed-in user
+#[post("/api/v1/keys", data = "<request>")]
+pub fn create_key(
+  auth: Result<ApiAuth, ApiError>,
+  request: Json<NewApiKey>,
+) -> ApiResult<CreatedApiKey> {
+  let auth = auth?;
+  auth.require_session()?;
+  for scope in request.scopes.iter() {
+    auth.require(*scope)?;
+  }
+  if request.scopes.is_empty() {
+    return Err(ApiError::bad_request("A key needs at least one scope"));
+  }
+  let created = with_web_users(|users| {
+    users.create_api_key(&auth.username, &request.name, &request.scopes)
+  })
+  .ok_or_else(no_users)?;
+  match created {
+    Ok((info, key)) => ok(CreatedApiKey { info, key }),
+    Err(e) => Err(ApiError::bad_request(e.to_string())),
+  }
+}
+
+/// Revokes one of the signed-in user's API keys
+#[delete("/api/v1/keys/<id>")]
+pub fn revoke_key(
+  auth: Result<ApiAuth, ApiError>,
+  id: String,
+) -> Result<Status, ApiError> {
+  let auth = auth?;
+  auth.require_session()?;
+  let revoked = with_web_users(|users| {
+    if users.list_api_keys(Some(&auth.username)).iter().all(|k| k.id != id) {
+      return Err(ApiError::not_found(format!("No API key {id}")));
+    }
+    users
+      .revoke_api_key(&auth.username, &id)
+      .map_err(|e| ApiError::internal(e.to_string()))
+  })
+  .ok_or_else(no_users)?;
+  revoked.map(|_| Status::NoContent)
+}
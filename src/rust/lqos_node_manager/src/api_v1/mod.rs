@@ -0,0 +1,70 @@
+//! The versioned REST API, for automation. Every route is under
+//! `/api/v1`, authenticates with an API key (or the web UI sign-in),
+//! checks the caller's scopes, and answers in JSON, including errors.
+//! `/api/v1/openapi.json` describes it.
+mod auth;
+mod circuits;
+mod config;
+mod error;
+mod keys;
+mod network;
+mod openapi;
+use crate::cache_control::NoCache;
+pub use auth::ApiAuth;
+pub use error::ApiError;
+use lqos_bus::{bus_request, BusRequest, BusResponse};
+use rocket::{
+  serde::{json::Json, Serialize},
+  Catcher, Route,
+};
+
+/// A successful response is the value as JSON
+pub type ApiResult<T> = Result<NoCache<Json<T>>, ApiError>;
+
+fn ok<T: Serialize>(value: T) -> ApiResult<T> {
+  Ok(NoCache::new(Json(value)))
+}
+
+/// A plain message, for requests that don't return data
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Message {
+  pub message: String,
+}
+
+/// Sends a single request to `lqosd`
+async fn lqosd(request: BusRequest) -> Result<BusResponse, ApiError> {
+  bus_request(vec![request])
+    .await
+    .map_err(ApiError::bus)?
+    .into_iter()
+    .next()
+    .ok_or_else(ApiError::unexpected_response)
+}
+
+pub fn routes() -> Vec<Route> {
+  routes![
+    circuits::list_circuits,
+    circuits::get_circuit,
+    circuits::put_circuit,
+    circuits::delete_circuit,
+    circuits::reload,
+    network::tree,
+    network::unknown_devices,
+    network::queue,
+    network::flows,
+    config::lqosd_config,
+    config::python_config,
+    config::put_python_config,
+    config::revisions,
+    config::rollback,
+    keys::list_keys,
+    keys::create_key,
+    keys::revoke_key,
+    openapi::openapi,
+  ]
+}
+
+pub fn catchers() -> Vec<Catcher> {
+  catchers![error::default_catcher]
+}
@@ -0,0 +1,98 @@
+use super::{lqosd, ok, ApiAuth, ApiError, ApiResult};
+use crate::network_tree::scoped_layer;
+use lqos_bus::{
+  BusRequest, BusResponse, FlowTransport, IpStats, QueueStoreTransit,
+};
+use lqos_config::{ApiScope, NetworkJsonTransport};
+use rocket::serde::Serialize;
+use std::net::IpAddr;
+
+/// A `network.json` node, with its current throughput
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TreeNode {
+  /// The node's position in the tree, for requesting its children
+  pub index: usize,
+  #[serde(flatten)]
+  pub node: NetworkJsonTransport,
+}
+
+/// A node and its immediate children. Without `parent`, the root.
+/// Scoped users only see nodes in their scope; for them, the root's
+/// children are the tops of their part of the tree.
+#[get("/api/v1/tree?<parent>")]
+pub async fn tree(
+  auth: Result<ApiAuth, ApiError>,
+  parent: Option<usize>,
+) -> ApiResult<Vec<TreeNode>> {
+  let auth = auth?;
+  auth.require(ApiScope::Read)?;
+  let parent = parent.unwrap_or(0);
+  match lqosd(BusRequest::GetNetworkMap { parent }).await? {
+    BusResponse::NetworkMap(nodes) => {
+      let nodes = scoped_layer(nodes, &auth.scope).await;
+      if nodes.is_empty() {
+        return Err(ApiError::forbidden(format!(
+          "Node {parent} isn't in your scope"
+        )));
+      }
+      ok(
+        nodes
+          .into_iter()
+          .map(|(index, node)| TreeNode { index, node })
+          .collect(),
+      )
+    }
+    BusResponse::Fail(e) => Err(ApiError::not_found(e)),
+    _ => Err(ApiError::unexpected_response()),
+  }
+}
+
+/// Addresses passing traffic that aren't in `ShapedDevices.csv`. None
+/// are in a scoped user's scope.
+#[get("/api/v1/unknown_devices")]
+pub async fn unknown_devices(
+  auth: Result<ApiAuth, ApiError>,
+) -> ApiResult<Vec<IpStats>> {
+  let auth = auth?;
+  auth.require(ApiScope::Read)?;
+  ok(crate::unknown_devices::unknown_devices(&auth.scope).await)
+}
+
+/// A circuit's queue statistics
+#[get("/api/v1/queues/<circuit_id>")]
+pub async fn queue(
+  auth: Result<ApiAuth, ApiError>,
+  circuit_id: String,
+) -> ApiResult<QueueStoreTransit> {
+  let auth = auth?;
+  auth.require(ApiScope::Read)?;
+  auth.require_circuit(&circuit_id)?;
+  match lqosd(BusRequest::GetRawQueueData(circuit_id.clone())).await? {
+    BusResponse::RawQueueData(Some(queue)) => ok(*queue),
+    BusResponse::RawQueueData(None) => Err(ApiError::not_found(format!(
+      "No queue data for circuit {circuit_id}"
+    ))),
+    _ => Err(ApiError::unexpected_response()),
+  }
+}
+
+/// The flows an address is taking part in
+#[get("/api/v1/flows/<ip>")]
+pub async fn flows(
+  auth: Result<ApiAuth, ApiError>,
+  ip: String,
+) -> ApiResult<Vec<(FlowTransport, Option<FlowTransport>)>> {
+  let auth = auth?;
+  auth.require(ApiScope::Read)?;
+  if ip.parse::<IpAddr>().is_err() {
+    return Err(ApiError::bad_request(format!("{ip} isn't an IP address")));
+  }
+  if !auth.scope.allows_ip(&ip) {
+    return Err(ApiError::forbidden(format!("{ip} isn't in your scope")));
+  }
+  match lqosd(BusRequest::GetFlowStats(ip)).await? {
+    BusResponse::FlowData(flows) => ok(flows),
+    _ => Err(ApiError::unexpected_response()),
+  }
+}
@@ -0,0 +1,366 @@
+use crate::cache_control::NoCache;
+use lqos_config::ApiScope;
+use rocket::serde::json::{json, Json, Value};
+
+/// One API operation, as described in the OpenAPI document
+struct Operation {
+  method: &'static str,
+  path: &'static str,
+  tag: &'static str,
+  summary: &'static str,
+  /// The scope needed, or `None` for operations that need a web UI
+  /// sign-in rather than an API key
+  scope: Option<ApiScope>,
+  /// The request body's schema
+  request: Option<&'static str>,
+  /// The response body's schema, or `None` for 204 No Content
+  response: Option<&'static str>,
+  /// Whether the response is a list of `response`
+  list: bool,
+  /// Optional integer query parameters
+  query: &'static [&'static str],
+}
+
+const fn op(
+  method: &'static str,
+  path: &'static str,
+  tag: &'static str,
+  summary: &'static str,
+  scope: Option<ApiScope>,
+) -> Operation {
+  Operation {
+    method,
+    path,
+    tag,
+    summary,
+    scope,
+    request: None,
+    response: Some("Value"),
+    list: false,
+    query: &[],
+  }
+}
+
+const READ: Option<ApiScope> = Some(ApiScope::Read);
+const SHAPING: Option<ApiScope> = Some(ApiScope::ShapingWrite);
+const CONFIG: Option<ApiScope> = Some(ApiScope::ConfigWrite);
+
+const OPERATIONS: &[Operation] = &[
+  Operation {
+    response: Some("Circuit"),
+    list: true,
+    ..op("get", "/circuits", "Circuits", "List circuits", READ)
+  },
+  Operation {
+    response: Some("Circuit"),
+    ..op("get", "/circuits/{circuit_id}", "Circuits", "Get a circuit", READ)
+  },
+  Operation {
+    request: Some("ShapedDeviceList"),
+    response: Some("Circuit"),
+    ..op(
+      "put",
+      "/circuits/{circuit_id}",
+      "Circuits",
+      "Add or replace a circuit, shaping it immediately",
+      SHAPING,
+    )
+  },
+  Operation {
+    response: None,
+    ..op(
+      "delete",
+      "/circuits/{circuit_id}",
+      "Circuits",
+      "Remove a circuit, shaping the change immediately",
+      SHAPING,
+    )
+  },
+  Operation {
+    response: Some("Message"),
+    ..op("post", "/reload", "Circuits", "Rebuild the queues", SHAPING)
+  },
+  Operation {
+    list: true,
+    query: &["parent"],
+    ..op("get", "/tree", "Network", "A network.json node and children", READ)
+  },
+  Operation {
+    list: true,
+    ..op(
+      "get",
+      "/unknown_devices",
+      "Network",
+      "Addresses passing traffic that aren't shaped",
+      READ,
+    )
+  },
+  op("get", "/queues/{circuit_id}", "Network", "Queue statistics", READ),
+  Operation {
+    list: true,
+    ..op("get", "/flows/{ip}", "Network", "An address's flows", READ)
+  },
+  op("get", "/config/lqosd", "Configuration", "The lqosd settings", READ),
+  op("get", "/config/python", "Configuration", "The ispConfig settings", READ),
+  Operation {
+    request: Some("Value"),
+    response: Some("Message"),
+    ..op(
+      "put",
+      "/config/python",
+      "Configuration",
+      "Save the ispConfig settings",
+      CONFIG,
+    )
+  },
+  Operation {
+    list: true,
+    ..op(
+      "get",
+      "/config/revisions",
+      "Configuration",
+      "Recorded configuration revisions",
+      READ,
+    )
+  },
+  op(
+    "post",
+    "/config/revisions/{id}/rollback",
+    "Configuration",
+    "Restore a configuration revision",
+    CONFIG,
+  ),
+  Operation {
+    response: Some("ApiKeyInfo"),
+    list: true,
+    ..op("get", "/keys", "API keys", "Your API keys", None)
+  },
+  Operation {
+    request: Some("NewApiKey"),
+    response: Some("CreatedApiKey"),
+    ..op("post", "/keys", "API keys", "Create an API key", None)
+  },
+  Operation {
+    response: None,
+    ..op("delete", "/keys/{id}", "API keys", "Revoke an API key", None)
+  },
+];
+
+fn schema_ref(name: &str) -> Value {
+  if name == "Value" {
+    json!({ "type": "object" })
+  } else {
+    json!({ "$ref": format!("#/components/schemas/{name}") })
+  }
+}
+
+fn json_content(schema: Value) -> Value {
+  json!({ "application/json": { "schema": schema } })
+}
+
+fn path_parameters(path: &str) -> Vec<&str> {
+  path
+    .split('/')
+    .filter_map(|part| part.strip_prefix('{')?.strip_suffix('}'))
+    .collect()
+}
+
+fn describe(operation: &Operation) -> Value {
+  let mut parameters: Vec<Value> = path_parameters(operation.path)
+    .into_iter()
+    .map(|name| {
+      json!({
+        "name": name, "in": "path", "required": true,
+        "schema": { "type": "string" }
+      })
+    })
+    .collect();
+  parameters.extend(operation.query.iter().map(|name| {
+    json!({
+      "name": name, "in": "query", "required": false,
+      "schema": { "type": "integer", "minimum": 0 }
+    })
+  }));
+
+  let success = match operation.response {
+    Some(name) => {
+      let schema = if operation.list {
+        json!({ "type": "array", "items": schema_ref(name) })
+      } else {
+        schema_ref(name)
+      };
+      json!({ "200": { "description": "OK", "content": json_content(schema) } })
+    }
+    None => json!({ "204": { "description": "Done" } }),
+  };
+  let mut responses = success;
+  responses["default"] = json!({
+    "description": "An error",
+    "content": json_content(schema_ref("ApiError")),
+  });
+
+  let mut description = json!({
+    "tags": [operation.tag],
+    "summary": operation.summary,
+    "parameters": parameters,
+    "responses": responses,
+  });
+  match operation.scope {
+    Some(scope) => {
+      description["security"] = json!([{ "apiKey": [] }]);
+      description["x-required-scope"] = json!(scope.to_string());
+    }
+    None => {
+      description["description"] =
+        json!("Needs a web UI sign-in; API keys can't manage keys.");
+    }
+  }
+  if let Some(name) = operation.request {
+    description["requestBody"] =
+      json!({ "required": true, "content": json_content(schema_ref(name)) });
+  }
+  description
+}
+
+fn schemas() -> Value {
+  let scope = json!({
+    "type": "string",
+    "enum": ApiScope::ALL.iter().map(|s| s.to_string()).collect::<Vec<_>>(),
+  });
+  let cidr = json!({
+    "type": "array",
+    "items": {
+      "type": "array", "minItems": 2, "maxItems": 2,
+      "items": { "oneOf": [{ "type": "string" }, { "type": "integer" }] }
+    },
+    "description": "Address and prefix length pairs, e.g. [[\"10.0.0.1\", 32]]",
+  });
+  let key_info = json!({
+    "type": "object",
+    "properties": {
+      "id": { "type": "string" },
+      "name": { "type": "string" },
+      "username": { "type": "string" },
+      "scopes": { "type": "array", "items": scope },
+      "created": { "type": "integer", "description": "Unix time" },
+    },
+  });
+  let mut created_key = key_info.clone();
+  created_key["properties"]["key"] = json!({
+    "type": "string",
+    "description": "The key itself, which is only shown once",
+  });
+  json!({
+    "ShapedDevice": {
+      "type": "object",
+      "required": [
+        "device_id", "device_name", "parent_node", "mac", "ipv4", "ipv6",
+        "download_min_mbps", "upload_min_mbps", "download_max_mbps",
+        "upload_max_mbps", "comment", "circuit_id", "circuit_name",
+      ],
+      "properties": {
+        "circuit_id": {
+          "type": "string",
+          "description": "May be empty, in which case the path's is used",
+        },
+        "circuit_name": { "type": "string" },
+        "device_id": { "type": "string" },
+        "device_name": { "type": "string" },
+        "parent_node": { "type": "string" },
+        "mac": { "type": "string" },
+        "ipv4": cidr,
+        "ipv6": cidr,
+        "download_min_mbps": { "type": "integer" },
+        "upload_min_mbps": { "type": "integer" },
+        "download_max_mbps": { "type": "integer" },
+        "upload_max_mbps": { "type": "integer" },
+        "comment": { "type": "string" },
+        "burst": { "type": "object", "nullable": true },
+        "tags": {
+          "type": "object",
+          "additionalProperties": { "type": "string" },
+        },
+      },
+    },
+    "ShapedDeviceList": {
+      "type": "array",
+      "minItems": 1,
+      "items": schema_ref("ShapedDevice"),
+    },
+    "Circuit": {
+      "type": "object",
+      "properties": {
+        "circuit_id": { "type": "string" },
+        "circuit_name": { "type": "string" },
+        "parent_node": { "type": "string" },
+        "devices": { "type": "array", "items": schema_ref("ShapedDevice") },
+      },
+    },
+    "Message": {
+      "type": "object",
+      "properties": { "message": { "type": "string" } },
+    },
+    "ApiError": {
+      "type": "object",
+      "properties": {
+        "error": {
+          "type": "object",
+          "properties": {
+            "status": { "type": "integer" },
+            "message": { "type": "string" },
+          },
+        },
+      },
+    },
+    "ApiKeyInfo": key_info,
+    "NewApiKey": {
+      "type": "object",
+      "required": ["name", "scopes"],
+      "properties": {
+        "name": { "type": "string" },
+        "scopes": { "type": "array", "items": scope, "minItems": 1 },
+      },
+    },
+    "CreatedApiKey": created_key,
+  })
+}
+
+/// The OpenAPI document describing `/api/v1`
+pub fn document() -> Value {
+  let mut paths = json!({});
+  for operation in OPERATIONS.iter() {
+    paths[operation.path][operation.method] = describe(operation);
+  }
+  json!({
+    "openapi": "3.0.3",
+    "info": {
+      "title": "LibreQoS Node Manager",
+      "version": env!("CARGO_PKG_VERSION"),
+      "description": "Send an API key, created with `lqusers add-key` or \
+        `POST /keys`, as `Authorization: Bearer <key>`. Each operation \
+        lists the scope it needs as `x-required-scope`. Users limited \
+        to part of the network by `lqusers set-scope`, and their keys, \
+        only see circuits and nodes in it; anything else, and the \
+        configuration, is refused with a 403.",
+    },
+    "servers": [{ "url": "/api/v1" }],
+    "tags": [
+      { "name": "Circuits" },
+      { "name": "Network" },
+      { "name": "Configuration" },
+      { "name": "API keys" },
+    ],
+    "paths": paths,
+    "components": {
+      "securitySchemes": {
+        "apiKey": { "type": "http", "scheme": "bearer" },
+      },
+      "schemas": schemas(),
+    },
+  })
+}
+
+#[get("/api/v1/openapi.json")]
+pub fn openapi() -> NoCache<Json<Value>> {
+  NoCache::new(Json(document()))
+}
@@ -1,7 +1,9 @@
-use std::sync::Mutex;
+use std::{net::IpAddr, sync::Mutex, time::Duration};
 
 use anyhow::Error;
-use lqos_config::{UserRole, WebUsers};
+use lqos_config::{
+  AuthenticationError, BackendCheck, LoginThrottle, UserRole, WebUsers,
+};
 use once_cell::sync::Lazy;
 use rocket::serde::{json::Json, Deserialize, Serialize};
 use rocket::{
@@ -13,6 +15,27 @@ use rocket::{
 static WEB_USERS: Lazy<Mutex<Option<WebUsers>>> =
   Lazy::new(|| Mutex::new(None));
 
+static LOGIN_THROTTLE: Lazy<Mutex<LoginThrottle>> =
+  Lazy::new(|| Mutex::new(LoginThrottle::default()));
+
+/// How long a failed login waits before answering, to slow guessing
+const FAILED_LOGIN_DELAY: Duration = Duration::from_secs(1);
+
+/// Loads the web users if they haven't been loaded yet, or reloads
+/// them if `lqusers` has changed them. Leaves `None` if there are no
+/// users yet.
+fn refresh_web_users(users: &mut Option<WebUsers>) {
+  match users {
+    // If the file can't be read, keep the users we have
+    Some(users) => users.reload_if_changed().unwrap_or_default(),
+    None => {
+      if WebUsers::does_users_file_exist().unwrap_or(false) {
+        *users = WebUsers::load_or_create().ok();
+      }
+    }
+  }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AuthGuard {
   Admin,
@@ -28,25 +51,24 @@ impl<'r> FromRequest<'r> for AuthGuard {
     request: &'r Request<'_>,
   ) -> Outcome<Self, Self::Error> {
     let mut lock = WEB_USERS.lock().unwrap();
-    if lock.is_none() {
-      if WebUsers::does_users_file_exist().unwrap() {
-        *lock = Some(WebUsers::load_or_create().unwrap());
-      } else {
-        // There is no user list, so we're redirecting to the
-        // new user page.
-        return Outcome::Success(AuthGuard::FirstUse);
-      }
+    refresh_web_users(&mut lock);
+    if lock.is_none() && !WebUsers::does_users_file_exist().unwrap() {
+      // There is no user list, so we're redirecting to the
+      // new user page.
+      return Outcome::Success(AuthGuard::FirstUse);
     }
 
-    if let Some(users) = &*lock {
+    if let Some(users) = &mut *lock {
       if let Some(token) = request.cookies().get("User-Token") {
-        match users.get_role_from_token(token.value()) {
-          Ok(UserRole::Admin) => return Outcome::Success(AuthGuard::Admin),
-          Ok(UserRole::ReadOnly) => {
+        match users.session_user(token.value()) {
+          Ok((_, UserRole::Admin)) => {
+            return Outcome::Success(AuthGuard::Admin)
+          }
+          Ok((_, UserRole::ReadOnly)) => {
             return Outcome::Success(AuthGuard::ReadOnly)
           }
           _ => {
-            return Outcome::Failure((
+            return Outcome::Error((
               Status::Unauthorized,
               Error::msg("Invalid token"),
             ))
@@ -60,7 +82,7 @@ impl<'r> FromRequest<'r> for AuthGuard {
       }
     }
 
-    Outcome::Failure((Status::Unauthorized, Error::msg("Access Denied")))
+    Outcome::Error((Status::Unauthorized, Error::msg("Access Denied")))
   }
 }
 
@@ -82,15 +104,27 @@ pub fn create_first_user(
   if WebUsers::does_users_file_exist().unwrap() {
     return Json("ERROR".to_string());
   }
-  let mut lock = WEB_USERS.lock().unwrap();
   let mut users = WebUsers::load_or_create().unwrap();
   users.allow_anonymous(info.allow_anonymous).unwrap();
-  let token = users
+  users
     .add_or_update_user(&info.username, &info.password, UserRole::Admin)
     .unwrap();
-  cookies.add(Cookie::new("User-Token", token));
-  *lock = Some(users);
-  Json("OK".to_string())
+  // The first user can't sign in yet if admins need an authenticator
+  let password = users.local_password(&info.username).verify(&info.password);
+  let result = users.login_checked(
+    &info.username,
+    &password,
+    None,
+    BackendCheck::local(),
+  );
+  *WEB_USERS.lock().unwrap() = Some(users);
+  match result {
+    Ok(token) => {
+      cookies.add(Cookie::new("User-Token", token));
+      Json("OK".to_string())
+    }
+    Err(_) => Json("ENROLL".to_string()),
+  }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -98,21 +132,81 @@ pub fn create_first_user(
 pub struct LoginAttempt {
   pub username: String,
   pub password: String,
+  /// A code from the user's authenticator app, or a recovery code
+  #[serde(default)]
+  pub code: Option<String>,
 }
 
+/// Signs in, starting a new session. Answers "OK", "ERROR" for a bad
+/// username, password or code, or "LOCKED" after too many failures for
+/// the username or from the client's address. "TOTP" asks for a code
+/// from the user's authenticator app, and "ENROLL" means the user must
+/// enroll one with `lqusers` before signing in. "UNAVAILABLE" means
+/// the LDAP or RADIUS server couldn't be reached. Anonymous viewers
+/// don't sign in: `AuthGuard` lets them in without a session.
 #[post("/api/login", data = "<info>")]
-pub fn login(cookies: &CookieJar, info: Json<LoginAttempt>) -> Json<String> {
-  let mut lock = WEB_USERS.lock().unwrap();
-  if lock.is_none() && WebUsers::does_users_file_exist().unwrap() {
-    *lock = Some(WebUsers::load_or_create().unwrap());
+pub async fn login(
+  cookies: &CookieJar<'_>,
+  client: Option<IpAddr>,
+  info: Json<LoginAttempt>,
+) -> Json<String> {
+  let user_key = format!("user:{}", info.username.to_lowercase());
+  let client_key =
+    format!("ip:{}", client.map_or("unknown".to_string(), |ip| ip.to_string()));
+  let keys = [user_key.as_str(), client_key.as_str()];
+  if LOGIN_THROTTLE.lock().unwrap().locked_for(&keys).is_some() {
+    return Json("LOCKED".to_string());
   }
-  if let Some(users) = &*lock {
-    if let Ok(token) = users.login(&info.username, &info.password) {
+
+  // Ask the password backend and check the password against a copy
+  // of the user's hash first, without holding the users while a server
+  // answers or Argon2 runs
+  let local = with_web_users(|users| users.local_password(&info.username));
+  let (username, password) = (info.username.clone(), info.password.clone());
+  let checks = rocket::tokio::task::spawn_blocking(move || {
+    let check = WebUsers::check_backend(&username, &password);
+    (check, local.map(|local| local.verify(&password)))
+  })
+  .await;
+  let result = match checks {
+    Ok((check, Some(password))) => with_web_users(|users| {
+      let code = info.code.as_deref();
+      users.login_checked(&info.username, &password, code, check)
+    }),
+    _ => None,
+  };
+  match result {
+    Some(Ok(token)) => {
+      LOGIN_THROTTLE.lock().unwrap().succeeded(&user_key);
       cookies.add(Cookie::new("User-Token", token));
-      return Json("OK".to_string());
+      Json("OK".to_string())
+    }
+    Some(Err(AuthenticationError::SecondFactorRequired)) => {
+      Json("TOTP".to_string())
     }
+    Some(Err(AuthenticationError::SecondFactorNotEnrolled)) => {
+      Json("ENROLL".to_string())
+    }
+    Some(Err(AuthenticationError::BackendUnavailable(_))) => {
+      Json("UNAVAILABLE".to_string())
+    }
+    _ => {
+      let locked = LOGIN_THROTTLE.lock().unwrap().failed(&keys);
+      rocket::tokio::time::sleep(FAILED_LOGIN_DELAY).await;
+      Json(if locked { "LOCKED" } else { "ERROR" }.to_string())
+    }
+  }
+}
+
+/// Ends the current session
+#[post("/api/logout")]
+pub fn logout(cookies: &CookieJar) -> Json<String> {
+  if let Some(token) = cookies.get("User-Token") {
+    let token = token.value().to_string();
+    with_web_users(|users| users.logout(&token)).transpose().ok();
+    cookies.remove(Cookie::from("User-Token"));
   }
-  Json("ERROR".to_string())
+  Json("OK".to_string())
 }
 
 #[get("/api/admin_check")]
@@ -125,11 +219,45 @@ pub fn admin_check(auth: AuthGuard) -> Json<bool> {
 
 #[get("/api/username")]
 pub fn username(_auth: AuthGuard, cookies: &CookieJar) -> Json<String> {
-  if let Some(token) = cookies.get("User-Token") {
-    let lock = WEB_USERS.lock().unwrap();
-    if let Some(users) = &*lock {
-      return Json(users.get_username(token.value()));
-    }
-  }
-  Json("Anonymous".to_string())
+  Json(username_from_cookies(cookies))
+}
+
+/// Runs `f` on the web users, loading them first if needed. Returns
+/// `None` if there are no users yet.
+pub fn with_web_users<T>(f: impl FnOnce(&mut WebUsers) -> T) -> Option<T> {
+  let mut lock = WEB_USERS.lock().unwrap();
+  refresh_web_users(&mut lock);
+  lock.as_mut().map(f)
+}
+
+/// The token of the session the request belongs to, or `None` if it
+/// has no session, such as for an anonymous viewer
+pub fn session_token(cookies: &CookieJar) -> Option<String> {
+  let token = cookies.get("User-Token")?.value().to_string();
+  with_web_users(|users| users.has_session(&token))
+    .unwrap_or(false)
+    .then_some(token)
+}
+
+/// Is the session still there, or, for no session, may anonymous
+/// viewers still watch? Checking doesn't keep the session from going
+/// idle.
+pub fn session_is_valid(token: Option<&str>) -> bool {
+  with_web_users(|users| match token {
+    Some(token) => users.has_session(token),
+    None => users.do_we_allow_anonymous(),
+  })
+  // No users yet, so everyone is setting up the first one
+  .unwrap_or_else(|| !WebUsers::does_users_file_exist().unwrap_or(true))
+}
+
+/// The name of the logged in user, for recording who made a change
+pub fn username_from_cookies(cookies: &CookieJar) -> String {
+  cookies
+    .get("User-Token")
+    .and_then(|token| {
+      with_web_users(|users| users.session_user(token.value()).ok())
+    })
+    .flatten()
+    .map_or("Anonymous".to_string(), |(username, _)| username)
 }
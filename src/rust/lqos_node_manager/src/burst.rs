@@ -0,0 +1,24 @@
+use crate::{auth_guard::AuthGuard, cache_control::NoCache, scope::Scope};
+use lqos_bus::{bus_request, BurstStatus, BusRequest, BusResponse};
+use rocket::serde::json::Json;
+
+#[get("/api/circuit_burst/<circuit_id>")]
+pub async fn circuit_burst(
+  _auth: AuthGuard,
+  scope: Scope,
+  circuit_id: String,
+) -> NoCache<Json<Option<BurstStatus>>> {
+  if !scope.allows_circuit(&circuit_id) {
+    return NoCache::new(Json(None));
+  }
+  let responses = bus_request(vec![BusRequest::GetBurstStatus {
+    circuit_id: Some(circuit_id),
+  }])
+  .await
+  .unwrap();
+  let result = match &responses[0] {
+    BusResponse::BurstStatus(status) => status.first().cloned(),
+    _ => None,
+  };
+  NoCache::new(Json(result))
+}
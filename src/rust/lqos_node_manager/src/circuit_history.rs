@@ -0,0 +1,32 @@
+use crate::{auth_guard::AuthGuard, cache_control::NoCache, scope::Scope};
+use lqos_bus::{bus_request, BusRequest, BusResponse};
+use lqos_config::{CircuitSample, HistoryRange};
+use rocket::serde::msgpack::MsgPack;
+
+/// A circuit's recorded history, oldest first. `range` is one of `1h`
+/// (the default), `24h`, `7d` or `30d`; an unknown range, or a circuit
+/// outside the user's scope, has no samples.
+#[get("/api/circuit_history/<circuit_id>?<range>")]
+pub async fn circuit_history(
+  _auth: AuthGuard,
+  scope: Scope,
+  circuit_id: String,
+  range: Option<String>,
+) -> NoCache<MsgPack<Vec<CircuitSample>>> {
+  if !scope.allows_circuit(&circuit_id) {
+    return NoCache::new(MsgPack(Vec::new()));
+  }
+  let Ok(range) = range.as_deref().unwrap_or("1h").parse::<HistoryRange>()
+  else {
+    return NoCache::new(MsgPack(Vec::new()));
+  };
+  let responses =
+    bus_request(vec![BusRequest::GetCircuitHistory { circuit_id, range }])
+      .await
+      .unwrap();
+  let result = match responses.into_iter().next() {
+    Some(BusResponse::CircuitHistory(samples)) => samples,
+    _ => Vec::new(),
+  };
+  NoCache::new(MsgPack(result))
+}
@@ -1,19 +1,33 @@
-use crate::{auth_guard::AuthGuard, cache_control::NoCache};
+use crate::{
+  auth_guard::{username_from_cookies, AuthGuard},
+  scope::Unscoped,
+  cache_control::NoCache,
+};
 use default_net::get_interfaces;
 use lqos_bus::{bus_request, BusRequest, BusResponse};
-use lqos_config::{EtcLqos, LibreQoSConfig, Tunables};
-use rocket::{fs::NamedFile, serde::{json::Json, Serialize}};
+use lqos_config::{
+  EtcLqos, LibreQoSConfig, RevisionDiff, RevisionInfo, Tunables,
+};
+use rocket::{
+  fs::NamedFile,
+  http::CookieJar,
+  serde::{json::Json, Serialize},
+};
 
 // Note that NoCache can be replaced with a cache option
 // once the design work is complete.
 #[get("/config")]
-pub async fn config_page<'a>(_auth: AuthGuard) -> NoCache<Option<NamedFile>> {
+pub async fn config_page<'a>(
+  _auth: AuthGuard,
+  _unscoped: Unscoped,
+) -> NoCache<Option<NamedFile>> {
   NoCache::new(NamedFile::open("static/config.html").await.ok())
 }
 
 #[get("/api/list_nics")]
 pub async fn get_nic_list<'a>(
   _auth: AuthGuard,
+  _unscoped: Unscoped,
 ) -> NoCache<Json<Vec<(String, String, String)>>> {
   let result = get_interfaces()
     .iter()
@@ -33,6 +47,7 @@ pub async fn get_nic_list<'a>(
 #[get("/api/python_config")]
 pub async fn get_current_python_config(
   _auth: AuthGuard,
+  _unscoped: Unscoped,
 ) -> NoCache<Json<LibreQoSConfig>> {
   let config = lqos_config::LibreQoSConfig::load().unwrap();
   println!("{config:#?}");
@@ -42,6 +57,7 @@ pub async fn get_current_python_config(
 #[get("/api/lqosd_config")]
 pub async fn get_current_lqosd_config(
   _auth: AuthGuard,
+  _unscoped: Unscoped,
 ) -> NoCache<Json<EtcLqos>> {
   let config = lqos_config::EtcLqos::load().unwrap();
   println!("{config:#?}");
@@ -51,15 +67,81 @@ pub async fn get_current_lqosd_config(
 #[post("/api/python_config", data = "<config>")]
 pub async fn update_python_config(
   _auth: AuthGuard,
+  _unscoped: Unscoped,
+  cookies: &CookieJar<'_>,
   config: Json<LibreQoSConfig>,
 ) -> Json<String> {
   config.save().unwrap();
+  let author = username_from_cookies(cookies);
+  if let Err(e) = lqos_config::record_config_revision(
+    &author,
+    "Updated settings from the web UI",
+  ) {
+    warn!("Unable to record configuration revision: {e}");
+  }
   Json("OK".to_string())
 }
 
+#[get("/api/config_revisions")]
+pub async fn config_revisions(
+  _auth: AuthGuard,
+  _unscoped: Unscoped,
+) -> NoCache<Json<Vec<RevisionInfo>>> {
+  let responses =
+    bus_request(vec![BusRequest::ListConfigRevisions]).await.unwrap();
+  let result = match &responses[0] {
+    BusResponse::ConfigRevisions(revisions) => revisions.clone(),
+    _ => Vec::new(),
+  };
+  NoCache::new(Json(result))
+}
+
+/// Compares two revisions. Without `to`, compares against the current
+/// files.
+#[get("/api/config_revisions/diff?<from>&<to>")]
+pub async fn config_revision_diff(
+  _auth: AuthGuard,
+  _unscoped: Unscoped,
+  from: String,
+  to: Option<String>,
+) -> NoCache<Json<Option<RevisionDiff>>> {
+  let responses =
+    bus_request(vec![BusRequest::DiffConfigRevisions { from, to }])
+      .await
+      .unwrap();
+  let result = match &responses[0] {
+    BusResponse::ConfigRevisionDiff(diff) => Some(diff.clone()),
+    _ => None,
+  };
+  NoCache::new(Json(result))
+}
+
+#[post("/api/config_revisions/<id>/rollback")]
+pub async fn rollback_config_revision(
+  auth: AuthGuard,
+  _unscoped: Unscoped,
+  cookies: &CookieJar<'_>,
+  id: String,
+) -> Json<String> {
+  if auth != AuthGuard::Admin {
+    return Json("Error: Not authorized".to_string());
+  }
+  let author = username_from_cookies(cookies);
+  let responses =
+    bus_request(vec![BusRequest::RollbackConfigRevision { id, author }])
+      .await
+      .unwrap();
+  match &responses[0] {
+    BusResponse::ConfigRevision(..) => Json("OK".to_string()),
+    BusResponse::Fail(e) => Json(format!("Error: {e}")),
+    _ => Json("Error: Unexpected response from lqosd".to_string()),
+  }
+}
+
 #[post("/api/lqos_tuning/<period>", data = "<tuning>")]
 pub async fn update_lqos_tuning(
   auth: AuthGuard,
+  _unscoped: Unscoped,
   period: u64,
   tuning: Json<Tunables>,
 ) -> Json<String> {
@@ -87,7 +169,7 @@ pub struct LqosStats {
 }
 
 #[get("/api/stats")]
-pub async fn stats() -> NoCache<Json<LqosStats>> {
+pub async fn stats(_unscoped: Unscoped) -> NoCache<Json<LqosStats>> {
   for msg in bus_request(vec![BusRequest::GetLqosStats]).await.unwrap() {
     if let BusResponse::LqosdStats { bus_requests, time_to_poll_hosts, high_watermark, tracked_flows } = msg {
       return NoCache::new(Json(LqosStats {
@@ -0,0 +1,159 @@
+use super::{forward, Frame};
+use crate::{
+  auth_guard::{session_token, AuthGuard},
+  scope::Scope,
+  tracker::SHAPED_DEVICES,
+};
+use dashmap::DashMap;
+use lqos_bus::{
+  bus_request, BusRequest, BusResponse, FlowTransport, QueueStoreTransit,
+};
+use lqos_config::NetworkJsonTransport;
+use once_cell::sync::Lazy;
+use rocket::{
+  http::{CookieJar, Status},
+  serde::{msgpack, Serialize},
+  tokio::sync::watch,
+};
+use rocket_ws::{Channel, WebSocket};
+use std::{net::IpAddr, sync::Arc};
+
+/// One second of a circuit's statistics. Sent as an array, in field
+/// order.
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct CircuitFrame {
+  queue: QueueStoreTransit,
+  throughput: Vec<(String, u64, u64)>,
+  funnel: Vec<(usize, NetworkJsonTransport)>,
+  flows: Vec<(FlowTransport, Option<FlowTransport>)>,
+}
+
+/// A watched circuit, and for users with a scope, whose scope to show
+/// the nodes above it in
+type CircuitKey = (String, Option<String>);
+
+/// The latest frame for each circuit being watched. An empty frame
+/// means nothing has been gathered yet.
+static CIRCUITS: Lazy<DashMap<CircuitKey, watch::Sender<Frame>>> =
+  Lazy::new(DashMap::new);
+
+/// Pushes a circuit's queue statistics, per-host throughput, the
+/// throughput of the nodes above it and its hosts' flows once a
+/// second. Pages watching the same circuit share the frames. Users
+/// with a scope may only watch circuits in it.
+#[get("/ws/circuit/<circuit_id>")]
+pub fn circuit_channel(
+  _auth: AuthGuard,
+  scope: Scope,
+  cookies: &CookieJar,
+  ws: WebSocket,
+  circuit_id: String,
+) -> Result<Channel<'static>, Status> {
+  if !scope.allows_circuit(&circuit_id) {
+    return Err(Status::Forbidden);
+  }
+  let session = session_token(cookies);
+  let frames = CIRCUITS
+    .entry((circuit_id, scope.tenant().map(str::to_string)))
+    .or_insert_with(|| watch::channel(Frame::default()).0)
+    .subscribe();
+  Ok(ws.channel(move |stream| {
+    Box::pin(forward(
+      stream,
+      session,
+      frames,
+      (),
+      |_, frame: &Frame| Some(frame.clone()).filter(|f| !f.is_empty()),
+      |_, _| {},
+    ))
+  }))
+}
+
+/// Gathers the statistics for each circuit that is being watched,
+/// forgetting those nobody is watching any more.
+pub(super) async fn tick() {
+  CIRCUITS.retain(|_, frames| frames.receiver_count() > 0);
+  if CIRCUITS.is_empty() {
+    return;
+  }
+  let hosts = match bus_request(vec![BusRequest::GetHostCounter]).await {
+    Ok(responses) => match responses.into_iter().next() {
+      Some(BusResponse::HostCounters(hosts)) => hosts,
+      _ => Vec::new(),
+    },
+    Err(_) => return,
+  };
+
+  let keys: Vec<CircuitKey> =
+    CIRCUITS.iter().map(|entry| entry.key().clone()).collect();
+  for key in keys {
+    let (circuit_id, tenant) = &key;
+    let mut frame = gather(circuit_id, &hosts).await;
+    if let Some(tenant) = tenant {
+      let scope = Scope::for_user(tenant);
+      if !scope.allows_circuit(circuit_id) {
+        CIRCUITS.remove(&key);
+        continue;
+      }
+      frame.funnel.retain(|(i, node)| scope.allows_tree_node(*i, node));
+    }
+    if let Ok(encoded) = msgpack::to_compact_vec(&frame) {
+      if let Some(frames) = CIRCUITS.get(&key) {
+        frames.send_replace(Arc::new(encoded));
+      }
+    }
+  }
+}
+
+async fn gather(
+  circuit_id: &str,
+  hosts: &[(IpAddr, u64, u64)],
+) -> CircuitFrame {
+  let mut throughput = Vec::new();
+  let parent = {
+    let devices = SHAPED_DEVICES.read().unwrap();
+    for (ip, down, up) in hosts.iter() {
+      let lookup = match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => *ip,
+      };
+      if let Some(c) = devices.trie.longest_match(lookup) {
+        if devices.devices[*c.1].circuit_id == circuit_id {
+          throughput.push((ip.to_string(), *down, *up));
+        }
+      }
+    }
+    devices
+      .devices
+      .iter()
+      .find(|d| d.circuit_id == circuit_id)
+      .map(|d| d.parent_node.clone())
+      .filter(|parent| !parent.is_empty())
+  };
+
+  let mut requests =
+    vec![BusRequest::GetRawQueueData(circuit_id.to_string())];
+  if let Some(target) = parent {
+    requests.push(BusRequest::GetFunnel { target });
+  }
+  requests.extend(
+    throughput.iter().map(|(ip, _, _)| BusRequest::GetFlowStats(ip.clone())),
+  );
+
+  let mut frame = CircuitFrame {
+    queue: QueueStoreTransit::default(),
+    throughput,
+    funnel: Vec::new(),
+    flows: Vec::new(),
+  };
+  for response in bus_request(requests).await.unwrap_or_default() {
+    match response {
+      BusResponse::RawQueueData(Some(queue)) => frame.queue = *queue,
+      BusResponse::NetworkMap(funnel) => frame.funnel = funnel,
+      BusResponse::FlowData(flows) => frame.flows.extend(flows),
+      _ => {}
+    }
+  }
+  frame
+}
@@ -0,0 +1,266 @@
+use super::{forward, Frame};
+use crate::{
+  auth_guard::{session_token, AuthGuard},
+  network_tree::scoped_site_funnel,
+  scope::Scope,
+  tracker::{
+    cpu_usage_now, host_counts_from, parse_tag_filter, ram_usage_now,
+    scoped_host_counts, scoped_throughput, scoped_top_10, throughput_now,
+    with_plans, IpStatsWithPlan, ThroughputPerSecond, SCOPED_POOL,
+  },
+};
+use dashmap::DashMap;
+use lqos_bus::{bus_request, BusRequest, BusResponse};
+use lqos_config::NetworkJsonTransport;
+use once_cell::sync::Lazy;
+use rocket::{
+  http::CookieJar,
+  serde::{msgpack, Serialize},
+  tokio::sync::watch,
+};
+use rocket_ws::{Channel, WebSocket};
+use std::{collections::HashMap, sync::Arc};
+
+/// One second of dashboard statistics. Sent as an array, in field order.
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct DashboardFrame<'a> {
+  throughput: ThroughputPerSecond,
+  site_funnel: &'a [(usize, NetworkJsonTransport)],
+  cpu: &'a [u32],
+  ram: &'a [u64],
+  top_downloaders: &'a [IpStatsWithPlan],
+  worst_rtt: &'a [IpStatsWithPlan],
+  rtt_histogram: &'a [u32],
+  host_counts: (u32, u32),
+}
+
+/// Which frame a dashboard is sent: its tag filter (`""` for none)
+/// and, for a user with a scope, their name
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+struct FrameKey {
+  tag: String,
+  tenant: Option<String>,
+}
+
+/// The latest frames
+type Frames = Arc<HashMap<FrameKey, Frame>>;
+
+static FRAMES: Lazy<watch::Sender<Frames>> =
+  Lazy::new(|| watch::channel(Frames::default()).0);
+
+/// The filters dashboards are using, other than none, with how many
+/// use each
+static TAG_FILTERS: Lazy<DashMap<FrameKey, usize>> = Lazy::new(DashMap::new);
+
+/// A dashboard's filter, counted in `TAG_FILTERS` until dropped
+struct TagFilter(FrameKey);
+
+impl TagFilter {
+  fn new(tag: &str, tenant: Option<String>) -> Self {
+    let tag = parse_tag_filter(Some(tag))
+      .map(|(name, value)| format!("{name}:{value}"))
+      .unwrap_or_default();
+    let key = FrameKey { tag, tenant };
+    if key != FrameKey::default() {
+      *TAG_FILTERS.entry(key.clone()).or_insert(0) += 1;
+    }
+    Self(key)
+  }
+
+  /// The frame to send. Until the filter's first frame is gathered,
+  /// that is the unfiltered one, except for users with a scope.
+  fn select(&self, frames: &Frames) -> Option<Frame> {
+    frames
+      .get(&self.0)
+      .or_else(|| match self.0.tenant {
+        None => frames.get(&FrameKey::default()),
+        Some(_) => None,
+      })
+      .cloned()
+  }
+}
+
+impl Drop for TagFilter {
+  fn drop(&mut self) {
+    if self.0 != FrameKey::default() {
+      TAG_FILTERS.remove_if_mut(&self.0, |_, count| {
+        *count -= 1;
+        *count == 0
+      });
+    }
+  }
+}
+
+/// Pushes the dashboard's statistics once a second. Send a `name:value`
+/// text message to filter the top downloaders and worst RTT by tag, or
+/// an empty one to stop filtering. Users with a scope only see hosts
+/// and nodes in it.
+#[get("/ws/dashboard?<tag>")]
+pub fn dashboard_channel(
+  _auth: AuthGuard,
+  scope: Scope,
+  cookies: &CookieJar,
+  ws: WebSocket,
+  tag: Option<String>,
+) -> Channel<'static> {
+  let session = session_token(cookies);
+  let frames = FRAMES.subscribe();
+  let tenant = scope.tenant().map(str::to_string);
+  ws.channel(move |stream| {
+    Box::pin(forward(
+      stream,
+      session,
+      frames,
+      TagFilter::new(tag.as_deref().unwrap_or_default(), tenant.clone()),
+      |filter, frames: &Frames| filter.select(frames),
+      move |filter, tag| *filter = TagFilter::new(&tag, tenant.clone()),
+    ))
+  })
+}
+
+/// Gathers the statistics, with one request to lqosd, and encodes a
+/// frame for each tag filter in use. Users with a scope have their
+/// own frames, with another request for each.
+pub(super) async fn tick() {
+  if FRAMES.receiver_count() == 0 {
+    return;
+  }
+  let (tenant_keys, filters): (Vec<FrameKey>, Vec<FrameKey>) = TAG_FILTERS
+    .iter()
+    .map(|entry| entry.key().clone())
+    .partition(|key| key.tenant.is_some());
+  let mut tags = vec![String::new()];
+  tags.extend(filters.into_iter().map(|key| key.tag));
+  let mut requests = vec![
+    BusRequest::TopMapQueues(4),
+    BusRequest::RttHistogram,
+    BusRequest::AllUnknownIps,
+  ];
+  for tag in tags.iter() {
+    let tag = parse_tag_filter(Some(tag));
+    requests.push(BusRequest::GetTopNDownloaders {
+      start: 0,
+      end: 10,
+      tag: tag.clone(),
+    });
+    requests.push(BusRequest::GetWorstRtt { start: 0, end: 10, tag });
+  }
+  let Ok(responses) = bus_request(requests).await else {
+    return;
+  };
+
+  let mut site_funnel = Vec::new();
+  let mut rtt_histogram = Vec::new();
+  let mut host_counts = host_counts_from(&[]);
+  let mut top_downloaders = Vec::new();
+  let mut worst_rtt = Vec::new();
+  for response in responses {
+    match response {
+      BusResponse::NetworkMap(nodes) => site_funnel = nodes,
+      BusResponse::RttHistogram(histogram) => rtt_histogram = histogram,
+      BusResponse::AllUnknownIps(unknowns) => {
+        host_counts = host_counts_from(&unknowns)
+      }
+      BusResponse::TopDownloaders(stats) => {
+        top_downloaders.push(with_plans(&stats))
+      }
+      BusResponse::WorstRtt(stats) => worst_rtt.push(with_plans(&stats)),
+      _ => {}
+    }
+  }
+
+  let throughput = throughput_now().await;
+  let cpu = cpu_usage_now();
+  let ram = ram_usage_now();
+  let mut frames = HashMap::new();
+  for (i, tag) in tags.into_iter().enumerate() {
+    let frame = DashboardFrame {
+      throughput,
+      site_funnel: &site_funnel,
+      cpu: &cpu,
+      ram: &ram,
+      top_downloaders: top_downloaders.get(i).map_or(&[], Vec::as_slice),
+      worst_rtt: worst_rtt.get(i).map_or(&[], Vec::as_slice),
+      rtt_histogram: &rtt_histogram,
+      host_counts,
+    };
+    if let Ok(encoded) = msgpack::to_compact_vec(&frame) {
+      frames.insert(FrameKey { tag, tenant: None }, Arc::new(encoded));
+    }
+  }
+
+  let mut tenants: HashMap<String, Vec<String>> = HashMap::new();
+  for key in tenant_keys {
+    if let Some(tenant) = key.tenant {
+      tenants.entry(tenant).or_default().push(key.tag);
+    }
+  }
+  for (tenant, tags) in tenants {
+    tenant_frames(&mut frames, tenant, tags, &cpu, &ram).await;
+  }
+  FRAMES.send_replace(Arc::new(frames));
+}
+
+/// Encodes a frame for each of a scoped user's tag filters, with only
+/// the hosts and nodes in their scope. The shaper's RTT histogram
+/// includes everyone's hosts, so is left out.
+async fn tenant_frames(
+  frames: &mut HashMap<FrameKey, Frame>,
+  tenant: String,
+  tags: Vec<String>,
+  cpu: &[u32],
+  ram: &[u64],
+) {
+  let scope = Scope::for_user(&tenant);
+  let mut requests = vec![BusRequest::GetHostCounter];
+  for tag in tags.iter() {
+    let tag = parse_tag_filter(Some(tag));
+    requests.push(BusRequest::GetTopNDownloaders {
+      start: 0,
+      end: SCOPED_POOL,
+      tag: tag.clone(),
+    });
+    requests.push(BusRequest::GetWorstRtt { start: 0, end: SCOPED_POOL, tag });
+  }
+  let Ok(responses) = bus_request(requests).await else {
+    return;
+  };
+
+  let mut throughput = ThroughputPerSecond::default();
+  let mut top_downloaders = Vec::new();
+  let mut worst_rtt = Vec::new();
+  for response in responses {
+    match response {
+      BusResponse::HostCounters(hosts) => {
+        throughput = scoped_throughput(&hosts, &scope)
+      }
+      BusResponse::TopDownloaders(stats) => {
+        top_downloaders.push(scoped_top_10(&stats, &scope))
+      }
+      BusResponse::WorstRtt(stats) => {
+        worst_rtt.push(scoped_top_10(&stats, &scope))
+      }
+      _ => {}
+    }
+  }
+
+  let site_funnel = scoped_site_funnel(&scope).await;
+  let host_counts = scoped_host_counts(&scope);
+  for (i, tag) in tags.into_iter().enumerate() {
+    let frame = DashboardFrame {
+      throughput,
+      site_funnel: &site_funnel,
+      cpu,
+      ram,
+      top_downloaders: top_downloaders.get(i).map_or(&[], Vec::as_slice),
+      worst_rtt: worst_rtt.get(i).map_or(&[], Vec::as_slice),
+      rtt_histogram: &[],
+      host_counts,
+    };
+    if let Ok(encoded) = msgpack::to_compact_vec(&frame) {
+      let key = FrameKey { tag, tenant: Some(tenant.clone()) };
+      frames.insert(key, Arc::new(encoded));
+    }
+  }
+}
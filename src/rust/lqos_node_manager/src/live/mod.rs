@@ -0,0 +1,106 @@
+//! WebSocket channels that push live statistics to the web UI. One
+//! task gathers the data for each channel once a second, however many
+//! pages are watching it, so open dashboards don't each poll lqosd.
+//!
+//! Frames are MessagePack-encoded exactly as the REST endpoints encode
+//! their responses, so the pages read them the same way. Each socket
+//! checks its session every few seconds, closing with a policy
+//! violation once the user has signed out or been removed.
+mod circuit;
+mod dashboard;
+
+pub use circuit::circuit_channel;
+pub use dashboard::dashboard_channel;
+use crate::auth_guard::session_is_valid;
+use rocket::{
+  futures::{SinkExt, StreamExt},
+  tokio::{
+    select,
+    sync::watch,
+    time::{interval, interval_at, Instant, MissedTickBehavior},
+  },
+};
+use rocket_ws::{
+  frame::{CloseCode, CloseFrame},
+  result::Error,
+  stream::DuplexStream,
+  Message,
+};
+use std::{sync::Arc, time::Duration};
+
+/// An encoded frame, shared by every subscriber
+type Frame = Arc<Vec<u8>>;
+
+/// How often an open socket checks that its session is still valid
+const SESSION_CHECK: Duration = Duration::from_secs(5);
+
+/// Once a second, gathers and sends the data for every channel that
+/// has a subscriber. Called from the main program as a "fairing";
+/// it never returns.
+pub async fn update_live_channels() {
+  let mut ticker = interval(Duration::from_secs(1));
+  ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+  loop {
+    ticker.tick().await;
+    dashboard::tick().await;
+    circuit::tick().await;
+  }
+}
+
+/// Sends the current frame, then each new one, until the WebSocket
+/// closes or `session` (`None` for an anonymous viewer) is no longer
+/// valid. `select_frame` picks the page's frame from what was
+/// gathered, and `on_text` handles text messages from the page, such
+/// as a new filter.
+async fn forward<T, S>(
+  mut stream: DuplexStream,
+  session: Option<String>,
+  mut frames: watch::Receiver<T>,
+  mut state: S,
+  select_frame: impl Fn(&S, &T) -> Option<Frame>,
+  on_text: impl Fn(&mut S, String),
+) -> Result<(), Error> {
+  let mut send = true;
+  let mut session_check =
+    interval_at(Instant::now() + SESSION_CHECK, SESSION_CHECK);
+  loop {
+    if send {
+      let frame = select_frame(&state, &frames.borrow_and_update());
+      if let Some(frame) = frame {
+        stream.send(Message::binary(frame.as_slice())).await?;
+      }
+    }
+    select! {
+      changed = frames.changed() => {
+        if changed.is_err() {
+          break;
+        }
+        send = true;
+      }
+      _ = session_check.tick() => {
+        send = false;
+        if !session_is_valid(session.as_deref()) {
+          let close = CloseFrame {
+            code: CloseCode::Policy,
+            reason: "The session has ended".into(),
+          };
+          stream.send(Message::Close(Some(close))).await?;
+          break;
+        }
+      }
+      message = stream.next() => {
+        send = false;
+        match message {
+          Some(Ok(Message::Text(text))) => {
+            on_text(&mut state, text);
+            send = true;
+          }
+          Some(Ok(Message::Close(_))) | None => break,
+          Some(Ok(_)) => {}
+          Some(Err(e)) => return Err(e),
+        }
+      }
+    }
+  }
+  Ok(())
+}
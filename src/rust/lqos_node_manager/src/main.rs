@@ -2,15 +2,23 @@
 extern crate rocket;
 use rocket::fairing::AdHoc;
 mod cache_control;
+mod circuit_history;
 mod shaped_devices;
+mod shaped_editor;
 mod static_pages;
 mod tracker;
 mod unknown_devices;
 use rocket_async_compression::Compression;
+mod api_v1;
 mod auth_guard;
+mod burst;
 mod config_control;
+mod live;
+mod network_editor;
 mod network_tree;
 mod queue_info;
+mod quotas;
+mod scope;
 mod toasts;
 
 // Use JemAllocator only on supported platforms
@@ -34,7 +42,14 @@ fn rocket() -> _ {
         rocket::tokio::spawn(tracker::update_total_throughput_buffer());
       })
     }))
+    .attach(AdHoc::on_liftoff("Live updates", |_| {
+      Box::pin(async move {
+        rocket::tokio::spawn(live::update_live_channels());
+      })
+    }))
     .register("/", catchers![static_pages::login])
+    .register("/api/v1", api_v1::catchers())
+    .mount("/", api_v1::routes())
     .mount(
       "/",
       routes![
@@ -45,6 +60,7 @@ fn rocket() -> _ {
         static_pages::circuit_queue,
         config_control::config_page,
         network_tree::tree_page,
+        network_editor::network_edit_page,
         static_pages::ip_dump,
         // Our JS library
         static_pages::lqos_js,
@@ -59,12 +75,29 @@ fn rocket() -> _ {
         tracker::worst_10_rtt,
         tracker::rtt_histogram,
         tracker::host_counts,
+        live::dashboard_channel,
+        live::circuit_channel,
         shaped_devices::all_shaped_devices,
         shaped_devices::shaped_devices_count,
         shaped_devices::shaped_devices_range,
         shaped_devices::shaped_devices_search,
         shaped_devices::reload_required,
         shaped_devices::reload_libreqos,
+        shaped_devices::validate_config,
+        shaped_editor::staged_summary,
+        shaped_editor::staged_device,
+        shaped_editor::staged_circuit,
+        shaped_editor::stage_device,
+        shaped_editor::stage_device_removal,
+        shaped_editor::stage_circuit_removal,
+        shaped_editor::discard_edits,
+        shaped_editor::commit_edits,
+        network_editor::network_summary,
+        network_editor::stage_node,
+        network_editor::stage_move,
+        network_editor::stage_node_removal,
+        network_editor::discard_network_edits,
+        network_editor::commit_network_edits,
         unknown_devices::all_unknown_devices,
         unknown_devices::unknown_devices_count,
         unknown_devices::unknown_devices_range,
@@ -79,17 +112,28 @@ fn rocket() -> _ {
         queue_info::pcap,
         queue_info::request_analysis,
         queue_info::dns_query,
+        quotas::all_quotas,
+        quotas::circuit_quota,
+        quotas::top_up_quota,
+        quotas::reset_quota,
+        burst::circuit_burst,
+        circuit_history::circuit_history,
         config_control::get_nic_list,
         config_control::get_current_python_config,
         config_control::get_current_lqosd_config,
         config_control::update_python_config,
+        config_control::config_revisions,
+        config_control::config_revision_diff,
+        config_control::rollback_config_revision,
         config_control::update_lqos_tuning,
         auth_guard::create_first_user,
         auth_guard::login,
+        auth_guard::logout,
         auth_guard::admin_check,
         static_pages::login_page,
         auth_guard::username,
         network_tree::tree_entry,
+        network_tree::tree_entry_by_id,
         network_tree::tree_clients,
         network_tree::network_tree_summary,
         network_tree::node_names,
@@ -117,6 +161,17 @@ fn rocket() -> _ {
   if cfg!(debug_assertions) {
     server
   } else {
-    server.attach(Compression::fairing())
+    // The compressor doesn't know about WebSocket upgrades; the
+    // upgraded connection isn't compressed, so don't claim it is.
+    server.attach(Compression::fairing()).attach(AdHoc::on_response(
+      "Uncompressed upgrades",
+      |request, response| {
+        Box::pin(async move {
+          if request.headers().contains("Upgrade") {
+            response.remove_header("Content-Encoding");
+          }
+        })
+      },
+    ))
   }
 }
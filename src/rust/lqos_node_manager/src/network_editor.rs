@@ -0,0 +1,317 @@
+//! Editing `network.json` from the web UI. As with shaped devices,
+//! edits are staged here until an administrator reviews the validation
+//! findings and orphaned circuits, then commits or discards them.
+use crate::{
+  auth_guard::{username_from_cookies, AuthGuard},
+  scope::{forget_resolved_scopes, Unscoped},
+  cache_control::NoCache,
+  tracker::{replace_shaped_devices, SHAPED_DEVICES},
+};
+use lqos_bus::{bus_request, BusRequest, BusResponse};
+use lqos_config::{
+  ConfigShapedDevices, NetworkTree, NodeSettings, ShapedDevice,
+  ValidationFinding, ValidationSeverity,
+};
+use once_cell::sync::Lazy;
+use rocket::{
+  fs::NamedFile,
+  http::CookieJar,
+  serde::{json::Json, Deserialize, Serialize},
+};
+use std::sync::Mutex;
+
+/// Edits that haven't been committed yet
+#[derive(Clone)]
+struct StagedNetwork {
+  /// The tree as it was when editing started
+  base: NetworkTree,
+
+  /// The tree with the edits applied
+  tree: NetworkTree,
+
+  /// Nodes renamed, as (old, new), in the order they were renamed.
+  /// Circuits attached to a renamed node follow it.
+  renames: Vec<(String, String)>,
+}
+
+static STAGED: Lazy<Mutex<Option<StagedNetwork>>> =
+  Lazy::new(|| Mutex::new(None));
+
+/// The tree being edited, for review before committing it
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct NetworkSummary {
+  /// Are there any edits?
+  staged: bool,
+
+  /// The staged tree, or `network.json` if nothing is staged
+  tree: NetworkTree,
+
+  /// Validation findings for the tree and shaped devices
+  findings: Vec<ValidationFinding>,
+
+  /// Circuits whose parent node isn't in the tree
+  orphaned_circuits: Vec<String>,
+
+  /// Has `network.json` changed since editing started?
+  conflict: bool,
+
+  /// Why `network.json` couldn't be loaded, if it couldn't
+  error: Option<String>,
+}
+
+/// A node to add, or new settings for an existing one
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct NodeEdit {
+  /// The name of the node being changed, or `None` to add a node
+  original_name: Option<String>,
+
+  /// Where to add a new node, or `None` for the top level
+  parent: Option<String>,
+
+  #[serde(flatten)]
+  settings: NodeSettings,
+}
+
+/// The shaped devices with the staged renames applied
+fn renamed_devices(renames: &[(String, String)]) -> Vec<ShapedDevice> {
+  let mut devices = SHAPED_DEVICES.read().unwrap().devices.clone();
+  for (from, to) in renames.iter() {
+    for device in devices.iter_mut().filter(|d| &d.parent_node == from) {
+      device.parent_node.clone_from(to);
+    }
+  }
+  devices
+}
+
+/// Applies an edit to the staged tree, starting a new set of edits if
+/// there isn't one. A failed edit leaves the tree as it was.
+fn stage(
+  edit: impl FnOnce(&mut StagedNetwork) -> Result<(), String>,
+) -> Json<String> {
+  let mut lock = STAGED.lock().unwrap();
+  if lock.is_none() {
+    match NetworkTree::load() {
+      Ok(base) => {
+        *lock = Some(StagedNetwork {
+          tree: base.clone(),
+          base,
+          renames: Vec::new(),
+        })
+      }
+      Err(e) => return Json(format!("Error: {e}")),
+    }
+  }
+  let mut edited = lock.as_ref().unwrap().clone();
+  if let Err(e) = edit(&mut edited) {
+    return Json(format!("Error: {e}"));
+  }
+  *lock = if edited.tree == edited.base {
+    None
+  } else {
+    Some(edited)
+  };
+  Json("OK".to_string())
+}
+
+#[get("/network_edit")]
+pub async fn network_edit_page<'a>(
+  _auth: AuthGuard,
+  _unscoped: Unscoped,
+) -> NoCache<Option<NamedFile>> {
+  NoCache::new(NamedFile::open("static/network-edit.html").await.ok())
+}
+
+#[get("/api/network_edit")]
+pub fn network_summary(
+  auth: AuthGuard,
+  _unscoped: Unscoped,
+) -> NoCache<Json<NetworkSummary>> {
+  let lock = STAGED.lock().unwrap();
+  let mut summary = NetworkSummary {
+    staged: false,
+    tree: NetworkTree::default(),
+    findings: Vec::new(),
+    orphaned_circuits: Vec::new(),
+    conflict: false,
+    error: None,
+  };
+  let current = match NetworkTree::load() {
+    Ok(current) => current,
+    Err(e) => {
+      summary.error = Some(e.to_string());
+      return NoCache::new(Json(summary));
+    }
+  };
+  let devices = match lock.as_ref().filter(|_| auth == AuthGuard::Admin) {
+    Some(staged) => {
+      summary.staged = true;
+      summary.conflict = staged.base != current;
+      summary.tree = staged.tree.clone();
+      renamed_devices(&staged.renames)
+    }
+    None => {
+      summary.tree = current;
+      renamed_devices(&[])
+    }
+  };
+  match summary.tree.to_json() {
+    Ok(json) => {
+      summary.findings = lqos_config::validate_network_edit(
+        &ConfigShapedDevices::from_devices(devices.clone()),
+        &json,
+      );
+    }
+    Err(e) => summary.error = Some(e.to_string()),
+  }
+  summary.orphaned_circuits = summary.tree.orphaned_circuits(&devices);
+  NoCache::new(Json(summary))
+}
+
+/// Adds a node, or changes a node's name, capacity or type. Renaming a
+/// node moves its circuits with it when the edits are committed.
+#[post("/api/network_edit/node", data = "<edit>")]
+pub fn stage_node(
+  auth: AuthGuard,
+  _unscoped: Unscoped,
+  edit: Json<NodeEdit>,
+) -> Json<String> {
+  if auth != AuthGuard::Admin {
+    return Json("Error: Not authorized".to_string());
+  }
+  let edit = edit.into_inner();
+  stage(|staged| {
+    let mut settings = edit.settings;
+    settings.name = settings.name.trim().to_string();
+    match edit.original_name {
+      Some(original) => {
+        let renamed = original != settings.name;
+        let name = settings.name.clone();
+        staged
+          .tree
+          .update_node(&original, settings)
+          .map_err(|e| e.to_string())?;
+        if renamed {
+          staged.renames.push((original, name));
+        }
+      }
+      None => staged
+        .tree
+        .add_node(edit.parent.as_deref(), settings)
+        .map_err(|e| e.to_string())?,
+    }
+    Ok(())
+  })
+}
+
+/// Moves a node, with everything below it, under `parent`, or to the
+/// top level if there is no `parent`.
+#[post("/api/network_edit/node/<name>/move?<parent>")]
+pub fn stage_move(
+  auth: AuthGuard,
+  _unscoped: Unscoped,
+  name: String,
+  parent: Option<String>,
+) -> Json<String> {
+  if auth != AuthGuard::Admin {
+    return Json("Error: Not authorized".to_string());
+  }
+  stage(|staged| {
+    staged
+      .tree
+      .move_node(&name, parent.as_deref().filter(|p| !p.is_empty()))
+      .map_err(|e| e.to_string())
+  })
+}
+
+/// Removes a node, with everything below it. Circuits attached to the
+/// removed nodes are listed as orphaned.
+#[post("/api/network_edit/node/<name>/delete")]
+pub fn stage_node_removal(
+  auth: AuthGuard,
+  _unscoped: Unscoped,
+  name: String,
+) -> Json<String> {
+  if auth != AuthGuard::Admin {
+    return Json("Error: Not authorized".to_string());
+  }
+  stage(|staged| {
+    staged.tree.remove_node(&name).map(|_| ()).map_err(|e| e.to_string())
+  })
+}
+
+#[post("/api/network_edit/discard")]
+pub fn discard_network_edits(
+  auth: AuthGuard,
+  _unscoped: Unscoped,
+) -> Json<String> {
+  if auth != AuthGuard::Admin {
+    return Json("Error: Not authorized".to_string());
+  }
+  *STAGED.lock().unwrap() = None;
+  Json("OK".to_string())
+}
+
+/// Writes the staged tree to `network.json`, keeping the previous file
+/// as `network.json.backup`, then reloads LibreQoS. Circuits attached to
+/// renamed nodes are updated in `ShapedDevices.csv`.
+#[post("/api/network_edit/commit")]
+pub async fn commit_network_edits(
+  auth: AuthGuard,
+  _unscoped: Unscoped,
+  cookies: &CookieJar<'_>,
+) -> Json<String> {
+  if auth != AuthGuard::Admin {
+    return Json("Error: Not authorized".to_string());
+  }
+  if let Err(e) = write_staged(&username_from_cookies(cookies)) {
+    return Json(format!("Error: {e}"));
+  }
+  let responses = bus_request(vec![BusRequest::ReloadLibreQoS]).await;
+  match responses.as_ref().map(|r| r.first()) {
+    Ok(Some(BusResponse::ReloadLibreQoS(message))) => Json(message.clone()),
+    _ => Json(
+      "Error: Saved network.json, but unable to reload LibreQoS".to_string(),
+    ),
+  }
+}
+
+fn write_staged(author: &str) -> Result<(), String> {
+  let mut lock = STAGED.lock().unwrap();
+  let Some(staged) = lock.as_ref() else {
+    return Err("There are no changes to commit".to_string());
+  };
+  if NetworkTree::load().map_err(|e| e.to_string())? != staged.base {
+    return Err(
+      "network.json has changed since editing started; discard the \
+       changes and make them again"
+        .to_string(),
+    );
+  }
+  let json = staged.tree.to_json().map_err(|e| e.to_string())?;
+  let devices = renamed_devices(&staged.renames);
+  let devices = ConfigShapedDevices::from_devices(devices);
+  let errors: Vec<String> = lqos_config::validate_network_edit(&devices, &json)
+    .into_iter()
+    .filter(|f| f.severity == ValidationSeverity::Error)
+    .map(|f| f.message)
+    .collect();
+  if !errors.is_empty() {
+    return Err(errors.join("; "));
+  }
+  staged.tree.save().map_err(|e| e.to_string())?;
+  forget_resolved_scopes();
+  if !staged.renames.is_empty() {
+    devices.write_csv("ShapedDevices.csv").map_err(|e| e.to_string())?;
+    replace_shaped_devices(devices);
+  }
+  if let Err(e) = lqos_config::record_config_revision(
+    author,
+    "Edited network.json in the web UI",
+  ) {
+    warn!("Unable to record configuration revision: {e}");
+  }
+  *lock = None;
+  Ok(())
+}
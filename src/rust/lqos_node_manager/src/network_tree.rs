@@ -1,4 +1,4 @@
-use std::net::IpAddr;
+use std::{cmp::Reverse, net::IpAddr};
 
 use lqos_bus::{bus_request, BusRequest, BusResponse};
 use lqos_config::NetworkJsonTransport;
@@ -7,7 +7,10 @@ use rocket::{
   serde::{json::Json, Serialize, msgpack::MsgPack},
 };
 
-use crate::{cache_control::NoCache, tracker::SHAPED_DEVICES};
+use crate::{
+  auth_guard::AuthGuard, cache_control::NoCache, scope::Scope,
+  tracker::SHAPED_DEVICES,
+};
 
 // Note that NoCache can be replaced with a cache option
 // once the design work is complete.
@@ -16,23 +19,114 @@ pub async fn tree_page<'a>() -> NoCache<Option<NamedFile>> {
   NoCache::new(NamedFile::open("static/tree.html").await.ok())
 }
 
-#[get("/api/network_tree/<parent>")]
-pub async fn tree_entry(
-  parent: usize,
-) -> NoCache<MsgPack<Vec<(usize, NetworkJsonTransport)>>> {
-  let responses =
-    bus_request(vec![BusRequest::GetNetworkMap { parent }]).await.unwrap();
+/// The tops of a scoped user's part of the tree
+async fn scoped_top_nodes(scope: &Scope) -> Vec<(usize, NetworkJsonTransport)> {
+  let requests: Vec<BusRequest> = scope
+    .top_nodes()
+    .unwrap_or_default()
+    .iter()
+    .map(|parent| BusRequest::GetNetworkMap { parent: *parent })
+    .collect();
+  if requests.is_empty() {
+    return Vec::new();
+  }
+  let mut result = Vec::new();
+  for response in bus_request(requests).await.unwrap_or_default() {
+    if let BusResponse::NetworkMap(nodes) = response {
+      result.extend(nodes.into_iter().next());
+    }
+  }
+  result.retain(|(index, node)| scope.allows_tree_node(*index, node));
+  result
+}
+
+/// The busiest tops of a scoped user's part of the tree, in place of
+/// the root's busiest children
+pub(crate) async fn scoped_site_funnel(
+  scope: &Scope,
+) -> Vec<(usize, NetworkJsonTransport)> {
+  let mut result = scoped_top_nodes(scope).await;
+  result.sort_by_key(|(_, node)| {
+    Reverse(node.current_throughput.0 + node.current_throughput.1)
+  });
+  result.truncate(4);
+  result
+}
+
+/// A node and its children. Users with a scope only see nodes in it;
+/// for them, the root's children are the tops of their part of the
+/// tree.
+async fn tree_layer(
+  request: BusRequest,
+  scope: &Scope,
+) -> Vec<(usize, NetworkJsonTransport)> {
+  let responses = bus_request(vec![request]).await.unwrap();
   let result = match &responses[0] {
     BusResponse::NetworkMap(nodes) => nodes.to_owned(),
     _ => Vec::new(),
   };
+  scoped_layer(result, scope).await
+}
 
-  NoCache::new(MsgPack(result))
+/// Filters a node and its children, from lqosd, to the user's scope.
+/// A node outside the scope has nothing the user may see.
+pub(crate) async fn scoped_layer(
+  mut result: Vec<(usize, NetworkJsonTransport)>,
+  scope: &Scope,
+) -> Vec<(usize, NetworkJsonTransport)> {
+  if !scope.is_restricted() {
+    return result;
+  }
+  match result.first() {
+    Some((0, _)) => {
+      // The root's own figures would include everyone's traffic
+      let (_, mut root) = result.swap_remove(0);
+      let tops = scoped_top_nodes(scope).await;
+      root.current_throughput = tops.iter().fold((0, 0), |(d, u), (_, n)| {
+        (d + n.current_throughput.0, u + n.current_throughput.1)
+      });
+      root.max_throughput = (0, 0);
+      root.rtts.clear();
+      let mut result = vec![(0, root)];
+      result.extend(tops);
+      result
+    }
+    Some((index, node)) if scope.allows_tree_node(*index, node) => {
+      result.retain(|(index, node)| scope.allows_tree_node(*index, node));
+      result
+    }
+    _ => Vec::new(),
+  }
+}
+
+#[get("/api/network_tree/<parent>")]
+pub async fn tree_entry(
+  _auth: AuthGuard,
+  scope: Scope,
+  parent: usize,
+) -> NoCache<MsgPack<Vec<(usize, NetworkJsonTransport)>>> {
+  let request = BusRequest::GetNetworkMap { parent };
+  NoCache::new(MsgPack(tree_layer(request, &scope).await))
+}
+
+#[get("/api/network_tree/by_id/<id>")]
+pub async fn tree_entry_by_id(
+  _auth: AuthGuard,
+  scope: Scope,
+  id: String,
+) -> NoCache<MsgPack<Vec<(usize, NetworkJsonTransport)>>> {
+  let request = BusRequest::GetNetworkMapById { id };
+  NoCache::new(MsgPack(tree_layer(request, &scope).await))
 }
 
 #[get("/api/network_tree_summary")]
 pub async fn network_tree_summary(
+  _auth: AuthGuard,
+  scope: Scope,
 ) -> NoCache<MsgPack<Vec<(usize, NetworkJsonTransport)>>> {
+  if scope.is_restricted() {
+    return NoCache::new(MsgPack(scoped_site_funnel(&scope).await));
+  }
   let responses =
     bus_request(vec![BusRequest::TopMapQueues(4)]).await.unwrap();
   let result = match &responses[0] {
@@ -53,9 +147,14 @@ pub struct CircuitThroughput {
 
 #[get("/api/tree_clients/<parent>")]
 pub async fn tree_clients(
+  _auth: AuthGuard,
+  scope: Scope,
   parent: String,
 ) -> NoCache<MsgPack<Vec<CircuitThroughput>>> {
   let mut result = Vec::new();
+  if !scope.allows_node_name(&parent) {
+    return NoCache::new(MsgPack(result));
+  }
   for msg in
     bus_request(vec![BusRequest::GetHostCounter]).await.unwrap().iter()
   {
@@ -87,10 +186,13 @@ pub async fn tree_clients(
 
 #[post("/api/node_names", data = "<nodes>")]
 pub async fn node_names(
+  _auth: AuthGuard,
+  scope: Scope,
   nodes: Json<Vec<usize>>,
 ) -> NoCache<Json<Vec<(usize, String)>>> {
   let mut result = Vec::new();
-  for msg in bus_request(vec![BusRequest::GetNodeNamesFromIds(nodes.0)])
+  let nodes = nodes.0.into_iter().filter(|n| scope.allows_node(*n)).collect();
+  for msg in bus_request(vec![BusRequest::GetNodeNamesFromIds(nodes)])
     .await
     .unwrap()
     .iter()
@@ -99,15 +201,22 @@ pub async fn node_names(
       result.extend_from_slice(map);
     }
   }
+  result.retain(|(_, name)| scope.allows_node_name(name));
 
   NoCache::new(Json(result))
 }
 
+/// The nodes above a circuit. Users with a scope only see those in it.
 #[get("/api/funnel_for_queue/<circuit_id>")]
 pub async fn funnel_for_queue(
+  _auth: AuthGuard,
+  scope: Scope,
   circuit_id: String,
 ) -> NoCache<MsgPack<Vec<(usize, NetworkJsonTransport)>>> {
   let mut result = Vec::new();
+  if !scope.allows_circuit(&circuit_id) {
+    return NoCache::new(MsgPack(result));
+  }
 
   let target = SHAPED_DEVICES
     .read()
@@ -127,5 +236,6 @@ pub async fn funnel_for_queue(
       result.extend_from_slice(map);
     }
   }
+  result.retain(|(index, node)| scope.allows_tree_node(*index, node));
   NoCache::new(MsgPack(result))
 }
@@ -1,6 +1,8 @@
 use crate::auth_guard::AuthGuard;
 use crate::cache_control::NoCache;
+use crate::scope::Scope;
 use crate::tracker::{SHAPED_DEVICES, lookup_dns};
+use dashmap::DashMap;
 use lqos_bus::{bus_request, BusRequest, BusResponse, FlowTransport, PacketHeader, QueueStoreTransit};
 use rocket::fs::NamedFile;
 use rocket::http::Status;
@@ -8,8 +10,13 @@ use rocket::response::content::RawJson;
 use rocket::serde::json::Json;
 use rocket::serde::Serialize;
 use rocket::serde::msgpack::MsgPack;
+use once_cell::sync::Lazy;
 use std::net::IpAddr;
 
+/// The address each packet capture session is for, so that users with
+/// a scope can only fetch captures of hosts in it
+static CAPTURES: Lazy<DashMap<usize, String>> = Lazy::new(DashMap::new);
+
 #[derive(Serialize, Clone)]
 #[serde(crate = "rocket::serde")]
 pub struct CircuitInfo {
@@ -21,17 +28,25 @@ pub struct CircuitInfo {
 pub async fn watch_circuit(
   circuit_id: String,
   _auth: AuthGuard,
-) -> NoCache<Json<String>> {
+  scope: Scope,
+) -> Result<NoCache<Json<String>>, Status> {
+  if !scope.allows_circuit(&circuit_id) {
+    return Err(Status::Forbidden);
+  }
   bus_request(vec![BusRequest::WatchQueue(circuit_id)]).await.unwrap();
 
-  NoCache::new(Json("OK".to_string()))
+  Ok(NoCache::new(Json("OK".to_string())))
 }
 
 #[get("/api/circuit_info/<circuit_id>")]
 pub async fn circuit_info(
   circuit_id: String,
   _auth: AuthGuard,
-) -> NoCache<MsgPack<CircuitInfo>> {
+  scope: Scope,
+) -> Result<NoCache<MsgPack<CircuitInfo>>, Status> {
+  if !scope.allows_circuit(&circuit_id) {
+    return Err(Status::Forbidden);
+  }
   if let Some(device) = SHAPED_DEVICES
     .read()
     .unwrap()
@@ -46,13 +61,13 @@ pub async fn circuit_info(
         device.upload_max_mbps as u64 * 1_000_000,
       ),
     };
-    NoCache::new(MsgPack(result))
+    Ok(NoCache::new(MsgPack(result)))
   } else {
     let result = CircuitInfo {
       name: "Nameless".to_string(),
       capacity: (1_000_000, 1_000_000),
     };
-    NoCache::new(MsgPack(result))
+    Ok(NoCache::new(MsgPack(result)))
   }
 }
 
@@ -60,7 +75,11 @@ pub async fn circuit_info(
 pub async fn current_circuit_throughput(
   circuit_id: String,
   _auth: AuthGuard,
-) -> NoCache<MsgPack<Vec<(String, u64, u64)>>> {
+  scope: Scope,
+) -> Result<NoCache<MsgPack<Vec<(String, u64, u64)>>>, Status> {
+  if !scope.allows_circuit(&circuit_id) {
+    return Err(Status::Forbidden);
+  }
   let mut result = Vec::new();
   // Get a list of host counts
   // This is really inefficient, but I'm struggling to find a better way.
@@ -85,14 +104,18 @@ pub async fn current_circuit_throughput(
     }
   }
 
-  NoCache::new(MsgPack(result))
+  Ok(NoCache::new(MsgPack(result)))
 }
 
 #[get("/api/raw_queue_by_circuit/<circuit_id>")]
 pub async fn raw_queue_by_circuit(
   circuit_id: String,
   _auth: AuthGuard,
-) -> NoCache<MsgPack<QueueStoreTransit>> {
+  scope: Scope,
+) -> Result<NoCache<MsgPack<QueueStoreTransit>>, Status> {
+  if !scope.allows_circuit(&circuit_id) {
+    return Err(Status::Forbidden);
+  }
 
   let responses =
     bus_request(vec![BusRequest::GetRawQueueData(circuit_id)]).await.unwrap();
@@ -103,11 +126,14 @@ pub async fn raw_queue_by_circuit(
     }
     _ => QueueStoreTransit::default()
   };
-  NoCache::new(MsgPack(result))
+  Ok(NoCache::new(MsgPack(result)))
 }
 
 #[get("/api/flows/<ip_list>")]
-pub async fn flow_stats(ip_list: String, _auth: AuthGuard) -> NoCache<MsgPack<Vec<(FlowTransport, Option<FlowTransport>)>>> {
+pub async fn flow_stats(ip_list: String, _auth: AuthGuard, scope: Scope) -> Result<NoCache<MsgPack<Vec<(FlowTransport, Option<FlowTransport>)>>>, Status> {
+  if !ip_list.split(',').all(|ip| scope.allows_ip(ip)) {
+    return Err(Status::Forbidden);
+  }
   let mut result = Vec::new();
   let request: Vec<BusRequest> = ip_list.split(',').map(|ip| BusRequest::GetFlowStats(ip.to_string())).collect();
   let responses = bus_request(request).await.unwrap();
@@ -116,7 +142,7 @@ pub async fn flow_stats(ip_list: String, _auth: AuthGuard) -> NoCache<MsgPack<Ve
       result.extend_from_slice(flow);
     }
   }
-  NoCache::new(MsgPack(result))
+  Ok(NoCache::new(MsgPack(result)))
 }
 
 #[derive(Serialize, Clone)]
@@ -127,30 +153,51 @@ pub enum RequestAnalysisResult {
 }
 
 #[get("/api/request_analysis/<ip>")]
-pub async fn request_analysis(ip: String) -> NoCache<Json<RequestAnalysisResult>> {
-  for r in bus_request(vec![BusRequest::GatherPacketData(ip)]).await.unwrap() {
+pub async fn request_analysis(ip: String, _auth: AuthGuard, scope: Scope) -> Result<NoCache<Json<RequestAnalysisResult>>, Status> {
+  if !scope.allows_ip(&ip) {
+    return Err(Status::Forbidden);
+  }
+  for r in bus_request(vec![BusRequest::GatherPacketData(ip.clone())]).await.unwrap() {
     if let BusResponse::PacketCollectionSession{session_id, countdown} = r {
-      return NoCache::new(Json(RequestAnalysisResult::Ok{session_id, countdown}));
+      // Captures are started by hand, so this rarely fills up
+      if CAPTURES.len() >= 1024 {
+        CAPTURES.clear();
+      }
+      CAPTURES.insert(session_id, ip);
+      return Ok(NoCache::new(Json(RequestAnalysisResult::Ok{session_id, countdown})));
     }
   }
 
-  NoCache::new(Json(RequestAnalysisResult::Fail))
+  Ok(NoCache::new(Json(RequestAnalysisResult::Fail)))
+}
+
+/// May the user fetch a packet capture? Users with a scope may only
+/// fetch captures of hosts in it, started from this node manager.
+fn allows_capture(scope: &Scope, id: usize) -> bool {
+  !scope.is_restricted()
+    || CAPTURES.get(&id).is_some_and(|ip| scope.allows_ip(&ip))
 }
 
 #[get("/api/packet_dump/<id>")]
-pub async fn packet_dump(id: usize, _auth: AuthGuard) -> NoCache<Json<Vec<PacketHeader>>> {
+pub async fn packet_dump(id: usize, _auth: AuthGuard, scope: Scope) -> Result<NoCache<Json<Vec<PacketHeader>>>, Status> {
+  if !allows_capture(&scope, id) {
+    return Err(Status::Forbidden);
+  }
   let mut result = Vec::new();
   for r in bus_request(vec![BusRequest::GetPacketHeaderDump(id)]).await.unwrap() {
     if let BusResponse::PacketDump(Some(packets)) = r {
       result.extend(packets);
     }
   }
-  NoCache::new(Json(result))
+  Ok(NoCache::new(Json(result)))
 }
 
 #[allow(unused_variables)]
 #[get("/api/pcap/<id>/<filename>")]
-pub async fn pcap(id: usize, filename: String) -> Result<NoCache<NamedFile>, Status> {
+pub async fn pcap(id: usize, filename: String, _auth: AuthGuard, scope: Scope) -> Result<NoCache<NamedFile>, Status> {
+  if !allows_capture(&scope, id) {
+    return Err(Status::Forbidden);
+  }
   // The unusued _filename parameter is there to allow the changing of the
   // filename on the client side. See Github issue 291.
   for r in bus_request(vec![BusRequest::GetPcapDump(id)]).await.unwrap() {
@@ -173,7 +220,9 @@ pub async fn dns_query(ip: String) -> NoCache<String> {
 
 #[cfg(feature = "equinix_tests")]
 #[get("/api/run_btest")]
-pub async fn run_btest() -> NoCache<RawJson<String>> {
+pub async fn run_btest(
+  _unscoped: crate::scope::Unscoped,
+) -> NoCache<RawJson<String>> {
   let responses =
     bus_request(vec![BusRequest::RequestLqosEquinixTest]).await.unwrap();
   let result = match &responses[0] {
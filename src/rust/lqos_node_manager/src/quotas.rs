@@ -0,0 +1,77 @@
+use crate::{auth_guard::AuthGuard, cache_control::NoCache, scope::Scope};
+use lqos_bus::{bus_request, BusRequest, BusResponse, QuotaStatus};
+use rocket::serde::json::Json;
+
+async fn quota_request(request: BusRequest) -> Vec<QuotaStatus> {
+  let responses = bus_request(vec![request]).await.unwrap();
+  match &responses[0] {
+    BusResponse::QuotaUsage(usage) => usage.clone(),
+    _ => Vec::new(),
+  }
+}
+
+#[get("/api/quotas")]
+pub async fn all_quotas(
+  _auth: AuthGuard,
+  scope: Scope,
+) -> NoCache<Json<Vec<QuotaStatus>>> {
+  let mut result =
+    quota_request(BusRequest::GetQuotaUsage { circuit_id: None }).await;
+  result.retain(|q| scope.allows_circuit(&q.circuit_id));
+  NoCache::new(Json(result))
+}
+
+#[get("/api/circuit_quota/<circuit_id>")]
+pub async fn circuit_quota(
+  _auth: AuthGuard,
+  scope: Scope,
+  circuit_id: String,
+) -> NoCache<Json<Option<QuotaStatus>>> {
+  if !scope.allows_circuit(&circuit_id) {
+    return NoCache::new(Json(None));
+  }
+  let result = quota_request(BusRequest::GetQuotaUsage {
+    circuit_id: Some(circuit_id),
+  })
+  .await;
+  NoCache::new(Json(result.into_iter().next()))
+}
+
+async fn change_quota(
+  auth: AuthGuard,
+  scope: Scope,
+  circuit_id: &str,
+  request: BusRequest,
+) -> Json<String> {
+  if auth != AuthGuard::Admin || !scope.allows_circuit(circuit_id) {
+    return Json("Error: Not authorized".to_string());
+  }
+  let responses = bus_request(vec![request]).await.unwrap();
+  match &responses[0] {
+    BusResponse::QuotaUsage(..) => Json("OK".to_string()),
+    BusResponse::Fail(e) => Json(format!("Error: {e}")),
+    _ => Json("Error: Unexpected response from lqosd".to_string()),
+  }
+}
+
+#[post("/api/circuit_quota/<circuit_id>/top_up/<gigabytes>")]
+pub async fn top_up_quota(
+  auth: AuthGuard,
+  scope: Scope,
+  circuit_id: String,
+  gigabytes: u64,
+) -> Json<String> {
+  let request =
+    BusRequest::GrantQuotaTopUp { circuit_id: circuit_id.clone(), gigabytes };
+  change_quota(auth, scope, &circuit_id, request).await
+}
+
+#[post("/api/circuit_quota/<circuit_id>/reset")]
+pub async fn reset_quota(
+  auth: AuthGuard,
+  scope: Scope,
+  circuit_id: String,
+) -> Json<String> {
+  let request = BusRequest::ResetQuotaUsage { circuit_id: circuit_id.clone() };
+  change_quota(auth, scope, &circuit_id, request).await
+}
@@ -0,0 +1,197 @@
+//! Tenant scopes. A user with a scope in `lqusers.toml` only sees the
+//! circuits and `network.json` nodes in it, such as a smaller WISP
+//! reselling capacity on our shapers. Routes take a `Scope` to filter
+//! what they return and to refuse anything outside it, or an
+//! `Unscoped` if tenants may not use them at all.
+use crate::{auth_guard::with_web_users, tracker::SHAPED_DEVICES};
+use anyhow::Error;
+use dashmap::DashMap;
+use lqos_config::{
+  NetworkJson, NetworkJsonTransport, ScopedView, ShapedDevice, UserScope,
+};
+use once_cell::sync::Lazy;
+use rocket::{
+  http::Status,
+  request::{FromRequest, Outcome},
+  Request,
+};
+use std::{net::IpAddr, sync::Arc};
+
+/// What the signed-in user may see. Users without a scope, including
+/// anonymous ones, may see everything.
+#[derive(Clone, Debug, Default)]
+pub struct Scope(Option<Arc<(String, ScopedView)>>);
+
+/// Scopes already resolved, by username, with the scope each was
+/// resolved from in case `lqusers` changes it
+static RESOLVED: Lazy<DashMap<String, (UserScope, Scope)>> =
+  Lazy::new(DashMap::new);
+
+/// Forgets the resolved scopes, when `network.json` or
+/// `ShapedDevices.csv` changes
+pub fn forget_resolved_scopes() {
+  RESOLVED.clear();
+}
+
+impl Scope {
+  /// The scope of a user, resolved against the current `network.json`
+  /// and `ShapedDevices.csv`. Resolved scopes are kept until either
+  /// changes.
+  pub fn for_user(username: &str) -> Self {
+    let Some(scope) =
+      with_web_users(|users| users.user_scope(username)).flatten()
+    else {
+      return Self::default();
+    };
+    let cached = RESOLVED
+      .get(username)
+      .filter(|resolved| resolved.0 == scope)
+      .map(|resolved| resolved.1.clone());
+    if let Some(cached) = cached {
+      return cached;
+    }
+
+    let network = NetworkJson::load().unwrap_or_else(|e| {
+      warn!("Unable to load network.json; only {username}'s tags apply: {e}");
+      NetworkJson::default()
+    });
+    // Held until the scope is saved, so that a reload waits and then
+    // forgets it
+    let devices = SHAPED_DEVICES.read().unwrap();
+    let view = ScopedView::resolve(&scope, &network, &devices.devices);
+    let resolved = Self(Some(Arc::new((username.to_string(), view))));
+    RESOLVED.insert(username.to_string(), (scope, resolved.clone()));
+    resolved
+  }
+
+  /// Is the user limited to part of the network?
+  pub fn is_restricted(&self) -> bool {
+    self.0.is_some()
+  }
+
+  /// The name of a user limited to part of the network
+  pub fn tenant(&self) -> Option<&str> {
+    self.0.as_ref().map(|t| t.0.as_str())
+  }
+
+  fn view(&self) -> Option<&ScopedView> {
+    self.0.as_ref().map(|t| &t.1)
+  }
+
+  /// Users without a scope pass every check
+  fn check(&self, allows: impl FnOnce(&ScopedView) -> bool) -> bool {
+    match self.view() {
+      Some(view) => allows(view),
+      None => true,
+    }
+  }
+
+  /// May the user see and act on the circuit?
+  pub fn allows_circuit(&self, circuit_id: &str) -> bool {
+    self.check(|v| v.allows_circuit(circuit_id))
+  }
+
+  /// May the user see the device? Checks the device as given, so also
+  /// suits new and changed devices.
+  pub fn allows_device(&self, device: &ShapedDevice) -> bool {
+    self.check(|v| v.admits(device))
+  }
+
+  /// May the user see the `network.json` node at this index?
+  pub fn allows_node(&self, index: usize) -> bool {
+    self.check(|v| v.allows_node(index))
+  }
+
+  /// May the user see a node lqosd sent? Checks the name as well as the
+  /// index, in case lqosd hasn't loaded the latest `network.json` yet.
+  pub fn allows_tree_node(
+    &self,
+    index: usize,
+    node: &NetworkJsonTransport,
+  ) -> bool {
+    self.allows_node(index) && self.allows_node_name(&node.name)
+  }
+
+  /// May the user see the `network.json` node with this name?
+  pub fn allows_node_name(&self, name: &str) -> bool {
+    self.check(|v| v.allows_node_name(name))
+  }
+
+  /// May the user see the host? Hosts that aren't in a circuit are
+  /// outside every scope.
+  pub fn allows_ip(&self, ip: &str) -> bool {
+    match ip.trim().parse::<IpAddr>() {
+      Ok(ip) => self.allows_host(ip),
+      Err(_) => !self.is_restricted(),
+    }
+  }
+
+  /// Like `allows_ip`, for a parsed address
+  pub fn allows_host(&self, ip: IpAddr) -> bool {
+    if !self.is_restricted() {
+      return true;
+    }
+    let lookup = match ip {
+      IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+      IpAddr::V6(ip) => ip,
+    };
+    let devices = SHAPED_DEVICES.read().unwrap();
+    devices.trie.longest_match(lookup).is_some_and(|(_, i)| {
+      self.allows_circuit(&devices.devices[*i].circuit_id)
+    })
+  }
+
+  /// The tops of the user's part of the tree, or `None` if they may
+  /// see all of it
+  pub fn top_nodes(&self) -> Option<&[usize]> {
+    self.view().map(|v| v.top_nodes())
+  }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Scope {
+  type Error = anyhow::Error;
+
+  async fn from_request(
+    request: &'r Request<'_>,
+  ) -> Outcome<Self, Self::Error> {
+    let Some(token) = request.cookies().get("User-Token") else {
+      return Outcome::Success(Self::default());
+    };
+    let token = token.value().to_string();
+    match with_web_users(|users| users.session_user(&token).ok()) {
+      Some(Some((username, _))) => {
+        Outcome::Success(Self::for_user(&username))
+      }
+      Some(None) => {
+        Outcome::Error((Status::Unauthorized, Error::msg("Invalid token")))
+      }
+      // There are no users yet, so no scopes either
+      None => Outcome::Success(Self::default()),
+    }
+  }
+}
+
+/// Refuses users with a scope, for routes that see or change the whole
+/// network or the configuration
+#[derive(Debug, Clone, Copy)]
+pub struct Unscoped;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Unscoped {
+  type Error = anyhow::Error;
+
+  async fn from_request(
+    request: &'r Request<'_>,
+  ) -> Outcome<Self, Self::Error> {
+    match Scope::from_request(request).await {
+      Outcome::Success(scope) if scope.is_restricted() => Outcome::Error((
+        Status::Forbidden,
+        Error::msg("Not available to scoped users"),
+      )),
+      Outcome::Success(_) => Outcome::Success(Unscoped),
+      Outcome::Error(e) => Outcome::Error(e),
+      Outcome::Forward(status) => Outcome::Forward(status),
+    }
+  }
+}
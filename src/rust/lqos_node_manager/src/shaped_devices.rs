@@ -2,23 +2,39 @@ use std::sync::atomic::AtomicBool;
 
 use crate::auth_guard::AuthGuard;
 use crate::cache_control::NoCache;
+use crate::scope::{Scope, Unscoped};
 use crate::tracker::SHAPED_DEVICES;
 use lqos_bus::{bus_request, BusRequest, BusResponse};
-use lqos_config::ShapedDevice;
+use lqos_config::{ConfigShapedDevices, ShapedDevice, ValidationFinding};
+use std::sync::RwLockReadGuard;
 use rocket::serde::json::Json;
 
 static RELOAD_REQUIRED: AtomicBool = AtomicBool::new(false);
 
+/// The devices the user may see
+fn visible_devices<'a>(
+  reader: &'a RwLockReadGuard<ConfigShapedDevices>,
+  scope: &'a Scope,
+) -> impl Iterator<Item = &'a ShapedDevice> {
+  reader.devices.iter().filter(|d| scope.allows_circuit(&d.circuit_id))
+}
+
 #[get("/api/all_shaped_devices")]
 pub fn all_shaped_devices(
   _auth: AuthGuard,
+  scope: Scope,
 ) -> NoCache<Json<Vec<ShapedDevice>>> {
-  NoCache::new(Json(SHAPED_DEVICES.read().unwrap().devices.clone()))
+  let reader = SHAPED_DEVICES.read().unwrap();
+  NoCache::new(Json(visible_devices(&reader, &scope).cloned().collect()))
 }
 
 #[get("/api/shaped_devices_count")]
-pub fn shaped_devices_count(_auth: AuthGuard) -> NoCache<Json<usize>> {
-  NoCache::new(Json(SHAPED_DEVICES.read().unwrap().devices.len()))
+pub fn shaped_devices_count(
+  _auth: AuthGuard,
+  scope: Scope,
+) -> NoCache<Json<usize>> {
+  let reader = SHAPED_DEVICES.read().unwrap();
+  NoCache::new(Json(visible_devices(&reader, &scope).count()))
 }
 
 #[get("/api/shaped_devices_range/<start>/<end>")]
@@ -26,10 +42,14 @@ pub fn shaped_devices_range(
   start: usize,
   end: usize,
   _auth: AuthGuard,
+  scope: Scope,
 ) -> NoCache<Json<Vec<ShapedDevice>>> {
   let reader = SHAPED_DEVICES.read().unwrap();
-  let result: Vec<ShapedDevice> =
-    reader.devices.iter().skip(start).take(end).cloned().collect();
+  let result: Vec<ShapedDevice> = visible_devices(&reader, &scope)
+    .skip(start)
+    .take(end)
+    .cloned()
+    .collect();
   NoCache::new(Json(result))
 }
 
@@ -37,21 +57,30 @@ pub fn shaped_devices_range(
 pub fn shaped_devices_search(
   term: String,
   _auth: AuthGuard,
+  scope: Scope,
 ) -> NoCache<Json<Vec<ShapedDevice>>> {
   let term = term.trim().to_lowercase();
   let reader = SHAPED_DEVICES.read().unwrap();
-  let result: Vec<ShapedDevice> = reader
-    .devices
-    .iter()
+  let result: Vec<ShapedDevice> = visible_devices(&reader, &scope)
     .filter(|s| {
       s.circuit_name.trim().to_lowercase().contains(&term)
         || s.device_name.trim().to_lowercase().contains(&term)
+        || s.tags.values().any(|v| v.to_lowercase().contains(&term))
+        || matches_tag(s, &term)
     })
     .cloned()
     .collect();
   NoCache::new(Json(result))
 }
 
+/// Does a `name:value` search term match one of a device's tags?
+fn matches_tag(device: &ShapedDevice, term: &str) -> bool {
+  let Some((name, value)) = term.split_once(':') else { return false };
+  device
+    .tag(name.trim())
+    .is_some_and(|tag| tag.to_lowercase() == value.trim())
+}
+
 #[get("/api/reload_required")]
 pub fn reload_required() -> NoCache<Json<bool>> {
   NoCache::new(Json(
@@ -60,7 +89,10 @@ pub fn reload_required() -> NoCache<Json<bool>> {
 }
 
 #[get("/api/reload_libreqos")]
-pub async fn reload_libreqos(auth: AuthGuard) -> NoCache<Json<String>> {
+pub async fn reload_libreqos(
+  auth: AuthGuard,
+  _unscoped: Unscoped,
+) -> NoCache<Json<String>> {
   if auth != AuthGuard::Admin {
     return NoCache::new(Json("Not authorized".to_string()));
   }
@@ -74,3 +106,17 @@ pub async fn reload_libreqos(auth: AuthGuard) -> NoCache<Json<String>> {
   RELOAD_REQUIRED.store(false, std::sync::atomic::Ordering::Relaxed);
   NoCache::new(Json(result))
 }
+
+#[get("/api/validate_config")]
+pub async fn validate_config(
+  _auth: AuthGuard,
+  _unscoped: Unscoped,
+) -> NoCache<Json<Vec<ValidationFinding>>> {
+  let responses =
+    bus_request(vec![BusRequest::ValidateConfiguration]).await.unwrap();
+  let result = match &responses[0] {
+    BusResponse::ValidationFindings(findings) => findings.clone(),
+    _ => Vec::new(),
+  };
+  NoCache::new(Json(result))
+}
@@ -0,0 +1,333 @@
+//! Editing `ShapedDevices.csv` from the web UI. Edits are staged here
+//! until an administrator reviews the differences and validation
+//! findings, then commits them (writing the file and reloading
+//! LibreQoS) or discards them.
+use crate::{
+  auth_guard::{username_from_cookies, AuthGuard},
+  scope::Unscoped,
+  cache_control::NoCache,
+  tracker::{replace_shaped_devices, SHAPED_DEVICES},
+};
+use lqos_bus::{bus_request, BusRequest, BusResponse};
+use lqos_config::{
+  ConfigShapedDevices, RevisionDiff, ShapedDevice, ValidationFinding,
+  ValidationSeverity,
+};
+use once_cell::sync::Lazy;
+use rocket::{
+  http::CookieJar,
+  serde::{json::Json, Deserialize, Serialize},
+};
+use std::sync::Mutex;
+
+/// Edits that haven't been committed yet
+struct StagedEdit {
+  /// The devices as they were when editing started
+  base: Vec<ShapedDevice>,
+
+  /// The devices with the edits applied
+  devices: Vec<ShapedDevice>,
+}
+
+static STAGED: Lazy<Mutex<Option<StagedEdit>>> =
+  Lazy::new(|| Mutex::new(None));
+
+/// The staged edits, for review before committing them
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct StagedSummary {
+  /// Are there any edits?
+  staged: bool,
+
+  /// How the staged devices differ from `ShapedDevices.csv`
+  diff: Option<RevisionDiff>,
+
+  /// Validation findings for the staged devices
+  findings: Vec<ValidationFinding>,
+
+  /// Has `ShapedDevices.csv` changed since editing started?
+  conflict: bool,
+}
+
+/// A device to add, or a replacement for an existing one. Addresses
+/// are comma-separated, as in `ShapedDevices.csv`.
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct DeviceEdit {
+  /// The ID of the device being replaced, or `None` to add a device
+  original_device_id: Option<String>,
+  device: ShapedDevice,
+  ipv4: String,
+  ipv6: String,
+}
+
+fn current_devices() -> Vec<ShapedDevice> {
+  SHAPED_DEVICES.read().unwrap().devices.clone()
+}
+
+/// Applies an edit to the staged devices, starting a new set of edits
+/// if there isn't one. `edit` must check everything before it changes
+/// anything, so that a failed edit leaves the devices as they were.
+fn stage(
+  edit: impl FnOnce(&mut Vec<ShapedDevice>) -> Result<(), String>,
+) -> Json<String> {
+  let mut lock = STAGED.lock().unwrap();
+  let staged = lock.get_or_insert_with(|| {
+    let base = current_devices();
+    StagedEdit { devices: base.clone(), base }
+  });
+  let result = edit(&mut staged.devices);
+  if staged.devices == staged.base {
+    *lock = None;
+  }
+  match result {
+    Ok(()) => Json("OK".to_string()),
+    Err(e) => Json(format!("Error: {e}")),
+  }
+}
+
+/// The devices as they will be once the edits are committed
+fn staged_devices() -> Vec<ShapedDevice> {
+  match STAGED.lock().unwrap().as_ref() {
+    Some(staged) => staged.devices.clone(),
+    None => current_devices(),
+  }
+}
+
+fn parse_addresses<T, E>(
+  list: &str,
+  parse: impl Fn(&str) -> Result<T, E>,
+) -> Result<Vec<T>, String> {
+  list
+    .split(',')
+    .map(str::trim)
+    .filter(|address| !address.is_empty())
+    .map(|address| {
+      parse(address).map_err(|_| format!("{address} isn't a valid address"))
+    })
+    .collect()
+}
+
+/// Copies the fields that describe the whole circuit from `device` to
+/// the circuit's other devices, which must agree.
+fn share_circuit_fields(devices: &mut [ShapedDevice], device: &ShapedDevice) {
+  for other in devices.iter_mut() {
+    if other.circuit_id == device.circuit_id {
+      other.circuit_name.clone_from(&device.circuit_name);
+      other.parent_node.clone_from(&device.parent_node);
+      other.download_min_mbps = device.download_min_mbps;
+      other.upload_min_mbps = device.upload_min_mbps;
+      other.download_max_mbps = device.download_max_mbps;
+      other.upload_max_mbps = device.upload_max_mbps;
+      other.burst.clone_from(&device.burst);
+    }
+  }
+}
+
+#[get("/api/shaped_edit")]
+pub fn staged_summary(
+  auth: AuthGuard,
+  _unscoped: Unscoped,
+) -> NoCache<Json<StagedSummary>> {
+  let lock = STAGED.lock().unwrap();
+  let summary = match lock.as_ref().filter(|_| auth == AuthGuard::Admin) {
+    None => StagedSummary {
+      staged: false,
+      diff: None,
+      findings: Vec::new(),
+      conflict: false,
+    },
+    Some(staged) => {
+      let base = ConfigShapedDevices::from_devices(staged.base.clone());
+      let edited = ConfigShapedDevices::from_devices(staged.devices.clone());
+      StagedSummary {
+        staged: true,
+        diff: lqos_config::diff_shaped_devices(&base, &edited).ok(),
+        findings: lqos_config::validate_shaped_devices(&edited),
+        conflict: staged.base != current_devices(),
+      }
+    }
+  };
+  NoCache::new(Json(summary))
+}
+
+#[get("/api/shaped_edit/device/<device_id>")]
+pub fn staged_device(
+  _auth: AuthGuard,
+  _unscoped: Unscoped,
+  device_id: String,
+) -> NoCache<Json<Option<ShapedDevice>>> {
+  let device =
+    staged_devices().into_iter().find(|d| d.device_id == device_id);
+  NoCache::new(Json(device))
+}
+
+#[get("/api/shaped_edit/circuit/<circuit_id>")]
+pub fn staged_circuit(
+  _auth: AuthGuard,
+  _unscoped: Unscoped,
+  circuit_id: String,
+) -> NoCache<Json<Vec<ShapedDevice>>> {
+  let devices =
+    staged_devices().into_iter().filter(|d| d.circuit_id == circuit_id);
+  NoCache::new(Json(devices.collect()))
+}
+
+/// Adds or replaces a device. The circuit name, parent node, rates
+/// and burst allowance apply to every device in the circuit.
+#[post("/api/shaped_edit/device", data = "<edit>")]
+pub fn stage_device(
+  auth: AuthGuard,
+  _unscoped: Unscoped,
+  edit: Json<DeviceEdit>,
+) -> Json<String> {
+  if auth != AuthGuard::Admin {
+    return Json("Error: Not authorized".to_string());
+  }
+  let edit = edit.into_inner();
+  let mut device = edit.device;
+  stage(|devices| {
+    device.ipv4 = parse_addresses(&edit.ipv4, ShapedDevice::parse_cidr_v4)?;
+    device.ipv6 = parse_addresses(&edit.ipv6, ShapedDevice::parse_cidr_v6)?;
+    if device.circuit_id.trim().is_empty() {
+      return Err("The circuit ID is required".to_string());
+    }
+    if device.device_id.trim().is_empty() {
+      return Err("The device ID is required".to_string());
+    }
+    if device.ipv4.is_empty() && device.ipv6.is_empty() {
+      return Err("The device needs at least one address".to_string());
+    }
+    let original = edit.original_device_id.as_deref();
+    let position = match original {
+      Some(id) => match devices.iter().position(|d| d.device_id == id) {
+        Some(position) => Some(position),
+        None => return Err(format!("There is no device {id}")),
+      },
+      None => None,
+    };
+    if original != Some(device.device_id.as_str())
+      && devices.iter().any(|d| d.device_id == device.device_id)
+    {
+      return Err(format!("Device ID {} is already used", device.device_id));
+    }
+    share_circuit_fields(devices, &device);
+    match position {
+      Some(position) => devices[position] = device,
+      None => {
+        // Keep a circuit's devices together
+        let at = devices
+          .iter()
+          .rposition(|d| d.circuit_id == device.circuit_id)
+          .map_or(devices.len(), |i| i + 1);
+        devices.insert(at, device);
+      }
+    }
+    Ok(())
+  })
+}
+
+#[post("/api/shaped_edit/device/<device_id>/delete")]
+pub fn stage_device_removal(
+  auth: AuthGuard,
+  _unscoped: Unscoped,
+  device_id: String,
+) -> Json<String> {
+  if auth != AuthGuard::Admin {
+    return Json("Error: Not authorized".to_string());
+  }
+  stage(|devices| {
+    let before = devices.len();
+    devices.retain(|d| d.device_id != device_id);
+    if devices.len() == before {
+      return Err(format!("There is no device {device_id}"));
+    }
+    Ok(())
+  })
+}
+
+#[post("/api/shaped_edit/circuit/<circuit_id>/delete")]
+pub fn stage_circuit_removal(
+  auth: AuthGuard,
+  _unscoped: Unscoped,
+  circuit_id: String,
+) -> Json<String> {
+  if auth != AuthGuard::Admin {
+    return Json("Error: Not authorized".to_string());
+  }
+  stage(|devices| {
+    let before = devices.len();
+    devices.retain(|d| d.circuit_id != circuit_id);
+    if devices.len() == before {
+      return Err(format!("There is no circuit {circuit_id}"));
+    }
+    Ok(())
+  })
+}
+
+#[post("/api/shaped_edit/discard")]
+pub fn discard_edits(auth: AuthGuard, _unscoped: Unscoped) -> Json<String> {
+  if auth != AuthGuard::Admin {
+    return Json("Error: Not authorized".to_string());
+  }
+  *STAGED.lock().unwrap() = None;
+  Json("OK".to_string())
+}
+
+/// Writes the staged devices to `ShapedDevices.csv`, provided they
+/// pass validation and the file hasn't changed since editing started,
+/// then reloads LibreQoS.
+#[post("/api/shaped_edit/commit")]
+pub async fn commit_edits(
+  auth: AuthGuard,
+  _unscoped: Unscoped,
+  cookies: &CookieJar<'_>,
+) -> Json<String> {
+  if auth != AuthGuard::Admin {
+    return Json("Error: Not authorized".to_string());
+  }
+  if let Err(e) = write_staged(&username_from_cookies(cookies)) {
+    return Json(format!("Error: {e}"));
+  }
+  let responses = bus_request(vec![BusRequest::ReloadLibreQoS]).await;
+  match responses.as_ref().map(|r| r.first()) {
+    Ok(Some(BusResponse::ReloadLibreQoS(message))) => Json(message.clone()),
+    _ => Json(
+      "Error: Saved ShapedDevices.csv, but unable to reload LibreQoS"
+        .to_string(),
+    ),
+  }
+}
+
+fn write_staged(author: &str) -> Result<(), String> {
+  let mut lock = STAGED.lock().unwrap();
+  let Some(staged) = lock.as_ref() else {
+    return Err("There are no changes to commit".to_string());
+  };
+  if staged.base != current_devices() {
+    return Err(
+      "ShapedDevices.csv has changed since editing started; discard the \
+       changes and make them again"
+        .to_string(),
+    );
+  }
+  let edited = ConfigShapedDevices::from_devices(staged.devices.clone());
+  let errors: Vec<String> = lqos_config::validate_shaped_devices(&edited)
+    .into_iter()
+    .filter(|f| f.severity == ValidationSeverity::Error)
+    .map(|f| f.message)
+    .collect();
+  if !errors.is_empty() {
+    return Err(errors.join("; "));
+  }
+  edited.write_csv("ShapedDevices.csv").map_err(|e| e.to_string())?;
+  if let Err(e) = lqos_config::record_config_revision(
+    author,
+    "Edited shaped devices in the web UI",
+  ) {
+    warn!("Unable to record configuration revision: {e}");
+  }
+  replace_shaped_devices(edited);
+  *lock = None;
+  Ok(())
+}
@@ -7,3 +7,10 @@ use std::sync::RwLock;
 /// the underlying file changes.
 pub static SHAPED_DEVICES: Lazy<RwLock<ConfigShapedDevices>> =
   Lazy::new(|| RwLock::new(ConfigShapedDevices::default()));
+
+/// Replaces the shaped devices, forgetting the scopes resolved against
+/// the old ones
+pub fn replace_shaped_devices(devices: ConfigShapedDevices) {
+  *SHAPED_DEVICES.write().unwrap() = devices;
+  crate::scope::forget_resolved_scopes();
+}
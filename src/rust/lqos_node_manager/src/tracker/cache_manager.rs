@@ -3,7 +3,8 @@
 //! when there are multiple clients.
 use super::cache::*;
 use anyhow::Result;
-use lqos_config::ConfigShapedDevices;
+use crate::scope::forget_resolved_scopes;
+use lqos_config::{ConfigShapedDevices, NetworkJson};
 use lqos_utils::file_watcher::FileWatcher;
 use nix::sys::{
   time::{TimeSpec, TimeValLike},
@@ -27,6 +28,10 @@ pub async fn update_tracking() {
     info!("Watching for ShapedDevices.csv changes");
     let _ = watch_for_shaped_devices_changing();
   });
+  spawn_blocking(|| {
+    info!("Watching for network.json changes");
+    let _ = watch_for_network_json_changing();
+  });
   let interval_ms = 1000;
   info!("Updating throughput ring buffer at {interval_ms} ms cadence.");
 
@@ -90,10 +95,10 @@ fn load_shaped_devices() {
   let shaped_devices = ConfigShapedDevices::load();
   if let Ok(new_file) = shaped_devices {
     info!("ShapedDevices.csv loaded");
-    *SHAPED_DEVICES.write().unwrap() = new_file;
+    replace_shaped_devices(new_file);
   } else {
     warn!("ShapedDevices.csv failed to load, see previous error messages. Reverting to empty set.");
-    *SHAPED_DEVICES.write().unwrap() = ConfigShapedDevices::default();
+    replace_shaped_devices(ConfigShapedDevices::default());
   }
 }
 
@@ -119,6 +124,22 @@ fn watch_for_shaped_devices_changing() -> Result<()> {
   }
 }
 
+/// Watches `network.json`, so that scopes are resolved against the
+/// new tree when it changes.
+fn watch_for_network_json_changing() -> Result<()> {
+  let watch_path = NetworkJson::path().map_err(|e| {
+    error!("Unable to generate path for network.json: {e}");
+    anyhow::Error::msg("Unable to create path for network.json")
+  })?;
+  let mut watcher = FileWatcher::new("network.json", watch_path);
+  watcher.set_file_created_callback(forget_resolved_scopes);
+  watcher.set_file_changed_callback(forget_resolved_scopes);
+  loop {
+    let result = watcher.watch();
+    info!("network.json watcher returned: {result:?}");
+  }
+}
+
 /// Fires once per second and updates the global traffic ringbuffer.
 pub async fn update_total_throughput_buffer() {
   loop {
@@ -1,12 +1,12 @@
 mod cache;
 mod cache_manager;
-use std::net::IpAddr;
+use std::{collections::BTreeMap, net::IpAddr};
 
 use self::cache::{
   CPU_USAGE, NUM_CPUS, RAM_USED, TOTAL_RAM, THROUGHPUT_BUFFER,
 };
-use crate::{auth_guard::AuthGuard, cache_control::NoCache};
-pub use cache::SHAPED_DEVICES;
+use crate::{auth_guard::AuthGuard, cache_control::NoCache, scope::Scope};
+pub use cache::{replace_shaped_devices, SHAPED_DEVICES};
 pub use cache_manager::{update_tracking, update_total_throughput_buffer};
 use lqos_bus::{bus_request, BusRequest, BusResponse, IpStats, TcHandle};
 use rocket::serde::{Deserialize, Serialize, msgpack::MsgPack};
@@ -22,6 +22,7 @@ pub struct IpStatsWithPlan {
   pub tc_handle: TcHandle,
   pub circuit_id: String,
   pub plan: (u32, u32),
+  pub tags: BTreeMap<String, String>,
 }
 
 impl From<&IpStats> for IpStatsWithPlan {
@@ -34,6 +35,7 @@ impl From<&IpStats> for IpStatsWithPlan {
       tc_handle: i.tc_handle,
       circuit_id: i.circuit_id.clone(),
       plan: (0, 0),
+      tags: i.tags.clone(),
     };
 
     if !result.circuit_id.is_empty() {
@@ -58,6 +60,61 @@ impl From<&IpStats> for IpStatsWithPlan {
   }
 }
 
+/// How many hosts to ask lqosd for when a top 10 is filtered to a
+/// user's scope, so that there are usually still ten left
+pub(crate) const SCOPED_POOL: u32 = 1000;
+
+/// The first ten hosts in the user's scope, with their plans
+pub(crate) fn scoped_top_10(
+  stats: &[IpStats],
+  scope: &Scope,
+) -> Vec<IpStatsWithPlan> {
+  let stats: Vec<IpStats> = stats
+    .iter()
+    .filter(|s| scope.allows_ip(&s.ip_address))
+    .take(10)
+    .cloned()
+    .collect();
+  with_plans(&stats)
+}
+
+/// The throughput of the hosts in the user's scope, from lqosd's host
+/// counters (in bytes per second). Packets aren't counted per host.
+pub(crate) fn scoped_throughput(
+  hosts: &[(IpAddr, u64, u64)],
+  scope: &Scope,
+) -> ThroughputPerSecond {
+  let (down, up) = hosts
+    .iter()
+    .filter(|(ip, _, _)| scope.allows_host(*ip))
+    .fold((0, 0), |(d, u), (_, down, up)| (d + down * 8, u + up * 8));
+  ThroughputPerSecond {
+    bits_per_second: (down, up),
+    packets_per_second: (0, 0),
+    shaped_bits_per_second: (down, up),
+  }
+}
+
+/// The number of shaped devices in the user's scope. Hosts that
+/// aren't shaped are in nobody's scope, so none are counted.
+pub(crate) fn scoped_host_counts(scope: &Scope) -> (u32, u32) {
+  let devices = SHAPED_DEVICES.read().unwrap();
+  let count = devices
+    .devices
+    .iter()
+    .filter(|d| scope.allows_circuit(&d.circuit_id))
+    .count();
+  (count as u32, 0)
+}
+
+/// Reads a `name:value` tag filter, such as `plan:gold`
+pub(crate) fn parse_tag_filter(
+  tag: Option<&str>,
+) -> Option<(String, String)> {
+  let (name, value) = tag?.split_once(':')?;
+  Some((name.trim().to_string(), value.trim().to_string()))
+}
+
 /// Stores total system throughput per second.
 #[derive(Debug, Clone, Copy, Serialize, Default)]
 #[serde(crate = "rocket::serde")]
@@ -67,48 +124,109 @@ pub struct ThroughputPerSecond {
   pub shaped_bits_per_second: (u64, u64),
 }
 
+/// The most recent second of total throughput
+pub(crate) async fn throughput_now() -> ThroughputPerSecond {
+  THROUGHPUT_BUFFER.read().await.current()
+}
+
 #[get("/api/current_throughput")]
 pub async fn current_throughput(
   _auth: AuthGuard,
+  scope: Scope,
 ) -> NoCache<MsgPack<ThroughputPerSecond>> {
-  let result = THROUGHPUT_BUFFER.read().await.current();
+  if !scope.is_restricted() {
+    return NoCache::new(MsgPack(throughput_now().await));
+  }
+  let mut result = ThroughputPerSecond::default();
+  if let Ok(messages) = bus_request(vec![BusRequest::GetHostCounter]).await {
+    for msg in messages {
+      if let BusResponse::HostCounters(hosts) = msg {
+        result = scoped_throughput(&hosts, &scope);
+      }
+    }
+  }
   NoCache::new(MsgPack(result))
 }
 
+/// The shaper's throughput history. Users with a scope have none, as
+/// it includes everyone's traffic.
 #[get("/api/throughput_ring_buffer")]
 pub async fn throughput_ring_buffer(
   _auth: AuthGuard,
+  scope: Scope,
 ) -> NoCache<MsgPack<(usize, Vec<ThroughputPerSecond>)>> {
+  if scope.is_restricted() {
+    return NoCache::new(MsgPack((0, Vec::new())));
+  }
   let result = THROUGHPUT_BUFFER.read().await.copy();
   NoCache::new(MsgPack(result))
 }
 
-#[get("/api/cpu")]
-pub fn cpu_usage(_auth: AuthGuard) -> NoCache<MsgPack<Vec<u32>>> {
-  let usage: Vec<u32> = CPU_USAGE
+/// Usage of each CPU core, as a percentage
+pub(crate) fn cpu_usage_now() -> Vec<u32> {
+  CPU_USAGE
     .iter()
     .take(NUM_CPUS.load(std::sync::atomic::Ordering::Relaxed))
     .map(|cpu| cpu.load(std::sync::atomic::Ordering::Relaxed))
-    .collect();
+    .collect()
+}
+
+/// RAM used, and the total RAM
+pub(crate) fn ram_usage_now() -> Vec<u64> {
+  let ram_usage = RAM_USED.load(std::sync::atomic::Ordering::Relaxed);
+  let total_ram = TOTAL_RAM.load(std::sync::atomic::Ordering::Relaxed);
+  vec![ram_usage, total_ram]
+}
+
+/// Adds the circuit name and plan to each host's statistics
+pub(crate) fn with_plans(stats: &[IpStats]) -> Vec<IpStatsWithPlan> {
+  stats.iter().map(|tt| tt.into()).collect()
+}
+
+/// The number of shaped devices, and of the hosts lqosd doesn't know
+/// that aren't covered by `ShapedDevices.csv` either
+pub(crate) fn host_counts_from(unknowns: &[IpStats]) -> (u32, u32) {
+  let devices = SHAPED_DEVICES.read().unwrap();
+  let really_unknown = unknowns
+    .iter()
+    .filter(|ip| {
+      if let Ok(ip) = ip.ip_address.parse::<IpAddr>() {
+        let lookup = match ip {
+          IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+          IpAddr::V6(ip) => ip,
+        };
+        devices.trie.longest_match(lookup).is_none()
+      } else {
+        false
+      }
+    })
+    .count();
+  (devices.devices.len() as u32, really_unknown as u32)
+}
 
-  NoCache::new(MsgPack(usage))
+#[get("/api/cpu")]
+pub fn cpu_usage(_auth: AuthGuard) -> NoCache<MsgPack<Vec<u32>>> {
+  NoCache::new(MsgPack(cpu_usage_now()))
 }
 
 #[get("/api/ram")]
 pub fn ram_usage(_auth: AuthGuard) -> NoCache<MsgPack<Vec<u64>>> {
-  let ram_usage = RAM_USED.load(std::sync::atomic::Ordering::Relaxed);
-  let total_ram = TOTAL_RAM.load(std::sync::atomic::Ordering::Relaxed);
-  NoCache::new(MsgPack(vec![ram_usage, total_ram]))
+  NoCache::new(MsgPack(ram_usage_now()))
 }
 
-#[get("/api/top_10_downloaders")]
-pub async fn top_10_downloaders(_auth: AuthGuard) -> NoCache<MsgPack<Vec<IpStatsWithPlan>>> {
-  if let Ok(messages) = bus_request(vec![BusRequest::GetTopNDownloaders { start: 0, end: 10 }]).await
+#[get("/api/top_10_downloaders?<tag>")]
+pub async fn top_10_downloaders(
+  _auth: AuthGuard,
+  scope: Scope,
+  tag: Option<&str>,
+) -> NoCache<MsgPack<Vec<IpStatsWithPlan>>> {
+  let tag = parse_tag_filter(tag);
+  let end = if scope.is_restricted() { SCOPED_POOL } else { 10 };
+  if let Ok(messages) = bus_request(vec![BusRequest::GetTopNDownloaders { start: 0, end, tag }]).await
   {
     for msg in messages {
       if let BusResponse::TopDownloaders(stats) = msg {
-        let result = stats.iter().map(|tt| tt.into()).collect();
-        return NoCache::new(MsgPack(result));
+        return NoCache::new(MsgPack(scoped_top_10(&stats, &scope)));
       }
     }
   }
@@ -116,14 +234,19 @@ pub async fn top_10_downloaders(_auth: AuthGuard) -> NoCache<MsgPack<Vec<IpStats
   NoCache::new(MsgPack(Vec::new()))
 }
 
-#[get("/api/worst_10_rtt")]
-pub async fn worst_10_rtt(_auth: AuthGuard) -> NoCache<MsgPack<Vec<IpStatsWithPlan>>> {
-  if let Ok(messages) = bus_request(vec![BusRequest::GetWorstRtt { start: 0, end: 10 }]).await
+#[get("/api/worst_10_rtt?<tag>")]
+pub async fn worst_10_rtt(
+  _auth: AuthGuard,
+  scope: Scope,
+  tag: Option<&str>,
+) -> NoCache<MsgPack<Vec<IpStatsWithPlan>>> {
+  let tag = parse_tag_filter(tag);
+  let end = if scope.is_restricted() { SCOPED_POOL } else { 10 };
+  if let Ok(messages) = bus_request(vec![BusRequest::GetWorstRtt { start: 0, end, tag }]).await
   {
     for msg in messages {
       if let BusResponse::WorstRtt(stats) = msg {
-        let result = stats.iter().map(|tt| tt.into()).collect();
-        return NoCache::new(MsgPack(result));
+        return NoCache::new(MsgPack(scoped_top_10(&stats, &scope)));
       }
     }
   }
@@ -131,8 +254,15 @@ pub async fn worst_10_rtt(_auth: AuthGuard) -> NoCache<MsgPack<Vec<IpStatsWithPl
   NoCache::new(MsgPack(Vec::new()))
 }
 
+/// The RTTs of every host, so empty for users with a scope
 #[get("/api/rtt_histogram")]
-pub async fn rtt_histogram(_auth: AuthGuard) -> NoCache<MsgPack<Vec<u32>>> {
+pub async fn rtt_histogram(
+  _auth: AuthGuard,
+  scope: Scope,
+) -> NoCache<MsgPack<Vec<u32>>> {
+  if scope.is_restricted() {
+    return NoCache::new(MsgPack(Vec::new()));
+  }
   if let Ok(messages) = bus_request(vec![BusRequest::RttHistogram]).await
   {
     for msg in messages {
@@ -147,33 +277,21 @@ pub async fn rtt_histogram(_auth: AuthGuard) -> NoCache<MsgPack<Vec<u32>>> {
 }
 
 #[get("/api/host_counts")]
-pub async fn host_counts(_auth: AuthGuard) -> NoCache<MsgPack<(u32, u32)>> {
-  let mut host_counts = (0, 0);
+pub async fn host_counts(
+  _auth: AuthGuard,
+  scope: Scope,
+) -> NoCache<MsgPack<(u32, u32)>> {
+  if scope.is_restricted() {
+    return NoCache::new(MsgPack(scoped_host_counts(&scope)));
+  }
   if let Ok(messages) = bus_request(vec![BusRequest::AllUnknownIps]).await {
     for msg in messages {
       if let BusResponse::AllUnknownIps(unknowns) = msg {
-        let really_unknown: Vec<IpStats> = unknowns
-          .iter()
-          .filter(|ip| {
-            if let Ok(ip) = ip.ip_address.parse::<IpAddr>() {
-              let lookup = match ip {
-                IpAddr::V4(ip) => ip.to_ipv6_mapped(),
-                IpAddr::V6(ip) => ip,
-              };
-              SHAPED_DEVICES.read().unwrap().trie.longest_match(lookup).is_none()
-            } else {
-              false
-            }
-          })
-          .cloned()
-          .collect();
-
-          host_counts = (really_unknown.len() as u32, 0);
+        return NoCache::new(MsgPack(host_counts_from(&unknowns)));
       }
     }
   }
 
   let n_devices = SHAPED_DEVICES.read().unwrap().devices.len();
-  let unknown = host_counts.0 - host_counts.1;
-  NoCache::new(MsgPack((n_devices as u32, unknown)))
+  NoCache::new(MsgPack((n_devices as u32, 0)))
 }
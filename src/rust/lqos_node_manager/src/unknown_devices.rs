@@ -1,12 +1,18 @@
 use std::net::IpAddr;
 
 use crate::{
-  auth_guard::AuthGuard, cache_control::NoCache, tracker::SHAPED_DEVICES
+  auth_guard::AuthGuard, cache_control::NoCache, scope::Scope,
+  tracker::SHAPED_DEVICES
 };
 use lqos_bus::{IpStats, bus_request, BusRequest, BusResponse};
 use rocket::serde::json::Json;
 
-async fn unknown_devices() -> Vec<IpStats> {
+/// Hosts passing traffic that aren't in `ShapedDevices.csv`. They
+/// aren't in any circuit, so users with a scope see none.
+pub async fn unknown_devices(scope: &Scope) -> Vec<IpStats> {
+  if scope.is_restricted() {
+    return Vec::new();
+  }
   if let Ok(messages) = bus_request(vec![BusRequest::AllUnknownIps]).await {
     for msg in messages {
       if let BusResponse::AllUnknownIps(unknowns) = msg {
@@ -35,13 +41,13 @@ async fn unknown_devices() -> Vec<IpStats> {
 }
 
 #[get("/api/all_unknown_devices")]
-pub async fn all_unknown_devices(_auth: AuthGuard) -> NoCache<Json<Vec<IpStats>>> {
-  NoCache::new(Json(unknown_devices().await))
+pub async fn all_unknown_devices(_auth: AuthGuard, scope: Scope) -> NoCache<Json<Vec<IpStats>>> {
+  NoCache::new(Json(unknown_devices(&scope).await))
 }
 
 #[get("/api/unknown_devices_count")]
-pub async fn unknown_devices_count(_auth: AuthGuard) -> NoCache<Json<usize>> {
-  NoCache::new(Json(unknown_devices().await.len()))
+pub async fn unknown_devices_count(_auth: AuthGuard, scope: Scope) -> NoCache<Json<usize>> {
+  NoCache::new(Json(unknown_devices(&scope).await.len()))
 }
 
 #[get("/api/unknown_devices_range/<start>/<end>")]
@@ -49,17 +55,18 @@ pub async fn unknown_devices_range(
   start: usize,
   end: usize,
   _auth: AuthGuard,
+  scope: Scope,
 ) -> NoCache<Json<Vec<IpStats>>> {
-  let reader = unknown_devices().await;
+  let reader = unknown_devices(&scope).await;
   let result: Vec<IpStats> =
     reader.iter().skip(start).take(end).cloned().collect();
   NoCache::new(Json(result))
 }
 
 #[get("/api/unknown_devices_csv")]
-pub async fn unknown_devices_csv(_auth: AuthGuard) -> NoCache<String> {
+pub async fn unknown_devices_csv(_auth: AuthGuard, scope: Scope) -> NoCache<String> {
   let mut result = "IP Address,Download,Upload\n".to_string();
-  let reader = unknown_devices().await;
+  let reader = unknown_devices(&scope).await;
 
   for unknown in reader.iter() {
     result += &format!("{},{},{}\n", unknown.ip_address, unknown.bits_per_second.0, unknown.bits_per_second.1);
@@ -10,13 +10,58 @@ function msgPackGet(url, success) {
     xhr.send(null);
 }
 
+// Opens a live-update WebSocket and passes each decoded frame to
+// onFrame, reconnecting if the connection drops. path is a function,
+// so a reconnection picks up the page's current filters. send() passes
+// a text message (such as a new filter) to the server.
+function liveChannel(path, onFrame) {
+    let channel = {
+        socket: null,
+        send: (text) => {
+            if (channel.socket != null && channel.socket.readyState == WebSocket.OPEN) {
+                channel.socket.send(text);
+            }
+        },
+    };
+    function connect() {
+        let scheme = window.location.protocol == "https:" ? "wss://" : "ws://";
+        let socket = new WebSocket(scheme + window.location.host + path());
+        socket.binaryType = "arraybuffer";
+        socket.onmessage = (event) => {
+            onFrame(msgpack.decode(new Uint8Array(event.data)));
+        };
+        socket.onclose = (event) => {
+            if (event.code == 1008) {
+                // The session ended; reloading takes us to the login page
+                window.location.reload();
+            } else {
+                setTimeout(connect, 5000);
+            }
+        };
+        channel.socket = socket;
+    }
+    connect();
+    return channel;
+}
+
 const NetTrans = {
     "name": 0,
     "max_throughput": 1,
     "current_throughput": 2,
     "rtts": 3,
     "parents": 4,
-    "immediate_parent": 5
+    "immediate_parent": 5,
+    "node_type": 6,
+    "id": 7,
+    "parent_ids": 8
+}
+
+// Link to a node's tree page, by stable id where there is one
+function treeUrl(index, id) {
+    if (id != null && id != "") {
+        return "/tree?id=" + encodeURIComponent(id);
+    }
+    return "/tree?parent=" + index;
 }
 
 const Circuit = {
@@ -34,6 +79,7 @@ const IpStats = {
     "tc_handle": 4,
     "circuit_id": 5,
     "plan": 6,
+    "tags": 7,
 }
 
 const FlowTrans = {
@@ -48,6 +94,36 @@ const FlowTrans = {
     "ecn": 8
 }
 
+const DashFrame = { // /ws/dashboard
+    "throughput": 0,
+    "site_funnel": 1,
+    "cpu": 2,
+    "ram": 3,
+    "top_downloaders": 4,
+    "worst_rtt": 5,
+    "rtt_histogram": 6,
+    "host_counts": 7,
+}
+
+const CircuitFrame = { // /ws/circuit/<circuit_id>
+    "queue": 0,
+    "throughput": 1,
+    "funnel": 2,
+    "flows": 3,
+}
+
+const CircuitSample = { // /api/circuit_history/<circuit_id>
+    "timestamp": 0,
+    "plan_mbps": 1,
+    "bits_avg": 2,
+    "bits_max": 3,
+    "rtt_p50": 4,
+    "rtt_p95": 5,
+    "drops": 6,
+    "marks": 7,
+    "backlog": 8,
+}
+
 const CircuitInfo = {
     "name" : 0,
     "capacity" : 1,
@@ -58,6 +134,8 @@ const QD = { // Queue data
     "history_head": 1,
     "current_download": 2,
     "current_upload": 3,
+    "kind": 4,
+    "sqm_profile": 5,
 }
 
 const CT = { // Cake transit
@@ -185,14 +263,50 @@ function updateHostCounts() {
     });
 }
 
+function reloadLibreQoS() {
+    $.get("/api/reload_libreqos", (result) => {
+        const myModal = new bootstrap.Modal(document.getElementById('reloadModal'), { focus: true });
+        $("#reloadLibreResult").text(result);
+        myModal.show();
+    });
+}
+
+function showValidationFindings(findings) {
+    const files = { ShapedDevices: "ShapedDevices.csv", NetworkJson: "network.json", IspConfig: "ispConfig.py" };
+    let html = "<table class='table table-sm'><thead><tr><th>Severity</th><th>File</th><th>Line</th><th>Circuit / Node</th><th>Message</th></tr></thead><tbody>";
+    for (const f of findings) {
+        const color = f.severity == "Error" ? "text-danger" : "text-warning";
+        let where = f.circuit_id != null ? f.circuit_id : "";
+        if (f.node != null) where = f.node;
+        html += "<tr><td class='" + color + "'>" + f.severity + "</td>";
+        html += "<td>" + files[f.source] + "</td>";
+        html += "<td>" + (f.line != null ? f.line : "") + "</td>";
+        html += "<td>" + $("<div>").text(where).html() + "</td>";
+        html += "<td>" + $("<div>").text(f.message).html() + "</td></tr>";
+    }
+    html += "</tbody></table>";
+    $("#validationFindings").html(html);
+    const myModal = new bootstrap.Modal(document.getElementById('validationModal'), { focus: true });
+    $("#btnReloadAnyway").off('click').on('click', () => {
+        myModal.hide();
+        reloadLibreQoS();
+    });
+    myModal.show();
+}
+
 function colorReloadButton() {
     $("body").append(reloadModal);
+    $("body").append(validationModal);
     $("#btnReload").on('click', () => {
-        $.get("/api/reload_libreqos", (result) => {
-            const myModal = new bootstrap.Modal(document.getElementById('reloadModal'), { focus: true });
-            $("#reloadLibreResult").text(result);
-            myModal.show();
-        });
+        // Check the configuration first, so problems can be fixed before
+        // they reach the shaper.
+        $.get("/api/validate_config", (findings) => {
+            if (findings.length > 0) {
+                showValidationFindings(findings);
+            } else {
+                reloadLibreQoS();
+            }
+        }).fail(() => reloadLibreQoS());
     });
     $.get("/api/reload_required", (req) => {
         if (req) {
@@ -291,6 +405,24 @@ const reloadModal = `
     </div>
   </div>`;
 
+const validationModal = `
+<div class='modal fade' id='validationModal' tabindex='-1' aria-labelledby='validationModalLabel' aria-hidden='true'>
+    <div class='modal-dialog modal-xl'>
+      <div class='modal-content'>
+        <div class='modal-header'>
+          <h1 class='modal-title fs-5' id='validationModalLabel'>Configuration Problems</h1>
+          <button type='button' class='btn-close' data-bs-dismiss='modal' aria-label='Close'></button>
+        </div>
+        <div class='modal-body' id='validationFindings'>
+        </div>
+        <div class='modal-footer'>
+          <button type='button' class='btn btn-secondary' data-bs-dismiss='modal'>Cancel</button>
+          <button type='button' class='btn btn-warning' id='btnReloadAnyway'>Reload Anyway</button>
+        </div>
+      </div>
+    </div>
+  </div>`;
+
 // MultiRingBuffer provides an interface for storing multiple ring-buffers
 // of performance data, with a view to them ending up on the same graph.
 class MultiRingBuffer {
@@ -3,9 +3,11 @@ use lqos_utils::hex_string::read_hex_string;
 use nix::libc::getpid;
 use pyo3::{
   exceptions::PyOSError, pyclass, pyfunction, pymethods, pymodule,
-  types::PyModule, wrap_pyfunction, PyResult, Python,
+  types::{PyDict, PyList, PyModule}, wrap_pyfunction, PyObject, PyResult,
+  Python, ToPyObject,
 };
 use std::{
+  collections::HashMap,
   fs::{remove_file, File},
   io::Write,
   path::Path,
@@ -32,6 +34,9 @@ fn liblqos_python(_py: Python, m: &PyModule) -> PyResult<()> {
   m.add_wrapped(wrap_pyfunction!(is_libre_already_running))?;
   m.add_wrapped(wrap_pyfunction!(create_lock_file))?;
   m.add_wrapped(wrap_pyfunction!(free_lock_file))?;
+  m.add_wrapped(wrap_pyfunction!(interface_pairs))?;
+  m.add_wrapped(wrap_pyfunction!(isp_settings))?;
+  m.add_wrapped(wrap_pyfunction!(sqm_profiles))?;
   Ok(())
 }
 
@@ -197,6 +202,101 @@ impl BatchedCommands {
   }
 }
 
+/// Provides a representation of a shaping bridge pair.
+/// Available through python by field name.
+#[pyclass]
+pub struct PyInterfacePair {
+  #[pyo3(get)]
+  pub name: String,
+  #[pyo3(get)]
+  pub internet_interface: String,
+  /// The interface download traffic leaves by. On a stick, the same
+  /// as `internet_interface`.
+  #[pyo3(get)]
+  pub isp_interface: String,
+  #[pyo3(get)]
+  pub on_a_stick: bool,
+}
+
+/// Returns the shaping bridge pairs: those listed in `/etc/lqos.conf`,
+/// or the single pair described by `ispConfig.py`.
+#[pyfunction]
+fn interface_pairs() -> PyResult<Vec<PyInterfacePair>> {
+  let pairs = lqos_config::LibreQoSConfig::load()
+    .and_then(|config| config.interface_pairs())
+    .map_err(|e| PyOSError::new_err(e.to_string()))?;
+  Ok(
+    pairs
+      .iter()
+      .map(|pair| PyInterfacePair {
+        name: pair.name.clone(),
+        internet_interface: pair.internet_interface.clone(),
+        isp_interface: pair.isp_facing_interface().to_string(),
+        on_a_stick: pair.on_a_stick,
+      })
+      .collect(),
+  )
+}
+
+/// Returns the settings that replace `ispConfig.py` as a dictionary
+/// of sections (`interfaces`, `queues`, `subnets`, `integrations`), or
+/// `None` if they haven't been migrated into `/etc/lqos.conf`.
+#[pyfunction]
+fn isp_settings(py: Python) -> PyResult<PyObject> {
+  let Some(settings) = lqos_config::IspSettings::load() else {
+    return Ok(py.None());
+  };
+  let json = serde_json::to_value(settings)
+    .map_err(|e| PyOSError::new_err(e.to_string()))?;
+  Ok(json_to_python(py, &json))
+}
+
+/// Returns the SQM profile assigned to each circuit in
+/// `SqmProfiles.toml`, as a dictionary of circuit IDs to
+/// `(profile name, sqm)` tuples.
+#[pyfunction]
+fn sqm_profiles() -> PyResult<HashMap<String, (String, String)>> {
+  let profiles = lqos_config::SqmProfiles::load()
+    .map_err(|e| PyOSError::new_err(e.to_string()))?;
+  Ok(
+    profiles
+      .circuits
+      .keys()
+      .filter_map(|circuit_id| {
+        let profile = profiles.profile_for_circuit(circuit_id)?;
+        Some((
+          circuit_id.clone(),
+          (profile.name.clone(), profile.sqm.trim().to_string()),
+        ))
+      })
+      .collect(),
+  )
+}
+
+fn json_to_python(py: Python, value: &serde_json::Value) -> PyObject {
+  use serde_json::Value;
+  match value {
+    Value::Null => py.None(),
+    Value::Bool(b) => b.to_object(py),
+    Value::Number(n) => match n.as_i64() {
+      Some(i) => i.to_object(py),
+      None => n.as_f64().unwrap_or(0.0).to_object(py),
+    },
+    Value::String(s) => s.to_object(py),
+    Value::Array(items) => {
+      PyList::new(py, items.iter().map(|item| json_to_python(py, item)))
+        .to_object(py)
+    }
+    Value::Object(map) => {
+      let dict = PyDict::new(py);
+      for (key, value) in map.iter() {
+        let _ = dict.set_item(key, json_to_python(py, value));
+      }
+      dict.to_object(py)
+    }
+  }
+}
+
 /// Requests Rust-side validation of `ShapedDevices.csv`
 #[pyfunction]
 fn validate_shaped_devices() -> PyResult<String> {
@@ -0,0 +1,382 @@
+use crate::{tc::Directions, ClassId, PlannedCircuit, PlannedNode, QueuePlan};
+use std::collections::{HashMap, HashSet};
+
+/// An IP mapping: traffic for an address (or network) is classified
+/// into a class, on a CPU.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlannedMapping {
+  /// The address, e.g. `100.64.1.2` or `100.64.2.0/29`
+  pub ip: String,
+  /// The class traffic is placed in
+  pub class_id: ClassId,
+  /// The CPU that handles the class
+  pub cpu: u32,
+  /// Is this the upload mapping? Only used on a stick.
+  pub upload: bool,
+}
+
+/// Everything that has to happen to turn an applied plan into a new
+/// one, in the order it should be done: classes are added before IP
+/// mappings move to them, and removed once nothing maps to them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PlanChanges {
+  /// `tc` commands adding and changing classes and queue
+  /// disciplines, parents first
+  pub tc_additions: Vec<String>,
+  /// IP mappings to add or update
+  pub mappings: Vec<PlannedMapping>,
+  /// IP mappings to remove, as (address, upload)
+  pub unmappings: Vec<(String, bool)>,
+  /// `tc` commands removing classes, children first
+  pub tc_removals: Vec<String>,
+}
+
+impl PlanChanges {
+  /// Is there nothing to do?
+  pub fn is_empty(&self) -> bool {
+    self.tc_additions.is_empty()
+      && self.mappings.is_empty()
+      && self.unmappings.is_empty()
+      && self.tc_removals.is_empty()
+  }
+}
+
+impl QueuePlan {
+  /// Every IP mapping in the plan. On a stick, each address is mapped
+  /// once per direction.
+  pub fn ip_mappings(&self) -> Vec<PlannedMapping> {
+    let mut mappings = Vec::new();
+    for circuit in self.circuits.iter() {
+      let addresses = circuit
+        .devices
+        .iter()
+        .flat_map(|d| d.ipv4.iter().chain(d.ipv6.iter()));
+      for ip in addresses {
+        mappings.push(PlannedMapping {
+          ip: ip.clone(),
+          class_id: circuit.class_id,
+          cpu: circuit.cpu,
+          upload: false,
+        });
+        if self.settings.on_a_stick {
+          mappings.push(PlannedMapping {
+            ip: ip.clone(),
+            class_id: circuit.up_class_id,
+            cpu: circuit.up_cpu,
+            upload: true,
+          });
+        }
+      }
+    }
+    mappings
+  }
+
+  /// The changes that turn `old`, as applied, into this plan. Returns
+  /// `None` if this plan wasn't built from `old` (for example because
+  /// the settings changed), in which case it has to be applied in full.
+  pub fn changes_from(&self, old: &QueuePlan) -> Option<PlanChanges> {
+    if self.based_on != Some(old.revision) {
+      return None;
+    }
+    let mut changes = PlanChanges::default();
+
+    let old_nodes: HashMap<ClassId, &PlannedNode> =
+      old.nodes.iter().map(|n| (n.class_id, n)).collect();
+    let old_circuits: HashMap<ClassId, &PlannedCircuit> =
+      old.circuits.iter().map(|c| (c.class_id, c)).collect();
+    let mut kept: HashSet<ClassId> = HashSet::new();
+    let circuits = self.circuits_by_node();
+    for (index, node) in self.nodes.iter().enumerate() {
+      let previous = old_nodes.get(&node.class_id).filter(|o| {
+        o.name == node.name && o.parent_class_id == node.parent_class_id
+      });
+      match previous {
+        Some(o) => {
+          kept.insert(node.class_id);
+          let changed = [
+            (o.download_min_mbps, o.download_mbps)
+              != (node.download_min_mbps, node.download_mbps),
+            (o.upload_min_mbps, o.upload_mbps)
+              != (node.upload_min_mbps, node.upload_mbps),
+          ];
+          self.each_pair(&mut changes.tc_additions, |d| {
+            only_changed(d.node_classes(node, "change"), changed)
+          });
+        }
+        None => self.each_pair(&mut changes.tc_additions, |d| {
+          d.node_classes(node, "add")
+        }),
+      }
+      for circuit in circuits[index].iter().map(|c| &self.circuits[*c]) {
+        let previous = old_circuits.get(&circuit.class_id).filter(|o| {
+          o.key == circuit.key && o.parent_class_id == circuit.parent_class_id
+        });
+        match previous {
+          Some(o) => {
+            kept.insert(circuit.class_id);
+            self.circuit_changes(old, o, circuit, &mut changes.tc_additions);
+          }
+          None => self.each_pair(&mut changes.tc_additions, |d| {
+            let mut commands = d.circuit_classes(circuit, "add");
+            if let Some(qdiscs) = circuit.qdiscs(&self.settings) {
+              commands.extend(d.circuit_qdiscs(circuit, "add", &qdiscs));
+            }
+            commands
+          }),
+        }
+      }
+    }
+
+    let new_mappings = self.ip_mappings();
+    let mapped: HashMap<(&str, bool), &PlannedMapping> = new_mappings
+      .iter()
+      .map(|m| ((m.ip.as_str(), m.upload), m))
+      .collect();
+    let old_mappings = old.ip_mappings();
+    let previously: HashMap<(&str, bool), &PlannedMapping> = old_mappings
+      .iter()
+      .map(|m| ((m.ip.as_str(), m.upload), m))
+      .collect();
+    for (key, mapping) in mapped.iter() {
+      if previously.get(key) != Some(mapping) {
+        changes.mappings.push((*mapping).clone());
+      }
+    }
+    for key in previously.keys() {
+      if !mapped.contains_key(key) {
+        changes.unmappings.push((key.0.to_string(), key.1));
+      }
+    }
+    changes.mappings.sort_by(|a, b| (&a.ip, a.upload).cmp(&(&b.ip, b.upload)));
+    changes.unmappings.sort();
+
+    for circuit in old.circuits.iter() {
+      if !kept.contains(&circuit.class_id) {
+        self.each_pair(&mut changes.tc_removals, |d| {
+          d.delete_classes(circuit.class_id, circuit.up_class_id)
+        });
+      }
+    }
+    for node in old.nodes.iter().rev() {
+      if !kept.contains(&node.class_id) {
+        self.each_pair(&mut changes.tc_removals, |d| {
+          d.delete_classes(node.class_id, node.up_class_id)
+        });
+      }
+    }
+    Some(changes)
+  }
+
+  /// Changes to the rates and queue disciplines of a circuit that
+  /// kept its classes
+  fn circuit_changes(
+    &self,
+    old: &QueuePlan,
+    was: &PlannedCircuit,
+    now: &PlannedCircuit,
+    commands: &mut Vec<String>,
+  ) {
+    let changed = [
+      (was.download_min_mbps, was.download_max_mbps)
+        != (now.download_min_mbps, now.download_max_mbps),
+      (was.upload_min_mbps, was.upload_max_mbps)
+        != (now.upload_min_mbps, now.upload_max_mbps),
+    ];
+    self.each_pair(commands, |d| {
+      only_changed(d.circuit_classes(now, "change"), changed)
+    });
+
+    let before = was.qdiscs(&old.settings);
+    let after = now.qdiscs(&self.settings);
+    if before == after {
+      return;
+    }
+    match (before, after) {
+      (Some(before), Some(after)) => {
+        let changed = [before.0 != after.0, before.1 != after.1];
+        self.each_pair(commands, |d| {
+          only_changed(d.circuit_qdiscs(now, "replace", &after), changed)
+        });
+      }
+      (None, Some(after)) => self.each_pair(commands, |d| {
+        d.circuit_qdiscs(now, "add", &after)
+      }),
+      (Some(_), None) => {
+        self.each_pair(commands, |d| d.delete_circuit_qdiscs(now))
+      }
+      (None, None) => {}
+    }
+  }
+
+  fn each_pair(
+    &self,
+    commands: &mut Vec<String>,
+    for_pair: impl Fn(&Directions) -> Vec<String>,
+  ) {
+    for pair in self.settings.pairs.iter() {
+      commands.extend(for_pair(&Directions::of(pair)));
+    }
+  }
+}
+
+/// Keeps the download and/or upload command of a pair
+fn only_changed(commands: Vec<String>, changed: [bool; 2]) -> Vec<String> {
+  commands
+    .into_iter()
+    .zip(changed)
+    .filter(|(_, changed)| *changed)
+    .map(|(command, _)| command)
+    .collect()
+}
+
+#[cfg(test)]
+mod test {
+  use crate::plan::test::{device, network, settings};
+  use crate::{PlannerSettings, QueuePlan};
+  use lqos_config::{InterfacePair, ShapedDevice, SqmProfiles};
+
+  fn bridge() -> PlannerSettings {
+    PlannerSettings {
+      pairs: vec![InterfacePair {
+        name: "default".to_string(),
+        internet_interface: "eth0".to_string(),
+        isp_interface: "eth1".to_string(),
+        on_a_stick: false,
+        internet_vlan: 0,
+        isp_vlan: 0,
+        internet_inner_vlan: 0,
+        isp_inner_vlan: 0,
+      }],
+      ..settings(2)
+    }
+  }
+
+  fn devices() -> Vec<ShapedDevice> {
+    vec![
+      device("1", "AP_A", "100.64.0.1", 100),
+      device("2", "AP_A", "100.64.0.2", 100),
+      device("3", "Site_2", "100.64.0.3", 100),
+    ]
+  }
+
+  fn plan(devices: &[ShapedDevice], previous: Option<&QueuePlan>) -> QueuePlan {
+    plan_with(bridge(), devices, previous)
+  }
+
+  fn plan_with(
+    settings: PlannerSettings,
+    devices: &[ShapedDevice],
+    previous: Option<&QueuePlan>,
+  ) -> QueuePlan {
+    QueuePlan::build(
+      settings,
+      &network(),
+      devices,
+      &SqmProfiles::default(),
+      previous,
+    )
+    .unwrap()
+  }
+
+  #[test]
+  fn a_speed_change_is_one_class_change() {
+    let old = plan(&devices(), None);
+    let mut devices = devices();
+    devices[1].download_max_mbps = 200;
+    let new = plan(&devices, Some(&old));
+    let changes = new.changes_from(&old).unwrap();
+    assert_eq!(
+      changes.tc_additions,
+      vec![
+        "class change dev eth1 classid 0x1:0x7 htb rate 1mbit ceil 218mbit \
+         prio 3"
+      ]
+    );
+    assert!(changes.mappings.is_empty() && changes.unmappings.is_empty());
+    assert!(changes.tc_removals.is_empty());
+
+    let unchanged = plan(&devices, Some(&new));
+    assert!(unchanged.changes_from(&new).unwrap().is_empty());
+  }
+
+  #[test]
+  fn new_circuits_get_fresh_class_ids() {
+    let old = plan(&devices(), None);
+    let mut devices = devices();
+    devices.insert(0, device("4", "AP_A", "100.64.0.4", 50));
+    let new = plan(&devices, Some(&old));
+    let changes = new.changes_from(&old).unwrap();
+    let added = new.circuits.iter().find(|c| c.circuit_id == "4").unwrap();
+    // The gap LibreQoS.py left before Site_1's children is unused
+    assert_eq!(added.class_id.minor, 4);
+    assert_eq!(new.next_minor, old.next_minor);
+    assert_eq!(changes.tc_additions.len(), 4);
+    assert!(changes.tc_additions[0].starts_with(&format!(
+      "class add dev eth1 parent 0x1:0x5 classid {}",
+      added.class_id
+    )));
+    assert_eq!(changes.mappings.len(), 1);
+    assert_eq!(changes.mappings[0].ip, "100.64.0.4");
+    // Everything else kept its classes
+    for circuit in old.circuits.iter() {
+      let now = new.circuits.iter().find(|c| c.key == circuit.key).unwrap();
+      assert_eq!(now.class_id, circuit.class_id);
+    }
+  }
+
+  #[test]
+  fn removed_circuits_are_unmapped_then_deleted() {
+    let old = plan(&devices(), None);
+    let new = plan(&devices()[..2], Some(&old));
+    let changes = new.changes_from(&old).unwrap();
+    assert!(changes.tc_additions.is_empty());
+    assert_eq!(changes.unmappings, vec![("100.64.0.3".to_string(), false)]);
+    let removed = &old.circuits[2];
+    assert_eq!(
+      changes.tc_removals,
+      vec![
+        format!("class del dev eth1 classid {}", removed.class_id),
+        format!("class del dev eth0 classid {}", removed.up_class_id),
+      ]
+    );
+  }
+
+  #[test]
+  fn removed_class_ids_are_reused() {
+    let old = plan(&devices(), None);
+    let removed = &old.circuits[2];
+    let without = plan(&devices()[..2], Some(&old));
+    let mut devices = devices();
+    devices[2] = device("4", "Site_2", "100.64.0.4", 100);
+    let new = plan(&devices, Some(&without));
+    let added = new.circuits.iter().find(|c| c.circuit_id == "4").unwrap();
+    assert_eq!(added.class_id, removed.class_id);
+    assert_eq!(new.next_minor, old.next_minor);
+  }
+
+  #[test]
+  fn moved_addresses_are_remapped() {
+    let old = plan(&devices(), None);
+    let mut devices = devices();
+    devices[0].ipv4 = vec![("100.64.1.0".parse().unwrap(), 24)];
+    devices[2].parent_node = "AP_A".to_string();
+    let new = plan(&devices, Some(&old));
+    let changes = new.changes_from(&old).unwrap();
+    let moved = new.circuits.iter().find(|c| c.circuit_id == "3").unwrap();
+    assert_eq!(moved.queue, 1);
+    let remapped: Vec<&str> =
+      changes.mappings.iter().map(|m| m.ip.as_str()).collect();
+    assert_eq!(remapped, vec!["100.64.0.3", "100.64.1.0/24"]);
+    assert_eq!(changes.unmappings, vec![("100.64.0.1".to_string(), false)]);
+    assert_eq!(changes.tc_removals.len(), 2);
+  }
+
+  #[test]
+  fn new_settings_need_a_full_rebuild() {
+    let old = plan(&devices(), None);
+    let settings = PlannerSettings { sqm: "fq_codel".to_string(), ..bridge() };
+    let new = plan_with(settings, &devices(), Some(&old));
+    assert!(new.changes_from(&old).is_none());
+    assert!(new.full_commands().iter().any(|c| c.ends_with("fq_codel")));
+  }
+}
@@ -0,0 +1,132 @@
+use crate::{PlannedDevice, PlannerSettings};
+use lqos_config::{ShapedDevice, SqmProfiles};
+use std::collections::{HashMap, HashSet};
+
+/// Circuit rates are raised by this much to allow for TCP overhead
+const TCP_OVERHEAD_FACTOR: f64 = 1.09;
+
+/// The rate everything is queued at in monitor mode
+pub(crate) const MONITOR_MODE_MBPS: u32 = 10_000;
+
+/// A circuit from `ShapedDevices.csv`, before it is placed in the tree
+#[derive(Clone, Debug)]
+pub(crate) struct Circuit {
+  /// Identifies the circuit between plans: its circuit ID, or for rows
+  /// without one, the device ID
+  pub(crate) key: String,
+  pub(crate) circuit_id: String,
+  pub(crate) circuit_name: String,
+  /// `None` if the circuit has no parent node, and so is placed under
+  /// a generated one
+  pub(crate) parent_node: Option<String>,
+  pub(crate) comment: String,
+  pub(crate) download_min_mbps: u32,
+  pub(crate) upload_min_mbps: u32,
+  pub(crate) download_max_mbps: u32,
+  pub(crate) upload_max_mbps: u32,
+  /// The circuit's SQM profile name and queue discipline
+  pub(crate) sqm_profile: Option<(String, String)>,
+  pub(crate) devices: Vec<PlannedDevice>,
+}
+
+impl Circuit {
+  /// How much the circuit counts for when balancing CPUs
+  pub(crate) fn weight(&self) -> u64 {
+    self.download_max_mbps as u64 + self.upload_max_mbps as u64
+  }
+}
+
+/// Groups `ShapedDevices.csv` rows into circuits, in the order they
+/// first appear. As in `LibreQoS.py`, the first device of a circuit
+/// decides its rates and parent node, and each row without a circuit
+/// ID is a circuit of its own.
+pub(crate) fn group_circuits(
+  devices: &[ShapedDevice],
+  settings: &PlannerSettings,
+  profiles: &SqmProfiles,
+) -> Vec<Circuit> {
+  let mut circuits: Vec<Circuit> = Vec::new();
+  let mut by_id: HashMap<&str, usize> = HashMap::new();
+  let mut keys: HashSet<String> = HashSet::new();
+  for device in devices.iter() {
+    let planned = PlannedDevice::from_shaped_device(device);
+    if !device.circuit_id.is_empty() {
+      if let Some(index) = by_id.get(device.circuit_id.as_str()) {
+        circuits[*index].devices.push(planned);
+        continue;
+      }
+      by_id.insert(&device.circuit_id, circuits.len());
+    }
+
+    let mut key = if device.circuit_id.is_empty() {
+      format!("device:{}", device.device_id)
+    } else {
+      device.circuit_id.clone()
+    };
+    let mut duplicate = 1;
+    while keys.contains(&key) {
+      duplicate += 1;
+      key = format!("device:{}#{duplicate}", device.device_id);
+    }
+    keys.insert(key.clone());
+
+    let parent = device.parent_node.trim();
+    let rate = |mbps: u32| {
+      let mbps = if settings.monitor_mode { MONITOR_MODE_MBPS } else { mbps };
+      (mbps as f64 * TCP_OVERHEAD_FACTOR).round_ties_even() as u32
+    };
+    circuits.push(Circuit {
+      key,
+      circuit_id: device.circuit_id.clone(),
+      circuit_name: if device.circuit_id.is_empty()
+        && device.circuit_name.is_empty()
+      {
+        device.device_name.clone()
+      } else {
+        device.circuit_name.clone()
+      },
+      parent_node: if parent.is_empty() || parent == "none" {
+        None
+      } else {
+        Some(parent.to_string())
+      },
+      comment: device.comment.clone(),
+      download_min_mbps: rate(device.download_min_mbps),
+      upload_min_mbps: rate(device.upload_min_mbps),
+      download_max_mbps: rate(device.download_max_mbps),
+      upload_max_mbps: rate(device.upload_max_mbps),
+      sqm_profile: profiles
+        .profile_for_circuit(&device.circuit_id)
+        .map(|p| (p.name.clone(), p.sqm.clone())),
+      devices: vec![planned],
+    });
+  }
+  circuits
+}
+
+impl PlannedDevice {
+  fn from_shaped_device(device: &ShapedDevice) -> Self {
+    Self {
+      device_id: device.device_id.clone(),
+      device_name: device.device_name.clone(),
+      mac: device.mac.clone(),
+      comment: device.comment.clone(),
+      ipv4: device
+        .ipv4
+        .iter()
+        .map(|(ip, prefix)| match prefix {
+          32 => ip.to_string(),
+          _ => format!("{ip}/{prefix}"),
+        })
+        .collect(),
+      ipv6: device
+        .ipv6
+        .iter()
+        .map(|(ip, prefix)| match prefix {
+          128 => ip.to_string(),
+          _ => format!("{ip}/{prefix}"),
+        })
+        .collect(),
+    }
+  }
+}
@@ -0,0 +1,38 @@
+use thiserror::Error;
+
+/// Errors building or applying a queue plan
+#[derive(Debug, Error)]
+pub enum PlannerError {
+  /// Unable to read or write a file
+  #[error("Unable to access {0}: {1}")]
+  Io(String, String),
+
+  /// `network.json` isn't valid, or doesn't match the expected layout
+  #[error("Unable to parse network.json: {0}")]
+  NetworkJson(String),
+
+  /// `queuingStructure.json` isn't valid, or describes queues that
+  /// don't match the current settings
+  #[error("Unable to use queuingStructure.json: {0}")]
+  QueuingStructure(String),
+
+  /// Unable to load the LibreQoS configuration
+  #[error("Unable to load the configuration: {0}")]
+  Config(String),
+
+  /// Unable to count an interface's transmit queues
+  #[error("Unable to count the queues of interface {0}")]
+  QueueCount(String),
+
+  /// LibreQoS needs at least two queues per interface
+  #[error("Interface {0} has {1} queue(s); LibreQoS needs 2 or more")]
+  TooFewQueues(String, u32),
+
+  /// A queue has run out of HTB minor class IDs
+  #[error("Queue {0} has run out of class IDs")]
+  ClassIdsExhausted(u32),
+
+  /// `tc` couldn't be run, or reported errors
+  #[error("tc failed: {0}")]
+  Tc(String),
+}
@@ -0,0 +1,29 @@
+//! `lqos_queue_planner` builds the LibreQoS queue structure from
+//! `network.json` and `ShapedDevices.csv`, the same way `LibreQoS.py`
+//! does: it assigns nodes and circuits to CPUs, hands out HTB class
+//! IDs and writes `queuingStructure.json` in the same format.
+//!
+//! Given the plan that is already applied, a new plan keeps the class
+//! IDs of everything that didn't move, and [`QueuePlan::changes_from`]
+//! lists just the `tc` commands and IP mappings needed to get from one
+//! to the other. Changing one customer's speed becomes a single
+//! `tc class change` per interface, rather than a full rebuild.
+
+#![warn(missing_docs)]
+mod changes;
+mod circuits;
+mod errors;
+mod network;
+mod plan;
+mod queuing_structure;
+mod settings;
+mod tc;
+
+pub use changes::{PlanChanges, PlannedMapping};
+pub use errors::PlannerError;
+pub use network::{load_network, parse_network, NetworkNode};
+pub use plan::{
+  ClassId, PlannedCircuit, PlannedDevice, PlannedNode, QueuePlan,
+};
+pub use settings::PlannerSettings;
+pub use tc::run_tc_batch;
@@ -0,0 +1,148 @@
+use crate::PlannerError;
+use serde::{
+  de::{MapAccess, Visitor},
+  Deserialize, Deserializer,
+};
+use std::{fmt, path::Path};
+
+/// A `network.json` node. Children are kept in file order, because
+/// the order decides which CPU each top-level node lands on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NetworkNode {
+  /// The node's name, which circuits use as their parent node
+  pub name: String,
+
+  /// Download capacity, in Mbps
+  pub download_mbps: u32,
+
+  /// Upload capacity, in Mbps
+  pub upload_mbps: u32,
+
+  /// Nodes below this one
+  pub children: Vec<NetworkNode>,
+}
+
+/// Reads `network.json`.
+pub fn load_network(path: &Path) -> Result<Vec<NetworkNode>, PlannerError> {
+  let raw = std::fs::read_to_string(path)
+    .map_err(|e| PlannerError::Io(path.display().to_string(), e.to_string()))?;
+  parse_network(&raw)
+}
+
+/// Parses the contents of `network.json`.
+pub fn parse_network(raw: &str) -> Result<Vec<NetworkNode>, PlannerError> {
+  serde_json::from_str::<Level>(raw)
+    .map(|level| level.0)
+    .map_err(|e| PlannerError::NetworkJson(e.to_string()))
+}
+
+/// One level of the tree: a JSON object of nodes, by name
+struct Level(Vec<NetworkNode>);
+
+impl<'de> Deserialize<'de> for Level {
+  fn deserialize<D: Deserializer<'de>>(
+    deserializer: D,
+  ) -> Result<Self, D::Error> {
+    struct LevelVisitor;
+
+    impl<'de> Visitor<'de> for LevelVisitor {
+      type Value = Level;
+
+      fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an object of network nodes")
+      }
+
+      fn visit_map<A: MapAccess<'de>>(
+        self,
+        mut map: A,
+      ) -> Result<Level, A::Error> {
+        let mut nodes = Vec::new();
+        while let Some((name, node)) = map.next_entry::<String, RawNode>()? {
+          nodes.push(NetworkNode {
+            name,
+            download_mbps: node.download.0,
+            upload_mbps: node.upload.0,
+            children: node.children.map(|c| c.0).unwrap_or_default(),
+          });
+        }
+        Ok(Level(nodes))
+      }
+    }
+
+    deserializer.deserialize_map(LevelVisitor)
+  }
+}
+
+#[derive(Deserialize)]
+struct RawNode {
+  #[serde(rename = "downloadBandwidthMbps")]
+  download: Mbps,
+  #[serde(rename = "uploadBandwidthMbps")]
+  upload: Mbps,
+  #[serde(default)]
+  children: Option<Level>,
+}
+
+/// A rate, which integrations sometimes write as a string or a float
+struct Mbps(u32);
+
+impl<'de> Deserialize<'de> for Mbps {
+  fn deserialize<D: Deserializer<'de>>(
+    deserializer: D,
+  ) -> Result<Self, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+      Integer(u64),
+      Float(f64),
+      Text(String),
+    }
+    let invalid = || serde::de::Error::custom("invalid bandwidth");
+    let mbps = match Raw::deserialize(deserializer)? {
+      Raw::Integer(n) => n.min(u32::MAX as u64) as u32,
+      Raw::Float(n) if n >= 0.0 => n as u32,
+      Raw::Float(_) => return Err(invalid()),
+      Raw::Text(s) => s.trim().parse().map_err(|_| invalid())?,
+    };
+    Ok(Mbps(mbps))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn nodes_keep_file_order() {
+    let network = parse_network(
+      r#"{
+        "Zulu": { "downloadBandwidthMbps": 1000, "uploadBandwidthMbps": "500",
+          "type": "site", "children": {
+            "Bravo": { "downloadBandwidthMbps": 100.0,
+              "uploadBandwidthMbps": 50 },
+            "Alpha": { "downloadBandwidthMbps": 100, "uploadBandwidthMbps": 50,
+              "children": {} }
+          } },
+        "Alpha Site": { "downloadBandwidthMbps": 200,
+          "uploadBandwidthMbps": 200 }
+      }"#,
+    )
+    .unwrap();
+    let names: Vec<&str> = network.iter().map(|n| n.name.as_str()).collect();
+    assert_eq!(names, vec!["Zulu", "Alpha Site"]);
+    assert_eq!(network[0].upload_mbps, 500);
+    let children: Vec<&str> =
+      network[0].children.iter().map(|n| n.name.as_str()).collect();
+    assert_eq!(children, vec!["Bravo", "Alpha"]);
+    assert_eq!(network[0].children[0].download_mbps, 100);
+  }
+
+  #[test]
+  fn bad_rates_are_rejected() {
+    let result = parse_network(
+      r#"{ "A": { "downloadBandwidthMbps": "fast",
+        "uploadBandwidthMbps": 1 } }"#,
+    );
+    assert!(matches!(result, Err(PlannerError::NetworkJson(_))));
+  }
+}
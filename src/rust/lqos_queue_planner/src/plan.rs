@@ -0,0 +1,862 @@
+use crate::{
+  circuits::{group_circuits, Circuit, MONITOR_MODE_MBPS},
+  NetworkNode, PlannerError, PlannerSettings,
+};
+use log::warn;
+use lqos_config::{ShapedDevice, SqmProfiles};
+use std::{
+  cmp::Reverse,
+  collections::{BTreeMap, HashMap, HashSet},
+  fmt,
+  sync::atomic::{AtomicU64, Ordering},
+};
+
+/// The first minor class ID handed out on each queue. 1 and 2 are the
+/// queue's root and default classes.
+const FIRST_MINOR: u32 = 3;
+
+/// HTB minor class IDs are 16 bits
+const MAX_MINOR: u32 = 0xffff;
+
+/// HTB can only nest so deep, so `LibreQoS.py` flattens the tree
+/// below this (doubled, plus one) depth.
+const MAX_NESTED_DEPTH: usize = 8;
+
+static NEXT_REVISION: AtomicU64 = AtomicU64::new(1);
+
+/// A number identifying a newly made plan
+pub(crate) fn next_revision() -> u64 {
+  NEXT_REVISION.fetch_add(1, Ordering::Relaxed)
+}
+
+/// An HTB class handle, `major:minor`. Each queue (CPU) has its own
+/// major number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ClassId {
+  /// The queue's HTB handle
+  pub major: u32,
+  /// The class within the queue
+  pub minor: u32,
+}
+
+impl ClassId {
+  /// The handle as a single number, as the IP mapping table stores it
+  pub fn as_u32(&self) -> u32 {
+    (self.major << 16) | self.minor
+  }
+}
+
+impl fmt::Display for ClassId {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "0x{:x}:0x{:x}", self.major, self.minor)
+  }
+}
+
+/// A device belonging to a planned circuit
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlannedDevice {
+  /// Device ID from `ShapedDevices.csv`
+  pub device_id: String,
+  /// Device name
+  pub device_name: String,
+  /// MAC address, if known
+  pub mac: String,
+  /// Comment
+  pub comment: String,
+  /// IPv4 addresses and networks, e.g. `100.64.1.2` or `100.64.2.0/29`
+  pub ipv4: Vec<String>,
+  /// IPv6 addresses and networks
+  pub ipv6: Vec<String>,
+}
+
+/// A `network.json` node, placed on a queue with its HTB classes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlannedNode {
+  /// The node's name
+  pub name: String,
+  /// The index of the parent node in [`QueuePlan::nodes`], or `None`
+  /// at the top of the tree
+  pub parent: Option<usize>,
+  /// The queue (1-based) the node and everything below it use
+  pub queue: u32,
+  /// The download CPU
+  pub cpu: u32,
+  /// The upload CPU
+  pub up_cpu: u32,
+  /// The download class
+  pub class_id: ClassId,
+  /// The upload class
+  pub up_class_id: ClassId,
+  /// The parent node's download class, or `None` for the queue's root
+  pub parent_class_id: Option<ClassId>,
+  /// The parent node's upload class, or `None` for the queue's root
+  pub up_parent_class_id: Option<ClassId>,
+  /// Download ceiling, in Mbps
+  pub download_mbps: u32,
+  /// Upload ceiling, in Mbps
+  pub upload_mbps: u32,
+  /// Guaranteed download rate, in Mbps
+  pub download_min_mbps: u32,
+  /// Guaranteed upload rate, in Mbps
+  pub upload_min_mbps: u32,
+}
+
+impl PlannedNode {
+  /// The download parent handle passed to `tc`, e.g. `0x1:` for the
+  /// queue's root
+  pub fn parent_handle(&self) -> String {
+    handle_or_root(self.parent_class_id, self.class_id.major)
+  }
+
+  /// The upload parent handle passed to `tc`
+  pub fn up_parent_handle(&self) -> String {
+    handle_or_root(self.up_parent_class_id, self.up_class_id.major)
+  }
+}
+
+fn handle_or_root(class_id: Option<ClassId>, major: u32) -> String {
+  class_id.map_or(format!("0x{major:x}:"), |id| id.to_string())
+}
+
+/// A circuit, placed under its parent node with its HTB classes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlannedCircuit {
+  /// Identifies the circuit between plans: its circuit ID, or the
+  /// device ID of circuits without one
+  pub key: String,
+  /// Circuit ID
+  pub circuit_id: String,
+  /// Circuit name
+  pub circuit_name: String,
+  /// The parent node's name
+  pub parent_node: String,
+  /// The index of the parent node in [`QueuePlan::nodes`]
+  pub node: usize,
+  /// Comment
+  pub comment: String,
+  /// The queue (1-based)
+  pub queue: u32,
+  /// The download CPU
+  pub cpu: u32,
+  /// The upload CPU
+  pub up_cpu: u32,
+  /// The download class
+  pub class_id: ClassId,
+  /// The upload class
+  pub up_class_id: ClassId,
+  /// The parent node's download class
+  pub parent_class_id: ClassId,
+  /// The parent node's upload class
+  pub up_parent_class_id: ClassId,
+  /// Guaranteed download rate, in Mbps
+  pub download_min_mbps: u32,
+  /// Guaranteed upload rate, in Mbps
+  pub upload_min_mbps: u32,
+  /// Download ceiling, in Mbps
+  pub download_max_mbps: u32,
+  /// Upload ceiling, in Mbps
+  pub upload_max_mbps: u32,
+  /// The circuit's SQM profile, if it has one
+  pub sqm_profile: Option<String>,
+  /// The SQM profile's queue discipline, which replaces the global one
+  pub sqm: Option<String>,
+  /// The circuit's devices
+  pub devices: Vec<PlannedDevice>,
+}
+
+impl PlannedCircuit {
+  /// The queue disciplines attached to the circuit's download and
+  /// upload classes, or `None` for plain HTB.
+  pub fn qdiscs(&self, settings: &PlannerSettings) -> Option<(String, String)> {
+    let sqm = self.sqm.as_deref().unwrap_or(&settings.sqm);
+    if settings.monitor_mode || sqm == "none" {
+      return None;
+    }
+    Some((
+      sqm_fixup_rate(self.download_max_mbps, sqm),
+      sqm_fixup_rate(self.upload_max_mbps, sqm),
+    ))
+  }
+}
+
+/// Cake needs a longer `rtt` to cope with rates below 5 Mbps, where a
+/// single MTU takes several milliseconds to send.
+fn sqm_fixup_rate(mbps: u32, sqm: &str) -> String {
+  if !sqm.starts_with("cake") || sqm.contains("rtt") {
+    return sqm.to_string();
+  }
+  match mbps {
+    1 => format!("{sqm} rtt 300"),
+    2 => format!("{sqm} rtt 180"),
+    3 => format!("{sqm} rtt 140"),
+    4 => format!("{sqm} rtt 120"),
+    _ => sqm.to_string(),
+  }
+}
+
+/// A complete queue structure: every node and circuit with its queue
+/// and class IDs.
+#[derive(Clone, Debug)]
+pub struct QueuePlan {
+  /// The settings the plan was built with
+  pub settings: PlannerSettings,
+  /// Nodes, parents before their children
+  pub nodes: Vec<PlannedNode>,
+  /// Circuits, in the order they were placed
+  pub circuits: Vec<PlannedCircuit>,
+  /// The names of the generated parent nodes
+  pub generated_pns: Vec<String>,
+  /// The next free minor class ID, by queue
+  pub next_minor: BTreeMap<u32, u32>,
+  /// Devices whose parent node isn't in the tree, as (ID, name)
+  pub unshaped_devices: Vec<(String, String)>,
+  pub(crate) revision: u64,
+  pub(crate) based_on: Option<u64>,
+}
+
+impl QueuePlan {
+  /// Builds a plan. Given the previous plan (built with the same
+  /// settings), nodes and circuits that haven't moved keep their class
+  /// IDs, so that only what changed needs to be applied. Without one,
+  /// the class IDs match those `LibreQoS.py` would choose.
+  pub fn build(
+    settings: PlannerSettings,
+    network: &[NetworkNode],
+    devices: &[ShapedDevice],
+    profiles: &SqmProfiles,
+    previous: Option<&QueuePlan>,
+  ) -> Result<Self, PlannerError> {
+    if settings.queues == 0 {
+      return Err(PlannerError::Config("No queues are available".to_string()));
+    }
+    let previous = previous.filter(|p| p.settings == settings);
+    let generated_pns: Vec<String> =
+      (1..=settings.queues).map(|n| format!("Generated_PN_{n}")).collect();
+    let mut circuits = group_circuits(devices, &settings, profiles);
+    assign_generated_parents(
+      &mut circuits,
+      &settings,
+      &generated_pns,
+      previous,
+    );
+    let tree = prepare_tree(network, &settings, &generated_pns);
+
+    let plan = match previous {
+      Some(previous) => {
+        match Builder::new(&settings, &circuits, Some(previous)).run(&tree) {
+          Err(PlannerError::ClassIdsExhausted(queue)) => {
+            warn!("Queue {queue} has run out of class IDs; rebuilding them");
+            Builder::new(&settings, &circuits, None).run(&tree)?
+          }
+          result => result?,
+        }
+      }
+      None => Builder::new(&settings, &circuits, None).run(&tree)?,
+    };
+    Ok(Self { settings, generated_pns, ..plan })
+  }
+
+  /// The indices of each node's children
+  pub(crate) fn children_by_node(&self) -> Vec<Vec<usize>> {
+    let mut children = vec![Vec::new(); self.nodes.len()];
+    for (index, node) in self.nodes.iter().enumerate() {
+      if let Some(parent) = node.parent {
+        children[parent].push(index);
+      }
+    }
+    children
+  }
+
+  /// The indices of each node's circuits
+  pub(crate) fn circuits_by_node(&self) -> Vec<Vec<usize>> {
+    let mut circuits = vec![Vec::new(); self.nodes.len()];
+    for (index, circuit) in self.circuits.iter().enumerate() {
+      circuits[circuit.node].push(index);
+    }
+    circuits
+  }
+}
+
+/// Places circuits without a parent node under the generated parent
+/// nodes, one per queue: by bandwidth with binpacking, otherwise
+/// round-robin. Circuits keep the generated node they had in the
+/// previous plan.
+fn assign_generated_parents(
+  circuits: &mut [Circuit],
+  settings: &PlannerSettings,
+  generated: &[String],
+  previous: Option<&QueuePlan>,
+) {
+  let weight = |circuit: &Circuit| {
+    if settings.use_binpacking {
+      circuit.weight()
+    } else {
+      1
+    }
+  };
+  let previous_parents: HashMap<&str, &str> = previous
+    .map(|p| {
+      p.circuits
+        .iter()
+        .filter(|c| p.generated_pns.contains(&c.parent_node))
+        .map(|c| (c.key.as_str(), c.parent_node.as_str()))
+        .collect()
+    })
+    .unwrap_or_default();
+
+  let mut load = vec![0; generated.len()];
+  let mut unplaced = Vec::new();
+  for (index, circuit) in circuits.iter_mut().enumerate() {
+    if circuit.parent_node.is_some() {
+      continue;
+    }
+    let kept = previous_parents
+      .get(circuit.key.as_str())
+      .and_then(|parent| generated.iter().position(|g| g == parent));
+    match kept {
+      Some(bin) => {
+        load[bin] += weight(circuit);
+        circuit.parent_node = Some(generated[bin].clone());
+      }
+      None => unplaced.push(index),
+    }
+  }
+  if settings.use_binpacking {
+    unplaced.sort_by_key(|index| Reverse(circuits[*index].weight()));
+  }
+  for index in unplaced {
+    let bin = (0..load.len()).min_by_key(|bin| load[*bin]).unwrap_or(0);
+    load[bin] += weight(&circuits[index]);
+    circuits[index].parent_node = Some(generated[bin].clone());
+  }
+}
+
+/// Adds the generated parent nodes, caps each node at its parent's
+/// rates and flattens the tree to the depth HTB supports.
+fn prepare_tree(
+  network: &[NetworkNode],
+  settings: &PlannerSettings,
+  generated: &[String],
+) -> Vec<NetworkNode> {
+  let (download, upload) = if settings.monitor_mode {
+    (MONITOR_MODE_MBPS, MONITOR_MODE_MBPS)
+  } else {
+    (settings.generated_download_mbps, settings.generated_upload_mbps)
+  };
+  let mut tree = network.to_vec();
+  tree.extend(generated.iter().map(|name| NetworkNode {
+    name: name.clone(),
+    download_mbps: download,
+    upload_mbps: upload,
+    children: Vec::new(),
+  }));
+  inherit_maxes(
+    &mut tree,
+    settings.upstream_download_mbps,
+    settings.upstream_upload_mbps,
+  );
+  flatten(tree, 1)
+}
+
+fn inherit_maxes(nodes: &mut [NetworkNode], download: u32, upload: u32) {
+  for node in nodes.iter_mut() {
+    node.download_mbps = node.download_mbps.min(download);
+    node.upload_mbps = node.upload_mbps.min(upload);
+    inherit_maxes(&mut node.children, node.download_mbps, node.upload_mbps);
+  }
+}
+
+/// Below the maximum depth, every descendant becomes a direct child.
+/// Depth counts up by two per level, as in `LibreQoS.py`.
+fn flatten(nodes: Vec<NetworkNode>, depth: usize) -> Vec<NetworkNode> {
+  nodes
+    .into_iter()
+    .map(|mut node| {
+      let children = std::mem::take(&mut node.children);
+      node.children = if depth <= MAX_NESTED_DEPTH {
+        flatten(children, depth + 2)
+      } else {
+        let mut flat = Vec::new();
+        flatten_into(children, &mut flat);
+        flat
+      };
+      node
+    })
+    .collect()
+}
+
+fn flatten_into(nodes: Vec<NetworkNode>, flat: &mut Vec<NetworkNode>) {
+  for mut node in nodes {
+    let children = std::mem::take(&mut node.children);
+    flat.push(node);
+    flatten_into(children, flat);
+  }
+}
+
+/// Walks the tree, handing out queues and class IDs.
+struct Builder<'a> {
+  settings: &'a PlannerSettings,
+  circuits: &'a [Circuit],
+  /// Unplaced circuits, by parent node name
+  by_parent: HashMap<&'a str, Vec<usize>>,
+  previous: Option<&'a QueuePlan>,
+  previous_nodes: HashMap<&'a str, &'a PlannedNode>,
+  previous_circuits: HashMap<&'a str, &'a PlannedCircuit>,
+  /// Previous class IDs already taken by this plan
+  claimed: HashSet<ClassId>,
+  /// The next free minor, by queue - 1
+  next_minor: Vec<u32>,
+  /// Minors below `next_minor` that the previous plan didn't use, by
+  /// queue - 1, lowest last
+  free_minors: Vec<Vec<u32>>,
+  nodes: Vec<PlannedNode>,
+  planned: Vec<PlannedCircuit>,
+}
+
+impl<'a> Builder<'a> {
+  fn new(
+    settings: &'a PlannerSettings,
+    circuits: &'a [Circuit],
+    previous: Option<&'a QueuePlan>,
+  ) -> Self {
+    let mut by_parent: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (index, circuit) in circuits.iter().enumerate() {
+      if let Some(parent) = &circuit.parent_node {
+        by_parent.entry(parent.as_str()).or_default().push(index);
+      }
+    }
+    let next_minor: Vec<u32> = (1..=settings.queues)
+      .map(|queue| {
+        previous
+          .and_then(|p| p.next_minor.get(&queue).copied())
+          .unwrap_or(FIRST_MINOR)
+      })
+      .collect();
+    // Minors freed by removed or moved nodes and circuits are handed
+    // out again before new ones, so that churn doesn't use up the
+    // range. Those freed by this plan wait for the next one, once
+    // their classes have been deleted.
+    let free_minors = (1..=settings.queues)
+      .map(|queue| {
+        let Some(previous) = previous else { return Vec::new() };
+        let used: HashSet<u32> = previous
+          .nodes
+          .iter()
+          .filter(|n| n.queue == queue)
+          .map(|n| n.class_id.minor)
+          .chain(
+            previous
+              .circuits
+              .iter()
+              .filter(|c| c.queue == queue)
+              .map(|c| c.class_id.minor),
+          )
+          .collect();
+        (FIRST_MINOR..next_minor[queue as usize - 1])
+          .rev()
+          .filter(|minor| !used.contains(minor))
+          .collect()
+      })
+      .collect();
+    Self {
+      settings,
+      circuits,
+      by_parent,
+      previous,
+      previous_nodes: previous
+        .map(|p| p.nodes.iter().map(|n| (n.name.as_str(), n)).collect())
+        .unwrap_or_default(),
+      previous_circuits: previous
+        .map(|p| p.circuits.iter().map(|c| (c.key.as_str(), c)).collect())
+        .unwrap_or_default(),
+      claimed: HashSet::new(),
+      next_minor,
+      free_minors,
+      nodes: Vec::new(),
+      planned: Vec::new(),
+    }
+  }
+
+  fn run(mut self, tree: &[NetworkNode]) -> Result<QueuePlan, PlannerError> {
+    let queues = self.top_level_queues(tree);
+    for (node, queue) in tree.iter().zip(queues) {
+      self.place(node, queue, None)?;
+    }
+
+    let mut unplaced: Vec<usize> =
+      self.by_parent.values().flatten().copied().collect();
+    unplaced.sort_unstable();
+    let unshaped_devices = unplaced
+      .iter()
+      .flat_map(|index| self.circuits[*index].devices.iter())
+      .map(|d| (d.device_id.clone(), d.device_name.clone()))
+      .collect();
+    Ok(QueuePlan {
+      settings: self.settings.clone(),
+      nodes: self.nodes,
+      circuits: self.planned,
+      generated_pns: Vec::new(),
+      next_minor: (1..).zip(self.next_minor).collect(),
+      unshaped_devices,
+      revision: next_revision(),
+      based_on: self.previous.map(|p| p.revision),
+    })
+  }
+
+  /// Top-level nodes keep their previous queue. The rest go to the
+  /// queue with the fewest top-level nodes, which is round-robin when
+  /// starting afresh.
+  fn top_level_queues(&self, tree: &[NetworkNode]) -> Vec<u32> {
+    let mut counts = vec![0; self.settings.queues as usize];
+    let mut queues: Vec<Option<u32>> = tree
+      .iter()
+      .map(|node| {
+        self
+          .previous_nodes
+          .get(node.name.as_str())
+          .filter(|old| {
+            old.parent.is_none() && old.queue <= self.settings.queues
+          })
+          .map(|old| old.queue)
+      })
+      .collect();
+    for queue in queues.iter().flatten() {
+      counts[*queue as usize - 1] += 1;
+    }
+    for queue in queues.iter_mut().filter(|q| q.is_none()) {
+      let emptiest = (0..counts.len()).min_by_key(|q| counts[*q]).unwrap_or(0);
+      counts[emptiest] += 1;
+      *queue = Some(emptiest as u32 + 1);
+    }
+    queues.into_iter().map(|q| q.unwrap_or(1)).collect()
+  }
+
+  fn place(
+    &mut self,
+    node: &NetworkNode,
+    queue: u32,
+    parent: Option<usize>,
+  ) -> Result<(), PlannerError> {
+    let settings = self.settings;
+    let (major, up_major) = (queue, queue + settings.stick_offset());
+    let (parent_class_id, up_parent_class_id, parent_download, parent_upload) =
+      match parent.map(|p| &self.nodes[p]) {
+        Some(p) => (
+          Some(p.class_id),
+          Some(p.up_class_id),
+          p.download_mbps,
+          p.upload_mbps,
+        ),
+        None => (
+          None,
+          None,
+          settings.upstream_download_mbps,
+          settings.upstream_upload_mbps,
+        ),
+      };
+    let (download_mbps, upload_mbps) = if settings.monitor_mode {
+      (MONITOR_MODE_MBPS, MONITOR_MODE_MBPS)
+    } else {
+      (
+        node.download_mbps.min(parent_download),
+        node.upload_mbps.min(parent_upload),
+      )
+    };
+
+    let kept = self
+      .previous_nodes
+      .get(node.name.as_str())
+      .map(|old| (old.queue, old.parent_class_id, old.class_id));
+    let minor = match kept {
+      Some((old_queue, old_parent, old_class))
+        if old_queue == queue
+          && old_parent == parent_class_id
+          && self.claimed.insert(old_class) =>
+      {
+        old_class.minor
+      }
+      _ => self.allocate(queue)?,
+    };
+    let index = self.nodes.len();
+    let class_id = ClassId { major, minor };
+    self.nodes.push(PlannedNode {
+      name: node.name.clone(),
+      parent,
+      queue,
+      cpu: queue - 1,
+      up_cpu: queue - 1 + settings.stick_offset(),
+      class_id,
+      up_class_id: ClassId { major: up_major, minor },
+      parent_class_id,
+      up_parent_class_id,
+      download_mbps,
+      upload_mbps,
+      download_min_mbps: percent_95(download_mbps),
+      upload_min_mbps: percent_95(upload_mbps),
+    });
+
+    let circuits = self.by_parent.remove(node.name.as_str());
+    for circuit in circuits.unwrap_or_default() {
+      self.place_circuit(circuit, index)?;
+    }
+
+    if !node.children.is_empty() {
+      if self.previous.is_none() {
+        // LibreQoS.py leaves a gap before each node's children
+        self.next_minor[queue as usize - 1] += 1;
+      }
+      for child in node.children.iter() {
+        self.place(child, queue, Some(index))?;
+      }
+    }
+    Ok(())
+  }
+
+  fn place_circuit(
+    &mut self,
+    circuit: usize,
+    node: usize,
+  ) -> Result<(), PlannerError> {
+    let circuit = &self.circuits[circuit];
+    let parent = &self.nodes[node];
+    let (queue, parent_class_id) = (parent.queue, parent.class_id);
+    let kept = self
+      .previous_circuits
+      .get(circuit.key.as_str())
+      .map(|old| (old.queue, old.parent_class_id, old.class_id));
+    let minor = match kept {
+      Some((old_queue, old_parent, old_class))
+        if old_queue == queue
+          && old_parent == parent_class_id
+          && self.claimed.insert(old_class) =>
+      {
+        old_class.minor
+      }
+      _ => self.allocate(queue)?,
+    };
+
+    let parent = &self.nodes[node];
+    let download_max_mbps = circuit.download_max_mbps.min(parent.download_mbps);
+    let upload_max_mbps = circuit.upload_max_mbps.min(parent.upload_mbps);
+    self.planned.push(PlannedCircuit {
+      key: circuit.key.clone(),
+      circuit_id: circuit.circuit_id.clone(),
+      circuit_name: circuit.circuit_name.clone(),
+      parent_node: parent.name.clone(),
+      node,
+      comment: circuit.comment.clone(),
+      queue,
+      cpu: parent.cpu,
+      up_cpu: parent.up_cpu,
+      class_id: ClassId { major: parent.class_id.major, minor },
+      up_class_id: ClassId { major: parent.up_class_id.major, minor },
+      parent_class_id,
+      up_parent_class_id: parent.up_class_id,
+      download_min_mbps: circuit.download_min_mbps.min(download_max_mbps),
+      upload_min_mbps: circuit.upload_min_mbps.min(upload_max_mbps),
+      download_max_mbps,
+      upload_max_mbps,
+      sqm_profile: circuit.sqm_profile.as_ref().map(|p| p.0.clone()),
+      sqm: circuit.sqm_profile.as_ref().map(|p| p.1.clone()),
+      devices: circuit.devices.clone(),
+    });
+    Ok(())
+  }
+
+  fn allocate(&mut self, queue: u32) -> Result<u32, PlannerError> {
+    if let Some(minor) = self.free_minors[queue as usize - 1].pop() {
+      return Ok(minor);
+    }
+    let next = &mut self.next_minor[queue as usize - 1];
+    if *next > MAX_MINOR {
+      return Err(PlannerError::ClassIdsExhausted(queue));
+    }
+    *next += 1;
+    Ok(*next - 1)
+  }
+}
+
+/// HTB rates are set to 95% of the ceiling
+pub(crate) fn percent_95(mbps: u32) -> u32 {
+  (mbps as f64 * 0.95).round_ties_even() as u32
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+  use super::*;
+  use crate::parse_network;
+
+  pub(crate) fn settings(queues: u32) -> PlannerSettings {
+    PlannerSettings {
+      pairs: Vec::new(),
+      queues,
+      on_a_stick: false,
+      sqm: "cake diffserv4".to_string(),
+      monitor_mode: false,
+      upstream_download_mbps: 1000,
+      upstream_upload_mbps: 1000,
+      generated_download_mbps: 1000,
+      generated_upload_mbps: 500,
+      use_binpacking: false,
+    }
+  }
+
+  pub(crate) fn device(
+    circuit_id: &str,
+    parent: &str,
+    ip: &str,
+    download_max_mbps: u32,
+  ) -> ShapedDevice {
+    ShapedDevice {
+      circuit_id: circuit_id.to_string(),
+      circuit_name: format!("Circuit {circuit_id}"),
+      device_id: format!("{circuit_id}-{ip}"),
+      device_name: format!("Device {ip}"),
+      parent_node: parent.to_string(),
+      ipv4: vec![(ip.parse().unwrap(), 32)],
+      download_min_mbps: 1,
+      upload_min_mbps: 1,
+      download_max_mbps,
+      upload_max_mbps: 10,
+      ..Default::default()
+    }
+  }
+
+  pub(crate) fn network() -> Vec<NetworkNode> {
+    parse_network(
+      r#"{
+        "Site_1": { "downloadBandwidthMbps": 2000, "uploadBandwidthMbps": 900,
+          "children": {
+            "AP_A": { "downloadBandwidthMbps": 500, "uploadBandwidthMbps": 500 }
+          } },
+        "Site_2": { "downloadBandwidthMbps": 500, "uploadBandwidthMbps": 500 }
+      }"#,
+    )
+    .unwrap()
+  }
+
+  fn find<'a>(plan: &'a QueuePlan, name: &str) -> &'a PlannedNode {
+    plan.nodes.iter().find(|n| n.name == name).unwrap()
+  }
+
+  #[test]
+  fn class_ids_match_libreqos_py() {
+    let devices = vec![
+      device("1", "AP_A", "100.64.0.1", 100),
+      device("2", "Site_1", "100.64.0.2", 100),
+      device("3", "", "100.64.0.3", 100),
+      device("4", "", "100.64.0.4", 100),
+    ];
+    let plan = QueuePlan::build(
+      settings(2),
+      &network(),
+      &devices,
+      &SqmProfiles::default(),
+      None,
+    )
+    .unwrap();
+
+    let site_1 = find(&plan, "Site_1");
+    assert_eq!(site_1.class_id.to_string(), "0x1:0x3");
+    assert_eq!(site_1.parent_handle(), "0x1:");
+    assert_eq!((site_1.download_mbps, site_1.upload_mbps), (1000, 900));
+    assert_eq!(site_1.download_min_mbps, 950);
+    // Circuit 2 takes 0x4, then a gap is left before the children
+    let ap_a = find(&plan, "AP_A");
+    assert_eq!(ap_a.class_id.to_string(), "0x1:0x6");
+    assert_eq!(ap_a.parent_handle(), "0x1:0x3");
+    let site_2 = find(&plan, "Site_2");
+    assert_eq!((site_2.queue, site_2.class_id.minor), (2, 3));
+    // Generated nodes continue round-robin after the network's nodes
+    let generated = find(&plan, "Generated_PN_1");
+    assert_eq!((generated.queue, generated.class_id.minor), (1, 8));
+    assert_eq!(find(&plan, "Generated_PN_2").upload_mbps, 500);
+
+    let circuit = |id: &str| plan.circuits.iter().find(|c| c.circuit_id == id);
+    assert_eq!(circuit("1").unwrap().class_id.to_string(), "0x1:0x7");
+    assert_eq!(circuit("2").unwrap().class_id.to_string(), "0x1:0x4");
+    assert_eq!(circuit("3").unwrap().parent_node, "Generated_PN_1");
+    assert_eq!(circuit("4").unwrap().parent_node, "Generated_PN_2");
+    // 100 Mbps plus TCP overhead
+    assert_eq!(circuit("1").unwrap().download_max_mbps, 109);
+    assert_eq!(plan.next_minor.get(&1), Some(&10));
+  }
+
+  #[test]
+  fn deep_trees_are_flattened() {
+    let mut node = NetworkNode {
+      name: "Level_7".to_string(),
+      download_mbps: 100,
+      upload_mbps: 100,
+      children: Vec::new(),
+    };
+    for level in (1..7).rev() {
+      node = NetworkNode {
+        name: format!("Level_{level}"),
+        download_mbps: 100,
+        upload_mbps: 100,
+        children: vec![node],
+      };
+    }
+    let plan = QueuePlan::build(
+      settings(2),
+      &[node],
+      &[],
+      &SqmProfiles::default(),
+      None,
+    )
+    .unwrap();
+    let depth = |name: &str| {
+      let mut node = find(&plan, name);
+      let mut depth = 0;
+      while let Some(parent) = node.parent {
+        node = &plan.nodes[parent];
+        depth += 1;
+      }
+      depth
+    };
+    assert_eq!(depth("Level_5"), 4);
+    assert_eq!(depth("Level_6"), 5);
+    assert_eq!(depth("Level_7"), 5);
+  }
+
+  #[test]
+  fn binpacking_balances_bandwidth() {
+    let devices = vec![
+      device("1", "", "100.64.0.1", 10),
+      device("2", "", "100.64.0.2", 500),
+      device("3", "", "100.64.0.3", 20),
+    ];
+    let settings = PlannerSettings { use_binpacking: true, ..settings(2) };
+    let plan = QueuePlan::build(
+      settings,
+      &[],
+      &devices,
+      &SqmProfiles::default(),
+      None,
+    )
+    .unwrap();
+    let parents: Vec<&str> =
+      plan.circuits.iter().map(|c| c.parent_node.as_str()).collect();
+    assert_eq!(
+      parents,
+      vec!["Generated_PN_1", "Generated_PN_2", "Generated_PN_2"]
+    );
+    assert_eq!(plan.circuits[0].circuit_id, "2");
+  }
+
+  #[test]
+  fn unknown_parents_are_reported() {
+    let devices = vec![device("1", "Nowhere", "100.64.0.1", 10)];
+    let plan = QueuePlan::build(
+      settings(2),
+      &network(),
+      &devices,
+      &SqmProfiles::default(),
+      None,
+    )
+    .unwrap();
+    assert!(plan.circuits.is_empty());
+    assert_eq!(plan.unshaped_devices[0].1, "Device 100.64.0.1");
+  }
+}
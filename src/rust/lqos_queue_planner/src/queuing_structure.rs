@@ -0,0 +1,460 @@
+use crate::{
+  plan::{next_revision, percent_95},
+  ClassId, PlannedCircuit, PlannedDevice, PlannedNode, PlannerError,
+  PlannerSettings, QueuePlan,
+};
+use serde_json::{json, Map, Value};
+use std::{collections::BTreeMap, path::Path};
+
+impl QueuePlan {
+  /// The plan in the `queuingStructure.json` format written by
+  /// `LibreQoS.py`, which `lqosd` reads to track the queues.
+  pub fn queuing_structure(&self) -> Value {
+    let children = self.children_by_node();
+    let circuits = self.circuits_by_node();
+    let mut network = Map::new();
+    for (index, node) in self.nodes.iter().enumerate() {
+      if node.parent.is_none() {
+        let json = self.node_json(index, &children, &circuits);
+        network.insert(node.name.clone(), json);
+      }
+    }
+    let counters: Map<String, Value> = self
+      .next_minor
+      .iter()
+      .map(|(queue, minor)| (queue.to_string(), json!(minor)))
+      .collect();
+    json!({
+      "Network": network,
+      "lastUsedClassIDCounterByCPU": counters,
+      "generatedPNs": self.generated_pns,
+    })
+  }
+
+  fn node_json(
+    &self,
+    index: usize,
+    children: &[Vec<usize>],
+    circuits: &[Vec<usize>],
+  ) -> Value {
+    let node = &self.nodes[index];
+    let mut json = json!({
+      "downloadBandwidthMbps": node.download_mbps,
+      "uploadBandwidthMbps": node.upload_mbps,
+      "downloadBandwidthMbpsMin": node.download_min_mbps,
+      "uploadBandwidthMbpsMin": node.upload_min_mbps,
+      "classid": node.class_id.to_string(),
+      "up_classid": node.up_class_id.to_string(),
+      "parentClassID": node.parent_handle(),
+      "up_parentClassID": node.up_parent_handle(),
+      "classMajor": format!("0x{:x}", node.class_id.major),
+      "up_classMajor": format!("0x{:x}", node.up_class_id.major),
+      "classMinor": format!("0x{:x}", node.class_id.minor),
+      "cpuNum": format!("0x{:x}", node.cpu),
+      "up_cpuNum": format!("0x{:x}", node.up_cpu),
+    });
+    if !circuits[index].is_empty() {
+      json["circuits"] = circuits[index]
+        .iter()
+        .map(|c| circuit_json(&self.circuits[*c]))
+        .collect();
+    }
+    if !children[index].is_empty() {
+      let nested: Map<String, Value> = children[index]
+        .iter()
+        .map(|child| {
+          (
+            self.nodes[*child].name.clone(),
+            self.node_json(*child, children, circuits),
+          )
+        })
+        .collect();
+      json["children"] = Value::Object(nested);
+    }
+    json
+  }
+
+  /// Writes `queuingStructure.json`.
+  pub fn write_queuing_structure(
+    &self,
+    path: &Path,
+  ) -> Result<(), PlannerError> {
+    write_json(path, &self.queuing_structure())
+  }
+
+  /// Writes `statsByCircuit.json` and `statsByParentNode.json`, which
+  /// the InfluxDB graphing scripts read.
+  pub fn write_graphing_stats(
+    &self,
+    directory: &Path,
+  ) -> Result<(), PlannerError> {
+    let circuits: Vec<Value> =
+      self.circuits.iter().map(circuit_json).collect();
+    let nodes: Vec<Value> = self
+      .nodes
+      .iter()
+      .map(|node| {
+        json!({
+          "parentNodeName": node.name,
+          "classID": node.class_id.to_string(),
+          "maxDownload": node.download_mbps,
+          "maxUpload": node.upload_mbps,
+        })
+      })
+      .collect();
+    write_json(&directory.join("statsByCircuit.json"), &json!(circuits))?;
+    write_json(&directory.join("statsByParentNode.json"), &json!(nodes))
+  }
+
+  /// Reads back the plan in `queuingStructure.json`, whether written
+  /// by `LibreQoS.py` or by [`QueuePlan::write_queuing_structure`],
+  /// so that changes can be made to queues built by another process.
+  pub fn load_queuing_structure(
+    settings: PlannerSettings,
+    path: &Path,
+  ) -> Result<Self, PlannerError> {
+    let raw = std::fs::read_to_string(path).map_err(|e| {
+      PlannerError::Io(path.display().to_string(), e.to_string())
+    })?;
+    let json: Value = serde_json::from_str(&raw)
+      .map_err(|e| PlannerError::QueuingStructure(e.to_string()))?;
+    Self::from_queuing_structure(settings, &json)
+  }
+
+  /// The plan described by a `queuingStructure.json` document. Fails
+  /// if it was built with a different number of queues.
+  pub fn from_queuing_structure(
+    settings: PlannerSettings,
+    json: &Value,
+  ) -> Result<Self, PlannerError> {
+    let network = json["Network"]
+      .as_object()
+      .ok_or_else(|| invalid("there is no Network object"))?;
+    let mut reader =
+      Reader { settings: &settings, nodes: Vec::new(), circuits: Vec::new() };
+    reader.read_nodes(network, None)?;
+    let (nodes, circuits) = (reader.nodes, reader.circuits);
+
+    let mut next_minor = BTreeMap::new();
+    let counters = json["lastUsedClassIDCounterByCPU"].as_object();
+    for (queue, minor) in counters.into_iter().flatten() {
+      let queue: u32 =
+        queue.parse().map_err(|_| invalid("a class ID counter is invalid"))?;
+      if queue == 0 || queue > settings.queues {
+        return Err(invalid("it was built for a different number of queues"));
+      }
+      next_minor.insert(queue, number(minor)?);
+    }
+    let generated_pns = json["generatedPNs"]
+      .as_array()
+      .into_iter()
+      .flatten()
+      .filter_map(|name| name.as_str().map(str::to_string))
+      .collect();
+    Ok(Self {
+      settings,
+      nodes,
+      circuits,
+      generated_pns,
+      next_minor,
+      unshaped_devices: Vec::new(),
+      revision: next_revision(),
+      based_on: None,
+    })
+  }
+}
+
+/// Rebuilds the nodes and circuits of a `queuingStructure.json`
+struct Reader<'a> {
+  settings: &'a PlannerSettings,
+  nodes: Vec<PlannedNode>,
+  circuits: Vec<PlannedCircuit>,
+}
+
+impl<'a> Reader<'a> {
+  fn read_nodes(
+    &mut self,
+    level: &Map<String, Value>,
+    parent: Option<usize>,
+  ) -> Result<(), PlannerError> {
+    for (name, json) in level.iter() {
+      let class_id = parse_class_id(&json["classid"])?;
+      let up_class_id = parse_class_id(&json["up_classid"])?;
+      let queue = class_id.major;
+      if queue == 0
+        || queue > self.settings.queues
+        || up_class_id.major != queue + self.settings.stick_offset()
+      {
+        return Err(invalid("it was built for a different number of queues"));
+      }
+      let download_mbps = number(&json["downloadBandwidthMbps"])?;
+      let upload_mbps = number(&json["uploadBandwidthMbps"])?;
+      let parent_node = parent.map(|p| &self.nodes[p]);
+      let index = self.nodes.len();
+      self.nodes.push(PlannedNode {
+        name: name.clone(),
+        parent,
+        queue,
+        cpu: queue - 1,
+        up_cpu: up_class_id.major - 1,
+        class_id,
+        up_class_id,
+        parent_class_id: parent_node.map(|p| p.class_id),
+        up_parent_class_id: parent_node.map(|p| p.up_class_id),
+        download_mbps,
+        upload_mbps,
+        download_min_mbps: number(&json["downloadBandwidthMbpsMin"])
+          .unwrap_or(percent_95(download_mbps)),
+        upload_min_mbps: number(&json["uploadBandwidthMbpsMin"])
+          .unwrap_or(percent_95(upload_mbps)),
+      });
+
+      for circuit in json["circuits"].as_array().into_iter().flatten() {
+        self.read_circuit(circuit, index)?;
+      }
+      if let Some(children) = json["children"].as_object() {
+        self.read_nodes(children, Some(index))?;
+      }
+    }
+    Ok(())
+  }
+
+  fn read_circuit(
+    &mut self,
+    json: &Value,
+    node: usize,
+  ) -> Result<(), PlannerError> {
+    let parent = &self.nodes[node];
+    let class_id = parse_class_id(&json["classid"])?;
+    let up_class_id = parse_class_id(&json["up_classid"])?;
+    if class_id.major != parent.class_id.major {
+      return Err(invalid("a circuit is on a different queue to its node"));
+    }
+    let devices: Vec<PlannedDevice> = json["devices"]
+      .as_array()
+      .into_iter()
+      .flatten()
+      .map(|device| PlannedDevice {
+        device_id: text(&device["deviceID"]),
+        device_name: text(&device["deviceName"]),
+        mac: text(&device["mac"]),
+        comment: text(&device["comment"]),
+        ipv4: texts(&device["ipv4s"]),
+        ipv6: texts(&device["ipv6s"]),
+      })
+      .collect();
+    let circuit_id = text(&json["circuitID"]);
+    let key = match devices.first() {
+      Some(device) if circuit_id.is_empty() => {
+        format!("device:{}", device.device_id)
+      }
+      _ => circuit_id.clone(),
+    };
+    self.circuits.push(PlannedCircuit {
+      key,
+      circuit_id,
+      circuit_name: text(&json["circuitName"]),
+      parent_node: parent.name.clone(),
+      node,
+      comment: text(&json["comment"]),
+      queue: parent.queue,
+      cpu: parent.cpu,
+      up_cpu: parent.up_cpu,
+      class_id,
+      up_class_id,
+      parent_class_id: parent.class_id,
+      up_parent_class_id: parent.up_class_id,
+      download_min_mbps: number(&json["minDownload"])?,
+      upload_min_mbps: number(&json["minUpload"])?,
+      download_max_mbps: number(&json["maxDownload"])?,
+      upload_max_mbps: number(&json["maxUpload"])?,
+      sqm_profile: json["sqmProfile"].as_str().map(str::to_string),
+      sqm: json["sqm"].as_str().map(str::to_string),
+      devices,
+    });
+    Ok(())
+  }
+}
+
+fn invalid(reason: &str) -> PlannerError {
+  PlannerError::QueuingStructure(reason.to_string())
+}
+
+/// Parses a class ID such as `0x1:0x5`
+fn parse_class_id(json: &Value) -> Result<ClassId, PlannerError> {
+  let hex = |part: &str| {
+    u32::from_str_radix(part.trim_start_matches("0x"), 16).ok()
+  };
+  json
+    .as_str()
+    .and_then(|id| id.split_once(':'))
+    .and_then(|(major, minor)| {
+      Some(ClassId { major: hex(major)?, minor: hex(minor)? })
+    })
+    .ok_or_else(|| invalid(&format!("{json} isn't a class ID")))
+}
+
+/// Reads a rate or counter, which `LibreQoS.py` may have written as a
+/// float
+fn number(json: &Value) -> Result<u32, PlannerError> {
+  json
+    .as_f64()
+    .filter(|n| *n >= 0.0)
+    .map(|n| n.round() as u32)
+    .ok_or_else(|| invalid(&format!("{json} isn't a number")))
+}
+
+fn text(json: &Value) -> String {
+  json.as_str().unwrap_or_default().to_string()
+}
+
+fn texts(json: &Value) -> Vec<String> {
+  json
+    .as_array()
+    .into_iter()
+    .flatten()
+    .filter_map(|s| s.as_str().map(str::to_string))
+    .collect()
+}
+
+fn circuit_json(circuit: &PlannedCircuit) -> Value {
+  let mut json = json!({
+    "maxDownload": circuit.download_max_mbps,
+    "maxUpload": circuit.upload_max_mbps,
+    "minDownload": circuit.download_min_mbps,
+    "minUpload": circuit.upload_min_mbps,
+    "circuitID": circuit.circuit_id,
+    "circuitName": circuit.circuit_name,
+    "ParentNode": circuit.parent_node,
+    "devices": circuit.devices.iter().map(device_json).collect::<Vec<_>>(),
+    "classid": circuit.class_id.to_string(),
+    "up_classid": circuit.up_class_id.to_string(),
+    "classMajor": format!("0x{:x}", circuit.class_id.major),
+    "up_classMajor": format!("0x{:x}", circuit.up_class_id.major),
+    "classMinor": format!("0x{:x}", circuit.class_id.minor),
+    "comment": circuit.comment,
+  });
+  if let (Some(profile), Some(sqm)) = (&circuit.sqm_profile, &circuit.sqm) {
+    json["sqmProfile"] = json!(profile);
+    json["sqm"] = json!(sqm);
+  }
+  json
+}
+
+fn device_json(device: &PlannedDevice) -> Value {
+  json!({
+    "deviceID": device.device_id,
+    "deviceName": device.device_name,
+    "mac": device.mac,
+    "ipv4s": device.ipv4,
+    "ipv6s": device.ipv6,
+    "comment": device.comment,
+  })
+}
+
+fn write_json(path: &Path, json: &Value) -> Result<(), PlannerError> {
+  let error = |e: String| PlannerError::Io(path.display().to_string(), e);
+  let raw =
+    serde_json::to_string_pretty(json).map_err(|e| error(e.to_string()))?;
+  std::fs::write(path, raw).map_err(|e| error(e.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+  use crate::plan::test::{device, network, settings};
+  use crate::{PlannedCircuit, PlannedNode, PlannerError, QueuePlan};
+  use lqos_config::SqmProfiles;
+
+  #[test]
+  fn written_in_the_libreqos_py_layout() {
+    let devices = vec![device("1", "AP_A", "100.64.0.1", 100)];
+    let plan = QueuePlan::build(
+      settings(2),
+      &network(),
+      &devices,
+      &SqmProfiles::default(),
+      None,
+    )
+    .unwrap();
+    let json = plan.queuing_structure();
+    let ap_a = &json["Network"]["Site_1"]["children"]["AP_A"];
+    assert_eq!(ap_a["classid"], "0x1:0x5");
+    assert_eq!(ap_a["parentClassID"], "0x1:0x3");
+    assert_eq!(ap_a["cpuNum"], "0x0");
+    let circuit = &ap_a["circuits"][0];
+    assert_eq!(circuit["classMinor"], "0x6");
+    assert_eq!(circuit["devices"][0]["ipv4s"][0], "100.64.0.1");
+    assert!(circuit.get("sqm").is_none());
+    assert_eq!(json["lastUsedClassIDCounterByCPU"]["1"], 8);
+    assert_eq!(json["generatedPNs"][1], "Generated_PN_2");
+  }
+
+  #[test]
+  fn read_back_for_changes() {
+    let mut devices = vec![
+      device("1", "AP_A", "100.64.0.1", 100),
+      device("2", "Site_2", "100.64.0.2", 100),
+    ];
+    let profiles = SqmProfiles::default();
+    let built =
+      QueuePlan::build(settings(2), &network(), &devices, &profiles, None)
+        .unwrap();
+    let json = built.queuing_structure();
+    let read =
+      QueuePlan::from_queuing_structure(settings(2), &json).unwrap();
+    // The JSON objects are sorted, so compare without the order
+    let nodes = |plan: &QueuePlan| {
+      let mut nodes: Vec<PlannedNode> = plan
+        .nodes
+        .iter()
+        .map(|n| PlannedNode { parent: None, ..n.clone() })
+        .collect();
+      nodes.sort_by_key(|n| n.class_id);
+      nodes
+    };
+    let circuits = |plan: &QueuePlan| {
+      let mut circuits: Vec<PlannedCircuit> = plan
+        .circuits
+        .iter()
+        .map(|c| PlannedCircuit { node: 0, ..c.clone() })
+        .collect();
+      circuits.sort_by_key(|c| c.class_id);
+      circuits
+    };
+    assert_eq!(nodes(&read), nodes(&built));
+    assert_eq!(circuits(&read), circuits(&built));
+    assert_eq!(read.next_minor, built.next_minor);
+
+    devices.push(device("3", "Site_2", "100.64.0.3", 50));
+    let plan = QueuePlan::build(
+      settings(2),
+      &network(),
+      &devices,
+      &profiles,
+      Some(&read),
+    )
+    .unwrap();
+    let changes = plan.changes_from(&read).unwrap();
+    assert_eq!(changes.mappings.len(), 1);
+    assert_eq!(changes.mappings[0].ip, "100.64.0.3");
+    assert_eq!(changes.mappings[0].class_id.to_string(), "0x2:0x6");
+    assert!(changes.unmappings.is_empty());
+    assert!(changes.tc_removals.is_empty());
+  }
+
+  #[test]
+  fn other_queue_counts_are_rejected() {
+    let devices = vec![device("1", "Site_2", "100.64.0.1", 100)];
+    let plan = QueuePlan::build(
+      settings(4),
+      &network(),
+      &devices,
+      &SqmProfiles::default(),
+      None,
+    )
+    .unwrap();
+    let result =
+      QueuePlan::from_queuing_structure(settings(2), &plan.queuing_structure());
+    assert!(matches!(result, Err(PlannerError::QueuingStructure(_))));
+  }
+}
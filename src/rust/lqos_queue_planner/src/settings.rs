@@ -0,0 +1,114 @@
+use crate::PlannerError;
+use lqos_config::{InterfacePair, LibreQoSConfig};
+use std::path::Path;
+
+/// Queue count used when shell commands are disabled, as `LibreQoS.py`
+/// does for simulated runs
+const SIMULATED_QUEUES: u32 = 16;
+
+/// The global settings a queue plan is built with. Plans built with
+/// different settings can't be turned into one another piecemeal.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlannerSettings {
+  /// The shaping bridge pairs the queues are built on
+  pub pairs: Vec<InterfacePair>,
+
+  /// The number of queues (and CPUs) each direction is shaped with
+  pub queues: u32,
+
+  /// On a stick, upload uses the `queues` queues after the download
+  /// ones
+  pub on_a_stick: bool,
+
+  /// The default queue discipline, e.g. `cake diffserv4`
+  pub sqm: String,
+
+  /// In monitor mode, everything is queued at 10 Gbps with no SQM
+  pub monitor_mode: bool,
+
+  /// Upstream download capacity, in Mbps
+  pub upstream_download_mbps: u32,
+
+  /// Upstream upload capacity, in Mbps
+  pub upstream_upload_mbps: u32,
+
+  /// Download capacity of each generated parent node, in Mbps
+  pub generated_download_mbps: u32,
+
+  /// Upload capacity of each generated parent node, in Mbps
+  pub generated_upload_mbps: u32,
+
+  /// Balance circuits without a parent node by bandwidth, rather than
+  /// by count
+  pub use_binpacking: bool,
+}
+
+impl PlannerSettings {
+  /// Reads the settings from the LibreQoS configuration, counting the
+  /// queues each interface has.
+  pub fn from_config(config: &LibreQoSConfig) -> Result<Self, PlannerError> {
+    let pairs = config
+      .interface_pairs()
+      .map_err(|e| PlannerError::Config(e.to_string()))?;
+    let mut queues = u32::MAX;
+    for pair in pairs.iter() {
+      for interface in pair.interfaces() {
+        queues = queues.min(queues_available(interface, config)?);
+      }
+    }
+    let on_a_stick = pairs.iter().any(|p| p.on_a_stick);
+    if on_a_stick {
+      queues /= 2;
+    }
+    Ok(Self {
+      pairs,
+      queues,
+      on_a_stick,
+      sqm: config.sqm.clone(),
+      monitor_mode: config.monitor_mode,
+      upstream_download_mbps: config.total_download_mbps,
+      upstream_upload_mbps: config.total_upload_mbps,
+      generated_download_mbps: config.generated_download_mbps,
+      generated_upload_mbps: config.generated_upload_mbps,
+      use_binpacking: config.use_binpacking,
+    })
+  }
+
+  /// The offset from a download queue to its upload queue
+  pub fn stick_offset(&self) -> u32 {
+    if self.on_a_stick {
+      self.queues
+    } else {
+      0
+    }
+  }
+}
+
+/// The number of queues an interface can be shaped with: its transmit
+/// queues (or the configured override), but no more than there are
+/// CPUs.
+fn queues_available(
+  interface: &str,
+  config: &LibreQoSConfig,
+) -> Result<u32, PlannerError> {
+  if !config.enable_shell_commands {
+    return Ok(SIMULATED_QUEUES);
+  }
+  let queues = if config.override_queue_count == 0 {
+    let path = Path::new("/sys/class/net").join(interface).join("queues");
+    let entries = std::fs::read_dir(path)
+      .map_err(|_| PlannerError::QueueCount(interface.to_string()))?;
+    entries
+      .flatten()
+      .filter(|e| e.file_name().to_string_lossy().contains("tx-"))
+      .count() as u32
+  } else {
+    config.override_queue_count
+  };
+  if queues < 2 {
+    return Err(PlannerError::TooFewQueues(interface.to_string(), queues));
+  }
+  let cpus = std::thread::available_parallelism()
+    .map_or(queues, |n| n.get() as u32);
+  Ok(queues.min(cpus))
+}
@@ -0,0 +1,223 @@
+use crate::{
+  ClassId, PlannedCircuit, PlannedNode, PlannerError, PlannerSettings,
+  QueuePlan,
+};
+use lqos_config::InterfacePair;
+use std::{
+  io::Write,
+  process::{Command, Stdio},
+};
+
+const TC: &str = "/sbin/tc";
+
+/// The interfaces of a pair that download and upload traffic leave by
+pub(crate) struct Directions<'a> {
+  pub(crate) download: &'a str,
+  pub(crate) upload: &'a str,
+}
+
+impl<'a> Directions<'a> {
+  pub(crate) fn of(pair: &'a InterfacePair) -> Self {
+    Self {
+      download: pair.isp_facing_interface(),
+      upload: &pair.internet_interface,
+    }
+  }
+
+  pub(crate) fn node_classes(
+    &self,
+    node: &PlannedNode,
+    verb: &str,
+  ) -> Vec<String> {
+    let (parent, up_parent) = if verb == "add" {
+      (
+        format!(" parent {}", node.parent_handle()),
+        format!(" parent {}", node.up_parent_handle()),
+      )
+    } else {
+      (String::new(), String::new())
+    };
+    vec![
+      format!(
+        "class {verb} dev {}{parent} classid {} htb rate {}mbit ceil {}mbit prio 3",
+        self.download, node.class_id, node.download_min_mbps, node.download_mbps
+      ),
+      format!(
+        "class {verb} dev {}{up_parent} classid {} htb rate {}mbit ceil {}mbit prio 3",
+        self.upload, node.up_class_id, node.upload_min_mbps, node.upload_mbps
+      ),
+    ]
+  }
+
+  pub(crate) fn circuit_classes(
+    &self,
+    circuit: &PlannedCircuit,
+    verb: &str,
+  ) -> Vec<String> {
+    let (parent, up_parent) = if verb == "add" {
+      (
+        format!(" parent {}", circuit.parent_class_id),
+        format!(" parent {}", circuit.up_parent_class_id),
+      )
+    } else {
+      (String::new(), String::new())
+    };
+    vec![
+      format!(
+        "class {verb} dev {}{parent} classid {} htb rate {}mbit ceil {}mbit prio 3",
+        self.download,
+        circuit.class_id,
+        circuit.download_min_mbps,
+        circuit.download_max_mbps
+      ),
+      format!(
+        "class {verb} dev {}{up_parent} classid {} htb rate {}mbit ceil {}mbit prio 3",
+        self.upload,
+        circuit.up_class_id,
+        circuit.upload_min_mbps,
+        circuit.upload_max_mbps
+      ),
+    ]
+  }
+
+  /// Attaches (or replaces) the circuit's queue disciplines
+  pub(crate) fn circuit_qdiscs(
+    &self,
+    circuit: &PlannedCircuit,
+    verb: &str,
+    qdiscs: &(String, String),
+  ) -> Vec<String> {
+    vec![
+      format!(
+        "qdisc {verb} dev {} parent {} {}",
+        self.download, circuit.class_id, qdiscs.0
+      ),
+      format!(
+        "qdisc {verb} dev {} parent {} {}",
+        self.upload, circuit.up_class_id, qdiscs.1
+      ),
+    ]
+  }
+
+  /// Removes the circuit's queue disciplines, leaving plain HTB
+  pub(crate) fn delete_circuit_qdiscs(
+    &self,
+    circuit: &PlannedCircuit,
+  ) -> Vec<String> {
+    vec![
+      format!("qdisc del dev {} parent {}", self.download, circuit.class_id),
+      format!("qdisc del dev {} parent {}", self.upload, circuit.up_class_id),
+    ]
+  }
+
+  /// Deletes a class, along with its queue discipline
+  pub(crate) fn delete_classes(
+    &self,
+    class_id: ClassId,
+    up_class_id: ClassId,
+  ) -> Vec<String> {
+    vec![
+      format!("class del dev {} classid {class_id}", self.download),
+      format!("class del dev {} classid {up_class_id}", self.upload),
+    ]
+  }
+
+  /// The MQ root and each queue's HTB root and default classes. On a
+  /// stick, upload shares the download MQ root.
+  fn queue_roots(&self, settings: &PlannerSettings) -> Vec<String> {
+    let mut commands = Vec::new();
+    let directions = [
+      (self.download, 0, settings.upstream_download_mbps),
+      (self.upload, settings.stick_offset(), settings.upstream_upload_mbps),
+    ];
+    for (direction, (interface, offset, mbps)) in
+      directions.iter().enumerate()
+    {
+      if direction == 0 || !settings.on_a_stick {
+        commands
+          .push(format!("qdisc replace dev {interface} root handle 7FFF: mq"));
+      }
+      for queue in 1..=settings.queues {
+        let major = format!("0x{:x}", queue + offset);
+        commands.extend([
+          format!(
+            "qdisc add dev {interface} parent 7FFF:{major} handle {major}: \
+             htb default 2"
+          ),
+          format!(
+            "class add dev {interface} parent {major}: classid {major}:1 \
+             htb rate {mbps}mbit ceil {mbps}mbit"
+          ),
+          format!(
+            "qdisc add dev {interface} parent {major}:1 {}",
+            settings.sqm
+          ),
+          format!(
+            "class add dev {interface} parent {major}:1 classid {major}:2 \
+             htb rate {}mbit ceil {}mbit prio 5",
+            (mbps.saturating_sub(1) as f64 / 4.0).round_ties_even() as u32,
+            mbps.saturating_sub(1)
+          ),
+          format!(
+            "qdisc add dev {interface} parent {major}:2 {}",
+            settings.sqm
+          ),
+        ]);
+      }
+    }
+    commands
+  }
+}
+
+impl QueuePlan {
+  /// Every `tc` command needed to build the queues from scratch, for
+  /// each interface pair in turn. Run them once any existing MQ root
+  /// has been deleted.
+  pub fn full_commands(&self) -> Vec<String> {
+    let circuits = self.circuits_by_node();
+    let mut commands = Vec::new();
+    for pair in self.settings.pairs.iter() {
+      let directions = Directions::of(pair);
+      commands.extend(directions.queue_roots(&self.settings));
+      for (index, node) in self.nodes.iter().enumerate() {
+        commands.extend(directions.node_classes(node, "add"));
+        for circuit in circuits[index].iter().map(|c| &self.circuits[*c]) {
+          commands.extend(directions.circuit_classes(circuit, "add"));
+          if let Some(qdiscs) = circuit.qdiscs(&self.settings) {
+            commands.extend(directions.circuit_qdiscs(circuit, "add", &qdiscs));
+          }
+        }
+      }
+    }
+    commands
+  }
+}
+
+/// Runs `tc` commands as a single batch, carrying on past failures.
+/// Returns an error describing any that failed.
+pub fn run_tc_batch(commands: &[String]) -> Result<(), PlannerError> {
+  if commands.is_empty() {
+    return Ok(());
+  }
+  let mut child = Command::new(TC)
+    .args(["-f", "-b", "-"])
+    .stdin(Stdio::piped())
+    .stdout(Stdio::null())
+    .stderr(Stdio::piped())
+    .spawn()
+    .map_err(|e| PlannerError::Tc(e.to_string()))?;
+  if let Some(mut stdin) = child.stdin.take() {
+    stdin
+      .write_all((commands.join("\n") + "\n").as_bytes())
+      .map_err(|e| PlannerError::Tc(e.to_string()))?;
+  }
+  let output =
+    child.wait_with_output().map_err(|e| PlannerError::Tc(e.to_string()))?;
+  if output.status.success() {
+    Ok(())
+  } else {
+    Err(PlannerError::Tc(
+      String::from_utf8_lossy(&output.stderr).trim().to_string(),
+    ))
+  }
+}
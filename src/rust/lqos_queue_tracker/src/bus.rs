@@ -1,19 +1,49 @@
 use crate::{
-  circuit_to_queue::CIRCUIT_TO_QUEUE, queue_store::QueueStore, still_watching,
+  circuit_to_queue::CIRCUIT_TO_QUEUE, queue_store::QueueStore,
+  queue_structure::QUEUE_STRUCTURE, still_watching,
 };
-use lqos_bus::BusResponse;
+use lqos_bus::{BusResponse, CircuitQueue, QueueStoreTransit};
 
-/// Retrieves the raw queue data for a given circuit ID.
-/// 
+/// Retrieves the raw queue data for a given circuit ID, along with
+/// the SQM profile its queues were built with.
+///
 /// # Arguments
 /// * `circuit_id` - The circuit ID to retrieve data for.
 pub fn get_raw_circuit_data(circuit_id: &str) -> BusResponse {
   still_watching(circuit_id);
   if let Some(circuit) = CIRCUIT_TO_QUEUE.get(circuit_id) {
     let cv: QueueStore = circuit.value().clone();
-    let transit = Box::new(cv.into());
+    let mut transit: Box<QueueStoreTransit> = Box::new(cv.into());
+    transit.sqm_profile =
+      get_circuit_queue(circuit_id).and_then(|q| q.sqm_profile);
     BusResponse::RawQueueData(Some(transit))
   } else {
     BusResponse::RawQueueData(None)
   }
 }
+
+/// Finds the queue assigned to a circuit in `queuingStructure.json`.
+/// Returns `None` if the circuit isn't listed, or the file hasn't
+/// been loaded.
+///
+/// # Arguments
+/// * `circuit_id` - The circuit ID to search for.
+pub fn get_circuit_queue(circuit_id: &str) -> Option<CircuitQueue> {
+  let structure = QUEUE_STRUCTURE.read().unwrap();
+  structure
+    .maybe_queues
+    .as_ref()?
+    .iter()
+    .find(|q| q.circuit_id.as_deref() == Some(circuit_id))
+    .map(|q| CircuitQueue {
+      class_id: q.class_id,
+      up_class_id: q.up_class_id,
+      parent_class_id: q.parent_class_id,
+      cpu: q.cpu_num,
+      up_cpu: q.up_cpu_num,
+      download_bandwidth_mbps: q.download_bandwidth_mbps,
+      upload_bandwidth_mbps: q.upload_bandwidth_mbps,
+      sqm_profile: q.sqm_profile.clone(),
+      sqm: q.sqm.clone(),
+    })
+}
@@ -0,0 +1,126 @@
+use crate::queue_structure::QUEUE_STRUCTURE;
+use crate::queue_types::tc_cake::TcCake;
+use log::error;
+use lqos_bus::TcHandle;
+use lqos_config::LibreQoSConfig;
+use serde_json::Value;
+use std::{collections::HashMap, process::Command};
+
+const TC: &str = "/sbin/tc";
+
+/// A circuit's CAKE counters, as `(download, upload)` pairs
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CircuitQueueCounters {
+  /// The circuit's ID
+  pub circuit_id: String,
+  /// Packets dropped since the queue was created
+  pub drops: (u64, u64),
+  /// Packets ECN marked since the queue was created
+  pub marks: (u64, u64),
+  /// Bytes waiting in the queue now
+  pub backlog: (u32, u32),
+}
+
+/// Reads the CAKE counters of every circuit in `queuingStructure.json`,
+/// with one `tc` call per interface. Unlike the watched-queue reader,
+/// this covers every circuit, so it's meant to be called rarely.
+/// Circuits whose queues aren't found are left out.
+pub fn read_circuit_queue_counters() -> Vec<CircuitQueueCounters> {
+  let Ok(config) = LibreQoSConfig::load() else {
+    return Vec::new();
+  };
+  let internet = read_cake_queues(&config.internet_interface);
+  let isp = if config.on_a_stick_mode {
+    HashMap::new()
+  } else {
+    read_cake_queues(&config.isp_interface)
+  };
+
+  let structure = QUEUE_STRUCTURE.read().unwrap();
+  let Some(queues) = structure.maybe_queues.as_ref() else {
+    return Vec::new();
+  };
+  queues
+    .iter()
+    .filter_map(|queue| {
+      let circuit_id = queue.circuit_id.as_ref()?;
+      let (download, upload) = if config.on_a_stick_mode {
+        (internet.get(&queue.class_id), internet.get(&queue.up_class_id))
+      } else {
+        (isp.get(&queue.class_id), internet.get(&queue.class_id))
+      };
+      let (download, upload) = (download?, upload?);
+      Some(CircuitQueueCounters {
+        circuit_id: circuit_id.clone(),
+        drops: (download.drops as u64, upload.drops as u64),
+        marks: (ecn_marks(download), ecn_marks(upload)),
+        backlog: (download.backlog, upload.backlog),
+      })
+    })
+    .collect()
+}
+
+fn ecn_marks(cake: &TcCake) -> u64 {
+  cake.tins.iter().map(|tin| tin.ecn_marks as u64).sum()
+}
+
+/// Reads an interface's CAKE queues, by the class they're attached to
+fn read_cake_queues(interface: &str) -> HashMap<TcHandle, TcCake> {
+  let output = Command::new(TC)
+    .args(["-s", "-j", "qdisc", "show", "dev", interface])
+    .output();
+  match output {
+    Ok(output) => parse_cake_queues(&String::from_utf8_lossy(&output.stdout)),
+    Err(e) => {
+      error!("Failed to call process tc -s -j qdisc show dev {interface}");
+      error!("{:?}", e);
+      HashMap::new()
+    }
+  }
+}
+
+/// Picks the CAKE queues out of `tc` output, skipping any other kind
+/// rather than failing on queue types we don't otherwise parse.
+fn parse_cake_queues(json: &str) -> HashMap<TcHandle, TcCake> {
+  let Ok(Value::Array(qdiscs)) = serde_json::from_str::<Value>(json) else {
+    return HashMap::new();
+  };
+  qdiscs
+    .iter()
+    .filter_map(|qdisc| {
+      let map = qdisc.as_object()?;
+      if map.get("kind")?.as_str()? != "cake" {
+        return None;
+      }
+      let cake = TcCake::from_json(map).ok()?;
+      Some((cake.parent, cake))
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn cake_queues_by_parent() {
+    let json = r#"[
+      {"kind":"mq","handle":"7fff:","root":true,"options":{}},
+      {"kind":"noqueue","handle":"0:","parent":"1:2"},
+      {"kind":"cake","handle":"9cb1:","parent":"3:6","bytes":10,
+       "packets":1,"drops":4,"backlog":1514,
+       "tins":[{"ecn_mark":2},{"ecn_mark":3}]}
+    ]"#;
+    let queues = parse_cake_queues(json);
+    assert_eq!(queues.len(), 1);
+    let cake = &queues[&TcHandle::from_string("3:6").unwrap()];
+    assert_eq!(cake.drops, 4);
+    assert_eq!(cake.backlog, 1514);
+    assert_eq!(ecn_marks(cake), 5);
+  }
+
+  #[test]
+  fn bad_json_is_empty() {
+    assert!(parse_cake_queues("not json").is_empty());
+  }
+}
@@ -6,20 +6,27 @@
 
 #![warn(missing_docs)]
 mod bus;
+mod circuit_counters;
 mod circuit_to_queue;
 mod interval;
 mod queue_diff;
 mod queue_store;
 mod queue_structure;
 mod queue_types;
+mod rate_schedule;
 mod tracking;
 
 /// How many history items do we store?
 const NUM_QUEUE_HISTORY: usize = 600;
 
-pub use bus::get_raw_circuit_data;
+pub use bus::{get_circuit_queue, get_raw_circuit_data};
+pub use circuit_counters::{read_circuit_queue_counters, CircuitQueueCounters};
 pub use interval::set_queue_refresh_interval;
 pub use queue_structure::spawn_queue_structure_monitor;
+pub use rate_schedule::{
+  get_effective_circuit_rates, libreqos_is_running, set_burst_ceiling,
+  set_rate_override, spawn_rate_plan_scheduler, BurstCeiling, RateOverride,
+};
 pub use queue_types::deserialize_tc_tree; // Exported for the benchmarker
 pub use tracking::spawn_queue_monitor;
 pub use tracking::{add_watched_queue, still_watching};
@@ -71,8 +71,13 @@ impl Into<QueueStoreTransit> for QueueStore {
       history_head: self.history_head,
       //prev_download: self.prev_download.map(|d| d.into()),
       //prev_upload: self.prev_upload.map(|u| u.into()),
+      kind: (
+        self.current_download.kind().to_string(),
+        self.current_upload.kind().to_string(),
+      ),
       current_download: self.current_download.into(),
       current_upload: self.current_upload.into(),
+      sqm_profile: None,
     }
   }
 }
@@ -46,6 +46,7 @@ pub async fn spawn_queue_structure_monitor() {
 fn update_queue_structure() {
   info!("queueingStructure.json reloaded");
   QUEUE_STRUCTURE.write().unwrap().update();
+  crate::rate_schedule::queues_rebuilt();
 }
 
 /// Fires up a Linux file system watcher than notifies
@@ -28,6 +28,8 @@ pub struct QueueNode {
   pub device_id: Option<String>,
   pub device_name: Option<String>,
   pub mac: Option<String>,
+  pub sqm_profile: Option<String>,
+  pub sqm: Option<String>,
   pub children: Vec<QueueNode>,
 }
 
@@ -176,6 +178,12 @@ impl QueueNode {
           "mac" => {
             grab_string_option!(result.mac, key.as_str(), value);
           }
+          "sqmProfile" => {
+            grab_string_option!(result.sqm_profile, key.as_str(), value);
+          }
+          "sqm" => {
+            grab_string_option!(result.sqm, key.as_str(), value);
+          }
           "ipv4s" => {} // Ignore
           "ipv6s" => {}
           "circuits" => {
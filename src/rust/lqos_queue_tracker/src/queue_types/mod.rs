@@ -17,6 +17,17 @@ pub enum QueueType {
 }
 
 impl QueueType {
+  /// The queue discipline's name, as `tc` reports it
+  pub fn kind(&self) -> &'static str {
+    match self {
+      QueueType::Mq(_) => "mq",
+      QueueType::Htb(_) => "htb",
+      QueueType::FqCodel(_) => "fq_codel",
+      QueueType::Cake(_) => "cake",
+      QueueType::ClsAct => "clsact",
+    }
+  }
+
   fn parse(
     kind: &str,
     map: &serde_json::Map<std::string::String, Value>,
@@ -0,0 +1,355 @@
+use crate::queue_structure::QUEUE_STRUCTURE;
+use dashmap::DashMap;
+use log::{debug, error, info, warn};
+use log_once::warn_once;
+use lqos_bus::{CircuitRateStatus, TcHandle};
+use lqos_config::{
+  CircuitRates, InterfacePair, LibreQoSConfig, PlanTime, RatePlans,
+  RateWindow,
+};
+use lqos_utils::fdtimer::periodic;
+use once_cell::sync::Lazy;
+use std::{
+  collections::HashMap,
+  path::Path,
+  process::Command,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex,
+  },
+  time::{Duration, SystemTime},
+};
+
+const TC: &str = "/sbin/tc";
+
+/// Held by `LibreQoS.py` while it rebuilds the queues
+const LIBREQOS_LOCK_FILE: &str = "/run/lqos/libreqos.lock";
+
+/// A lock file older than this was left behind by a crashed run
+const STALE_LOCK: Duration = Duration::from_secs(60 * 60);
+
+/// How often to check for window boundaries and burst changes
+const SCHEDULE_INTERVAL_MS: u64 = 1_000;
+
+/// How often (in ticks) to re-read the rate plans and configuration
+const RELOAD_TICKS: u64 = 10;
+
+struct ScheduledCircuit {
+  status: CircuitRateStatus,
+  class_id: TcHandle,
+  up_class_id: TcHandle,
+  /// The rates last applied with `tc`
+  applied: CircuitRates,
+}
+
+static RATE_SCHEDULE: Lazy<Mutex<HashMap<String, ScheduledCircuit>>> =
+  Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// The settings the scheduler works from. The last `RatePlans.toml`
+/// that loaded is kept in use while the file has errors.
+#[derive(Default)]
+struct ScheduleSettings {
+  plans: RatePlans,
+  pairs: Vec<InterfacePair>,
+  monitor_mode: bool,
+  ticks: u64,
+}
+
+impl ScheduleSettings {
+  fn reload(&mut self) {
+    match RatePlans::load() {
+      Ok(plans) => self.plans = plans,
+      Err(e) => warn_once!("Keeping the previous rate plans: {e}"),
+    }
+    let Ok(config) = LibreQoSConfig::load() else {
+      error!("Unable to load LibreQoS configuration for rate plans");
+      return;
+    };
+    self.monitor_mode = config.monitor_mode;
+    match config.interface_pairs() {
+      Ok(pairs) => self.pairs = pairs,
+      Err(e) => error!("Unable to read interface pairs for rate plans: {e:?}"),
+    }
+  }
+}
+
+static SETTINGS: Lazy<Mutex<ScheduleSettings>> =
+  Lazy::new(|| Mutex::new(ScheduleSettings::default()));
+
+static BURST_CEILINGS: Lazy<DashMap<String, BurstCeiling>> =
+  Lazy::new(DashMap::new);
+
+/// A temporary raise in a circuit's ceiling while it has burst
+/// allowance left. It applies on top of the circuit's rate plan: the
+/// higher rate wins. A direction set to 0 isn't raised.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BurstCeiling {
+  /// Download ceiling, in Mbps
+  pub download_mbps: u64,
+  /// Upload ceiling, in Mbps
+  pub upload_mbps: u64,
+}
+
+impl BurstCeiling {
+  fn raise(&self, rates: CircuitRates) -> CircuitRates {
+    CircuitRates {
+      download_max_mbps: rates.download_max_mbps.max(self.download_mbps),
+      upload_max_mbps: rates.upload_max_mbps.max(self.upload_mbps),
+      ..rates
+    }
+  }
+}
+
+/// Raises a circuit's ceiling while it bursts, until cleared (with
+/// `None`). The change is applied by the rate plan scheduler.
+pub fn set_burst_ceiling(circuit_id: &str, burst: Option<BurstCeiling>) {
+  match burst {
+    Some(burst) => {
+      BURST_CEILINGS.insert(circuit_id.to_string(), burst);
+    }
+    None => {
+      BURST_CEILINGS.remove(circuit_id);
+    }
+  }
+}
+
+static RATE_OVERRIDES: Lazy<DashMap<String, RateOverride>> =
+  Lazy::new(DashMap::new);
+
+/// A temporary ceiling on a circuit's rates, such as a fair-use limit.
+/// It applies on top of the circuit's rate plan: the lower rate wins.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RateOverride {
+  /// Why the circuit is limited, reported with its effective rate
+  pub reason: String,
+  /// Download ceiling, in Mbps
+  pub download_mbps: u64,
+  /// Upload ceiling, in Mbps
+  pub upload_mbps: u64,
+}
+
+impl RateOverride {
+  fn limit(&self, rates: CircuitRates) -> CircuitRates {
+    let download_max = rates.download_max_mbps.min(self.download_mbps);
+    let upload_max = rates.upload_max_mbps.min(self.upload_mbps);
+    CircuitRates {
+      download_min_mbps: rates.download_min_mbps.min(download_max),
+      upload_min_mbps: rates.upload_min_mbps.min(upload_max),
+      download_max_mbps: download_max,
+      upload_max_mbps: upload_max,
+    }
+  }
+}
+
+/// Limits a circuit's rates until the override is cleared (with
+/// `None`). The change is applied by the rate plan scheduler.
+pub fn set_rate_override(
+  circuit_id: &str,
+  rate_override: Option<RateOverride>,
+) {
+  match rate_override {
+    Some(rate_override) => {
+      RATE_OVERRIDES.insert(circuit_id.to_string(), rate_override);
+    }
+    None => {
+      RATE_OVERRIDES.remove(circuit_id);
+    }
+  }
+}
+
+/// Set when `queuingStructure.json` is rewritten: the queues are being
+/// rebuilt at their base rates.
+static QUEUES_REBUILT: AtomicBool = AtomicBool::new(true);
+
+pub(crate) fn queues_rebuilt() {
+  QUEUES_REBUILT.store(true, Ordering::Relaxed);
+}
+
+/// Starts a thread that applies rate plans (from `RatePlans.toml`),
+/// burst ceilings and rate overrides, changing circuits' HTB classes
+/// in place as plan windows open and close.
+pub fn spawn_rate_plan_scheduler() {
+  std::thread::spawn(|| {
+    info!("Starting the rate plan scheduler.");
+    periodic(SCHEDULE_INTERVAL_MS, "Rate Plans", &mut || {
+      apply_rate_plans();
+    });
+  });
+}
+
+/// The rates circuits are currently shaped at, optionally limited to
+/// a single circuit.
+pub fn get_effective_circuit_rates(
+  circuit_id: Option<&str>,
+) -> Vec<CircuitRateStatus> {
+  let schedule = RATE_SCHEDULE.lock().unwrap();
+  let mut result: Vec<CircuitRateStatus> = schedule
+    .values()
+    .filter(|c| {
+      circuit_id.is_none() || circuit_id == Some(c.status.circuit_id.as_str())
+    })
+    .map(|c| c.status.clone())
+    .collect();
+  result.sort_by(|a, b| a.circuit_id.cmp(&b.circuit_id));
+  result
+}
+
+/// Is `LibreQoS.py` rebuilding the queues? A lock file left behind by
+/// a crashed run is ignored once it is stale.
+pub fn libreqos_is_running() -> bool {
+  std::fs::metadata(Path::new(LIBREQOS_LOCK_FILE))
+    .and_then(|m| m.modified())
+    .map(|modified| {
+      SystemTime::now()
+        .duration_since(modified)
+        .map_or(true, |age| age < STALE_LOCK)
+    })
+    .unwrap_or(false)
+}
+
+fn window_label(window: &RateWindow) -> String {
+  if window.name.is_empty() {
+    format!("{}-{}", window.start, window.end)
+  } else {
+    window.name.clone()
+  }
+}
+
+fn apply_rate_plans() {
+  // Wait for a reload to finish: until then, the classes may not exist
+  // or may be about to be replaced.
+  if libreqos_is_running() {
+    return;
+  }
+  let mut settings = SETTINGS.lock().unwrap();
+  if settings.ticks % RELOAD_TICKS == 0 {
+    settings.reload();
+  }
+  settings.ticks += 1;
+  if settings.pairs.is_empty() {
+    return;
+  }
+  let circuits: Vec<_> = {
+    let structure = QUEUE_STRUCTURE.read().unwrap();
+    let Some(queues) = structure.maybe_queues.as_ref() else { return };
+    queues
+      .iter()
+      .filter(|q| q.device_id.is_none())
+      .filter_map(|q| {
+        let base = CircuitRates {
+          download_min_mbps: q.download_bandwidth_mbps_min,
+          upload_min_mbps: q.upload_bandwidth_mbps_min,
+          download_max_mbps: q.download_bandwidth_mbps,
+          upload_max_mbps: q.upload_bandwidth_mbps,
+        };
+        Some((q.circuit_id.clone()?, q.class_id, q.up_class_id, base))
+      })
+      .collect()
+  };
+
+  let mut schedule = RATE_SCHEDULE.lock().unwrap();
+  if QUEUES_REBUILT.swap(false, Ordering::Relaxed) {
+    // Everything is back at its base rate
+    schedule.clear();
+  }
+  let time = PlanTime::now();
+  let mut next = HashMap::with_capacity(circuits.len());
+  for (circuit_id, class_id, up_class_id, base) in circuits {
+    let plan = settings.plans.plan_for_circuit(&circuit_id);
+    let window = plan.and_then(|p| p.active_window(time));
+    let effective = window.map_or(base, |w| w.apply(base));
+    let burst = BURST_CEILINGS.get(&circuit_id).map(|b| *b);
+    let effective = burst.map_or(effective, |b| b.raise(effective));
+    let rate_override = RATE_OVERRIDES.get(&circuit_id).map(|o| o.clone());
+    let effective =
+      rate_override.as_ref().map_or(effective, |o| o.limit(effective));
+    let applied = schedule
+      .get(&circuit_id)
+      .filter(|c| c.class_id == class_id && c.up_class_id == up_class_id)
+      .map_or(base, |c| c.applied);
+
+    let mut scheduled = ScheduledCircuit {
+      status: CircuitRateStatus {
+        circuit_id: circuit_id.clone(),
+        plan: plan.map(|p| p.name.clone()),
+        window: window.map(window_label),
+        override_reason: rate_override.map(|o| o.reason),
+        bursting: burst.is_some(),
+        base,
+        effective: applied,
+      },
+      class_id,
+      up_class_id,
+      applied,
+    };
+    if effective != applied && !settings.monitor_mode {
+      // Leave the old rates recorded on failure, so it's retried
+      if change_circuit_rates(&settings.pairs, &scheduled, effective) {
+        debug!(
+          "Circuit {circuit_id} now shaped at {}/{} Mbps ({})",
+          effective.download_max_mbps,
+          effective.upload_max_mbps,
+          scheduled
+            .status
+            .override_reason
+            .as_deref()
+            .or(scheduled.status.window.as_deref())
+            .unwrap_or("base rate"),
+        );
+        scheduled.applied = effective;
+        scheduled.status.effective = effective;
+      }
+    }
+    next.insert(circuit_id, scheduled);
+  }
+  *schedule = next;
+}
+
+fn change_circuit_rates(
+  pairs: &[InterfacePair],
+  circuit: &ScheduledCircuit,
+  rates: CircuitRates,
+) -> bool {
+  let mut success = true;
+  for pair in pairs {
+    for (interface, class_id, min, max) in [
+      (
+        pair.isp_facing_interface(),
+        circuit.class_id,
+        rates.download_min_mbps,
+        rates.download_max_mbps,
+      ),
+      (
+        pair.internet_interface.as_str(),
+        circuit.up_class_id,
+        rates.upload_min_mbps,
+        rates.upload_max_mbps,
+      ),
+    ] {
+      let class_id = class_id.to_string();
+      let rate = format!("{min}mbit");
+      let ceil = format!("{max}mbit");
+      let result = Command::new(TC)
+        .args([
+          "class", "change", "dev", interface, "classid", &class_id, "htb",
+          "rate", &rate, "ceil", &ceil, "prio", "3",
+        ])
+        .output();
+      match result {
+        Ok(output) if output.status.success() => {}
+        Ok(output) => {
+          warn!(
+            "tc class change dev {interface} classid {class_id} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+          );
+          success = false;
+        }
+        Err(e) => {
+          error!("Unable to run {TC}: {e:?}");
+          success = false;
+        }
+      }
+    }
+  }
+  success
+}
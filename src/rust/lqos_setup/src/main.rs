@@ -1,5 +1,6 @@
 use colored::Colorize;
 use default_net::{get_interfaces, interface::InterfaceType, Interface};
+use lqos_config::ConfigRevisions;
 use uuid::Uuid;
 use std::{fs, path::Path, process::Command};
 
@@ -52,6 +53,7 @@ pub fn read_line_as_number() -> u32 {
 }
 
 const LQOS_CONF: &str = "/etc/lqos.conf";
+const LQOS_DIRECTORY: &str = "/opt/libreqos/src";
 const ISP_CONF: &str = "/opt/libreqos/src/ispConfig.py";
 const NETWORK_JSON: &str = "/opt/libreqos/src/network.json";
 const SHAPED_DEVICES: &str = "/opt/libreqos/src/ShapedDevices.csv";
@@ -207,6 +209,14 @@ fn write_shaped_devices() {
     .expect("Unable to write file");
 }
 
+fn record_revision() {
+  let revisions = ConfigRevisions::for_directory(LQOS_DIRECTORY);
+  if let Err(e) = revisions.record("lqos_setup", "Initial setup") {
+    let message = format!("Unable to save a configuration revision: {e}");
+    println!("{}", message.red());
+  }
+}
+
 fn anonymous() -> bool {
   println!("{}", "Help Improve LibreQoS with Anonymous Statistics?".yellow());
   println!("{}", "We'd really appreciate it if you'd allow anonymous statistics".green());
@@ -229,6 +239,7 @@ fn main() {
   let interfaces = get_available_interfaces();
   let mut if_internet: Option<String> = None;
   let mut if_isp: Option<String> = None;
+  let mut changed = false;
   if should_build(LQOS_CONF) {
     println!(
       "{}{}",
@@ -241,6 +252,7 @@ fn main() {
     if let (Some(internet), Some(isp)) = (&if_internet, &if_isp) {
       write_etc_lqos_conf(internet, isp, allow_anonymous);
     }
+    changed = true;
   }
 
   if should_build(ISP_CONF) {
@@ -258,6 +270,7 @@ fn main() {
         internet,
       )
     }
+    changed = true;
   }
 
   if should_build(NETWORK_JSON) {
@@ -267,6 +280,7 @@ fn main() {
       "does not exist, making a simple flat network.".white()
     );
     write_network_json();
+    changed = true;
   }
   if should_build(SHAPED_DEVICES) {
     println!(
@@ -276,6 +290,10 @@ fn main() {
     );
     println!("{}", "Don't forget to add some users!".magenta());
     write_shaped_devices();
+    changed = true;
+  }
+  if changed {
+    record_revision();
   }
   if should_build(LQUSERS) {
     println!("Enter a username for the web manager:");
@@ -10,20 +10,29 @@ struct BifrostInterface {
   scan_vlans: u32,
 }
 
+#[repr(C)]
+#[derive(Default, Clone, Debug)]
+struct BifrostVlanKey {
+  ifindex: u32,
+  outer_vlan: u16,
+  inner_vlan: u16,
+}
+
 #[repr(C)]
 #[derive(Default, Clone, Debug)]
 struct BifrostVlan {
-  redirect_to: u32,
+  redirect_outer: u16,
+  redirect_inner: u16,
 }
 
 const INTERFACE_PATH: &str = "/sys/fs/bpf/bifrost_interface_map";
-const VLAN_PATH: &str = "/sys/fs/bpf/bifrost_vlan_map";
+const VLAN_PATH: &str = "/sys/fs/bpf/bifrost_vlans";
 
 pub(crate) fn clear_bifrost() -> Result<()> {
   info!("Clearing bifrost maps");
   let mut interface_map =
     BpfMap::<u32, BifrostInterface>::from_path(INTERFACE_PATH)?;
-  let mut vlan_map = BpfMap::<u32, BifrostVlan>::from_path(VLAN_PATH)?;
+  let mut vlan_map = BpfMap::<BifrostVlanKey, BifrostVlan>::from_path(VLAN_PATH)?;
   info!("Clearing VLANs");
   vlan_map.clear_no_repeat()?;
   info!("Clearing Interfaces");
@@ -54,17 +63,26 @@ pub(crate) fn map_interfaces(mappings: &[BridgeInterface]) -> Result<()> {
 
 pub(crate) fn map_vlans(mappings: &[BridgeVlan]) -> Result<()> {
   info!("VLAN maps");
-  let mut vlan_map = BpfMap::<u32, BifrostVlan>::from_path(VLAN_PATH)?;
+  let mut vlan_map = BpfMap::<BifrostVlanKey, BifrostVlan>::from_path(VLAN_PATH)?;
   for mapping in mappings.iter() {
-    let mut key: u32 =
-      (interface_name_to_index(&mapping.parent)? << 16) | mapping.tag;
-    let mut val = BifrostVlan { redirect_to: mapping.redirect_to };
+    let mut key = BifrostVlanKey {
+      ifindex: interface_name_to_index(&mapping.parent)?,
+      outer_vlan: mapping.tag as u16,
+      inner_vlan: mapping.inner_tag.unwrap_or(0) as u16,
+    };
+    let mut val = BifrostVlan {
+      redirect_outer: mapping.redirect_to as u16,
+      redirect_inner: mapping.redirect_inner_to.unwrap_or(0) as u16,
+    };
     vlan_map.insert(&mut key, &mut val)?;
     info!(
-      "Mapped bifrost VLAN: {}:{} => {}",
-      mapping.parent, mapping.tag, mapping.redirect_to
+      "Mapped bifrost VLAN: {}:{}.{} => {}.{}",
+      mapping.parent,
+      mapping.tag,
+      key.inner_vlan,
+      mapping.redirect_to,
+      val.redirect_inner
     );
-    info!("{key}");
   }
   Ok(())
 }
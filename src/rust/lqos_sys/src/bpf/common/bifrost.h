@@ -29,17 +29,33 @@ struct {
 	__uint(pinning, LIBBPF_PIN_BY_NAME);
 } bifrost_interface_map SEC(".maps");
 
-// TODO: This could be a u32 if we don't need any additional info.
-// Which VLAN should the keyed VLAN be redirected to?
+// Identifies a VLAN (or QinQ pair of VLANs) that should be
+// redirected.
+struct bifrost_vlan_key {
+    // The interface index on which the tag arrives.
+    __u32 ifindex;
+    // The outer (S-VLAN) tag, or the only tag.
+    __u16 outer_vlan;
+    // The inner (C-VLAN) tag. 0 matches any single-tagged frame,
+    // and any QinQ frame without a more specific entry.
+    __u16 inner_vlan;
+};
+
+// What the keyed VLAN(s) should be rewritten to.
 struct bifrost_vlan {
-    __u32 redirect_to;
+    // New outer tag (or only tag).
+    __u16 redirect_outer;
+    // New inner tag. 0 leaves the inner tag alone.
+    __u16 redirect_inner;
 };
 
-// Hash map of VLANs that should be redirected.
+// Hash map of VLANs that should be redirected. Replaces the
+// single-tag `bifrost_vlan_map`, under a new name because the key
+// layout changed and pinned maps are reused by name.
 struct {
 	__uint(type, BPF_MAP_TYPE_HASH);
 	__uint(max_entries, 64);
-	__type(key, __u32);
+	__type(key, struct bifrost_vlan_key);
 	__type(value, struct bifrost_vlan);
 	__uint(pinning, LIBBPF_PIN_BY_NAME);
-} bifrost_vlan_map SEC(".maps");
\ No newline at end of file
+} bifrost_vlans SEC(".maps");
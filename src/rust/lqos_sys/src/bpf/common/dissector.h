@@ -43,6 +43,10 @@ struct dissector_t
     // Current VLAN tag. If there are multiple tags, it will be
     // the INNER tag.
     __be16 current_vlan;
+    // Outer (first) VLAN tag, 0 if untagged.
+    __be16 outer_vlan;
+    // Inner (second) VLAN tag, 0 if there are fewer than two tags.
+    __be16 inner_vlan;
     // IP protocol from __UAPI_DEF_IN_IPPROTO
     __u8 ip_protocol;
     __u16 src_port;
@@ -110,6 +114,8 @@ static __always_inline bool dissector_new(
     dissector->l3offset = 0;
     dissector->skb_len = dissector->end - dissector->start;
     dissector->current_vlan = 0;
+    dissector->outer_vlan = 0;
+    dissector->inner_vlan = 0;
     dissector->ip_protocol = 0;
     dissector->src_port = 0;
     dissector->dst_port = 0;
@@ -131,6 +137,47 @@ static __always_inline bool is_ip(__u16 eth_type)
     return eth_type == ETH_P_IP || eth_type == ETH_P_IPV6;
 }
 
+// Rewrites the VLAN tags of a frame if Bifrost has a matching VLAN
+// redirect. An exact (outer, inner) match is preferred, falling back
+// to a match on the outer tag alone.
+static __always_inline void bifrost_redirect_vlans(
+    struct dissector_t *dissector,
+    struct vlan_hdr *outer_tag,
+    struct vlan_hdr *inner_tag)
+{
+    if (outer_tag == NULL) return;
+    struct bifrost_vlan_key key = {0};
+    key.ifindex = dissector->ctx->ingress_ifindex;
+    key.outer_vlan = bpf_ntohs(dissector->outer_vlan);
+    key.inner_vlan = bpf_ntohs(dissector->inner_vlan);
+#ifdef VERBOSE
+    bpf_debug("Searching for redirect %u:%u.%u", key.ifindex,
+              key.outer_vlan, key.inner_vlan);
+#endif
+    struct bifrost_vlan *vlan_info = NULL;
+    if (key.inner_vlan != 0)
+    {
+        vlan_info = bpf_map_lookup_elem(&bifrost_vlans, &key);
+    }
+    if (vlan_info == NULL)
+    {
+        key.inner_vlan = 0;
+        vlan_info = bpf_map_lookup_elem(&bifrost_vlans, &key);
+    }
+    if (vlan_info)
+    {
+#ifdef VERBOSE
+        bpf_debug("Redirect to VLAN %u.%u", vlan_info->redirect_outer,
+                  vlan_info->redirect_inner);
+#endif
+        outer_tag->h_vlan_TCI = bpf_htons(vlan_info->redirect_outer);
+        if (inner_tag != NULL && vlan_info->redirect_inner != 0)
+        {
+            inner_tag->h_vlan_TCI = bpf_htons(vlan_info->redirect_inner);
+        }
+    }
+}
+
 // Locates the layer-3 offset, if present. Fast returns for various
 // common non-IP types. Will perform VLAN redirection if requested.
 static __always_inline bool dissector_find_l3_offset(
@@ -144,6 +191,8 @@ static __always_inline bool dissector_find_l3_offset(
     }
     __u32 offset = sizeof(struct ethhdr);
     __u16 eth_type = bpf_ntohs(dissector->ethernet_header->h_proto);
+    struct vlan_hdr *outer_tag = NULL;
+    struct vlan_hdr *inner_tag = NULL;
 
     // Fast return for unwrapped IP
     if (eth_type == ETH_P_IP || eth_type == ETH_P_IPV6)
@@ -176,29 +225,25 @@ static __always_inline bool dissector_find_l3_offset(
             }
             struct vlan_hdr *vlan = (struct vlan_hdr *)(dissector->start + offset);
             dissector->current_vlan = vlan->h_vlan_TCI;
+            if (outer_tag == NULL)
+            {
+                outer_tag = vlan;
+                dissector->outer_vlan = vlan->h_vlan_TCI;
+            }
+            else if (inner_tag == NULL)
+            {
+                inner_tag = vlan;
+                dissector->inner_vlan = vlan->h_vlan_TCI;
+            }
             eth_type = bpf_ntohs(vlan->h_vlan_encapsulated_proto);
             offset += sizeof(struct vlan_hdr);
-            // VLAN Redirection is requested, so lookup a detination and
-            // switch the VLAN tag if required
-            if (vlan_redirect)
+            // VLAN Redirection is requested, so once we've left the
+            // VLAN stack lookup a destination and switch the tags if
+            // required.
+            if (vlan_redirect && eth_type != ETH_P_8021Q &&
+                eth_type != ETH_P_8021AD)
             {
-#ifdef VERBOSE
-                bpf_debug("Searching for redirect %u:%u",
-                          dissector->ctx->ingress_ifindex,
-                          bpf_ntohs(dissector->current_vlan));
-#endif
-                __u32 key = (dissector->ctx->ingress_ifindex << 16) |
-                            bpf_ntohs(dissector->current_vlan);
-                struct bifrost_vlan *vlan_info = NULL;
-                vlan_info = bpf_map_lookup_elem(&bifrost_vlan_map, &key);
-                if (vlan_info)
-                {
-#ifdef VERBOSE
-                    bpf_debug("Redirect to VLAN %u",
-                              bpf_htons(vlan_info->redirect_to));
-#endif
-                    vlan->h_vlan_TCI = bpf_htons(vlan_info->redirect_to);
-                }
+                bifrost_redirect_vlans(dissector, outer_tag, inner_tag);
             }
         }
         break;
@@ -240,7 +285,7 @@ static __always_inline bool dissector_find_l3_offset(
             struct mpls_label *mpls = (struct mpls_label *)(dissector->start + offset);
             // Are we at the bottom of the stack?
             offset += 4; // 32-bits
-            if (mpls->entry & MPLS_LS_S_MASK)
+            if (bpf_ntohl(mpls->entry) & MPLS_LS_S_MASK)
             {
                 // We've hit the bottom
                 if SKB_OVERFLOW_OFFSET (dissector->start, dissector->end,
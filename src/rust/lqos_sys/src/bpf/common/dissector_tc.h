@@ -31,10 +31,13 @@ struct tc_dissector_t
     struct in6_addr src_ip;
     // Destination IP, encoded by `ip_hash.h` functions.
     struct in6_addr dst_ip;
-    // Current VLAN detected.
-    // TODO: This can probably be removed since the packet dissector
-    // now finds this.
+    // Current VLAN detected. If there are multiple tags, it will be
+    // the INNER tag.
     __be16 current_vlan;
+    // Outer (first) VLAN tag, 0 if untagged.
+    __be16 outer_vlan;
+    // Inner (second) VLAN tag, 0 if there are fewer than two tags.
+    __be16 inner_vlan;
 };
 
 // Constructor for a dissector
@@ -53,7 +56,10 @@ static __always_inline bool tc_dissector_new(
     dissector->end = (void *)(long)ctx->data_end;
     dissector->ethernet_header = (struct ethhdr *)NULL;
     dissector->l3offset = 0;
+    // An offloaded tag is always the outermost one.
     dissector->current_vlan = bpf_htons(ctx->vlan_tci);
+    dissector->outer_vlan = dissector->current_vlan;
+    dissector->inner_vlan = 0;
 
     // Check that there's room for an ethernet header
     if SKB_OVERFLOW (dissector->start, dissector->end, ethhdr)
@@ -109,8 +115,14 @@ static __always_inline bool tc_dissector_find_l3_offset(
             //bpf_debug("TC Found VLAN");
             struct vlan_hdr *vlan = (struct vlan_hdr *)
                 (dissector->start + offset);
-            // Calculated from the SKB
-            //dissector->current_vlan = vlan->h_vlan_TCI;
+            // The outer tag comes from the SKB, unless it wasn't
+            // offloaded (as happens with BPF_PROG_TEST_RUN)
+            if (dissector->outer_vlan == 0) {
+                dissector->outer_vlan = vlan->h_vlan_TCI;
+            } else if (dissector->inner_vlan == 0) {
+                dissector->inner_vlan = vlan->h_vlan_TCI;
+            }
+            dissector->current_vlan = vlan->h_vlan_TCI;
             eth_type = bpf_ntohs(vlan->h_vlan_encapsulated_proto);
             offset += sizeof(struct vlan_hdr);
         }
@@ -154,7 +166,7 @@ static __always_inline bool tc_dissector_find_l3_offset(
                 (dissector->start + offset);
             // Are we at the bottom of the stack?
             offset += 4; // 32-bits
-            if (mpls->entry & MPLS_LS_S_MASK) {
+            if (bpf_ntohl(mpls->entry) & MPLS_LS_S_MASK) {
                 // We've hit the bottom
                 if SKB_OVERFLOW_OFFSET(dissector->start, dissector->end,
                     offset, iphdr) 
@@ -47,6 +47,23 @@ struct {
 	__uint(map_flags, BPF_F_NO_PREALLOC);
 } map_ip_to_cpu_and_tc_recip SEC(".maps");
 
+// On a stick, does the frame's VLAN tagging identify the Internet
+// side? With QinQ the (outer, inner) pair must match, otherwise the
+// innermost tag is compared.
+static __always_inline bool is_internet_vlan(
+    __be16 current_vlan,
+    __be16 outer_vlan,
+    __be16 inner_vlan,
+    __be16 internet_vlan,
+    __be16 internet_inner_vlan
+)
+{
+    if (internet_inner_vlan != 0) {
+        return outer_vlan == internet_vlan && inner_vlan == internet_inner_vlan;
+    }
+    return current_vlan == internet_vlan;
+}
+
 // Performs an LPM lookup for an `ip_hash.h` encoded address, taking
 // into account redirection and "on a stick" setup.
 static __always_inline struct ip_hash_info * setup_lookup_key_and_tc_cpu(
@@ -61,6 +78,9 @@ static __always_inline struct ip_hash_info * setup_lookup_key_and_tc_cpu(
     // Which VLAN represents the Internet, in redirection scenarios? (i.e.
     // when direction == 3)
     __be16 internet_vlan,
+    // Inner (C-VLAN) tag that, combined with `internet_vlan` as the
+    // outer tag, represents the Internet. 0 if not using QinQ.
+    __be16 internet_inner_vlan,
     // Out variable setting the real "direction" of traffic when it has to
     // be calculated.
     int * out_effective_direction
@@ -79,7 +99,8 @@ static __always_inline struct ip_hash_info * setup_lookup_key_and_tc_cpu(
         );
         return ip_info;
     } else {
-        if (dissector->current_vlan == internet_vlan) {
+        if (is_internet_vlan(dissector->current_vlan, dissector->outer_vlan,
+            dissector->inner_vlan, internet_vlan, internet_inner_vlan)) {
             // Packet is coming IN from the Internet.
             // Therefore it is download.
             lookup_key->address = dissector->dst_ip;
@@ -118,6 +139,9 @@ static __always_inline struct ip_hash_info * tc_setup_lookup_key_and_tc_cpu(
     // Which VLAN represents the Internet, in redirection scenarios? (i.e.
     // when direction == 3)
     __be16 internet_vlan,
+    // Inner (C-VLAN) tag that, combined with `internet_vlan` as the
+    // outer tag, represents the Internet. 0 if not using QinQ.
+    __be16 internet_inner_vlan,
     // Out variable setting the real "direction" of traffic when it has to
     // be calculated.
     int * out_effective_direction
@@ -138,7 +162,8 @@ static __always_inline struct ip_hash_info * tc_setup_lookup_key_and_tc_cpu(
         //bpf_debug("Current VLAN (TC): %d", dissector->current_vlan);
         //bpf_debug("Source: %x", dissector->src_ip.in6_u.u6_addr32[3]);
         //bpf_debug("Dest: %x", dissector->dst_ip.in6_u.u6_addr32[3]);
-        if (dissector->current_vlan == internet_vlan) {
+        if (is_internet_vlan(dissector->current_vlan, dissector->outer_vlan,
+            dissector->inner_vlan, internet_vlan, internet_inner_vlan)) {
             // Packet is going OUT to the Internet.
             // Therefore, it is UPLOAD.
             lookup_key->address = dissector->src_ip;
@@ -0,0 +1,60 @@
+#pragma once
+
+#include <linux/bpf.h>
+#include <bpf/bpf_helpers.h>
+#include <stdbool.h>
+#include "debug.h"
+
+// Support for explaining how a packet is classified, using
+// BPF_PROG_TEST_RUN. Userspace prepends a `packet_trace_meta`
+// block to the frame as XDP metadata. Live traffic never carries
+// metadata with the magic number, so the XDP program only fills in
+// the trace when it is explicitly asked to - and the results are
+// copied back to userspace along with the frame.
+//
+// On the TC side, the `__sk_buff` mark is set to the magic number
+// and the results are returned in `cb`.
+
+#define PACKET_TRACE_MAGIC 0x4C515452 // "LQTR"
+
+// How far through classification did the packet get?
+#define PACKET_TRACE_STAGE_NONE 0
+#define PACKET_TRACE_STAGE_DISSECTED 1
+#define PACKET_TRACE_STAGE_CLASSIFIED 2
+
+// Metadata prepended to a traced frame. Must remain a multiple of
+// 4 bytes, and no more than 32 bytes (the XDP metadata limit).
+struct packet_trace_meta {
+    // Must equal PACKET_TRACE_MAGIC for the trace to be recorded.
+    __u32 magic;
+    // One of the PACKET_TRACE_STAGE_ constants
+    __u32 stage;
+    // 1 = download (from the Internet), 2 = upload (from the ISP)
+    __u32 effective_direction;
+    // Non-zero if the LPM lookup found an entry
+    __u32 matched;
+    // The CPU index from the LPM entry
+    __u32 cpu;
+    // The TC handle from the LPM entry
+    __u32 tc_handle;
+    // The CPU map destination, if the CPU is available
+    __u32 cpu_dest;
+};
+
+// Returns a pointer to the trace metadata if this is a trace run,
+// NULL otherwise.
+static __always_inline struct packet_trace_meta * packet_trace_xdp(
+    struct xdp_md *ctx
+) {
+    void *meta = (void *)(long)ctx->data_meta;
+    void *data = (void *)(long)ctx->data;
+    struct packet_trace_meta *trace = meta;
+    if ((void *)(trace + 1) > data) return NULL;
+    if (trace->magic != PACKET_TRACE_MAGIC) return NULL;
+    return trace;
+}
+
+// Is this SKB a trace run?
+static __always_inline bool packet_trace_tc(struct __sk_buff *skb) {
+    return skb->mark == PACKET_TRACE_MAGIC;
+}
@@ -69,4 +69,55 @@ static __always_inline void track_traffic(
             bpf_debug("Failed to insert flow");
         }
     }
-}
\ No newline at end of file
+}
+
+// Counter for each interface, so that traffic can be totalled per
+// bridge pair.
+struct interface_counter {
+    __u64 download_bytes;
+    __u64 upload_bytes;
+    __u64 download_packets;
+    __u64 upload_packets;
+};
+
+// Pinned map storing counters per ingress interface index.
+struct
+{
+	__uint(type, BPF_MAP_TYPE_PERCPU_HASH);
+	__type(key, __u32);
+	__type(value, struct interface_counter);
+	__uint(max_entries, 64);
+	__uint(pinning, LIBBPF_PIN_BY_NAME);
+} map_interface_traffic SEC(".maps");
+
+static __always_inline void track_interface_traffic(
+    int direction,
+    __u32 ifindex,
+    __u32 size
+) {
+    // Per-CPU, so no sync required
+    struct interface_counter * counter =
+        (struct interface_counter *)bpf_map_lookup_elem(
+            &map_interface_traffic, &ifindex
+        );
+    if (counter) {
+        if (direction == 1) {
+            counter->download_packets += 1;
+            counter->download_bytes += size;
+        } else {
+            counter->upload_packets += 1;
+            counter->upload_bytes += size;
+        }
+    } else {
+        struct interface_counter new_counter = {0};
+        if (direction == 1) {
+            new_counter.download_packets = 1;
+            new_counter.download_bytes = size;
+        } else {
+            new_counter.upload_packets = 1;
+            new_counter.upload_bytes = size;
+        }
+        bpf_map_update_elem(&map_interface_traffic, &ifindex, &new_counter,
+            BPF_NOEXIST);
+    }
+}
@@ -18,6 +18,7 @@
 #include "common/tcp_rtt.h"
 #include "common/bifrost.h"
 #include "common/heimdall.h"
+#include "common/packet_trace.h"
 
 //#define VERBOSE 1
 
@@ -53,6 +54,10 @@ int direction = 255;
 // these are mapped to the respective VLAN facing directions.
 __be16 internet_vlan = 0; // Note: turn these into big-endian
 __be16 isp_vlan = 0;
+// QinQ on a stick: the inner (C-VLAN) tags that, combined with the
+// outer tags above, identify each side. 0 if not using QinQ.
+__be16 internet_inner_vlan = 0;
+__be16 isp_inner_vlan = 0;
 
 // XDP Entry Point
 SEC("xdp")
@@ -85,6 +90,10 @@ int xdp_prog(struct xdp_md *ctx)
         }
     }
 
+    // Are we explaining a packet (BPF_PROG_TEST_RUN), rather than
+    // handling live traffic?
+    struct packet_trace_meta * trace = packet_trace_xdp(ctx);
+
     struct dissector_t dissector = {0};
 #ifdef VERBOSE
     bpf_debug("(XDP) START XDP");
@@ -101,6 +110,7 @@ int xdp_prog(struct xdp_md *ctx)
 #ifdef VERBOSE
     bpf_debug("(XDP) Spotted VLAN: %u", dissector.current_vlan);
 #endif
+    if (trace) trace->stage = PACKET_TRACE_STAGE_DISSECTED;
 
     // Determine the lookup key by direction
     struct ip_hash_key lookup_key;
@@ -110,6 +120,7 @@ int xdp_prog(struct xdp_md *ctx)
         &lookup_key, 
         &dissector, 
         internet_vlan, 
+        internet_inner_vlan,
         &effective_direction
     );
 #ifdef VERBOSE
@@ -123,20 +134,33 @@ int xdp_prog(struct xdp_md *ctx)
         tc_handle = ip_info->tc_handle;
         cpu = ip_info->cpu;
     }
-    // Update the traffic tracking buffers
-    track_traffic(
-        effective_direction, 
-        &lookup_key.address, 
-        ctx->data_end - ctx->data, // end - data = length
-        tc_handle
-    );
+    if (trace) {
+        trace->stage = PACKET_TRACE_STAGE_CLASSIFIED;
+        trace->effective_direction = effective_direction;
+        trace->matched = ip_info ? 1 : 0;
+        trace->cpu = cpu;
+        trace->tc_handle = tc_handle;
+    } else {
+        // Update the traffic tracking buffers
+        track_traffic(
+            effective_direction, 
+            &lookup_key.address, 
+            ctx->data_end - ctx->data, // end - data = length
+            tc_handle
+        );
+        track_interface_traffic(
+            effective_direction,
+            ctx->ingress_ifindex,
+            ctx->data_end - ctx->data
+        );
+    }
 
 
     // Send on its way
     if (tc_handle != 0) {
         // Send data to Heimdall
         __u8 heimdall_mode = get_heimdall_mode();
-        if (heimdall_mode > 0 && is_heimdall_watching(&dissector, effective_direction)) {
+        if (!trace && heimdall_mode > 0 && is_heimdall_watching(&dissector, effective_direction)) {
 #ifdef VERBOSE
             bpf_debug("(XDP) Storing Heimdall Data");
 #endif            
@@ -151,6 +175,7 @@ int xdp_prog(struct xdp_md *ctx)
             return XDP_PASS; // No CPU found
         }
         __u32 cpu_dest = *cpu_lookup;
+        if (trace) trace->cpu_dest = cpu_dest;
 
         // Redirect based on CPU
 #ifdef VERBOSE
@@ -214,19 +239,28 @@ int tc_iphash_to_cpu(struct __sk_buff *skb)
         &lookup_key, 
         &dissector, 
         internet_vlan, 
+        internet_inner_vlan,
         &effective_direction
     );
 #ifdef VERBOSE
     bpf_debug("(TC) effective direction: %d", effective_direction);
 #endif
 
-    // Call pping to obtain RTT times
-    struct parsing_context context = {0};
-    context.now = bpf_ktime_get_ns();
-    context.tcp = NULL;
-    context.dissector = &dissector;
-    context.active_host = &lookup_key.address;
-    tc_pping_start(&context);
+    // Trace runs report their classification in the SKB control
+    // block, and don't contribute to RTT tracking.
+    if (packet_trace_tc(skb)) {
+        skb->cb[0] = effective_direction;
+        skb->cb[1] = ip_info ? 1 : 0;
+        skb->cb[2] = ip_info ? ip_info->cpu : 0;
+    } else {
+        // Call pping to obtain RTT times
+        struct parsing_context context = {0};
+        context.now = bpf_ktime_get_ns();
+        context.tcp = NULL;
+        context.dissector = &dissector;
+        context.active_host = &lookup_key.address;
+        tc_pping_start(&context);
+    }
 
     if (ip_info && ip_info->tc_handle != 0) {
         // We found a matching mapped TC flow
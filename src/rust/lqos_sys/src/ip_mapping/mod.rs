@@ -2,7 +2,6 @@ use crate::bpf_map::BpfMap;
 use anyhow::Result;
 use lqos_bus::TcHandle;
 use lqos_utils::XdpIpAddress;
-use std::net::IpAddr;
 mod ip_hash_data;
 mod ip_hash_key;
 mod ip_to_map;
@@ -52,8 +51,7 @@ pub fn del_ip_from_tc(address: &str, upload: bool) -> Result<()> {
   };
   let ip_to_add = IpToMap::new(address, TcHandle::from_string("0:0")?, 0)?;
   let mut bpf_map = BpfMap::<IpHashKey, IpHashData>::from_path(bpf_path)?;
-  let ip = address.parse::<IpAddr>()?;
-  let ip = XdpIpAddress::from_ip(ip);
+  let ip = XdpIpAddress::from_ip(ip_to_add.subnet);
   let mut key = IpHashKey { prefixlen: ip_to_add.prefix, address: ip.0 };
   bpf_map.delete(&mut key)?;
   Ok(())
@@ -76,16 +74,21 @@ pub fn clear_ips_from_tc() -> Result<()> {
 
 /// Query the underlying IP address to TC map and return the currently active dataset.
 pub fn list_mapped_ips() -> Result<Vec<(IpHashKey, IpHashData)>> {
-  let bpf_map = BpfMap::<IpHashKey, IpHashData>::from_path(
-    "/sys/fs/bpf/map_ip_to_cpu_and_tc",
-  )?;
-  let mut raw = bpf_map.dump_vec();
-
-  let bpf_map2 = BpfMap::<IpHashKey, IpHashData>::from_path(
-    "/sys/fs/bpf/map_ip_to_cpu_and_tc_recip",
-  )?;
-  let raw2 = bpf_map2.dump_vec();
-  raw.extend_from_slice(&raw2);
-
+  let mut raw = list_mapped_ips_for(false)?;
+  raw.extend_from_slice(&list_mapped_ips_for(true)?);
   Ok(raw)
 }
+
+/// Like `list_mapped_ips`, for only the download map, or only the
+/// upload map used on a stick.
+pub fn list_mapped_ips_for(
+  upload: bool,
+) -> Result<Vec<(IpHashKey, IpHashData)>> {
+  let bpf_path = if upload {
+    "/sys/fs/bpf/map_ip_to_cpu_and_tc_recip"
+  } else {
+    "/sys/fs/bpf/map_ip_to_cpu_and_tc"
+  };
+  let bpf_map = BpfMap::<IpHashKey, IpHashData>::from_path(bpf_path)?;
+  Ok(bpf_map.dump_vec())
+}
@@ -1,4 +1,7 @@
 use std::sync::Mutex;
+use dashmap::DashMap;
+use log::info;
+use lqos_config::InterfacePair;
 use once_cell::sync::Lazy;
 use crate::lqos_kernel::{
   attach_xdp_and_tc_to_interface, unload_xdp_from_interface,
@@ -23,13 +26,16 @@ unsafe impl Send for LqosKernBpfWrapper {}
 
 pub(crate) static BPF_SKELETON: Lazy<Mutex<Option<LqosKernBpfWrapper>>> = Lazy::new(|| Mutex::new(None));
 
+/// Each attached interface has its own copy of the programs (with its
+/// own `direction` setting). They are kept here, keyed by interface name,
+/// so that individual programs can be test-run later.
+pub(crate) static INTERFACE_SKELETONS: Lazy<DashMap<String, LqosKernBpfWrapper>> = Lazy::new(DashMap::new);
+
 /// A wrapper-type that stores the interfaces to which the XDP and TC programs should
 /// be attached. Performs the attachment process, and hooks "drop" to unattach the
 /// programs when the structure falls out of scope.
 pub struct LibreQoSKernels {
-  to_internet: String,
-  to_isp: String,
-  on_a_stick: bool,
+  attached: Vec<String>,
 }
 
 impl LibreQoSKernels {
@@ -46,22 +52,9 @@ impl LibreQoSKernels {
   /// * `heimdall_event_handler` - C function pointer to the ringbuffer
   ///    event handler exported by Heimdall.
   pub fn new<S: ToString>(to_internet: S, to_isp: S, heimdall_event_handler: ring_buffer_sample_fn) -> anyhow::Result<Self> {
-    let kernel = Self {
-      to_internet: to_internet.to_string(),
-      to_isp: to_isp.to_string(),
-      on_a_stick: false,
-    };
-    let skeleton = attach_xdp_and_tc_to_interface(
-      &kernel.to_internet,
-      InterfaceDirection::Internet,
-      heimdall_event_handler,
-    )?;
-    attach_xdp_and_tc_to_interface(
-      &kernel.to_isp,
-      InterfaceDirection::IspNetwork,
-      heimdall_event_handler,
-    )?;
-    BPF_SKELETON.lock().unwrap().replace(LqosKernBpfWrapper { ptr: skeleton });
+    let mut kernel = Self { attached: Vec::new() };
+    kernel.attach(&to_internet.to_string(), InterfaceDirection::Internet, heimdall_event_handler)?;
+    kernel.attach(&to_isp.to_string(), InterfaceDirection::IspNetwork, heimdall_event_handler)?;
     Ok(kernel)
   }
 
@@ -76,34 +69,88 @@ impl LibreQoSKernels {
   /// * `stick_interfaace` - the name of the VLAN trunked interface.
   /// * `internet_vlan` - the VLAN ID facing the Internet. Endianness is fixed for you.
   /// * `isp_vlan` - the VLAN ID facing the ISP core router. Endianness is fixed for you.
+  /// * `inner_vlans` - with QinQ, the inner (C-VLAN) tags `(internet, isp)` that must
+  ///   accompany the VLANs above. Use 0 for single-tagged operation.
   pub fn on_a_stick_mode<S: ToString>(
     stick_interface: S,
     internet_vlan: u16,
     isp_vlan: u16,
+    inner_vlans: (u16, u16),
     heimdall_event_handler: ring_buffer_sample_fn,
   ) -> anyhow::Result<Self> {
-    let kernel = Self {
-      to_internet: stick_interface.to_string(),
-      to_isp: String::new(),
-      on_a_stick: true,
-    };
-    let skeleton = attach_xdp_and_tc_to_interface(
-      &kernel.to_internet,
-      InterfaceDirection::OnAStick(internet_vlan, isp_vlan),
+    let mut kernel = Self { attached: Vec::new() };
+    kernel.attach(
+      &stick_interface.to_string(),
+      InterfaceDirection::OnAStick {
+        internet: internet_vlan,
+        isp: isp_vlan,
+        internet_inner: inner_vlans.0,
+        isp_inner: inner_vlans.1,
+      },
       heimdall_event_handler,
     )?;
-    BPF_SKELETON.lock().unwrap().replace(LqosKernBpfWrapper { ptr: skeleton });
     Ok(kernel)
   }
+
+  /// Creates a new `LibreQosKernels` structure serving several shaping
+  /// bridge pairs. Each pair is attached with its own direction (and VLAN)
+  /// settings; all of them share the pinned IP mapping and throughput maps.
+  /// If any attachment fails, the interfaces attached so far are detached
+  /// again and an error is returned.
+  ///
+  /// ## Arguments
+  ///
+  /// * `pairs` - the interface pairs, usually from `LibreQoSConfig::interface_pairs`.
+  /// * `heimdall_event_handler` - C function pointer to the ringbuffer
+  ///    event handler exported by Heimdall.
+  pub fn with_pairs(
+    pairs: &[InterfacePair],
+    heimdall_event_handler: ring_buffer_sample_fn,
+  ) -> anyhow::Result<Self> {
+    let mut kernel = Self { attached: Vec::new() };
+    for pair in pairs.iter() {
+      info!("Attaching to interface pair {}", pair.name);
+      if pair.on_a_stick {
+        kernel.attach(
+          &pair.internet_interface,
+          InterfaceDirection::OnAStick {
+            internet: pair.internet_vlan,
+            isp: pair.isp_vlan,
+            internet_inner: pair.internet_inner_vlan,
+            isp_inner: pair.isp_inner_vlan,
+          },
+          heimdall_event_handler,
+        )?;
+      } else {
+        kernel.attach(&pair.internet_interface, InterfaceDirection::Internet, heimdall_event_handler)?;
+        kernel.attach(&pair.isp_interface, InterfaceDirection::IspNetwork, heimdall_event_handler)?;
+      }
+    }
+    Ok(kernel)
+  }
+
+  fn attach(
+    &mut self,
+    interface: &str,
+    direction: InterfaceDirection,
+    heimdall_event_handler: ring_buffer_sample_fn,
+  ) -> anyhow::Result<()> {
+    let skeleton = attach_xdp_and_tc_to_interface(interface, direction, heimdall_event_handler)?;
+    self.attached.push(interface.to_string());
+    INTERFACE_SKELETONS.insert(interface.to_string(), LqosKernBpfWrapper { ptr: skeleton });
+    // The first attached interface provides the map iterators
+    let mut primary = BPF_SKELETON.lock().unwrap();
+    if primary.is_none() {
+      primary.replace(LqosKernBpfWrapper { ptr: skeleton });
+    }
+    Ok(())
+  }
 }
 
 impl Drop for LibreQoSKernels {
   fn drop(&mut self) {
-    if !self.on_a_stick {
-      let _ = unload_xdp_from_interface(&self.to_internet);
-      let _ = unload_xdp_from_interface(&self.to_isp);
-    } else {
-      let _ = unload_xdp_from_interface(&self.to_internet);
+    for interface in self.attached.iter() {
+      let _ = unload_xdp_from_interface(interface);
     }
   }
 }
@@ -15,6 +15,7 @@ mod cpu_map;
 mod ip_mapping;
 mod kernel_wrapper;
 mod lqos_kernel;
+mod packet_trace;
 mod tcp_rtt;
 mod throughput;
 mod linux;
@@ -25,10 +26,14 @@ pub mod heimdall_data;
 
 pub use ip_mapping::{
   add_ip_to_tc, clear_ips_from_tc, del_ip_from_tc, list_mapped_ips,
+  list_mapped_ips_for,
 };
 pub use kernel_wrapper::LibreQoSKernels;
 pub use linux::num_possible_cpus;
 pub use lqos_kernel::max_tracked_ips;
+pub use packet_trace::{trace_packet, KernelTrace};
 pub use tcp_rtt::{rtt_for_each, RttTrackingEntry};
-pub use throughput::{throughput_for_each, HostCounter};
+pub use throughput::{
+  interface_counters, throughput_for_each, HostCounter, InterfaceCounter,
+};
 pub use bpf_iterator::iterate_heimdall;
\ No newline at end of file
@@ -109,7 +109,14 @@ unsafe fn load_kernel(skeleton: *mut bpf::lqos_kern) -> Result<()> {
 pub enum InterfaceDirection {
   Internet,
   IspNetwork,
-  OnAStick(u16, u16),
+  OnAStick {
+    internet: u16,
+    isp: u16,
+    /// Inner (C-VLAN) tag of the Internet side with QinQ, 0 if unused.
+    internet_inner: u16,
+    /// Inner (C-VLAN) tag of the ISP side with QinQ, 0 if unused.
+    isp_inner: u16,
+  },
 }
 
 pub fn attach_xdp_and_tc_to_interface(
@@ -127,11 +134,19 @@ pub fn attach_xdp_and_tc_to_interface(
     (*(*skeleton).data).direction = match direction {
       InterfaceDirection::Internet => 1,
       InterfaceDirection::IspNetwork => 2,
-      InterfaceDirection::OnAStick(..) => 3,
+      InterfaceDirection::OnAStick { .. } => 3,
     };
-    if let InterfaceDirection::OnAStick(internet, isp) = direction {
+    if let InterfaceDirection::OnAStick {
+      internet,
+      isp,
+      internet_inner,
+      isp_inner,
+    } = direction
+    {
       (*(*skeleton).bss).internet_vlan = internet.to_be();
       (*(*skeleton).bss).isp_vlan = isp.to_be();
+      (*(*skeleton).bss).internet_inner_vlan = internet_inner.to_be();
+      (*(*skeleton).bss).isp_inner_vlan = isp_inner.to_be();
     }
     load_kernel(skeleton)?;
     let _ = unload_xdp_from_interface(interface_name); // Ignoring error, it's ok if there isn't one
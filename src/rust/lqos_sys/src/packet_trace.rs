@@ -0,0 +1,173 @@
+use crate::{
+  kernel_wrapper::INTERFACE_SKELETONS,
+  lqos_kernel::{bpf, interface_name_to_index},
+};
+use anyhow::{Error, Result};
+use zerocopy::{AsBytes, FromBytes};
+
+/// Must match `PACKET_TRACE_MAGIC` in `packet_trace.h`
+const PACKET_TRACE_MAGIC: u32 = 0x4C51_5452;
+
+/// Mirrors `struct packet_trace_meta` in `packet_trace.h`. It is
+/// prepended to the frame as XDP metadata, and filled in by the
+/// XDP program.
+#[repr(C)]
+#[derive(Debug, Clone, Default, AsBytes, FromBytes)]
+struct PacketTraceMeta {
+  magic: u32,
+  stage: u32,
+  effective_direction: u32,
+  matched: u32,
+  cpu: u32,
+  tc_handle: u32,
+  cpu_dest: u32,
+}
+
+const META_SIZE: usize = std::mem::size_of::<PacketTraceMeta>();
+
+/// The raw results of running a frame through the XDP and TC programs
+/// with `BPF_PROG_TEST_RUN`.
+#[derive(Debug, Clone)]
+pub struct KernelTrace {
+  /// The XDP program's return code (e.g. 4 = `XDP_REDIRECT`)
+  pub xdp_action: u32,
+
+  /// The frame as the XDP program left it (VLAN rewrites included)
+  pub xdp_frame_out: Vec<u8>,
+
+  /// How far through classification the XDP program got.
+  /// 0 = nothing, 1 = dissected, 2 = classified.
+  pub stage: u32,
+
+  /// 1 = download, 2 = upload, 0 = undecided
+  pub effective_direction: u32,
+
+  /// Did the XDP LPM lookup find an entry?
+  pub matched: bool,
+
+  /// CPU from the LPM entry
+  pub cpu: u32,
+
+  /// TC handle from the LPM entry
+  pub tc_handle: u32,
+
+  /// CPU map destination, if the CPU was available
+  pub cpu_dest: u32,
+
+  /// The TC program's return code (e.g. 0 = `TC_ACT_OK`)
+  pub tc_action: i32,
+
+  /// The `skb->priority` (TC handle) set by the TC program
+  pub tc_priority: u32,
+
+  /// 1 = download, 2 = upload, 0 = undecided (TC side)
+  pub tc_direction: u32,
+}
+
+/// Runs a frame through the XDP program attached to `ingress`, and
+/// then the TC egress program attached to `egress` - without
+/// actually transmitting anything. Returns the decisions each made.
+///
+/// ## Arguments
+///
+/// * `ingress` - the interface on which the frame notionally arrived.
+/// * `egress` - the interface from which the frame would leave.
+/// * `frame` - the Ethernet frame to trace.
+pub fn trace_packet(
+  ingress: &str,
+  egress: &str,
+  frame: &[u8],
+) -> Result<KernelTrace> {
+  let xdp_fd = program_fd(ingress, |s| unsafe { (*s).progs.xdp_prog })?;
+  let tc_fd = program_fd(egress, |s| unsafe { (*s).progs.tc_iphash_to_cpu })?;
+  let ingress_index = interface_name_to_index(ingress)?;
+  let egress_index = interface_name_to_index(egress)?;
+
+  // XDP: the metadata block goes in front of the frame
+  let meta = PacketTraceMeta { magic: PACKET_TRACE_MAGIC, ..Default::default() };
+  let mut data_in = meta.as_bytes().to_vec();
+  data_in.extend_from_slice(frame);
+  let mut data_out = vec![0u8; data_in.len() + 256];
+  let mut xdp_ctx: bpf::xdp_md = unsafe { std::mem::zeroed() };
+  xdp_ctx.data = META_SIZE as u32;
+  xdp_ctx.data_end = data_in.len() as u32;
+  xdp_ctx.ingress_ifindex = ingress_index;
+  let mut xdp_ctx_out: bpf::xdp_md = unsafe { std::mem::zeroed() };
+
+  let mut opts: bpf::bpf_test_run_opts = unsafe { std::mem::zeroed() };
+  opts.sz = std::mem::size_of::<bpf::bpf_test_run_opts>() as _;
+  opts.data_in = data_in.as_ptr() as *const _;
+  opts.data_size_in = data_in.len() as u32;
+  opts.data_out = data_out.as_mut_ptr() as *mut _;
+  opts.data_size_out = data_out.len() as u32;
+  opts.ctx_in = &xdp_ctx as *const bpf::xdp_md as *const _;
+  opts.ctx_size_in = std::mem::size_of::<bpf::xdp_md>() as u32;
+  opts.ctx_out = &mut xdp_ctx_out as *mut bpf::xdp_md as *mut _;
+  opts.ctx_size_out = std::mem::size_of::<bpf::xdp_md>() as u32;
+  opts.repeat = 1;
+  let err = unsafe { bpf::bpf_prog_test_run_opts(xdp_fd, &mut opts) };
+  if err != 0 {
+    return Err(Error::msg(format!("XDP test run failed ({err})")));
+  }
+  let xdp_action = opts.retval;
+  data_out.truncate(opts.data_size_out as usize);
+  let meta_out = xdp_ctx_out.data as usize;
+  let trace = data_out
+    .get(..META_SIZE)
+    .filter(|_| meta_out >= META_SIZE)
+    .and_then(PacketTraceMeta::read_from)
+    .unwrap_or_default();
+  let xdp_frame_out =
+    data_out.get(meta_out..).map(|f| f.to_vec()).unwrap_or_default();
+
+  // TC: the mark flags a trace run, results come back in the
+  // control block.
+  let mut skb_ctx: bpf::__sk_buff = unsafe { std::mem::zeroed() };
+  skb_ctx.mark = PACKET_TRACE_MAGIC;
+  skb_ctx.ifindex = egress_index;
+  let mut skb_ctx_out: bpf::__sk_buff = unsafe { std::mem::zeroed() };
+  let mut tc_out = vec![0u8; xdp_frame_out.len() + 256];
+  let mut opts: bpf::bpf_test_run_opts = unsafe { std::mem::zeroed() };
+  opts.sz = std::mem::size_of::<bpf::bpf_test_run_opts>() as _;
+  opts.data_in = xdp_frame_out.as_ptr() as *const _;
+  opts.data_size_in = xdp_frame_out.len() as u32;
+  opts.data_out = tc_out.as_mut_ptr() as *mut _;
+  opts.data_size_out = tc_out.len() as u32;
+  opts.ctx_in = &skb_ctx as *const bpf::__sk_buff as *const _;
+  opts.ctx_size_in = std::mem::size_of::<bpf::__sk_buff>() as u32;
+  opts.ctx_out = &mut skb_ctx_out as *mut bpf::__sk_buff as *mut _;
+  opts.ctx_size_out = std::mem::size_of::<bpf::__sk_buff>() as u32;
+  opts.repeat = 1;
+  let err = unsafe { bpf::bpf_prog_test_run_opts(tc_fd, &mut opts) };
+  if err != 0 {
+    return Err(Error::msg(format!("TC test run failed ({err})")));
+  }
+
+  Ok(KernelTrace {
+    xdp_action,
+    xdp_frame_out,
+    stage: trace.stage,
+    effective_direction: trace.effective_direction,
+    matched: trace.matched != 0,
+    cpu: trace.cpu,
+    tc_handle: trace.tc_handle,
+    cpu_dest: trace.cpu_dest,
+    tc_action: opts.retval as i32,
+    tc_priority: skb_ctx_out.priority,
+    tc_direction: skb_ctx_out.cb[0],
+  })
+}
+
+fn program_fd(
+  interface: &str,
+  program: impl Fn(*mut bpf::lqos_kern) -> *mut bpf::bpf_program,
+) -> Result<i32> {
+  let skeleton = INTERFACE_SKELETONS.get(interface).ok_or_else(|| {
+    Error::msg(format!("No LibreQoS programs are attached to {interface}"))
+  })?;
+  let fd = unsafe { bpf::bpf_program__fd(program(skeleton.get_ptr())) };
+  if fd < 0 {
+    return Err(Error::msg("Unable to find program file descriptor"));
+  }
+  Ok(fd)
+}
@@ -1,6 +1,12 @@
+use crate::{lqos_kernel::interface_name_to_index, num_possible_cpus};
+use anyhow::{Error, Result};
+use libbpf_sys::{bpf_map_lookup_elem, bpf_obj_get};
 use lqos_utils::XdpIpAddress;
+use std::ffi::{c_void, CString};
 use zerocopy::FromBytes;
 
+const INTERFACE_TRAFFIC_PATH: &str = "/sys/fs/bpf/map_interface_traffic";
+
 /// Representation of the XDP map from map_traffic
 #[repr(C)]
 #[derive(Debug, Clone, Default, FromBytes)]
@@ -33,3 +39,60 @@ pub fn throughput_for_each(
     crate::bpf_iterator::iterate_throughput(callback);
   }
 }
+
+/// Representation of the XDP map from map_interface_traffic: traffic
+/// that arrived on an interface, by direction.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, FromBytes, PartialEq, Eq)]
+pub struct InterfaceCounter {
+  /// Download bytes counter (keeps incrementing)
+  pub download_bytes: u64,
+
+  /// Upload bytes counter (keeps incrementing)
+  pub upload_bytes: u64,
+
+  /// Download packets counter (keeps incrementing)
+  pub download_packets: u64,
+
+  /// Upload packets counter (keeps incrementing)
+  pub upload_packets: u64,
+}
+
+impl std::ops::AddAssign for InterfaceCounter {
+  fn add_assign(&mut self, rhs: Self) {
+    self.download_bytes += rhs.download_bytes;
+    self.upload_bytes += rhs.upload_bytes;
+    self.download_packets += rhs.download_packets;
+    self.upload_packets += rhs.upload_packets;
+  }
+}
+
+/// Returns the traffic counters for everything that has arrived on
+/// `interface`, summed across CPUs. An interface that hasn't seen any
+/// traffic returns zeroes.
+pub fn interface_counters(interface: &str) -> Result<InterfaceCounter> {
+  let mut ifindex = interface_name_to_index(interface)?;
+  let cpus = num_possible_cpus()
+    .map_err(|_| Error::msg("Unable to count possible CPUs"))?;
+  let mut values = vec![InterfaceCounter::default(); cpus as usize];
+
+  let path = CString::new(INTERFACE_TRAFFIC_PATH)?;
+  let fd = unsafe { bpf_obj_get(path.as_ptr()) };
+  if fd < 0 {
+    return Err(Error::msg("Unable to open the interface traffic map"));
+  }
+  let err = unsafe {
+    bpf_map_lookup_elem(
+      fd,
+      &mut ifindex as *mut u32 as *mut c_void,
+      values.as_mut_ptr() as *mut c_void,
+    )
+  };
+  let _ = nix::unistd::close(fd);
+
+  let mut total = InterfaceCounter::default();
+  if err == 0 {
+    values.into_iter().for_each(|v| total += v);
+  }
+  Ok(total)
+}
@@ -16,6 +16,10 @@ pub mod hex_string;
 pub mod packet_scale;
 mod string_table_enum;
 
+/// Build synthetic Ethernet frames, for feeding into the XDP/TC
+/// programs with `BPF_PROG_TEST_RUN`.
+pub mod synthetic_frame;
+
 /// Utilities dealing with Unix Timestamps
 pub mod unix_time;
 mod xdp_ip_address;
@@ -0,0 +1,408 @@
+use std::net::IpAddr;
+use thiserror::Error;
+
+const ETH_P_IP: u16 = 0x0800;
+const ETH_P_IPV6: u16 = 0x86DD;
+const ETH_P_8021Q: u16 = 0x8100;
+const ETH_P_8021AD: u16 = 0x88A8;
+const ETH_P_PPP_SES: u16 = 0x8864;
+const ETH_P_MPLS_UC: u16 = 0x8847;
+const PPP_IP: u16 = 0x0021;
+const PPP_IPV6: u16 = 0x0057;
+
+/// IP protocol number for ICMP
+pub const IPPROTO_ICMP: u8 = 1;
+/// IP protocol number for TCP
+pub const IPPROTO_TCP: u8 = 6;
+/// IP protocol number for UDP
+pub const IPPROTO_UDP: u8 = 17;
+/// IP protocol number for ICMPv6
+pub const IPPROTO_ICMPV6: u8 = 58;
+
+/// A header wrapped around the IP packet in a synthetic frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encapsulation {
+  /// An 802.1Q VLAN tag (customer tag in QinQ)
+  Vlan(u16),
+
+  /// An 802.1ad service tag (the outer tag in QinQ)
+  ServiceVlan(u16),
+
+  /// A PPPoE session header, with the given session ID. Must be
+  /// the innermost encapsulation.
+  Pppoe(u16),
+
+  /// An MPLS label stack entry. Consecutive entries form a stack;
+  /// the last one is marked as the bottom of the stack.
+  Mpls(u32),
+}
+
+/// Describes an Ethernet frame to build for feeding into the XDP/TC
+/// programs with `BPF_PROG_TEST_RUN`. The frame is complete enough
+/// to pass the dissector: Ethernet, optional encapsulation (VLAN
+/// tags, PPPoE, MPLS), IP header and a minimal layer-4 header.
+#[derive(Debug, Clone)]
+pub struct SyntheticFrame {
+  /// Source IP address
+  pub src: IpAddr,
+
+  /// Destination IP address
+  pub dst: IpAddr,
+
+  /// Headers between Ethernet and IP, outermost first
+  pub encapsulation: Vec<Encapsulation>,
+
+  /// IP protocol number (e.g. 6 for TCP)
+  pub protocol: u8,
+
+  /// Source port (TCP and UDP only)
+  pub src_port: u16,
+
+  /// Destination port (TCP and UDP only)
+  pub dst_port: u16,
+}
+
+impl SyntheticFrame {
+  /// Create a new frame description, with default ports.
+  ///
+  /// ## Arguments
+  ///
+  /// * `src` - source IP address
+  /// * `dst` - destination IP address. Must be the same family as `src`.
+  /// * `protocol` - IP protocol number
+  pub fn new(src: IpAddr, dst: IpAddr, protocol: u8) -> Self {
+    Self {
+      src,
+      dst,
+      encapsulation: Vec::new(),
+      protocol,
+      src_port: 12345,
+      dst_port: 80,
+    }
+  }
+
+  /// Adds an 802.1Q VLAN tag to the frame, if one is provided.
+  pub fn with_vlan(mut self, vlan: Option<u16>) -> Self {
+    if let Some(vlan) = vlan {
+      self.encapsulation.push(Encapsulation::Vlan(vlan));
+    }
+    self
+  }
+
+  /// Adds a QinQ pair: an 802.1ad service tag around an 802.1Q
+  /// customer tag.
+  pub fn with_qinq(self, service_vlan: u16, customer_vlan: u16) -> Self {
+    self
+      .with_encapsulation(Encapsulation::ServiceVlan(service_vlan))
+      .with_encapsulation(Encapsulation::Vlan(customer_vlan))
+  }
+
+  /// Adds a header inside any that are already present.
+  pub fn with_encapsulation(mut self, encapsulation: Encapsulation) -> Self {
+    self.encapsulation.push(encapsulation);
+    self
+  }
+
+  /// Builds the frame, returning the raw bytes.
+  pub fn build(&self) -> Result<Vec<u8>, SyntheticFrameError> {
+    let l4 = self.l4_header();
+    let (ethertype, l3) = match (self.src, self.dst) {
+      (IpAddr::V4(src), IpAddr::V4(dst)) => {
+        let total_len = 20 + l4.len();
+        let mut ip = vec![0x45, 0, 0, 0, 0, 0, 0x40, 0, 64, self.protocol, 0, 0];
+        ip[2..4].copy_from_slice(&(total_len as u16).to_be_bytes());
+        ip.extend_from_slice(&src.octets());
+        ip.extend_from_slice(&dst.octets());
+        let checksum = ipv4_checksum(&ip);
+        ip[10..12].copy_from_slice(&checksum.to_be_bytes());
+        (ETH_P_IP, ip)
+      }
+      (IpAddr::V6(src), IpAddr::V6(dst)) => {
+        let mut ip = vec![0x60, 0, 0, 0];
+        ip.extend_from_slice(&(l4.len() as u16).to_be_bytes());
+        ip.push(self.protocol);
+        ip.push(64);
+        ip.extend_from_slice(&src.octets());
+        ip.extend_from_slice(&dst.octets());
+        (ETH_P_IPV6, ip)
+      }
+      _ => return Err(SyntheticFrameError::MixedAddressFamilies),
+    };
+
+    let mut frame = Vec::with_capacity(64 + l3.len() + l4.len());
+    frame.extend_from_slice(&[0x02, 0, 0, 0, 0, 0x02]); // Destination MAC
+    frame.extend_from_slice(&[0x02, 0, 0, 0, 0, 0x01]); // Source MAC
+    let payload_len = l3.len() + l4.len();
+    let needs_ethertype =
+      self.encapsulate(&mut frame, ethertype, payload_len)?;
+    if needs_ethertype {
+      frame.extend_from_slice(&ethertype.to_be_bytes());
+    }
+    frame.extend_from_slice(&l3);
+    frame.extend_from_slice(&l4);
+
+    // Pad to the Ethernet minimum
+    if frame.len() < 60 {
+      frame.resize(60, 0);
+    }
+    Ok(frame)
+  }
+
+  /// Writes the encapsulation headers. Returns true if the IP
+  /// ethertype still needs to be written (i.e. the innermost header
+  /// was a VLAN tag, or there wasn't one).
+  fn encapsulate(
+    &self,
+    frame: &mut Vec<u8>,
+    ethertype: u16,
+    payload_len: usize,
+  ) -> Result<bool, SyntheticFrameError> {
+    let mut needs_ethertype = true;
+    for (i, layer) in self.encapsulation.iter().enumerate() {
+      let next = self.encapsulation.get(i + 1);
+      match *layer {
+        Encapsulation::Vlan(tag) | Encapsulation::ServiceVlan(tag) => {
+          if tag > 4095 {
+            return Err(SyntheticFrameError::InvalidVlan);
+          }
+          if !needs_ethertype {
+            return Err(SyntheticFrameError::InvalidEncapsulation);
+          }
+          let tpid = if matches!(layer, Encapsulation::Vlan(..)) {
+            ETH_P_8021Q
+          } else {
+            ETH_P_8021AD
+          };
+          frame.extend_from_slice(&tpid.to_be_bytes());
+          frame.extend_from_slice(&tag.to_be_bytes());
+        }
+        Encapsulation::Pppoe(session) => {
+          if !needs_ethertype || next.is_some() {
+            return Err(SyntheticFrameError::InvalidEncapsulation);
+          }
+          let ppp_proto = if ethertype == ETH_P_IP { PPP_IP } else { PPP_IPV6 };
+          frame.extend_from_slice(&ETH_P_PPP_SES.to_be_bytes());
+          frame.extend_from_slice(&[0x11, 0]); // Version 1, type 1, session data
+          frame.extend_from_slice(&session.to_be_bytes());
+          frame.extend_from_slice(&(payload_len as u16 + 2).to_be_bytes());
+          frame.extend_from_slice(&ppp_proto.to_be_bytes());
+          needs_ethertype = false;
+        }
+        Encapsulation::Mpls(label) => {
+          if label > 0xFFFFF {
+            return Err(SyntheticFrameError::InvalidMplsLabel);
+          }
+          if needs_ethertype {
+            frame.extend_from_slice(&ETH_P_MPLS_UC.to_be_bytes());
+          }
+          let bottom = !matches!(next, Some(Encapsulation::Mpls(..)));
+          if next.is_some() && bottom {
+            return Err(SyntheticFrameError::InvalidEncapsulation);
+          }
+          let entry = (label << 12) | ((bottom as u32) << 8) | 64;
+          frame.extend_from_slice(&entry.to_be_bytes());
+          needs_ethertype = false;
+        }
+      }
+    }
+    Ok(needs_ethertype)
+  }
+
+  fn l4_header(&self) -> Vec<u8> {
+    let mut ports = Vec::with_capacity(4);
+    ports.extend_from_slice(&self.src_port.to_be_bytes());
+    ports.extend_from_slice(&self.dst_port.to_be_bytes());
+    match self.protocol {
+      IPPROTO_TCP => {
+        let mut tcp = ports;
+        tcp.extend_from_slice(&[0, 0, 0, 1]); // Sequence
+        tcp.extend_from_slice(&[0, 0, 0, 0]); // Ack
+        tcp.extend_from_slice(&[0x50, 0x02]); // Offset 5, SYN
+        tcp.extend_from_slice(&[0xFF, 0xFF, 0, 0, 0, 0]); // Window, csum, urg
+        tcp
+      }
+      IPPROTO_UDP => {
+        let mut udp = ports;
+        udp.extend_from_slice(&8u16.to_be_bytes());
+        udp.extend_from_slice(&[0, 0]);
+        udp
+      }
+      IPPROTO_ICMP => vec![8, 0, 0xF7, 0xFF, 0, 0, 0, 0], // Echo request
+      IPPROTO_ICMPV6 => vec![128, 0, 0, 0, 0, 0, 0, 0], // Echo request
+      _ => Vec::new(),
+    }
+  }
+}
+
+fn ipv4_checksum(header: &[u8]) -> u16 {
+  let mut sum: u32 = header
+    .chunks(2)
+    .map(|c| u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)]) as u32)
+    .sum();
+  while sum > 0xFFFF {
+    sum = (sum & 0xFFFF) + (sum >> 16);
+  }
+  !(sum as u16)
+}
+
+/// Reads the VLAN tags (outermost first) from the front of an
+/// Ethernet frame. Useful for comparing a frame before and after
+/// it passed through the XDP program, to see if any tags were
+/// rewritten. Only the VLAN ID is returned; priority bits are
+/// discarded.
+pub fn read_vlan_tags(frame: &[u8]) -> Vec<u16> {
+  let mut result = Vec::new();
+  let mut offset = 12;
+  while frame.len() >= offset + 4 {
+    let ethertype = u16::from_be_bytes([frame[offset], frame[offset + 1]]);
+    if ethertype != ETH_P_8021Q && ethertype != ETH_P_8021AD {
+      break;
+    }
+    let tci = u16::from_be_bytes([frame[offset + 2], frame[offset + 3]]);
+    result.push(tci & 0x0FFF);
+    offset += 4;
+  }
+  result
+}
+
+/// Errors that can occur building a synthetic frame
+#[derive(Error, Debug)]
+pub enum SyntheticFrameError {
+  /// Source and destination must both be IPv4 or both be IPv6
+  #[error("Source and destination IP addresses must be the same family")]
+  MixedAddressFamilies,
+  /// VLAN tags are 12 bits
+  #[error("VLAN tags must be in the range 0-4095")]
+  InvalidVlan,
+  /// MPLS labels are 20 bits
+  #[error("MPLS labels must be in the range 0-1048575")]
+  InvalidMplsLabel,
+  /// Headers were stacked in an order that can't be represented,
+  /// e.g. a VLAN tag inside PPPoE.
+  #[error("Unsupported order of encapsulation headers")]
+  InvalidEncapsulation,
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn build_ipv4_tcp() {
+    let frame = SyntheticFrame::new(
+      "100.64.1.2".parse().unwrap(),
+      "1.1.1.1".parse().unwrap(),
+      IPPROTO_TCP,
+    )
+    .build()
+    .unwrap();
+    assert_eq!(frame.len(), 60); // 54 bytes, padded
+    assert_eq!(&frame[12..14], &[0x08, 0x00]);
+    assert_eq!(&frame[26..30], &[100, 64, 1, 2]);
+    assert_eq!(ipv4_checksum(&frame[14..34]), 0);
+  }
+
+  #[test]
+  fn build_tagged_ipv6_udp() {
+    let frame = SyntheticFrame::new(
+      "fd77::1".parse().unwrap(),
+      "fd77::2".parse().unwrap(),
+      IPPROTO_UDP,
+    )
+    .with_vlan(Some(42))
+    .build()
+    .unwrap();
+    assert_eq!(frame.len(), 18 + 40 + 8);
+    assert_eq!(&frame[16..18], &[0x86, 0xDD]);
+    assert_eq!(read_vlan_tags(&frame), vec![42]);
+  }
+
+  #[test]
+  fn mixed_families_fail() {
+    let result = SyntheticFrame::new(
+      "100.64.1.2".parse().unwrap(),
+      "fd77::2".parse().unwrap(),
+      IPPROTO_UDP,
+    )
+    .build();
+    assert!(result.is_err());
+  }
+
+  #[test]
+  fn untagged_has_no_vlans() {
+    let frame = SyntheticFrame::new(
+      "100.64.1.2".parse().unwrap(),
+      "1.1.1.1".parse().unwrap(),
+      IPPROTO_ICMP,
+    )
+    .build()
+    .unwrap();
+    assert!(read_vlan_tags(&frame).is_empty());
+    assert_eq!(frame.len(), 60);
+  }
+
+  #[test]
+  fn build_qinq() {
+    let frame = SyntheticFrame::new(
+      "100.64.1.2".parse().unwrap(),
+      "1.1.1.1".parse().unwrap(),
+      IPPROTO_UDP,
+    )
+    .with_qinq(200, 42)
+    .build()
+    .unwrap();
+    assert_eq!(&frame[12..14], &[0x88, 0xA8]);
+    assert_eq!(&frame[16..18], &[0x81, 0x00]);
+    assert_eq!(&frame[20..22], &[0x08, 0x00]);
+    assert_eq!(read_vlan_tags(&frame), vec![200, 42]);
+  }
+
+  #[test]
+  fn build_pppoe_ipv6() {
+    let frame = SyntheticFrame::new(
+      "fd77::1".parse().unwrap(),
+      "fd77::2".parse().unwrap(),
+      IPPROTO_UDP,
+    )
+    .with_encapsulation(Encapsulation::Pppoe(7))
+    .build()
+    .unwrap();
+    assert_eq!(&frame[12..14], &[0x88, 0x64]);
+    assert_eq!(&frame[16..18], &[0, 7]); // Session
+    assert_eq!(&frame[18..20], &(48u16 + 2).to_be_bytes());
+    assert_eq!(&frame[20..22], &[0, 0x57]);
+    assert_eq!(frame[22] >> 4, 6);
+  }
+
+  #[test]
+  fn build_mpls_stack() {
+    let frame = SyntheticFrame::new(
+      "100.64.1.2".parse().unwrap(),
+      "1.1.1.1".parse().unwrap(),
+      IPPROTO_TCP,
+    )
+    .with_encapsulation(Encapsulation::Mpls(16))
+    .with_encapsulation(Encapsulation::Mpls(17))
+    .build()
+    .unwrap();
+    assert_eq!(&frame[12..14], &[0x88, 0x47]);
+    let outer = u32::from_be_bytes(frame[14..18].try_into().unwrap());
+    let inner = u32::from_be_bytes(frame[18..22].try_into().unwrap());
+    assert_eq!((outer >> 12, outer & 0x100), (16, 0));
+    assert_eq!((inner >> 12, inner & 0x100), (17, 0x100));
+    assert_eq!(frame[22], 0x45);
+  }
+
+  #[test]
+  fn vlan_inside_pppoe_fails() {
+    let result = SyntheticFrame::new(
+      "100.64.1.2".parse().unwrap(),
+      "1.1.1.1".parse().unwrap(),
+      IPPROTO_TCP,
+    )
+    .with_encapsulation(Encapsulation::Pppoe(1))
+    .with_vlan(Some(5))
+    .build();
+    assert!(result.is_err());
+  }
+}
@@ -0,0 +1,206 @@
+use crate::{
+  shaped_devices_tracker::SHAPED_DEVICES,
+  throughput_tracker::THROUGHPUT_TRACKER,
+};
+use log::debug;
+use lqos_bus::{BurstStatus, BusResponse};
+use lqos_config::BurstSettings;
+use lqos_queue_tracker::{set_burst_ceiling, BurstCeiling};
+use once_cell::sync::Lazy;
+use std::{collections::HashMap, sync::Mutex};
+
+/// How often (in seconds) to re-read circuits' burst settings
+const RELOAD_SECONDS: u64 = 10;
+
+const BITS_PER_MBIT: f64 = 1_000_000.0;
+
+static BURSTS: Lazy<Mutex<BurstTracker>> =
+  Lazy::new(|| Mutex::new(BurstTracker::default()));
+
+/// A token bucket holding the traffic a circuit may send above its
+/// maximum rate, in bits.
+struct Bucket {
+  size: f64,
+  tokens: f64,
+  refill_per_second: f64,
+  /// Set when the bucket runs dry. The circuit can't burst again until
+  /// the bucket has refilled completely.
+  exhausted: bool,
+  /// Was the circuit above its maximum rate last second?
+  over_max: bool,
+}
+
+impl Bucket {
+  fn new(max_mbps: u32, burst_mbps: u32, settings: &BurstSettings) -> Self {
+    let extra_mbps = burst_mbps.saturating_sub(max_mbps) as f64;
+    let size = extra_mbps * BITS_PER_MBIT * settings.duration_seconds as f64;
+    Self {
+      size,
+      tokens: size,
+      refill_per_second: size / settings.refill_seconds as f64,
+      exhausted: size <= 0.0,
+      over_max: false,
+    }
+  }
+
+  /// Spends a second's traffic above the maximum rate, or refills the
+  /// bucket if there wasn't any.
+  fn tick(&mut self, used_bits: f64, max_bits: f64) {
+    let excess = used_bits - max_bits;
+    self.over_max = excess > 0.0;
+    if self.over_max && !self.exhausted {
+      self.tokens -= excess;
+      if self.tokens <= 0.0 {
+        self.tokens = 0.0;
+        self.exhausted = true;
+      }
+    } else {
+      self.tokens = (self.tokens + self.refill_per_second).min(self.size);
+      if self.tokens >= self.size && self.size > 0.0 {
+        self.exhausted = false;
+      }
+    }
+  }
+
+  fn percent(&self) -> f32 {
+    if self.size > 0.0 {
+      (self.tokens / self.size * 100.0) as f32
+    } else {
+      0.0
+    }
+  }
+}
+
+struct BurstCircuit {
+  settings: BurstSettings,
+  /// The circuit's maximum rates (download, upload), in Mbps
+  max_mbps: (u32, u32),
+  download: Bucket,
+  upload: Bucket,
+  ceiling: Option<BurstCeiling>,
+}
+
+impl BurstCircuit {
+  fn new(settings: BurstSettings, max_mbps: (u32, u32)) -> Self {
+    Self {
+      settings,
+      max_mbps,
+      download: Bucket::new(max_mbps.0, settings.download_mbps, &settings),
+      upload: Bucket::new(max_mbps.1, settings.upload_mbps, &settings),
+      ceiling: None,
+    }
+  }
+
+  /// The ceiling to apply while either bucket has allowance left
+  fn wanted_ceiling(&self) -> Option<BurstCeiling> {
+    if self.download.exhausted && self.upload.exhausted {
+      return None;
+    }
+    let rate = |bucket: &Bucket, mbps: u32| {
+      if bucket.exhausted {
+        0
+      } else {
+        mbps as u64
+      }
+    };
+    Some(BurstCeiling {
+      download_mbps: rate(&self.download, self.settings.download_mbps),
+      upload_mbps: rate(&self.upload, self.settings.upload_mbps),
+    })
+  }
+
+  fn status(&self, circuit_id: &str) -> BurstStatus {
+    BurstStatus {
+      circuit_id: circuit_id.to_string(),
+      burst_mbps: (self.settings.download_mbps, self.settings.upload_mbps),
+      bucket_percent: (self.download.percent(), self.upload.percent()),
+      bursting: (
+        self.download.over_max && !self.download.exhausted,
+        self.upload.over_max && !self.upload.exhausted,
+      ),
+    }
+  }
+}
+
+#[derive(Default)]
+struct BurstTracker {
+  circuits: HashMap<String, BurstCircuit>,
+  ticks: u64,
+}
+
+impl BurstTracker {
+  /// Picks up changes to `ShapedDevices.csv`. Buckets are kept for
+  /// circuits whose settings haven't changed.
+  fn reload(&mut self) {
+    let mut wanted = HashMap::new();
+    {
+      let devices = SHAPED_DEVICES.read().unwrap();
+      for device in devices.devices.iter() {
+        if let Some(burst) = device.burst {
+          let max_mbps = (device.download_max_mbps, device.upload_max_mbps);
+          wanted
+            .entry(device.circuit_id.clone())
+            .or_insert((burst, max_mbps));
+        }
+      }
+    }
+    self.circuits.retain(|circuit_id, circuit| {
+      let keep = wanted.get(circuit_id).is_some_and(|(burst, max_mbps)| {
+        circuit.settings == *burst && circuit.max_mbps == *max_mbps
+      });
+      if !keep && circuit.ceiling.is_some() {
+        set_burst_ceiling(circuit_id, None);
+      }
+      keep
+    });
+    for (circuit_id, (burst, max_mbps)) in wanted {
+      self
+        .circuits
+        .entry(circuit_id)
+        .or_insert_with(|| BurstCircuit::new(burst, max_mbps));
+    }
+  }
+}
+
+/// Spends or refills each bursting circuit's allowance from the last
+/// second's traffic. Called once per second by the throughput monitor.
+pub(crate) fn track_bursts() {
+  let mut bursts = BURSTS.lock().unwrap();
+  if bursts.ticks % RELOAD_SECONDS == 0 {
+    bursts.reload();
+  }
+  bursts.ticks += 1;
+  if bursts.circuits.is_empty() {
+    return;
+  }
+
+  let bytes = THROUGHPUT_TRACKER
+    .circuit_bytes_per_second(|id| bursts.circuits.contains_key(id));
+  for (circuit_id, circuit) in bursts.circuits.iter_mut() {
+    let (down, up) = bytes.get(circuit_id).copied().unwrap_or((0, 0));
+    let max_bits = |mbps: u32| mbps as f64 * BITS_PER_MBIT;
+    circuit.download.tick(down as f64 * 8.0, max_bits(circuit.max_mbps.0));
+    circuit.upload.tick(up as f64 * 8.0, max_bits(circuit.max_mbps.1));
+
+    let ceiling = circuit.wanted_ceiling();
+    if ceiling != circuit.ceiling {
+      if ceiling.is_none() {
+        debug!("Circuit {circuit_id} has used its burst allowance");
+      }
+      set_burst_ceiling(circuit_id, ceiling);
+      circuit.ceiling = ceiling;
+    }
+  }
+}
+
+pub fn burst_status(circuit_id: Option<&str>) -> BusResponse {
+  let bursts = BURSTS.lock().unwrap();
+  let mut result: Vec<BurstStatus> = bursts
+    .circuits
+    .iter()
+    .filter(|(id, _)| circuit_id.is_none() || circuit_id == Some(id.as_str()))
+    .map(|(id, circuit)| circuit.status(id))
+    .collect();
+  result.sort_by(|a, b| a.circuit_id.cmp(&b.circuit_id));
+  BusResponse::BurstStatus(result)
+}
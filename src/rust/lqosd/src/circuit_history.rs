@@ -0,0 +1,247 @@
+use crate::{
+  shaped_devices_tracker::SHAPED_DEVICES,
+  throughput_tracker::THROUGHPUT_TRACKER,
+};
+use log::{error, info, warn};
+use lqos_bus::BusResponse;
+use lqos_config::{CircuitHistory, CircuitSample, EtcLqos, HistoryRange};
+use lqos_queue_tracker::{
+  get_effective_circuit_rates, read_circuit_queue_counters,
+  CircuitQueueCounters,
+};
+use lqos_utils::unix_time::unix_now;
+use once_cell::sync::Lazy;
+use std::{
+  collections::HashMap,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex,
+  },
+};
+
+/// How often (in seconds) to record a sample for each circuit
+const SAMPLE_SECONDS: u64 = 60;
+
+/// How often (in samples) to delete the history of departed circuits
+const EXPIRE_SAMPLES: u64 = 24 * 60;
+
+static HISTORY: Lazy<Mutex<HistoryTracker>> =
+  Lazy::new(|| Mutex::new(HistoryTracker::default()));
+
+/// Set while a minute's samples are being written
+static WRITING: AtomicBool = AtomicBool::new(false);
+
+/// The queue counters at the last sample, to count each minute's drops
+/// and marks. Only used by the writer.
+static LAST_COUNTERS: Lazy<Mutex<HashMap<String, CircuitQueueCounters>>> =
+  Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// A circuit's throughput so far this minute, in bits per second
+#[derive(Default)]
+struct Throughput {
+  total: (u64, u64),
+  max: (u64, u64),
+}
+
+#[derive(Default)]
+struct HistoryTracker {
+  circuits: HashMap<String, Throughput>,
+  seconds: u64,
+  /// The throughput tracker's cycle when this minute started
+  first_cycle: u64,
+  samples: u64,
+}
+
+/// Adds the last second's throughput to each circuit's minute, and
+/// records the minute once it's complete. Called once per second by
+/// the throughput monitor.
+pub(crate) fn track_circuit_history() {
+  let mut history = HISTORY.lock().unwrap();
+  if history.seconds == 0 {
+    history.first_cycle = THROUGHPUT_TRACKER.cycle.load(Ordering::Relaxed);
+  }
+  let per_circuit = THROUGHPUT_TRACKER.circuit_bytes_per_second(|_| true);
+  for (circuit_id, bytes) in per_circuit {
+    let bits = (bytes.0 * 8, bytes.1 * 8);
+    let throughput = history.circuits.entry(circuit_id).or_default();
+    throughput.total.0 += bits.0;
+    throughput.total.1 += bits.1;
+    throughput.max.0 = throughput.max.0.max(bits.0);
+    throughput.max.1 = throughput.max.1.max(bits.1);
+  }
+  history.seconds += 1;
+  if history.seconds < SAMPLE_SECONDS {
+    return;
+  }
+
+  let circuits = std::mem::take(&mut history.circuits);
+  let seconds = std::mem::take(&mut history.seconds);
+  history.samples += 1;
+  let expire = history.samples % EXPIRE_SAMPLES == 0;
+  if !recording_enabled() {
+    return;
+  }
+  let latencies = THROUGHPUT_TRACKER.circuit_latencies(history.first_cycle);
+  drop(history);
+
+  // Reading every circuit's queue and writing the files takes a while,
+  // so it's done away from the throughput monitor.
+  if WRITING.swap(true, Ordering::AcqRel) {
+    warn!("Still recording the last minute of circuit history; skipping");
+    return;
+  }
+  std::thread::spawn(move || {
+    record_minute(circuits, seconds, latencies, expire);
+    WRITING.store(false, Ordering::Release);
+  });
+}
+
+fn recording_enabled() -> bool {
+  EtcLqos::load()
+    .map(|cfg| cfg.circuit_history.map(|h| h.enabled).unwrap_or(true))
+    .unwrap_or(false)
+}
+
+fn record_minute(
+  circuits: HashMap<String, Throughput>,
+  seconds: u64,
+  mut latencies: HashMap<String, Vec<f32>>,
+  expire: bool,
+) {
+  let history = match CircuitHistory::open() {
+    Ok(history) => history,
+    Err(e) => {
+      error!("Unable to open the circuit history: {e}");
+      return;
+    }
+  };
+  let timestamp = unix_now().unwrap_or(0).saturating_sub(seconds);
+  let plans = plan_rates(&circuits);
+  let queues = queue_changes();
+
+  let mut failed = 0;
+  for (circuit_id, throughput) in circuits {
+    let mut rtts = latencies.remove(&circuit_id).unwrap_or_default();
+    rtts.sort_by(|a, b| a.total_cmp(b));
+    let queue = queues.get(&circuit_id).copied().unwrap_or_default();
+    let sample = CircuitSample {
+      timestamp,
+      plan_mbps: plans.get(&circuit_id).copied().unwrap_or_default(),
+      bits_avg: (throughput.total.0 / seconds, throughput.total.1 / seconds),
+      bits_max: throughput.max,
+      rtt_p50: percentile(&rtts, 50),
+      rtt_p95: percentile(&rtts, 95),
+      drops: queue.drops,
+      marks: queue.marks,
+      backlog: queue.backlog,
+    };
+    if let Err(e) = history.record(&circuit_id, &sample) {
+      if failed == 0 {
+        error!("Unable to record circuit {circuit_id}'s history: {e}");
+      }
+      failed += 1;
+    }
+  }
+  if failed > 1 {
+    error!("Unable to record the history of {failed} circuits");
+  }
+
+  if expire {
+    match history.remove_expired() {
+      Ok(0) => {}
+      Ok(n) => info!("Removed the history of {n} departed circuits"),
+      Err(e) => warn!("Unable to remove old circuit history: {e}"),
+    }
+  }
+}
+
+/// Each circuit's current ceiling, falling back to `ShapedDevices.csv`
+/// for circuits the rate scheduler doesn't know yet
+fn plan_rates(
+  circuits: &HashMap<String, Throughput>,
+) -> HashMap<String, (u64, u64)> {
+  let mut plans: HashMap<String, (u64, u64)> =
+    get_effective_circuit_rates(None)
+      .into_iter()
+      .map(|c| {
+        let rates = c.effective;
+        (c.circuit_id, (rates.download_max_mbps, rates.upload_max_mbps))
+      })
+      .collect();
+  let devices = SHAPED_DEVICES.read().unwrap();
+  for device in devices.devices.iter() {
+    if circuits.contains_key(&device.circuit_id) {
+      plans.entry(device.circuit_id.clone()).or_insert((
+        device.download_max_mbps as u64,
+        device.upload_max_mbps as u64,
+      ));
+    }
+  }
+  plans
+}
+
+/// A circuit's queue over the last minute, as `(download, upload)`
+#[derive(Clone, Copy, Default)]
+struct QueueChange {
+  drops: (u32, u32),
+  marks: (u32, u32),
+  backlog: (u32, u32),
+}
+
+/// Each circuit's drops and marks since the last sample, and its
+/// current backlog
+fn queue_changes() -> HashMap<String, QueueChange> {
+  let mut last = LAST_COUNTERS.lock().unwrap();
+  let current: HashMap<String, CircuitQueueCounters> =
+    read_circuit_queue_counters()
+      .into_iter()
+      .map(|c| (c.circuit_id.clone(), c))
+      .collect();
+  let changes = current
+    .iter()
+    .map(|(circuit_id, now)| {
+      let before = last.get(circuit_id);
+      let change = QueueChange {
+        drops: (
+          since(now.drops.0, before.map(|b| b.drops.0)),
+          since(now.drops.1, before.map(|b| b.drops.1)),
+        ),
+        marks: (
+          since(now.marks.0, before.map(|b| b.marks.0)),
+          since(now.marks.1, before.map(|b| b.marks.1)),
+        ),
+        backlog: now.backlog,
+      };
+      (circuit_id.clone(), change)
+    })
+    .collect();
+  *last = current;
+  changes
+}
+
+/// How much a counter has grown. Counters start again when a queue is
+/// rebuilt; the first reading of a queue has nothing to compare with.
+fn since(now: u64, before: Option<u64>) -> u32 {
+  let change = match before {
+    Some(before) if now >= before => now - before,
+    Some(_) => now,
+    None => 0,
+  };
+  change.min(u32::MAX as u64) as u32
+}
+
+fn percentile(sorted: &[f32], percent: usize) -> Option<f32> {
+  if sorted.is_empty() {
+    None
+  } else {
+    Some(sorted[(sorted.len() - 1) * percent / 100])
+  }
+}
+
+pub fn circuit_history(circuit_id: &str, range: HistoryRange) -> BusResponse {
+  let now = unix_now().unwrap_or(0);
+  match CircuitHistory::open().and_then(|h| h.query(circuit_id, range, now)) {
+    Ok(samples) => BusResponse::CircuitHistory(samples),
+    Err(e) => BusResponse::Fail(e.to_string()),
+  }
+}
@@ -0,0 +1,43 @@
+use crate::program_control;
+use log::{info, warn};
+use lqos_bus::BusResponse;
+use lqos_config::ConfigRevisions;
+
+pub fn list_revisions() -> BusResponse {
+  match ConfigRevisions::open().and_then(|r| r.list()) {
+    Ok(revisions) => BusResponse::ConfigRevisions(revisions),
+    Err(e) => BusResponse::Fail(e.to_string()),
+  }
+}
+
+pub fn record_revision(author: &str, comment: &str) -> BusResponse {
+  match lqos_config::record_config_revision(author, comment) {
+    Ok(revision) => BusResponse::ConfigRevision(revision),
+    Err(e) => BusResponse::Fail(e.to_string()),
+  }
+}
+
+pub fn diff_revisions(from: &str, to: Option<&str>) -> BusResponse {
+  match ConfigRevisions::open().and_then(|r| r.diff(from, to)) {
+    Ok(diff) => BusResponse::ConfigRevisionDiff(diff),
+    Err(e) => BusResponse::Fail(e.to_string()),
+  }
+}
+
+pub fn rollback(id: &str, author: &str) -> BusResponse {
+  let result = ConfigRevisions::open().and_then(|r| r.rollback(id, author));
+  let revision = match result {
+    Ok(revision) => revision,
+    Err(e) => return BusResponse::Fail(e.to_string()),
+  };
+  info!("{author} rolled the configuration back to revision {id}");
+  match program_control::reload_libre_qos() {
+    BusResponse::Fail(e) => {
+      warn!("Reload after rolling back to revision {id} failed: {e}");
+      BusResponse::Fail(format!(
+        "Rolled back to revision {id}, but reloading LibreQoS failed: {e}"
+      ))
+    }
+    _ => BusResponse::ConfigRevision(revision),
+  }
+}
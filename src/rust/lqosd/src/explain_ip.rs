@@ -0,0 +1,242 @@
+use crate::{
+  shaped_devices_tracker::{NETWORK_JSON, SHAPED_DEVICES},
+  throughput_tracker::{ip_counters, is_unknown_ip},
+};
+use lqos_bus::{
+  BusResponse, ExplainedDevice, IpExplanation, IpMapping, TcHandle,
+};
+use lqos_config::LibreQoSConfig;
+use lqos_utils::XdpIpAddress;
+use std::net::{IpAddr, Ipv6Addr};
+
+/// Gathers everything `lqosd` knows about an IP address, and notes
+/// any places where the sources disagree with one another.
+pub(crate) fn explain_ip(ip: &str) -> BusResponse {
+  let ip: IpAddr = match ip.trim().parse() {
+    Ok(ip) => ip,
+    Err(_) => return BusResponse::Fail(format!("{ip} is not an IP address")),
+  };
+  let xdp_ip = XdpIpAddress::from_ip(ip);
+  let lookup = xdp_ip.as_ipv6();
+  let mut notes = Vec::new();
+
+  // ShapedDevices.csv
+  let (shaped_device, csv_prefix) = {
+    let devices = SHAPED_DEVICES.read().unwrap();
+    if let Some((net, id)) = devices.trie.longest_match(lookup) {
+      let d = &devices.devices[*id];
+      let prefix = (net.network_address(), net.netmask() as u32);
+      let device = ExplainedDevice {
+        circuit_id: d.circuit_id.clone(),
+        circuit_name: d.circuit_name.clone(),
+        device_id: d.device_id.clone(),
+        device_name: d.device_name.clone(),
+        parent_node: d.parent_node.clone(),
+        matched_prefix: prefix_to_string(prefix.0, prefix.1),
+        download_max_mbps: d.download_max_mbps,
+        upload_max_mbps: d.upload_max_mbps,
+      };
+      (Some(device), Some(prefix))
+    } else {
+      (None, None)
+    }
+  };
+
+  // XDP mapping tables. On a stick, upload is mapped separately to
+  // the upload classes and CPUs.
+  let (xdp_mapping, xdp_prefix) = match xdp_lookup(&xdp_ip, false) {
+    Ok(found) => found,
+    Err(e) => {
+      notes.push(format!("Unable to read the XDP IP mapping table: {e}"));
+      (None, None)
+    }
+  };
+  let xdp_upload_mapping = match xdp_lookup(&xdp_ip, true) {
+    Ok((mapping, _)) => mapping,
+    Err(e) => {
+      notes.push(format!("Unable to read the XDP upload mapping table: {e}"));
+      None
+    }
+  };
+
+  // queuingStructure.json
+  let queue = shaped_device
+    .as_ref()
+    .and_then(|d| lqos_queue_tracker::get_circuit_queue(&d.circuit_id));
+
+  // network.json
+  let mut network_parents = Vec::new();
+  if let Some(device) = &shaped_device {
+    if !device.parent_node.is_empty() {
+      let net_json = NETWORK_JSON.read().unwrap();
+      if let Some(parents) =
+        net_json.get_parents_for_circuit_id(&device.parent_node)
+      {
+        network_parents = parents
+          .iter()
+          .filter_map(|idx| net_json.nodes.get(*idx))
+          .map(|n| n.name.clone())
+          .collect();
+      } else {
+        notes.push(format!(
+          "Parent node {} is not in network.json.",
+          device.parent_node
+        ));
+      }
+    }
+  }
+
+  // Throughput tracker
+  let counters = ip_counters(&xdp_ip);
+  let unknown = is_unknown_ip(&xdp_ip);
+
+  // ispConfig.py subnets
+  let (in_allowed_subnets, in_ignored_subnets) = match LibreQoSConfig::load() {
+    Ok(config) => (
+      config.allowed_subnets_trie().longest_match(lookup).is_some(),
+      config.ignored_subnets_trie().longest_match(lookup).is_some(),
+    ),
+    Err(e) => {
+      notes.push(format!("Unable to load ispConfig.py: {e}"));
+      (false, false)
+    }
+  };
+
+  // Cross-check the sources
+  match (&shaped_device, &xdp_mapping) {
+    (Some(device), None) => notes.push(format!(
+      "ShapedDevices.csv assigns this IP to circuit {}, but it has no XDP mapping. Has LibreQoS been run since the CSV changed?",
+      device.circuit_id
+    )),
+    (None, Some(mapping)) => notes.push(format!(
+      "XDP maps this IP to {}, but no ShapedDevices.csv entry matches it. The mapping may be stale.",
+      mapping.tc_handle.to_string()
+    )),
+    (Some(_), Some(_)) => {
+      if let (Some((csv_net, csv_len)), Some((xdp_net, xdp_len))) =
+        (csv_prefix, xdp_prefix)
+      {
+        let xdp_net = XdpIpAddress(xdp_net).as_ipv6();
+        if xdp_net != ipv6_of(csv_net) || xdp_len != csv_len {
+          notes.push(format!(
+            "The XDP mapping matched {}, but the CSV matched {}.",
+            prefix_to_string(IpAddr::V6(xdp_net), xdp_len),
+            prefix_to_string(csv_net, csv_len),
+          ));
+        }
+      }
+    }
+    (None, None) => notes.push("This IP is not shaped.".to_string()),
+  }
+  if let Some(device) = &shaped_device {
+    if let Some(queue) = &queue {
+      let checks = [
+        ("", &xdp_mapping, queue.class_id, queue.cpu),
+        (" upload", &xdp_upload_mapping, queue.up_class_id, queue.up_cpu),
+      ];
+      for (direction, mapping, class_id, cpu) in checks {
+        let Some(mapping) = mapping else { continue };
+        if mapping.tc_handle != class_id {
+          notes.push(format!(
+            "XDP sends this IP's{direction} traffic to class {}, but queuingStructure.json lists class {}.",
+            mapping.tc_handle.to_string(),
+            class_id.to_string()
+          ));
+        }
+        if mapping.cpu != cpu {
+          notes.push(format!(
+            "XDP sends this IP's{direction} traffic to CPU {}, but queuingStructure.json lists CPU {}.",
+            mapping.cpu, cpu
+          ));
+        }
+      }
+    } else {
+      notes.push(format!(
+        "Circuit {} is not in queuingStructure.json.",
+        device.circuit_id
+      ));
+    }
+    if !in_allowed_subnets {
+      notes.push("The IP is outside allowedSubnets, so LibreQoS will not shape it.".to_string());
+    }
+    if in_ignored_subnets {
+      notes.push("The IP is inside ignoredSubnets, so LibreQoS will not shape it.".to_string());
+    }
+  }
+  if let (Some(counters), Some(mapping)) = (&counters, &xdp_mapping) {
+    if counters.tc_handle.as_u32() != 0 && counters.tc_handle != mapping.tc_handle {
+      notes.push(format!(
+        "Recent traffic was counted against {}, but XDP now maps the IP to {}.",
+        counters.tc_handle.to_string(),
+        mapping.tc_handle.to_string()
+      ));
+    }
+  }
+  if unknown {
+    notes.push("Traffic has been seen in the last five minutes without a TC handle; this IP is listed as unknown.".to_string());
+  }
+
+  BusResponse::IpExplanation(Box::new(IpExplanation {
+    ip: ip.to_string(),
+    shaped_device,
+    xdp_mapping,
+    xdp_upload_mapping,
+    queue,
+    network_parents,
+    counters,
+    in_allowed_subnets,
+    in_ignored_subnets,
+    unknown,
+    notes,
+  }))
+}
+
+/// The most specific entry covering an address in the download or
+/// upload mapping table, and its prefix
+fn xdp_lookup(
+  ip: &XdpIpAddress,
+  upload: bool,
+) -> anyhow::Result<(Option<IpMapping>, Option<([u8; 16], u32)>)> {
+  let raw = lqos_sys::list_mapped_ips_for(upload)?;
+  let found = raw
+    .iter()
+    .filter(|(key, _)| prefix_contains(&key.address, key.prefixlen, &ip.0))
+    .max_by_key(|(key, _)| key.prefixlen)
+    .map(|(key, data)| {
+      let mapping = IpMapping {
+        ip_address: XdpIpAddress(key.address).as_ip().to_string(),
+        prefix_length: key.prefixlen,
+        tc_handle: TcHandle::from_u32(data.tc_handle),
+        cpu: data.cpu,
+      };
+      (Some(mapping), Some((key.address, key.prefixlen)))
+    })
+    .unwrap_or((None, None));
+  Ok(found)
+}
+
+/// Does the (XDP encoded) network `net/prefix` contain `ip`?
+fn prefix_contains(net: &[u8; 16], prefix: u32, ip: &[u8; 16]) -> bool {
+  if prefix == 0 {
+    return true;
+  }
+  let mask = u128::MAX << (128 - prefix.min(128));
+  u128::from_be_bytes(*net) & mask == u128::from_be_bytes(*ip) & mask
+}
+
+fn ipv6_of(ip: IpAddr) -> Ipv6Addr {
+  match ip {
+    IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+    IpAddr::V6(ip) => ip,
+  }
+}
+
+/// Formats an IPv6 (possibly IPv4-mapped) prefix, using IPv4 notation
+/// where possible.
+fn prefix_to_string(ip: IpAddr, prefix: u32) -> String {
+  let ip = ipv6_of(ip);
+  match ip.to_ipv4_mapped() {
+    Some(v4) if prefix >= 96 => format!("{v4}/{}", prefix - 96),
+    _ => format!("{ip}/{prefix}"),
+  }
+}
@@ -1,8 +1,16 @@
+mod burst;
+mod circuit_history;
+mod config_revisions;
+mod explain_ip;
 mod file_lock;
 mod ip_mapping;
 #[cfg(feature = "equinix_tests")]
 mod lqos_daht_test;
+mod packet_trace;
 mod program_control;
+mod queue_planner;
+mod quotas;
+mod self_test;
 mod shaped_devices_tracker;
 mod throughput_tracker;
 mod anonymous_usage;
@@ -20,8 +28,9 @@ use lqos_bus::{BusRequest, BusResponse, UnixSocketServer, StatsRequest};
 use lqos_config::LibreQoSConfig;
 use lqos_heimdall::{n_second_packet_dump, perf_interface::heimdall_handle_events, start_heimdall};
 use lqos_queue_tracker::{
-  add_watched_queue, get_raw_circuit_data, spawn_queue_monitor,
-  spawn_queue_structure_monitor,
+  add_watched_queue, get_effective_circuit_rates, get_raw_circuit_data,
+  spawn_queue_monitor, spawn_queue_structure_monitor,
+  spawn_rate_plan_scheduler,
 };
 use lqos_sys::LibreQoSKernels;
 use lts_client::collector::start_long_term_stats;
@@ -61,16 +70,11 @@ async fn main() -> Result<()> {
   tuning::tune_lqosd_from_config_file(&config)?;
 
   // Start the XDP/TC kernels
-  let kernels = if config.on_a_stick_mode {
-    LibreQoSKernels::on_a_stick_mode(
-      &config.internet_interface,
-      config.stick_vlans.1,
-      config.stick_vlans.0,
-      Some(heimdall_handle_events),
-    )?
-  } else {
-    LibreQoSKernels::new(&config.internet_interface, &config.isp_interface, Some(heimdall_handle_events))?
-  };
+  let pairs = config.interface_pairs()?;
+  let kernels =
+    LibreQoSKernels::with_pairs(&pairs, Some(heimdall_handle_events))?;
+  throughput_tracker::set_interface_pairs(&pairs);
+  self_test::startup_self_test(&config)?;
 
   // Spawn tracking sub-systems
   let long_term_stats_tx = start_long_term_stats().await;
@@ -83,6 +87,7 @@ async fn main() -> Result<()> {
     throughput_tracker::spawn_throughput_monitor(long_term_stats_tx.clone()),
   );
   spawn_queue_monitor();
+  spawn_rate_plan_scheduler();
 
   // Handle signals
   let mut signals = Signals::new([SIGINT, SIGHUP, SIGTERM])?;
@@ -142,14 +147,14 @@ fn handle_bus_requests(
         throughput_tracker::current_throughput()
       }
       BusRequest::GetHostCounter => throughput_tracker::host_counters(),
-      BusRequest::GetTopNDownloaders { start, end } => {
-        throughput_tracker::top_n(*start, *end)
+      BusRequest::GetTopNDownloaders { start, end, tag } => {
+        throughput_tracker::top_n(*start, *end, tag.as_ref())
       }
-      BusRequest::GetWorstRtt { start, end } => {
-        throughput_tracker::worst_n(*start, *end)
+      BusRequest::GetWorstRtt { start, end, tag } => {
+        throughput_tracker::worst_n(*start, *end, tag.as_ref())
       }
-      BusRequest::GetBestRtt { start, end } => {
-        throughput_tracker::best_n(*start, *end)
+      BusRequest::GetBestRtt { start, end, tag } => {
+        throughput_tracker::best_n(*start, *end, tag.as_ref())
       }
       BusRequest::MapIpToFlow { ip_address, tc_handle, cpu, upload } => {
         map_ip_to_flow(ip_address, tc_handle, *cpu, *upload)
@@ -180,6 +185,9 @@ fn handle_bus_requests(
       BusRequest::GetNetworkMap { parent } => {
         shaped_devices_tracker::get_one_network_map_layer(*parent)
       }
+      BusRequest::GetNetworkMapById { id } => {
+        shaped_devices_tracker::get_one_network_map_layer_by_id(id)
+      }
       BusRequest::TopMapQueues(n_queues) => {
         shaped_devices_tracker::get_top_n_root_queues(*n_queues)
       }
@@ -228,6 +236,51 @@ fn handle_bus_requests(
       BusRequest::GetLongTermStats(StatsRequest::Tree) => {
         long_term_stats::get_stats_tree()
       }
+      BusRequest::TracePacket { interface, frame } => {
+        packet_trace::trace_packet(interface, frame)
+      }
+      BusRequest::ExplainIp(ip) => explain_ip::explain_ip(ip),
+      BusRequest::GetSelfTestResults => self_test::self_test_results(),
+      BusRequest::GetPairThroughput => throughput_tracker::pair_throughput(),
+      BusRequest::ValidateConfiguration => {
+        validation::validate_configuration()
+      }
+      BusRequest::ListConfigRevisions => config_revisions::list_revisions(),
+      BusRequest::RecordConfigRevision { author, comment } => {
+        config_revisions::record_revision(author, comment)
+      }
+      BusRequest::DiffConfigRevisions { from, to } => {
+        config_revisions::diff_revisions(from, to.as_deref())
+      }
+      BusRequest::RollbackConfigRevision { id, author } => {
+        config_revisions::rollback(id, author)
+      }
+      BusRequest::GetEffectiveCircuitRates { circuit_id } => {
+        BusResponse::CircuitRates(get_effective_circuit_rates(
+          circuit_id.as_deref(),
+        ))
+      }
+      BusRequest::GetQuotaUsage { circuit_id } => {
+        quotas::quota_usage(circuit_id.as_deref())
+      }
+      BusRequest::GrantQuotaTopUp { circuit_id, gigabytes } => {
+        quotas::grant_top_up(circuit_id, *gigabytes)
+      }
+      BusRequest::ResetQuotaUsage { circuit_id } => {
+        quotas::reset_usage(circuit_id)
+      }
+      BusRequest::GetBurstStatus { circuit_id } => {
+        burst::burst_status(circuit_id.as_deref())
+      }
+      BusRequest::GetCircuitHistory { circuit_id, range } => {
+        circuit_history::circuit_history(circuit_id, *range)
+      }
+      BusRequest::UpsertCircuit { devices, author } => {
+        queue_planner::upsert_circuit(devices, author)
+      }
+      BusRequest::RemoveCircuit { circuit_id, author } => {
+        queue_planner::remove_circuit(circuit_id, author)
+      }
     });
   }
 }
@@ -0,0 +1,133 @@
+use anyhow::{Error, Result};
+use log::warn;
+use lqos_bus::{BusResponse, PacketTrace, TcHandle, TraceDirection, TraceFrame};
+use lqos_config::LibreQoSConfig;
+use lqos_sys::KernelTrace;
+use lqos_utils::synthetic_frame::{read_vlan_tags, SyntheticFrame};
+use std::net::IpAddr;
+
+const XDP_REDIRECT: u32 = 4;
+
+pub(crate) fn trace_packet(interface: &str, frame: &TraceFrame) -> BusResponse {
+  match run_trace(interface, frame) {
+    Ok(trace) => BusResponse::PacketTrace(trace),
+    Err(e) => {
+      warn!("Packet trace failed: {e:?}");
+      BusResponse::Fail(e.to_string())
+    }
+  }
+}
+
+pub(crate) fn run_trace(interface: &str, frame: &TraceFrame) -> Result<PacketTrace> {
+  let frame = match frame {
+    TraceFrame::Raw(bytes) => bytes.clone(),
+    TraceFrame::Synthetic { src, dst, vlan, protocol } => {
+      let src: IpAddr = src.parse()?;
+      let dst: IpAddr = dst.parse()?;
+      SyntheticFrame::new(src, dst, *protocol).with_vlan(*vlan).build()?
+    }
+  };
+  let egress = egress_interface(interface)?;
+  let kernel = lqos_sys::trace_packet(interface, &egress, &frame)?;
+  Ok(explain(interface, &frame, &kernel))
+}
+
+/// Traffic arriving on one side of a bridge pair leaves from the
+/// other. On a stick, it leaves the way it came in.
+fn egress_interface(interface: &str) -> Result<String> {
+  let pairs = LibreQoSConfig::load()?.interface_pairs()?;
+  for pair in pairs.iter() {
+    if pair.on_a_stick && interface == pair.internet_interface {
+      return Ok(interface.to_string());
+    } else if interface == pair.internet_interface {
+      return Ok(pair.isp_interface.clone());
+    } else if interface == pair.isp_interface {
+      return Ok(pair.internet_interface.clone());
+    }
+  }
+  Err(Error::msg(format!("{interface} is not a LibreQoS interface")))
+}
+
+fn explain(interface: &str, frame: &[u8], kernel: &KernelTrace) -> PacketTrace {
+  let vlans_in = read_vlan_tags(frame);
+  let vlans_out = read_vlan_tags(&kernel.xdp_frame_out);
+  let effective_direction = TraceDirection::from(kernel.effective_direction);
+  let tc_direction = TraceDirection::from(kernel.tc_direction);
+  let mut notes = Vec::new();
+
+  match kernel.stage {
+    0 => notes.push(
+      "The XDP dissector did not find an IP header. The encapsulation may be unsupported, or the frame is not IP."
+        .to_string(),
+    ),
+    1 => notes.push("The packet was dissected, but not classified.".to_string()),
+    _ => {}
+  }
+  if kernel.stage > 1 && !kernel.matched {
+    notes.push(format!(
+      "No IP mapping matched the {} address for a {effective_direction:?} packet; it will not be shaped.",
+      if effective_direction == TraceDirection::Download { "destination" } else { "source" },
+    ));
+  }
+  if kernel.matched && kernel.xdp_action != XDP_REDIRECT {
+    notes.push(format!(
+      "The IP mapping selected CPU {}, which is not available in the CPU map.",
+      kernel.cpu
+    ));
+  }
+  if vlans_in != vlans_out {
+    notes.push(format!("Bifrost rewrote VLAN tags {vlans_in:?} to {vlans_out:?}."));
+  }
+  if kernel.stage > 1 && tc_direction != effective_direction {
+    notes.push(format!(
+      "XDP decided {effective_direction:?} but TC egress decided {tc_direction:?}; check the interface and VLAN direction settings."
+    ));
+  }
+  if kernel.matched && kernel.tc_priority != kernel.tc_handle {
+    notes.push(format!(
+      "XDP selected TC handle {} but TC egress assigned {}.",
+      TcHandle::from_u32(kernel.tc_handle).to_string(),
+      TcHandle::from_u32(kernel.tc_priority).to_string(),
+    ));
+  }
+
+  PacketTrace {
+    interface: interface.to_string(),
+    xdp_verdict: xdp_action_name(kernel.xdp_action).to_string(),
+    dissected: kernel.stage > 0,
+    matched: kernel.matched,
+    effective_direction,
+    cpu: if kernel.matched { Some(kernel.cpu) } else { None },
+    tc_handle: TcHandle::from_u32(kernel.tc_handle),
+    vlans_in,
+    vlans_out,
+    tc_verdict: tc_action_name(kernel.tc_action).to_string(),
+    tc_egress_handle: TcHandle::from_u32(kernel.tc_priority),
+    tc_direction,
+    notes,
+  }
+}
+
+fn xdp_action_name(action: u32) -> &'static str {
+  match action {
+    0 => "XDP_ABORTED",
+    1 => "XDP_DROP",
+    2 => "XDP_PASS",
+    3 => "XDP_TX",
+    4 => "XDP_REDIRECT",
+    _ => "UNKNOWN",
+  }
+}
+
+fn tc_action_name(action: i32) -> &'static str {
+  match action {
+    -1 => "TC_ACT_UNSPEC",
+    0 => "TC_ACT_OK",
+    1 => "TC_ACT_RECLASSIFY",
+    2 => "TC_ACT_SHOT",
+    3 => "TC_ACT_PIPE",
+    4 => "TC_ACT_STOLEN",
+    7 => "TC_ACT_REDIRECT",
+    _ => "UNKNOWN",
+  }
+}
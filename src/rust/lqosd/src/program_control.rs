@@ -1,6 +1,10 @@
+use crate::queue_planner;
 use lqos_bus::BusResponse;
 
 pub fn reload_libre_qos() -> BusResponse {
+  if queue_planner::planner_enabled() {
+    return queue_planner::reload();
+  }
   let result = lqos_config::load_libreqos();
   match result {
     Ok(message) => BusResponse::ReloadLibreQoS(message),
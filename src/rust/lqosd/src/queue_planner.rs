@@ -0,0 +1,376 @@
+use crate::shaped_devices_tracker;
+use anyhow::{anyhow, bail, Result};
+use log::{error, info, warn};
+use lqos_bus::{BusResponse, TcHandle};
+use lqos_config::{
+  ConfigShapedDevices, EtcLqos, LibreQoSConfig, ShapedDevice, SqmProfiles,
+  ValidationFinding, ValidationSeverity,
+};
+use lqos_queue_planner::{
+  load_network, run_tc_batch, PlanChanges, PlannerSettings, QueuePlan,
+};
+use lqos_queue_tracker::libreqos_is_running;
+use once_cell::sync::Lazy;
+use std::{
+  path::{Path, PathBuf},
+  process::Command,
+  sync::Mutex,
+  time::{Instant, SystemTime},
+};
+
+const TC: &str = "/sbin/tc";
+
+/// The plan last applied, and when `queuingStructure.json` was written
+/// for it. If the file has changed since (because `LibreQoS.py` ran),
+/// the plan no longer describes the queues.
+struct AppliedPlan {
+  plan: QueuePlan,
+  written: Option<SystemTime>,
+}
+
+static APPLIED_PLAN: Lazy<Mutex<Option<AppliedPlan>>> =
+  Lazy::new(|| Mutex::new(None));
+
+/// If applying changes failed partway, when `queuingStructure.json` had
+/// last been written. Circuit edits are refused until a rebuild rewrites
+/// it, since it no longer describes the queues.
+static PARTIALLY_APPLIED: Lazy<Mutex<Option<Option<SystemTime>>>> =
+  Lazy::new(|| Mutex::new(None));
+
+/// Is the built-in queue planner enabled in `/etc/lqos.conf`?
+pub(crate) fn planner_enabled() -> bool {
+  EtcLqos::load()
+    .ok()
+    .and_then(|cfg| cfg.queue_planner)
+    .is_some_and(|planner| planner.enabled)
+}
+
+/// Builds the queues from `network.json` and `ShapedDevices.csv`,
+/// applying only what changed since the last reload.
+pub(crate) fn reload() -> BusResponse {
+  match rebuild() {
+    Ok(message) => BusResponse::ReloadLibreQoS(message),
+    Err(e) => {
+      error!("Unable to rebuild the queues: {e:?}");
+      BusResponse::Fail(format!("Unable to rebuild the queues: {e}"))
+    }
+  }
+}
+
+fn rebuild() -> Result<String> {
+  if libreqos_is_running() {
+    bail!("LibreQoS.py is rebuilding the queues");
+  }
+  check_findings(lqos_config::validate_configuration())?;
+
+  let directory = PathBuf::from(EtcLqos::load()?.lqos_directory);
+  let config = LibreQoSConfig::load()?;
+  let settings = PlannerSettings::from_config(&config)?;
+  let network = load_network(&directory.join("network.json"))?;
+  let devices = ConfigShapedDevices::load()?;
+  let profiles = SqmProfiles::load().unwrap_or_else(|e| {
+    warn!("Unable to load SqmProfiles.toml, using the global sqm: {e}");
+    SqmProfiles::default()
+  });
+  let structure = directory.join("queuingStructure.json");
+
+  let start = Instant::now();
+  let mut applied = APPLIED_PLAN.lock().unwrap();
+  let previous = applied
+    .take()
+    .filter(|applied| applied.written == modified(&structure))
+    .map(|applied| applied.plan);
+  let plan = QueuePlan::build(
+    settings,
+    &network,
+    &devices.devices,
+    &profiles,
+    previous.as_ref(),
+  )?;
+  for (id, name) in plan.unshaped_devices.iter() {
+    warn!("Device {name} ({id}) has no valid parent node, so is not shaped");
+  }
+
+  let changes = previous.as_ref().and_then(|old| plan.changes_from(old));
+  let summary = if !config.enable_shell_commands {
+    for command in plan.full_commands() {
+      info!("tc {command}");
+    }
+    "Simulated run; no commands were executed".to_string()
+  } else {
+    let result = match changes {
+      Some(changes) => apply_changes(&changes),
+      None => apply_in_full(&plan, &directory),
+    };
+    result.inspect_err(|_| mark_partially_applied(&structure))?
+  };
+
+  *PARTIALLY_APPLIED.lock().unwrap() = None;
+  *applied = Some(save(plan, &directory)?);
+
+  let message = format!("{summary} in {} ms", start.elapsed().as_millis());
+  info!("{message}");
+  Ok(message)
+}
+
+/// Deletes the existing queues and builds them all, as `LibreQoS.py`
+/// does.
+fn apply_in_full(plan: &QueuePlan, directory: &Path) -> Result<String> {
+  for pair in plan.settings.pairs.iter() {
+    for interface in pair.interfaces() {
+      let root = Command::new(TC)
+        .args(["qdisc", "show", "dev", interface, "root"])
+        .output()?;
+      if String::from_utf8_lossy(&root.stdout).contains("mq") {
+        Command::new(TC)
+          .args(["qdisc", "delete", "dev", interface, "root"])
+          .output()?;
+      }
+    }
+  }
+  lqos_sys::clear_ips_from_tc()?;
+
+  let commands = plan.full_commands();
+  std::fs::write(directory.join("linux_tc.txt"), commands.join("\n") + "\n")?;
+  if let Err(e) = run_tc_batch(&commands) {
+    warn!("{e}");
+  }
+  let mappings = plan.ip_mappings();
+  for mapping in mappings.iter() {
+    let handle = TcHandle::from_u32(mapping.class_id.as_u32());
+    lqos_sys::add_ip_to_tc(&mapping.ip, handle, mapping.cpu, mapping.upload)?;
+  }
+  Ok(format!(
+    "Rebuilt the queues: {} tc commands, {} IP mappings",
+    commands.len(),
+    mappings.len()
+  ))
+}
+
+/// Applies the changes from the previous plan: new classes first, then
+/// the IP mappings, then removals once nothing maps to them. If a step
+/// fails, the error says which had already run.
+fn apply_changes(changes: &PlanChanges) -> Result<String> {
+  if changes.is_empty() {
+    return Ok("The queues are up to date".to_string());
+  }
+  let mut done = Vec::new();
+  if let Err(e) = apply_steps(changes, &mut done) {
+    if done.is_empty() {
+      done.push("nothing".to_string());
+    }
+    bail!(
+      "{e}; already applied: {}. Reload LibreQoS to rebuild the queues",
+      done.join(", ")
+    );
+  }
+  if let Err(e) = run_tc_batch(&changes.tc_removals) {
+    warn!("{e}");
+  }
+  Ok(format!(
+    "Updated the queues: {} tc commands, {} IP mappings changed, {} removed",
+    changes.tc_additions.len() + changes.tc_removals.len(),
+    changes.mappings.len(),
+    changes.unmappings.len()
+  ))
+}
+
+fn apply_steps(changes: &PlanChanges, done: &mut Vec<String>) -> Result<()> {
+  run_tc_batch(&changes.tc_additions)?;
+  done.push(format!("{} tc additions", changes.tc_additions.len()));
+  let total = changes.mappings.len();
+  for (i, mapping) in changes.mappings.iter().enumerate() {
+    let handle = TcHandle::from_u32(mapping.class_id.as_u32());
+    let result =
+      lqos_sys::add_ip_to_tc(&mapping.ip, handle, mapping.cpu, mapping.upload);
+    if let Err(e) = result {
+      done.push(format!("{i} of {total} IP mappings"));
+      return Err(e);
+    }
+  }
+  done.push(format!("{total} IP mappings"));
+  let total = changes.unmappings.len();
+  for (i, (ip, upload)) in changes.unmappings.iter().enumerate() {
+    if let Err(e) = lqos_sys::del_ip_from_tc(ip, *upload) {
+      done.push(format!("{i} of {total} IP removals"));
+      return Err(e);
+    }
+  }
+  Ok(())
+}
+
+/// Adds a circuit, or replaces its devices, applying just the new or
+/// changed classes and mappings.
+pub(crate) fn upsert_circuit(
+  devices: &[ShapedDevice],
+  author: &str,
+) -> BusResponse {
+  let Some(circuit_id) = devices.first().map(|d| d.circuit_id.clone()) else {
+    return BusResponse::Fail("A circuit needs at least one device".into());
+  };
+  if circuit_id.is_empty() {
+    return BusResponse::Fail("The circuit needs a circuit ID".into());
+  }
+  if devices.iter().any(|d| d.circuit_id != circuit_id) {
+    return BusResponse::Fail(
+      "Every device must have the same circuit ID".into(),
+    );
+  }
+  let comment = format!("Updated circuit {circuit_id}");
+  to_response(update_circuit(&circuit_id, author, &comment, |shaped| {
+    let existing = shaped.iter().position(|d| d.circuit_id == circuit_id);
+    shaped.retain(|d| d.circuit_id != circuit_id);
+    let at = existing.unwrap_or(shaped.len());
+    shaped.splice(at..at, devices.iter().cloned());
+    Ok(())
+  }))
+}
+
+/// Removes a circuit, deleting its classes and mappings.
+pub(crate) fn remove_circuit(circuit_id: &str, author: &str) -> BusResponse {
+  let comment = format!("Removed circuit {circuit_id}");
+  to_response(update_circuit(circuit_id, author, &comment, |shaped| {
+    let before = shaped.len();
+    shaped.retain(|d| d.circuit_id != circuit_id);
+    if shaped.len() == before {
+      bail!("There is no circuit {circuit_id}");
+    }
+    Ok(())
+  }))
+}
+
+fn to_response(result: Result<String>) -> BusResponse {
+  match result {
+    Ok(message) => {
+      info!("{message}");
+      BusResponse::Ack
+    }
+    Err(e) => {
+      error!("Unable to update the circuit: {e:?}");
+      BusResponse::Fail(format!("Unable to update the circuit: {e}"))
+    }
+  }
+}
+
+/// Edits `ShapedDevices.csv` and applies the difference to the queues,
+/// starting from the plan last applied or, failing that, the one in
+/// `queuingStructure.json`. Changes that would need a full rebuild are
+/// refused, leaving everything as it was. The edit is recorded as a
+/// configuration revision, so it can be rolled back.
+fn update_circuit(
+  circuit_id: &str,
+  author: &str,
+  comment: &str,
+  edit: impl FnOnce(&mut Vec<ShapedDevice>) -> Result<()>,
+) -> Result<String> {
+  if libreqos_is_running() {
+    bail!("LibreQoS.py is rebuilding the queues");
+  }
+  let directory = PathBuf::from(EtcLqos::load()?.lqos_directory);
+  let config = LibreQoSConfig::load()?;
+  let settings = PlannerSettings::from_config(&config)?;
+  let network = load_network(&directory.join("network.json"))?;
+  let profiles = SqmProfiles::load().unwrap_or_else(|e| {
+    warn!("Unable to load SqmProfiles.toml, using the global sqm: {e}");
+    SqmProfiles::default()
+  });
+  let mut shaped = ConfigShapedDevices::load()?;
+  edit(&mut shaped.devices)?;
+  check_findings(lqos_config::validate_shaped_devices(&shaped))?;
+
+  let structure = directory.join("queuingStructure.json");
+  let partial = *PARTIALLY_APPLIED.lock().unwrap();
+  if partial.is_some_and(|written| written == modified(&structure)) {
+    bail!(
+      "An earlier change failed partway; reload LibreQoS to rebuild the queues"
+    );
+  }
+  let mut applied = APPLIED_PLAN.lock().unwrap();
+  let loaded;
+  let previous = match applied
+    .as_ref()
+    .filter(|applied| applied.written == modified(&structure))
+  {
+    Some(applied) => &applied.plan,
+    None => {
+      loaded = QueuePlan::load_queuing_structure(settings.clone(), &structure)
+        .map_err(|e| anyhow!("{e}; reload LibreQoS to rebuild the queues"))?;
+      &loaded
+    }
+  };
+  let plan = QueuePlan::build(
+    settings,
+    &network,
+    &shaped.devices,
+    &profiles,
+    Some(previous),
+  )?;
+  let Some(changes) = plan.changes_from(previous) else {
+    bail!("The queues need rebuilding; reload LibreQoS instead");
+  };
+
+  // Until the new plan is saved, nothing describes the queues
+  *applied = None;
+  if config.enable_shell_commands {
+    apply_changes(&changes)
+      .inspect_err(|_| mark_partially_applied(&structure))?;
+  } else {
+    for command in changes.tc_additions.iter().chain(&changes.tc_removals) {
+      info!("tc {command}");
+    }
+  }
+  shaped.write_csv("ShapedDevices.csv")?;
+  if let Err(e) = lqos_config::record_config_revision(author, comment) {
+    warn!("Unable to record configuration revision: {e}");
+  }
+  *applied = Some(save(plan, &directory)?);
+  drop(applied);
+  shaped_devices_tracker::load_shaped_devices();
+  Ok(format!(
+    "Circuit {circuit_id} updated: {} tc commands, {} IP mappings changed, \
+     {} removed",
+    changes.tc_additions.len() + changes.tc_removals.len(),
+    changes.mappings.len(),
+    changes.unmappings.len()
+  ))
+}
+
+fn check_findings(findings: Vec<ValidationFinding>) -> Result<()> {
+  let errors: Vec<String> = findings
+    .into_iter()
+    .filter(|f| f.severity == ValidationSeverity::Error)
+    .map(|f| f.message)
+    .collect();
+  if !errors.is_empty() {
+    bail!("The configuration is invalid: {}", errors.join("; "));
+  }
+  Ok(())
+}
+
+/// Writes `queuingStructure.json` and the other files `LibreQoS.py`
+/// leaves behind for the plan.
+fn save(plan: QueuePlan, directory: &Path) -> Result<AppliedPlan> {
+  let structure = directory.join("queuingStructure.json");
+  plan.write_queuing_structure(&structure)?;
+  if let Err(e) = plan.write_graphing_stats(directory) {
+    warn!("{e}");
+  }
+  for (from, to) in [
+    ("ShapedDevices.csv", "lastGoodConfig.csv"),
+    ("network.json", "lastGoodConfig.json"),
+    ("ShapedDevices.csv", "ShapedDevices.lastLoaded.csv"),
+  ] {
+    if let Err(e) = std::fs::copy(directory.join(from), directory.join(to)) {
+      warn!("Unable to copy {from} to {to}: {e}");
+    }
+  }
+  Ok(AppliedPlan { plan, written: modified(&structure) })
+}
+
+fn mark_partially_applied(structure: &Path) {
+  *PARTIALLY_APPLIED.lock().unwrap() = Some(modified(structure));
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+  std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
@@ -0,0 +1,179 @@
+use crate::throughput_tracker::THROUGHPUT_TRACKER;
+use log::{error, info, warn};
+use lqos_bus::{BusResponse, QuotaStatus};
+use lqos_config::{quota_today, QuotaPlan, QuotaPlans, QuotaUsage};
+use lqos_queue_tracker::{set_rate_override, RateOverride};
+use once_cell::sync::Lazy;
+use std::{collections::HashSet, sync::Mutex};
+
+/// How often (in seconds) to check circuits against their quotas
+const CHECK_SECONDS: u64 = 10;
+
+/// How often (in seconds) to save usage and re-read `Quotas.toml`
+const SAVE_SECONDS: u64 = 60;
+
+const BYTES_PER_GB: u64 = 1_000_000_000;
+
+static QUOTAS: Lazy<Mutex<QuotaTracker>> =
+  Lazy::new(|| Mutex::new(QuotaTracker::new()));
+
+struct QuotaTracker {
+  plans: QuotaPlans,
+  usage: QuotaUsage,
+  throttled: HashSet<String>,
+  ticks: u64,
+}
+
+impl QuotaTracker {
+  fn new() -> Self {
+    let plans = QuotaPlans::load().unwrap_or_else(|e| {
+      error!("Unable to load quotas: {e}");
+      QuotaPlans::default()
+    });
+    let usage = QuotaUsage::load().unwrap_or_else(|e| {
+      error!("Unable to load quota usage, starting afresh: {e}");
+      QuotaUsage::default()
+    });
+    Self { plans, usage, throttled: HashSet::new(), ticks: 0 }
+  }
+
+  fn status(&self, circuit_id: &str, plan: &QuotaPlan) -> QuotaStatus {
+    let today = quota_today();
+    let start = plan.period_start(today);
+    let usage = self.usage.circuit(circuit_id);
+    QuotaStatus {
+      circuit_id: circuit_id.to_string(),
+      quota: plan.name.clone(),
+      period_start: start.to_string(),
+      next_reset: plan.next_reset(today).map(|d| d.to_string()),
+      used_bytes: usage.map_or((0, 0), |u| u.used_since(start)),
+      limit_bytes: plan.limit_bytes(),
+      top_up_bytes: usage.map_or(0, |u| u.top_ups_since(start)),
+      throttled: self.throttled.contains(circuit_id),
+      fair_use_mbps: (plan.fair_use_download_mbps, plan.fair_use_upload_mbps),
+    }
+  }
+
+  /// Slows circuits that have used up their quota to their fair-use
+  /// rates, and restores the others.
+  fn check(&mut self) {
+    let mut throttled = HashSet::new();
+    for circuit_id in self.plans.circuits.keys() {
+      let Some(plan) = self.plans.plan_for_circuit(circuit_id) else {
+        continue;
+      };
+      let status = self.status(circuit_id, plan);
+      let used = status.used_bytes.0.saturating_add(status.used_bytes.1);
+      if used < status.limit_bytes.saturating_add(status.top_up_bytes) {
+        continue;
+      }
+      if !self.throttled.contains(circuit_id) {
+        info!(
+          "Circuit {circuit_id} has used its {} quota; applying fair-use rates",
+          plan.name
+        );
+      }
+      set_rate_override(
+        circuit_id,
+        Some(RateOverride {
+          reason: format!("Quota {} used up", plan.name),
+          download_mbps: plan.fair_use_download_mbps,
+          upload_mbps: plan.fair_use_upload_mbps,
+        }),
+      );
+      throttled.insert(circuit_id.clone());
+    }
+    for circuit_id in self.throttled.difference(&throttled) {
+      info!("Circuit {circuit_id} is within its quota; restoring its rates");
+      set_rate_override(circuit_id, None);
+    }
+    self.throttled = throttled;
+  }
+
+  fn save(&mut self) {
+    self.usage.prune(&self.plans, quota_today());
+    if let Err(e) = self.usage.save() {
+      error!("Unable to save quota usage: {e}");
+    }
+  }
+}
+
+/// Adds the last second's traffic to each circuit's quota usage.
+/// Called once per second by the throughput monitor.
+pub(crate) fn track_quota_usage() {
+  let mut quotas = QUOTAS.lock().unwrap();
+  quotas.ticks += 1;
+  if quotas.ticks % SAVE_SECONDS == 0 {
+    match QuotaPlans::load() {
+      Ok(plans) => quotas.plans = plans,
+      Err(e) => warn!("Keeping the previous quotas: {e}"),
+    }
+  }
+  if quotas.plans.circuits.is_empty() && quotas.throttled.is_empty() {
+    return;
+  }
+
+  let bytes = THROUGHPUT_TRACKER
+    .circuit_bytes_per_second(|id| quotas.plans.circuits.contains_key(id));
+  let today = quota_today();
+  for (circuit_id, bytes) in bytes {
+    quotas.usage.add(&circuit_id, today, bytes);
+  }
+
+  if quotas.ticks % CHECK_SECONDS == 0 {
+    quotas.check();
+  }
+  if quotas.ticks % SAVE_SECONDS == 0 {
+    quotas.save();
+  }
+}
+
+pub fn quota_usage(circuit_id: Option<&str>) -> BusResponse {
+  let quotas = QUOTAS.lock().unwrap();
+  let result = quotas
+    .plans
+    .circuits
+    .keys()
+    .filter(|id| circuit_id.is_none() || circuit_id == Some(id.as_str()))
+    .filter_map(|id| {
+      let plan = quotas.plans.plan_for_circuit(id)?;
+      Some(quotas.status(id, plan))
+    })
+    .collect();
+  BusResponse::QuotaUsage(result)
+}
+
+/// Applies a change to one circuit's usage, then re-checks and saves
+/// straight away so the change takes effect promptly.
+fn change_usage(
+  circuit_id: &str,
+  change: impl FnOnce(&mut QuotaUsage),
+) -> BusResponse {
+  let mut quotas = QUOTAS.lock().unwrap();
+  let Some(plan) = quotas.plans.plan_for_circuit(circuit_id).cloned() else {
+    return BusResponse::Fail(format!("Circuit {circuit_id} has no quota"));
+  };
+  change(&mut quotas.usage);
+  quotas.check();
+  quotas.save();
+  BusResponse::QuotaUsage(vec![quotas.status(circuit_id, &plan)])
+}
+
+pub fn grant_top_up(circuit_id: &str, gigabytes: u64) -> BusResponse {
+  if gigabytes == 0 {
+    return BusResponse::Fail("A top-up must be at least 1 GB".to_string());
+  }
+  info!("Granting circuit {circuit_id} a {gigabytes} GB quota top-up");
+  change_usage(circuit_id, |usage| {
+    usage.grant_top_up(
+      circuit_id,
+      quota_today(),
+      gigabytes.saturating_mul(BYTES_PER_GB),
+    )
+  })
+}
+
+pub fn reset_usage(circuit_id: &str) -> BusResponse {
+  info!("Resetting circuit {circuit_id}'s quota usage");
+  change_usage(circuit_id, |usage| usage.reset(circuit_id))
+}
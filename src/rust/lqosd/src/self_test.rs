@@ -0,0 +1,285 @@
+use crate::packet_trace::run_trace;
+use anyhow::{Error, Result};
+use log::{error, info, warn};
+use lqos_bus::{
+  BusResponse, PacketTrace, SelfTestResult, TcHandle, TraceDirection,
+  TraceFrame,
+};
+use lqos_config::{EtcLqos, InterfacePair, LibreQoSConfig};
+use lqos_utils::{
+  synthetic_frame::{Encapsulation, SyntheticFrame, IPPROTO_TCP},
+  XdpIpAddress,
+};
+use once_cell::sync::Lazy;
+use std::{net::IpAddr, sync::RwLock};
+
+static SELF_TEST_RESULTS: Lazy<RwLock<Option<Vec<SelfTestResult>>>> =
+  Lazy::new(|| RwLock::new(None));
+
+// Documentation addresses (RFC 5737, RFC 3849). They shouldn't
+// collide with a real customer, but if one is mapped anyway its family
+// is skipped rather than overwritten.
+const FAMILIES: [(&str, &str, &str); 2] = [
+  ("IPv4", "192.0.2.1", "198.51.100.1"),
+  ("IPv6", "2001:db8::1", "2001:db8:ffff::1"),
+];
+
+// Temporarily mapped to the test customer. The TC handle doesn't need
+// to exist, nothing is transmitted.
+const TEST_HANDLE: u32 = 0x7FFF_7FFE;
+const TEST_CPU: u32 = 0;
+
+struct SelfTestCase {
+  name: String,
+  interface: String,
+  frame: SyntheticFrame,
+  direction: TraceDirection,
+}
+
+/// Runs the self-test if it is enabled in `/etc/lqos.conf`: a suite
+/// of synthetic frames is run through the loaded XDP/TC programs,
+/// catching driver or kernel combinations that break VLAN, PPPoE or
+/// MPLS handling. Returns an error if any case fails and `strict` is
+/// set; the daemon should not start.
+pub(crate) fn startup_self_test(config: &LibreQoSConfig) -> Result<()> {
+  let settings = EtcLqos::load().ok().and_then(|cfg| cfg.self_test);
+  let settings = match settings {
+    Some(settings) if settings.enabled => settings,
+    _ => return Ok(()),
+  };
+
+  info!("Running the dissector/classifier self-test");
+  let results = run_self_test(config);
+  let failures = results.iter().filter(|r| !r.passed).count();
+  for result in results.iter().filter(|r| !r.passed) {
+    warn!("Self-test {} failed: {}", result.name, result.problems.join("; "));
+  }
+  if failures == 0 {
+    info!("Self-test passed ({} cases)", results.len());
+  } else {
+    warn!("Self-test: {failures} of {} cases failed", results.len());
+  }
+  *SELF_TEST_RESULTS.write().unwrap() = Some(results);
+
+  if failures > 0 && settings.strict {
+    error!("Refusing to start: the self-test failed, and strict mode is set in /etc/lqos.conf");
+    return Err(Error::msg("Dissector/classifier self-test failed"));
+  }
+  Ok(())
+}
+
+pub(crate) fn self_test_results() -> BusResponse {
+  BusResponse::SelfTestResults(SELF_TEST_RESULTS.read().unwrap().clone())
+}
+
+fn run_self_test(config: &LibreQoSConfig) -> Vec<SelfTestResult> {
+  let handle = TcHandle::from_u32(TEST_HANDLE);
+  let families: Vec<_> = FAMILIES
+    .into_iter()
+    .filter(|(family, customer, _)| {
+      let mapped = already_mapped(customer);
+      if mapped {
+        warn!("Self-test skipping {family}: {customer} is already mapped");
+      }
+      !mapped
+    })
+    .collect();
+  for (_, customer, _) in families.iter() {
+    for upload in [false, true] {
+      if let Err(e) = lqos_sys::add_ip_to_tc(customer, handle, TEST_CPU, upload) {
+        warn!("Self-test unable to map {customer}: {e:?}");
+      }
+    }
+  }
+
+  let results = build_cases(config, &families)
+    .into_iter()
+    .map(|case| run_case(case, handle))
+    .collect();
+
+  for (_, customer, _) in families.iter() {
+    for upload in [false, true] {
+      if let Err(e) = lqos_sys::del_ip_from_tc(customer, upload) {
+        warn!("Self-test unable to remove mapping for {customer}: {e:?}");
+      }
+    }
+  }
+  results
+}
+
+/// Is there already a mapping for exactly this address, which the
+/// self-test would overwrite and then delete?
+fn already_mapped(address: &str) -> bool {
+  let Ok(ip) = address.parse::<IpAddr>() else {
+    return false;
+  };
+  let address = XdpIpAddress::from_ip(ip).0;
+  match lqos_sys::list_mapped_ips() {
+    Ok(mapped) => mapped
+      .iter()
+      .any(|(key, _)| key.prefixlen == 128 && key.address == address),
+    Err(e) => {
+      warn!("Self-test unable to read the IP mapping table: {e:?}");
+      false
+    }
+  }
+}
+
+fn build_cases(
+  config: &LibreQoSConfig,
+  families: &[(&str, &str, &str)],
+) -> Vec<SelfTestCase> {
+  let pairs = match config.interface_pairs() {
+    Ok(pairs) => pairs,
+    Err(e) => {
+      warn!("Self-test unable to read the interface pairs: {e:?}");
+      return Vec::new();
+    }
+  };
+  let mut cases = Vec::new();
+  for pair in pairs.iter() {
+    let mut pair_cases = build_pair_cases(pair, families);
+    if pairs.len() > 1 {
+      for case in pair_cases.iter_mut() {
+        case.name = format!("{}: {}", pair.name, case.name);
+      }
+    }
+    cases.extend(pair_cases);
+  }
+  cases
+}
+
+fn build_pair_cases(
+  pair: &InterfacePair,
+  families: &[(&str, &str, &str)],
+) -> Vec<SelfTestCase> {
+  use Encapsulation::*;
+  // On a stick, every frame already carries the stick VLAN - so the
+  // plain 802.1Q case is covered, and QinQ is covered when the stick
+  // tags have inner tags. A customer tag inside a plain stick tag
+  // would become the innermost tag, which the classifier compares with
+  // the stick tags, so it isn't a valid case.
+  let encapsulations: Vec<(&str, Vec<Encapsulation>)> =
+    if pair.on_a_stick {
+      vec![
+        ("stick VLAN", vec![]),
+        ("PPPoE", vec![Pppoe(1)]),
+        ("MPLS", vec![Mpls(16)]),
+      ]
+    } else {
+      vec![
+        ("untagged", vec![]),
+        ("802.1Q", vec![Vlan(100)]),
+        ("QinQ", vec![ServiceVlan(200), Vlan(100)]),
+        ("PPPoE", vec![Pppoe(1)]),
+        ("MPLS", vec![Mpls(16)]),
+      ]
+    };
+
+  let mut cases = Vec::new();
+  for (family, customer, remote) in families.iter() {
+    let customer: IpAddr = customer.parse().unwrap();
+    let remote: IpAddr = remote.parse().unwrap();
+    for (encapsulation, layers) in encapsulations.iter() {
+      for direction in [TraceDirection::Download, TraceDirection::Upload] {
+        let download = direction == TraceDirection::Download;
+        let (src, dst) =
+          if download { (remote, customer) } else { (customer, remote) };
+        let mut frame = SyntheticFrame::new(src, dst, IPPROTO_TCP);
+        let mut name = format!("{family} {encapsulation} {direction:?}");
+        let interface = if pair.on_a_stick {
+          let (tag, inner) = if download {
+            (pair.internet_vlan, pair.internet_inner_vlan)
+          } else {
+            (pair.isp_vlan, pair.isp_inner_vlan)
+          };
+          if inner == 0 {
+            frame = frame.with_vlan(Some(tag));
+            name += &format!(" (VLAN {tag})");
+          } else {
+            frame = frame.with_qinq(tag, inner);
+            name += &format!(" (VLAN {tag}.{inner})");
+          }
+          pair.internet_interface.clone()
+        } else if download {
+          pair.internet_interface.clone()
+        } else {
+          pair.isp_interface.clone()
+        };
+        for layer in layers.iter() {
+          frame = frame.with_encapsulation(*layer);
+        }
+        cases.push(SelfTestCase { name, interface, frame, direction });
+      }
+    }
+  }
+  cases
+}
+
+fn run_case(case: SelfTestCase, handle: TcHandle) -> SelfTestResult {
+  let trace = case
+    .frame
+    .build()
+    .map_err(Error::from)
+    .and_then(|frame| run_trace(&case.interface, &TraceFrame::Raw(frame)));
+  match trace {
+    Ok(trace) => {
+      let problems = check_trace(&trace, case.direction, handle);
+      SelfTestResult {
+        name: case.name,
+        passed: problems.is_empty(),
+        problems,
+        trace: Some(trace),
+      }
+    }
+    Err(e) => SelfTestResult {
+      name: case.name,
+      passed: false,
+      problems: vec![format!("Unable to run the frame: {e}")],
+      trace: None,
+    },
+  }
+}
+
+fn check_trace(
+  trace: &PacketTrace,
+  direction: TraceDirection,
+  handle: TcHandle,
+) -> Vec<String> {
+  let mut problems = Vec::new();
+  if !trace.dissected {
+    problems.push("XDP did not find the IP header".to_string());
+  } else if !trace.matched {
+    problems.push("XDP did not match the test IP mapping".to_string());
+  }
+  if trace.xdp_verdict != "XDP_REDIRECT" {
+    problems.push(format!("XDP returned {}, expected XDP_REDIRECT", trace.xdp_verdict));
+  }
+  if trace.effective_direction != direction {
+    problems.push(format!(
+      "XDP classified the frame as {:?}, expected {direction:?}",
+      trace.effective_direction
+    ));
+  }
+  if trace.tc_handle != handle {
+    problems.push(format!(
+      "XDP selected TC handle {}, expected {}",
+      trace.tc_handle.to_string(),
+      handle.to_string()
+    ));
+  }
+  if trace.tc_direction != direction {
+    problems.push(format!(
+      "TC egress classified the frame as {:?}, expected {direction:?}",
+      trace.tc_direction
+    ));
+  }
+  if trace.tc_egress_handle != handle {
+    problems.push(format!(
+      "TC egress assigned handle {}, expected {}",
+      trace.tc_egress_handle.to_string(),
+      handle.to_string()
+    ));
+  }
+  problems
+}
@@ -13,7 +13,7 @@ pub static SHAPED_DEVICES: Lazy<RwLock<ConfigShapedDevices>> =
     Lazy::new(|| RwLock::new(ConfigShapedDevices::default()));
 pub static STATS_NEEDS_NEW_SHAPED_DEVICES: AtomicBool = AtomicBool::new(false);
 
-fn load_shaped_devices() {
+pub(crate) fn load_shaped_devices() {
     info!("ShapedDevices.csv has changed. Attempting to load it.");
     let shaped_devices = ConfigShapedDevices::load();
     if let Ok(new_file) = shaped_devices {
@@ -67,6 +67,15 @@ pub fn get_one_network_map_layer(parent_idx: usize) -> BusResponse {
     }
 }
 
+pub fn get_one_network_map_layer_by_id(id: &str) -> BusResponse {
+    let index = NETWORK_JSON.read().unwrap().get_index_for_id(id);
+    if let Some(index) = index {
+        get_one_network_map_layer(index)
+    } else {
+        BusResponse::Fail("No such node".to_string())
+    }
+}
+
 pub fn get_top_n_root_queues(n_queues: usize) -> BusResponse {
     let net_json = NETWORK_JSON.read().unwrap();
     if let Some(parent) = net_json.get_cloned_entry_by_index(0) {
@@ -98,6 +107,8 @@ pub fn get_top_n_root_queues(n_queues: usize) -> BusResponse {
                     parents: Vec::new(),
                     immediate_parent: None,
                     node_type: None,
+                    id: String::new(),
+                    parent_ids: Vec::new(),
                 },
             ));
         }
@@ -1,4 +1,5 @@
 mod heimdall_data;
+mod pair_throughput;
 mod throughput_entry;
 mod tracking_data;
 use crate::{
@@ -6,9 +7,12 @@ use crate::{
     throughput_tracker::tracking_data::ThroughputTracker, long_term_stats::get_network_tree,
 };
 pub use heimdall_data::get_flow_stats;
+pub use pair_throughput::{pair_throughput, set_interface_pairs};
 use log::{info, warn};
-use lqos_bus::{BusResponse, IpStats, TcHandle, XdpPpingResult};
+use lqos_bus::{BusResponse, ExplainedCounters, IpStats, TcHandle, XdpPpingResult};
 use lqos_utils::{unix_time::time_since_boot, XdpIpAddress};
+use std::collections::BTreeMap;
+use throughput_entry::ThroughputEntry;
 use lts_client::collector::{StatsUpdateMessage, ThroughputSummary, HostSummary};
 use once_cell::sync::Lazy;
 use tokio::{
@@ -51,6 +55,10 @@ async fn throughput_task(interval_ms: u64, long_term_stats_tx: Sender<StatsUpdat
           THROUGHPUT_TRACKER.apply_rtt_data();
           THROUGHPUT_TRACKER.update_totals();
           THROUGHPUT_TRACKER.next_cycle();
+          pair_throughput::update_pair_throughput();
+          crate::quotas::track_quota_usage();
+          crate::burst::track_bursts();
+          crate::circuit_history::track_circuit_history();
           let duration_ms = start.elapsed().as_micros();
           TIME_TO_POLL_HOSTS.store(duration_ms as u64, std::sync::atomic::Ordering::Relaxed);
 
@@ -107,6 +115,7 @@ async fn submit_throughput_stats(long_term_stats_tx: Sender<StatsUpdateMessage>)
             circuit_id: host.circuit_id.clone(),
             bits_per_second: (host.bytes_per_second.0 * 8, host.bytes_per_second.1 * 8),
             median_rtt: host.median_latency().unwrap_or(0.0),
+            tags: host.tags.clone(),
         })
         .collect();
 
@@ -156,15 +165,36 @@ fn retire_check(cycle: u64, recent_cycle: u64) -> bool {
     cycle < recent_cycle + RETIRE_AFTER_SECONDS
 }
 
-type TopList = (XdpIpAddress, (u64, u64), (u64, u64), f32, TcHandle, String);
+type TopList = (
+    XdpIpAddress,
+    (u64, u64),
+    (u64, u64),
+    f32,
+    TcHandle,
+    String,
+    BTreeMap<String, String>,
+);
 
-pub fn top_n(start: u32, end: u32) -> BusResponse {
+/// Does a host's circuit carry the tag a Top-N list is filtered by?
+fn has_tag(entry: &ThroughputEntry, tag: Option<&(String, String)>) -> bool {
+    let Some((name, value)) = tag else { return true };
+    entry.tags.iter().any(|(k, v)| {
+        k.eq_ignore_ascii_case(name) && v.eq_ignore_ascii_case(value)
+    })
+}
+
+pub fn top_n(
+    start: u32,
+    end: u32,
+    tag: Option<&(String, String)>,
+) -> BusResponse {
     let mut full_list: Vec<TopList> = {
       let tp_cycle = THROUGHPUT_TRACKER.cycle.load(std::sync::atomic::Ordering::Relaxed);
       THROUGHPUT_TRACKER.raw_data
         .iter()
         .filter(|v| !v.key().as_ip().is_loopback())
         .filter(|d| retire_check(tp_cycle, d.most_recent_cycle))
+        .filter(|te| has_tag(te, tag))
         .map(|te| {
           (
             *te.key(),
@@ -173,6 +203,7 @@ pub fn top_n(start: u32, end: u32) -> BusResponse {
             te.median_latency().unwrap_or(0.0),
             te.tc_handle,
             te.circuit_id.as_ref().unwrap_or(&String::new()).clone(),
+            te.tags.clone(),
           )
         })
         .collect()
@@ -190,6 +221,7 @@ pub fn top_n(start: u32, end: u32) -> BusResponse {
           median_rtt,
           tc_handle,
           circuit_id,
+          tags,
         )| IpStats {
           ip_address: ip.as_ip().to_string(),
           circuit_id: circuit_id.clone(),
@@ -197,19 +229,25 @@ pub fn top_n(start: u32, end: u32) -> BusResponse {
           packets_per_second: (*packets_dn, *packets_up),
           median_tcp_rtt: *median_rtt,
           tc_handle: *tc_handle,
+          tags: tags.clone(),
         },
       )
       .collect();
     BusResponse::TopDownloaders(result)
   }
 
-  pub fn worst_n(start: u32, end: u32) -> BusResponse {
+  pub fn worst_n(
+    start: u32,
+    end: u32,
+    tag: Option<&(String, String)>,
+  ) -> BusResponse {
     let mut full_list: Vec<TopList> = {
       let tp_cycle = THROUGHPUT_TRACKER.cycle.load(std::sync::atomic::Ordering::Relaxed);
       THROUGHPUT_TRACKER.raw_data
         .iter()
         .filter(|v| !v.key().as_ip().is_loopback())
         .filter(|d| retire_check(tp_cycle, d.most_recent_cycle))
+        .filter(|te| has_tag(te, tag))
         .filter(|te| te.median_latency().is_some())
         .map(|te| {
           (
@@ -219,6 +257,7 @@ pub fn top_n(start: u32, end: u32) -> BusResponse {
             te.median_latency().unwrap_or(0.0),
             te.tc_handle,
             te.circuit_id.as_ref().unwrap_or(&String::new()).clone(),
+            te.tags.clone(),
           )
         })
         .collect()
@@ -236,6 +275,7 @@ pub fn top_n(start: u32, end: u32) -> BusResponse {
           median_rtt,
           tc_handle,
           circuit_id,
+          tags,
         )| IpStats {
           ip_address: ip.as_ip().to_string(),
           circuit_id: circuit_id.clone(),
@@ -243,19 +283,25 @@ pub fn top_n(start: u32, end: u32) -> BusResponse {
           packets_per_second: (*packets_dn, *packets_up),
           median_tcp_rtt: *median_rtt,
           tc_handle: *tc_handle,
+          tags: tags.clone(),
         },
       )
       .collect();
     BusResponse::WorstRtt(result)
   }
 
-  pub fn best_n(start: u32, end: u32) -> BusResponse {
+  pub fn best_n(
+    start: u32,
+    end: u32,
+    tag: Option<&(String, String)>,
+  ) -> BusResponse {
     let mut full_list: Vec<TopList> = {
       let tp_cycle = THROUGHPUT_TRACKER.cycle.load(std::sync::atomic::Ordering::Relaxed);
       THROUGHPUT_TRACKER.raw_data
         .iter()
         .filter(|v| !v.key().as_ip().is_loopback())
         .filter(|d| retire_check(tp_cycle, d.most_recent_cycle))
+        .filter(|te| has_tag(te, tag))
         .filter(|te| te.median_latency().is_some())
         .map(|te| {
           (
@@ -265,6 +311,7 @@ pub fn top_n(start: u32, end: u32) -> BusResponse {
             te.median_latency().unwrap_or(0.0),
             te.tc_handle,
             te.circuit_id.as_ref().unwrap_or(&String::new()).clone(),
+            te.tags.clone(),
           )
         })
         .collect()
@@ -283,6 +330,7 @@ pub fn top_n(start: u32, end: u32) -> BusResponse {
           median_rtt,
           tc_handle,
           circuit_id,
+          tags,
         )| IpStats {
           ip_address: ip.as_ip().to_string(),
           circuit_id: circuit_id.clone(),
@@ -290,6 +338,7 @@ pub fn top_n(start: u32, end: u32) -> BusResponse {
           packets_per_second: (*packets_dn, *packets_up),
           median_tcp_rtt: *median_rtt,
           tc_handle: *tc_handle,
+          tags: tags.clone(),
         },
       )
       .collect();
@@ -438,8 +487,42 @@ pub fn all_unknown_ips() -> BusResponse {
           packets_per_second: (*packets_dn, *packets_up),
           median_tcp_rtt: *median_rtt,
           tc_handle: *tc_handle,
+          tags: BTreeMap::new(),
         },
       )
       .collect();
     BusResponse::AllUnknownIps(result)
-  }
\ No newline at end of file
+  }
+
+/// Current counters for a single IP address, if it has been seen.
+pub fn ip_counters(ip: &XdpIpAddress) -> Option<ExplainedCounters> {
+    THROUGHPUT_TRACKER.raw_data.get(ip).map(|te| ExplainedCounters {
+        bytes: te.bytes,
+        packets: te.packets,
+        bits_per_second: (te.bytes_per_second.0 * 8, te.bytes_per_second.1 * 8),
+        packets_per_second: te.packets_per_second,
+        median_tcp_rtt: te.median_latency(),
+        tc_handle: te.tc_handle,
+        circuit_id: te.circuit_id.clone(),
+    })
+}
+
+/// Would `all_unknown_ips` list this address? It must have been seen
+/// in the last five minutes, without a TC handle.
+pub fn is_unknown_ip(ip: &XdpIpAddress) -> bool {
+    let boot_time = time_since_boot();
+    if boot_time.is_err() {
+        return false;
+    }
+    let five_minutes_ago =
+        Duration::from(boot_time.unwrap()).saturating_sub(Duration::from_secs(300));
+    THROUGHPUT_TRACKER
+        .raw_data
+        .get(ip)
+        .map(|te| {
+            !ip.as_ip().is_loopback()
+                && te.tc_handle.as_u32() == 0
+                && te.last_seen as u128 > five_minutes_ago.as_nanos()
+        })
+        .unwrap_or(false)
+}
@@ -0,0 +1,102 @@
+use log::{info, warn};
+use lqos_bus::{BusResponse, PairThroughput};
+use lqos_config::InterfacePair;
+use lqos_sys::InterfaceCounter;
+use once_cell::sync::Lazy;
+use std::{collections::HashSet, sync::RwLock};
+
+static PAIRS: Lazy<RwLock<Vec<PairCounters>>> =
+  Lazy::new(|| RwLock::new(Vec::new()));
+
+struct PairCounters {
+  name: String,
+  interfaces: Vec<String>,
+  // The kernel counters are pinned, and outlive the daemon. Totals
+  // are reported relative to the first reading.
+  baseline: Option<InterfaceCounter>,
+  previous: InterfaceCounter,
+  current: InterfaceCounter,
+  // Interfaces whose counters couldn't be read last time, so that a
+  // failure is logged once rather than every second
+  failing: HashSet<String>,
+}
+
+/// Sets the interface pairs whose throughput should be tracked.
+pub fn set_interface_pairs(pairs: &[InterfacePair]) {
+  let mut lock = PAIRS.write().unwrap();
+  *lock = pairs
+    .iter()
+    .map(|pair| PairCounters {
+      name: pair.name.clone(),
+      interfaces: pair.interfaces().iter().map(|i| i.to_string()).collect(),
+      baseline: None,
+      previous: InterfaceCounter::default(),
+      current: InterfaceCounter::default(),
+      failing: HashSet::new(),
+    })
+    .collect();
+}
+
+/// Reads the per-interface kernel counters. Called once per second by
+/// the throughput monitor.
+pub(crate) fn update_pair_throughput() {
+  let mut lock = PAIRS.write().unwrap();
+  for pair in lock.iter_mut() {
+    let mut total = InterfaceCounter::default();
+    for interface in pair.interfaces.iter() {
+      match lqos_sys::interface_counters(interface) {
+        Ok(counters) => {
+          total += counters;
+          if pair.failing.remove(interface) {
+            info!("Reading traffic counters for {interface} again");
+          }
+        }
+        Err(e) => {
+          if pair.failing.insert(interface.clone()) {
+            warn!("Unable to read traffic counters for {interface}: {e:?}");
+          }
+        }
+      }
+    }
+    if pair.baseline.is_none() {
+      pair.baseline = Some(total);
+      pair.current = total;
+    }
+    pair.previous = pair.current;
+    pair.current = total;
+  }
+}
+
+/// Current throughput of each shaping bridge pair.
+pub fn pair_throughput() -> BusResponse {
+  let lock = PAIRS.read().unwrap();
+  BusResponse::PairThroughput(
+    lock
+      .iter()
+      .map(|pair| {
+        let baseline = pair.baseline.unwrap_or_default();
+        let (c, p) = (&pair.current, &pair.previous);
+        PairThroughput {
+          name: pair.name.clone(),
+          interfaces: pair.interfaces.clone(),
+          bits_per_second: (
+            c.download_bytes.saturating_sub(p.download_bytes) * 8,
+            c.upload_bytes.saturating_sub(p.upload_bytes) * 8,
+          ),
+          packets_per_second: (
+            c.download_packets.saturating_sub(p.download_packets),
+            c.upload_packets.saturating_sub(p.upload_packets),
+          ),
+          bytes: (
+            c.download_bytes.saturating_sub(baseline.download_bytes),
+            c.upload_bytes.saturating_sub(baseline.upload_bytes),
+          ),
+          packets: (
+            c.download_packets.saturating_sub(baseline.download_packets),
+            c.upload_packets.saturating_sub(baseline.upload_packets),
+          ),
+        }
+      })
+      .collect(),
+  )
+}
@@ -1,8 +1,10 @@
 use lqos_bus::TcHandle;
+use std::collections::BTreeMap;
 
 #[derive(Debug)]
 pub(crate) struct ThroughputEntry {
   pub(crate) circuit_id: Option<String>,
+  pub(crate) tags: BTreeMap<String, String>,
   pub(crate) network_json_parents: Option<Vec<usize>>,
   pub(crate) first_cycle: u64,
   pub(crate) most_recent_cycle: u64,
@@ -25,21 +27,29 @@ impl ThroughputEntry {
   /// Also explicitly rejects 0 values, and flows that have
   /// less than 1 Mb of data---they are usually long-polling.
   pub(crate) fn median_latency(&self) -> Option<f32> {
-    // Reject sub 1Mb flows
-    if self.bytes.0 < 1_000_000 || self.bytes.1 < 1_000_000 {
-      return None;
-    }
-
-    let mut shifted: Vec<f32> = self
-      .recent_rtt_data
-      .iter()
-      .filter(|n| **n != 0)
-      .map(|n| *n as f32 / 100.0)
-      .collect();
+    let mut shifted = self.recent_latencies()?;
     if shifted.len() < 5 {
       return None;
     }
     shifted.sort_by(|a, b| a.partial_cmp(b).unwrap());
     Some(shifted[shifted.len() / 2])
   }
+
+  /// The recent RTT samples in milliseconds, skipping zeroes. Hosts
+  /// with less than 1 Mb of data have `None`, like `median_latency`.
+  pub(crate) fn recent_latencies(&self) -> Option<Vec<f32>> {
+    // Reject sub 1Mb flows
+    if self.bytes.0 < 1_000_000 || self.bytes.1 < 1_000_000 {
+      return None;
+    }
+
+    Some(
+      self
+        .recent_rtt_data
+        .iter()
+        .filter(|n| **n != 0)
+        .map(|n| *n as f32 / 100.0)
+        .collect(),
+    )
+  }
 }
@@ -1,4 +1,7 @@
-use std::sync::atomic::AtomicU64;
+use std::{
+  collections::{BTreeMap, HashMap},
+  sync::atomic::AtomicU64,
+};
 use crate::{shaped_devices_tracker::{SHAPED_DEVICES, NETWORK_JSON}, stats::{HIGH_WATERMARK_DOWN, HIGH_WATERMARK_UP}};
 use super::{throughput_entry::ThroughputEntry, RETIRE_AFTER_SECONDS};
 use dashmap::DashMap;
@@ -54,15 +57,18 @@ impl ThroughputTracker {
     });
   }
 
-  fn lookup_circuit_id(xdp_ip: &XdpIpAddress) -> Option<String> {
-    let mut circuit_id = None;
+  /// The circuit ID and tags of the shaped device an address belongs to
+  fn lookup_device(
+    xdp_ip: &XdpIpAddress,
+  ) -> (Option<String>, BTreeMap<String, String>) {
     let lookup = xdp_ip.as_ipv6();
     let cfg = SHAPED_DEVICES.read().unwrap();
     if let Some((_, id)) = cfg.trie.longest_match(lookup) {
-      circuit_id = Some(cfg.devices[*id].circuit_id.clone());
+      let device = &cfg.devices[*id];
+      (Some(device.circuit_id.clone()), device.tags.clone())
+    } else {
+      (None, BTreeMap::new())
     }
-    //println!("{lookup:?} Found circuit_id: {circuit_id:?}");
-    circuit_id
   }
 
   pub(crate) fn get_node_name_for_circuit_id(
@@ -95,7 +101,7 @@ impl ThroughputTracker {
 
   pub(crate) fn refresh_circuit_ids(&self) {
     self.raw_data.iter_mut().for_each(|mut data| {
-      data.circuit_id = Self::lookup_circuit_id(data.key());
+      (data.circuit_id, data.tags) = Self::lookup_device(data.key());
       data.network_json_parents =
         Self::lookup_network_parents(data.circuit_id.clone());
     });
@@ -137,9 +143,10 @@ impl ThroughputTracker {
           }
         }
       } else {
-        let circuit_id = Self::lookup_circuit_id(xdp_ip);
+        let (circuit_id, tags) = Self::lookup_device(xdp_ip);
         let mut entry = ThroughputEntry {
           circuit_id: circuit_id.clone(),
+          tags,
           network_json_parents: Self::lookup_network_parents(circuit_id),
           first_cycle: self_cycle,
           most_recent_cycle: 0,
@@ -253,6 +260,45 @@ impl ThroughputTracker {
     self.cycle.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
   }
 
+  /// Totals the last second's bytes (download, upload) for each
+  /// circuit that `include` accepts.
+  pub(crate) fn circuit_bytes_per_second(
+    &self,
+    include: impl Fn(&str) -> bool,
+  ) -> HashMap<String, (u64, u64)> {
+    let mut bytes: HashMap<String, (u64, u64)> = HashMap::new();
+    self.raw_data.iter().for_each(|entry| {
+      if let Some(circuit_id) = &entry.circuit_id {
+        if include(circuit_id) {
+          let total = bytes.entry(circuit_id.clone()).or_default();
+          total.0 += entry.bytes_per_second.0;
+          total.1 += entry.bytes_per_second.1;
+        }
+      }
+    });
+    bytes
+  }
+
+  /// Pools the recent RTT samples (in milliseconds) of each circuit's
+  /// hosts that have had fresh RTT data since `since_cycle`.
+  pub(crate) fn circuit_latencies(
+    &self,
+    since_cycle: u64,
+  ) -> HashMap<String, Vec<f32>> {
+    let mut latencies: HashMap<String, Vec<f32>> = HashMap::new();
+    self.raw_data.iter().for_each(|entry| {
+      if entry.last_fresh_rtt_data_cycle < since_cycle {
+        return;
+      }
+      if let Some(circuit_id) = &entry.circuit_id {
+        if let Some(recent) = entry.recent_latencies() {
+          latencies.entry(circuit_id.clone()).or_default().extend(recent);
+        }
+      }
+    });
+    latencies
+  }
+
   pub(crate) fn bits_per_second(&self) -> (u64, u64) {
     (self.bytes_per_second.0.load(std::sync::atomic::Ordering::Relaxed) * 8, self.bytes_per_second.1.load(std::sync::atomic::Ordering::Relaxed) * 8)
   }
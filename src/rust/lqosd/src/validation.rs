@@ -8,3 +8,7 @@ pub fn validate_shaped_devices_csv() -> BusResponse {
     Err(e) => BusResponse::ShapedDevicesValidation(format!("{e:#?}")),
   }
 }
+
+pub fn validate_configuration() -> BusResponse {
+  BusResponse::ValidationFindings(lqos_config::validate_configuration())
+}
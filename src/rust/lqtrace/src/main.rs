@@ -0,0 +1,234 @@
+use anyhow::{Error, Result};
+use clap::{Parser, Subcommand};
+use lqos_bus::{
+  bus_request, BusRequest, BusResponse, IpExplanation, PacketTrace,
+  SelfTestResult, TraceFrame,
+};
+use std::process::exit;
+
+#[derive(Parser)]
+#[command()]
+struct Args {
+  #[command(subcommand)]
+  command: Option<Commands>,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+  /// Build a frame from a description and trace it.
+  Synthetic {
+    /// The interface on which the packet arrives
+    #[arg(long)]
+    interface: String,
+
+    /// Source IP address (v4 or v6)
+    #[arg(long)]
+    src: String,
+
+    /// Destination IP address (v4 or v6)
+    #[arg(long)]
+    dst: String,
+
+    /// 802.1Q VLAN tag, if any
+    #[arg(long)]
+    vlan: Option<u16>,
+
+    /// Protocol: tcp, udp, icmp, icmpv6 or a protocol number
+    #[arg(long, default_value = "tcp")]
+    proto: String,
+  },
+  /// Trace a raw Ethernet frame, provided as a hex string.
+  Raw {
+    /// The interface on which the packet arrives
+    #[arg(long)]
+    interface: String,
+
+    /// The frame, in hex (e.g. from Wireshark's "copy as hex stream")
+    frame: String,
+  },
+  /// Explain how an IP address is mapped, queued and counted.
+  Ip {
+    /// The IP address to explain
+    ip: String,
+  },
+  /// Show the results of lqosd's startup self-test.
+  SelfTest,
+}
+
+fn parse_protocol(proto: &str) -> Result<u8> {
+  match proto.to_lowercase().as_str() {
+    "tcp" => Ok(6),
+    "udp" => Ok(17),
+    "icmp" => Ok(1),
+    "icmpv6" => Ok(58),
+    n => n
+      .parse()
+      .map_err(|_| Error::msg(format!("Unknown protocol: {proto}"))),
+  }
+}
+
+fn parse_hex_frame(frame: &str) -> Result<Vec<u8>> {
+  let frame: String = frame.chars().filter(|c| c.is_ascii_hexdigit()).collect();
+  if frame.len() % 2 != 0 {
+    return Err(Error::msg("Frame must contain an even number of hex digits"));
+  }
+  (0..frame.len())
+    .step_by(2)
+    .map(|i| u8::from_str_radix(&frame[i..i + 2], 16).map_err(Error::from))
+    .collect()
+}
+
+fn print_trace(trace: &PacketTrace) {
+  println!("Interface           : {}", trace.interface);
+  println!("XDP verdict         : {}", trace.xdp_verdict);
+  println!("Dissected           : {}", trace.dissected);
+  println!("Matched IP mapping  : {}", trace.matched);
+  println!("Direction (XDP)     : {:?}", trace.effective_direction);
+  match trace.cpu {
+    Some(cpu) => println!("CPU                 : {cpu}"),
+    None => println!("CPU                 : -"),
+  }
+  println!("TC handle (XDP)     : {}", trace.tc_handle.to_string());
+  println!("VLANs in            : {:?}", trace.vlans_in);
+  println!("VLANs out           : {:?}", trace.vlans_out);
+  println!("TC verdict          : {}", trace.tc_verdict);
+  println!("TC handle (egress)  : {}", trace.tc_egress_handle.to_string());
+  println!("Direction (TC)      : {:?}", trace.tc_direction);
+  if !trace.notes.is_empty() {
+    println!();
+    for note in trace.notes.iter() {
+      println!(" * {note}");
+    }
+  }
+}
+
+fn print_explanation(explanation: &IpExplanation) {
+  println!("IP                  : {}", explanation.ip);
+  if let Some(device) = &explanation.shaped_device {
+    println!("Circuit             : {} ({})", device.circuit_name, device.circuit_id);
+    println!("Device              : {} ({})", device.device_name, device.device_id);
+    println!("Matched prefix      : {}", device.matched_prefix);
+    println!("Plan (Mbps)         : {} / {}", device.download_max_mbps, device.upload_max_mbps);
+  } else {
+    println!("Circuit             : -");
+  }
+  if let Some(mapping) = &explanation.xdp_mapping {
+    println!(
+      "XDP mapping         : {}/{} -> {} on CPU {}",
+      mapping.ip_address,
+      mapping.prefix_length,
+      mapping.tc_handle.to_string(),
+      mapping.cpu
+    );
+  } else {
+    println!("XDP mapping         : -");
+  }
+  if let Some(mapping) = &explanation.xdp_upload_mapping {
+    println!(
+      "XDP upload mapping  : {}/{} -> {} on CPU {}",
+      mapping.ip_address,
+      mapping.prefix_length,
+      mapping.tc_handle.to_string(),
+      mapping.cpu
+    );
+  }
+  if let Some(queue) = &explanation.queue {
+    println!(
+      "Queue               : {} (CPU {}) / {} (CPU {}), parent {}",
+      queue.class_id.to_string(),
+      queue.cpu,
+      queue.up_class_id.to_string(),
+      queue.up_cpu,
+      queue.parent_class_id.to_string()
+    );
+    match (&queue.sqm_profile, &queue.sqm) {
+      (Some(profile), Some(sqm)) => {
+        println!("SQM profile         : {profile} ({sqm})")
+      }
+      _ => println!("SQM profile         : - (global sqm)"),
+    }
+  } else {
+    println!("Queue               : -");
+  }
+  println!("Network parents     : {}", explanation.network_parents.join(" -> "));
+  if let Some(counters) = &explanation.counters {
+    println!("Bytes               : {} / {}", counters.bytes.0, counters.bytes.1);
+    println!("Packets             : {} / {}", counters.packets.0, counters.packets.1);
+    println!(
+      "Current bps         : {} / {}",
+      counters.bits_per_second.0, counters.bits_per_second.1
+    );
+    match counters.median_tcp_rtt {
+      Some(rtt) => println!("Median TCP RTT      : {rtt:.2} ms"),
+      None => println!("Median TCP RTT      : -"),
+    }
+    println!("TC handle (traffic) : {}", counters.tc_handle.to_string());
+  } else {
+    println!("Counters            : not seen");
+  }
+  println!("Allowed subnets     : {}", explanation.in_allowed_subnets);
+  println!("Ignored subnets     : {}", explanation.in_ignored_subnets);
+  println!("Unknown IP          : {}", explanation.unknown);
+  if !explanation.notes.is_empty() {
+    println!();
+    for note in explanation.notes.iter() {
+      println!(" * {note}");
+    }
+  }
+}
+
+fn print_self_test(results: &[SelfTestResult]) {
+  for result in results.iter() {
+    let status = if result.passed { "PASS" } else { "FAIL" };
+    println!("[{status}] {}", result.name);
+    for problem in result.problems.iter() {
+      println!("       * {problem}");
+    }
+  }
+  let failed = results.iter().filter(|r| !r.passed).count();
+  println!();
+  println!("{} cases, {failed} failed", results.len());
+}
+
+#[tokio::main(flavor = "current_thread")]
+pub async fn main() -> Result<()> {
+  let cli = Args::parse();
+
+  let request = match cli.command {
+    Some(Commands::Synthetic { interface, src, dst, vlan, proto }) => {
+      BusRequest::TracePacket {
+        interface,
+        frame: TraceFrame::Synthetic {
+          src,
+          dst,
+          vlan,
+          protocol: parse_protocol(&proto)?,
+        },
+      }
+    }
+    Some(Commands::Raw { interface, frame }) => BusRequest::TracePacket {
+      interface,
+      frame: TraceFrame::Raw(parse_hex_frame(&frame)?),
+    },
+    Some(Commands::Ip { ip }) => BusRequest::ExplainIp(ip),
+    Some(Commands::SelfTest) => BusRequest::GetSelfTestResults,
+    None => {
+      println!("Run with --help to see instructions");
+      exit(0);
+    }
+  };
+
+  for response in bus_request(vec![request]).await? {
+    match response {
+      BusResponse::PacketTrace(trace) => print_trace(&trace),
+      BusResponse::IpExplanation(explanation) => print_explanation(&explanation),
+      BusResponse::SelfTestResults(Some(results)) => print_self_test(&results),
+      BusResponse::SelfTestResults(None) => {
+        println!("The self-test has not run. Enable it in the [self_test] section of /etc/lqos.conf.");
+      }
+      BusResponse::Fail(err) => return Err(Error::msg(err)),
+      _ => return Err(Error::msg("Unexpected response from lqosd")),
+    }
+  }
+  Ok(())
+}
@@ -1,7 +1,12 @@
 use anyhow::Result;
 use clap::{Parser, Subcommand};
-use lqos_config::{UserRole, WebUsers};
-use std::process::exit;
+use lqos_config::{ApiScope, BackendResult, UserRole, UserScope, WebUsers};
+use qrcode::{render::unicode::Dense1x2, QrCode};
+use std::{
+  io::{stdin, stdout, Write},
+  process::exit,
+  time::{SystemTime, UNIX_EPOCH},
+};
 
 #[derive(Parser)]
 #[command()]
@@ -33,6 +38,123 @@ enum Commands {
   },
   /// List users
   List,
+  /// Create an API key for a user. The key is only shown once.
+  AddKey {
+    /// Username the key acts as
+    #[arg(long)]
+    username: String,
+
+    /// What the key is for, such as "billing"
+    #[arg(long)]
+    name: String,
+
+    /// What the key may do: read, shaping-write or config-write. May
+    /// be given more than once.
+    #[arg(long = "scope", required = true)]
+    scopes: Vec<ApiScope>,
+  },
+  /// Revoke an API key
+  DelKey {
+    /// Username the key belongs to
+    #[arg(long)]
+    username: String,
+
+    /// The key's ID, from list-keys
+    id: String,
+  },
+  /// List API keys
+  ListKeys {
+    /// Only list this user's keys
+    #[arg(long)]
+    username: Option<String>,
+  },
+  /// List signed-in web UI sessions
+  ListSessions {
+    /// Only list this user's sessions
+    #[arg(long)]
+    username: Option<String>,
+  },
+  /// Sign out a web UI session
+  DelSession {
+    /// The session's ID, from list-sessions
+    id: String,
+  },
+  /// Sign out all of a user's web UI sessions
+  DelSessions {
+    /// Username to sign out
+    #[arg(long)]
+    username: String,
+  },
+  /// Enroll an authenticator app for a user, who then needs a code
+  /// from it to sign in to the web UI. Replaces any earlier app.
+  EnrollTotp {
+    /// Username to enroll
+    #[arg(long)]
+    username: String,
+  },
+  /// Remove a user's authenticator app and recovery codes
+  ResetTotp {
+    /// Username to reset
+    #[arg(long)]
+    username: String,
+  },
+  /// Replace an enrolled user's recovery codes
+  RecoveryCodes {
+    /// Username to issue codes for
+    #[arg(long)]
+    username: String,
+  },
+  /// Check a username and password with the LDAP or RADIUS server set
+  /// in /etc/lqos.conf, without signing in
+  TestBackend {
+    /// Username to check
+    #[arg(long)]
+    username: String,
+
+    /// Password to check
+    #[arg(long)]
+    password: String,
+  },
+  /// Restrict a user to circuits under some network.json nodes, or
+  /// with some tags, such as for a reseller. Replaces any earlier scope.
+  SetScope {
+    /// Username to restrict
+    #[arg(long)]
+    username: String,
+
+    /// A network.json node, by name or id. May be given more than once.
+    #[arg(long = "node")]
+    nodes: Vec<String>,
+
+    /// A circuit tag, as name:value. May be given more than once.
+    #[arg(long = "tag")]
+    tags: Vec<String>,
+  },
+  /// Let a user see the whole network again
+  ClearScope {
+    /// Username to let see everything
+    #[arg(long)]
+    username: String,
+  },
+}
+
+fn print_recovery_codes(codes: &[String]) {
+  println!("\nRecovery codes, each usable once in place of a code:\n");
+  for code in codes {
+    println!("  {code}");
+  }
+  println!("\nKeep them safe; they can't be shown again.");
+}
+
+/// How long ago (or until) a time is, such as "3h"
+fn age(time: u64, now: u64) -> String {
+  let seconds = now.abs_diff(time);
+  match seconds {
+    0..=119 => format!("{seconds}s"),
+    120..=7199 => format!("{}m", seconds / 60),
+    7200..=172_799 => format!("{}h", seconds / 3600),
+    _ => format!("{}d", seconds / 86400),
+  }
 }
 
 fn main() -> Result<()> {
@@ -49,6 +171,110 @@ fn main() -> Result<()> {
       println!("All Users\n");
       users.print_users()?;
     }
+    Some(Commands::AddKey { username, name, scopes }) => {
+      let (info, key) = users.create_api_key(&username, &name, &scopes)?;
+      println!("Created API key {} for {username}:\n{key}", info.id);
+      println!("Keep it safe; it can't be shown again.");
+    }
+    Some(Commands::DelKey { username, id }) => {
+      users.revoke_api_key(&username, &id)?;
+    }
+    Some(Commands::ListKeys { username }) => {
+      for key in users.list_api_keys(username.as_deref()) {
+        let scopes: Vec<String> =
+          key.scopes.iter().map(|s| s.to_string()).collect();
+        println!(
+          "{:<14} {:<20} {:<20} {}",
+          key.id,
+          key.username,
+          key.name,
+          scopes.join(",")
+        );
+      }
+    }
+    Some(Commands::ListSessions { username }) => {
+      let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+      println!(
+        "{:<14} {:<20} {:<10} {:<10} Expires in",
+        "ID", "Username", "Signed in", "Last used"
+      );
+      for session in users.list_sessions(username.as_deref()) {
+        println!(
+          "{:<14} {:<20} {:<10} {:<10} {}",
+          session.id,
+          session.username,
+          format!("{} ago", age(session.created, now)),
+          format!("{} ago", age(session.last_seen, now)),
+          age(session.expires, now),
+        );
+      }
+    }
+    Some(Commands::DelSession { id }) => {
+      users.revoke_session(&id)?;
+    }
+    Some(Commands::DelSessions { username }) => {
+      let count = users.revoke_user_sessions(&username)?;
+      println!("Signed out {count} sessions for {username}");
+    }
+    Some(Commands::EnrollTotp { username }) => {
+      let (secret, uri) = users.begin_totp(&username)?;
+      let qr = QrCode::new(uri.as_bytes())?
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .quiet_zone(true)
+        .build();
+      println!("Scan this with an authenticator app:\n\n{qr}\n");
+      println!("Or add it by hand, with the key {secret}, or open:\n{uri}\n");
+      print!("Then enter the code the app shows: ");
+      stdout().flush()?;
+      let mut code = String::new();
+      stdin().read_line(&mut code)?;
+      let codes = users.confirm_totp(&username, &secret, code.trim())?;
+      println!("Enrolled {username}.");
+      print_recovery_codes(&codes);
+    }
+    Some(Commands::ResetTotp { username }) => {
+      users.reset_totp(&username)?;
+    }
+    Some(Commands::RecoveryCodes { username }) => {
+      let codes = users.new_recovery_codes(&username)?;
+      print_recovery_codes(&codes);
+    }
+    Some(Commands::TestBackend { username, password }) => {
+      let check = WebUsers::check_backend(&username, &password);
+      let backend = check.backend();
+      match check.result() {
+        None => println!("Passwords are checked locally; no server to ask"),
+        Some(BackendResult::Accepted(role)) => {
+          println!("{backend} accepted {username} as {role}");
+        }
+        Some(BackendResult::Rejected) => {
+          println!("{backend} rejected {username}, or gave them no role");
+        }
+        Some(BackendResult::Unreachable(reason)) => {
+          println!("Unable to ask {backend}: {reason}");
+        }
+      }
+    }
+    Some(Commands::SetScope { username, nodes, tags }) => {
+      if nodes.is_empty() && tags.is_empty() {
+        println!("Give at least one --node or --tag");
+        exit(1);
+      }
+      if let Some(tag) = tags.iter().find(|t| !t.contains(':')) {
+        println!("{tag} isn't a name:value tag");
+        exit(1);
+      }
+      let scope = UserScope { nodes, tags };
+      users.set_scope(&username, Some(scope.clone()))?;
+      println!("{username} may now see {scope}");
+    }
+    Some(Commands::ClearScope { username }) => {
+      users.set_scope(&username, None)?;
+    }
     None => {
       println!("Run with --help to see instructions");
       exit(0);
@@ -49,11 +49,13 @@ pub(crate) async fn collate_stats(comm_tx: Sender<SenderChannelMessage>) {
             }
         });
 
+        // Keyed by the stable node id, so that node indices moving (or
+        // two nodes sharing a name) don't mix up the history.
         e.network_tree.iter().for_each(|(index, node)| {
-            if let Some(t) = tree_accumulator.get_mut(&node.name) {
+            if let Some(t) = tree_accumulator.get_mut(&node.id) {
                 t.push((*index, node));
             } else {
-                tree_accumulator.insert(node.name.clone(), vec![(*index, node)]);
+                tree_accumulator.insert(node.id.clone(), vec![(*index, node)]);
             }
         });
     });
@@ -79,13 +81,14 @@ pub(crate) async fn collate_stats(comm_tx: Sender<SenderChannelMessage>) {
             circuit_id: host[0].circuit_id.clone(),
             bits: StatsSummary{ min: (bits.down.min, bits.up.min), max: (bits.down.max, bits.up.max), avg: (bits.down.avg, bits.up.avg) },
             rtt: StatsRttSummary{ min: rtt.min, max: rtt.max, avg: rtt.avg },
+            tags: host[0].tags.clone(),
         };
         stats_hosts.push(sh);
     }
 
     // Get network tree min/max data
     let mut tree_entries = Vec::new();
-    for (name, nodes) in tree_accumulator.into_iter() {
+    for (id, nodes) in tree_accumulator.into_iter() {
         let bits = MinMaxAvgPair::from_slice(
             &nodes
                 .iter()
@@ -101,13 +104,15 @@ pub(crate) async fn collate_stats(comm_tx: Sender<SenderChannelMessage>) {
 
         let n = StatsTreeNode {
             index: nodes[0].0,
-            name: name.to_string(),
+            id,
+            name: nodes[0].1.name.clone(),
             max_throughput: nodes[0].1.max_throughput,
             current_throughput: StatsSummary{ min: (bits.down.min.into(), bits.up.min.into()), max: (bits.down.max.into(), bits.up.max.into()), avg: (bits.down.avg.into(), bits.up.avg.into()) },
             rtt: StatsRttSummary{ min: rtt.min, max: rtt.max, avg: rtt.avg },
             parents: nodes[0].1.parents.clone(),
             immediate_parent: nodes[0].1.immediate_parent,
             node_type: nodes[0].1.node_type.clone(),
+            parent_ids: nodes[0].1.parent_ids.clone(),
         };
         tree_entries.push(n);
     }
@@ -3,12 +3,14 @@ use lqos_config::NetworkJsonNode;
 #[derive(Debug, Clone)]
 pub struct NetworkTreeEntry {
     pub name: String,
+    pub id: String,
     pub max_throughput: (u32, u32),
     pub current_throughput: (u32, u32),
     pub rtts: (u16, u16, u16),
     pub parents: Vec<usize>,
     pub immediate_parent: Option<usize>,
     pub node_type: Option<String>,
+    pub parent_ids: Vec<String>,
 }
 
 impl From<&NetworkJsonNode> for NetworkTreeEntry {
@@ -34,6 +36,7 @@ impl From<&NetworkJsonNode> for NetworkTreeEntry {
 
         Self {
             name: value.name.clone(),
+            id: value.id.clone(),
             max_throughput: value.max_throughput,
             parents: value.parents.clone(),
             immediate_parent: value.immediate_parent,
@@ -42,6 +45,7 @@ impl From<&NetworkJsonNode> for NetworkTreeEntry {
                 value.current_throughput.1.load(std::sync::atomic::Ordering::Relaxed) as u32,
             ),
             node_type: value.node_type.clone(),
+            parent_ids: value.parent_ids.clone(),
             rtts: (min as u16, max as u16, avg as u16),
         }
     }
@@ -4,7 +4,7 @@
 //! 
 //! Note that ThroughputSummary should be boxed, to avoid copying
 
-use std::net::IpAddr;
+use std::{collections::BTreeMap, net::IpAddr};
 
 #[derive(Debug)]
 pub struct ThroughputSummary {
@@ -20,4 +20,5 @@ pub struct HostSummary {
     pub circuit_id: Option<String>,
     pub bits_per_second: (u64, u64),
     pub median_rtt: f32,
+    pub tags: BTreeMap<String, String>,
 }
\ No newline at end of file
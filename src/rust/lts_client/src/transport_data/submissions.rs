@@ -3,6 +3,7 @@
 
 use lqos_config::ShapedDevice;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use uisp::Device;
 
 use crate::collector::CakeStats;
@@ -54,6 +55,9 @@ pub struct StatsHost {
     pub bits: StatsSummary,
     /// Host's RTT statistics
     pub rtt: StatsRttSummary,
+    /// Tags from the host's circuit in ShapedDevices.csv
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
 }
 
 /// Node inside a traffic summary tree
@@ -61,6 +65,10 @@ pub struct StatsHost {
 pub struct StatsTreeNode {
     /// Index in the tree vector
     pub index: usize,
+    /// Stable node id (from network.json, or generated from the node's
+    /// path in the tree)
+    #[serde(default)]
+    pub id: String,
     /// Name (from network.json)
     pub name: String,
     /// Maximum allowed throughput (from network.json)
@@ -75,6 +83,9 @@ pub struct StatsTreeNode {
     pub immediate_parent: Option<usize>,
     /// Node Type
     pub node_type: Option<String>,
+    /// Stable ids of parents in the tree, matching `parents`
+    #[serde(default)]
+    pub parent_ids: Vec<String>,
 }
 
 /// Collation of all stats for a given time period
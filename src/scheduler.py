@@ -2,9 +2,9 @@ import time
 import datetime
 from LibreQoS import refreshShapers, refreshShapersUpdateOnly
 from graphInfluxDB import refreshBandwidthGraphs, refreshLatencyGraphs
-from ispConfig import influxDBEnabled, automaticImportUISP, automaticImportSplynx
+from ispSettings import influxDBEnabled, automaticImportUISP, automaticImportSplynx
 try:
-	from ispConfig import queueRefreshIntervalMins
+	from ispSettings import queueRefreshIntervalMins
 except:
 	queueRefreshIntervalMins = 30
 if automaticImportUISP:
@@ -12,13 +12,13 @@ if automaticImportUISP:
 if automaticImportSplynx:
 	from integrationSplynx import importFromSplynx
 try:
-	from ispConfig import automaticImportPowercode
+	from ispSettings import automaticImportPowercode
 except:
 	automaticImportPowercode = False
 if automaticImportPowercode:
 	from integrationPowercode import importFromPowercode
 try:
-	from ispConfig import automaticImportSonar
+	from ispSettings import automaticImportSonar
 except:
 	automaticImportSonar = False
 if automaticImportSonar: